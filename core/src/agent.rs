@@ -0,0 +1,706 @@
+use std::collections::{HashMap, HashSet};
+
+use tokio::sync::{mpsc, oneshot, watch};
+
+use crate::auth::{self, AuthFailure};
+use crate::chat::Chat;
+use crate::inference::stream::StreamEvent;
+use crate::inference::types::{ContentItem, Message, Role, Usage};
+use crate::loops::{LoopGuard, Stuck};
+use crate::models;
+use crate::policy::Verdict;
+use crate::secrets::{self, SecretAction, SecretsBlocked};
+use crate::task::TASK_TOOL;
+use crate::tree::GitTree;
+
+/// What happens during a turn, for frontends to display.
+pub enum AgentEvent<'a> {
+    Stream(&'a StreamEvent),
+    ToolStart { id: &'a str, name: &'a str, input: &'a serde_json::Value },
+    ToolDone { id: &'a str, name: &'a str, output: &'a str, is_error: bool },
+    /// Messages typed during the turn, sent along with the tool results
+    Steer { text: &'a str },
+}
+
+/// Callbacks for the parts of a turn, for code embedding the agent loop in its own frontend.
+/// Every method does nothing unless overridden.
+pub trait AgentEvents {
+    fn on_text_delta(&mut self, _text: &str) {}
+
+    fn on_thinking_delta(&mut self, _text: &str) {}
+
+    /// A tool call is about to run, after any approval
+    fn on_tool_use_start(&mut self, _id: &str, _name: &str, _input: &serde_json::Value) {}
+
+    fn on_tool_result(&mut self, _id: &str, _name: &str, _output: &str, _is_error: bool) {}
+
+    /// The tokens of one model reply, once it is complete
+    fn on_usage(&mut self, _usage: &Usage) {}
+}
+
+impl AgentEvent<'_> {
+    /// Calls the method of `events` this event is for.
+    pub fn dispatch(&self, events: &mut impl AgentEvents) {
+        match self {
+            AgentEvent::Stream(StreamEvent::Text(text)) => events.on_text_delta(text),
+            AgentEvent::Stream(StreamEvent::Thinking(text)) => events.on_thinking_delta(text),
+            AgentEvent::Stream(StreamEvent::Done { usage: Some(usage), .. }) => events.on_usage(usage),
+            AgentEvent::Stream(_) => {},
+            AgentEvent::ToolStart { id, name, input } => events.on_tool_use_start(id, name, input),
+            AgentEvent::ToolDone { id, name, output, is_error } => events.on_tool_result(id, name, output, *is_error),
+            AgentEvent::Steer { .. } => {},
+        }
+    }
+}
+
+/// Triggers cancellation of whatever turn is running, e.g. from a Ctrl-C handler.
+#[derive(Clone)]
+pub struct CancelSource {
+    tx: watch::Sender<u64>,
+}
+
+/// Handed to a running turn.  Cancellations from before `reset` are ignored so a Ctrl-C
+/// pressed between turns doesn't cancel the next one.
+pub struct CancelToken {
+    rx: watch::Receiver<u64>,
+}
+
+pub fn cancel_pair() -> (CancelSource, CancelToken) {
+    let (tx, rx) = watch::channel(0);
+    (CancelSource { tx }, CancelToken { rx })
+}
+
+impl CancelSource {
+    pub fn cancel(&self) {
+        self.tx.send_modify(|count| *count += 1);
+    }
+}
+
+impl CancelToken {
+    pub fn reset(&mut self) {
+        self.rx.borrow_and_update();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.rx.has_changed().unwrap_or(false)
+    }
+
+    pub async fn cancelled(&mut self) {
+        if self.rx.changed().await.is_err() {
+            // Source dropped, nothing can cancel anymore
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// The user's answer to a tool call waiting for approval.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    Approve,
+    /// Run the tool with these arguments instead
+    Edit(serde_json::Value),
+    /// Don't run it, telling the model why
+    Deny(String),
+    /// Run it and every later call of the same tool without asking
+    AlwaysAllow,
+}
+
+/// A tool call waiting for the user, answered through `reply`.
+pub struct ApprovalRequest {
+    pub name: String,
+    pub input: serde_json::Value,
+    /// Why the command policy holds the call for approval, when it does
+    pub reason: Option<String>,
+    pub reply: oneshot::Sender<Decision>,
+}
+
+/*
+    * Holds every tool call for approval before it runs, for `--approve-each`.  Requests go to
+    * whoever holds the receiver from `approval_channel`, so a frontend can answer them while
+    * it keeps drawing and reading keys.  Tools allowed with `Decision::AlwaysAllow` aren't
+    * asked about again for as long as this lives.
+*/
+pub struct Approvals {
+    tx: mpsc::UnboundedSender<ApprovalRequest>,
+    always: HashSet<String>,
+    denied: usize,
+}
+
+pub fn approval_channel() -> (Approvals, mpsc::UnboundedReceiver<ApprovalRequest>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    (Approvals { tx, always: HashSet::new(), denied: 0 }, rx)
+}
+
+impl Approvals {
+    async fn decide(&mut self, name: &str, input: &serde_json::Value) -> Decision {
+        if self.always.contains(name) {
+            return Decision::Approve;
+        }
+        self.ask(name, input, None).await
+    }
+
+    // Asks about the call even for a tool that is always allowed
+    async fn ask(&mut self, name: &str, input: &serde_json::Value, reason: Option<String>) -> Decision {
+        let (reply, answer) = oneshot::channel();
+        let request = ApprovalRequest { name: name.to_string(), input: input.clone(), reason, reply };
+        // Nobody left to ask, which must not run the tool unasked
+        let decision = match self.tx.send(request) {
+            Ok(_) => answer.await.unwrap_or_else(|_| Decision::Deny(String::new())),
+            Err(_) => Decision::Deny(String::new()),
+        };
+        match &decision {
+            Decision::AlwaysAllow => {
+                self.always.insert(name.to_string());
+            },
+            Decision::Deny(_) => self.denied += 1,
+            _ => {},
+        }
+        decision
+    }
+
+    /// How many tool calls the user denied so far.
+    pub fn denied(&self) -> usize {
+        self.denied
+    }
+}
+
+/// Secrets found in a message about to be sent, for the user to say what is done with them.
+pub struct SecretRequest {
+    /// A line for each secret, where it is and how it starts
+    pub found: Vec<String>,
+    /// What `secrets.action` says, for an answer that picks nothing
+    pub default: SecretAction,
+    pub reply: oneshot::Sender<SecretAction>,
+}
+
+/// Asks whoever holds the receiver from `secret_channel` before masking or blocking secrets,
+/// so they can be sent anyway.
+pub struct SecretPrompts {
+    tx: mpsc::UnboundedSender<SecretRequest>,
+}
+
+pub fn secret_channel() -> (SecretPrompts, mpsc::UnboundedReceiver<SecretRequest>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    (SecretPrompts { tx }, rx)
+}
+
+impl SecretPrompts {
+    async fn decide(&self, found: Vec<String>, default: SecretAction) -> SecretAction {
+        let (reply, answer) = oneshot::channel();
+        match self.tx.send(SecretRequest { found, default, reply }) {
+            Ok(_) => answer.await.unwrap_or(default),
+            Err(_) => default,
+        }
+    }
+}
+
+/// A new API key wanted after the provider rejected the one in use and nothing in `[auth]`
+/// gave another, None on the reply to give up.
+pub struct KeyRequest {
+    pub provider: String,
+    pub failure: AuthFailure,
+    pub reply: oneshot::Sender<Option<String>>,
+}
+
+/// Asks whoever holds the receiver from `key_channel` for an API key, so a turn can go on
+/// with it instead of failing.
+pub struct KeyPrompts {
+    tx: mpsc::UnboundedSender<KeyRequest>,
+}
+
+pub fn key_channel() -> (KeyPrompts, mpsc::UnboundedReceiver<KeyRequest>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    (KeyPrompts { tx }, rx)
+}
+
+impl KeyPrompts {
+    async fn ask(&self, provider: &str, failure: AuthFailure) -> Option<String> {
+        let (reply, answer) = oneshot::channel();
+        self.tx.send(KeyRequest { provider: provider.to_string(), failure, reply }).ok()?;
+        answer.await.ok().flatten().map(|key| key.trim().to_string()).filter(|key| !key.is_empty())
+    }
+}
+
+/// What a frontend can do to a running turn besides cancelling it.
+#[derive(Default)]
+pub struct Controls {
+    pub approvals: Option<Approvals>,
+    /*
+        * Messages typed while the turn runs.  They are sent once the tool call running
+        * finishes, after its result, and the calls the model asked for after it are skipped
+        * so the model can take the new instruction into account first.  Anything queued while
+        * the model writes its final answer is left for the frontend to send next.
+    */
+    pub steering: Option<mpsc::UnboundedReceiver<String>>,
+    pub budget: Option<Budget>,
+    /// Asked before secrets are masked or blocked, which `secrets.action` decides without it
+    pub secrets: Option<SecretPrompts>,
+    /// Asked for a key when the provider rejects the one in use and `[auth]` has no new one
+    pub keys: Option<KeyPrompts>,
+}
+
+impl Controls {
+    fn steered(&mut self) -> Vec<String> {
+        let mut queued = Vec::new();
+        if let Some(steering) = self.steering.as_mut() {
+            while let Ok(text) = steering.try_recv() {
+                queued.push(text);
+            }
+        }
+        queued
+    }
+
+    /// Messages typed too late for the last turn, to be sent as the next one.
+    pub fn queued(&mut self) -> Option<String> {
+        Some(self.steered().join("\n\n")).filter(|text| !text.is_empty())
+    }
+}
+
+const SKIPPED_OUTPUT: &str = "Not run, the user sent a new message first.";
+const STUCK_OUTPUT: &str = "Not run, the turn was stopped for going in circles.";
+const GAVE_UP_OUTPUT: &str = "Not run, the turn was stopped after a tool kept failing.";
+
+/// The model's tool call denied by the user, ending a one-shot run with its own exit status.
+#[derive(Debug)]
+pub struct Denied {
+    pub calls: usize,
+}
+
+impl std::fmt::Display for Denied {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} tool call(s) denied", self.calls)
+    }
+}
+
+impl std::error::Error for Denied {}
+
+// The tool result telling the model a call was denied
+fn denied_output(feedback: &str) -> String {
+    match feedback.trim() {
+        "" => "The user denied this tool call, it was not run.".to_string(),
+        feedback => format!("The user denied this tool call, it was not run.  Their feedback: {}", feedback),
+    }
+}
+
+/// Limits on what a turn may spend, for `--max-cost` and `--max-tokens`.
+#[derive(Debug, Clone, Default)]
+pub struct Budget {
+    /// USD at the prices of the configured model
+    pub max_cost: Option<f64>,
+    /// Input and output tokens of every request together
+    pub max_tokens: Option<u64>,
+    /// Used by earlier turns of the same run, counted towards the limits
+    pub spent: Usage,
+}
+
+impl Budget {
+    /*
+        * Whether the next request, of about `next_input` tokens counted locally, would take
+        * `spent` past a limit.  Its output isn't known beforehand, so a request that fits can
+        * still end up crossing one and the run stops at the one after.
+    */
+    fn exceeded(&self, spent: &Usage, next_input: u32, model: &str) -> Option<BudgetExceeded> {
+        let tokens = |usage: &Usage| usage.input_tokens as u64 + usage.cache_creation_input_tokens as u64
+            + usage.cache_read_input_tokens as u64 + usage.output_tokens as u64;
+        let projected = Usage { input_tokens: spent.input_tokens.saturating_add(next_input), ..spent.clone() };
+        if let Some(max_tokens) = self.max_tokens.filter(|max| tokens(&projected) > *max || tokens(spent) >= *max) {
+            return Some(BudgetExceeded { spent: format!("{} tokens", tokens(spent)), limit: format!("{} token", max_tokens), next: (tokens(spent) < max_tokens).then_some(next_input) });
+        }
+        let cost = |usage: &Usage| models::lookup(model).map_or(0.0, |info| info.cost(usage));
+        let max_cost = self.max_cost.filter(|max| cost(&projected) > *max || cost(spent) >= *max)?;
+        Some(BudgetExceeded { spent: format!("${:.4}", cost(spent)), limit: format!("${:.2}", max_cost), next: (cost(spent) < max_cost).then_some(next_input) })
+    }
+}
+
+/// A turn stopped before its next request because it used up its `Budget`.
+#[derive(Debug)]
+pub struct BudgetExceeded {
+    pub spent: String,
+    pub limit: String,
+    /// Estimated input tokens of the request that was held back
+    pub next: Option<u32>,
+}
+
+impl std::fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Stopped, the run used {} of its {} budget", self.spent, self.limit)?;
+        match self.next {
+            Some(next) => write!(f, " and the next request would add about {} input tokens", next),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for BudgetExceeded {}
+
+/// A tool failed more than `max_tool_retries` times in a row, so the turn was handed back.
+#[derive(Debug)]
+pub struct ToolFailed {
+    pub tool: String,
+    pub attempts: usize,
+    pub error: String,
+}
+
+impl std::fmt::Display for ToolFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Stopped, {} failed {} times in a row: {}", self.tool, self.attempts, self.error)
+    }
+}
+
+impl std::error::Error for ToolFailed {}
+
+/*
+    * Tool failures of one turn.  A failed call is sent back as an error result so the model
+    * can fix its arguments and try again, but only `max_retries` times in a row for the same
+    * tool, a success in between resets its count.
+*/
+struct Failures {
+    max_retries: usize,
+    counts: HashMap<String, usize>,
+    gave_up: Option<ToolFailed>,
+}
+
+impl Failures {
+    fn new(max_retries: usize) -> Self {
+        Failures { max_retries, counts: HashMap::new(), gave_up: None }
+    }
+
+    // The result for a call of `tool` and whether it is an error
+    fn record(&mut self, tool: &str, output: Result<String, anyhow::Error>) -> (String, bool) {
+        match output {
+            Ok(output) => {
+                self.counts.remove(tool);
+                (output, false)
+            },
+            Err(e) => {
+                let count = self.counts.entry(tool.to_string()).or_insert(0);
+                *count += 1;
+                if self.max_retries > 0 && *count > self.max_retries && self.gave_up.is_none() {
+                    self.gave_up = Some(ToolFailed { tool: tool.to_string(), attempts: *count, error: e.to_string() });
+                }
+                (format!("Error: {}", e), true)
+            },
+        }
+    }
+}
+
+// A `task` call approved to run alongside the others of its reply, `slot` is its result
+struct Batched {
+    slot: usize,
+    id: String,
+    input: serde_json::Value,
+    note: String,
+}
+
+async fn run_batch(
+    chat: &Chat,
+    batch: Vec<Batched>,
+    results: &mut [ContentItem],
+    failures: &mut Failures,
+    on_event: &mut impl FnMut(AgentEvent<'_>),
+) {
+    let calls = batch.iter().map(|call| (call.id.as_str(), &call.input)).collect::<Vec<_>>();
+    let outputs = chat.run_tasks(&calls).await;
+    for (call, output) in batch.iter().zip(outputs) {
+        let (output, is_error) = failures.record(TASK_TOOL, output.map(|output| format!("{}{}", call.note, output)));
+        on_event(AgentEvent::ToolDone { id: &call.id, name: TASK_TOOL, output: &output, is_error });
+        results[call.slot] = ContentItem::ToolResult { tool_use_id: call.id.clone(), content: output, is_error };
+    }
+}
+
+/// `run_turn` reporting to `events` instead of a closure.
+pub async fn run_turn_with(
+    chat: &mut Chat,
+    message: Message,
+    events: &mut impl AgentEvents,
+    controls: &mut Controls,
+    cancel: &mut CancelToken,
+) -> Result<TurnOutcome, anyhow::Error> {
+    run_turn(chat, message, &mut |event| event.dispatch(events), controls, cancel).await
+}
+
+/*
+    * Gets a new key after the provider turned a request down with a 401 or 403, from `[auth]`
+    * if it has one and else from the user through `controls.keys`, false when neither did.
+*/
+async fn reauthenticate(chat: &mut Chat, failure: AuthFailure, controls: &Controls) -> bool {
+    let provider = chat.config().provider.clone();
+    log::warn!("{}, looking for a new key", failure.describe(&provider));
+    let mut key = auth::refresh(&chat.config().auth, &chat.config().api_key).await;
+    if key.is_none() {
+        if let Some(keys) = controls.keys.as_ref() {
+            key = keys.ask(&provider, failure).await;
+        }
+    }
+    match key {
+        Some(key) => match chat.set_api_key(&key).await {
+            Ok(()) => true,
+            Err(e) => {
+                log::error!("Could not send with the new key: {:#}", e);
+                false
+            },
+        },
+        None => false,
+    }
+}
+
+/// How a turn ended.
+pub struct TurnOutcome {
+    /// The last reply, the one without tool calls unless the turn was cut short
+    pub reply: Message,
+    /// The reply was cut off and can be picked up with `Chat::continue_response`
+    pub incomplete: bool,
+}
+
+/// The model kept calling tools past `max_turns` rounds.
+#[derive(Debug)]
+pub struct RoundLimit {
+    pub rounds: usize,
+}
+
+impl std::fmt::Display for RoundLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Stopped after {} rounds of tool calls, raise max_turns in pprog.toml to allow more", self.rounds)
+    }
+}
+
+impl std::error::Error for RoundLimit {}
+
+/*
+    * Runs one user turn to completion: the reply is streamed, every tool call it contains is
+    * executed and the results are sent back, until the model answers without calling tools.
+    * This is the loop the web frontend drives over HTTP, done in process for the REPL.  In
+    * plan mode the turn also ends once a plan is proposed, to wait for the user.  A turn
+    * past `max_turns` rounds, or one that `LoopGuard` finds going in circles, is stopped.
+*/
+#[tracing::instrument(name = "turn", skip_all, fields(round))]
+pub async fn run_turn(
+    chat: &mut Chat,
+    message: Message,
+    on_event: &mut impl FnMut(AgentEvent),
+    controls: &mut Controls,
+    cancel: &mut CancelToken,
+) -> Result<TurnOutcome, anyhow::Error> {
+    let max_rounds = match chat.config().max_turns {
+        0 => usize::MAX,
+        max_turns => max_turns,
+    };
+    let mut guard = LoopGuard::new(chat.config().max_repeats, GitTree::get_git_root().ok());
+    let mut failures = Failures::new(chat.config().max_tool_retries);
+    let mut spent = controls.budget.as_ref().map(|budget| budget.spent.clone()).unwrap_or_default();
+    cancel.reset();
+    let mut next = message;
+    for round in 0..=max_rounds {
+        tracing::Span::current().record("round", round + 1);
+        let sent_tool_results = next.content.iter().any(|item| matches!(item, ContentItem::ToolResult { .. }));
+        let mut blocked = None;
+        if let (Some(prompts), Some(default)) = (controls.secrets.as_ref(), chat.secret_action()) {
+            let found = chat.find_secrets(&next);
+            if !found.is_empty() {
+                match prompts.decide(secrets::describe(&next, &found), default).await {
+                    SecretAction::Send => chat.allow_secrets(&next, &found),
+                    action => blocked = secrets::apply(&mut next, &found, action).err(),
+                }
+            }
+        }
+        let over_budget = controls.budget.as_ref().and_then(|budget| {
+            let next_input = chat.estimate_request(&next).map_or(0, |estimate| estimate.input() as u32);
+            budget.exceeded(&spent, next_input, &chat.config().model)
+        });
+        let result = if cancel.is_cancelled() {
+            Err(anyhow::anyhow!("Response cancelled"))
+        } else if let Some(blocked) = blocked {
+            Err(blocked.into())
+        } else if let Some(exceeded) = over_budget {
+            Err(exceeded.into())
+        } else {
+            let mut on_stream = |event: &StreamEvent| {
+                if let StreamEvent::Done { usage: Some(usage), .. } = event {
+                    spent.add(usage);
+                }
+                on_event(AgentEvent::Stream(event))
+            };
+            let mut result = chat.send_message_streaming(next.clone(), &mut on_stream, cancel.cancelled()).await;
+            // A rejected key is replaced and the request sent again, once
+            if let Some(failure) = result.as_ref().err().and_then(AuthFailure::of) {
+                if reauthenticate(chat, failure, controls).await {
+                    result = chat.send_message_streaming(next.clone(), &mut on_stream, cancel.cancelled()).await;
+                }
+                let provider = chat.config().provider.clone();
+                result = result.map_err(|e| match AuthFailure::of(&e) {
+                    Some(failure) => e.context(failure.advice(&provider)),
+                    None => e,
+                });
+            }
+            result
+        };
+        let reply = match result {
+            Ok(reply) => reply,
+            Err(e) if sent_tool_results => {
+                // The tool calls already ran, their results must stay in the history
+                if e.is::<SecretsBlocked>() {
+                    // A message steering the turn held them, it mustn't go out with a later request
+                    let found = chat.find_secrets(&next);
+                    let _ = secrets::apply(&mut next, &found, SecretAction::Mask);
+                }
+                chat.close_turn(next);
+                return Err(e);
+            },
+            Err(e) => return Err(e),
+        };
+        if chat.is_incomplete() {
+            return Ok(TurnOutcome { reply, incomplete: true });
+        }
+
+        let mut results = Vec::new();
+        let mut steered = Vec::new();
+        let mut stuck = None;
+        let tasks = reply.content.iter().filter(|item| matches!(item, ContentItem::ToolUse { name, .. } if name == TASK_TOOL)).count();
+        let parallel = tasks > 1 && chat.parallel_tasks() > 1;
+        let mut batch = Vec::new();
+        for item in &reply.content {
+            let ContentItem::ToolUse { id, name, input } = item else { continue };
+            if name != TASK_TOOL && !batch.is_empty() {
+                // Later calls may depend on what the sub-agents found
+                run_batch(chat, std::mem::take(&mut batch), &mut results, &mut failures, on_event).await;
+                steered = controls.steered();
+            }
+            if stuck.is_none() {
+                stuck = guard.check(name, input);
+            }
+            if stuck.is_some() {
+                results.push(ContentItem::ToolResult { tool_use_id: id.clone(), content: STUCK_OUTPUT.to_string(), is_error: false });
+                continue;
+            }
+            if failures.gave_up.is_some() {
+                results.push(ContentItem::ToolResult { tool_use_id: id.clone(), content: GAVE_UP_OUTPUT.to_string(), is_error: false });
+                continue;
+            }
+            if !steered.is_empty() {
+                results.push(ContentItem::ToolResult { tool_use_id: id.clone(), content: SKIPPED_OUTPUT.to_string(), is_error: false });
+                continue;
+            }
+            on_event(AgentEvent::ToolStart { id, name, input });
+            let decision = match (chat.command_policy().check(name, input), controls.approvals.as_mut()) {
+                (Verdict::Allow, Some(approvals)) => Ok(approvals.decide(name, input).await),
+                (Verdict::Allow, None) => Ok(Decision::Approve),
+                (Verdict::Ask(refusal), Some(approvals)) => Ok(approvals.ask(name, input, Some(refusal.reason)).await),
+                // Nobody to ask, which must not run the command unasked
+                (Verdict::Ask(refusal), None) => Err(refusal.output(true)),
+                (Verdict::Deny(refusal), _) => Err(refusal.output(false)),
+            };
+            let decision = match decision {
+                Ok(decision) => decision,
+                Err(output) => {
+                    on_event(AgentEvent::ToolDone { id, name, output: &output, is_error: false });
+                    results.push(ContentItem::ToolResult { tool_use_id: id.clone(), content: output, is_error: false });
+                    steered = controls.steered();
+                    continue;
+                },
+            };
+            let (input, note) = match decision {
+                Decision::Deny(feedback) => {
+                    let output = denied_output(&feedback);
+                    on_event(AgentEvent::ToolDone { id, name, output: &output, is_error: false });
+                    results.push(ContentItem::ToolResult { tool_use_id: id.clone(), content: output, is_error: false });
+                    steered = controls.steered();
+                    continue;
+                },
+                Decision::Edit(edited) => {
+                    let note = format!("The user changed the arguments to {} before it ran.\n", edited);
+                    (edited, note)
+                },
+                Decision::Approve | Decision::AlwaysAllow => (input.clone(), String::new()),
+            };
+            if parallel && name == TASK_TOOL {
+                batch.push(Batched { slot: results.len(), id: id.clone(), input, note });
+                results.push(ContentItem::ToolResult { tool_use_id: id.clone(), content: String::new(), is_error: false });
+                continue;
+            }
+            let item = ContentItem::ToolUse { id: id.clone(), name: name.clone(), input };
+            // The ask rules were settled above, with the user or by refusing
+            let output = chat.handle_approved_tool_use(&item).await.map(|output| format!("{}{}", note, output));
+            let (output, is_error) = failures.record(name, output);
+            on_event(AgentEvent::ToolDone { id, name, output: &output, is_error });
+            results.push(ContentItem::ToolResult { tool_use_id: id.clone(), content: output, is_error });
+            steered = controls.steered();
+        }
+        if !batch.is_empty() {
+            run_batch(chat, batch, &mut results, &mut failures, on_event).await;
+            steered = controls.steered();
+        }
+        if !steered.is_empty() {
+            let text = steered.join("\n\n");
+            on_event(AgentEvent::Steer { text: &text });
+            results.push(ContentItem::Text { text });
+        }
+        if let Some(reason) = stuck {
+            chat.close_turn(Message { role: Role::User, content: results });
+            return Err(Stuck { reason }.into());
+        }
+        if let Some(failed) = failures.gave_up {
+            chat.close_turn(Message { role: Role::User, content: results });
+            return Err(failed.into());
+        }
+
+        if results.is_empty() {
+            return Ok(TurnOutcome { reply, incomplete: false });
+        }
+        next = Message { role: Role::User, content: results };
+        if chat.proposed_plan().is_some() {
+            // The plan goes to the user for approval before the model hears back
+            chat.close_turn_for_plan(next);
+            return Ok(TurnOutcome { reply, incomplete: false });
+        }
+    }
+    chat.close_turn(next);
+    Err(RoundLimit { rounds: max_rounds }.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inference::types::StopReason;
+
+    #[derive(Default)]
+    struct Recorder {
+        seen: Vec<String>,
+    }
+
+    impl AgentEvents for Recorder {
+        fn on_text_delta(&mut self, text: &str) {
+            self.seen.push(format!("text {}", text));
+        }
+
+        fn on_thinking_delta(&mut self, text: &str) {
+            self.seen.push(format!("thinking {}", text));
+        }
+
+        fn on_tool_result(&mut self, _id: &str, name: &str, _output: &str, is_error: bool) {
+            self.seen.push(format!("result {} {}", name, is_error));
+        }
+
+        fn on_usage(&mut self, usage: &Usage) {
+            self.seen.push(format!("usage {}", usage.output_tokens));
+        }
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let usage = Usage { output_tokens: 7, ..Default::default() };
+        let events = [
+            StreamEvent::Thinking("hmm".to_string()),
+            StreamEvent::Text("Done.".to_string()),
+            StreamEvent::ToolInput("{}".to_string()),
+            StreamEvent::Done { stop_reason: StopReason::EndTurn, stop_sequence: None, usage: Some(usage), timing: Default::default() },
+        ];
+        let mut recorder = Recorder::default();
+        for event in &events {
+            AgentEvent::Stream(event).dispatch(&mut recorder);
+        }
+        let input = serde_json::json!({"statement": "false"});
+        AgentEvent::ToolStart { id: "t1", name: "execute", input: &input }.dispatch(&mut recorder);
+        AgentEvent::ToolDone { id: "t1", name: "execute", output: "exit status: 1", is_error: true }.dispatch(&mut recorder);
+        assert_eq!(recorder.seen, ["thinking hmm", "text Done.", "usage 7", "result execute true"]);
+    }
+}
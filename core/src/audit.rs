@@ -0,0 +1,281 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::config::{AuditConfig, ProjectConfig};
+use crate::crypto::{Cipher, ENCRYPTED_PREFIX};
+use crate::inference::types::{ContentItem, Message, StopReason, Usage};
+use crate::store::Store;
+
+const AUDIT_FILE: &str = "audit.jsonl";
+
+// The `prev` of the first entry of a log that was never pruned
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/*
+    * The opt-in audit log of every prompt sent to a model and the response that came back,
+    * one JSON object per line in `.pprog/audit.jsonl`.  Each entry carries the SHA-256 hash
+    * of the one before it and its own hash over both, so editing, removing or reordering an
+    * entry breaks the chain from there on, which `verify` finds.  Entries older than
+    * `retention_days` are pruned when pprog starts, the first entry kept then links to the
+    * last one removed.  The file is only readable by its owner, and with the session store
+    * encrypted every line is encrypted with its key, the hashes are over the plaintext.
+*/
+struct AuditLog {
+    state: Mutex<Chain>,
+}
+
+struct Chain {
+    file: File,
+    seq: u64,
+    last_hash: String,
+    cipher: Option<Cipher>,
+}
+
+static AUDIT: OnceLock<AuditLog> = OnceLock::new();
+
+/// How a log checked out with `verify`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verification {
+    Intact { entries: usize },
+    /// The 1-based number of the first line that doesn't follow from the ones before it
+    Broken { line: usize, reason: String },
+}
+
+/// Starts the audit log when `config.enabled` is set, pruning entries past the retention.
+pub fn init(config: &AuditConfig) -> Result<(), anyhow::Error> {
+    if !config.enabled {
+        return Ok(());
+    }
+    let path = project_log()?;
+    let cipher = Store::open()?.cipher().cloned();
+    if config.retention_days > 0 {
+        prune(&path, Utc::now() - chrono::Duration::days(config.retention_days as i64), cipher.as_ref())?;
+    }
+    let (seq, last_hash) = match fs::read_to_string(&path) {
+        Ok(text) => match text.lines().last().map(|line| open_line(line, cipher.as_ref())).transpose()? {
+            Some(last) => {
+                let last = serde_json::from_str::<Value>(&last)?;
+                (last["seq"].as_u64().unwrap_or_default(), last["hash"].as_str().unwrap_or(GENESIS).to_string())
+            },
+            None => (0, GENESIS.to_string()),
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (0, GENESIS.to_string()),
+        Err(e) => return Err(anyhow::anyhow!("Could not read the audit log {}: {}", path.display(), e)),
+    };
+    let file = ProjectConfig::private_file().create(true).append(true).open(&path)?;
+    let _ = AUDIT.set(AuditLog { state: Mutex::new(Chain { file, seq, last_hash, cipher }) });
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    AUDIT.get().is_some()
+}
+
+/// Records a prompt about to be sent.
+pub fn record_prompt(provider: &str, model: &str, system: Option<&str>, messages: &[Message]) {
+    if !enabled() {
+        return;
+    }
+    append(json!({
+        "type": "prompt",
+        "provider": provider,
+        "model": model,
+        "system": system,
+        "messages": messages,
+    }));
+}
+
+/// A response's content, stop reason and usage.
+pub type Reply<'a> = (&'a [ContentItem], Option<&'a StopReason>, Option<&'a Usage>);
+
+/// Records what came back for the last prompt, the reply or why there was none.
+pub fn record_response(provider: &str, model: &str, reply: Result<Reply, &anyhow::Error>) {
+    if !enabled() {
+        return;
+    }
+    let entry = match reply {
+        Ok((content, stop_reason, usage)) => json!({
+            "type": "response",
+            "provider": provider,
+            "model": model,
+            "content": content,
+            "stop_reason": stop_reason,
+            "usage": usage,
+        }),
+        Err(e) => json!({"type": "response", "provider": provider, "model": model, "error": e.to_string()}),
+    };
+    append(entry);
+}
+
+fn append(entry: Value) {
+    let Some(audit) = AUDIT.get() else { return };
+    if let Err(e) = audit.state.lock().unwrap().write(entry) {
+        log::error!("Failed to write the audit log: {}", e);
+    }
+}
+
+// The JSON of a line of the log, decrypted with `cipher`
+fn open_line(line: &str, cipher: Option<&Cipher>) -> Result<String, anyhow::Error> {
+    match cipher {
+        Some(cipher) => cipher.decrypt(line),
+        None if line.starts_with(ENCRYPTED_PREFIX) => Err(anyhow::anyhow!("The audit log is encrypted but no key was loaded")),
+        None => Ok(line.to_string()),
+    }
+}
+
+// The hash over the previous hash and the entry without its own `hash`
+fn entry_hash(prev: &str, entry: &Value) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(prev.as_bytes());
+    hasher.update(entry.to_string().as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+impl Chain {
+    fn link(&mut self, mut entry: Value, time: DateTime<Utc>) -> Value {
+        self.seq += 1;
+        entry["seq"] = json!(self.seq);
+        entry["time"] = json!(time.to_rfc3339());
+        entry["user"] = json!(std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or_default());
+        entry["prev"] = json!(self.last_hash);
+        let hash = entry_hash(&self.last_hash, &entry);
+        entry["hash"] = json!(hash);
+        self.last_hash = hash;
+        entry
+    }
+
+    // Links `entry` and appends it, encrypted when there is a cipher and never in the clear then
+    fn write(&mut self, entry: Value) -> Result<(), anyhow::Error> {
+        let entry = self.link(entry, Utc::now()).to_string();
+        let line = match &self.cipher {
+            Some(cipher) => cipher.encrypt(&entry)?,
+            None => entry,
+        };
+        writeln!(self.file, "{}", line)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+// Drops the entries from before `cutoff`, the oldest come first
+fn prune(path: &Path, cutoff: DateTime<Utc>, cipher: Option<&Cipher>) -> Result<(), anyhow::Error> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut kept = Vec::new();
+    for line in text.lines() {
+        let time = serde_json::from_str::<Value>(&open_line(line, cipher)?).ok()
+            .and_then(|entry| entry["time"].as_str().and_then(|time| DateTime::parse_from_rfc3339(time).ok()));
+        if !kept.is_empty() || time.is_none_or(|time| time >= cutoff) {
+            kept.push(line);
+        }
+    }
+    if kept.len() < text.lines().count() {
+        let mut text = kept.join("\n");
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        ProjectConfig::private_file().create(true).write(true).truncate(true).open(path)?.write_all(text.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Checks that every entry of the log at `path` hashes to its `hash` and links to the one
+/// before, decrypting its lines with `cipher`.
+pub fn verify(path: &Path, cipher: Option<&Cipher>) -> Result<Verification, anyhow::Error> {
+    let text = fs::read_to_string(path)?;
+    let mut prev: Option<String> = None;
+    let mut count = 0;
+    for (index, line) in text.lines().enumerate() {
+        let broken = |reason: &str| Ok(Verification::Broken { line: index + 1, reason: reason.to_string() });
+        let line = match (cipher, line.starts_with(ENCRYPTED_PREFIX)) {
+            (Some(cipher), true) => match cipher.decrypt(line) {
+                Ok(line) => line,
+                Err(_) => return broken("can't be decrypted, the entry was changed"),
+            },
+            _ => open_line(line, cipher)?,
+        };
+        let Ok(mut entry) = serde_json::from_str::<Value>(&line) else { return broken("not JSON") };
+        let Some(hash) = entry.as_object_mut().and_then(|fields| fields.remove("hash")) else { return broken("no hash") };
+        let link = entry["prev"].as_str().unwrap_or_default().to_string();
+        if prev.as_ref().is_some_and(|prev| *prev != link) {
+            return broken("doesn't follow the entry before it, an entry was removed or reordered");
+        }
+        if hash.as_str() != Some(entry_hash(&link, &entry).as_str()) {
+            return broken("its hash doesn't match, the entry was changed");
+        }
+        prev = hash.as_str().map(str::to_string);
+        count += 1;
+    }
+    Ok(Verification::Intact { entries: count })
+}
+
+/// The log of this project, for `pprog audit verify`.
+pub fn project_log() -> Result<PathBuf, anyhow::Error> {
+    Ok(ProjectConfig::data_dir()?.join(AUDIT_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain_and_verify() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(AUDIT_FILE);
+        let file = ProjectConfig::private_file().create(true).append(true).open(&path)?;
+        let mut chain = Chain { file, seq: 0, last_hash: GENESIS.to_string(), cipher: None };
+        let old = Utc::now() - chrono::Duration::days(40);
+        let lines = [
+            chain.link(json!({"type": "prompt", "messages": []}), old),
+            chain.link(json!({"type": "response", "content": []}), old),
+            chain.link(json!({"type": "prompt", "messages": [{"role": "user", "content": "hi"}]}), Utc::now()),
+            chain.link(json!({"type": "response", "content": [{"type": "text", "text": "hello"}]}), Utc::now()),
+        ].map(|entry| entry.to_string());
+        fs::write(&path, format!("{}\n", lines.join("\n")))?;
+        assert_eq!(verify(&path, None)?, Verification::Intact { entries: 4 });
+
+        fs::write(&path, format!("{}\n", lines.join("\n").replace("hello", "goodbye")))?;
+        assert!(matches!(verify(&path, None)?, Verification::Broken { line: 4, .. }));
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[2]))?;
+        assert!(matches!(verify(&path, None)?, Verification::Broken { line: 2, .. }));
+
+        fs::write(&path, format!("{}\n", lines.join("\n")))?;
+        prune(&path, Utc::now() - chrono::Duration::days(30), None)?;
+        assert_eq!(fs::read_to_string(&path)?.lines().count(), 2);
+        assert_eq!(verify(&path, None)?, Verification::Intact { entries: 2 });
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypted_log() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(AUDIT_FILE);
+        let cipher = Cipher::from_key(&Cipher::generate_key()?)?;
+        let file = ProjectConfig::private_file().create(true).append(true).open(&path)?;
+        let mut chain = Chain { file, seq: 0, last_hash: GENESIS.to_string(), cipher: Some(cipher.clone()) };
+        chain.write(json!({"type": "prompt", "messages": [{"role": "user", "content": "the launch code"}]}))?;
+        chain.write(json!({"type": "response", "content": []}))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        }
+        let text = fs::read_to_string(&path)?;
+        assert!(text.lines().all(|line| line.starts_with(ENCRYPTED_PREFIX)) && !text.contains("launch"));
+        assert_eq!(verify(&path, Some(&cipher))?, Verification::Intact { entries: 2 });
+        assert!(verify(&path, None).is_err());
+
+        let other = Cipher::from_key(&Cipher::generate_key()?)?;
+        assert!(matches!(verify(&path, Some(&other))?, Verification::Broken { line: 1, .. }));
+        Ok(())
+    }
+}
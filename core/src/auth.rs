@@ -0,0 +1,145 @@
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::config::{AuthConfig, OAuthConfig};
+use crate::crypto::{keychain_lookup, keychain_store};
+use crate::inference::types::InferenceError;
+use crate::transport;
+
+/// Why the provider turned a request down for its credentials.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthFailure {
+    /// A key or token that worked but ran out, refreshing it may help
+    Expired,
+    /// A key the provider doesn't know, mistyped or revoked
+    Invalid,
+    /// A valid key not allowed to use the model or endpoint
+    Forbidden,
+}
+
+impl AuthFailure {
+    /// The failure behind `error`, None when it isn't a 401 or 403 from the provider.
+    pub fn of(error: &anyhow::Error) -> Option<Self> {
+        let (status, body) = error.chain().find_map(|cause| match cause.downcast_ref::<InferenceError>() {
+            Some(InferenceError::ApiError(status, body)) => Some((status.as_u16(), body.to_lowercase())),
+            _ => None,
+        })?;
+        // Providers say so in the body, the status is the same for a key that never worked
+        let expired = body.contains("expired") || body.contains("invalid_token");
+        match status {
+            401 | 403 if expired => Some(AuthFailure::Expired),
+            401 => Some(AuthFailure::Invalid),
+            403 => Some(AuthFailure::Forbidden),
+            _ => None,
+        }
+    }
+
+    pub fn describe(&self, provider: &str) -> String {
+        match self {
+            AuthFailure::Expired => format!("The {} credentials have expired", provider),
+            AuthFailure::Invalid => format!("The {} API key was rejected as invalid", provider),
+            AuthFailure::Forbidden => format!("The {} API key is not allowed to make this request", provider),
+        }
+    }
+
+    /// What to tell the user once nothing fixed it.
+    pub fn advice(&self, provider: &str) -> String {
+        format!("{}, set api_key in pprog.toml or [auth] to refresh it", self.describe(provider))
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    refresh_token: Option<String>,
+}
+
+// Trades the refresh token in the keychain for an access token, keeping a rotated refresh token
+async fn refresh_oauth(oauth: &OAuthConfig) -> Result<Option<String>, anyhow::Error> {
+    let Some(refresh_token) = keychain_lookup(&oauth.refresh_keychain)? else {
+        anyhow::bail!("No refresh token under {} in the OS keychain", oauth.refresh_keychain);
+    };
+    let mut form = vec![("grant_type", "refresh_token"), ("refresh_token", refresh_token.trim())];
+    if !oauth.client_id.is_empty() {
+        form.push(("client_id", oauth.client_id.as_str()));
+    }
+    let response = transport::client().post(&oauth.token_url).form(&form).send().await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        anyhow::bail!("Token refresh at {} failed ({}): {}", oauth.token_url, status, response.text().await.unwrap_or_default());
+    }
+    let tokens: TokenResponse = response.json().await?;
+    if let Some(rotated) = tokens.refresh_token.filter(|rotated| rotated != refresh_token.trim()) {
+        keychain_store(&oauth.refresh_keychain, "pprog OAuth refresh token", &rotated)?;
+    }
+    Ok(Some(tokens.access_token))
+}
+
+fn run_key_cmd(key_cmd: &str) -> Result<Option<String>, anyhow::Error> {
+    let output = Command::new("bash").arg("-c").arg(key_cmd).output()?;
+    if !output.status.success() {
+        anyhow::bail!("{} ended with {}: {}", key_cmd, output.status, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(Some(String::from_utf8(output.stdout)?))
+}
+
+/*
+    * A key from the sources in `config` that isn't `current`, without asking anyone: the
+    * output of `key_cmd`, the `keychain` entry, then an OAuth refresh.  A refreshed token is
+    * stored in the `keychain` entry so the next run starts with it.  A source that fails is
+    * logged and the next one tried, None when none gave a new key.
+*/
+pub async fn refresh(config: &AuthConfig, current: &str) -> Option<String> {
+    let fresh = |key: Option<String>| key.map(|key| key.trim().to_string()).filter(|key| !key.is_empty() && key != current);
+    if !config.key_cmd.is_empty() {
+        match run_key_cmd(&config.key_cmd) {
+            Ok(key) => if let Some(key) = fresh(key) { return Some(key) },
+            Err(e) => log::error!("Failed to get an API key from auth.key_cmd: {}", e),
+        }
+    }
+    if !config.keychain.is_empty() {
+        match keychain_lookup(&config.keychain) {
+            Ok(key) => if let Some(key) = fresh(key) { return Some(key) },
+            Err(e) => log::error!("Failed to read the API key {} from the OS keychain: {}", config.keychain, e),
+        }
+    }
+    let oauth = config.oauth.as_ref()?;
+    match refresh_oauth(oauth).await {
+        Ok(token) => {
+            let token = fresh(token)?;
+            if !config.keychain.is_empty() {
+                if let Err(e) = keychain_store(&config.keychain, "pprog API token", &token) {
+                    log::error!("Failed to keep the refreshed token: {}", e);
+                }
+            }
+            Some(token)
+        },
+        Err(e) => {
+            log::error!("{}", e);
+            None
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_auth() {
+        let error = |status: u16, body: &str| anyhow::Error::new(InferenceError::ApiError(reqwest::StatusCode::from_u16(status).unwrap(), body.to_string()))
+            .context("Could not send");
+        assert_eq!(AuthFailure::of(&error(401, r#"{"error":{"message":"invalid x-api-key"}}"#)), Some(AuthFailure::Invalid));
+        assert_eq!(AuthFailure::of(&error(401, r#"{"error":{"code":"token_expired"}}"#)), Some(AuthFailure::Expired));
+        assert_eq!(AuthFailure::of(&error(403, "Your credit balance is too low")), Some(AuthFailure::Forbidden));
+        assert_eq!(AuthFailure::of(&error(429, "Rate limited")), None);
+        assert_eq!(AuthFailure::of(&anyhow::anyhow!("Response cancelled")), None);
+
+        let config = AuthConfig { key_cmd: "echo sk-fresh".to_string(), ..Default::default() };
+        assert_eq!(refresh(&config, "sk-stale").await.as_deref(), Some("sk-fresh"));
+        assert_eq!(refresh(&config, "sk-fresh").await, None);
+        assert_eq!(refresh(&AuthConfig { key_cmd: "exit 1".to_string(), ..Default::default() }, "").await, None);
+    }
+}
@@ -0,0 +1,140 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use similar::TextDiff;
+
+use crate::config::ProjectConfig;
+
+/// State of one file before the agent first touched it during a turn.  `object` is the
+/// hash of the stored contents, or `None` if the file didn't exist yet.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FileSnapshot {
+    pub path: String,
+    pub object: Option<String>,
+}
+
+/*
+    * A checkpoint records the pre-turn contents of every file the agent writes during one
+    * turn.  File contents live in a content-addressed object store so unchanged files are
+    * only stored once no matter how many checkpoints reference them, and restoring never
+    * depends on git.
+*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub session_id: String,
+    pub files: Vec<FileSnapshot>,
+}
+
+impl Checkpoint {
+    pub fn new(session_id: &str) -> Self {
+        let created_at = Utc::now();
+        Checkpoint {
+            id: format!("{}-{}", created_at.format("%Y%m%dT%H%M%S%3f"), &session_id[..session_id.len().min(8)]),
+            created_at,
+            session_id: session_id.to_string(),
+            files: Vec::new(),
+        }
+    }
+
+    fn dir() -> Result<PathBuf, anyhow::Error> {
+        let dir = ProjectConfig::data_dir()?.join("checkpoints");
+        fs::create_dir_all(dir.join("objects"))?;
+        Ok(dir)
+    }
+
+    fn store_object(contents: &[u8]) -> Result<String, anyhow::Error> {
+        let hash = format!("{:x}", Sha256::digest(contents));
+        let path = Self::dir()?.join("objects").join(&hash);
+        if !path.exists() {
+            fs::write(path, contents)?;
+        }
+        Ok(hash)
+    }
+
+    /// Records the current state of `path` unless it was already captured this turn.
+    pub fn snapshot(&mut self, root: &Path, path: &str) -> Result<(), anyhow::Error> {
+        if self.files.iter().any(|file| file.path == path) {
+            return Ok(());
+        }
+
+        let full_path = root.join(path);
+        let object = if full_path.is_file() {
+            Some(Self::store_object(&fs::read(&full_path)?)?)
+        } else {
+            None
+        };
+
+        self.files.push(FileSnapshot { path: path.to_string(), object });
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), anyhow::Error> {
+        let path = Self::dir()?.join(format!("{}.json", self.id));
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// The most recent checkpoint, if any.
+    pub fn latest() -> Result<Option<Checkpoint>, anyhow::Error> {
+        let mut latest: Option<Checkpoint> = None;
+        for entry in fs::read_dir(Self::dir()?)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                let checkpoint: Checkpoint = serde_json::from_str(&fs::read_to_string(&path)?)?;
+                if latest.as_ref().is_none_or(|current| checkpoint.created_at > current.created_at) {
+                    latest = Some(checkpoint);
+                }
+            }
+        }
+        Ok(latest)
+    }
+
+    /// A unified diff of every file from before the turn to what is on disk now.
+    pub fn diff(&self, root: &Path) -> Result<String, anyhow::Error> {
+        let dir = Self::dir()?;
+        let mut diff = String::new();
+        for file in &self.files {
+            let before = match &file.object {
+                Some(hash) => Some(fs::read_to_string(dir.join("objects").join(hash))?),
+                None => None,
+            };
+            let after = fs::read_to_string(root.join(&file.path)).ok();
+            let old_header = if before.is_some() { file.path.as_str() } else { "/dev/null" };
+            let new_header = if after.is_some() { file.path.as_str() } else { "/dev/null" };
+            let before = before.unwrap_or_default();
+            let after = after.unwrap_or_default();
+            diff.push_str(&TextDiff::from_lines(&before, &after).unified_diff().header(old_header, new_header).to_string());
+        }
+        Ok(diff)
+    }
+
+    /// Puts every file back the way it was before the turn and discards the checkpoint so
+    /// the next undo steps further back.  Returns the restored paths.
+    pub fn restore(&self, root: &Path) -> Result<Vec<String>, anyhow::Error> {
+        let dir = Self::dir()?;
+        for file in &self.files {
+            let full_path = root.join(&file.path);
+            match &file.object {
+                Some(hash) => {
+                    if let Some(parent) = full_path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::write(&full_path, fs::read(dir.join("objects").join(hash))?)?;
+                },
+                None => {
+                    if full_path.exists() {
+                        fs::remove_file(&full_path)?;
+                    }
+                },
+            }
+        }
+
+        fs::remove_file(dir.join(format!("{}.json", self.id)))?;
+        Ok(self.files.iter().map(|file| file.path.clone()).collect())
+    }
+}
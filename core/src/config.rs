@@ -0,0 +1,1043 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+use crate::inference::middleware;
+use crate::permissions::ProjectPolicy;
+use crate::pii::Scrubber;
+use crate::policy::CommandPolicy;
+use crate::secrets;
+use crate::tree::GitTree;
+
+static SHARED: OnceLock<Arc<ProjectConfig>> = OnceLock::new();
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectConfig {
+    pub model: String,
+    pub check_cmd: String,
+    #[serde(default)]
+    pub base_url: String,
+    #[serde(default)]
+    pub api_key: String,
+    #[serde(default)]
+    pub max_context: usize,
+    #[serde(default)]
+    pub max_output_tokens: u32,
+    #[serde(default)]
+    pub provider: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// Sent to providers that take a seed, OpenAI and compatible servers, for replies that repeat
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Sample at temperature 0 with `seed`, or 0 when unset, for runs as repeatable as the API allows
+    #[serde(default)]
+    pub deterministic: bool,
+    /// Text that ends a reply where the model writes it, like `</answer>` for a script reading up to a marker
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop_sequences: Vec<String>,
+    /// Cheaper model used for housekeeping calls like compaction, defaults to `model`
+    #[serde(default)]
+    pub small_model: String,
+    /// Fraction of the context window at which older turns are summarized
+    #[serde(default = "default_compact_threshold")]
+    pub compact_threshold: f32,
+    /// Messages of a session kept in memory, older ones are left in the store, 0 keeps them all
+    #[serde(default = "default_history_messages")]
+    pub history_messages: usize,
+    /// Encrypt session contents in `.pprog/pprog.db` with a key from the OS keychain
+    #[serde(default)]
+    pub encrypt_sessions: bool,
+    /// Colors for code blocks in the terminal chat: `dark`, `light` or `none`, defaults to `theme.preset`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub highlight_theme: String,
+    /// Seconds a chat turn has to run before a desktop notification is sent when it ends, 0 for never
+    #[serde(default = "default_notify_after_secs")]
+    pub notify_after_secs: u64,
+    /// Rounds of tool calls a turn may take before it is stopped, 0 for no limit
+    #[serde(default = "default_max_turns")]
+    pub max_turns: usize,
+    /// Identical tool calls, with no file changed in between, that stop a turn as stuck, 0 for never
+    #[serde(default = "default_max_repeats")]
+    pub max_repeats: usize,
+    /// Failed calls of one tool in a row sent back to the model to correct before the turn stops, 0 for no limit
+    #[serde(default = "default_max_tool_retries")]
+    pub max_tool_retries: usize,
+    /// Replies asked for in all before structured output gives up, each retry told what was wrong with the last
+    #[serde(default = "default_structured_attempts")]
+    pub structured_attempts: usize,
+    /// Fix trailing commas and prose around structured output locally before asking again
+    #[serde(default = "default_repair_json")]
+    pub repair_json: bool,
+    /// Paths left out of the file tree and repository map, in `.gitignore` syntax on top of `.gitignore` itself
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+    /// Give `small_model` the first try at each message, handing it to `model` when it's unsure or needs tools
+    #[serde(default)]
+    pub cheap_first: bool,
+    /// Requests larger than this many tokens go straight to `model` with `cheap_first`
+    #[serde(default = "default_cheap_first_max_tokens")]
+    pub cheap_first_max_tokens: usize,
+    /// Docs searched for excerpts related to each message, added to the system prompt, in `.gitignore` syntax
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
+    /// Excerpts of `docs` added for each message
+    #[serde(default = "default_docs_results")]
+    pub docs_results: usize,
+    /// Tokens the map of public declarations in the system prompt may take, 0 to leave it out
+    #[serde(default = "default_repo_map_tokens")]
+    pub repo_map_tokens: usize,
+    /// Send the system prompt with repeated instructions, markdown decoration and extra whitespace taken out, see `prompt::compress`
+    #[serde(default)]
+    pub compress_prompt: bool,
+    /// Tools a sub-agent started with the `task` tool may use
+    #[serde(default = "default_subagent_tools")]
+    pub subagent_tools: Vec<String>,
+    /// Sub-agents that may run at once when several are started in one reply, 1 to run them in turn
+    #[serde(default = "default_max_parallel_tasks")]
+    pub max_parallel_tasks: usize,
+    /// Page output taller than the terminal, like `sessions show`, through $PAGER
+    #[serde(default = "default_pager")]
+    pub pager: bool,
+    /// Key chords for the terminal chat
+    #[serde(default)]
+    pub keys: KeyBindings,
+    /// Colors for the terminal chat
+    #[serde(default)]
+    pub theme: ThemeConfig,
+    /// Where the vectors for `semantic_search` come from
+    #[serde(default)]
+    pub embeddings: EmbeddingsConfig,
+    /// Log every provider request and response to `.pprog/wire.jsonl`, with credentials blanked out
+    #[serde(default)]
+    pub wire_log: bool,
+    /// Regexes of more secrets blanked out of the wire log and by the `redact` middleware, on top of API keys and the usual token formats
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redact: Vec<String>,
+    /// What happens to credentials found in messages and tool results before they are sent
+    #[serde(default)]
+    pub secrets: SecretsConfig,
+    /// Personal data swapped for placeholders in requests and back in replies, off by default
+    #[serde(default)]
+    pub pii: PiiConfig,
+    /// The steps every request and reply passes through, see `inference::middleware`
+    #[serde(default)]
+    pub middleware: MiddlewareConfig,
+    /// Where the file tools may read and write, see `Sandbox`
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+    /// The only tools the model may call, every one when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<String>>,
+    /// What the shell tools refuse to run and what they ask about first, see `CommandPolicy`
+    #[serde(default)]
+    pub commands: CommandsConfig,
+    /// The hosts `fetch_url` may connect to, see `Egress`
+    #[serde(default)]
+    pub egress: EgressConfig,
+    /// Where a new API key comes from when the provider rejects the one in use, see `auth::refresh`
+    #[serde(default)]
+    pub auth: AuthConfig,
+    /// Where traces and usage counters are exported over OTLP, off unless `endpoint` is set
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    /// Soft limits on what the project spends a day and a month
+    #[serde(default)]
+    pub budget: BudgetConfig,
+    /// The hash-chained log of every prompt and response kept for compliance, off by default
+    #[serde(default)]
+    pub audit: AuditConfig,
+    /// Example exchanges sent ahead of requests for a task, see `examples_for`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub examples: Vec<FewShotExample>,
+    /// The shape of the messages `pprog commit` writes
+    #[serde(default)]
+    pub commit: CommitConfig,
+    /// Where runs without a terminal, like `pprog -p` in a scheduled job, report how they went
+    #[serde(default)]
+    pub webhook: WebhookConfig,
+    /// What `pprog hook pre-commit` holds staged changes to
+    #[serde(default)]
+    pub hook: HookConfig,
+    /// `github` or `gitlab`, for an origin on a host whose name doesn't tell which of them it runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forge: Option<String>,
+    /// Setups for different kinds of work by name, picked with `--persona`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub personas: BTreeMap<String, Persona>,
+    /// The persona this run was started as, see `with_persona`
+    #[serde(skip)]
+    pub persona: Option<Persona>,
+    /// Requests go without the agent's tools, for the clients of the OpenAI-compatible proxy
+    #[serde(skip)]
+    pub no_tools: bool,
+}
+
+/// Key chords like `ctrl-x` or `alt-enter` for actions in `pprog chat` and `--tui`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct KeyBindings {
+    pub submit: String,
+    pub newline: String,
+    pub cancel: String,
+    pub editor: String,
+    pub regenerate: String,
+    pub approve_tool: String,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            submit: String::from("enter"),
+            newline: String::from("alt-enter"),
+            cancel: String::from("ctrl-c"),
+            editor: String::from("ctrl-x"),
+            regenerate: String::from("alt-r"),
+            approve_tool: String::from("ctrl-y"),
+        }
+    }
+}
+
+/// Colors for `pprog chat` and `--tui`.  `preset` is `dark`, `light` or `none`, the other
+/// keys override single colors with a name like `dark_cyan`, `#ff8700`, a 256-color number
+/// or `default` for the terminal's own color.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ThemeConfig {
+    pub preset: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assistant: Option<String>,
+    /// Tool calls and their results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool: Option<String>,
+    /// Hints, notices and the status line
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub info: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff_added: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff_removed: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff_hunk: Option<String>,
+}
+
+impl Default for ThemeConfig {
+    fn default() -> Self {
+        ThemeConfig {
+            preset: String::from("dark"),
+            user: None,
+            assistant: None,
+            tool: None,
+            info: None,
+            error: None,
+            diff_added: None,
+            diff_removed: None,
+            diff_hunk: None,
+        }
+    }
+}
+
+/// The embeddings API the workspace index is built with.  `provider` is `openai`, for the
+/// OpenAI API or a compatible server at `base_url`, `voyage` or `ollama`.  An empty `model`
+/// is the provider's default, and a changed one re-embeds every file.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct EmbeddingsConfig {
+    pub provider: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub model: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub base_url: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub api_key: String,
+}
+
+impl Default for EmbeddingsConfig {
+    fn default() -> Self {
+        EmbeddingsConfig {
+            provider: String::from("openai"),
+            model: String::new(),
+            base_url: String::new(),
+            api_key: String::new(),
+        }
+    }
+}
+
+/// An OpenTelemetry collector taking OTLP over HTTP, like `http://localhost:4318`.  `headers`
+/// go with every export, for a collector that wants an API key.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub endpoint: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    pub service_name: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig {
+            endpoint: String::new(),
+            headers: BTreeMap::new(),
+            service_name: String::from("pprog"),
+        }
+    }
+}
+
+/// Soft limits in USD on all requests from the project in the current local day and month.
+/// Crossing one prints a warning before the next request, and with `require_force` holds it
+/// back unless pprog was started with `--force`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(default)]
+pub struct BudgetConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monthly: Option<f64>,
+    pub require_force: bool,
+}
+
+/// Keys, tokens and passwords looked for in every message and tool result before it goes
+/// to the provider.  `action` is `mask` to send them as `[REDACTED <kind>]`, `block` to send
+/// nothing, or `off`.  Matches of `patterns` count as secrets on top of the usual formats, and
+/// of `allow` don't.  Random-looking text counts too at `min_entropy` bits a character or
+/// more, 0 to leave it at the formats.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct SecretsConfig {
+    pub action: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub patterns: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    pub min_entropy: f64,
+}
+
+impl Default for SecretsConfig {
+    fn default() -> Self {
+        SecretsConfig {
+            action: String::from("mask"),
+            patterns: Vec::new(),
+            allow: Vec::new(),
+            min_entropy: 4.0,
+        }
+    }
+}
+
+/// Emails, phone numbers and the values matching `patterns`, by the placeholder name they get,
+/// sent to the provider as placeholders like `[EMAIL_1]` when `enabled`.  The placeholders are
+/// put back in replies, tool calls included.  Values matching one of `allow` are sent as is.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct PiiConfig {
+    pub enabled: bool,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub patterns: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+}
+
+/// Middleware requests pass through in the order of `chain`: `redact` blanks secrets out of
+/// them, `log` logs them and `cache` answers one sent before from `.pprog/cache` for
+/// `cache_ttl_secs`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct MiddlewareConfig {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub chain: Vec<String>,
+    pub cache_ttl_secs: u64,
+}
+
+impl Default for MiddlewareConfig {
+    fn default() -> Self {
+        MiddlewareConfig {
+            chain: Vec::new(),
+            cache_ttl_secs: 86_400,
+        }
+    }
+}
+
+/// The directory the file tools are held to, the git root unless `root` is set, and
+/// directories outside it they may read from but not write to.  Both are relative to the git
+/// root unless absolute.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct SandboxConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub read_only: Vec<String>,
+}
+
+/// The commands `execute` and `compile_check` refuse, matching one of `deny`, and run only
+/// once the user allows them, matching one of `ask`.  Both come on top of the rules pprog
+/// ships unless `defaults` is off, and a command matching one of the `allow` regexes runs
+/// whatever else it matches.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct CommandsConfig {
+    pub defaults: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<CommandRule>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ask: Vec<CommandRule>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+}
+
+impl Default for CommandsConfig {
+    fn default() -> Self {
+        CommandsConfig {
+            defaults: true,
+            deny: Vec::new(),
+            ask: Vec::new(),
+            allow: Vec::new(),
+        }
+    }
+}
+
+/// A regex for commands and the reason given to the model when one is held back, `name`
+/// the pattern itself unless set.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CommandRule {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    pub pattern: String,
+    #[serde(default)]
+    pub reason: String,
+}
+
+/// The hosts the web tools may reach: none matching `deny`, only those matching `allow` when
+/// it's set, and none on a private address unless `allow_private`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct EgressConfig {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+    pub allow_private: bool,
+}
+
+/// Where a key is fetched when `api_key` is empty or the provider rejects it: the output of
+/// `key_cmd`, the OS keychain entry `keychain`, then an `oauth` refresh.  Nothing is tried
+/// for a source left empty.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct AuthConfig {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub key_cmd: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub keychain: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oauth: Option<OAuthConfig>,
+}
+
+/// A token endpoint taking `grant_type=refresh_token`, the refresh token kept in the OS
+/// keychain entry `refresh_keychain`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OAuthConfig {
+    pub token_url: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub client_id: String,
+    #[serde(default = "default_refresh_keychain")]
+    pub refresh_keychain: String,
+}
+
+fn default_refresh_keychain() -> String {
+    String::from("oauth-refresh-token")
+}
+
+/// The audit log in `.pprog/audit.jsonl`, kept when `enabled`.  Entries older than
+/// `retention_days` are dropped when pprog starts, 0 keeps them all.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    pub retention_days: u32,
+}
+
+/// Conventional Commits messages from `pprog commit`, `type` one of `types` and the scope one
+/// of `scopes`, or any when it's empty.  `template` lays the message out from `{type}`,
+/// `{scope}` (`(scope)` or nothing), `{breaking}` (`!` or nothing), `{subject}` and `{body}`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct CommitConfig {
+    pub types: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+    pub template: String,
+    pub max_subject: usize,
+}
+
+impl Default for CommitConfig {
+    fn default() -> Self {
+        CommitConfig {
+            types: ["feat", "fix", "docs", "style", "refactor", "perf", "test", "build", "ci", "chore", "revert"]
+                .map(String::from)
+                .to_vec(),
+            scopes: Vec::new(),
+            template: String::from("{type}{scope}{breaking}: {subject}\n\n{body}"),
+            max_subject: 72,
+        }
+    }
+}
+
+/// A JSON summary POSTed to `url` when a `pprog -p` or forge run ends, off while it's empty.
+/// Its `text` is what Slack and compatible incoming webhooks show.  `transcript_url` links
+/// the run, `{session}` in it replaced by the session id, and `only_failures` skips the runs
+/// that went well.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub transcript_url: String,
+    pub only_failures: bool,
+}
+
+/// The review of staged changes in `pprog hook pre-commit`, which fails on any change that
+/// breaks one of `policies`.  It uses `model`, or `small_model` when that's empty, and gives up
+/// after `timeout_secs` or before a request that could take it past `max_cost` USD, letting
+/// the commit through unless `strict`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct HookConfig {
+    pub policies: Vec<String>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub model: String,
+    pub timeout_secs: u64,
+    pub max_cost: f64,
+    pub strict: bool,
+}
+
+impl Default for HookConfig {
+    fn default() -> Self {
+        HookConfig {
+            policies: vec![
+                String::from("No secrets, like API keys, tokens, passwords or private keys"),
+                String::from("No TODO or FIXME comments on public functions, types or modules"),
+                String::from("No debugging output left behind, like dbg!, console.log or stray prints"),
+            ],
+            model: String::new(),
+            timeout_secs: 30,
+            max_cost: 0.05,
+            strict: false,
+        }
+    }
+}
+
+/// A setup for one kind of work, like reviewing for security issues.  `prompt` is added to
+/// the system prompt, `model` and `temperature` replace the top-level ones when set, and a
+/// non-empty `tools` is the only tools the agent may use.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(default)]
+pub struct Persona {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub prompt: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub model: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+}
+
+impl Persona {
+    /// Added to the system prompt.
+    pub fn system_prompt(&self) -> String {
+        let mut prompt = String::new();
+        if !self.prompt.trim().is_empty() {
+            prompt.push_str(&format!("\n{}\n", self.prompt.trim()));
+        }
+        if !self.tools.is_empty() {
+            prompt.push_str(&format!("\nOnly these tools are available: {}.\n", self.tools.join(", ")));
+        }
+        prompt
+    }
+
+    pub fn allows(&self, tool: &str) -> bool {
+        self.tools.is_empty() || self.tools.iter().any(|allowed| allowed == tool)
+    }
+}
+
+/// An input/output pair shown to the model as an earlier exchange.  `task` is one of
+/// `chat`, `title` or `compact`, matching the request the example is meant for.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FewShotExample {
+    pub task: String,
+    pub input: String,
+    pub output: String,
+}
+
+fn default_history_messages() -> usize {
+    1000
+}
+
+fn default_compact_threshold() -> f32 {
+    0.8
+}
+
+fn default_notify_after_secs() -> u64 {
+    60
+}
+
+fn default_max_turns() -> usize {
+    25
+}
+
+fn default_max_repeats() -> usize {
+    3
+}
+
+fn default_cheap_first_max_tokens() -> usize {
+    16000
+}
+
+fn default_docs_results() -> usize {
+    4
+}
+
+fn default_max_tool_retries() -> usize {
+    3
+}
+
+fn default_structured_attempts() -> usize {
+    3
+}
+
+fn default_repair_json() -> bool {
+    true
+}
+
+fn default_repo_map_tokens() -> usize {
+    2000
+}
+
+fn default_subagent_tools() -> Vec<String> {
+    vec!["read_file".to_string(), "execute".to_string()]
+}
+
+fn default_max_parallel_tasks() -> usize {
+    4
+}
+
+fn default_pager() -> bool {
+    true
+}
+
+impl Default for ProjectConfig {
+    fn default() -> Self {
+        ProjectConfig {
+            model: String::from("claude-3-5-haiku-latest"),
+            check_cmd: String::new(),
+            base_url: String::from("https://api.anthropic.com/v1"),
+            api_key: String::new(),
+            max_context: 100000,
+            max_output_tokens: 8096,
+            provider: String::from("anthropic"),
+            temperature: None,
+            seed: None,
+            deterministic: false,
+            stop_sequences: Vec::new(),
+            small_model: String::new(),
+            compact_threshold: default_compact_threshold(),
+            history_messages: default_history_messages(),
+            encrypt_sessions: false,
+            highlight_theme: String::new(),
+            notify_after_secs: default_notify_after_secs(),
+            max_turns: default_max_turns(),
+            max_repeats: default_max_repeats(),
+            max_tool_retries: default_max_tool_retries(),
+            structured_attempts: default_structured_attempts(),
+            repair_json: default_repair_json(),
+            ignore: Vec::new(),
+            cheap_first: false,
+            cheap_first_max_tokens: default_cheap_first_max_tokens(),
+            docs: Vec::new(),
+            docs_results: default_docs_results(),
+            repo_map_tokens: default_repo_map_tokens(),
+            compress_prompt: false,
+            subagent_tools: default_subagent_tools(),
+            max_parallel_tasks: default_max_parallel_tasks(),
+            pager: default_pager(),
+            keys: KeyBindings::default(),
+            theme: ThemeConfig::default(),
+            embeddings: EmbeddingsConfig::default(),
+            wire_log: false,
+            redact: Vec::new(),
+            secrets: SecretsConfig::default(),
+            pii: PiiConfig::default(),
+            middleware: MiddlewareConfig::default(),
+            sandbox: SandboxConfig::default(),
+            tools: None,
+            commands: CommandsConfig::default(),
+            egress: EgressConfig::default(),
+            auth: AuthConfig::default(),
+            telemetry: TelemetryConfig::default(),
+            budget: BudgetConfig::default(),
+            audit: AuditConfig::default(),
+            examples: Vec::new(),
+            commit: CommitConfig::default(),
+            webhook: WebhookConfig::default(),
+            hook: HookConfig::default(),
+            forge: None,
+            personas: BTreeMap::new(),
+            persona: None,
+            no_tools: false,
+        }
+    }
+}
+
+impl ProjectConfig {
+    pub const CONFIG_FILE: &'static str = "pprog.toml";
+    pub const DATA_DIR: &'static str = ".pprog";
+    const DATA_GITIGNORE: &'static str = "# pprog runtime state\npprog.db*\nsessions.imported/\ncheckpoints/\nreplay/\nlocks/\nhistory\nindex.db*\nwire.jsonl\naudit.jsonl\nlast_exchange.json\ndebug-*.json\nserve-token\ncache/\n";
+
+    fn detect_check_cmd() -> String {
+        let root_path = match GitTree::get_git_root() {
+            Ok(root) => root,
+            Err(e) => {
+                println!("Unable to determine Git root directory: {}", e);
+                std::process::exit(1);
+            }
+        };
+
+        // Check for Rust project (Cargo.toml)
+        if root_path.join("Cargo.toml").exists() {
+            println!("Detected Rust project");
+            return String::from("cargo check");
+        }
+        
+        // TODO if Typescript project should look through package.json first for any kind of build
+        // command and then default to running typescript compiler -> node {result of tsc}
+        if root_path.join("tsconfig.json").exists() {
+            println!("Detected TypeScript project");
+            return String::from("tsc --noEmit");
+        }
+        
+        // Check for Java project (gradlew)
+        if root_path.join("gradlew").exists() {
+            println!("Detected Java project");
+            return String::from("./gradlew check");
+        }
+        
+        if root_path.join("package.json").exists() {
+            println!("Detected Node.js project");
+            let package_json = std::fs::read_to_string(root_path.join("package.json")).unwrap_or_default();
+            let package_data: serde_json::Value = serde_json::from_str(&package_json).unwrap_or_default();
+            if let Some(main) = package_data.get("main").and_then(|v| v.as_str().map(String::from)) {
+                return format!("node {}", main);
+            }
+        }
+
+        println!("Unable to detect project type");
+        String::new()
+    }
+
+
+    pub fn config_path() -> Result<PathBuf, String> {
+        GitTree::get_git_root()
+            .map(|root| root.join(Self::CONFIG_FILE))
+            .map_err(|e| {
+                info!("Unable to determine Git root directory: {}", e);
+                format!("Unable to determine Git root directory: {}", e)
+            })
+    }
+
+    /// The temperature to send, 0 when `deterministic`.
+    pub fn sampling_temperature(&self) -> Option<f32> {
+        match self.deterministic {
+            true => Some(0.0),
+            false => self.temperature,
+        }
+    }
+
+    /// The seed to send to providers that take one.
+    pub fn sampling_seed(&self) -> Option<u64> {
+        match self.deterministic {
+            true => Some(self.seed.unwrap_or_default()),
+            false => self.seed,
+        }
+    }
+
+    /// The tokens `model` takes in a request, `max_context` when it is set.
+    pub fn context_window(&self) -> usize {
+        match self.max_context {
+            0 => crate::models::lookup(&self.model).map(|info| info.context_window).unwrap_or(100000),
+            max_context => max_context,
+        }
+    }
+
+    /// Copy of this config targeting the cheaper housekeeping model.
+    pub fn with_small_model(&self) -> Self {
+        let mut config = self.clone();
+        if !self.small_model.is_empty() {
+            config.model = self.small_model.clone();
+        }
+        config
+    }
+
+    /// Copy of this config set up as the persona called `name`.
+    pub fn with_persona(&self, name: &str) -> Result<Self, anyhow::Error> {
+        let persona = self.personas.get(name).ok_or_else(|| match self.personas.len() {
+            0 => anyhow::anyhow!("No persona {:?}, none are set up under [personas] in pprog.toml", name),
+            _ => anyhow::anyhow!("No persona {:?}, pick one of {}", name, self.personas.keys().cloned().collect::<Vec<String>>().join(", ")),
+        })?;
+        let mut config = self.clone();
+        if !persona.model.is_empty() {
+            config.model = persona.model.clone();
+        }
+        if persona.temperature.is_some() {
+            config.temperature = persona.temperature;
+        }
+        config.persona = Some(persona.clone());
+        Ok(config)
+    }
+
+    /// Directory under the git root holding pprog's per-project state.  Runtime
+    /// state is kept out of git by a `.gitignore` maintained inside the directory.
+    pub fn data_dir() -> Result<PathBuf, anyhow::Error> {
+        let dir = GitTree::get_git_root()?.join(Self::DATA_DIR);
+        fs::create_dir_all(&dir)?;
+
+        let gitignore_path = dir.join(".gitignore");
+        if fs::read_to_string(&gitignore_path).ok().as_deref() != Some(Self::DATA_GITIGNORE) {
+            fs::write(&gitignore_path, Self::DATA_GITIGNORE)?;
+        }
+        Ok(dir)
+    }
+
+    /// Options for a file under the data directory only its owner can read, the mode set as
+    /// it is created rather than after, when another user could already have opened it.
+    pub fn private_file() -> fs::OpenOptions {
+        let mut options = fs::OpenOptions::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options
+    }
+
+    pub fn examples_for<'a>(&'a self, task: &'a str) -> impl Iterator<Item = &'a FewShotExample> {
+        self.examples.iter().filter(move |example| example.task == task)
+    }
+
+    /// The colors for code blocks, those of the theme preset unless `highlight_theme` is set.
+    pub fn code_theme(&self) -> &str {
+        match self.highlight_theme.as_str() {
+            "" => &self.theme.preset,
+            name => name,
+        }
+    }
+
+    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        let config_path = Self::config_path()?;
+
+        let content = fs::read_to_string(config_path)?;
+        let mut config: ProjectConfig = toml::from_str(&content)?;
+        // The repository's own policy narrows what pprog.toml allows
+        let root = GitTree::get_git_root()?;
+        if let Some(policy) = ProjectPolicy::load(&root)? {
+            policy.apply(&mut config, &root)?;
+        }
+        config.check()?;
+        info!("Loaded project config: {:?}", config);
+        Ok(config)
+    }
+
+    /*
+        * The config of pprog.toml, the default when there is none, with the repository policy
+        * applied either way.  A pprog.toml that can't be read or doesn't parse is an error, and
+        * so is a policy that exists but doesn't parse or apply: running on the defaults would
+        * drop every restriction they add.
+    */
+    pub fn load_or_default() -> Result<Self, anyhow::Error> {
+        match GitTree::get_git_root() {
+            Ok(root) => Self::load_at(&root),
+            // Outside a repository there's no policy to apply
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// `load_or_default` for the repository at `git_root`.
+    pub fn load_at(git_root: &Path) -> Result<Self, anyhow::Error> {
+        let path = git_root.join(Self::CONFIG_FILE);
+        let mut config = match fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content).map_err(|e| anyhow::anyhow!("Invalid {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(anyhow::anyhow!("Could not read {}: {}", path.display(), e)),
+        };
+        if let Some(policy) = ProjectPolicy::load(git_root)? {
+            policy.apply(&mut config, git_root)?;
+        }
+        config.check()?;
+        Ok(config)
+    }
+
+    // The settings that only fail once compiled, checked on load so a broken one stops pprog
+    // at startup rather than being left out of the first session
+    fn check(&self) -> Result<(), anyhow::Error> {
+        CommandPolicy::new(&self.commands)?;
+        secrets::Guard::new(&self.secrets)?;
+        middleware::check(self)?;
+        if self.pii.enabled {
+            Scrubber::new(&self.pii)?;
+        }
+        Ok(())
+    }
+
+    /// The config as first loaded in this process, for code that would otherwise read
+    /// pprog.toml again each time.  Edits to the file after that are seen by `load` but not
+    /// here.  Fails like `load_or_default`, until a load succeeds.
+    pub fn shared() -> Result<Arc<ProjectConfig>, anyhow::Error> {
+        if let Some(config) = SHARED.get() {
+            return Ok(config.clone());
+        }
+        let config = Arc::new(Self::load_or_default()?);
+        Ok(SHARED.get_or_init(|| config).clone())
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let config_str = toml::to_string_pretty(self)?;
+        let config_path = Self::config_path()?;
+        fs::write(config_path, config_str)?;
+        info!("Saved project config");
+        Ok(())
+    }
+
+    pub fn init() -> Result<(), Box<dyn std::error::Error>> {
+        let config_dir = match GitTree::get_git_root() {
+            Ok(d) => d,
+            Err(_) => {
+                eprintln!("Could not find git root, please make sure git is initialized.");
+                std::process::exit(1);
+            }
+
+        };
+        let config_file = config_dir.join("pprog.toml");
+        if config_file.exists() {
+            return Err("Project already initialized".into());
+        }
+
+        // Try to get API key from environment first
+        let api_key = std::env::var("ANTHROPIC_API_KEY")
+            .or_else(|_| std::env::var("OPENAI_API_KEY"))
+            .unwrap_or_default();
+
+        // Detect appropriate check command
+        let check_cmd = Self::detect_check_cmd();
+
+        // Create config with detected values
+        let config = ProjectConfig {
+            check_cmd,
+            api_key,
+            ..ProjectConfig::default()
+        };
+        config.save()?;
+
+        info!("Initialized project with config: {:?}", config);
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_examples_round_trip_and_filter_by_task() -> Result<(), anyhow::Error> {
+        let content = r#"
+            model = "claude-3-5-sonnet-latest"
+            check_cmd = "cargo check"
+
+            [[examples]]
+            task = "title"
+            input = "User: fix the flaky test"
+            output = "Fix flaky test"
+
+            [[examples]]
+            task = "chat"
+            input = "add a flag"
+            output = "Which command should get it?"
+        "#;
+        let config: ProjectConfig = toml::from_str(content)?;
+        let titles: Vec<&str> = config.examples_for("title").map(|example| example.output.as_str()).collect();
+        assert_eq!(titles, vec!["Fix flaky test"]);
+
+        let reloaded: ProjectConfig = toml::from_str(&toml::to_string(&config)?)?;
+        assert_eq!(reloaded.examples, config.examples);
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_persona() -> Result<(), anyhow::Error> {
+        let content = r#"
+            model = "claude-3-5-haiku-latest"
+            check_cmd = "cargo check"
+
+            [personas.security-auditor]
+            prompt = "Look for injection and unsafe input handling."
+            model = "claude-3-5-sonnet-latest"
+            tools = ["read_file", "semantic_search"]
+            temperature = 0.0
+        "#;
+        let config: ProjectConfig = toml::from_str(content)?;
+        let auditor = config.with_persona("security-auditor")?;
+        assert_eq!(auditor.model, "claude-3-5-sonnet-latest");
+        assert_eq!(auditor.temperature, Some(0.0));
+        let persona = auditor.persona.as_ref().unwrap();
+        assert!(persona.allows("read_file") && !persona.allows("execute"));
+        assert!(persona.system_prompt().ends_with("Only these tools are available: read_file, semantic_search.\n"));
+        assert!(config.with_persona("reviewer").unwrap_err().to_string().contains("security-auditor"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_deterministic_sampling() {
+        let mut config = ProjectConfig { temperature: Some(0.7), ..Default::default() };
+        assert_eq!((config.sampling_temperature(), config.sampling_seed()), (Some(0.7), None));
+        config.deterministic = true;
+        assert_eq!((config.sampling_temperature(), config.sampling_seed()), (Some(0.0), Some(0)));
+        config.seed = Some(42);
+        assert_eq!(config.sampling_seed(), Some(42));
+    }
+
+    #[test]
+    fn test_broken_policy_stops_loading() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let repo = dir.path().join("repo");
+        std::fs::create_dir_all(dir.path().join("other"))?;
+        let policy = repo.join(ProjectConfig::DATA_DIR).join(crate::permissions::POLICY_FILE);
+        std::fs::create_dir_all(policy.parent().unwrap())?;
+        // With no pprog.toml the policy still narrows the defaults
+        std::fs::write(&policy, "tools = [\"read_file\"]")?;
+        assert_eq!(ProjectConfig::load_at(&repo)?.tools, Some(vec!["read_file".to_string()]));
+
+        std::fs::write(&policy, "tools = [\"read_file\"]\n[comands]\ndeny = []")?;
+        assert!(ProjectConfig::load_at(&repo).is_err());
+        std::fs::write(&policy, "[sandbox]\nroot = \"../other\"")?;
+        assert!(ProjectConfig::load_at(&repo).is_err());
+        std::fs::write(&policy, "[commands]\ndeny = [{ pattern = \"(\" }]")?;
+        assert!(ProjectConfig::load_at(&repo).unwrap_err().to_string().contains("Invalid project policy"));
+        std::fs::remove_file(&policy)?;
+        std::fs::write(repo.join(ProjectConfig::CONFIG_FILE), "model = \"claude-3-5-haiku-latest\"\ncheck_cmd = \"cargo check\"\n[commands]\nask = [{ pattern = \"(\" }]")?;
+        assert!(ProjectConfig::load_at(&repo).is_err());
+
+        // A pprog.toml that doesn't parse or can't be read isn't taken for a missing one
+        std::fs::write(repo.join(ProjectConfig::CONFIG_FILE), "model = \"claude-3-5-haiku-latest\"\n[commands\n")?;
+        assert!(ProjectConfig::load_at(&repo).unwrap_err().to_string().starts_with("Invalid"));
+        std::fs::remove_file(repo.join(ProjectConfig::CONFIG_FILE))?;
+        std::fs::create_dir(repo.join(ProjectConfig::CONFIG_FILE))?;
+        assert!(ProjectConfig::load_at(&repo).unwrap_err().to_string().starts_with("Could not read"));
+        Ok(())
+    }
+}
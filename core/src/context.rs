@@ -0,0 +1,402 @@
+use std::collections::{BTreeSet, HashMap};
+
+use similar::TextDiff;
+
+use crate::inference::types::{ContentItem, Message, Role};
+use crate::tokens::TokenCounter;
+
+pub const OMITTED_MARKER: &str = "[earlier context omitted]";
+/// Start of the text a pinned message is carried as once its turn is dropped or compacted
+pub const PINNED_PREFIX: &str = "[pinned";
+
+/// What a tool result is sent as when a later one has the same content
+pub const REPEATED_MARKER: &str = "[same output as a later tool call, see below]";
+
+// Shorter results cost about as little as the marker
+const MIN_REPEATED_CHARS: usize = 200;
+
+// What an image is counted as, about what a screenshot-sized image costs with Anthropic
+const IMAGE_TOKENS: usize = 1600;
+
+/// Fits the conversation into the model's context window before each request.  The
+/// session itself keeps the full transcript, only the outgoing copy is shortened.
+pub struct ContextManager {
+    counter: TokenCounter,
+    window: usize,
+    reserved_output: usize,
+}
+
+impl ContextManager {
+    pub fn new(window: usize, reserved_output: usize) -> Self {
+        Self {
+            counter: TokenCounter::bpe(),
+            window,
+            reserved_output,
+        }
+    }
+
+    /// Counts with `counter` in place of the BPE tokenizer.
+    pub fn with_counter(mut self, counter: TokenCounter) -> Self {
+        self.counter = counter;
+        self
+    }
+
+    pub fn set_counter(&mut self, counter: TokenCounter) {
+        self.counter = counter;
+    }
+
+    pub fn content_to_string(content: &[ContentItem]) -> String {
+        content.iter()
+            .map(|item| match item {
+                ContentItem::Text { text } => text.clone(),
+                ContentItem::ToolUse { name, input, .. } => format!("tool {} with input: {:?}", name, input),
+                ContentItem::ToolResult { content, .. } => format!("tool result: {}", content),
+                ContentItem::Image { source } => format!("[image {}]", source.media_type),
+                ContentItem::Unknown(value) => value.to_string(),
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    pub fn count_text(&self, text: &str) -> usize {
+        self.counter.count(text)
+    }
+
+    pub fn count_message(&self, message: &Message) -> usize {
+        // Combine role and content for complete message token count
+        let images = message.content.iter().filter(|item| matches!(item, ContentItem::Image { .. })).count();
+        self.count_text(&format!("{:?} {}", message.role, Self::content_to_string(&message.content))) + images * IMAGE_TOKENS
+    }
+
+    pub fn count_messages(&self, messages: &[Message]) -> usize {
+        messages.iter().map(|msg| self.count_message(msg)).sum()
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    pub fn reserved_output(&self) -> usize {
+        self.reserved_output
+    }
+
+    pub fn counter(&self) -> &TokenCounter {
+        &self.counter
+    }
+
+    /// Tokens available for messages once the system prompt and output are accounted for.
+    pub fn budget(&self, system_tokens: usize) -> usize {
+        self.window.saturating_sub(self.reserved_output + system_tokens)
+    }
+
+    /*
+        * Returns the messages that fit the budget.  Oldest tool outputs (and write_file
+        * contents) are elided first since they are usually the bulk of the history, then
+        * whole turns are dropped from the front.  A turn always starts at a user text message
+        * so tool_use/tool_result pairs are never split, and the current turn is never touched.
+        * Pinned messages are never elided, and if their turn is dropped their content is
+        * carried verbatim into the first remaining message.  Before any of that, a tool result
+        * repeated word for word later on, like a file read twice, is only sent the last time,
+        * and after it a file read again goes as a diff, see `diff_rereads`.
+    */
+    pub fn fit(&self, messages: &[Message], pinned: &BTreeSet<usize>, system_tokens: usize) -> Vec<Message> {
+        let mut fitted = self.fit_to_budget(messages, pinned, system_tokens);
+        Self::diff_rereads(&mut fitted);
+        fitted
+    }
+
+    fn fit_to_budget(&self, messages: &[Message], pinned: &BTreeSet<usize>, system_tokens: usize) -> Vec<Message> {
+        let budget = self.budget(system_tokens);
+        let mut fitted = messages.to_vec();
+        Self::dedupe_results(&mut fitted, pinned);
+        let mut total = self.count_messages(&fitted);
+        if total <= budget {
+            return fitted;
+        }
+
+        let current_turn = Self::turn_starts(&fitted).last().copied().unwrap_or(0);
+        let mut omitted = false;
+
+        for (idx, message) in fitted.iter_mut().enumerate().take(current_turn) {
+            if total <= budget {
+                break;
+            }
+            if pinned.contains(&idx) {
+                continue;
+            }
+            let before = self.count_message(message);
+            if Self::elide(message) {
+                omitted = true;
+                total = total - before + self.count_message(message);
+            }
+        }
+
+        let mut carried = Vec::new();
+        if total > budget {
+            let starts = Self::turn_starts(&fitted);
+            let pinned_tokens: usize = pinned.iter()
+                .filter_map(|&idx| fitted.get(idx))
+                .map(|msg| self.count_message(msg))
+                .sum();
+            let mut drop_to = 0;
+            for &start in starts.iter().filter(|&&start| start > 0) {
+                if start > current_turn {
+                    break;
+                }
+                drop_to = start;
+                let remaining = self.count_messages(&fitted[start..]);
+                if remaining + pinned_tokens <= budget {
+                    break;
+                }
+            }
+            if drop_to > 0 {
+                carried = pinned.iter()
+                    .filter(|&&idx| idx < drop_to)
+                    .map(|&idx| ContentItem::Text { text: Self::pinned_text(&fitted[idx]) })
+                    .collect();
+                fitted.drain(..drop_to);
+                omitted = true;
+            }
+        }
+
+        if omitted {
+            if let Some(first) = fitted.first_mut() {
+                carried.insert(0, ContentItem::Text { text: OMITTED_MARKER.to_string() });
+                first.content.splice(0..0, carried);
+            }
+        }
+
+        fitted
+    }
+
+    /// Pinned message rendered as text so it survives when its turn is dropped or summarized.
+    pub fn pinned_text(message: &Message) -> String {
+        let role = format!("{:?}", message.role).to_lowercase();
+        format!("{} {} message]\n{}", PINNED_PREFIX, role, Self::content_to_string(&message.content))
+    }
+
+    /// Indices of messages that begin a turn, i.e. user messages carrying text.
+    pub fn turn_starts(messages: &[Message]) -> Vec<usize> {
+        messages.iter()
+            .enumerate()
+            .filter(|(_, msg)| {
+                msg.role == Role::User && msg.content.iter().any(|item| matches!(item, ContentItem::Text { .. }))
+            })
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// Swaps each tool result that comes again later in `messages` for `REPEATED_MARKER`,
+    /// keeping the last, the one least likely to be dropped.  Returns how many were swapped.
+    pub fn dedupe_results(messages: &mut [Message], pinned: &BTreeSet<usize>) -> usize {
+        let mut seen = std::collections::HashSet::new();
+        let mut swapped = 0;
+        for (idx, message) in messages.iter_mut().enumerate().rev() {
+            for item in message.content.iter_mut().rev() {
+                let ContentItem::ToolResult { content, is_error: false, .. } = item else { continue };
+                if content.len() < MIN_REPEATED_CHARS || seen.insert(content.clone()) || pinned.contains(&idx) {
+                    continue;
+                }
+                *content = REPEATED_MARKER.to_string();
+                swapped += 1;
+            }
+        }
+        swapped
+    }
+
+    /*
+        * Swaps a `read_file` result for a diff against the version of the file the model saw
+        * last, from an earlier read or its own `write_file`, when the diff is under half the
+        * size.  Run on what is left once the messages fit, so the version a diff is against
+        * is always still there: after an elided or dropped read the next one goes whole.
+        * Returns how many results were swapped.
+    */
+    pub fn diff_rereads(messages: &mut [Message]) -> usize {
+        let mut seen: HashMap<String, String> = HashMap::new();
+        let mut calls: HashMap<String, (String, Option<String>)> = HashMap::new();
+        let mut swapped = 0;
+        for message in messages.iter_mut() {
+            for item in message.content.iter_mut() {
+                match item {
+                    ContentItem::ToolUse { id, name, input } if name == "read_file" || name == "write_file" => {
+                        let Some(path) = input["path"].as_str() else { continue };
+                        let written = input["content"].as_str().filter(|content| *content != OMITTED_MARKER).map(str::to_string);
+                        calls.insert(id.clone(), (path.trim_start_matches("./").to_string(), written));
+                    },
+                    ContentItem::ToolResult { tool_use_id, content, is_error: false } => {
+                        let Some((path, written)) = calls.remove(tool_use_id.as_str()) else { continue };
+                        if let Some(written) = written {
+                            seen.insert(path, written);
+                            continue;
+                        }
+                        if content == OMITTED_MARKER || content == REPEATED_MARKER {
+                            seen.remove(&path);
+                            continue;
+                        }
+                        let current = std::mem::take(content);
+                        *content = match seen.get(&path) {
+                            Some(before) if *before == current => format!("[{} is unchanged since the version earlier in the conversation]", path),
+                            Some(before) => {
+                                let diff = TextDiff::from_lines(before.as_str(), current.as_str()).unified_diff().header(&path, &path).to_string();
+                                match diff.len() < current.len() / 2 {
+                                    true => format!("[{} changed since the version earlier in the conversation, here as a diff against it]\n{}", path, diff),
+                                    false => current.clone(),
+                                }
+                            },
+                            None => current.clone(),
+                        };
+                        swapped += usize::from(*content != current);
+                        seen.insert(path, current);
+                    },
+                    _ => {},
+                }
+            }
+        }
+        swapped
+    }
+
+    fn elide(message: &mut Message) -> bool {
+        let mut changed = false;
+        for item in message.content.iter_mut() {
+            match item {
+                ContentItem::ToolResult { content, .. } if content != OMITTED_MARKER => {
+                    *content = OMITTED_MARKER.to_string();
+                    changed = true;
+                },
+                ContentItem::ToolUse { name, input, .. } if name == "write_file" => {
+                    if let Some(content) = input.get_mut("content") {
+                        if content != OMITTED_MARKER {
+                            *content = serde_json::Value::String(OMITTED_MARKER.to_string());
+                            changed = true;
+                        }
+                    }
+                },
+                _ => {}
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn text(role: Role, text: &str) -> Message {
+        Message { role, content: vec![ContentItem::Text { text: text.to_string() }] }
+    }
+
+    fn history() -> Vec<Message> {
+        vec![
+            text(Role::User, "read the file"),
+            Message {
+                role: Role::Assistant,
+                content: vec![ContentItem::ToolUse {
+                    id: "t1".to_string(),
+                    name: "read_file".to_string(),
+                    input: json!({"path": "big.txt"}),
+                }],
+            },
+            Message {
+                role: Role::User,
+                content: vec![ContentItem::ToolResult {
+                    tool_use_id: "t1".to_string(),
+                    content: "lorem ipsum ".repeat(200),
+                    is_error: false,
+                }],
+            },
+            text(Role::Assistant, "done"),
+            text(Role::User, "what next?"),
+        ]
+    }
+
+    #[test]
+    fn test_fit_returns_messages_untouched_when_under_budget() {
+        let manager = ContextManager::new(100_000, 1000);
+        assert_eq!(manager.fit(&history(), &BTreeSet::new(), 0), history());
+    }
+
+    #[test]
+    fn test_fit_elides_tool_output_before_dropping_turns() {
+        let manager = ContextManager::new(100, 0);
+        let fitted = manager.fit(&history(), &BTreeSet::new(), 0);
+
+        assert_eq!(fitted.len(), 5);
+        assert_eq!(fitted[0].content[0], ContentItem::Text { text: OMITTED_MARKER.to_string() });
+        assert_eq!(fitted[2].content[0], ContentItem::ToolResult {
+            tool_use_id: "t1".to_string(),
+            content: OMITTED_MARKER.to_string(),
+            is_error: false,
+        });
+    }
+
+    #[test]
+    fn test_fit_sends_repeated_results_once() {
+        let file = "fn main() {}\n".repeat(20);
+        let result = |id: &str, content: &str| Message {
+            role: Role::User,
+            content: vec![ContentItem::ToolResult { tool_use_id: id.to_string(), content: content.to_string(), is_error: false }],
+        };
+        let messages = vec![result("t1", &file), result("t2", "short"), result("t3", "short"), result("t4", &file)];
+        let fitted = ContextManager::new(100_000, 0).fit(&messages, &BTreeSet::new(), 0);
+        assert_eq!(fitted[0], result("t1", REPEATED_MARKER));
+        assert_eq!(fitted[1..], messages[1..]);
+        assert_eq!(ContextManager::dedupe_results(&mut messages.clone(), &BTreeSet::from([0])), 0);
+    }
+
+    #[test]
+    fn test_fit_sends_rereads_as_diffs() {
+        let call = |id: &str, name: &str, input: serde_json::Value| Message {
+            role: Role::Assistant,
+            content: vec![ContentItem::ToolUse { id: id.to_string(), name: name.to_string(), input }],
+        };
+        let result = |id: &str, content: &str| Message {
+            role: Role::User,
+            content: vec![ContentItem::ToolResult { tool_use_id: id.to_string(), content: content.to_string(), is_error: false }],
+        };
+        let before = (1..=40).map(|n| format!("line {}\n", n)).collect::<String>();
+        let after = before.replace("line 20\n", "line twenty\n");
+        let mut messages = vec![
+            call("t1", "read_file", json!({"path": "src/lib.rs"})), result("t1", &before),
+            call("t2", "read_file", json!({"path": "./src/lib.rs"})), result("t2", &after),
+            call("t3", "write_file", json!({"path": "src/lib.rs", "content": before})), result("t3", "Successfully wrote"),
+            call("t4", "read_file", json!({"path": "src/lib.rs"})), result("t4", &before),
+        ];
+        assert_eq!(ContextManager::diff_rereads(&mut messages), 2);
+        let ContentItem::ToolResult { content, .. } = &messages[3].content[0] else { panic!("not a tool result") };
+        assert!(content.starts_with("[src/lib.rs changed since the version earlier in the conversation, here as a diff against it]\n--- src/lib.rs"));
+        assert!(content.contains("-line 20\n+line twenty\n"));
+        assert_eq!(messages[7], result("t4", "[src/lib.rs is unchanged since the version earlier in the conversation]"));
+
+        // With the first read elided the next one goes whole
+        let mut elided = vec![call("t1", "read_file", json!({"path": "a.rs"})), result("t1", OMITTED_MARKER), call("t2", "read_file", json!({"path": "a.rs"})), result("t2", &after)];
+        assert_eq!(ContextManager::diff_rereads(&mut elided), 0);
+    }
+
+    #[test]
+    fn test_fit_drops_whole_turns_but_keeps_current() {
+        let manager = ContextManager::new(10, 0);
+        let fitted = manager.fit(&history(), &BTreeSet::new(), 0);
+
+        assert_eq!(fitted.len(), 1);
+        assert_eq!(fitted[0].content, vec![
+            ContentItem::Text { text: OMITTED_MARKER.to_string() },
+            ContentItem::Text { text: "what next?".to_string() },
+        ]);
+    }
+
+    #[test]
+    fn test_fit_carries_pinned_messages_verbatim() {
+        let manager = ContextManager::new(10, 0);
+        let pinned = BTreeSet::from([0]);
+        let fitted = manager.fit(&history(), &pinned, 0);
+
+        assert_eq!(fitted.len(), 1);
+        assert_eq!(fitted[0].content, vec![
+            ContentItem::Text { text: OMITTED_MARKER.to_string() },
+            ContentItem::Text { text: "[pinned user message]\nread the file".to_string() },
+            ContentItem::Text { text: "what next?".to_string() },
+        ]);
+    }
+}
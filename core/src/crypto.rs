@@ -0,0 +1,192 @@
+use std::io::Write;
+use std::process::{Command, ExitStatus, Stdio};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+
+pub const ENCRYPTED_PREFIX: &str = "enc:v1:";
+const KEY_ENV: &str = "PPROG_ENCRYPTION_KEY";
+const KEYCHAIN_SERVICE: &str = "pprog";
+
+/*
+    * AES-256-GCM over individual text fields.  Every value gets a fresh random nonce and is
+    * stored as `enc:v1:<base64 nonce + ciphertext>`, so encrypted and plaintext values can
+    * be told apart and data written before encryption was enabled still loads.
+*/
+#[derive(Clone)]
+pub struct Cipher {
+    key: LessSafeKey,
+    rng: SystemRandom,
+}
+
+impl Cipher {
+    pub fn from_key(key: &[u8]) -> Result<Self, anyhow::Error> {
+        let key = UnboundKey::new(&AES_256_GCM, key)
+            .map_err(|_| anyhow::anyhow!("Encryption key must be {} bytes", AES_256_GCM.key_len()))?;
+        Ok(Cipher { key: LessSafeKey::new(key), rng: SystemRandom::new() })
+    }
+
+    pub fn generate_key() -> Result<Vec<u8>, anyhow::Error> {
+        let mut key = vec![0u8; AES_256_GCM.key_len()];
+        SystemRandom::new().fill(&mut key).map_err(|_| anyhow::anyhow!("Failed to generate encryption key"))?;
+        Ok(key)
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> Result<String, anyhow::Error> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng.fill(&mut nonce).map_err(|_| anyhow::anyhow!("Failed to generate nonce"))?;
+
+        let mut data = plaintext.as_bytes().to_vec();
+        self.key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+            .map_err(|_| anyhow::anyhow!("Encryption failed"))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(data);
+        Ok(format!("{}{}", ENCRYPTED_PREFIX, STANDARD.encode(sealed)))
+    }
+
+    /// Decrypts a value from `encrypt`, values without the prefix are returned unchanged.
+    pub fn decrypt(&self, value: &str) -> Result<String, anyhow::Error> {
+        let Some(encoded) = value.strip_prefix(ENCRYPTED_PREFIX) else {
+            return Ok(value.to_string());
+        };
+
+        let mut sealed = STANDARD.decode(encoded)?;
+        if sealed.len() < NONCE_LEN {
+            return Err(anyhow::anyhow!("Encrypted value is truncated"));
+        }
+        let mut data = sealed.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&sealed).map_err(|_| anyhow::anyhow!("Invalid nonce"))?;
+        let plaintext = self.key
+            .open_in_place(nonce, Aad::empty(), &mut data)
+            .map_err(|_| anyhow::anyhow!("Decryption failed, the encryption key does not match"))?;
+        Ok(String::from_utf8(plaintext.to_vec())?)
+    }
+
+    /// Cipher with the key stored under `key_id`, from `PPROG_ENCRYPTION_KEY` if set and
+    /// otherwise from the OS keychain, where a new key is created on first use.
+    pub fn from_keychain(key_id: &str) -> Result<Self, anyhow::Error> {
+        if let Ok(encoded) = std::env::var(KEY_ENV) {
+            return Self::from_key(&STANDARD.decode(encoded.trim())?);
+        }
+
+        let with_hint = |e: anyhow::Error| anyhow::anyhow!("{}, set {} to a base64 encoded 32 byte key instead", e, KEY_ENV);
+        if let Some(encoded) = keychain_lookup(key_id).map_err(with_hint)? {
+            return Self::from_key(&STANDARD.decode(encoded.trim())?);
+        }
+
+        let key = Self::generate_key()?;
+        keychain_store(key_id, "pprog session key", &STANDARD.encode(&key)).map_err(with_hint)?;
+        log::info!("Created encryption key {} in the OS keychain", key_id);
+        Self::from_key(&key)
+    }
+}
+
+fn keychain_unavailable() -> anyhow::Error {
+    anyhow::anyhow!("No supported OS keychain found")
+}
+
+/// The secret stored under `key_id` in the OS keychain, if there is one.
+// Keychain access goes through the platform's own CLI so no keychain bindings are needed
+pub fn keychain_lookup(key_id: &str) -> Result<Option<String>, anyhow::Error> {
+    let output = if cfg!(target_os = "macos") {
+        Command::new("security")
+            .args(["find-generic-password", "-s", KEYCHAIN_SERVICE, "-a", key_id, "-w"])
+            .output()
+    } else if cfg!(target_os = "linux") {
+        Command::new("secret-tool")
+            .args(["lookup", "service", KEYCHAIN_SERVICE, "key_id", key_id])
+            .output()
+    } else {
+        return Err(keychain_unavailable());
+    };
+
+    match output {
+        Ok(output) if output.status.success() && !output.stdout.is_empty() => {
+            Ok(Some(String::from_utf8(output.stdout)?))
+        },
+        Ok(_) => Ok(None),
+        Err(_) => Err(keychain_unavailable()),
+    }
+}
+
+// Runs `command` with `input` on its stdin, keeping it out of the process list
+fn run_with_stdin(command: &mut Command, input: &str) -> std::io::Result<ExitStatus> {
+    command.stdin(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(input.as_bytes())?;
+            }
+            child.wait()
+        })
+}
+
+// `value` as one argument of a `security -i` command line
+fn security_quoted(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Stores `secret` under `key_id` in the OS keychain, shown there as `label`.
+pub fn keychain_store(key_id: &str, label: &str, secret: &str) -> Result<(), anyhow::Error> {
+    let status = if cfg!(target_os = "macos") {
+        /*
+            * `security -w <secret>` would show the secret to anyone running `ps`, and a bare
+            * `-w` prompts on the terminal rather than reading stdin.  Interactive mode reads
+            * the whole command from stdin instead, but doesn't fail when the command does, so
+            * the item is read back to tell.
+        */
+        if secret.contains(['\n', '\r']) {
+            anyhow::bail!("Failed to store {} in the OS keychain: it spans several lines", label);
+        }
+        let command = format!(
+            "add-generic-password -U -s {} -a {} -l {} -w {}\n",
+            security_quoted(KEYCHAIN_SERVICE),
+            security_quoted(key_id),
+            security_quoted(label),
+            security_quoted(secret),
+        );
+        let status = run_with_stdin(Command::new("security").arg("-i"), &command);
+        if let Ok(status) = &status {
+            if status.success() && keychain_lookup(key_id)?.as_deref().map(str::trim_end) != Some(secret) {
+                anyhow::bail!("Failed to store {} in the OS keychain", label);
+            }
+        }
+        status
+    } else {
+        // secret-tool reads the secret from stdin too
+        run_with_stdin(
+            Command::new("secret-tool").args(["store", "--label", label, "service", KEYCHAIN_SERVICE, "key_id", key_id]),
+            secret,
+        )
+    };
+
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(anyhow::anyhow!("Failed to store {} in the OS keychain: {}", label, status)),
+        Err(_) => Err(keychain_unavailable()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_and_plaintext_passthrough() -> Result<(), anyhow::Error> {
+        let cipher = Cipher::from_key(&Cipher::generate_key()?)?;
+        let sealed = cipher.encrypt("api_key = \"sk-secret\"")?;
+        assert!(sealed.starts_with(ENCRYPTED_PREFIX));
+        assert!(!sealed.contains("sk-secret"));
+        assert_ne!(sealed, cipher.encrypt("api_key = \"sk-secret\"")?);
+        assert_eq!(cipher.decrypt(&sealed)?, "api_key = \"sk-secret\"");
+        assert_eq!(cipher.decrypt("written before encryption")?, "written before encryption");
+
+        let other = Cipher::from_key(&Cipher::generate_key()?)?;
+        assert!(other.decrypt(&sealed).is_err());
+        assert_eq!(security_quoted(r#"to"k\en"#), r#""to\"k\\en""#);
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use reqwest::Url;
+
+use crate::config::EgressConfig;
+
+/// The tool the model reads a web page with.
+pub const FETCH_TOOL: &str = "fetch_url";
+
+// Redirects followed before giving up, each one checked like the first URL
+const MAX_REDIRECTS: usize = 5;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Characters of a page sent back, like the cut-off of mentioned files
+const MAX_PAGE_CHARS: usize = 40_000;
+
+// Enough bytes for that many characters of any UTF-8, the rest of a page isn't downloaded
+const MAX_PAGE_BYTES: usize = MAX_PAGE_CHARS * 4;
+
+// Ranges no public host is in.  Loopback, private and link-local cover the local machine,
+// internal services and cloud metadata endpoints like 169.254.169.254.
+fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_multicast()
+                // This network, 0.0.0.0/8, reaches the local machine on some systems
+                || a == 0
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (64..128).contains(&b))
+                // Benchmarking, 198.18.0.0/15
+                || (a == 198 && (b & 0xfe) == 18)
+                // Reserved 240.0.0.0/4, the broadcast address with it
+                || a >= 240
+        },
+        // IPv4-mapped ::ffff:a.b.c.d and the older IPv4-compatible ::a.b.c.d
+        IpAddr::V6(ip) => match ip.to_ipv4() {
+            Some(mapped) => is_private(IpAddr::V4(mapped)),
+            None => {
+                let segments = ip.segments();
+                let embedded = |high: u16, low: u16| Ipv4Addr::from((u32::from(high) << 16) | u32::from(low));
+                ip.is_loopback() || ip.is_unspecified() || ip.is_multicast()
+                    // Unique local fc00::/7 and link-local fe80::/10
+                    || (segments[0] & 0xfe00) == 0xfc00 || (segments[0] & 0xffc0) == 0xfe80
+                    // NAT64 64:ff9b::/96 and 6to4 2002::/16 reach the IPv4 address they carry
+                    || (segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] && is_private(IpAddr::V4(embedded(segments[6], segments[7]))))
+                    || (segments[0] == 0x2002 && is_private(IpAddr::V4(embedded(segments[1], segments[2]))))
+            },
+        },
+    }
+}
+
+/// Whether `host` is `domain` or one of its subdomains.
+pub fn matches(host: &str, domain: &str) -> bool {
+    let domain = domain.trim_start_matches("*.").trim_end_matches('.').to_lowercase();
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
+
+/*
+    * Where the web tools may connect.  A host matching `deny` is refused, and with `allow` set
+    * only hosts matching it are let through, a domain matching its subdomains too.  Whatever
+    * the lists say, a host resolving to a private, loopback or link-local address is refused
+    * unless `allow_private` is on, so a page can't point a tool at internal services.  The
+    * addresses checked are the ones connected to, so DNS can't change its answer in between.
+*/
+pub struct Egress {
+    config: EgressConfig,
+}
+
+impl Egress {
+    pub fn new(config: &EgressConfig) -> Self {
+        Egress { config: config.clone() }
+    }
+
+    /// The addresses `url` may be fetched from, or why it may not.
+    pub async fn check(&self, url: &Url) -> Result<Vec<SocketAddr>, anyhow::Error> {
+        if !matches!(url.scheme(), "http" | "https") {
+            anyhow::bail!("Only http and https URLs can be fetched, not {}", url);
+        }
+        let host = url.host_str().ok_or_else(|| anyhow::anyhow!("{} has no host", url))?.to_lowercase();
+        // An IP in brackets is matched without them, a fully qualified name without its final dot
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let host = host.strip_suffix('.').unwrap_or(host);
+        if let Some(rule) = self.config.deny.iter().find(|rule| matches(host, rule)) {
+            anyhow::bail!("{} is denied by egress.deny ({})", host, rule);
+        }
+        if !self.config.allow.is_empty() && !self.config.allow.iter().any(|rule| matches(host, rule)) {
+            anyhow::bail!("{} is not in egress.allow", host);
+        }
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addrs = match host.parse::<IpAddr>() {
+            Ok(ip) => vec![SocketAddr::new(ip, port)],
+            Err(_) => tokio::net::lookup_host((host, port)).await
+                .map_err(|e| anyhow::anyhow!("Could not resolve {}: {}", host, e))?
+                .collect(),
+        };
+        if !self.config.allow_private {
+            if let Some(addr) = addrs.iter().find(|addr| is_private(addr.ip())) {
+                anyhow::bail!("{} resolves to the private address {}, set egress.allow_private to reach it", host, addr.ip());
+            }
+        }
+        Ok(addrs)
+    }
+
+    /// GETs `url`, following redirects through `check` too.
+    pub async fn get(&self, url: &str) -> Result<reqwest::Response, anyhow::Error> {
+        let mut url = Url::parse(url).map_err(|e| anyhow::anyhow!("Invalid URL {}: {}", url, e))?;
+        for _ in 0..=MAX_REDIRECTS {
+            let addrs = self.check(&url).await?;
+            let mut client = reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .timeout(FETCH_TIMEOUT);
+            if let Some(domain) = url.domain() {
+                client = client.resolve_to_addrs(domain, &addrs);
+            }
+            let response = client.build()?.get(url.clone()).send().await?;
+            if !response.status().is_redirection() {
+                return Ok(response);
+            }
+            let location = response.headers().get(reqwest::header::LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .ok_or_else(|| anyhow::anyhow!("{} redirected without a location", url))?;
+            url = url.join(location)?;
+        }
+        Err(anyhow::anyhow!("Gave up after {} redirects", MAX_REDIRECTS))
+    }
+}
+
+/// The `fetch_url` tool: the page at `url` as text, cut off past about 40k characters.
+pub async fn fetch(config: &EgressConfig, url: &str) -> Result<String, anyhow::Error> {
+    let mut response = Egress::new(config).get(url).await?;
+    let status = response.status();
+    let content_type = response.headers().get(reqwest::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    // Streamed and stopped at the cap, a page that never ends can't fill memory
+    let mut body = Vec::new();
+    let mut more = false;
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > MAX_PAGE_BYTES {
+            body.extend_from_slice(&chunk[..MAX_PAGE_BYTES - body.len()]);
+            more = true;
+            break;
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let body = String::from_utf8_lossy(&body);
+    let mut page = format!("Status: {}\nContent-Type: {}\n\n", status, content_type);
+    match body.char_indices().nth(MAX_PAGE_CHARS).map(|(end, _)| end).or(more.then_some(body.len())) {
+        Some(end) => {
+            page.push_str(&body[..end]);
+            page.push_str(&format!("\n\n[Cut off after {} characters, the rest of the page wasn't read]", body[..end].chars().count()));
+        },
+        None => page.push_str(&body),
+    }
+    if !status.is_success() {
+        return Err(anyhow::anyhow!("{} answered {}.\n{}", url, status, page));
+    }
+    Ok(page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Why `url` is refused, empty when it isn't
+    async fn refused(egress: &Egress, url: &str) -> String {
+        egress.check(&Url::parse(url).unwrap()).await.err().map(|e| e.to_string()).unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn test_egress() {
+        let egress = Egress::new(&EgressConfig::default());
+        assert!(refused(&egress, "http://127.0.0.1:8080/admin").await.contains("private address 127.0.0.1"));
+        assert!(refused(&egress, "http://169.254.169.254/latest/meta-data/").await.contains("private"));
+        assert!(refused(&egress, "http://10.1.2.3/").await.contains("private"));
+        assert!(refused(&egress, "http://[::1]/").await.contains("private"));
+        assert!(refused(&egress, "http://[::ffff:192.168.0.1]/").await.contains("private"));
+        assert!(refused(&egress, "http://localhost/").await.contains("private"));
+        assert!(refused(&egress, "http://0.1.2.3/").await.contains("private"));
+        assert!(refused(&egress, "http://[::10.0.0.1]/").await.contains("private"));
+        assert!(refused(&egress, "file:///etc/passwd").await.contains("Only http and https"));
+        for url in ["http://224.0.0.1/", "http://239.255.255.250/", "http://240.0.0.1/", "http://255.255.255.255/", "http://198.18.0.1/", "http://198.19.255.1/"] {
+            assert!(refused(&egress, url).await.contains("private"), "{}", url);
+        }
+        // NAT64 and 6to4 addresses carrying 10.0.0.1 and 192.168.0.1
+        assert!(refused(&egress, "http://[64:ff9b::10.0.0.1]/").await.contains("private"));
+        assert!(refused(&egress, "http://[2002:c0a8:1::1]/").await.contains("private"));
+        assert!(refused(&egress, "http://[ff02::1]/").await.contains("private"));
+        assert_eq!(refused(&egress, "http://[64:ff9b::93.184.216.34]/").await, "");
+        assert_eq!(refused(&egress, "http://[2002:5db8:d822::1]/").await, "");
+        assert_eq!(refused(&egress, "https://93.184.216.34/").await, "");
+
+        let listed = Egress::new(&EgressConfig {
+            allow: vec!["docs.rs".to_string(), "*.github.com".to_string()],
+            deny: vec!["gist.github.com".to_string()],
+            allow_private: false,
+        });
+        assert!(refused(&listed, "https://example.com/").await.contains("not in egress.allow"));
+        assert!(refused(&listed, "https://gist.github.com/x").await.contains("denied by egress.deny"));
+        assert!(refused(&listed, "https://notdocs.rs/").await.contains("not in egress.allow"));
+        assert!(refused(&listed, "https://gist.github.com./x").await.contains("denied by egress.deny"));
+        let allowed = refused(&listed, "https://docs.rs./").await;
+        assert!(!allowed.contains("egress.allow") && !allowed.contains("egress.deny"));
+        let private = Egress::new(&EgressConfig { allow_private: true, ..Default::default() });
+        assert_eq!(refused(&private, "http://127.0.0.1:8080/").await, "");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_stops_at_cap() -> Result<(), anyhow::Error> {
+        use tokio::io::AsyncWriteExt;
+
+        // A page that never ends, written until the client hangs up
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            let Ok((mut stream, _)) = listener.accept().await else { return };
+            let chunk = vec![b'a'; 64 * 1024];
+            if stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\n").await.is_ok() {
+                while stream.write_all(&chunk).await.is_ok() {}
+            }
+        });
+        let config = EgressConfig { allow_private: true, ..Default::default() };
+        let page = fetch(&config, &format!("http://{}/", addr)).await?;
+        assert!(page.ends_with(&format!("{}\n\n[Cut off after {} characters, the rest of the page wasn't read]", "a".repeat(MAX_PAGE_CHARS), MAX_PAGE_CHARS)));
+        Ok(())
+    }
+}
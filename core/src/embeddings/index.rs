@@ -0,0 +1,242 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+
+use crate::config::ProjectConfig;
+use crate::secrets::Guard;
+use super::{cosine, EmbeddingProvider};
+
+const INDEX_FILE: &str = "index.db";
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS files (
+    collection TEXT NOT NULL,
+    path TEXT NOT NULL,
+    hash TEXT NOT NULL,
+    model TEXT NOT NULL,
+    PRIMARY KEY (collection, path)
+);
+CREATE TABLE IF NOT EXISTS chunks (
+    collection TEXT NOT NULL,
+    path TEXT NOT NULL,
+    start_line INTEGER NOT NULL,
+    end_line INTEGER NOT NULL,
+    text TEXT NOT NULL,
+    vector BLOB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS chunks_path ON chunks(collection, path);
+"#;
+
+// Lines per chunk, and lines shared with the chunk before so code at a boundary is found whole
+const CHUNK_LINES: usize = 40;
+const CHUNK_OVERLAP: usize = 8;
+
+// Chunks sent per embeddings request
+const BATCH_SIZE: usize = 64;
+
+// Larger files are likely generated or data, and lock files are noise to search
+const MAX_FILE_BYTES: u64 = 200_000;
+const SKIPPED_FILES: &[&str] = &["Cargo.lock", "package-lock.json", "yarn.lock", "pnpm-lock.yaml", "poetry.lock"];
+
+/// What a collection holds, for `pprog index status`.
+#[derive(Debug, Default, PartialEq)]
+pub struct IndexStatus {
+    pub files: usize,
+    pub chunks: usize,
+    pub models: Vec<String>,
+}
+
+/// A run of lines of a file, the unit that is embedded and searched.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub path: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub text: String,
+}
+
+/// Splits a file into overlapping chunks of `CHUNK_LINES` lines, numbered from 1.
+pub fn chunks(path: &str, source: &str) -> Vec<Chunk> {
+    let lines = source.lines().collect::<Vec<&str>>();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        let end = (start + CHUNK_LINES).min(lines.len());
+        let text = lines[start..end].join("\n");
+        if !text.trim().is_empty() {
+            chunks.push(Chunk { path: path.to_string(), start_line: start + 1, end_line: end, text });
+        }
+        if end == lines.len() {
+            break;
+        }
+        start = end - CHUNK_OVERLAP;
+    }
+    chunks
+}
+
+/// A chunk found by `Index::search`, `score` being its cosine similarity to the query.
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub chunk: Chunk,
+    pub score: f32,
+}
+
+/// What `Index::sync` changed.
+#[derive(Debug, Default, PartialEq)]
+pub struct SyncStats {
+    pub indexed: usize,
+    pub removed: usize,
+    pub chunks: usize,
+}
+
+fn encode(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+fn decode(bytes: &[u8]) -> Vec<f32> {
+    bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()
+}
+
+/*
+    * Vectors for the chunks of a set of files, kept in `.pprog/index.db` under a collection
+    * name so that code and docs are searched apart.  Every file is stored with the hash of
+    * its contents and the embedding model, and `sync` only embeds the files whose hash or
+    * model changed since.  A search compares the query with every chunk of the collection,
+    * which is quick enough at the size of a repository.
+*/
+pub struct Index {
+    conn: Connection,
+    collection: String,
+}
+
+impl Index {
+    pub fn open(collection: &str) -> Result<Self, anyhow::Error> {
+        Self::open_at(&ProjectConfig::data_dir()?.join(INDEX_FILE), collection)
+    }
+
+    pub fn open_at(path: &Path, collection: &str) -> Result<Self, anyhow::Error> {
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Index { conn, collection: collection.to_string() })
+    }
+
+    /// Embeds the files of `files` that changed, and drops the ones no longer listed.  The chunks
+    /// are kept as they are, the provider gets them with `secrets` masked.
+    pub async fn sync(&mut self, root: &Path, files: &[String], embedder: &EmbeddingProvider, secrets: &Guard) -> Result<SyncStats, anyhow::Error> {
+        let model = embedder.model();
+        let mut stale = Vec::new();
+        for path in files {
+            let skipped = SKIPPED_FILES.iter().any(|name| path == name || path.ends_with(&format!("/{}", name)));
+            if skipped || !std::fs::metadata(root.join(path)).is_ok_and(|meta| meta.len() <= MAX_FILE_BYTES) {
+                continue;
+            }
+            // Binary files don't read as text and are left out
+            let Ok(source) = std::fs::read_to_string(root.join(path)) else { continue };
+            let hash = format!("{:x}", Sha256::digest(&source));
+            let stored = self.conn.query_row(
+                "SELECT hash, model FROM files WHERE collection = ?1 AND path = ?2",
+                params![self.collection, path],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+            ).optional()?;
+            if stored.as_ref().is_some_and(|(stored_hash, stored_model)| *stored_hash == hash && stored_model == model) {
+                continue;
+            }
+            stale.push((path.clone(), hash, chunks(path, &source)));
+        }
+
+        let listed = files.iter().collect::<HashSet<&String>>();
+        let gone = {
+            let mut stmt = self.conn.prepare("SELECT path FROM files WHERE collection = ?1")?;
+            let paths = stmt.query_map(params![self.collection], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<String>, _>>()?;
+            paths.into_iter().filter(|path| !listed.contains(path)).collect::<Vec<String>>()
+        };
+
+        let texts = stale.iter().flat_map(|(_, _, chunks)| chunks.iter().map(|chunk| secrets.masked(&chunk.text))).collect::<Vec<String>>();
+        let mut vectors = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(BATCH_SIZE) {
+            vectors.extend(embedder.embed(batch).await?);
+        }
+
+        let tx = self.conn.unchecked_transaction()?;
+        for path in gone.iter().chain(stale.iter().map(|(path, _, _)| path)) {
+            tx.execute("DELETE FROM chunks WHERE collection = ?1 AND path = ?2", params![self.collection, path])?;
+            tx.execute("DELETE FROM files WHERE collection = ?1 AND path = ?2", params![self.collection, path])?;
+        }
+        let mut vectors = vectors.into_iter();
+        for (path, hash, chunks) in &stale {
+            tx.execute(
+                "INSERT INTO files (collection, path, hash, model) VALUES (?1, ?2, ?3, ?4)",
+                params![self.collection, path, hash, model],
+            )?;
+            for (chunk, vector) in chunks.iter().zip(vectors.by_ref()) {
+                tx.execute(
+                    "INSERT INTO chunks (collection, path, start_line, end_line, text, vector) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![self.collection, path, chunk.start_line as i64, chunk.end_line as i64, chunk.text, encode(&vector)],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(SyncStats { indexed: stale.len(), removed: gone.len(), chunks: texts.len() })
+    }
+
+    pub fn status(&self) -> Result<IndexStatus, anyhow::Error> {
+        let count = |table: &str| self.conn.query_row(
+            &format!("SELECT COUNT(*) FROM {} WHERE collection = ?1", table),
+            params![self.collection],
+            |row| row.get::<_, i64>(0),
+        );
+        let mut stmt = self.conn.prepare("SELECT DISTINCT model FROM files WHERE collection = ?1 ORDER BY model")?;
+        let models = stmt.query_map(params![self.collection], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(IndexStatus { files: count("files")? as usize, chunks: count("chunks")? as usize, models })
+    }
+
+    /// Drops everything in the collection, the next sync embeds every file again.
+    pub fn clear(&mut self) -> Result<(), anyhow::Error> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM chunks WHERE collection = ?1", params![self.collection])?;
+        tx.execute("DELETE FROM files WHERE collection = ?1", params![self.collection])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The `k` chunks closest to `query`, best first.
+    pub async fn search(&self, query: &str, embedder: &EmbeddingProvider, k: usize) -> Result<Vec<Hit>, anyhow::Error> {
+        let query = embedder.embed(&[query.to_string()]).await?.remove(0);
+        let mut stmt = self.conn.prepare("SELECT path, start_line, end_line, text, vector FROM chunks WHERE collection = ?1")?;
+        let mut hits = stmt.query_map(params![self.collection], |row| {
+            let chunk = Chunk {
+                path: row.get(0)?,
+                start_line: row.get::<_, i64>(1)? as usize,
+                end_line: row.get::<_, i64>(2)? as usize,
+                text: row.get(3)?,
+            };
+            Ok((chunk, row.get::<_, Vec<u8>>(4)?))
+        })?
+            .map(|row| row.map(|(chunk, vector)| Hit { score: cosine(&query, &decode(&vector)), chunk }))
+            .collect::<Result<Vec<Hit>, _>>()?;
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(k);
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks_overlap() {
+        let source = (1..=90).map(|n| format!("line {}", n)).collect::<Vec<String>>().join("\n");
+        let chunks = chunks("src/lib.rs", &source);
+        let lines = chunks.iter().map(|chunk| (chunk.start_line, chunk.end_line)).collect::<Vec<_>>();
+        assert_eq!(lines, vec![(1, 40), (33, 72), (65, 90)]);
+        assert!(chunks[1].text.starts_with("line 33\n"));
+        assert_eq!(decode(&encode(&[0.5, -1.25])), vec![0.5, -1.25]);
+    }
+}
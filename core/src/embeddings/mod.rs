@@ -0,0 +1,165 @@
+pub mod index;
+mod ollama;
+mod openai;
+mod voyage;
+
+pub use ollama::OllamaEmbeddings;
+pub use openai::OpenAIEmbeddings;
+pub use voyage::VoyageEmbeddings;
+
+use std::path::Path;
+
+use crate::config::{EmbeddingsConfig, ProjectConfig, SandboxConfig};
+use crate::inference::types::InferenceError;
+use crate::sandbox::Sandbox;
+use crate::secrets::Guard;
+use crate::tree::GitTree;
+
+/// The tool the model searches the workspace by meaning with.
+pub const SEARCH_TOOL: &str = "semantic_search";
+
+// The collection of the index holding the workspace files
+pub const WORKSPACE_COLLECTION: &str = "workspace";
+
+// The collection of the files matching `docs`
+const DOCS_COLLECTION: &str = "docs";
+
+// Snippets a search returns
+const SEARCH_RESULTS: usize = 5;
+
+/// Turns text into vectors whose closeness tells how related the texts are.
+#[allow(async_fn_in_trait)]
+pub trait Embedder {
+    /// Stored with indexed files, vectors of different models don't compare.
+    fn model(&self) -> &str;
+
+    /// One vector per text, in order.
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, InferenceError>;
+}
+
+pub enum EmbeddingProvider {
+    OpenAI(OpenAIEmbeddings),
+    Voyage(VoyageEmbeddings),
+    Ollama(OllamaEmbeddings),
+}
+
+impl EmbeddingProvider {
+    pub fn from_config(config: &EmbeddingsConfig) -> Result<Self, anyhow::Error> {
+        match config.provider.as_str() {
+            "openai" => Ok(EmbeddingProvider::OpenAI(OpenAIEmbeddings::from_config(config))),
+            "voyage" => Ok(EmbeddingProvider::Voyage(VoyageEmbeddings::from_config(config))),
+            "ollama" => Ok(EmbeddingProvider::Ollama(OllamaEmbeddings::from_config(config))),
+            provider => Err(anyhow::anyhow!("Unknown embeddings provider {:?}, use openai, voyage or ollama", provider)),
+        }
+    }
+
+    pub fn model(&self) -> &str {
+        match self {
+            EmbeddingProvider::OpenAI(embedder) => embedder.model(),
+            EmbeddingProvider::Voyage(embedder) => embedder.model(),
+            EmbeddingProvider::Ollama(embedder) => embedder.model(),
+        }
+    }
+
+    pub async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, anyhow::Error> {
+        let vectors = match self {
+            EmbeddingProvider::OpenAI(embedder) => embedder.embed(texts).await,
+            EmbeddingProvider::Voyage(embedder) => embedder.embed(texts).await,
+            EmbeddingProvider::Ollama(embedder) => embedder.embed(texts).await,
+        };
+        let vectors = vectors.map_err(|e| anyhow::Error::new(e).context("Could not compute embeddings"))?;
+        if vectors.len() != texts.len() {
+            return Err(anyhow::anyhow!("Asked for {} embeddings but got {}", texts.len(), vectors.len()));
+        }
+        Ok(vectors)
+    }
+}
+
+/// How close two vectors point, from -1 to 1.
+pub fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    match norm(a) * norm(b) {
+        0.0 => 0.0,
+        norms => dot / norms,
+    }
+}
+
+// What is embedded goes to the embeddings provider, with its secrets masked like in requests
+async fn sync_collection(config: &ProjectConfig, collection: &str, files: &[String], secrets: &Guard) -> Result<(index::Index, EmbeddingProvider, index::SyncStats), anyhow::Error> {
+    let embedder = EmbeddingProvider::from_config(&config.embeddings)?;
+    let mut index = index::Index::open(collection)?;
+    let stats = index.sync(&GitTree::get_git_root()?, files, &embedder, secrets).await?;
+    Ok((index, embedder, stats))
+}
+
+// Of the workspace files, those the file tools could read, the search shows the model no others
+fn readable(git_root: &Path, sandbox: &SandboxConfig, files: Vec<String>) -> Result<Vec<String>, anyhow::Error> {
+    let sandbox = Sandbox::new(git_root, sandbox)?;
+    Ok(files.into_iter().filter(|path| sandbox.can_read(&git_root.join(path))).collect())
+}
+
+fn workspace_files(config: &ProjectConfig) -> Result<Vec<String>, anyhow::Error> {
+    readable(&GitTree::get_git_root()?, &config.sandbox, GitTree::workspace_files(&config.ignore)?)
+}
+
+/// Brings the index of the workspace files not in `ignore` and inside the sandbox up to date.
+pub async fn sync_workspace(config: &ProjectConfig) -> Result<(index::Index, EmbeddingProvider, index::SyncStats), anyhow::Error> {
+    sync_collection(config, WORKSPACE_COLLECTION, &workspace_files(config)?, &Guard::new(&config.secrets)?).await
+}
+
+/// Syncs the workspace index, then finds the snippets closest to `query`.
+pub async fn search_workspace(config: &ProjectConfig, query: &str) -> Result<String, anyhow::Error> {
+    let secrets = Guard::new(&config.secrets)?;
+    let (index, embedder, stats) = sync_collection(config, WORKSPACE_COLLECTION, &workspace_files(config)?, &secrets).await?;
+    log::debug!("Workspace index synced: {:?}", stats);
+    Ok(format_hits(&index.search(&secrets.masked(query), &embedder, SEARCH_RESULTS).await?))
+}
+
+/// The excerpts of the files matching `docs` closest to `question`, empty when `docs` is.
+pub async fn docs_context(config: &ProjectConfig, question: &str) -> Result<String, anyhow::Error> {
+    if config.docs.is_empty() || config.docs_results == 0 {
+        return Ok(String::new());
+    }
+    let files = GitTree::workspace_files(&config.ignore)?.into_iter()
+        .filter(|path| GitTree::matches(path, &config.docs))
+        .collect::<Vec<String>>();
+    let secrets = Guard::new(&config.secrets)?;
+    let (index, embedder, stats) = sync_collection(config, DOCS_COLLECTION, &files, &secrets).await?;
+    log::debug!("Docs index synced: {:?}", stats);
+    let hits = index.search(&secrets.masked(question), &embedder, config.docs_results).await?;
+    Ok(if hits.is_empty() { String::new() } else { format!("{}\n", format_hits(&hits)) })
+}
+
+/// The results of a search as the tool returns them, each chunk under its path and lines.
+pub fn format_hits(hits: &[index::Hit]) -> String {
+    if hits.is_empty() {
+        return "Nothing indexed matched the query".to_string();
+    }
+    hits.iter()
+        .map(|hit| format!("{}:{}-{} ({:.2})\n```\n{}\n```", hit.chunk.path, hit.chunk.start_line, hit.chunk.end_line, hit.score, hit.chunk.text))
+        .collect::<Vec<String>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_readable() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let repo = dir.path().join("repo");
+        std::fs::create_dir_all(repo.join("app/src"))?;
+        std::fs::create_dir_all(dir.path().join("outside"))?;
+        std::fs::write(dir.path().join("outside/secrets.txt"), "")?;
+        std::os::unix::fs::symlink(dir.path().join("outside/secrets.txt"), repo.join("app/src/linked.txt"))?;
+        let files = ["app/src/main.rs", "app/src/linked.txt", "deploy/prod.env", "pprog.toml"].map(String::from).to_vec();
+
+        let sandbox = SandboxConfig { root: Some("app".to_string()), read_only: Vec::new() };
+        // pprog.toml stays readable to the tools wherever the root is
+        assert_eq!(readable(&repo, &sandbox, files.clone())?, ["app/src/main.rs", "pprog.toml"]);
+        assert_eq!(readable(&repo, &SandboxConfig::default(), files)?, ["app/src/main.rs", "deploy/prod.env", "pprog.toml"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+use reqwest::Client;
+use serde::Deserialize;
+
+use crate::config::EmbeddingsConfig;
+use crate::inference::types::InferenceError;
+use crate::transport;
+use super::Embedder;
+
+const DEFAULT_BASE_URL: &str = "http://localhost:11434";
+const DEFAULT_MODEL: &str = "nomic-embed-text";
+
+#[derive(Debug, Deserialize)]
+struct EmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+/// Embeddings from `/api/embed` of a local Ollama server, nothing leaves the machine.
+pub struct OllamaEmbeddings {
+    client: Client,
+    base_url: String,
+    model: String,
+}
+
+impl OllamaEmbeddings {
+    pub fn from_config(config: &EmbeddingsConfig) -> Self {
+        OllamaEmbeddings {
+            client: transport::client(),
+            base_url: match config.base_url.as_str() {
+                "" => DEFAULT_BASE_URL.to_string(),
+                base_url => base_url.trim_end_matches('/').to_string(),
+            },
+            model: match config.model.as_str() {
+                "" => DEFAULT_MODEL.to_string(),
+                model => model.to_string(),
+            },
+        }
+    }
+}
+
+impl Embedder for OllamaEmbeddings {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, InferenceError> {
+        let response = self.client
+            .post(format!("{}/api/embed", self.base_url))
+            .json(&serde_json::json!({"model": self.model, "input": texts}))
+            .send()
+            .await
+            .map_err(|e| InferenceError::NetworkError(format!("{}, is Ollama running at {}?", e, self.base_url)))?;
+        let status = response.status();
+        let text = response.text().await.map_err(|e| InferenceError::NetworkError(e.to_string()))?;
+        if !status.is_success() {
+            return Err(InferenceError::ApiError(status, text));
+        }
+        let response: EmbedResponse = serde_json::from_str(&text)
+            .map_err(|e| InferenceError::InvalidResponse(e.to_string()))?;
+        Ok(response.embeddings)
+    }
+}
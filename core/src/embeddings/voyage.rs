@@ -0,0 +1,65 @@
+use reqwest::Client;
+
+use crate::config::EmbeddingsConfig;
+use crate::inference::types::InferenceError;
+use crate::transport;
+use super::openai::EmbeddingResponse;
+use super::Embedder;
+
+const DEFAULT_BASE_URL: &str = "https://api.voyageai.com/v1";
+const DEFAULT_MODEL: &str = "voyage-code-3";
+
+/// Embeddings from the Voyage AI API, which has models trained on code.
+pub struct VoyageEmbeddings {
+    client: Client,
+    base_url: String,
+    api_key: String,
+    model: String,
+}
+
+impl VoyageEmbeddings {
+    pub fn from_config(config: &EmbeddingsConfig) -> Self {
+        VoyageEmbeddings {
+            client: transport::client(),
+            base_url: match config.base_url.as_str() {
+                "" => DEFAULT_BASE_URL.to_string(),
+                base_url => base_url.trim_end_matches('/').to_string(),
+            },
+            api_key: match config.api_key.as_str() {
+                "" => std::env::var("VOYAGE_API_KEY").unwrap_or_default(),
+                api_key => api_key.to_string(),
+            },
+            model: match config.model.as_str() {
+                "" => DEFAULT_MODEL.to_string(),
+                model => model.to_string(),
+            },
+        }
+    }
+}
+
+impl Embedder for VoyageEmbeddings {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, InferenceError> {
+        if self.api_key.is_empty() {
+            return Err(InferenceError::MissingApiKey("Voyage API key for embeddings not found".to_string()));
+        }
+        let response = self.client
+            .post(format!("{}/embeddings", self.base_url))
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(&serde_json::json!({"model": self.model, "input": texts}))
+            .send()
+            .await
+            .map_err(|e| InferenceError::NetworkError(e.to_string()))?;
+        let status = response.status();
+        let text = response.text().await.map_err(|e| InferenceError::NetworkError(e.to_string()))?;
+        if !status.is_success() {
+            return Err(InferenceError::ApiError(status, text));
+        }
+        let response: EmbeddingResponse = serde_json::from_str(&text)
+            .map_err(|e| InferenceError::InvalidResponse(e.to_string()))?;
+        Ok(response.into_vectors())
+    }
+}
@@ -0,0 +1,70 @@
+use std::path::Path;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::inference::types::{ContentItem, ImageSource};
+
+// The largest image providers accept, Anthropic's limit
+const MAX_IMAGE_BYTES: usize = 5 * 1024 * 1024;
+
+// Extensions treated as images when a file is mentioned
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp"];
+
+/// Whether a mentioned file should be attached as an image rather than as text.
+pub fn is_image_path(path: &str) -> bool {
+    Path::new(path).extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+// Media type from the file's magic bytes, so a misnamed file is still sent as what it is
+fn media_type(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [0xFF, 0xD8, 0xFF, ..] => Some("image/jpeg"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// An image content item for PNG, JPEG, GIF or WebP data.
+pub fn from_bytes(bytes: &[u8]) -> Result<ContentItem, anyhow::Error> {
+    let media_type = media_type(bytes).ok_or_else(|| anyhow::anyhow!("Not a PNG, JPEG, GIF or WebP image"))?;
+    if bytes.len() > MAX_IMAGE_BYTES {
+        return Err(anyhow::anyhow!(
+            "Image is {} KB, images up to {} KB can be sent",
+            bytes.len() / 1024,
+            MAX_IMAGE_BYTES / 1024,
+        ));
+    }
+    Ok(ContentItem::Image {
+        source: ImageSource {
+            source_type: "base64".to_string(),
+            media_type: media_type.to_string(),
+            data: STANDARD.encode(bytes),
+        },
+    })
+}
+
+pub fn from_file(path: &Path) -> Result<ContentItem, anyhow::Error> {
+    let bytes = std::fs::read(path).map_err(|e| anyhow::anyhow!("Could not read {}: {}", path.display(), e))?;
+    from_bytes(&bytes).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_bytes() -> Result<(), anyhow::Error> {
+        let png = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+        let ContentItem::Image { source } = from_bytes(&png)? else { panic!("not an image") };
+        assert_eq!((source.source_type.as_str(), source.media_type.as_str()), ("base64", "image/png"));
+        assert_eq!(STANDARD.decode(&source.data)?, png);
+        assert!(from_bytes(b"plain text").is_err());
+        assert!(is_image_path("docs/Screenshot.PNG"));
+        assert!(!is_image_path("src/main.rs"));
+        Ok(())
+    }
+}
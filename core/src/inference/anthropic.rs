@@ -0,0 +1,457 @@
+use reqwest::Client;
+use serde::{Serialize, Deserialize};
+use anyhow::Result;
+
+use crate::config::ProjectConfig;
+use crate::transport;
+use super::types::{
+    split_system, ContentItem, Inference, InferenceError, Message, ModelResponse, ResponseSchema, ResponseType, Role, Sampling, null_as_default, StopReason, Usage, CACHE_BREAK
+};
+use super::stream::{SseParser, StreamAccumulator};
+use super::tools::{self, AnthropicTool};
+use super::wire::{Exchange, WireLog};
+
+fn no_tools(tools: &serde_json::Value) -> bool {
+    tools.as_array().is_some_and(Vec::is_empty)
+}
+
+#[derive(Serialize)]
+struct AnthropicRequest<'a> {
+    model: &'a str,
+    messages: Vec<Message>,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "no_tools")]
+    tools: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    system: Vec<SystemBlock<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    stop_sequences: &'a [String],
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+}
+
+#[derive(Serialize)]
+pub(super) struct SystemBlock<'a> {
+    #[serde(rename = "type")]
+    block_type: &'static str,
+    text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_control: Option<serde_json::Value>,
+}
+
+/// The system prompt as text blocks, with a cache breakpoint after the stable part when
+/// `CACHE_BREAK` marks where it ends.  Cached prompts are shared between requests, so
+/// only the messages and the part that changes are paid for in full.
+pub(super) fn system_blocks(system: &str) -> Vec<SystemBlock<'_>> {
+    let (stable, volatile) = split_system(system);
+    let cached = system.contains(CACHE_BREAK);
+    [(stable, cached), (volatile, false)].into_iter()
+        .filter(|(text, _)| !text.trim().is_empty())
+        .map(|(text, cached)| SystemBlock {
+            block_type: "text",
+            text,
+            cache_control: cached.then(|| serde_json::json!({"type": "ephemeral"})),
+        })
+        .collect()
+}
+
+// Every field but the content may be missing, and blocks of types added later parse as unknown ones
+#[derive(Debug, Deserialize)]
+struct AnthropicResponse {
+    #[serde(default, deserialize_with = "null_as_default")]
+    id: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    model: String,
+    #[serde(default)]
+    role: Option<Role>,
+    content: Vec<ContentItem>,
+    #[serde(default)]
+    stop_reason: Option<StopReason>,
+    #[serde(default)]
+    stop_sequence: Option<String>,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+pub struct AnthropicInference {
+    model: String,
+    client: Client,
+    base_url: String,
+    api_key: String,
+    max_output_tokens: u32,
+    temperature: Option<f32>,
+    stop_sequences: Vec<String>,
+    with_tools: bool,
+    wire: WireLog,
+}
+
+impl AnthropicInference {
+    /// A provider on the config pprog.toml and the repository policy give, which fails when
+    /// they don't load rather than sending on the defaults.
+    pub fn load() -> Result<Self, anyhow::Error> {
+        Self::from_config(&*ProjectConfig::shared()?)
+    }
+
+    pub fn from_config(config: &ProjectConfig) -> Result<Self, anyhow::Error> {
+        Ok(AnthropicInference {
+            model: config.model.clone(),
+            client: transport::client(),
+            base_url: config.base_url.clone(),
+            api_key: config.api_key.clone(),
+            max_output_tokens: config.max_output_tokens,
+            temperature: config.sampling_temperature(),
+            stop_sequences: config.stop_sequences.clone(),
+            with_tools: !config.no_tools,
+            wire: WireLog::from_config(config)?,
+        })
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn get_tools(&self) -> Vec<AnthropicTool> {
+        match self.with_tools {
+            true => tools::definitions(),
+            false => Vec::new(),
+        }
+    }
+
+    fn get_tools_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        serde_json::to_value(self.get_tools())
+    }
+
+    async fn send(
+        &self,
+        messages: Vec<Message>,
+        system_message: Option<&str>,
+        stream: bool,
+        schema: Option<&ResponseSchema>,
+    ) -> Result<(transport::Response, Exchange), InferenceError> {
+        if self.api_key.is_empty() {
+            return Err(InferenceError::MissingApiKey("Anthropic API key not found".to_string()));
+        }
+
+        let system = system_blocks(system_message.unwrap_or(""));
+
+        let (tools, tool_choice) = match schema {
+            // Forcing a call of the one tool makes its input the structured reply
+            Some(schema) => (
+                serde_json::json!([{
+                    "name": schema.name,
+                    "description": "Reply with the requested data.",
+                    "input_schema": schema.schema,
+                }]),
+                Some(serde_json::json!({"type": "tool", "name": schema.name})),
+            ),
+            None => (self.get_tools_json().map_err(|e| InferenceError::SerializationError(e.to_string()))?, None),
+        };
+
+        let request = AnthropicRequest {
+            model: &self.model,
+            messages,
+            max_tokens: self.max_output_tokens,
+            tools,
+            tool_choice,
+            system,
+            temperature: self.temperature,
+            stop_sequences: &self.stop_sequences,
+            stream,
+        };
+
+        let request = self.client
+            .post(format!("{}/messages", self.base_url))
+            .header("Content-Type", "application/json")
+            .header("X-API-Key", &self.api_key)
+            .header("anthropic-version", "2023-06-01")
+            .json(&request);
+        self.wire.send("anthropic", request)
+            .await
+            .map_err(|e| InferenceError::NetworkError(e.to_string()))
+    }
+
+    pub async fn query_model(&self, messages: Vec<Message>, system_message: Option<&str>) -> Result<ModelResponse, InferenceError> {
+        self.complete(messages, system_message, None).await
+    }
+
+    /// Asks for a reply following `schema`, as the input of a forced tool call.
+    pub async fn query_model_structured(
+        &self,
+        messages: Vec<Message>,
+        system_message: Option<&str>,
+        schema: &ResponseSchema,
+    ) -> Result<ModelResponse, InferenceError> {
+        self.complete(messages, system_message, Some(schema)).await
+    }
+
+    async fn complete(
+        &self,
+        messages: Vec<Message>,
+        system_message: Option<&str>,
+        schema: Option<&ResponseSchema>,
+    ) -> Result<ModelResponse, InferenceError> {
+        let (response, mut exchange) = self.send(messages, system_message, false, schema).await?;
+
+        let status = response.status();
+        let response_text = response.text().await
+            .map_err(|e| InferenceError::NetworkError(e.to_string()))?;
+        exchange.body(response_text.as_bytes());
+        log::info!("{:?}", response_text);
+
+        if !status.is_success() {
+            return Err(InferenceError::ApiError(status, response_text));
+        }
+
+        let raw: serde_json::Value = serde_json::from_str(&response_text)
+            .map_err(|e| InferenceError::InvalidResponse(e.to_string()))?;
+        parse_response(raw)
+    }
+
+    /// Streams the response into `acc`.  On error `acc` keeps everything received so far.
+    pub async fn query_model_stream(
+        &self,
+        messages: Vec<Message>,
+        system_message: Option<&str>,
+        acc: &mut StreamAccumulator<'_>,
+    ) -> Result<(), InferenceError> {
+        let (mut response, mut exchange) = self.send(messages, system_message, true, None).await?;
+        let status = response.status();
+        if !status.is_success() {
+            let response_text = response.text().await
+                .map_err(|e| InferenceError::NetworkError(e.to_string()))?;
+            exchange.body(response_text.as_bytes());
+            return Err(InferenceError::ApiError(status, response_text));
+        }
+
+        let mut parser = SseParser::default();
+        while let Some(chunk) = response.chunk().await.map_err(|e| InferenceError::NetworkError(e.to_string()))? {
+            exchange.body(&chunk);
+            for event in parser.push(&chunk) {
+                let data: serde_json::Value = serde_json::from_str(&event.data)
+                    .map_err(|e| InferenceError::InvalidResponse(format!("Invalid stream event: {}", e)))?;
+                if apply_stream_event(&data, acc)? {
+                    return Ok(());
+                }
+            }
+        }
+        Err(InferenceError::NetworkError("Stream ended before message_stop".to_string()))
+    }
+}
+
+impl Inference for AnthropicInference {
+    async fn query_model(&self, messages: Vec<Message>, system_message: Option<&str>) -> Result<ModelResponse, InferenceError> {
+        AnthropicInference::query_model(self, messages, system_message).await
+    }
+
+    fn sampling(&self) -> Sampling {
+        Sampling { model: self.model.clone(), temperature: self.temperature, seed: None }
+    }
+
+    async fn query_model_stream(
+        &self,
+        messages: Vec<Message>,
+        system_message: Option<&str>,
+        acc: &mut StreamAccumulator<'_>,
+    ) -> Result<(), InferenceError> {
+        AnthropicInference::query_model_stream(self, messages, system_message, acc).await
+    }
+}
+
+fn parse_response(raw: serde_json::Value) -> Result<ModelResponse, InferenceError> {
+    let anthropic_response = AnthropicResponse::deserialize(&raw)
+        .map_err(|e| InferenceError::InvalidResponse(e.to_string()))?;
+    Ok(ModelResponse {
+        stop_reason: anthropic_response.stop_reason.unwrap_or_else(|| StopReason::implied(&anthropic_response.content)),
+        content: anthropic_response.content,
+        id: anthropic_response.id,
+        model: anthropic_response.model,
+        role: anthropic_response.role.unwrap_or(Role::Assistant),
+        message_type: ResponseType::Message,
+        stop_sequence: anthropic_response.stop_sequence,
+        usage: anthropic_response.usage,
+        raw: Some(raw),
+        timing: None,
+    })
+}
+
+/// Applies one Messages API stream event, returns true once the message is complete.
+fn apply_stream_event(data: &serde_json::Value, acc: &mut StreamAccumulator<'_>) -> Result<bool, InferenceError> {
+    let index = data["index"].as_u64().unwrap_or_default() as usize;
+    match data["type"].as_str().unwrap_or_default() {
+        "message_start" => {
+            let message = &data["message"];
+            acc.id = message["id"].as_str().unwrap_or_default().to_string();
+            acc.model = message["model"].as_str().unwrap_or_default().to_string();
+            acc.usage = Usage::deserialize(&message["usage"]).ok();
+        },
+        "content_block_start" => {
+            let block = &data["content_block"];
+            match block["type"].as_str() {
+                Some("tool_use") => acc.start_tool_use(
+                    index,
+                    block["id"].as_str().unwrap_or_default(),
+                    block["name"].as_str().unwrap_or_default(),
+                ),
+                Some("text") => acc.push_text(index, block["text"].as_str().unwrap_or_default()),
+                _ => {},
+            }
+        },
+        "content_block_delta" => {
+            let delta = &data["delta"];
+            match delta["type"].as_str() {
+                Some("text_delta") => acc.push_text(index, delta["text"].as_str().unwrap_or_default()),
+                Some("input_json_delta") => acc.push_tool_input(index, delta["partial_json"].as_str().unwrap_or_default()),
+                Some("thinking_delta") => acc.push_thinking(delta["thinking"].as_str().unwrap_or_default()),
+                _ => {},
+            }
+        },
+        "message_delta" => {
+            if let Some(reason) = data["delta"]["stop_reason"].as_str() {
+                acc.stop_reason = Some(StopReason::from(reason));
+            }
+            acc.stop_sequence = data["delta"]["stop_sequence"].as_str().map(String::from);
+            // The final output count arrives here, input counts came with message_start
+            if let (Some(usage), Some(output_tokens)) = (acc.usage.as_mut(), data["usage"]["output_tokens"].as_u64()) {
+                usage.output_tokens = output_tokens as u32;
+            }
+        },
+        "message_stop" => return Ok(true),
+        "error" => {
+            return Err(InferenceError::NetworkError(format!("Stream error: {}", data["error"]["message"].as_str().unwrap_or_default())));
+        },
+        _ => {},
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    // A JSON value of any shape, from a seeded generator so a failure repeats
+    fn any_value(rng: &mut fastrand::Rng, depth: usize) -> Value {
+        match rng.u8(0..if depth == 0 { 4 } else { 6 }) {
+            0 => Value::Null,
+            1 => json!(rng.bool()),
+            2 => json!(rng.i64(-1000..1000)),
+            3 => json!((0..rng.usize(0..8)).map(|_| rng.alphanumeric()).collect::<String>()),
+            4 => Value::Array((0..rng.usize(0..3)).map(|_| any_value(rng, depth - 1)).collect()),
+            _ => Value::Object((0..rng.usize(0..3)).map(|i| (format!("field_{}", i), any_value(rng, depth - 1))).collect()),
+        }
+    }
+
+    /*
+        * Responses as the API sends them now and might later: known blocks next to blocks of
+        * new types, extra fields at every level, optional fields left out or null.  Every one
+        * has to parse, with the known blocks intact and the unknown ones kept as they came.
+    */
+    #[test]
+    fn test_lenient_parsing() {
+        let mut rng = fastrand::Rng::with_seed(188);
+        for _ in 0..500 {
+            let mut content = Vec::new();
+            let mut expected = Vec::new();
+            for i in 0..rng.usize(0..5) {
+                let (mut block, item) = match rng.u8(0..3) {
+                    0 => (json!({"type": "text", "text": format!("text {}", i)}), ContentItem::Text { text: format!("text {}", i) }),
+                    1 => (
+                        json!({"type": "tool_use", "id": format!("toolu_{}", i), "name": "read_file", "input": {"path": "a.rs"}}),
+                        ContentItem::ToolUse { id: format!("toolu_{}", i), name: "read_file".to_string(), input: json!({"path": "a.rs"}) },
+                    ),
+                    _ => {
+                        let block = json!({"type": format!("new_block_{}", rng.u8(..)), "data": any_value(&mut rng, 3)});
+                        (block.clone(), ContentItem::Unknown(block))
+                    },
+                };
+                if !matches!(item, ContentItem::Unknown(_)) && rng.bool() {
+                    block["citations"] = any_value(&mut rng, 2);
+                }
+                let item = match (&item, rng.u8(0..8)) {
+                    // A known block missing what makes it one is kept as it came too
+                    (ContentItem::Text { .. }, 0) => {
+                        block.as_object_mut().unwrap().remove("text");
+                        ContentItem::Unknown(block.clone())
+                    },
+                    _ => item,
+                };
+                content.push(block);
+                expected.push(item);
+            }
+
+            let mut response = json!({"content": content});
+            for (field, value) in [
+                ("id", json!("msg_1")),
+                ("type", json!("message")),
+                ("role", json!("assistant")),
+                ("model", json!("claude-3-5-haiku-latest")),
+                ("stop_reason", json!("end_turn")),
+                ("stop_sequence", Value::Null),
+                ("usage", json!({"input_tokens": 10, "output_tokens": 5, "cache_creation_input_tokens": null, "service_tier": "standard"})),
+            ] {
+                match rng.u8(0..4) {
+                    0 => {},
+                    1 => response[field] = Value::Null,
+                    _ => response[field] = value,
+                }
+            }
+            response["container"] = any_value(&mut rng, 2);
+
+            let parsed = parse_response(response.clone()).unwrap_or_else(|e| panic!("{} for {}", e, response));
+            assert_eq!(parsed.content, expected, "for {}", response);
+            let reparsed = serde_json::from_value::<Vec<ContentItem>>(serde_json::to_value(&parsed.content).unwrap()).unwrap();
+            assert_eq!(reparsed, expected);
+        }
+    }
+
+    #[test]
+    fn test_system_blocks() {
+        let blocks = serde_json::to_value(system_blocks("Instructions.\n\u{1d}\nGit: 2 files changed")).unwrap();
+        assert_eq!(blocks, serde_json::json!([
+            {"type": "text", "text": "Instructions.\n", "cache_control": {"type": "ephemeral"}},
+            {"type": "text", "text": "\nGit: 2 files changed"},
+        ]));
+        assert_eq!(serde_json::to_value(system_blocks("Judge these.")).unwrap(), serde_json::json!([{"type": "text", "text": "Judge these."}]));
+        assert!(system_blocks("").is_empty());
+    }
+
+    #[test]
+    fn test_apply_stream_events() -> Result<(), InferenceError> {
+        let events = [
+            r#"{"type": "message_start", "message": {"id": "msg_1", "model": "claude-3-5-haiku-latest", "usage": {"input_tokens": 12, "output_tokens": 1}}}"#,
+            r#"{"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}}"#,
+            r#"{"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "Reading."}}"#,
+            r#"{"type": "content_block_start", "index": 1, "content_block": {"type": "tool_use", "id": "toolu_1", "name": "read_file", "input": {}}}"#,
+            r#"{"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "{\"path\": "}}"#,
+            r#"{"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "\"a.rs\"}"}}"#,
+            r#"{"type": "message_delta", "delta": {"stop_reason": "tool_use"}, "usage": {"output_tokens": 30}}"#,
+            r#"{"type": "message_stop"}"#,
+        ];
+        let mut acc = StreamAccumulator::new(|_| {});
+        let mut done = false;
+        for event in events {
+            done = apply_stream_event(&serde_json::from_str(event).unwrap(), &mut acc)?;
+        }
+        assert!(done);
+
+        let response = acc.finish()?;
+        assert_eq!(response.id, "msg_1");
+        assert_eq!(response.stop_reason, StopReason::ToolUse);
+        assert_eq!(response.usage.map(|usage| (usage.input_tokens, usage.output_tokens)), Some((12, 30)));
+        assert_eq!(response.content[1], ContentItem::ToolUse {
+            id: "toolu_1".to_string(),
+            name: "read_file".to_string(),
+            input: serde_json::json!({"path": "a.rs"}),
+        });
+
+        let mut acc = StreamAccumulator::new(|_| {});
+        apply_stream_event(&serde_json::json!({"type": "message_start", "message": {"id": "msg_2", "model": "claude-3-5-haiku-latest"}}), &mut acc)?;
+        apply_stream_event(&serde_json::json!({"type": "message_delta", "delta": {"stop_reason": "stop_sequence", "stop_sequence": "</answer>"}}), &mut acc)?;
+        assert_eq!(acc.finish()?.stop_sequence.as_deref(), Some("</answer>"));
+        Ok(())
+    }
+}
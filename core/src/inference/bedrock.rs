@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use aws_sdk_bedrockruntime::Client as BedrockClient;
+use aws_sdk_bedrockruntime::primitives::Blob;
+use serde_json::json;
+
+use super::types::{Message, ModelResponse, Inference, InferenceError, Sampling};
+use super::anthropic;
+use super::tools::{self, AnthropicTool};
+use super::wire::WireLog;
+
+pub struct AWSBedrockInference {
+    client: Arc<BedrockClient>, 
+    model_id: String,
+    temperature: f32,
+    max_tokens: Option<i32>,
+    stop_sequences: Vec<String>,
+    with_tools: bool,
+    wire: WireLog,
+}
+
+impl AWSBedrockInference {
+    pub async fn new(
+        model_id: String,
+        temperature: f32,
+        max_tokens: Option<i32>,
+        stop_sequences: Vec<String>,
+        wire: WireLog,
+    ) -> Result<Self> {
+        let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+        let client = Arc::new(BedrockClient::new(&config));
+
+        Ok(Self {
+            client,
+            model_id,
+            temperature,
+            max_tokens,
+            stop_sequences,
+            with_tools: true,
+            wire,
+        })
+    }
+
+    /// Sends requests without the agent's tools.
+    pub fn without_tools(mut self) -> Self {
+        self.with_tools = false;
+        self
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model_id
+    }
+
+    fn get_anthropic_tools(&self) -> Vec<AnthropicTool> {
+        match self.with_tools {
+            true => tools::definitions(),
+            false => Vec::new(),
+        }
+    }
+}
+
+impl Inference for AWSBedrockInference {
+    async fn query_model(&self, messages: Vec<Message>, system_message: Option<&str>) -> Result<ModelResponse, InferenceError> {
+        let body = if self.model_id.contains("anthropic") {
+            // Anthropic Claude models
+            let tools_json = match serde_json::to_value(self.get_anthropic_tools()) {
+                Ok(tools) => tools,
+                Err(_) => json!(null),
+            };
+            let sys_msg = anthropic::system_blocks(system_message.unwrap_or_default());
+
+            let mut body = json!({
+                "anthropic_version": "bedrock-2023-05-31",
+                "system": sys_msg,
+                "messages": messages,
+                "max_tokens": self.max_tokens.unwrap_or(2000),
+                "temperature": self.temperature,
+                "stop_sequences": self.stop_sequences,
+                "tools": tools_json
+            });
+            if !self.with_tools {
+                body.as_object_mut().map(|body| body.remove("tools"));
+            }
+            body
+        } else {
+            return Err(InferenceError::InvalidResponse(format!("Unsupported model: {}", self.model_id)));
+        };
+
+        let body = serde_json::to_string(&body).map_err(|e| 
+            InferenceError::SerializationError(e.to_string())
+        )?.into_bytes();
+        // The SDK signs and sends the request, only the model and the body are known here
+        let mut exchange = self.wire.request("bedrock", "POST", &format!("bedrock:model/{}/invoke", self.model_id), &Default::default(), Some(&body));
+        let response = self.client
+            .invoke_model()
+            .model_id(&self.model_id)
+            .accept("application/json")
+            .content_type("application/json")
+            .body(Blob::new(body))
+            .send()
+            .await
+            .map_err(|e| InferenceError::NetworkError(e.to_string()));
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                exchange.body(e.to_string().as_bytes());
+                return Err(e);
+            },
+        };
+        let body = response.body.into_inner();
+        exchange.set_status(200);
+        exchange.body(&body);
+
+        ModelResponse::from_bytes(&body)
+            .map_err(|e| InferenceError::InvalidResponse(e.to_string()))
+    }
+
+    fn sampling(&self) -> Sampling {
+        Sampling { model: self.model_id.clone(), temperature: Some(self.temperature), seed: None }
+    }
+}
@@ -0,0 +1,142 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// Records every request to providers and their responses to this file
+pub const RECORD_VAR: &str = "PPROG_RECORD";
+// Answers every request to providers from this file instead of the network
+pub const REPLAY_VAR: &str = "PPROG_REPLAY";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    pub body: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    /// The body as text, a JSON document or a server-sent event stream
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>,
+}
+
+/*
+    * A cassette of HTTP interactions with the providers, for tests and demos that run the
+    * same way every time without a network or a real API key.  With `PPROG_RECORD=<file>` each
+    * request and its response is written down as it happens, redacted like the wire log, and
+    * with `PPROG_REPLAY=<file>` requests are answered from the file instead: the first one not
+    * yet played with the same method and URL, so a run replays in the order it was recorded.
+    * Bedrock goes through the AWS SDK and is neither recorded nor replayed.
+*/
+enum Mode {
+    Record { path: PathBuf, cassette: Mutex<Cassette> },
+    // A cassette that failed to load fails every request with why
+    Replay { path: PathBuf, cassette: Result<Cassette, String>, played: Mutex<Vec<bool>> },
+}
+
+static MODE: OnceLock<Option<Mode>> = OnceLock::new();
+
+fn mode() -> Option<&'static Mode> {
+    MODE.get_or_init(|| {
+        if let Some(path) = std::env::var_os(REPLAY_VAR) {
+            let path = PathBuf::from(path);
+            let cassette = load(&path).map_err(|e| e.to_string());
+            let played = Mutex::new(vec![false; cassette.as_ref().map_or(0, |cassette| cassette.interactions.len())]);
+            return Some(Mode::Replay { path, cassette, played });
+        }
+        std::env::var_os(RECORD_VAR).map(|path| Mode::Record { path: PathBuf::from(path), cassette: Mutex::default() })
+    }).as_ref()
+}
+
+pub fn load(path: &Path) -> Result<Cassette, anyhow::Error> {
+    let text = fs::read_to_string(path).map_err(|e| anyhow::anyhow!("Failed to read the cassette {}: {}", path.display(), e))?;
+    serde_json::from_str(&text).map_err(|e| anyhow::anyhow!("Invalid cassette {}: {}", path.display(), e))
+}
+
+pub fn recording() -> bool {
+    matches!(mode(), Some(Mode::Record { .. }))
+}
+
+pub fn replaying() -> bool {
+    matches!(mode(), Some(Mode::Replay { .. }))
+}
+
+/// The recorded response to the next `method` request to `url`.
+pub fn replay(method: &str, url: &str) -> Result<RecordedResponse, anyhow::Error> {
+    let Some(Mode::Replay { path, cassette, played }) = mode() else {
+        anyhow::bail!("No cassette is being replayed");
+    };
+    let cassette = cassette.as_ref().map_err(|e| anyhow::anyhow!("{}", e))?;
+    let mut played = played.lock().unwrap();
+    next_match(cassette, &mut played, method, url)
+        .ok_or_else(|| anyhow::anyhow!("The cassette {} has no more responses to {} {}", path.display(), method, url))
+}
+
+fn next_match(cassette: &Cassette, played: &mut [bool], method: &str, url: &str) -> Option<RecordedResponse> {
+    let index = cassette.interactions.iter().zip(played.iter())
+        .position(|(interaction, played)| !played && interaction.request.method == method && interaction.request.url == url)?;
+    played[index] = true;
+    Some(cassette.interactions[index].response.clone())
+}
+
+/// Adds an interaction, already redacted, to the cassette being recorded.
+pub fn record(interaction: Interaction) {
+    let Some(Mode::Record { path, cassette }) = mode() else { return };
+    let mut cassette = cassette.lock().unwrap();
+    cassette.interactions.push(interaction);
+    // Written whole every time, so a run that is cut off still leaves a cassette that loads
+    let written = serde_json::to_string_pretty(&*cassette).map_err(anyhow::Error::from)
+        .and_then(|text| fs::write(path, text).map_err(anyhow::Error::from));
+    if let Err(e) = written {
+        log::error!("Failed to write the cassette {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn interaction(url: &str, body: &str) -> Interaction {
+        Interaction {
+            request: RecordedRequest { method: "POST".to_string(), url: url.to_string(), body: json!({}) },
+            response: RecordedResponse { status: 200, body: body.to_string() },
+        }
+    }
+
+    #[test]
+    fn test_replay_order() -> Result<(), anyhow::Error> {
+        let cassette = Cassette { interactions: vec![
+            interaction("https://api.anthropic.com/v1/messages", "first"),
+            interaction("https://api.openai.com/v1/chat/completions", "other"),
+            interaction("https://api.anthropic.com/v1/messages", "second"),
+        ] };
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("cassette.json");
+        fs::write(&path, serde_json::to_string(&cassette)?)?;
+        let cassette = load(&path)?;
+
+        let mut played = vec![false; cassette.interactions.len()];
+        let next = |played: &mut Vec<bool>| next_match(&cassette, played, "POST", "https://api.anthropic.com/v1/messages").map(|response| response.body);
+        assert_eq!(next(&mut played).as_deref(), Some("first"));
+        assert_eq!(next(&mut played).as_deref(), Some("second"));
+        assert_eq!(next(&mut played), None);
+        assert_eq!(played, [true, false, true]);
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use reqwest::StatusCode;
+
+use super::stream::StreamAccumulator;
+use super::types::{ContentItem, Inference, InferenceError, Message, ModelResponse, Sampling, StopReason};
+
+// Characters of text or tool input per chunk when a reply is streamed again
+const CHUNK_CHARS: usize = 16;
+
+/// How many faults a `ChaosInference` has injected so far.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Injected {
+    pub requests: usize,
+    pub errors: usize,
+    pub truncations: usize,
+}
+
+struct State {
+    rng: fastrand::Rng,
+    injected: Injected,
+}
+
+/*
+    * Wraps a provider to make it misbehave the way real ones do, for exercising the error,
+    * streaming and timeout handling of code built on the crate in CI: every request waits
+    * `delay` and up to `jitter` more, a share of them fail before reaching the provider with
+    * an overloaded, rate limited, server or network error, and a share of the replies are
+    * cut off.  A cut off reply stops at the output limit when it arrives in one piece, and a
+    * streamed one drops the connection partway, after some of the reply was delivered.
+    * Streamed replies are received whole from the provider and streamed again in small
+    * chunks, `chunk_delay` apart.  The faults come from a seeded generator, so a test that
+    * sets `seed` sees the same ones every run.
+*/
+pub struct ChaosInference<P> {
+    inner: P,
+    delay: Duration,
+    jitter: Duration,
+    chunk_delay: Duration,
+    error_rate: f64,
+    truncate_rate: f64,
+    state: Mutex<State>,
+}
+
+impl<P: Inference> ChaosInference<P> {
+    pub fn new(inner: P) -> Self {
+        ChaosInference {
+            inner,
+            delay: Duration::ZERO,
+            jitter: Duration::ZERO,
+            chunk_delay: Duration::ZERO,
+            error_rate: 0.0,
+            truncate_rate: 0.0,
+            state: Mutex::new(State { rng: fastrand::Rng::new(), injected: Injected::default() }),
+        }
+    }
+
+    pub fn seed(self, seed: u64) -> Self {
+        self.state.lock().unwrap().rng = fastrand::Rng::with_seed(seed);
+        self
+    }
+
+    /// Waits this long before every request.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Waits up to this much longer than `delay`, a random amount each request.
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Waits this long between the chunks of a streamed reply.
+    pub fn chunk_delay(mut self, chunk_delay: Duration) -> Self {
+        self.chunk_delay = chunk_delay;
+        self
+    }
+
+    /// Fails this share of requests, from 0 to 1.
+    pub fn error_rate(mut self, rate: f64) -> Self {
+        self.error_rate = rate;
+        self
+    }
+
+    /// Cuts off this share of replies, from 0 to 1.
+    pub fn truncate_rate(mut self, rate: f64) -> Self {
+        self.truncate_rate = rate;
+        self
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    pub fn injected(&self) -> Injected {
+        self.state.lock().unwrap().injected
+    }
+
+    // Waits out the delay, then fails the request or says where to cut the reply, a share of 0 to 1
+    async fn begin(&self) -> Result<Option<f64>, InferenceError> {
+        let (wait, error, cut) = {
+            let mut state = self.state.lock().unwrap();
+            state.injected.requests += 1;
+            let wait = self.delay + self.jitter.mul_f64(state.rng.f64());
+            let error = match state.rng.f64() < self.error_rate {
+                true => Some(state.rng.u8(0..4)),
+                false => None,
+            };
+            let cut = (error.is_none() && state.rng.f64() < self.truncate_rate).then(|| state.rng.f64());
+            match (error, cut) {
+                (Some(_), _) => state.injected.errors += 1,
+                (None, Some(_)) => state.injected.truncations += 1,
+                (None, None) => {},
+            }
+            (wait, error, cut)
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        match error {
+            Some(kind) => Err(injected_error(kind)),
+            None => Ok(cut),
+        }
+    }
+}
+
+fn injected_error(kind: u8) -> InferenceError {
+    let status = |code| StatusCode::from_u16(code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    match kind {
+        0 => InferenceError::ApiError(status(529), "Overloaded (injected by ChaosInference)".to_string()),
+        1 => InferenceError::ApiError(status(429), "Rate limited (injected by ChaosInference)".to_string()),
+        2 => InferenceError::ApiError(status(500), "Internal server error (injected by ChaosInference)".to_string()),
+        _ => InferenceError::NetworkError("Connection reset (injected by ChaosInference)".to_string()),
+    }
+}
+
+// Characters of text and tool input in `content`, what a cut is a share of
+fn streamed_chars(content: &[ContentItem]) -> usize {
+    content.iter()
+        .map(|item| match item {
+            ContentItem::Text { text } => text.chars().count(),
+            ContentItem::ToolUse { input, .. } => input.to_string().chars().count(),
+            _ => 0,
+        })
+        .sum()
+}
+
+// `text` up to `chars` characters, what of it remains of `chars`
+fn take_chars(text: &str, chars: &mut usize) -> String {
+    let taken = text.chars().take(*chars).collect::<String>();
+    *chars -= taken.chars().count();
+    taken
+}
+
+// A reply cut off at the output limit after `share` of it
+fn truncate(mut response: ModelResponse, share: f64) -> ModelResponse {
+    let mut left = (streamed_chars(&response.content) as f64 * share) as usize;
+    let mut content = Vec::new();
+    for item in response.content {
+        match item {
+            ContentItem::Text { text } if left > 0 => content.push(ContentItem::Text { text: take_chars(&text, &mut left) }),
+            // A tool call cut off has no input that parses, so none is kept
+            ContentItem::ToolUse { .. } | ContentItem::Text { .. } => break,
+            item => content.push(item),
+        }
+    }
+    response.content = content;
+    response.stop_reason = StopReason::MaxTokens;
+    response.stop_sequence = None;
+    response
+}
+
+impl<P: Inference> Inference for ChaosInference<P> {
+    async fn query_model(&self, messages: Vec<Message>, system_message: Option<&str>) -> Result<ModelResponse, InferenceError> {
+        let cut = self.begin().await?;
+        let response = self.inner.query_model(messages, system_message).await?;
+        Ok(match cut {
+            Some(share) => truncate(response, share),
+            None => response,
+        })
+    }
+
+    async fn query_model_stream(
+        &self,
+        messages: Vec<Message>,
+        system_message: Option<&str>,
+        acc: &mut StreamAccumulator<'_>,
+    ) -> Result<(), InferenceError> {
+        let cut = self.begin().await?;
+        let mut received = StreamAccumulator::new(|_| {});
+        self.inner.query_model_stream(messages, system_message, &mut received).await?;
+        let response = received.finish()?;
+
+        acc.id = response.id.clone();
+        acc.model = response.model.clone();
+        acc.usage = response.usage.clone();
+        let mut left = cut.map_or(usize::MAX, |share| (streamed_chars(&response.content) as f64 * share) as usize);
+        for (index, item) in response.content.into_iter().enumerate() {
+            let (chunks, is_tool) = match item {
+                ContentItem::Text { text } => (text.chars().collect::<Vec<char>>(), false),
+                ContentItem::ToolUse { id, name, input } => {
+                    acc.start_tool_use(index, &id, &name);
+                    (input.to_string().chars().collect::<Vec<char>>(), true)
+                },
+                ContentItem::Unknown(value) => {
+                    acc.push_unknown(index, value);
+                    continue;
+                },
+                ContentItem::ToolResult { .. } | ContentItem::Image { .. } => continue,
+            };
+            for chunk in chunks.chunks(CHUNK_CHARS) {
+                if left == 0 {
+                    return Err(InferenceError::NetworkError("Stream cut off (injected by ChaosInference)".to_string()));
+                }
+                let chunk = take_chars(&chunk.iter().collect::<String>(), &mut left);
+                match is_tool {
+                    true => acc.push_tool_input(index, &chunk),
+                    false => acc.push_text(index, &chunk),
+                }
+                if !self.chunk_delay.is_zero() {
+                    tokio::time::sleep(self.chunk_delay).await;
+                }
+            }
+        }
+        if cut.is_some() {
+            return Err(InferenceError::NetworkError("Stream cut off (injected by ChaosInference)".to_string()));
+        }
+        acc.stop_reason = Some(response.stop_reason);
+        acc.stop_sequence = response.stop_sequence;
+        Ok(())
+    }
+
+    fn sampling(&self) -> Sampling {
+        self.inner.sampling()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inference::MockInference;
+    use crate::inference::types::Role;
+
+    #[tokio::test]
+    async fn test_chaos() {
+        let user = Message { role: Role::User, content: vec![ContentItem::Text { text: "Explain".to_string() }] };
+        let reply = "A mutex lets one thread at a time into the code it guards.";
+
+        let failing = ChaosInference::new(MockInference::new("mock").text(reply)).error_rate(1.0);
+        assert!(failing.query_model(vec![user.clone()], None).await.is_err());
+        assert_eq!(failing.injected(), Injected { requests: 1, errors: 1, truncations: 0 });
+        assert_eq!(failing.inner().requests().len(), 0);
+
+        let cut = ChaosInference::new(MockInference::new("mock").text(reply).text(reply)).truncate_rate(1.0).seed(189);
+        let response = cut.query_model(vec![user.clone()], None).await.unwrap();
+        assert_eq!(response.stop_reason, StopReason::MaxTokens);
+        assert!(matches!(&response.content[..], [ContentItem::Text { text }] if reply.starts_with(text.as_str()) && text.len() < reply.len()));
+
+        let mut texts = Vec::new();
+        let mut acc = StreamAccumulator::new(|event| {
+            if let crate::inference::stream::StreamEvent::Text(text) = event {
+                texts.push(text.clone());
+            }
+        });
+        assert!(matches!(cut.query_model_stream(vec![user.clone()], None, &mut acc).await, Err(InferenceError::NetworkError(_))));
+        let partial = acc.partial_content();
+        drop(acc);
+        assert!(texts.iter().all(|text| text.chars().count() <= CHUNK_CHARS));
+        let streamed = texts.concat();
+        assert!(reply.starts_with(&streamed) && streamed.len() < reply.len());
+        assert!(partial.len() <= 1);
+
+        let calm = ChaosInference::new(MockInference::new("mock").text(reply));
+        let mut acc = StreamAccumulator::new(|_| {});
+        calm.query_model_stream(vec![user], None, &mut acc).await.unwrap();
+        assert_eq!(acc.finish().unwrap().content, vec![ContentItem::Text { text: reply.to_string() }]);
+    }
+}
@@ -0,0 +1,362 @@
+use reqwest::Client;
+use serde::{Serialize, Deserialize};
+use anyhow::Result;
+
+use crate::config::ProjectConfig;
+use crate::transport;
+use super::types::{
+    plain_system, ContentItem, Inference, InferenceError, Message, ModelResponse, ResponseType, Role, Sampling, StopReason, Usage, null_as_default
+};
+use super::stream::{SseParser, StreamAccumulator};
+use super::tools::{self, OpenAITool};
+use super::wire::{Exchange, WireLog};
+
+#[derive(Serialize)]
+struct DeepSeekRequest {
+    model: String,
+    messages: Vec<serde_json::Value>,
+    max_tokens: Option<u32>,
+    tools: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeepSeekResponse {
+    #[serde(default, deserialize_with = "null_as_default")]
+    id: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    model: String,
+    choices: Vec<DeepSeekChoice>,
+    #[serde(default)]
+    usage: Option<DeepSeekUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeepSeekUsage {
+    #[serde(default, deserialize_with = "null_as_default")]
+    prompt_tokens: u32,
+    #[serde(default, deserialize_with = "null_as_default")]
+    completion_tokens: u32,
+    #[serde(default, deserialize_with = "null_as_default")]
+    prompt_cache_hit_tokens: u32,
+    #[serde(default, deserialize_with = "null_as_default")]
+    prompt_cache_miss_tokens: u32,
+}
+
+impl From<DeepSeekUsage> for Usage {
+    fn from(usage: DeepSeekUsage) -> Self {
+        // DeepSeek caches every prompt, misses are the tokens written to the cache
+        let cached = usage.prompt_cache_hit_tokens + usage.prompt_cache_miss_tokens;
+        Usage {
+            input_tokens: usage.prompt_tokens.saturating_sub(cached),
+            cache_creation_input_tokens: usage.prompt_cache_miss_tokens,
+            cache_read_input_tokens: usage.prompt_cache_hit_tokens,
+            output_tokens: usage.completion_tokens,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DeepSeekChoice {
+    #[serde(default)]
+    finish_reason: Option<StopReason>,
+    message: DeepSeekMessage,
+}
+
+fn deserialize_content<'de, D>(deserializer: D) -> Result<Vec<ContentItem>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ContentWrapper {
+        String(String),
+        Null,
+        Array(Vec<ContentItem>),
+    }
+
+    let wrapper = ContentWrapper::deserialize(deserializer)?;
+    match wrapper {
+        ContentWrapper::String(s) => Ok(vec![ContentItem::Text { text: s }]),
+        ContentWrapper::Null => Ok(vec![]),
+        ContentWrapper::Array(v) => Ok(v),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DeepSeekMessage {
+    #[serde(default)]
+    role: Option<Role>,
+    #[serde(default, deserialize_with = "deserialize_content")]
+    content: Vec<ContentItem>,
+    #[serde(default)]
+    tool_calls: Option<Vec<DeepSeekToolCall>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeepSeekToolCall {
+    id: String,
+    #[serde(default, rename = "type")]
+    call_type: Option<String>,
+    function: DeepSeekFunctionCall,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeepSeekFunctionCall {
+    name: String,
+    arguments: String,
+}
+
+pub struct DeepSeekInference {
+    model: String,
+    client: Client,
+    base_url: String,
+    api_key: String,
+    max_output_tokens: u32,
+    temperature: Option<f32>,
+    stop_sequences: Vec<String>,
+    with_tools: bool,
+    wire: WireLog,
+}
+
+impl DeepSeekInference {
+    /// A provider on the config pprog.toml and the repository policy give, which fails when
+    /// they don't load rather than sending on the defaults.
+    pub fn load() -> Result<Self, anyhow::Error> {
+        Self::from_config(&*ProjectConfig::shared()?)
+    }
+
+    pub fn from_config(config: &ProjectConfig) -> Result<Self, anyhow::Error> {
+        Ok(DeepSeekInference {
+            model: config.model.clone(),
+            client: transport::client(),
+            base_url: config.base_url.clone(),
+            api_key: config.api_key.clone(),
+            max_output_tokens: config.max_output_tokens,
+            temperature: config.sampling_temperature(),
+            stop_sequences: config.stop_sequences.clone(),
+            with_tools: !config.no_tools,
+            wire: WireLog::from_config(config)?,
+        })
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn get_tools(&self) -> Vec<OpenAITool> {
+        match self.with_tools {
+            true => tools::definitions().into_iter().map(OpenAITool::from).collect(),
+            false => Vec::new(),
+        }
+    }
+
+    fn get_tools_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        serde_json::to_value(self.get_tools())
+    }
+
+    async fn send(&self, mut messages: Vec<Message>, system_message: Option<&str>, stream: bool) -> Result<(transport::Response, Exchange), InferenceError> {
+        if self.api_key.is_empty() {
+            return Err(InferenceError::MissingApiKey("DeepSeek API key not found".to_string()));
+        }
+
+        if let Some(sys_msg) = system_message {
+            messages.insert(0, Message {
+                role: Role::System,
+                content: vec![ContentItem::Text { text: plain_system(sys_msg) }],
+            });
+        }
+
+        let deepseek_messages = messages.into_iter().map(|msg| {
+            let content = msg.content.iter()
+                .filter_map(|item| {
+                    match item {
+                        ContentItem::Text { text } => Some(text.clone()),
+                        // DeepSeek's chat models take text only
+                        ContentItem::Image { .. } => Some("[image omitted, this model cannot see images]".to_string()),
+                        _ => None
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join(" ");
+
+            serde_json::json!({
+                "role": match msg.role {
+                    Role::User => "user",
+                    Role::Assistant => "assistant",
+                    Role::System => "system",
+                    Role::Developer => "developer",
+                },
+                "content": content
+            })
+        }).collect();
+
+        let tools = self.get_tools_json().ok().filter(|tools| tools.as_array().is_some_and(|tools| !tools.is_empty()));
+
+        let request = DeepSeekRequest {
+            model: self.model.clone(),
+            messages: deepseek_messages,
+            max_tokens: Some(self.max_output_tokens),
+            tools,
+            temperature: self.temperature,
+            stream,
+            stream_options: stream.then(|| serde_json::json!({"include_usage": true})),
+            stop: self.stop_sequences.clone(),
+        };
+
+        let request = self.client
+            .post(format!("{}/chat/completions", self.base_url))
+            .header("Content-Type", "application/json")
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(&request);
+        self.wire.send("deepseek", request)
+            .await
+            .map_err(|e| InferenceError::NetworkError(e.to_string()))
+    }
+
+    pub async fn query_model(&self, messages: Vec<Message>, system_message: Option<&str>) -> Result<ModelResponse, InferenceError> {
+        let (response, mut exchange) = self.send(messages, system_message, false).await?;
+
+        let status = response.status();
+        let response_text = response.text().await
+            .map_err(|e| InferenceError::NetworkError(e.to_string()))?;
+        exchange.body(response_text.as_bytes());
+        log::info!("{:?}", response_text);
+
+        if !status.is_success() {
+            return Err(InferenceError::ApiError(status, response_text));
+        }
+
+        let raw: serde_json::Value = serde_json::from_str(&response_text)
+            .map_err(|e| InferenceError::InvalidResponse(e.to_string()))?;
+        let deepseek_response = DeepSeekResponse::deserialize(&raw)
+            .map_err(|e| InferenceError::InvalidResponse(format!("Failed to parse DeepSeek response: {}", e)))?;
+
+        if deepseek_response.choices.is_empty() {
+            return Err(InferenceError::InvalidResponse("No choices in DeepSeek response".to_string()));
+        }
+
+        let first_choice = &deepseek_response.choices[0].message;
+        let mut content = first_choice.content.clone();
+
+        // Handle tool calls if present
+        if let Some(tool_calls) = &first_choice.tool_calls {
+            for tool_call in tool_calls {
+                if tool_call.call_type.as_deref().unwrap_or("function") == "function" {
+                    // Parse the arguments as JSON Value
+                    let input: serde_json::Value = serde_json::from_str(&tool_call.function.arguments)
+                        .map_err(|e| InferenceError::SerializationError(format!("Failed to parse tool arguments: {}", e)))?;
+
+                    content.push(ContentItem::ToolUse {
+                        id: tool_call.id.clone(),
+                        name: tool_call.function.name.clone(),
+                        input,
+                    });
+                }
+            }
+        }
+
+        Ok(ModelResponse {
+            stop_reason: deepseek_response.choices[0].finish_reason.clone().unwrap_or_else(|| StopReason::implied(&content)),
+            content,
+            id: deepseek_response.id,
+            model: deepseek_response.model,
+            role: first_choice.role.clone().unwrap_or(Role::Assistant),
+            message_type: ResponseType::Message,
+            stop_sequence: None,
+            usage: deepseek_response.usage.map(Usage::from),
+            raw: Some(raw),
+            timing: None,
+        })
+    }
+
+    /// Streams the response into `acc`.  On error `acc` keeps everything received so far.
+    pub async fn query_model_stream(
+        &self,
+        messages: Vec<Message>,
+        system_message: Option<&str>,
+        acc: &mut StreamAccumulator<'_>,
+    ) -> Result<(), InferenceError> {
+        let (mut response, mut exchange) = self.send(messages, system_message, true).await?;
+        let status = response.status();
+        if !status.is_success() {
+            let response_text = response.text().await
+                .map_err(|e| InferenceError::NetworkError(e.to_string()))?;
+            exchange.body(response_text.as_bytes());
+            return Err(InferenceError::ApiError(status, response_text));
+        }
+
+        let mut parser = SseParser::default();
+        while let Some(chunk) = response.chunk().await.map_err(|e| InferenceError::NetworkError(e.to_string()))? {
+            exchange.body(&chunk);
+            for event in parser.push(&chunk) {
+                if event.data == "[DONE]" {
+                    return Ok(());
+                }
+                let data: serde_json::Value = serde_json::from_str(&event.data)
+                    .map_err(|e| InferenceError::InvalidResponse(format!("Invalid stream chunk: {}", e)))?;
+                apply_stream_chunk(&data, acc);
+            }
+        }
+        Err(InferenceError::NetworkError("Stream ended before [DONE]".to_string()))
+    }
+}
+
+impl Inference for DeepSeekInference {
+    async fn query_model(&self, messages: Vec<Message>, system_message: Option<&str>) -> Result<ModelResponse, InferenceError> {
+        DeepSeekInference::query_model(self, messages, system_message).await
+    }
+
+    fn sampling(&self) -> Sampling {
+        Sampling { model: self.model.clone(), temperature: self.temperature, seed: None }
+    }
+
+    async fn query_model_stream(
+        &self,
+        messages: Vec<Message>,
+        system_message: Option<&str>,
+        acc: &mut StreamAccumulator<'_>,
+    ) -> Result<(), InferenceError> {
+        DeepSeekInference::query_model_stream(self, messages, system_message, acc).await
+    }
+}
+
+// Text goes into block 0 and tool call `i` into block `i + 1`
+fn apply_stream_chunk(data: &serde_json::Value, acc: &mut StreamAccumulator<'_>) {
+    if let Some(id) = data["id"].as_str() {
+        acc.id = id.to_string();
+    }
+    if let Some(model) = data["model"].as_str() {
+        acc.model = model.to_string();
+    }
+    if let Ok(usage) = DeepSeekUsage::deserialize(&data["usage"]) {
+        acc.usage = Some(Usage::from(usage));
+    }
+
+    let Some(choice) = data["choices"].get(0) else { return };
+    let delta = &choice["delta"];
+    // deepseek-reasoner streams its chain of thought ahead of the answer
+    if let Some(text) = delta["reasoning_content"].as_str() {
+        acc.push_thinking(text);
+    }
+    if let Some(text) = delta["content"].as_str() {
+        acc.push_text(0, text);
+    }
+    for call in delta["tool_calls"].as_array().into_iter().flatten() {
+        let index = call["index"].as_u64().unwrap_or_default() as usize + 1;
+        if let Some(id) = call["id"].as_str() {
+            acc.start_tool_use(index, id, call["function"]["name"].as_str().unwrap_or_default());
+        }
+        acc.push_tool_input(index, call["function"]["arguments"].as_str().unwrap_or_default());
+    }
+    if let Some(reason) = choice["finish_reason"].as_str() {
+        acc.stop_reason = Some(StopReason::from(reason));
+    }
+}
@@ -0,0 +1,427 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use regex::Regex;
+use sha2::{Digest, Sha256};
+
+use crate::config::ProjectConfig;
+use crate::crypto::Cipher;
+use crate::secrets;
+use crate::store::Store;
+use super::stream::StreamAccumulator;
+use super::types::{ContentItem, Inference, InferenceError, Message, ModelResponse, ResponseSchema, ResponseType, Role, Sampling};
+
+const REDACTED: &str = "[REDACTED]";
+// Under the data directory, one reply a file named by the hash of its request
+const CACHE_DIR: &str = "cache";
+
+/// A request on its way through the chain.  `schema` is set for structured requests.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub messages: Vec<Message>,
+    pub system: Option<String>,
+    pub schema: Option<ResponseSchema>,
+}
+
+/*
+    * A step every request to the provider passes through, for policies that hold for all
+    * of them.  `before` sees the request in the order of the chain and can change it, refuse
+    * it with an error or answer it itself, which skips the provider and the rest of the chain.
+    * `after` sees the reply on the way back, last middleware first, with the request as this
+    * one passed it on.  A streamed reply has already been shown by then, changes to it only
+    * reach the history.
+*/
+pub trait Middleware: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn before(&self, _request: &mut Request) -> Result<Option<ModelResponse>, InferenceError> {
+        Ok(None)
+    }
+
+    fn after(&self, _request: &Request, _response: &mut ModelResponse) {}
+}
+
+/// Blanks the usual key and token formats and the `redact` patterns out of requests.
+pub struct Redact {
+    patterns: Vec<Regex>,
+}
+
+impl Redact {
+    pub fn new(patterns: &[String]) -> Result<Self, anyhow::Error> {
+        let patterns = secrets::PATTERNS.iter().map(|(_, pattern)| *pattern)
+            .chain(patterns.iter().map(String::as_str))
+            .map(|pattern| Regex::new(pattern).map_err(|e| anyhow::anyhow!("Invalid redact pattern {:?}: {}", pattern, e)))
+            .collect::<Result<Vec<Regex>, anyhow::Error>>()?;
+        Ok(Redact { patterns })
+    }
+
+    fn redact(&self, text: &mut String) {
+        for pattern in &self.patterns {
+            if pattern.is_match(text) {
+                *text = pattern.replace_all(text, REDACTED).into_owned();
+            }
+        }
+    }
+}
+
+impl Middleware for Redact {
+    fn name(&self) -> &'static str {
+        "redact"
+    }
+
+    fn before(&self, request: &mut Request) -> Result<Option<ModelResponse>, InferenceError> {
+        if let Some(system) = &mut request.system {
+            self.redact(system);
+        }
+        for item in request.messages.iter_mut().flat_map(|message| message.content.iter_mut()) {
+            match item {
+                ContentItem::Text { text } => self.redact(text),
+                ContentItem::ToolResult { content, .. } => self.redact(content),
+                _ => {},
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Masks or blocks the secrets in every request as `secrets.action` says, the system prompt
+/// masked either way.
+pub struct Secrets {
+    guard: secrets::Guard,
+}
+
+impl Secrets {
+    pub fn new(guard: secrets::Guard) -> Self {
+        Secrets { guard }
+    }
+}
+
+impl Middleware for Secrets {
+    fn name(&self) -> &'static str {
+        "secrets"
+    }
+
+    fn before(&self, request: &mut Request) -> Result<Option<ModelResponse>, InferenceError> {
+        if let Some(system) = &mut request.system {
+            *system = self.guard.masked(system);
+        }
+        for message in &mut request.messages {
+            self.guard.apply(message).map_err(InferenceError::SecretsBlocked)?;
+        }
+        Ok(None)
+    }
+}
+
+/// Logs the size of every request and how its reply ended, at info level.
+pub struct Log;
+
+impl Middleware for Log {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    fn before(&self, request: &mut Request) -> Result<Option<ModelResponse>, InferenceError> {
+        let chars = request.messages.iter()
+            .flat_map(|message| &message.content)
+            .map(|item| match item {
+                ContentItem::Text { text } => text.len(),
+                ContentItem::ToolResult { content, .. } => content.len(),
+                ContentItem::ToolUse { input, .. } => input.to_string().len(),
+                ContentItem::Image { .. } | ContentItem::Unknown(_) => 0,
+            })
+            .sum::<usize>();
+        log::info!(
+            "Request of {} messages, {} characters and a system prompt of {}{}",
+            request.messages.len(), chars, request.system.as_ref().map_or(0, String::len),
+            request.schema.as_ref().map(|schema| format!(", for {}", schema.name)).unwrap_or_default(),
+        );
+        Ok(None)
+    }
+
+    fn after(&self, _request: &Request, response: &mut ModelResponse) {
+        let usage = response.usage.clone().unwrap_or_default();
+        log::info!("Reply {} ended with {:?}, {} tokens in and {} out", response.id, response.stop_reason, usage.input_tokens, usage.output_tokens);
+    }
+}
+
+/// Answers a request sent before with the same model from `.pprog/cache`, for `ttl`.  The
+/// replies are encrypted with `cipher`, that of the session store when it is encrypted.
+pub struct Cache {
+    dir: PathBuf,
+    model: String,
+    ttl: Duration,
+    cipher: Option<Cipher>,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf, model: &str, ttl: Duration, cipher: Option<Cipher>) -> Self {
+        Cache { dir, model: model.to_string(), ttl, cipher }
+    }
+
+    fn path(&self, request: &Request) -> PathBuf {
+        let key = serde_json::json!({
+            "model": self.model,
+            "system": request.system,
+            "messages": request.messages,
+            "schema": request.schema.as_ref().map(|schema| (&schema.name, &schema.schema)),
+        });
+        self.dir.join(format!("{:x}.json", Sha256::digest(key.to_string())))
+    }
+}
+
+impl Middleware for Cache {
+    fn name(&self) -> &'static str {
+        "cache"
+    }
+
+    fn before(&self, request: &mut Request) -> Result<Option<ModelResponse>, InferenceError> {
+        let path = self.path(request);
+        let fresh = fs::metadata(&path).and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| SystemTime::now().duration_since(modified).unwrap_or_default() < self.ttl);
+        if !fresh {
+            return Ok(None);
+        }
+        let cached = fs::read_to_string(&path).ok()
+            .and_then(|text| match &self.cipher {
+                Some(cipher) => cipher.decrypt(&text).ok(),
+                None => Some(text),
+            })
+            .and_then(|text| serde_json::from_str::<ModelResponse>(&text).ok());
+        // A cached reply cost nothing
+        Ok(cached.map(|response| ModelResponse { usage: None, ..response }))
+    }
+
+    fn after(&self, request: &Request, response: &mut ModelResponse) {
+        let write = || -> Result<(), anyhow::Error> {
+            let reply = serde_json::to_string(response)?;
+            let reply = match &self.cipher {
+                Some(cipher) => cipher.encrypt(&reply)?,
+                None => reply,
+            };
+            fs::create_dir_all(&self.dir)?;
+            ProjectConfig::private_file().write(true).create(true).truncate(true).open(self.path(request))?.write_all(reply.as_bytes())?;
+            Ok(())
+        };
+        if let Err(e) = write() {
+            log::error!("Failed to cache a reply in {}: {}", self.dir.display(), e);
+        }
+    }
+}
+
+/// The middleware named in `middleware.chain`, in order.  A name pprog doesn't know or
+/// middleware that can't be set up is an error, leaving one out could send what it keeps back.
+pub fn chain(config: &ProjectConfig) -> Result<Vec<Box<dyn Middleware>>, anyhow::Error> {
+    config.middleware.chain.iter()
+        .map(|name| {
+            let middleware: Result<Box<dyn Middleware>, anyhow::Error> = match name.as_str() {
+                "redact" => Redact::new(&config.redact).map(|redact| Box::new(redact) as Box<dyn Middleware>),
+                "log" => Ok(Box::new(Log)),
+                "cache" => {
+                    let dir = ProjectConfig::data_dir()?;
+                    let cipher = Store::open()?.cipher().cloned();
+                    Ok(Box::new(Cache::new(dir.join(CACHE_DIR), &config.model, Duration::from_secs(config.middleware.cache_ttl_secs), cipher)))
+                },
+                name => Err(unknown(name)),
+            };
+            middleware.map_err(|e| anyhow::anyhow!("Middleware {}: {}", name, e))
+        })
+        .collect()
+}
+
+fn unknown(name: &str) -> anyhow::Error {
+    anyhow::anyhow!("Unknown middleware {:?}, expected redact, log or cache", name)
+}
+
+/// Fails like `chain` on an unknown name or a `redact` pattern that doesn't compile, without
+/// setting anything up, for checking the config as it loads.
+pub fn check(config: &ProjectConfig) -> Result<(), anyhow::Error> {
+    if let Some(name) = config.middleware.chain.iter().find(|name| !["redact", "log", "cache"].contains(&name.as_str())) {
+        return Err(unknown(name));
+    }
+    Redact::new(&config.redact)?;
+    Ok(())
+}
+
+/// A request the chain passed on to the provider, with what each middleware passed on.
+pub struct Pending {
+    pub request: Request,
+    seen: Vec<Request>,
+}
+
+pub enum Begun {
+    /// A middleware answered the request itself
+    Answered(ModelResponse),
+    Send(Pending),
+}
+
+/// Another provider with its requests and replies passed through `chain`.
+pub struct MiddlewareInference<P> {
+    inner: P,
+    chain: Vec<Box<dyn Middleware>>,
+}
+
+impl<P: Inference> MiddlewareInference<P> {
+    pub fn new(inner: P, chain: Vec<Box<dyn Middleware>>) -> Self {
+        MiddlewareInference { inner, chain }
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// Runs the `before` of each middleware in order, until one answers.
+    pub fn begin(&self, mut request: Request) -> Result<Begun, InferenceError> {
+        let mut seen = Vec::new();
+        for middleware in &self.chain {
+            if let Some(mut response) = middleware.before(&mut request)? {
+                self.end(&seen, &mut response);
+                return Ok(Begun::Answered(response));
+            }
+            seen.push(request.clone());
+        }
+        Ok(Begun::Send(Pending { request, seen }))
+    }
+
+    /// Runs the `after` of each middleware that passed `pending` on, last first.
+    pub fn finish(&self, pending: &Pending, response: &mut ModelResponse) {
+        self.end(&pending.seen, response);
+    }
+
+    fn end(&self, seen: &[Request], response: &mut ModelResponse) {
+        for (middleware, request) in self.chain.iter().zip(seen).rev() {
+            middleware.after(request, response);
+        }
+    }
+}
+
+impl<P: Inference> Inference for MiddlewareInference<P> {
+    async fn query_model(&self, messages: Vec<Message>, system_message: Option<&str>) -> Result<ModelResponse, InferenceError> {
+        let pending = match self.begin(Request { messages, system: system_message.map(String::from), schema: None })? {
+            Begun::Answered(response) => return Ok(response),
+            Begun::Send(pending) => pending,
+        };
+        let mut response = self.inner.query_model(pending.request.messages.clone(), pending.request.system.as_deref()).await?;
+        self.finish(&pending, &mut response);
+        Ok(response)
+    }
+
+    async fn query_model_stream(
+        &self,
+        messages: Vec<Message>,
+        system_message: Option<&str>,
+        acc: &mut StreamAccumulator<'_>,
+    ) -> Result<(), InferenceError> {
+        let pending = match self.begin(Request { messages, system: system_message.map(String::from), schema: None })? {
+            Begun::Answered(response) => {
+                acc.push_response(response);
+                return Ok(());
+            },
+            Begun::Send(pending) => pending,
+        };
+        self.inner.query_model_stream(pending.request.messages.clone(), pending.request.system.as_deref(), acc).await?;
+        // Without a stop reason the reply is incomplete and `finish` fails on it
+        let Some(stop_reason) = acc.stop_reason.clone() else { return Ok(()) };
+        let content = acc.content();
+        let mut response = ModelResponse {
+            content: content.clone(),
+            id: acc.id.clone(),
+            model: acc.model.clone(),
+            role: Role::Assistant,
+            message_type: ResponseType::Message,
+            stop_reason,
+            stop_sequence: acc.stop_sequence.clone(),
+            usage: acc.usage.clone(),
+            raw: None,
+            timing: None,
+        };
+        self.finish(&pending, &mut response);
+        if response.content != content {
+            acc.replace_content(response.content);
+        }
+        acc.usage = response.usage;
+        Ok(())
+    }
+
+    fn sampling(&self) -> Sampling {
+        self.inner.sampling()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inference::MockInference;
+
+    #[tokio::test]
+    async fn test_chain() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let mock = MockInference::new("mock").text("The build is green.").text("Something else.");
+        let chain: Vec<Box<dyn Middleware>> = vec![
+            Box::new(Redact::new(&[r"hunter[0-9]+".to_string()])?),
+            Box::new(Log),
+            Box::new(Cache::new(dir.path().to_path_buf(), "mock", Duration::from_secs(60), None)),
+        ];
+        let inference = MiddlewareInference::new(mock.clone(), chain);
+        let messages = vec![Message { role: Role::User, content: vec![ContentItem::Text { text: "Is the build green? The password is hunter22".to_string() }] }];
+
+        let mut acc = StreamAccumulator::new(|_| {});
+        inference.query_model_stream(messages.clone(), Some("Be brief."), &mut acc).await?;
+        let streamed = acc.finish()?;
+        assert_eq!(mock.requests()[0].messages[0].content, vec![ContentItem::Text { text: "Is the build green? The password is [REDACTED]".to_string() }]);
+
+        // Answered from the cache, the redacted request is the same
+        let cached = inference.query_model(messages, Some("Be brief.")).await?;
+        assert_eq!(mock.requests().len(), 1);
+        assert_eq!((cached.content, cached.usage), (streamed.content, None));
+        let other = inference.query_model(vec![], Some("Be brief.")).await?;
+        assert_eq!(other.content, vec![ContentItem::Text { text: "Something else.".to_string() }]);
+
+        struct Refuse;
+        impl Middleware for Refuse {
+            fn name(&self) -> &'static str {
+                "refuse"
+            }
+
+            fn before(&self, _request: &mut Request) -> Result<Option<ModelResponse>, InferenceError> {
+                Err(InferenceError::InvalidResponse("refused".to_string()))
+            }
+        }
+        let refusing = MiddlewareInference::new(mock.clone(), vec![Box::new(Refuse)]);
+        assert!(refusing.query_model(vec![], None).await.is_err());
+        assert_eq!(mock.requests().len(), 2);
+
+        let config = |chain: &[&str], redact: &[&str]| {
+            let mut config = ProjectConfig { redact: redact.iter().map(|pattern| pattern.to_string()).collect(), ..Default::default() };
+            config.middleware.chain = chain.iter().map(|name| name.to_string()).collect();
+            config
+        };
+        assert_eq!(super::chain(&config(&["redact", "log"], &[]))?.len(), 2);
+        assert!(super::chain(&config(&["log", "compress"], &[])).is_err() && check(&config(&["log", "compress"], &[])).is_err());
+        assert!(super::chain(&config(&["redact"], &["("])).is_err() && check(&config(&[], &["("])).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cache_encrypted() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let mock = MockInference::new("mock").text("The build is green.");
+        let cipher = Cipher::from_key(&Cipher::generate_key()?)?;
+        let cache = Cache::new(dir.path().to_path_buf(), "mock", Duration::from_secs(60), Some(cipher));
+        let inference = MiddlewareInference::new(mock.clone(), vec![Box::new(cache)]);
+        let first = inference.query_model(vec![], Some("Be brief.")).await?;
+
+        let path = fs::read_dir(dir.path())?.next().expect("a cached reply")?.path();
+        let stored = fs::read_to_string(&path)?;
+        assert!(stored.starts_with(crate::crypto::ENCRYPTED_PREFIX) && !stored.contains("green"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        }
+        assert_eq!(inference.query_model(vec![], Some("Be brief.")).await?.content, first.content);
+        assert_eq!(mock.requests().len(), 1);
+        Ok(())
+    }
+}
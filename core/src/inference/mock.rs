@@ -0,0 +1,146 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use serde_json::Value;
+
+use crate::tokens;
+use super::types::{ContentItem, Inference, InferenceError, Message, ModelResponse, ResponseType, Role, Sampling, StopReason, Usage};
+
+enum Scripted {
+    Reply(Vec<ContentItem>, StopReason),
+    Error(InferenceError),
+}
+
+/// A request the mock answered, for checking what the agent sent.
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub system: Option<String>,
+    pub messages: Vec<Message>,
+}
+
+#[derive(Default)]
+struct Script {
+    queued: VecDeque<Scripted>,
+    requests: Vec<MockRequest>,
+}
+
+/*
+    * A provider that answers from a script instead of an API, for exercising the agent loop
+    * in tests: each request takes the next queued reply, text, tool calls or an error, and
+    * is kept for `requests`.  Clones share the script, so a test can keep one to queue more
+    * and inspect what was sent after handing another to `Chat::set_inference`.  Usage is
+    * estimated from the characters of the request and the reply.
+*/
+#[derive(Clone)]
+pub struct MockInference {
+    model: String,
+    script: Arc<Mutex<Script>>,
+}
+
+impl MockInference {
+    pub fn new(model: &str) -> Self {
+        MockInference { model: model.to_string(), script: Arc::default() }
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Queues a reply of `content` that stopped for `stop_reason`.
+    pub fn reply(self, content: Vec<ContentItem>, stop_reason: StopReason) -> Self {
+        self.script.lock().unwrap().queued.push_back(Scripted::Reply(content, stop_reason));
+        self
+    }
+
+    /// Queues a reply of `text` that ends the turn.
+    pub fn text(self, text: &str) -> Self {
+        self.reply(vec![ContentItem::Text { text: text.to_string() }], StopReason::EndTurn)
+    }
+
+    /// Queues a reply calling the tool `name` with `input`.
+    pub fn tool_use(self, name: &str, input: Value) -> Self {
+        let id = format!("toolu_mock_{}", uuid::Uuid::new_v4().simple());
+        self.reply(vec![ContentItem::ToolUse { id, name: name.to_string(), input }], StopReason::ToolUse)
+    }
+
+    /// Queues a failed request.
+    pub fn error(self, error: InferenceError) -> Self {
+        self.script.lock().unwrap().queued.push_back(Scripted::Error(error));
+        self
+    }
+
+    /// Every request answered so far, oldest first.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.script.lock().unwrap().requests.clone()
+    }
+
+    /// Replies queued and not yet sent.
+    pub fn remaining(&self) -> usize {
+        self.script.lock().unwrap().queued.len()
+    }
+}
+
+// Tokens of the text in `content`, estimated
+fn estimated(content: &[ContentItem]) -> u32 {
+    tokens::estimate(&crate::context::ContextManager::content_to_string(content)) as u32
+}
+
+impl Inference for MockInference {
+    async fn query_model(&self, messages: Vec<Message>, system_message: Option<&str>) -> Result<ModelResponse, InferenceError> {
+        let mut script = self.script.lock().unwrap();
+        let input_tokens = system_message.map_or(0, |system| tokens::estimate(system) as u32)
+            + messages.iter().map(|message| estimated(&message.content)).sum::<u32>();
+        script.requests.push(MockRequest { system: system_message.map(String::from), messages });
+        let (content, stop_reason) = match script.queued.pop_front() {
+            Some(Scripted::Reply(content, stop_reason)) => (content, stop_reason),
+            Some(Scripted::Error(error)) => return Err(error),
+            None => return Err(InferenceError::InvalidResponse(format!("The mock has no reply left for request {}", script.requests.len()))),
+        };
+        Ok(ModelResponse {
+            usage: Some(Usage { input_tokens, output_tokens: estimated(&content), ..Default::default() }),
+            id: format!("msg_mock_{}", script.requests.len()),
+            model: self.model.clone(),
+            role: Role::Assistant,
+            message_type: ResponseType::Message,
+            content,
+            stop_reason,
+            stop_sequence: None,
+            raw: None,
+            timing: None,
+        })
+    }
+
+    fn sampling(&self) -> Sampling {
+        Sampling { model: self.model.clone(), temperature: None, seed: None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_script() {
+        let mock = MockInference::new("mock-model")
+            .tool_use("read_file", json!({"path": "src/lib.rs"}))
+            .text("Done.")
+            .error(InferenceError::ApiError(reqwest::StatusCode::from_u16(529).unwrap(), "Overloaded".to_string()));
+        let user = Message { role: Role::User, content: vec![ContentItem::Text { text: "Read lib.rs".to_string() }] };
+
+        let first = mock.query_model(vec![user.clone()], Some("Be brief.")).await.unwrap();
+        assert_eq!(first.stop_reason, StopReason::ToolUse);
+        assert!(matches!(&first.content[0], ContentItem::ToolUse { name, .. } if name == "read_file"));
+
+        let events = mock.stream(vec![user.clone()], None).collect::<Vec<_>>().await;
+        assert!(events.iter().any(|event| matches!(event, Ok(crate::inference::stream::StreamEvent::Text(text)) if text == "Done.")));
+
+        assert!(matches!(mock.query_model(vec![user.clone()], None).await, Err(InferenceError::ApiError(..))));
+        assert!(matches!(mock.query_model(vec![user], None).await, Err(InferenceError::InvalidResponse(_))));
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[0].system.as_deref(), Some("Be brief."));
+        assert_eq!(mock.remaining(), 0);
+    }
+}
@@ -0,0 +1,23 @@
+pub mod anthropic;
+pub mod openai;
+pub mod deepseek;
+pub mod bedrock;
+pub mod cassette;
+pub mod chaos;
+pub mod middleware;
+pub mod mock;
+pub mod scrub;
+pub mod stream;
+pub mod tools;
+pub mod types;
+pub mod wire;
+
+// Re-export the inference types
+pub use anthropic::AnthropicInference;
+pub use openai::OpenAIInference;
+pub use deepseek::DeepSeekInference;
+pub use bedrock::AWSBedrockInference;
+pub use mock::MockInference;
+pub use chaos::ChaosInference;
+pub use middleware::MiddlewareInference;
+pub use scrub::ScrubbingInference;
@@ -0,0 +1,447 @@
+use reqwest::Client;
+use serde::{Serialize, Deserialize};
+use anyhow::Result;
+
+use crate::config::ProjectConfig;
+use crate::transport;
+use super::types::{
+    plain_system, ContentItem, Inference, InferenceError, Message, ModelResponse, ResponseSchema, ResponseType, Role, Sampling, StopReason, Usage, null_as_default
+};
+use super::stream::{SseParser, StreamAccumulator};
+use super::tools::{self, OpenAITool};
+use super::wire::{Exchange, WireLog};
+
+#[derive(Serialize)]
+struct OpenAIRequest {
+    model: String,
+    messages: Vec<serde_json::Value>,
+    max_tokens: Option<u32>,
+    tools: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIResponse {
+    #[serde(default, deserialize_with = "null_as_default")]
+    id: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    model: String,
+    choices: Vec<OpenAIChoice>,
+    #[serde(default)]
+    usage: Option<OpenAIUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIUsage {
+    #[serde(default, deserialize_with = "null_as_default")]
+    prompt_tokens: u32,
+    #[serde(default, deserialize_with = "null_as_default")]
+    completion_tokens: u32,
+    #[serde(default, deserialize_with = "null_as_default")]
+    prompt_tokens_details: Option<OpenAIPromptTokensDetails>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIPromptTokensDetails {
+    #[serde(default)]
+    cached_tokens: u32,
+}
+
+impl From<OpenAIUsage> for Usage {
+    fn from(usage: OpenAIUsage) -> Self {
+        // prompt_tokens includes cached tokens, report them separately
+        let cached = usage.prompt_tokens_details.map(|details| details.cached_tokens).unwrap_or(0);
+        Usage {
+            input_tokens: usage.prompt_tokens.saturating_sub(cached),
+            cache_creation_input_tokens: 0,
+            cache_read_input_tokens: cached,
+            output_tokens: usage.completion_tokens,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIChoice {
+    message: OpenAIMessage,
+    #[serde(default)]
+    finish_reason: Option<StopReason>,
+}
+
+fn deserialize_content<'de, D>(deserializer: D) -> Result<Vec<ContentItem>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ContentWrapper {
+        String(String),
+        Null,
+        Array(Vec<ContentItem>),
+    }
+
+    let wrapper = ContentWrapper::deserialize(deserializer)?;
+    match wrapper {
+        ContentWrapper::String(s) => Ok(vec![ContentItem::Text { text: s }]),
+        ContentWrapper::Null => Ok(vec![]),
+        ContentWrapper::Array(v) => Ok(v),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIMessage {
+    #[serde(default)]
+    role: Option<Role>,
+    #[serde(default, deserialize_with = "deserialize_content")]
+    content: Vec<ContentItem>,
+    #[serde(default)]
+    tool_calls: Option<Vec<OpenAIToolCall>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIToolCall {
+    id: String,
+    #[serde(default, rename = "type")]
+    call_type: Option<String>,
+    function: OpenAIFunctionCall,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIFunctionCall {
+    name: String,
+    arguments: String,
+}
+
+pub struct OpenAIInference {
+    model: String,
+    client: Client,
+    base_url: String,
+    api_key: String,
+    max_output_tokens: u32,
+    temperature: Option<f32>,
+    seed: Option<u64>,
+    stop_sequences: Vec<String>,
+    with_tools: bool,
+    wire: WireLog,
+}
+
+impl OpenAIInference {
+    /// A provider on the config pprog.toml and the repository policy give, which fails when
+    /// they don't load rather than sending on the defaults.
+    pub fn load() -> Result<Self, anyhow::Error> {
+        Self::from_config(&*ProjectConfig::shared()?)
+    }
+
+    pub fn from_config(config: &ProjectConfig) -> Result<Self, anyhow::Error> {
+        Ok(OpenAIInference {
+            model: config.model.clone(),
+            client: transport::client(),
+            base_url: config.base_url.clone(),
+            api_key: config.api_key.clone(),
+            max_output_tokens: config.max_output_tokens,
+            temperature: config.sampling_temperature(),
+            seed: config.sampling_seed(),
+            stop_sequences: config.stop_sequences.clone(),
+            with_tools: !config.no_tools,
+            wire: WireLog::from_config(config)?,
+        })
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn get_tools(&self) -> Vec<OpenAITool> {
+        match self.with_tools {
+            true => tools::definitions().into_iter().map(OpenAITool::from).collect(),
+            false => Vec::new(),
+        }
+    }
+
+    fn get_tools_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        serde_json::to_value(self.get_tools())
+    }
+
+    async fn send(
+        &self,
+        mut messages: Vec<Message>,
+        system_message: Option<&str>,
+        stream: bool,
+        schema: Option<&ResponseSchema>,
+    ) -> Result<(transport::Response, Exchange), InferenceError> {
+        if self.api_key.is_empty() {
+            return Err(InferenceError::MissingApiKey("OpenAI API key not found".to_string()));
+        }
+
+        if let Some(sys_msg) = system_message {
+            messages.insert(0, Message {
+                role: Role::System,
+                content: vec![ContentItem::Text { text: plain_system(sys_msg) }],
+            });
+        }
+
+        let openai_messages = messages.into_iter().map(|msg| {
+            let text = msg.content.iter()
+                .filter_map(|item| {
+                    match item {
+                        ContentItem::Text { text } => Some(text.clone()),
+                        _ => None
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join(" ");
+            // Messages with images need the list form of content, with images as data URLs
+            let images: Vec<serde_json::Value> = msg.content.iter()
+                .filter_map(|item| match item {
+                    ContentItem::Image { source } => Some(serde_json::json!({
+                        "type": "image_url",
+                        "image_url": {"url": format!("data:{};base64,{}", source.media_type, source.data)},
+                    })),
+                    _ => None,
+                })
+                .collect();
+            let content = match images.is_empty() {
+                true => serde_json::json!(text),
+                false => {
+                    let mut parts = vec![serde_json::json!({"type": "text", "text": text})];
+                    parts.extend(images);
+                    serde_json::Value::Array(parts)
+                },
+            };
+
+            serde_json::json!({
+                "role": match msg.role {
+                    Role::User => "user",
+                    Role::Assistant => "assistant",
+                    Role::System => "system",
+                    Role::Developer => "developer",
+                },
+                "content": content
+            })
+        }).collect();
+
+        let tools = match schema {
+            Some(_) => None,
+            // An empty list is refused, no tools are sent as none
+            None => self.get_tools_json().ok().filter(|tools| tools.as_array().is_some_and(|tools| !tools.is_empty())),
+        };
+        let response_format = schema.map(|schema| serde_json::json!({
+            "type": "json_schema",
+            "json_schema": {"name": schema.name, "schema": schema.schema},
+        }));
+
+        let request = OpenAIRequest {
+            model: self.model.clone(),
+            messages: openai_messages,
+            max_tokens: Some(self.max_output_tokens),
+            tools,
+            response_format,
+            temperature: self.temperature,
+            seed: self.seed,
+            stream,
+            stream_options: stream.then(|| serde_json::json!({"include_usage": true})),
+            stop: self.stop_sequences.clone(),
+        };
+
+        let request = self.client
+            .post(format!("{}/chat/completions", self.base_url))
+            .header("Content-Type", "application/json")
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(&request);
+        self.wire.send("openai", request)
+            .await
+            .map_err(|e| InferenceError::NetworkError(e.to_string()))
+    }
+
+    pub async fn query_model(&self, messages: Vec<Message>, system_message: Option<&str>) -> Result<ModelResponse, InferenceError> {
+        self.complete(messages, system_message, None).await
+    }
+
+    /// Asks for a reply following `schema`, as JSON text through `response_format`.
+    pub async fn query_model_structured(
+        &self,
+        messages: Vec<Message>,
+        system_message: Option<&str>,
+        schema: &ResponseSchema,
+    ) -> Result<ModelResponse, InferenceError> {
+        self.complete(messages, system_message, Some(schema)).await
+    }
+
+    async fn complete(
+        &self,
+        messages: Vec<Message>,
+        system_message: Option<&str>,
+        schema: Option<&ResponseSchema>,
+    ) -> Result<ModelResponse, InferenceError> {
+        let (response, mut exchange) = self.send(messages, system_message, false, schema).await?;
+
+        let status = response.status();
+        let response_text = response.text().await
+            .map_err(|e| InferenceError::NetworkError(e.to_string()))?;
+        exchange.body(response_text.as_bytes());
+        log::info!("{:?}", response_text);
+
+        if !status.is_success() {
+            return Err(InferenceError::ApiError(status, response_text));
+        }
+
+        let raw: serde_json::Value = serde_json::from_str(&response_text)
+            .map_err(|e| InferenceError::InvalidResponse(e.to_string()))?;
+        let openai_response = OpenAIResponse::deserialize(&raw)
+            .map_err(|e| InferenceError::InvalidResponse(format!("Failed to parse OpenAI response: {}", e)))?;
+
+        if openai_response.choices.is_empty() {
+            return Err(InferenceError::InvalidResponse("No choices in OpenAI response".to_string()));
+        }
+
+        let first_choice = &openai_response.choices[0].message;
+        let mut content = first_choice.content.clone();
+
+        // Handle tool calls if present
+        if let Some(tool_calls) = &first_choice.tool_calls {
+            for tool_call in tool_calls {
+                if tool_call.call_type.as_deref().unwrap_or("function") == "function" {
+                    // Parse the arguments as JSON Value
+                    let input: serde_json::Value = serde_json::from_str(&tool_call.function.arguments)
+                        .map_err(|e| InferenceError::SerializationError(format!("Failed to parse tool arguments: {}", e)))?;
+
+                    content.push(ContentItem::ToolUse {
+                        id: tool_call.id.clone(),
+                        name: tool_call.function.name.clone(),
+                        input,
+                    });
+                }
+            }
+        }
+
+        Ok(ModelResponse {
+            stop_reason: openai_response.choices[0].finish_reason.clone().unwrap_or_else(|| StopReason::implied(&content)),
+            content,
+            id: openai_response.id,
+            model: openai_response.model,
+            role: first_choice.role.clone().unwrap_or(Role::Assistant),
+            message_type: ResponseType::Message,
+            stop_sequence: None,
+            usage: openai_response.usage.map(Usage::from),
+            raw: Some(raw),
+            timing: None,
+        })
+    }
+
+    /// Streams the response into `acc`.  On error `acc` keeps everything received so far.
+    pub async fn query_model_stream(
+        &self,
+        messages: Vec<Message>,
+        system_message: Option<&str>,
+        acc: &mut StreamAccumulator<'_>,
+    ) -> Result<(), InferenceError> {
+        let (mut response, mut exchange) = self.send(messages, system_message, true, None).await?;
+        let status = response.status();
+        if !status.is_success() {
+            let response_text = response.text().await
+                .map_err(|e| InferenceError::NetworkError(e.to_string()))?;
+            exchange.body(response_text.as_bytes());
+            return Err(InferenceError::ApiError(status, response_text));
+        }
+
+        let mut parser = SseParser::default();
+        while let Some(chunk) = response.chunk().await.map_err(|e| InferenceError::NetworkError(e.to_string()))? {
+            exchange.body(&chunk);
+            for event in parser.push(&chunk) {
+                if event.data == "[DONE]" {
+                    return Ok(());
+                }
+                let data: serde_json::Value = serde_json::from_str(&event.data)
+                    .map_err(|e| InferenceError::InvalidResponse(format!("Invalid stream chunk: {}", e)))?;
+                apply_stream_chunk(&data, acc);
+            }
+        }
+        Err(InferenceError::NetworkError("Stream ended before [DONE]".to_string()))
+    }
+}
+
+impl Inference for OpenAIInference {
+    async fn query_model(&self, messages: Vec<Message>, system_message: Option<&str>) -> Result<ModelResponse, InferenceError> {
+        OpenAIInference::query_model(self, messages, system_message).await
+    }
+
+    fn sampling(&self) -> Sampling {
+        Sampling { model: self.model.clone(), temperature: self.temperature, seed: self.seed }
+    }
+
+    async fn query_model_stream(
+        &self,
+        messages: Vec<Message>,
+        system_message: Option<&str>,
+        acc: &mut StreamAccumulator<'_>,
+    ) -> Result<(), InferenceError> {
+        OpenAIInference::query_model_stream(self, messages, system_message, acc).await
+    }
+}
+
+// Text goes into block 0 and tool call `i` into block `i + 1`
+fn apply_stream_chunk(data: &serde_json::Value, acc: &mut StreamAccumulator<'_>) {
+    if let Some(id) = data["id"].as_str() {
+        acc.id = id.to_string();
+    }
+    if let Some(model) = data["model"].as_str() {
+        acc.model = model.to_string();
+    }
+    if let Ok(usage) = OpenAIUsage::deserialize(&data["usage"]) {
+        acc.usage = Some(Usage::from(usage));
+    }
+
+    let Some(choice) = data["choices"].get(0) else { return };
+    let delta = &choice["delta"];
+    if let Some(text) = delta["content"].as_str() {
+        acc.push_text(0, text);
+    }
+    for call in delta["tool_calls"].as_array().into_iter().flatten() {
+        let index = call["index"].as_u64().unwrap_or_default() as usize + 1;
+        if let Some(id) = call["id"].as_str() {
+            acc.start_tool_use(index, id, call["function"]["name"].as_str().unwrap_or_default());
+        }
+        acc.push_tool_input(index, call["function"]["arguments"].as_str().unwrap_or_default());
+    }
+    if let Some(reason) = choice["finish_reason"].as_str() {
+        acc.stop_reason = Some(StopReason::from(reason));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_stream_chunks() -> Result<(), InferenceError> {
+        let chunks = [
+            r#"{"id": "chatcmpl-1", "model": "gpt-4o", "choices": [{"index": 0, "delta": {"role": "assistant", "content": "On it."}, "finish_reason": null}]}"#,
+            r#"{"id": "chatcmpl-1", "model": "gpt-4o", "choices": [{"index": 0, "delta": {"tool_calls": [{"index": 0, "id": "call_1", "type": "function", "function": {"name": "execute", "arguments": ""}}]}, "finish_reason": null}]}"#,
+            r#"{"id": "chatcmpl-1", "model": "gpt-4o", "choices": [{"index": 0, "delta": {"tool_calls": [{"index": 0, "function": {"arguments": "{\"statement\": \"ls\"}"}}]}, "finish_reason": "tool_calls"}]}"#,
+            r#"{"id": "chatcmpl-1", "model": "gpt-4o", "choices": [], "usage": {"prompt_tokens": 20, "completion_tokens": 9, "prompt_tokens_details": {"cached_tokens": 8}}}"#,
+        ];
+        let mut acc = StreamAccumulator::new(|_| {});
+        for chunk in chunks {
+            apply_stream_chunk(&serde_json::from_str(chunk).unwrap(), &mut acc);
+        }
+
+        let response = acc.finish()?;
+        assert_eq!(response.stop_reason, StopReason::ToolUse);
+        assert_eq!(response.usage.map(|usage| (usage.input_tokens, usage.cache_read_input_tokens)), Some((12, 8)));
+        assert_eq!(response.content, vec![
+            ContentItem::Text { text: "On it.".to_string() },
+            ContentItem::ToolUse { id: "call_1".to_string(), name: "execute".to_string(), input: serde_json::json!({"statement": "ls"}) },
+        ]);
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+use std::sync::Arc;
+
+use crate::pii::Scrubber;
+use super::stream::StreamAccumulator;
+use super::types::{Inference, InferenceError, Message, ModelResponse, Sampling};
+
+/*
+    * Wraps a provider to keep personal data off it: requests go out with the values the
+    * `Scrubber` finds swapped for placeholders, and replies come back with the values put
+    * back, as they stream in and in the tool calls.  The history keeps the real values and
+    * is scrubbed again with every request, to the same placeholders.
+*/
+pub struct ScrubbingInference<P> {
+    inner: P,
+    scrubber: Arc<Scrubber>,
+}
+
+impl<P: Inference> ScrubbingInference<P> {
+    pub fn new(inner: P, scrubber: Scrubber) -> Self {
+        ScrubbingInference { inner, scrubber: Arc::new(scrubber) }
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    pub fn scrubber(&self) -> &Scrubber {
+        &self.scrubber
+    }
+}
+
+impl<P: Inference> Inference for ScrubbingInference<P> {
+    async fn query_model(&self, messages: Vec<Message>, system_message: Option<&str>) -> Result<ModelResponse, InferenceError> {
+        let (messages, system_message) = self.scrubber.scrub_request(messages, system_message);
+        let response = self.inner.query_model(messages, system_message.as_deref()).await?;
+        Ok(self.scrubber.restore_response(response))
+    }
+
+    async fn query_model_stream(
+        &self,
+        messages: Vec<Message>,
+        system_message: Option<&str>,
+        acc: &mut StreamAccumulator<'_>,
+    ) -> Result<(), InferenceError> {
+        let (messages, system_message) = self.scrubber.scrub_request(messages, system_message);
+        acc.restore_with(self.scrubber.clone());
+        self.inner.query_model_stream(messages, system_message.as_deref(), acc).await
+    }
+
+    fn sampling(&self) -> Sampling {
+        self.inner.sampling()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PiiConfig;
+    use crate::inference::MockInference;
+    use crate::inference::stream::StreamEvent;
+    use crate::inference::types::{ContentItem, Role};
+
+    #[tokio::test]
+    async fn test_scrubbing() -> Result<(), anyhow::Error> {
+        let mock = MockInference::new("mock")
+            .text("Sending the report to [EMAIL_1] now.")
+            .tool_use("write_file", serde_json::json!({"path": "owners.txt", "content": "[EMAIL_1]\n[PHONE_1]\n"}));
+        let inference = ScrubbingInference::new(mock.clone(), Scrubber::new(&PiiConfig::default())?);
+        let user = Message { role: Role::User, content: vec![ContentItem::Text { text: "Mail ana@acme.io, she's on +1 415-555-0132".to_string() }] };
+
+        let mut shown = String::new();
+        let mut acc = StreamAccumulator::new(|event| if let StreamEvent::Text(text) = event { shown.push_str(text) });
+        inference.query_model_stream(vec![user.clone()], Some("Be brief."), &mut acc).await?;
+        let response = acc.finish()?;
+        assert_eq!(response.content, vec![ContentItem::Text { text: "Sending the report to ana@acme.io now.".to_string() }]);
+        assert_eq!(shown, "Sending the report to ana@acme.io now.");
+        let sent = &mock.requests()[0];
+        assert_eq!(sent.messages[0].content, vec![ContentItem::Text { text: "Mail [EMAIL_1], she's on [PHONE_1]".to_string() }]);
+
+        let response = inference.query_model(vec![user], None).await?;
+        assert!(matches!(&response.content[0], ContentItem::ToolUse { input, .. } if input["content"] == "ana@acme.io\n+1 415-555-0132\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,442 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::Stream;
+
+use crate::pii::Scrubber;
+use super::types::{ContentItem, InferenceError, ModelResponse, ResponseType, Role, StopReason, Timing, Usage};
+
+/// Progress reported while a response streams in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamEvent {
+    Text(String),
+    /// Reasoning streamed ahead of the answer, shown as it comes but not kept in the reply
+    Thinking(String),
+    ToolUse { name: String },
+    /// A piece of the JSON input of the tool call being streamed
+    ToolInput(String),
+    /// The response is complete
+    Done { stop_reason: StopReason, stop_sequence: Option<String>, usage: Option<Usage>, timing: Timing },
+}
+
+/// One server-sent event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub data: String,
+}
+
+/// Splits a byte stream into server-sent events.  Chunks can end anywhere, including in
+/// the middle of a UTF-8 sequence, so bytes are only decoded once an event is complete.
+#[derive(Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+}
+
+impl SseParser {
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend(chunk.iter().filter(|&&byte| byte != b'\r'));
+
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.windows(2).position(|window| window == b"\n\n") {
+            let raw: Vec<u8> = self.buffer.drain(..end + 2).collect();
+            let raw = String::from_utf8_lossy(&raw[..end]);
+
+            let mut event = None;
+            let mut data = Vec::new();
+            for line in raw.lines() {
+                if let Some(value) = line.strip_prefix("event:") {
+                    event = Some(value.trim().to_string());
+                } else if let Some(value) = line.strip_prefix("data:") {
+                    data.push(value.strip_prefix(' ').unwrap_or(value));
+                }
+            }
+            if !data.is_empty() {
+                events.push(SseEvent { event, data: data.join("\n") });
+            }
+        }
+        events
+    }
+}
+
+enum Block {
+    Text(String),
+    ToolUse { id: String, name: String, input: String },
+    Unknown(serde_json::Value),
+}
+
+/*
+    * Builds a response from streamed deltas.  Blocks are keyed by the index the provider
+    * gives them so interleaved deltas land in the right place.  The accumulator is owned by
+    * the caller, so whatever arrived before an error or cancellation is still there to keep.
+*/
+pub struct StreamAccumulator<'a> {
+    pub id: String,
+    pub model: String,
+    pub stop_reason: Option<StopReason>,
+    /// Which of the requested stop sequences ended the response
+    pub stop_sequence: Option<String>,
+    pub usage: Option<Usage>,
+    blocks: BTreeMap<usize, Block>,
+    on_event: Box<dyn FnMut(&StreamEvent) + 'a>,
+    started: Instant,
+    first_token: Option<Duration>,
+    // Set by `push_response`, whose request was timed on its own
+    timing: Option<Timing>,
+    // Puts the values back for placeholders in the reply, see `restore_with`
+    scrubber: Option<Arc<Scrubber>>,
+    // Text not yet passed to `on_event` because it could be the start of a placeholder
+    held: String,
+}
+
+impl<'a> StreamAccumulator<'a> {
+    pub fn new(on_event: impl FnMut(&StreamEvent) + 'a) -> Self {
+        StreamAccumulator {
+            id: String::new(),
+            model: String::new(),
+            stop_reason: None,
+            stop_sequence: None,
+            usage: None,
+            blocks: BTreeMap::new(),
+            on_event: Box::new(on_event),
+            started: Instant::now(),
+            first_token: None,
+            timing: None,
+            scrubber: None,
+            held: String::new(),
+        }
+    }
+
+    /// Reports and returns the reply with the values `scrubber` swapped for placeholders in.
+    pub fn restore_with(&mut self, scrubber: Arc<Scrubber>) {
+        self.scrubber = Some(scrubber);
+    }
+
+    fn emit_text(&mut self, text: &str) {
+        let Some(scrubber) = self.scrubber.clone() else {
+            (self.on_event)(&StreamEvent::Text(text.to_string()));
+            return;
+        };
+        self.held.push_str(text);
+        let ready = self.held.drain(..Scrubber::held_from(&self.held)).collect::<String>();
+        if !ready.is_empty() {
+            (self.on_event)(&StreamEvent::Text(scrubber.restore(&ready)));
+        }
+    }
+
+    // Reports the text held back, before an event that shows it can't be a placeholder
+    fn flush_held(&mut self) {
+        if let Some(scrubber) = self.scrubber.clone().filter(|_| !self.held.is_empty()) {
+            let held = std::mem::take(&mut self.held);
+            (self.on_event)(&StreamEvent::Text(scrubber.restore(&held)));
+        }
+    }
+
+    fn restored(&self, mut content: Vec<ContentItem>) -> Vec<ContentItem> {
+        if let Some(scrubber) = &self.scrubber {
+            scrubber.restore_content(&mut content);
+        }
+        content
+    }
+
+    /// Times the response from now, for a request sent after text like a prefill was pushed.
+    pub fn start_clock(&mut self) {
+        self.started = Instant::now();
+        self.first_token = None;
+    }
+
+    fn first_token(&mut self) {
+        if self.first_token.is_none() {
+            self.first_token = Some(self.started.elapsed());
+        }
+    }
+
+    pub fn push_text(&mut self, index: usize, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        match self.blocks.entry(index).or_insert_with(|| Block::Text(String::new())) {
+            Block::Text(existing) => existing.push_str(text),
+            Block::ToolUse { .. } | Block::Unknown(_) => return,
+        }
+        self.first_token();
+        self.emit_text(text);
+    }
+
+    pub fn push_thinking(&mut self, text: &str) {
+        if !text.is_empty() {
+            self.first_token();
+            self.flush_held();
+            (self.on_event)(&StreamEvent::Thinking(text.to_string()));
+        }
+    }
+
+    pub fn start_tool_use(&mut self, index: usize, id: &str, name: &str) {
+        self.first_token();
+        self.flush_held();
+        self.blocks.insert(index, Block::ToolUse { id: id.to_string(), name: name.to_string(), input: String::new() });
+        (self.on_event)(&StreamEvent::ToolUse { name: name.to_string() });
+    }
+
+    pub fn push_tool_input(&mut self, index: usize, json: &str) {
+        if let Some(Block::ToolUse { input, .. }) = self.blocks.get_mut(&index) {
+            input.push_str(json);
+            (self.on_event)(&StreamEvent::ToolInput(json.to_string()));
+        }
+    }
+
+    /// Adds a block of a type pprog doesn't know, to keep with the reply.
+    pub fn push_unknown(&mut self, index: usize, value: serde_json::Value) {
+        self.blocks.insert(index, Block::Unknown(value));
+    }
+
+    /// Adds a complete response at once, for providers that don't stream.
+    pub fn push_response(&mut self, response: ModelResponse) {
+        self.id = response.id;
+        self.model = response.model;
+        self.stop_reason = Some(response.stop_reason);
+        self.stop_sequence = response.stop_sequence;
+        self.usage = response.usage;
+        self.timing = response.timing;
+        for item in response.content {
+            let index = self.blocks.len();
+            match item {
+                ContentItem::Text { text } => self.push_text(index, &text),
+                ContentItem::ToolUse { id, name, input } => {
+                    self.start_tool_use(index, &id, &name);
+                    self.push_tool_input(index, &input.to_string());
+                },
+                // Kept for the history, the provider may want them back with the next request
+                ContentItem::Unknown(value) => self.push_unknown(index, value),
+                ContentItem::ToolResult { .. } | ContentItem::Image { .. } => {},
+            }
+        }
+    }
+
+    /// Replaces what was received with `content`, without reporting it, for a reply changed
+    /// after it was streamed.
+    pub fn replace_content(&mut self, content: Vec<ContentItem>) {
+        self.blocks = content.into_iter()
+            .filter_map(|item| match item {
+                ContentItem::Text { text } => Some(Block::Text(text)),
+                ContentItem::ToolUse { id, name, input } => Some(Block::ToolUse { id, name, input: input.to_string() }),
+                ContentItem::Unknown(value) => Some(Block::Unknown(value)),
+                ContentItem::ToolResult { .. } | ContentItem::Image { .. } => None,
+            })
+            .enumerate()
+            .collect();
+    }
+
+    /// Everything received so far with tool calls, whose input is empty while it doesn't parse.
+    pub fn content(&self) -> Vec<ContentItem> {
+        let content = self.blocks.values()
+            .map(|block| match block {
+                Block::Text(text) => ContentItem::Text { text: text.clone() },
+                Block::ToolUse { id, name, input } => ContentItem::ToolUse {
+                    id: id.clone(),
+                    name: name.clone(),
+                    input: serde_json::from_str(input).unwrap_or_else(|_| serde_json::json!({})),
+                },
+                Block::Unknown(value) => ContentItem::Unknown(value.clone()),
+            })
+            .collect();
+        self.restored(content)
+    }
+
+    /// The text received so far.  Tool calls are left out since their input may be cut off
+    /// and a tool call without its result would leave the history invalid.
+    pub fn partial_content(&self) -> Vec<ContentItem> {
+        let content = self.blocks.values()
+            .filter_map(|block| match block {
+                Block::Text(text) if !text.trim().is_empty() => Some(ContentItem::Text { text: text.clone() }),
+                _ => None,
+            })
+            .collect();
+        self.restored(content)
+    }
+
+    pub fn finish(mut self) -> Result<ModelResponse, InferenceError> {
+        let content = std::mem::take(&mut self.blocks).into_values()
+            .map(|block| match block {
+                Block::Text(text) => Ok(ContentItem::Text { text }),
+                Block::ToolUse { id, name, input } => {
+                    let input = match input.trim() {
+                        "" => serde_json::json!({}),
+                        input => serde_json::from_str(input).map_err(|e| {
+                            InferenceError::InvalidResponse(format!("Invalid tool input for {}: {}", name, e))
+                        })?,
+                    };
+                    Ok(ContentItem::ToolUse { id, name, input })
+                },
+                Block::Unknown(value) => Ok(ContentItem::Unknown(value)),
+            })
+            .collect::<Result<Vec<ContentItem>, InferenceError>>()?;
+        let content = self.restored(content);
+        self.flush_held();
+        let stop_reason = self.stop_reason.take()
+            .ok_or_else(|| InferenceError::InvalidResponse("Stream ended before the response was complete".to_string()))?;
+        let timing = self.timing.unwrap_or(Timing { total: self.started.elapsed(), first_token: self.first_token });
+        (self.on_event)(&StreamEvent::Done {
+            stop_reason: stop_reason.clone(),
+            stop_sequence: self.stop_sequence.clone(),
+            usage: self.usage.clone(),
+            timing,
+        });
+
+        Ok(ModelResponse {
+            content,
+            id: self.id,
+            model: self.model,
+            role: Role::Assistant,
+            message_type: ResponseType::Message,
+            stop_reason,
+            stop_sequence: self.stop_sequence,
+            usage: self.usage,
+            raw: None,
+            timing: Some(timing),
+        })
+    }
+}
+
+type Buffered = Arc<Mutex<VecDeque<Result<StreamEvent, InferenceError>>>>;
+type Query<'a> = Pin<Box<dyn Future<Output = Result<(), InferenceError>> + 'a>>;
+
+/*
+    * A response as a `futures::Stream` of events, ending with `Done` or an error.  The request
+    * only makes progress while the stream is polled and it waits whenever events are left
+    * over, so a slow consumer slows the read from the connection, and dropping the stream
+    * closes it.  Frontends that want the assembled reply use `StreamAccumulator` instead.
+*/
+pub struct EventStream<'a> {
+    query: Option<Query<'a>>,
+    buffered: Buffered,
+}
+
+impl<'a> EventStream<'a> {
+    /// Runs `query`, which streams a response into the accumulator it is given and finishes it.
+    pub fn new<F, Fut>(query: F) -> Self
+    where
+        F: FnOnce(StreamAccumulator<'static>) -> Fut,
+        Fut: Future<Output = Result<(), InferenceError>> + 'a,
+    {
+        let buffered = Buffered::default();
+        let events = buffered.clone();
+        let acc = StreamAccumulator::new(move |event| events.lock().unwrap().push_back(Ok(event.clone())));
+        EventStream { query: Some(Box::pin(query(acc))), buffered }
+    }
+}
+
+impl Stream for EventStream<'_> {
+    type Item = Result<StreamEvent, InferenceError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(event) = self.buffered.lock().unwrap().pop_front() {
+                return Poll::Ready(Some(event));
+            }
+            let Some(query) = self.query.as_mut() else { return Poll::Ready(None) };
+            match query.as_mut().poll(cx) {
+                Poll::Pending if self.buffered.lock().unwrap().is_empty() => return Poll::Pending,
+                Poll::Pending => {},
+                Poll::Ready(result) => {
+                    self.query = None;
+                    if let Err(e) = result {
+                        self.buffered.lock().unwrap().push_back(Err(e));
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sse_parser_handles_split_chunks() {
+        let mut parser = SseParser::default();
+        let stream = "event: ping\ndata: {}\n\n: comment\r\ndata: {\"text\": \"h\u{e9}\"}\r\n\r\ndata: [DONE]\n\n".as_bytes();
+
+        let mut events = Vec::new();
+        for chunk in stream.chunks(5) {
+            events.extend(parser.push(chunk));
+        }
+        assert_eq!(events, vec![
+            SseEvent { event: Some("ping".to_string()), data: "{}".to_string() },
+            SseEvent { event: None, data: "{\"text\": \"h\u{e9}\"}".to_string() },
+            SseEvent { event: None, data: "[DONE]".to_string() },
+        ]);
+    }
+
+    #[test]
+    fn test_event_stream() {
+        use futures::StreamExt;
+
+        // Pending once, the way a read waiting on the network would be
+        let wait = || {
+            let mut waited = false;
+            futures::future::poll_fn(move |cx| {
+                if waited {
+                    return Poll::Ready(());
+                }
+                waited = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            })
+        };
+        let events = EventStream::new(|mut acc| async move {
+            acc.push_text(0, "Hel");
+            wait().await;
+            acc.push_text(0, "lo");
+            acc.stop_reason = Some(StopReason::EndTurn);
+            acc.finish().map(|_| ())
+        });
+        let events = futures::executor::block_on(events.collect::<Vec<_>>());
+        let events = events.into_iter().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(events[..2], [StreamEvent::Text("Hel".to_string()), StreamEvent::Text("lo".to_string())]);
+        assert!(matches!(&events[2], StreamEvent::Done { stop_reason: StopReason::EndTurn, usage: None, timing, .. } if timing.first_token.is_some()));
+        assert_eq!(events.len(), 3);
+
+        let events = EventStream::new(|mut acc| async move {
+            acc.push_text(0, "Hel");
+            Err(InferenceError::NetworkError("reset".to_string()))
+        });
+        let events = futures::executor::block_on(events.collect::<Vec<_>>());
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[1], Err(InferenceError::NetworkError(_))));
+    }
+
+    #[test]
+    fn test_accumulator_keeps_partial_text() -> Result<(), InferenceError> {
+        let mut streamed = String::new();
+        let mut acc = StreamAccumulator::new(|event| {
+            if let StreamEvent::Text(text) = event {
+                streamed.push_str(text);
+            }
+        });
+        acc.push_text(0, "Let me ");
+        acc.push_text(0, "check.");
+        acc.start_tool_use(1, "toolu_1", "read_file");
+        acc.push_tool_input(1, "{\"path\": \"src/ma");
+        assert_eq!(acc.partial_content(), vec![ContentItem::Text { text: "Let me check.".to_string() }]);
+        assert!(acc.finish().is_err());
+        assert_eq!(streamed, "Let me check.");
+
+        let mut acc = StreamAccumulator::new(|_| {});
+        acc.start_tool_use(0, "toolu_1", "read_file");
+        acc.push_tool_input(0, "{\"path\": \"src/main.rs\"}");
+        acc.stop_reason = Some(StopReason::ToolUse);
+        let response = acc.finish()?;
+        assert_eq!(response.content, vec![ContentItem::ToolUse {
+            id: "toolu_1".to_string(),
+            name: "read_file".to_string(),
+            input: serde_json::json!({"path": "src/main.rs"}),
+        }]);
+        Ok(())
+    }
+}
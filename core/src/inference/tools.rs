@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AnthropicTool {
+    pub name: String,
+    pub description: String,
+    #[serde(rename = "input_schema")]
+    pub input_schema: InputSchema,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OpenAITool {
+    pub name: String,
+    pub description: String,
+    #[serde(rename = "type")]
+    pub tool_type: String,
+    pub function: OpenAIToolFunction,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OpenAIToolFunction {
+    pub description: String,
+    pub name: String,
+    pub parameters: InputSchema,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InputSchema {
+    #[serde(rename = "type")]
+    pub schema_type: String,
+    pub properties: HashMap<String, PropertySchema>,
+    pub required: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PropertySchema {
+    #[serde(rename = "type")]
+    pub property_type: String,
+    pub description: String,
+}
+
+impl From<AnthropicTool> for OpenAITool {
+    fn from(tool: AnthropicTool) -> Self {
+        OpenAITool {
+            name: tool.name.clone(),
+            description: tool.description.clone(),
+            tool_type: "function".to_string(),
+            function: OpenAIToolFunction {
+                description: tool.description,
+                name: tool.name,
+                parameters: tool.input_schema,
+            },
+        }
+    }
+}
+
+// Builds a tool whose parameters are all required strings
+fn string_tool(name: &str, description: &str, params: &[(&str, &str)]) -> AnthropicTool {
+    AnthropicTool {
+        name: name.to_string(),
+        description: description.to_string(),
+        input_schema: InputSchema {
+            schema_type: "object".to_string(),
+            properties: params.iter()
+                .map(|(param, description)| {
+                    (param.to_string(), PropertySchema {
+                        property_type: "string".to_string(),
+                        description: description.to_string(),
+                    })
+                })
+                .collect(),
+            required: params.iter().map(|(param, _)| param.to_string()).collect(),
+        },
+    }
+}
+
+/// Tools offered to every provider, in the Anthropic shape.  OpenAI compatible providers
+/// convert them with `OpenAITool::from`.
+pub fn definitions() -> Vec<AnthropicTool> {
+    vec![
+        string_tool(
+            "read_file",
+            "Read file as string using path relative to root directory of project.",
+            &[("path", "The file path relative to the project root directory")],
+        ),
+        AnthropicTool {
+            name: "read_large_file".to_string(),
+            description: "Read part of a file too large for read_file, like a log or a dataset: the lines from offset, the last lines with tail, or only the lines matching a regex.  Lines come back numbered, at most 2000 at a time.".to_string(),
+            input_schema: InputSchema {
+                schema_type: "object".to_string(),
+                properties: [
+                    ("path", "string", "The file path relative to the project root directory"),
+                    ("offset", "integer", "The first line to read, counting from 1, 1 by default"),
+                    ("limit", "integer", "How many lines to return, 200 by default"),
+                    ("tail", "integer", "Read this many lines from the end of the file instead of from offset"),
+                    ("pattern", "string", "A regex, only the lines matching it from offset on are returned"),
+                ].iter()
+                    .map(|(param, property_type, description)| (param.to_string(), PropertySchema {
+                        property_type: property_type.to_string(),
+                        description: description.to_string(),
+                    }))
+                    .collect(),
+                required: vec!["path".to_string()],
+            },
+        },
+        string_tool(
+            "write_file",
+            "Write string to file at path relative to root directory of project.",
+            &[
+                ("path", "The file path relative to the project root directory"),
+                ("content", "The content to write to the file"),
+            ],
+        ),
+        string_tool(
+            "execute",
+            "Execute bash statements as a single string..",
+            &[("statement", "The bash statement to be executed.")],
+        ),
+        string_tool(
+            "compile_check",
+            "Check if project compiles or runs without error.",
+            &[("cmd", "The command to check for compiler/interpreter errors.")],
+        ),
+        string_tool(
+            "remember",
+            "Save a durable note about this project (build quirks, conventions, decisions) to project memory so it is available in future sessions.",
+            &[("note", "A short, self-contained note to remember.")],
+        ),
+        string_tool(
+            "plan",
+            "Propose a numbered plan of the steps you will take and wait for the user to approve it.  Only needed when asked to plan first.",
+            &[("plan", "The steps of the plan, numbered, one per line.")],
+        ),
+        string_tool(
+            "todo",
+            "Keep a task list for work with several steps, shown to the user and kept with the session.  Send the whole list each time a task starts or finishes.",
+            &[("items", "The whole list, one task per line starting with [ ] for pending, [~] for in progress or [x] for done.")],
+        ),
+        string_tool(
+            "task",
+            "Hand a scoped subtask, like finding every call site of a function, to a sub-agent with a fresh context and a restricted set of tools.  Only its summary comes back, which keeps your context small.",
+            &[("description", "The subtask and what the summary should report, self-contained since the sub-agent sees nothing else.")],
+        ),
+        string_tool(
+            "semantic_search",
+            "Find the code most related to a natural language description, like where sessions get saved, when you don't know the names to grep for.  Returns the best matching snippets with their paths and lines.",
+            &[("query", "What the code you are looking for does, in plain words.")],
+        ),
+        string_tool(
+            "fetch_url",
+            "Fetch a web page or file over http or https, like documentation or a changelog, and return its status, content type and body as text.",
+            &[("url", "The full URL to fetch.")],
+        ),
+    ]
+}
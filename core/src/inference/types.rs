@@ -0,0 +1,414 @@
+use serde::{Serialize, Deserialize};
+use serde::de::Error as SerdeError;
+use anyhow::Result;
+use std::time::Duration;
+
+use super::stream::{EventStream, StreamAccumulator};
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(tag = "type")]
+pub enum ContentItem {
+    #[serde(rename = "text")]
+    Text {
+        text: String,
+    },
+    #[serde(rename = "tool_use")]
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    #[serde(rename = "tool_result")]
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+        /// The tool failed and `content` says how, for the model to correct its call
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        is_error: bool,
+    },
+    #[serde(rename = "image")]
+    Image {
+        source: ImageSource,
+    },
+    /// A block of a type pprog doesn't know yet, or that doesn't parse, kept as it came
+    #[serde(untagged)]
+    Unknown(serde_json::Value),
+}
+
+/// Image data in the Anthropic shape, other providers convert it to theirs.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ImageSource {
+    /// Always `base64`
+    #[serde(rename = "type")]
+    pub source_type: String,
+    pub media_type: String,
+    pub data: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Message {
+    pub role: Role,
+    pub content: Vec<ContentItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+    System,
+    Developer, // because OpenAI just had to change the system name
+}
+
+/*
+    * Why the model stopped, under one name whichever provider sent it.  OpenAI's reasons are
+    * mapped onto Anthropic's where they mean the same, and any other is kept as it came.
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum StopReason {
+    EndTurn,
+    /// The reply was cut off at the output token limit
+    MaxTokens,
+    StopSequence,
+    ToolUse,
+    Refusal,
+    Other(String),
+}
+
+impl StopReason {
+    pub fn as_str(&self) -> &str {
+        match self {
+            StopReason::EndTurn => "end_turn",
+            StopReason::MaxTokens => "max_tokens",
+            StopReason::StopSequence => "stop_sequence",
+            StopReason::ToolUse => "tool_use",
+            StopReason::Refusal => "refusal",
+            StopReason::Other(reason) => reason,
+        }
+    }
+
+    /// Why a reply of `content` ended, for a provider that sent no reason.
+    pub fn implied(content: &[ContentItem]) -> Self {
+        match content.iter().any(|item| matches!(item, ContentItem::ToolUse { .. })) {
+            true => StopReason::ToolUse,
+            false => StopReason::EndTurn,
+        }
+    }
+}
+
+impl From<&str> for StopReason {
+    fn from(reason: &str) -> Self {
+        match reason {
+            "end_turn" | "stop" => StopReason::EndTurn,
+            "max_tokens" | "length" => StopReason::MaxTokens,
+            "stop_sequence" => StopReason::StopSequence,
+            "tool_use" | "tool_calls" | "function_call" => StopReason::ToolUse,
+            "refusal" | "content_filter" => StopReason::Refusal,
+            other => StopReason::Other(other.to_string()),
+        }
+    }
+}
+
+impl From<String> for StopReason {
+    fn from(reason: String) -> Self {
+        StopReason::from(reason.as_str())
+    }
+}
+
+impl From<StopReason> for String {
+    fn from(reason: StopReason) -> Self {
+        reason.as_str().to_string()
+    }
+}
+
+impl std::fmt::Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What kind of object a response is, a message for every reply the providers send now.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum ResponseType {
+    Message,
+    Other(String),
+}
+
+impl From<String> for ResponseType {
+    fn from(kind: String) -> Self {
+        match kind.as_str() {
+            "message" => ResponseType::Message,
+            _ => ResponseType::Other(kind),
+        }
+    }
+}
+
+impl From<ResponseType> for String {
+    fn from(kind: ResponseType) -> Self {
+        match kind {
+            ResponseType::Message => "message".to_string(),
+            ResponseType::Other(kind) => kind,
+        }
+    }
+}
+
+/// Marks where the stable instructions of a system prompt end and the part that changes
+/// from turn to turn begins, see `split_system`.
+pub const CACHE_BREAK: &str = "\u{1d}";
+
+/// A system prompt as the part before `CACHE_BREAK`, worth caching, and the part after it.
+pub fn split_system(system: &str) -> (&str, &str) {
+    system.split_once(CACHE_BREAK).unwrap_or((system, ""))
+}
+
+/// The system prompt without `CACHE_BREAK`, for providers that cache on their own.
+pub fn plain_system(system: &str) -> String {
+    system.replace(CACHE_BREAK, "")
+}
+
+/// A JSON Schema the reply has to follow, sent under `name`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResponseSchema {
+    pub name: String,
+    pub schema: serde_json::Value,
+}
+
+/// For `deserialize_with`: a field sent as null takes its default, like one left out.
+pub fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Option::unwrap_or_default)
+}
+
+/// Token counts for one request.  `input_tokens` excludes tokens written to or read
+/// from the provider's prompt cache, which are counted separately.
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Usage {
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub input_tokens: u32,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub cache_creation_input_tokens: u32,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub cache_read_input_tokens: u32,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub output_tokens: u32,
+}
+
+impl Usage {
+    pub fn add(&mut self, other: &Usage) {
+        self.input_tokens += other.input_tokens;
+        self.cache_creation_input_tokens += other.cache_creation_input_tokens;
+        self.cache_read_input_tokens += other.cache_read_input_tokens;
+        self.output_tokens += other.output_tokens;
+    }
+}
+
+/// How long a request took in all, and until the first token of the reply when it was streamed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Timing {
+    pub total: Duration,
+    pub first_token: Option<Duration>,
+}
+
+impl Timing {
+    /// The time spent writing the reply, after the first token when that is known.
+    pub fn generating(&self) -> Duration {
+        self.total.saturating_sub(self.first_token.unwrap_or_default())
+    }
+
+    /// Output tokens per second over `generating`.
+    pub fn output_rate(&self, output_tokens: u32) -> Option<f64> {
+        output_rate(output_tokens, self.generating())
+    }
+}
+
+/// `output_tokens` per second of `generating`, None when no time was measured.
+pub fn output_rate(output_tokens: u32, generating: Duration) -> Option<f64> {
+    (!generating.is_zero()).then(|| output_tokens as f64 / generating.as_secs_f64())
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ModelResponse {
+    pub content: Vec<ContentItem>,
+    pub id: String,
+    pub model: String,
+    pub role: Role,
+    #[serde(rename = "type")]
+    pub message_type: ResponseType,
+    pub stop_reason: StopReason,
+    pub stop_sequence: Option<String>,
+    #[serde(default)]
+    pub usage: Option<Usage>,
+    /// The provider's response as it came, for fields the mapping above leaves out.  Only
+    /// set on replies that weren't streamed.
+    #[serde(skip)]
+    pub raw: Option<serde_json::Value>,
+    #[serde(skip)]
+    pub timing: Option<Timing>,
+}
+
+impl ModelResponse {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, serde_json::Error> {
+        let value: serde_json::Value = serde_json::from_slice(bytes)?;
+        
+        let content = value.get("content")
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| serde_json::Error::missing_field("content"))?
+            .iter()
+            .map(ContentItem::deserialize)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let id = value.get("id")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| serde_json::Error::missing_field("id"))?
+            .to_string();
+
+        let model = value.get("model")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| serde_json::Error::missing_field("model"))?
+            .to_string();
+
+        let role = value.get("role")
+            .map(Role::deserialize)
+            .ok_or_else(|| serde_json::Error::missing_field("role"))??;
+
+        let message_type = value.get("type")
+            .and_then(|v| v.as_str())
+            .map(|v| ResponseType::from(v.to_string()))
+            .ok_or_else(|| serde_json::Error::missing_field("type"))?;
+
+        let stop_reason = value.get("stop_reason")
+            .and_then(|v| v.as_str())
+            .map(StopReason::from)
+            .ok_or_else(|| serde_json::Error::missing_field("stop_reason"))?;
+
+        // Optional fields
+        let stop_sequence = value.get("stop_sequence")
+            .and_then(|v| v.as_str())
+            .map(String::from);
+
+        let usage = value.get("usage")
+            .and_then(|v| Usage::deserialize(v).ok());
+
+        Ok(ModelResponse {
+            content,
+            id,
+            model,
+            role,
+            message_type,
+            stop_reason,
+            stop_sequence,
+            usage,
+            raw: Some(value),
+            timing: None,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum InferenceError {
+    NetworkError(String),
+    ApiError(reqwest::StatusCode, String),
+    InvalidResponse(String),
+    MissingApiKey(String),
+    SerializationError(String),
+    /// Not sent, it held secrets and `secrets.action` is block
+    SecretsBlocked(crate::secrets::SecretsBlocked),
+}
+
+impl std::fmt::Display for InferenceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            InferenceError::NetworkError(msg) => write!(f, "Network error: {}", msg),
+            InferenceError::ApiError(status, msg) => write!(f, "API error ({}): {}", status, msg),
+            InferenceError::InvalidResponse(msg) => write!(f, "Invalid response: {}", msg),
+            InferenceError::MissingApiKey(msg) => write!(f, "Missing API key: {}", msg),
+            InferenceError::SerializationError(msg) => write!(f, "Serialization error: {}", msg),
+            InferenceError::SecretsBlocked(blocked) => write!(f, "{}", blocked),
+        }
+    }
+}
+
+impl std::error::Error for InferenceError {}
+
+/// The settings a provider samples replies with, leaving out any it doesn't take.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sampling {
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+impl std::fmt::Display for Sampling {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.model)?;
+        if let Some(temperature) = self.temperature {
+            write!(f, ", temperature {}", temperature)?;
+        }
+        if let Some(seed) = self.seed {
+            write!(f, ", seed {}", seed)?;
+        }
+        Ok(())
+    }
+}
+
+// Only called on the concrete providers, so no Send bound is needed on the futures
+#[allow(async_fn_in_trait)]
+pub trait Inference {
+    async fn query_model(&self, messages: Vec<Message>, system_message: Option<&str>) -> Result<ModelResponse, InferenceError>;
+
+    /// What requests are sent with, recorded on sessions so a run can be repeated.
+    fn sampling(&self) -> Sampling;
+
+    /// Streams the response into `acc`, in one piece unless the provider streams.
+    async fn query_model_stream(
+        &self,
+        messages: Vec<Message>,
+        system_message: Option<&str>,
+        acc: &mut StreamAccumulator<'_>,
+    ) -> Result<(), InferenceError> {
+        acc.push_response(self.query_model(messages, system_message).await?);
+        Ok(())
+    }
+
+    /// The response as a `futures::Stream` of events, see `EventStream`.
+    fn stream(&self, messages: Vec<Message>, system_message: Option<&str>) -> EventStream<'_> where Self: Sized {
+        let system_message = system_message.map(String::from);
+        EventStream::new(|mut acc| async move {
+            self.query_model_stream(messages, system_message.as_deref(), &mut acc).await?;
+            acc.finish().map(|_| ())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stop_reason_names() -> Result<(), serde_json::Error> {
+        assert_eq!(StopReason::from("length"), StopReason::MaxTokens);
+        assert_eq!(StopReason::from("tool_calls"), StopReason::ToolUse);
+        assert_eq!(serde_json::from_str::<StopReason>("\"pause_turn\"")?, StopReason::Other("pause_turn".to_string()));
+        assert_eq!(serde_json::to_string(&StopReason::EndTurn)?, "\"end_turn\"");
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_bytes_keeps_raw() -> Result<(), serde_json::Error> {
+        let body = br#"{"id": "msg_1", "model": "claude", "role": "assistant", "type": "message", "content": [{"type": "text", "text": "Hi"}],
+            "stop_reason": "end_turn", "stop_sequence": null, "usage": {"input_tokens": 3, "output_tokens": 1}, "container": {"id": "c1"}}"#;
+        let response = ModelResponse::from_bytes(body)?;
+        assert_eq!(response.content, vec![ContentItem::Text { text: "Hi".to_string() }]);
+        assert_eq!((response.role.clone(), response.message_type.clone()), (Role::Assistant, ResponseType::Message));
+        assert_eq!(response.raw.as_ref().map(|raw| &raw["container"]["id"]), Some(&serde_json::json!("c1")));
+        assert_eq!(serde_json::to_value(&response)?.get("raw"), None);
+        Ok(())
+    }
+}
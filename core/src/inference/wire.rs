@@ -0,0 +1,353 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use regex::Regex;
+use reqwest::header::HeaderMap;
+use serde_json::{json, Map, Value};
+
+use crate::config::ProjectConfig;
+use crate::crypto::Cipher;
+use crate::secrets;
+use crate::store::Store;
+use crate::transport;
+use super::cassette::{self, Interaction, RecordedRequest, RecordedResponse};
+
+const WIRE_LOG_FILE: &str = "wire.jsonl";
+
+// The latest request and its response, kept whether or not `wire_log` is on
+const LAST_EXCHANGE_FILE: &str = "last_exchange.json";
+
+const REDACTED: &str = "[REDACTED]";
+
+// Configured keys shorter than this are placeholders like `x` for a local server, not secrets
+const MIN_SECRET_LEN: usize = 8;
+
+// Headers that carry credentials, compared lowercased
+const SECRET_HEADERS: &[&str] = &["authorization", "proxy-authorization", "x-api-key", "api-key", "cookie", "x-amz-security-token"];
+
+struct Inner {
+    // The wire log, when `wire_log` is set
+    file: Option<Mutex<File>>,
+    last: PathBuf,
+    // The configured API keys, blanked as they are
+    secrets: Vec<String>,
+    patterns: Vec<Regex>,
+    // That of the session store when it is encrypted, for every line and the last exchange
+    cipher: Option<Cipher>,
+}
+
+/*
+    * The opt-in log of every request pprog sends a provider and the response it gets back,
+    * one JSON object per line in `.pprog/wire.jsonl`, for seeing exactly what the model was
+    * sent.  The latest exchange is also kept in `.pprog/last_exchange.json` either way, for
+    * `dump_last`.  Credentials never reach either file: secret headers, the configured API
+    * keys, the usual key and token formats and the patterns in `redact` are replaced before
+    * writing.  Both files are only readable by their owner, and encrypted with the key of the
+    * session store when it is encrypted, since what was sent is as private as the sessions.
+*/
+#[derive(Clone, Default)]
+pub struct WireLog {
+    inner: Option<Arc<Inner>>,
+}
+
+/// One request in the wire log.  What is passed to `body` is logged as the response when it
+/// is dropped, so a stream cut off by an error is logged as far as it got.
+pub struct Exchange {
+    inner: Option<Arc<Inner>>,
+    // The request as logged, for `LAST_EXCHANGE_FILE`
+    request: Value,
+    id: String,
+    provider: &'static str,
+    status: Option<u16>,
+    started: Instant,
+    body: Vec<u8>,
+}
+
+impl WireLog {
+    /// Keeps the last exchange, and logs them all when `wire_log` is set.  Outside a git
+    /// repository there is nowhere to keep them and nothing is written.
+    pub fn from_config(config: &ProjectConfig) -> Result<Self, anyhow::Error> {
+        let Ok(dir) = ProjectConfig::data_dir() else { return Ok(WireLog::default()) };
+        let secrets = [&config.api_key, &config.embeddings.api_key].into_iter().cloned().collect::<Vec<String>>();
+        let cipher = Store::open()?.cipher().cloned();
+        WireLog::open(&dir, config.wire_log, secrets, &config.redact, cipher)
+            .map_err(|e| anyhow::anyhow!("Failed to open the wire log: {}", e))
+    }
+
+    /// Writes to `dir`, appending to the wire log there with `log`, blanking `secrets` and
+    /// matches of the `patterns` regexes and encrypting with `cipher`.
+    pub fn open(dir: &Path, log: bool, secrets: Vec<String>, patterns: &[String], cipher: Option<Cipher>) -> Result<Self, anyhow::Error> {
+        let patterns = secrets::PATTERNS.iter().map(|(_, pattern)| *pattern)
+            .chain(patterns.iter().map(String::as_str))
+            .map(|pattern| Regex::new(pattern).map_err(|e| anyhow::anyhow!("Invalid redact pattern {:?}: {}", pattern, e)))
+            .collect::<Result<Vec<Regex>, anyhow::Error>>()?;
+        let file = match log {
+            true => Some(Mutex::new(ProjectConfig::private_file().create(true).append(true).open(dir.join(WIRE_LOG_FILE))?)),
+            false => None,
+        };
+        let secrets = secrets.into_iter().filter(|secret| secret.len() >= MIN_SECRET_LEN).collect();
+        Ok(WireLog { inner: Some(Arc::new(Inner { file, last: dir.join(LAST_EXCHANGE_FILE), secrets, patterns, cipher })) })
+    }
+
+    /// Logs `request` and sends it, or answers it from the cassette being replayed.
+    pub async fn send(&self, provider: &'static str, request: reqwest::RequestBuilder) -> Result<(transport::Response, Exchange), anyhow::Error> {
+        let (client, request) = request.build_split();
+        let request = transport::compressed(request?);
+        let mut exchange = self.request(provider, request.method().as_str(), request.url().as_str(), request.headers(), request.body().and_then(|body| body.as_bytes()));
+        if cassette::replaying() {
+            // Cassettes keep URLs as they were logged, with any secret in them redacted
+            let url = exchange.request["url"].as_str().unwrap_or(request.url().as_str());
+            let recorded = cassette::replay(request.method().as_str(), url)?;
+            exchange.status = Some(recorded.status);
+            let response = http::Response::builder().status(recorded.status).body(recorded.body)?;
+            return Ok((transport::Response::new(response.into())?, exchange));
+        }
+        let response = client.execute(request).await?;
+        exchange.status = Some(response.status().as_u16());
+        Ok((transport::Response::new(response)?, exchange))
+    }
+
+    /// Logs a request sent some other way than `send`, like through the AWS SDK.
+    pub fn request(&self, provider: &'static str, method: &str, url: &str, headers: &HeaderMap, body: Option<&[u8]>) -> Exchange {
+        let mut exchange = Exchange {
+            inner: self.inner.clone(),
+            request: Value::Null,
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            provider,
+            status: None,
+            started: Instant::now(),
+            body: Vec::new(),
+        };
+        if let Some(inner) = &self.inner {
+            let headers = headers.iter()
+                .map(|(name, value)| {
+                    let value = match SECRET_HEADERS.contains(&name.as_str()) {
+                        true => REDACTED.to_string(),
+                        false => String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                    };
+                    (name.to_string(), json!(value))
+                })
+                .collect::<Map<String, Value>>();
+            exchange.request = inner.write(json!({
+                "id": exchange.id,
+                "provider": provider,
+                "type": "request",
+                "method": method,
+                "url": url,
+                "headers": headers,
+                "body": body.map(parse_body),
+            }));
+        }
+        exchange
+    }
+}
+
+impl Exchange {
+    /// Adds to the response body, a whole body or a chunk of a stream.
+    pub fn body(&mut self, chunk: &[u8]) {
+        if self.inner.is_some() {
+            self.body.extend_from_slice(chunk);
+        }
+    }
+
+    pub fn set_status(&mut self, status: u16) {
+        self.status = Some(status);
+    }
+}
+
+impl Drop for Exchange {
+    fn drop(&mut self) {
+        let Some(inner) = &self.inner else { return };
+        if cassette::recording() {
+            cassette::record(Interaction {
+                request: RecordedRequest {
+                    method: self.request["method"].as_str().unwrap_or_default().to_string(),
+                    url: self.request["url"].as_str().unwrap_or_default().to_string(),
+                    body: self.request["body"].clone(),
+                },
+                response: RecordedResponse {
+                    status: self.status.unwrap_or_default(),
+                    body: inner.redact_text(String::from_utf8_lossy(&self.body).into_owned()),
+                },
+            });
+        }
+        let response = inner.write(json!({
+            "id": self.id,
+            "provider": self.provider,
+            "type": "response",
+            "status": self.status,
+            "duration_ms": self.started.elapsed().as_millis() as u64,
+            "body": parse_body(&self.body),
+        }));
+        let last = json!({"request": std::mem::take(&mut self.request), "response": response});
+        // A `Value` always serializes
+        let text = serde_json::to_string_pretty(&last).unwrap_or_default();
+        if let Err(e) = inner.seal(text).and_then(|text| write_private(&inner.last, &text)) {
+            log::error!("Failed to save the last exchange: {}", e);
+        }
+    }
+}
+
+// JSON as JSON, a server-sent event stream as the list of its events' data, anything else as text
+fn parse_body(body: &[u8]) -> Value {
+    let text = String::from_utf8_lossy(body);
+    if let Ok(value) = serde_json::from_str(&text) {
+        return value;
+    }
+    let events = text.lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| serde_json::from_str(data.trim()).unwrap_or_else(|_| json!(data.trim())))
+        .collect::<Vec<Value>>();
+    match events.is_empty() {
+        true => json!(text),
+        false => Value::Array(events),
+    }
+}
+
+impl Inner {
+    fn redact(&self, value: &mut Value) {
+        match value {
+            Value::String(text) => {
+                for secret in &self.secrets {
+                    if text.contains(secret.as_str()) {
+                        *text = text.replace(secret.as_str(), REDACTED);
+                    }
+                }
+                for pattern in &self.patterns {
+                    if pattern.is_match(text) {
+                        *text = pattern.replace_all(text, REDACTED).into_owned();
+                    }
+                }
+            },
+            Value::Array(items) => items.iter_mut().for_each(|item| self.redact(item)),
+            Value::Object(fields) => fields.values_mut().for_each(|field| self.redact(field)),
+            _ => {},
+        }
+    }
+
+    fn redact_text(&self, text: String) -> String {
+        let mut value = Value::String(text);
+        self.redact(&mut value);
+        match value {
+            Value::String(text) => text,
+            _ => String::new(),
+        }
+    }
+
+    // Encrypted with the cipher there is one, never written in the clear then
+    fn seal(&self, text: String) -> Result<String, anyhow::Error> {
+        match &self.cipher {
+            Some(cipher) => cipher.encrypt(&text),
+            None => Ok(text),
+        }
+    }
+
+    // Redacts `entry` and adds it to the wire log, returning it as written
+    fn write(&self, mut entry: Value) -> Value {
+        self.redact(&mut entry);
+        entry["timestamp"] = json!(chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true));
+        if let Some(file) = &self.file {
+            let written = self.seal(entry.to_string())
+                .and_then(|line| Ok(writeln!(file.lock().unwrap(), "{}", line)?));
+            if let Err(e) = written {
+                log::error!("Failed to write the wire log: {}", e);
+            }
+        }
+        entry
+    }
+}
+
+// Replaces `path` with `text`, created readable only by its owner
+fn write_private(path: &Path, text: &str) -> Result<(), anyhow::Error> {
+    ProjectConfig::private_file().create(true).write(true).truncate(true).open(path)?.write_all(text.as_bytes())?;
+    Ok(())
+}
+
+/// Copies the last request sent from this project and its response, as pretty JSON, to
+/// `output` or a new `debug-<time>.json` in `.pprog`, decrypted to be read but only readable
+/// by its owner.
+pub fn dump_last(output: Option<&Path>) -> Result<PathBuf, anyhow::Error> {
+    let dir = ProjectConfig::data_dir()?;
+    let last = fs::read_to_string(dir.join(LAST_EXCHANGE_FILE))
+        .map_err(|_| anyhow::anyhow!("No request has been sent from this project yet"))?;
+    let last = open_last(&last, Store::open()?.cipher())?;
+    let output = match output {
+        Some(path) => path.to_path_buf(),
+        None => dir.join(format!("debug-{}.json", chrono::Local::now().format("%Y%m%d-%H%M%S"))),
+    };
+    write_private(&output, &last)?;
+    Ok(output)
+}
+
+// The last exchange as written, decrypted with `cipher`
+fn open_last(text: &str, cipher: Option<&Cipher>) -> Result<String, anyhow::Error> {
+    match cipher {
+        Some(cipher) => cipher.decrypt(text),
+        None if text.starts_with(crate::crypto::ENCRYPTED_PREFIX) => Err(anyhow::anyhow!("The last exchange is encrypted but no key was loaded")),
+        None => Ok(text.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redaction() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(WIRE_LOG_FILE);
+        let wire = WireLog::open(dir.path(), true, vec!["anthropic-key-123".to_string()], &[r"hunter\d".to_string()], None)?;
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", "anthropic-key-123".parse()?);
+        headers.insert("anthropic-version", "2023-06-01".parse()?);
+        let body = json!({"messages": [{"role": "user", "content": "my password is hunter2, key sk-proj-abcdefghijklmnopqrstuvwx"}]});
+        let mut exchange = wire.request("anthropic", "POST", "https://api.anthropic.com/v1/messages", &headers, Some(body.to_string().as_bytes()));
+        exchange.set_status(200);
+        exchange.body(b"event: message_start\ndata: {\"type\": \"message_start\"}\n\n");
+        exchange.body(b"data: {\"type\": \"message_stop\", \"echo\": \"anthropic-key-123\"}\n\n");
+        drop(exchange);
+
+        let log = std::fs::read_to_string(&path)?;
+        assert!(!log.contains("anthropic-key-123") && !log.contains("hunter2") && !log.contains("sk-proj"));
+        let lines = log.lines().map(serde_json::from_str).collect::<Result<Vec<Value>, _>>()?;
+        assert_eq!(lines[0]["headers"], json!({"x-api-key": REDACTED, "anthropic-version": "2023-06-01"}));
+        assert_eq!(lines[0]["body"]["messages"][0]["content"], json!("my password is [REDACTED], key [REDACTED]"));
+        assert_eq!(lines[1]["id"], lines[0]["id"]);
+        assert_eq!(lines[1]["status"], json!(200));
+        assert_eq!(lines[1]["body"], json!([{"type": "message_start"}, {"type": "message_stop", "echo": REDACTED}]));
+
+        let last: Value = serde_json::from_str(&std::fs::read_to_string(dir.path().join(LAST_EXCHANGE_FILE))?)?;
+        assert_eq!(last, json!({"request": lines[0], "response": lines[1]}));
+        Ok(())
+    }
+
+    #[test]
+    fn test_private_and_encrypted() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let cipher = Cipher::from_key(&Cipher::generate_key()?)?;
+        let wire = WireLog::open(dir.path(), true, Vec::new(), &[], Some(cipher.clone()))?;
+        let mut exchange = wire.request("openai", "POST", "https://api.openai.com/v1/chat/completions", &HeaderMap::new(), Some(b"{\"content\": \"the launch code\"}"));
+        exchange.body(b"{\"content\": \"noted\"}");
+        drop(exchange);
+
+        let log = fs::read_to_string(dir.path().join(WIRE_LOG_FILE))?;
+        let last = fs::read_to_string(dir.path().join(LAST_EXCHANGE_FILE))?;
+        assert_eq!(log.lines().count(), 2);
+        assert!(log.lines().chain([last.as_str()]).all(|text| text.starts_with(crate::crypto::ENCRYPTED_PREFIX)));
+        assert!(!log.contains("launch") && !last.contains("launch"));
+        let opened: Value = serde_json::from_str(&open_last(&last, Some(&cipher))?)?;
+        assert_eq!(opened["request"]["body"]["content"], json!("the launch code"));
+        assert!(open_last(&last, None).is_err());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            for file in [WIRE_LOG_FILE, LAST_EXCHANGE_FILE] {
+                assert_eq!(fs::metadata(dir.path().join(file))?.permissions().mode() & 0o777, 0o600);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::path::Path;
+
+use regex::bytes::Regex;
+
+/// The tool the model reads a slice of a file too large to read whole with.
+pub const LARGE_FILE_TOOL: &str = "read_large_file";
+
+// Lines returned when the call gives no limit, and the most it may ask for
+const DEFAULT_LINES: usize = 200;
+const MAX_LINES: usize = 2000;
+
+// A longer line is cut, minified files and logs of JSON blobs have lines of megabytes
+const MAX_LINE_CHARS: usize = 2000;
+
+// Of a longer line only this much is kept, for matching and showing, so no line fills memory
+const MAX_LINE_BYTES: usize = 1 << 20;
+
+// Characters of lines sent back, like the cut-off of fetched pages
+const MAX_OUTPUT_CHARS: usize = 40_000;
+
+/// Which lines of the file a call asks for.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Slice {
+    /// The first line, counting from 1
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+    /// The last this many lines instead of the ones from `offset`
+    pub tail: Option<usize>,
+    /// Only the lines matching this regex, from `offset` on
+    pub pattern: Option<String>,
+}
+
+impl Slice {
+    /// The slice asked for by a tool call, numbers given as JSON numbers or strings.
+    pub fn from_input(input: &serde_json::Value) -> Result<Self, anyhow::Error> {
+        let number = |field: &str| -> Result<Option<usize>, anyhow::Error> {
+            match &input[field] {
+                serde_json::Value::Null => Ok(None),
+                serde_json::Value::Number(number) => number.as_u64().map(|number| Some(number as usize))
+                    .ok_or_else(|| anyhow::anyhow!("'{}' must be a whole number, not {}", field, number)),
+                serde_json::Value::String(text) => text.trim().parse().map(Some)
+                    .map_err(|_| anyhow::anyhow!("'{}' must be a whole number, not {:?}", field, text)),
+                value => Err(anyhow::anyhow!("'{}' must be a whole number, not {}", field, value)),
+            }
+        };
+        Ok(Slice {
+            offset: number("offset")?,
+            limit: number("limit")?,
+            tail: number("tail")?,
+            pattern: input["pattern"].as_str().filter(|pattern| !pattern.is_empty()).map(str::to_string),
+        })
+    }
+}
+
+fn line_text(line: &[u8], skipped: usize) -> String {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let text = String::from_utf8_lossy(line);
+    match text.char_indices().nth(MAX_LINE_CHARS) {
+        Some((cut, _)) => format!("{}… [{} more bytes]", &text[..cut], text[cut..].len() + skipped),
+        None => text.into_owned(),
+    }
+}
+
+// The next line of `reader` into `line` without its newline, kept to MAX_LINE_BYTES, with the
+// number of bytes past them that were skipped.  None at the end of the file.
+fn next_line(reader: &mut impl BufRead, line: &mut Vec<u8>) -> io::Result<Option<usize>> {
+    line.clear();
+    let mut skipped = 0;
+    let mut read_any = false;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(read_any.then_some(skipped));
+        }
+        read_any = true;
+        let newline = buf.iter().position(|&byte| byte == b'\n');
+        let chunk = &buf[..newline.unwrap_or(buf.len())];
+        let keep = chunk.len().min(MAX_LINE_BYTES.saturating_sub(line.len()));
+        line.extend_from_slice(&chunk[..keep]);
+        skipped += chunk.len() - keep;
+        let used = newline.map_or(buf.len(), |idx| idx + 1);
+        reader.consume(used);
+        if newline.is_some() {
+            return Ok(Some(skipped));
+        }
+    }
+}
+
+// Where the last `count` lines start and the number of the first of them, in one pass that
+// keeps no more than their offsets
+fn tail_start(reader: &mut impl BufRead, count: usize) -> io::Result<(u64, usize)> {
+    let mut starts = VecDeque::from([0u64]);
+    let mut at = 0u64;
+    let mut lines = 1;
+    let mut ends_with_newline = false;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        for (idx, _) in buf.iter().enumerate().filter(|(_, &byte)| byte == b'\n') {
+            starts.push_back(at + idx as u64 + 1);
+            lines += 1;
+            if starts.len() > count + 1 {
+                starts.pop_front();
+            }
+        }
+        ends_with_newline = buf.ends_with(b"\n");
+        at += buf.len() as u64;
+        let used = buf.len();
+        reader.consume(used);
+    }
+    // A final newline ends the last line rather than starting an empty one
+    if ends_with_newline {
+        starts.pop_back();
+        lines -= 1;
+    }
+    while starts.len() > count {
+        starts.pop_front();
+    }
+    Ok((starts.front().copied().unwrap_or(at), lines - starts.len() + 1))
+}
+
+/*
+    * Reads the lines of `path` that `slice` asks for, numbered, a line at a time through a
+    * buffer rather than the whole file, so a log of several gigabytes takes no more memory
+    * than the lines returned.  The last lines are found in one pass that keeps only where
+    * they start.  Anything but a regular file is refused, a pipe or device could be read
+    * forever.  A `pattern` is matched line by line over the raw bytes, so a file that isn't
+    * UTF-8 can be searched too.  Long lines are cut and so is the whole output, with a note
+    * of where to go on.
+*/
+pub fn read(path: &Path, slice: &Slice) -> Result<String, anyhow::Error> {
+    let file = File::open(path).map_err(|e| anyhow::anyhow!("Error opening file {:?}: {}", path, e))?;
+    let metadata = file.metadata()?;
+    if !metadata.is_file() {
+        return Err(anyhow::anyhow!("{:?} is not a regular file", path));
+    }
+    let size = metadata.len();
+    if size == 0 {
+        return Ok(format!("{:?} is empty.", path));
+    }
+    let mut reader = BufReader::new(file);
+    let read_error = |e: io::Error| anyhow::anyhow!("Error reading file {:?}: {}", path, e);
+
+    let pattern = slice.pattern.as_deref().map(Regex::new).transpose()
+        .map_err(|e| anyhow::anyhow!("Invalid pattern: {}", e))?;
+    let limit = slice.limit.or(slice.tail).unwrap_or(DEFAULT_LINES).clamp(1, MAX_LINES);
+    let mut line = Vec::new();
+    let first_line = match slice.tail {
+        Some(tail) => {
+            let (start, first_line) = tail_start(&mut reader, tail.clamp(1, MAX_LINES)).map_err(read_error)?;
+            reader.seek(SeekFrom::Start(start)).map_err(read_error)?;
+            first_line
+        },
+        None => {
+            let offset = slice.offset.unwrap_or(1).max(1);
+            for _ in 1..offset {
+                if next_line(&mut reader, &mut line).map_err(read_error)?.is_none() {
+                    break;
+                }
+            }
+            offset
+        },
+    };
+    let mut output = String::new();
+    let mut shown = 0;
+    let mut last = None;
+    let mut cut_off = false;
+    let mut number = first_line - 1;
+    while let Some(skipped) = next_line(&mut reader, &mut line).map_err(read_error)? {
+        number += 1;
+        if pattern.as_ref().is_some_and(|pattern| !pattern.is_match(&line)) {
+            continue;
+        }
+        if shown == limit {
+            cut_off = true;
+            break;
+        }
+        let text = format!("{}: {}\n", number, line_text(&line, skipped));
+        if output.len() + text.len() > MAX_OUTPUT_CHARS && shown > 0 {
+            cut_off = true;
+            break;
+        }
+        output.push_str(&text);
+        shown += 1;
+        last = Some(number);
+    }
+
+    let what = match &slice.pattern {
+        Some(pattern) => format!("{} lines matching /{}/ from line {}", shown, pattern, first_line),
+        None => match last {
+            Some(last) => format!("lines {}-{}", first_line, last),
+            None => format!("no lines from line {}, the file has fewer", first_line),
+        },
+    };
+    let mut header = format!("{:?}, {} bytes: {}", path, size, what);
+    if let (true, Some(last)) = (cut_off, last) {
+        header.push_str(&format!(", more after line {}, go on with offset {}", last, last + 1));
+    }
+    Ok(format!("{}\n{}", header, output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("app.log");
+        let log = (1..=1000).map(|n| format!("{} {}", if n % 100 == 0 { "ERROR" } else { "INFO" }, n)).collect::<Vec<String>>().join("\n") + "\n";
+        std::fs::write(&path, log)?;
+
+        let head = read(&path, &Slice { limit: Some(2), ..Default::default() })?;
+        assert!(head.ends_with("lines 1-2, more after line 2, go on with offset 3\n1: INFO 1\n2: INFO 2\n"));
+        let middle = read(&path, &Slice { offset: Some(500), limit: Some(1), ..Default::default() })?;
+        assert!(middle.ends_with("500: ERROR 500\n"));
+        let tail = read(&path, &Slice { tail: Some(2), ..Default::default() })?;
+        assert!(tail.ends_with("lines 999-1000\n999: INFO 999\n1000: ERROR 1000\n"));
+        let errors = read(&path, &Slice { offset: Some(250), pattern: Some("^ERROR".to_string()), limit: Some(3), ..Default::default() })?;
+        assert!(errors.ends_with("3 lines matching /^ERROR/ from line 250, more after line 500, go on with offset 501\n300: ERROR 300\n400: ERROR 400\n500: ERROR 500\n"));
+        assert!(read(&path, &Slice { offset: Some(2000), ..Default::default() })?.contains("no lines from line 2000"));
+
+        // No final newline, a line past what is kept, and no reading of what isn't a file
+        let long = dir.path().join("long.log");
+        std::fs::write(&long, format!("first\n{}\nlast", "x".repeat(MAX_LINE_BYTES + 10)))?;
+        assert!(read(&long, &Slice { tail: Some(1), ..Default::default() })?.ends_with("lines 3-3\n3: last\n"));
+        let cut = read(&long, &Slice { offset: Some(2), limit: Some(1), ..Default::default() })?;
+        assert!(cut.ends_with(&format!("2: {}… [{} more bytes]\n", "x".repeat(MAX_LINE_CHARS), MAX_LINE_BYTES + 10 - MAX_LINE_CHARS)));
+        assert!(read(&long, &Slice { tail: Some(5), ..Default::default() })?.contains("lines 1-3"));
+        assert!(read(dir.path(), &Slice::default()).is_err());
+
+        let input = serde_json::json!({"path": "app.log", "offset": "10", "limit": 5, "pattern": ""});
+        assert_eq!(Slice::from_input(&input)?, Slice { offset: Some(10), limit: Some(5), ..Default::default() });
+        assert!(Slice::from_input(&serde_json::json!({"tail": -1})).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+//! The agent of pprog without its frontends, for Rust programs that embed it instead of
+//! running the `pprog` binary.
+//!
+//! The parts most programs need:
+//!
+//! - [`config::ProjectConfig`], the settings of `pprog.toml`, loaded from the git root of the
+//!   working directory or built in code.
+//! - [`chat::Chat`], a session with a model: the system prompt, context management, usage
+//!   records and the tools it runs.
+//! - [`agent::run_turn_with`], which runs one user turn to completion, tool calls included,
+//!   reporting to an [`agent::AgentEvents`] and taking approvals, steering and budgets
+//!   through [`agent::Controls`].
+//! - [`inference::types::Inference`], the trait of the providers, with
+//!   [`inference::MockInference`] and [`inference::ChaosInference`] for tests.
+//! - [`tooler::Tooler`], which carries out the tool calls against the project.
+//!
+//! ```no_run
+//! # #![recursion_limit = "256"]
+//! use pprog_core::agent::{self, AgentEvents, Controls};
+//! use pprog_core::chat::{Chat, InferenceProvider};
+//! use pprog_core::config::ProjectConfig;
+//! use pprog_core::inference::MockInference;
+//! use pprog_core::mentions;
+//!
+//! struct Printer;
+//!
+//! impl AgentEvents for Printer {
+//!     fn on_text_delta(&mut self, text: &str) {
+//!         print!("{}", text);
+//!     }
+//! }
+//!
+//! # async fn run() -> Result<(), anyhow::Error> {
+//! let mut chat = Chat::from_config(ProjectConfig::load_or_default()?).await?;
+//! // Scripted replies instead of the configured provider, as in a test
+//! chat.set_inference(InferenceProvider::Mock(MockInference::new("mock").text("Done.")));
+//! let (_source, mut cancel) = agent::cancel_pair();
+//! let message = mentions::user_message("Add a README");
+//! let outcome = agent::run_turn_with(&mut chat, message, &mut Printer, &mut Controls::default(), &mut cancel).await?;
+//! assert!(!outcome.incomplete);
+//! # Ok(())
+//! # }
+//! ```
+
+// Lets the code `pprog-derive` generates name `::pprog_core` inside this crate too
+extern crate self as pprog_core;
+
+pub mod agent;
+pub mod audit;
+pub mod auth;
+pub mod inference;
+pub mod chat;
+pub mod tree;
+pub mod config;
+pub mod checkpoint;
+pub mod largefile;
+pub mod lock;
+pub mod loops;
+pub mod memory;
+pub mod mentions;
+pub mod metrics;
+pub mod context;
+pub mod crypto;
+pub mod egress;
+pub mod embeddings;
+pub mod models;
+pub mod permissions;
+pub mod prefetch;
+pub mod pii;
+pub mod plan;
+pub mod policy;
+pub mod prompt;
+pub mod repomap;
+pub mod routing;
+pub mod sandbox;
+pub mod secrets;
+pub mod session;
+pub mod snapshot;
+pub mod images;
+pub mod store;
+pub mod structured;
+pub mod task;
+pub mod telemetry;
+pub mod tokens;
+pub mod todo;
+pub mod toolcache;
+pub mod tooler;
+pub mod transport;
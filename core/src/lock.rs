@@ -0,0 +1,75 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config::ProjectConfig;
+
+static LOCKING_DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turns every later lock into a no-op, for `--no-lock`.
+pub fn disable() {
+    LOCKING_DISABLED.store(true, Ordering::Relaxed);
+}
+
+/*
+    * Advisory lock held for as long as the value lives.  The OS releases it when the process
+    * exits, so a crashed run never leaves a stale lock behind, which is also why lock files
+    * are never deleted.  Locks only guard against other pprog processes, nothing stops an
+    * editor or git from touching the same files.
+*/
+#[derive(Debug)]
+pub struct Lock {
+    _file: Option<File>,
+}
+
+impl Lock {
+    /// Exclusive use of the working tree and checkpoints, taken by anything that writes files.
+    pub fn workspace() -> Result<Self, anyhow::Error> {
+        Self::acquire("workspace", "Workspace is busy: another pprog process is working in this repository")
+    }
+
+    /// Exclusive use of one saved session, taken by anything that appends to it.
+    pub fn session(id: &str) -> Result<Self, anyhow::Error> {
+        Self::acquire(&format!("session-{}", id), &format!("Session {} is busy: another pprog process is using it", id))
+    }
+
+    fn acquire(name: &str, busy: &str) -> Result<Self, anyhow::Error> {
+        if LOCKING_DISABLED.load(Ordering::Relaxed) {
+            return Ok(Lock { _file: None });
+        }
+        Self::acquire_in(&ProjectConfig::data_dir()?.join("locks"), name, busy)
+    }
+
+    fn acquire_in(dir: &Path, name: &str, busy: &str) -> Result<Self, anyhow::Error> {
+        fs::create_dir_all(dir)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(dir.join(format!("{}.lock", name)))?;
+        match file.try_lock() {
+            Ok(()) => Ok(Lock { _file: Some(file) }),
+            Err(TryLockError::WouldBlock) => Err(anyhow::anyhow!("{}, pass --no-lock to override", busy)),
+            Err(TryLockError::Error(e)) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_is_exclusive_until_dropped() -> Result<(), anyhow::Error> {
+        let dir = std::env::temp_dir().join(format!("pprog-locks-{}", uuid::Uuid::new_v4()));
+        let lock = Lock::acquire_in(&dir, "session-a", "busy")?;
+        let err = Lock::acquire_in(&dir, "session-a", "busy").unwrap_err();
+        assert_eq!(err.to_string(), "busy, pass --no-lock to override");
+        Lock::acquire_in(&dir, "session-b", "busy")?;
+
+        drop(lock);
+        Lock::acquire_in(&dir, "session-a", "busy")?;
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use sha2::{Digest, Sha256};
+
+/// A turn stopped because the model kept doing the same thing over.
+#[derive(Debug)]
+pub struct Stuck {
+    pub reason: String,
+}
+
+impl std::fmt::Display for Stuck {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Stopped, the agent looks stuck: {}", self.reason)
+    }
+}
+
+impl std::error::Error for Stuck {}
+
+fn digest(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/*
+    * Watches the tool calls of one turn for a model going in circles.  A call made again
+    * with the same arguments counts as a repeat until a file is written with new content,
+    * so running the tests between edits is fine while running them over and over isn't.
+    * Writes are tracked per file, starting from what was on disk, to catch a file switched
+    * back and forth between the same two versions.
+*/
+pub struct LoopGuard {
+    max_repeats: usize,
+    root: Option<PathBuf>,
+    calls: HashMap<String, usize>,
+    versions: HashMap<String, Vec<String>>,
+}
+
+impl LoopGuard {
+    /// Stops at the `max_repeats`th identical call, 0 to never stop for repeats.
+    pub fn new(max_repeats: usize, root: Option<PathBuf>) -> Self {
+        LoopGuard { max_repeats, root, calls: HashMap::new(), versions: HashMap::new() }
+    }
+
+    /// Why the call shouldn't run, None if it looks like progress.
+    pub fn check(&mut self, name: &str, input: &serde_json::Value) -> Option<String> {
+        if let ("write_file", Some(path), Some(content)) = (name, input["path"].as_str(), input["content"].as_str()) {
+            let on_disk = self.root.as_ref().and_then(|root| std::fs::read_to_string(root.join(path)).ok());
+            let versions = self.versions.entry(path.to_string()).or_insert_with(|| on_disk.iter().map(|c| digest(c)).collect());
+            let new = digest(content);
+            if versions.last() != Some(&new) {
+                let n = versions.len();
+                // A, B, A and now B again
+                if n >= 3 && versions[n - 2] == new && versions[n - 1] == versions[n - 3] {
+                    return Some(format!("{} keeps being switched back and forth between the same two versions", path));
+                }
+                versions.push(new);
+                self.calls.clear();
+                return None;
+            }
+        }
+        let count = self.calls.entry(format!("{} {}", name, input)).or_insert(0);
+        *count += 1;
+        if self.max_repeats > 0 && *count >= self.max_repeats {
+            return Some(format!("{} was called {} times with the same arguments {} and no file changed in between", name, count, input));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_loop_guard() {
+        let mut guard = LoopGuard::new(3, None);
+        let test = json!({"statement": "cargo test"});
+        let write = |content: &str| json!({"path": "src/lib.rs", "content": content});
+        assert_eq!(guard.check("run_command", &test), None);
+        assert_eq!(guard.check("write_file", &write("a")), None);
+        assert_eq!(guard.check("run_command", &test), None);
+        assert_eq!(guard.check("run_command", &test), None);
+        assert!(guard.check("run_command", &test).unwrap().contains("called 3 times"));
+
+        assert_eq!(guard.check("write_file", &write("b")), None);
+        assert_eq!(guard.check("write_file", &write("a")), None);
+        assert!(guard.check("write_file", &write("b")).unwrap().contains("back and forth"));
+    }
+}
@@ -0,0 +1,70 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::config::ProjectConfig;
+
+const MEMORY_FILE: &str = "memory.md";
+const MEMORY_HEADER: &str = "# Project memory\n\n";
+const REMEMBER_COMMAND: &str = "#remember";
+
+/// Durable notes about the project kept in `.pprog/memory.md`.  Unlike sessions the file
+/// is meant to be committed so the whole team's agent benefits from it.
+pub struct ProjectMemory;
+
+impl ProjectMemory {
+    fn path() -> Result<PathBuf, anyhow::Error> {
+        Ok(ProjectConfig::data_dir()?.join(MEMORY_FILE))
+    }
+
+    /// Memory contents for the system prompt, or `None` if nothing was remembered yet.
+    pub fn load() -> Option<String> {
+        let content = fs::read_to_string(Self::path().ok()?).ok()?;
+        let notes = content.trim_start_matches(MEMORY_HEADER).trim();
+        if notes.is_empty() {
+            None
+        } else {
+            Some(notes.to_string())
+        }
+    }
+
+    pub fn remember(note: &str) -> Result<(), anyhow::Error> {
+        let note = note.trim();
+        if note.is_empty() {
+            return Err(anyhow::anyhow!("Nothing to remember"));
+        }
+
+        let path = Self::path()?;
+        let is_new = !path.exists();
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        if is_new {
+            file.write_all(MEMORY_HEADER.as_bytes())?;
+        }
+        writeln!(file, "- {}", note.replace('\n', " "))?;
+        log::info!("Remembered note: {}", note);
+        Ok(())
+    }
+
+    /// Extracts the note from a `#remember <note>` chat command.
+    pub fn parse_command(text: &str) -> Option<&str> {
+        let rest = text.trim_start().strip_prefix(REMEMBER_COMMAND)?;
+        if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+            Some(rest.trim())
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(ProjectMemory::parse_command("#remember use cargo nextest"), Some("use cargo nextest"));
+        assert_eq!(ProjectMemory::parse_command("  #remember"), Some(""));
+        assert_eq!(ProjectMemory::parse_command("#remembering things"), None);
+        assert_eq!(ProjectMemory::parse_command("please #remember this"), None);
+    }
+}
@@ -0,0 +1,153 @@
+use std::path::Path;
+
+use crate::images;
+use crate::inference::types::{ContentItem, Message, Role};
+use crate::tree::GitTree;
+
+// Files longer than this are cut at a line boundary, the rest can be asked for by range
+const MAX_ATTACHED_CHARS: usize = 40_000;
+
+// Characters a mention can end with in prose without them being part of the path
+const TRAILING_PUNCTUATION: &[char] = &[',', '.', ';', ':', '!', '?', ')', '"', '\''];
+
+/// A `@path` or `@path:start-end` reference to a file in the workspace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mention {
+    pub path: String,
+    /// 1-based inclusive line range
+    pub lines: Option<(usize, usize)>,
+}
+
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+    (start >= 1 && end >= start).then_some((start, end))
+}
+
+/// Finds mentions of files in `files`.  Anything else after an `@`, like a handle or a
+/// decorator, is left alone.
+pub fn parse(text: &str, files: &[String]) -> Vec<Mention> {
+    let mut mentions: Vec<Mention> = Vec::new();
+    for word in text.split_whitespace() {
+        let Some(mut token) = word.strip_prefix('@') else { continue };
+        loop {
+            let (path, lines) = match token.rsplit_once(':') {
+                Some((path, range)) if parse_range(range).is_some() => (path, parse_range(range)),
+                _ => (token, None),
+            };
+            let path = path.strip_prefix("./").unwrap_or(path);
+            if files.iter().any(|file| file == path) {
+                let mention = Mention { path: path.to_string(), lines };
+                if !mentions.contains(&mention) {
+                    mentions.push(mention);
+                }
+                break;
+            }
+            match token.strip_suffix(TRAILING_PUNCTUATION) {
+                Some(stripped) => token = stripped,
+                None => break,
+            }
+        }
+    }
+    mentions
+}
+
+/// Completions for an `@` mention being typed at the end of `before_cursor`.
+pub fn complete(before_cursor: &str, files: &[String]) -> Vec<String> {
+    let word = before_cursor.rsplit(char::is_whitespace).next().unwrap_or_default();
+    let Some(prefix) = word.strip_prefix('@') else { return Vec::new() };
+    files.iter()
+        .filter(|file| file.starts_with(prefix))
+        .map(|file| format!("@{}", file))
+        .collect()
+}
+
+/// Renders the file as a fenced block labelled with its path.
+fn attachment(root: &Path, mention: &Mention) -> String {
+    let content = match std::fs::read_to_string(root.join(&mention.path)) {
+        Ok(content) => content,
+        Err(e) => return format!("{}: could not be read: {}", mention.path, e),
+    };
+    let lines: Vec<&str> = content.lines().collect();
+    let (start, end) = mention.lines.unwrap_or((1, lines.len()));
+    let end = end.min(lines.len());
+    if start > end {
+        return format!("{}: has only {} lines", mention.path, lines.len());
+    }
+
+    let mut body = String::new();
+    let mut last = start - 1;
+    for line in &lines[start - 1..end] {
+        if body.len() + line.len() > MAX_ATTACHED_CHARS && last >= start {
+            break;
+        }
+        body.push_str(line);
+        body.push('\n');
+        last += 1;
+    }
+
+    let lang = Path::new(&mention.path).extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    let label = match (start, last) {
+        (1, last) if last == lines.len() => mention.path.clone(),
+        (start, last) => format!("{} (lines {}-{} of {})", mention.path, start, last, lines.len()),
+    };
+    let mut text = format!("{}\n```{}\n{}```", label, lang, body);
+    if last < end {
+        text.push_str(&format!(
+            "\n[cut off for length, lines {}-{} are not included: read them with read_file or mention @{}:{}-{}]",
+            last + 1, end, mention.path, last + 1, end,
+        ));
+    }
+    text
+}
+
+/// Builds a user message from typed text, with every mentioned file attached after it,
+/// images as images.
+pub fn user_message(text: &str) -> Message {
+    let mut content = vec![ContentItem::Text { text: text.to_string() }];
+    if text.contains('@') {
+        match GitTree::get_git_root().and_then(|root| Ok((GitTree::list_files()?, root))) {
+            Ok((files, root)) => content.extend(parse(text, &files).iter().map(|mention| {
+                match images::is_image_path(&mention.path) {
+                    true => images::from_file(&root.join(&mention.path))
+                        .unwrap_or_else(|e| ContentItem::Text { text: format!("{}: could not be attached: {}", mention.path, e) }),
+                    false => ContentItem::Text { text: attachment(&root, mention) },
+                }
+            })),
+            Err(e) => log::error!("Could not list files for mentions: {}", e),
+        }
+    }
+    Message { role: Role::User, content }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mentions() {
+        let files = vec!["src/main.rs".to_string(), "README.md".to_string()];
+        let mentions = parse("compare @src/main.rs:10-20 with @./README.md, and ping @alice or foo@src/main.rs", &files);
+        assert_eq!(mentions, vec![
+            Mention { path: "src/main.rs".to_string(), lines: Some((10, 20)) },
+            Mention { path: "README.md".to_string(), lines: None },
+        ]);
+        assert_eq!(complete("look at @src/m", &files), vec!["@src/main.rs"]);
+        assert!(complete("look at src/m", &files).is_empty());
+    }
+
+    #[test]
+    fn test_long_files_are_cut() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let line = "x".repeat(99);
+        std::fs::write(dir.path().join("big.txt"), format!("{}\n", line).repeat(1000))?;
+
+        let text = attachment(dir.path(), &Mention { path: "big.txt".to_string(), lines: None });
+        assert!(text.starts_with("big.txt (lines 1-400 of 1000)\n```txt\n"));
+        assert!(text.ends_with("mention @big.txt:401-1000]"));
+
+        let text = attachment(dir.path(), &Mention { path: "big.txt".to_string(), lines: Some((5, 6)) });
+        assert_eq!(text, format!("big.txt (lines 5-6 of 1000)\n```txt\n{}\n{}\n```", line, line));
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::inference::types::{InferenceError, Usage};
+use crate::models;
+
+// Upper bounds in seconds of the latency histogram's buckets, replies with tools run long
+const LATENCY_BUCKETS: [f64; 9] = [0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0];
+
+// Provider and model
+type Model = (String, String);
+
+struct Histogram {
+    // Requests at or under each of `LATENCY_BUCKETS`
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+/*
+    * Counts of every model request this process made, by provider and model, for the
+    * `/metrics` endpoint of `pprog serve` to expose to Prometheus.  The counters only ever go
+    * up, Prometheus takes rates of them, and alerting on `pprog_cost_usd_total` catches a
+    * session burning money.
+*/
+struct Registry {
+    requests: BTreeMap<(String, String, String), u64>,
+    tokens: BTreeMap<(String, String, &'static str), u64>,
+    cost: BTreeMap<Model, f64>,
+    latency: BTreeMap<Model, Histogram>,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry::new());
+
+/// Counts a model request that took `elapsed`, with the usage its reply reported or why it failed.
+pub fn record_request(provider: &str, model: &str, elapsed: Duration, reply: Result<Option<&Usage>, &anyhow::Error>) {
+    REGISTRY.lock().unwrap().record(provider, model, elapsed, reply);
+}
+
+/// Everything counted so far, in Prometheus' text format.
+pub fn render() -> String {
+    REGISTRY.lock().unwrap().render()
+}
+
+// `ok`, the HTTP status the provider answered with, or the kind of error
+fn status(reply: Result<Option<&Usage>, &anyhow::Error>) -> String {
+    let Err(e) = reply else { return String::from("ok") };
+    match e.downcast_ref::<InferenceError>() {
+        Some(InferenceError::ApiError(status, _)) => status.as_u16().to_string(),
+        Some(InferenceError::NetworkError(_)) => String::from("network_error"),
+        Some(InferenceError::InvalidResponse(_)) => String::from("invalid_response"),
+        Some(InferenceError::MissingApiKey(_)) => String::from("configuration_error"),
+        Some(InferenceError::SerializationError(_)) => String::from("serialization_error"),
+        Some(InferenceError::SecretsBlocked(_)) => String::from("secrets_blocked"),
+        None => String::from("error"),
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn labels(pairs: &[(&str, &str)]) -> String {
+    let pairs = pairs.iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect::<Vec<String>>();
+    format!("{{{}}}", pairs.join(","))
+}
+
+impl Registry {
+    const fn new() -> Self {
+        Registry { requests: BTreeMap::new(), tokens: BTreeMap::new(), cost: BTreeMap::new(), latency: BTreeMap::new() }
+    }
+
+    fn record(&mut self, provider: &str, model: &str, elapsed: Duration, reply: Result<Option<&Usage>, &anyhow::Error>) {
+        let key = (provider.to_string(), model.to_string());
+        *self.requests.entry((key.0.clone(), key.1.clone(), status(reply))).or_default() += 1;
+
+        let seconds = elapsed.as_secs_f64();
+        let histogram = self.latency.entry(key.clone()).or_insert(Histogram { buckets: [0; LATENCY_BUCKETS.len()], sum: 0.0, count: 0 });
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+
+        let Ok(Some(usage)) = reply else { return };
+        let tokens = [
+            ("input", usage.input_tokens),
+            ("output", usage.output_tokens),
+            ("cache_read", usage.cache_read_input_tokens),
+            ("cache_write", usage.cache_creation_input_tokens),
+        ];
+        for (kind, count) in tokens {
+            *self.tokens.entry((key.0.clone(), key.1.clone(), kind)).or_default() += count as u64;
+        }
+        if let Some(info) = models::lookup(model) {
+            *self.cost.entry(key).or_default() += info.cost(usage);
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "# HELP pprog_requests_total Model requests by status, ok or the HTTP status or error kind.").ok();
+        writeln!(out, "# TYPE pprog_requests_total counter").ok();
+        for ((provider, model, status), count) in &self.requests {
+            writeln!(out, "pprog_requests_total{} {}", labels(&[("provider", provider), ("model", model), ("status", status)]), count).ok();
+        }
+        writeln!(out, "# HELP pprog_tokens_total Tokens the providers reported, by type.").ok();
+        writeln!(out, "# TYPE pprog_tokens_total counter").ok();
+        for ((provider, model, kind), count) in &self.tokens {
+            writeln!(out, "pprog_tokens_total{} {}", labels(&[("provider", provider), ("model", model), ("type", kind)]), count).ok();
+        }
+        writeln!(out, "# HELP pprog_cost_usd_total Cost of the requests in USD, for models with known prices.").ok();
+        writeln!(out, "# TYPE pprog_cost_usd_total counter").ok();
+        for ((provider, model), cost) in &self.cost {
+            writeln!(out, "pprog_cost_usd_total{} {}", labels(&[("provider", provider), ("model", model)]), cost).ok();
+        }
+        writeln!(out, "# HELP pprog_request_duration_seconds Time until a model request finished.").ok();
+        writeln!(out, "# TYPE pprog_request_duration_seconds histogram").ok();
+        for ((provider, model), histogram) in &self.latency {
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                let le = bound.to_string();
+                writeln!(out, "pprog_request_duration_seconds_bucket{} {}", labels(&[("provider", provider), ("model", model), ("le", &le)]), count).ok();
+            }
+            writeln!(out, "pprog_request_duration_seconds_bucket{} {}", labels(&[("provider", provider), ("model", model), ("le", "+Inf")]), histogram.count).ok();
+            writeln!(out, "pprog_request_duration_seconds_sum{} {}", labels(&[("provider", provider), ("model", model)]), histogram.sum).ok();
+            writeln!(out, "pprog_request_duration_seconds_count{} {}", labels(&[("provider", provider), ("model", model)]), histogram.count).ok();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut registry = Registry::new();
+        let usage = Usage { input_tokens: 1000, output_tokens: 100, ..Default::default() };
+        registry.record("anthropic", "claude-3-5-haiku-latest", Duration::from_millis(800), Ok(Some(&usage)));
+        let overloaded = anyhow::Error::new(InferenceError::ApiError(reqwest::StatusCode::from_u16(529).unwrap(), String::new()));
+        registry.record("anthropic", "claude-3-5-haiku-latest", Duration::from_secs(3), Err(&overloaded));
+
+        let text = registry.render();
+        let model = "provider=\"anthropic\",model=\"claude-3-5-haiku-latest\"";
+        assert!(text.contains(&format!("pprog_requests_total{{{},status=\"ok\"}} 1\n", model)));
+        assert!(text.contains(&format!("pprog_requests_total{{{},status=\"529\"}} 1\n", model)));
+        assert!(text.contains(&format!("pprog_tokens_total{{{},type=\"output\"}} 100\n", model)));
+        assert!(text.contains(&format!("pprog_cost_usd_total{{{}}} 0.0012\n", model)));
+        assert!(text.contains(&format!("pprog_request_duration_seconds_bucket{{{},le=\"1\"}} 1\n", model)));
+        assert!(text.contains(&format!("pprog_request_duration_seconds_bucket{{{},le=\"+Inf\"}} 2\n", model)));
+        assert!(text.contains(&format!("pprog_request_duration_seconds_count{{{}}} 2\n", model)));
+    }
+}
@@ -0,0 +1,75 @@
+use crate::inference::types::Usage;
+
+/// Static facts about the models pprog knows how to talk to.  Lookups are by prefix so
+/// dated snapshots (`claude-3-5-haiku-20241022`) and Bedrock ids
+/// (`anthropic.claude-3-5-haiku-20241022-v1:0`) resolve to the same entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelInfo {
+    pub name: &'static str,
+    pub context_window: usize,
+    pub max_output_tokens: u32,
+    /// USD per million input and output tokens
+    pub input_price: f64,
+    pub output_price: f64,
+    /// USD per million input tokens written to and read from the prompt cache.  Providers
+    /// that cache without being asked charge nothing extra for writes, so it's `input_price`.
+    pub cache_write_price: f64,
+    pub cache_read_price: f64,
+}
+
+pub static MODELS: &[ModelInfo] = &[
+    ModelInfo { name: "claude-3-5-haiku", context_window: 200_000, max_output_tokens: 8192, input_price: 0.8, output_price: 4.0, cache_write_price: 1.0, cache_read_price: 0.08 },
+    ModelInfo { name: "claude-3-5-sonnet", context_window: 200_000, max_output_tokens: 8192, input_price: 3.0, output_price: 15.0, cache_write_price: 3.75, cache_read_price: 0.3 },
+    ModelInfo { name: "claude-3-opus", context_window: 200_000, max_output_tokens: 4096, input_price: 15.0, output_price: 75.0, cache_write_price: 18.75, cache_read_price: 1.5 },
+    ModelInfo { name: "claude-3-haiku", context_window: 200_000, max_output_tokens: 4096, input_price: 0.25, output_price: 1.25, cache_write_price: 0.3, cache_read_price: 0.03 },
+    ModelInfo { name: "gpt-4o-mini", context_window: 128_000, max_output_tokens: 16_384, input_price: 0.15, output_price: 0.6, cache_write_price: 0.15, cache_read_price: 0.075 },
+    ModelInfo { name: "gpt-4o", context_window: 128_000, max_output_tokens: 16_384, input_price: 2.5, output_price: 10.0, cache_write_price: 2.5, cache_read_price: 1.25 },
+    ModelInfo { name: "o1-mini", context_window: 128_000, max_output_tokens: 65_536, input_price: 3.0, output_price: 12.0, cache_write_price: 3.0, cache_read_price: 1.5 },
+    ModelInfo { name: "o1", context_window: 200_000, max_output_tokens: 100_000, input_price: 15.0, output_price: 60.0, cache_write_price: 15.0, cache_read_price: 7.5 },
+    ModelInfo { name: "deepseek-chat", context_window: 64_000, max_output_tokens: 8192, input_price: 0.27, output_price: 1.1, cache_write_price: 0.27, cache_read_price: 0.07 },
+    ModelInfo { name: "deepseek-reasoner", context_window: 64_000, max_output_tokens: 8192, input_price: 0.55, output_price: 2.19, cache_write_price: 0.55, cache_read_price: 0.14 },
+];
+
+impl ModelInfo {
+    /// Cost in USD of a request, with cache writes and reads at their own prices.
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        let tokens = [
+            (usage.input_tokens, self.input_price),
+            (usage.cache_creation_input_tokens, self.cache_write_price),
+            (usage.cache_read_input_tokens, self.cache_read_price),
+            (usage.output_tokens, self.output_price),
+        ];
+        tokens.iter().map(|(count, price)| *count as f64 * price).sum::<f64>() / 1_000_000.0
+    }
+}
+
+pub fn lookup(model: &str) -> Option<&'static ModelInfo> {
+    MODELS.iter()
+        .filter(|info| model.starts_with(info.name) || model.contains(&format!(".{}", info.name)))
+        .max_by_key(|info| info.name.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_prefers_longest_prefix() {
+        assert_eq!(lookup("gpt-4o-mini-2024-07-18").unwrap().name, "gpt-4o-mini");
+        assert_eq!(lookup("gpt-4o").unwrap().name, "gpt-4o");
+        assert_eq!(lookup("anthropic.claude-3-5-haiku-20241022-v1:0").unwrap().name, "claude-3-5-haiku");
+        assert!(lookup("llama3").is_none());
+    }
+
+    #[test]
+    fn test_cost() {
+        let usage = Usage { input_tokens: 1_000_000, output_tokens: 100_000, ..Default::default() };
+        assert!((lookup("claude-3-5-sonnet-latest").unwrap().cost(&usage) - 4.5).abs() < 1e-9);
+        // A cached prompt of a million tokens, written once and read back
+        let write = Usage { cache_creation_input_tokens: 1_000_000, ..Default::default() };
+        let read = Usage { cache_read_input_tokens: 1_000_000, ..Default::default() };
+        assert!((lookup("claude-3-5-sonnet-latest").unwrap().cost(&write) - 3.75).abs() < 1e-9);
+        assert!((lookup("claude-3-5-sonnet-latest").unwrap().cost(&read) - 0.3).abs() < 1e-9);
+        assert!((lookup("gpt-4o").unwrap().cost(&read) - 1.25).abs() < 1e-9);
+    }
+}
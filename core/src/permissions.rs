@@ -0,0 +1,207 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::config::{BudgetConfig, CommandRule, CommandsConfig, ProjectConfig, SandboxConfig};
+use crate::egress;
+use crate::policy::CommandPolicy;
+
+/// The policy a repository ships, checked in under `.pprog`.
+pub const POLICY_FILE: &str = "policy.toml";
+
+// An allow list no host is on, for two lists with nothing in common.  `.invalid` is reserved
+// and never resolves.
+const NO_HOSTS: &str = "invalid";
+
+/// Rules added to the ones from pprog.toml, `defaults` only able to turn the shipped rules on.
+#[derive(Debug, Clone, Deserialize, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicyCommands {
+    pub defaults: Option<bool>,
+    pub deny: Vec<CommandRule>,
+    pub ask: Vec<CommandRule>,
+}
+
+/// Hosts the web tools may reach, only able to narrow what pprog.toml lets them.
+#[derive(Debug, Clone, Deserialize, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicyEgress {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    pub allow_private: Option<bool>,
+}
+
+/*
+    * What a repository lets the agent do, from `.pprog/policy.toml`, so it can ship safe
+    * defaults with the code.  It's applied on top of pprog.toml and can only take away: the
+    * tools are the ones both allow, the sandbox root the narrower of the two, rules and
+    * read-only paths are added and budgets are the lower of the two.  Unknown keys are an
+    * error rather than a rule silently not applied.
+*/
+#[derive(Debug, Clone, Deserialize, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectPolicy {
+    /// The only tools the model may call
+    pub tools: Option<Vec<String>>,
+    pub sandbox: SandboxConfig,
+    pub commands: PolicyCommands,
+    pub egress: PolicyEgress,
+    pub budget: BudgetConfig,
+}
+
+// The domains on both lists, the narrower one of each pair where one is within the other
+fn shared_hosts(user: &[String], policy: &[String]) -> Vec<String> {
+    let bare = |domain: &String| domain.trim_start_matches("*.").trim_end_matches('.').to_lowercase();
+    let mut hosts = Vec::new();
+    for (user, policy) in user.iter().map(bare).flat_map(|user| policy.iter().map(bare).map(move |policy| (user.clone(), policy))) {
+        let narrower = match (egress::matches(&policy, &user), egress::matches(&user, &policy)) {
+            (true, _) => policy,
+            (_, true) => user,
+            _ => continue,
+        };
+        if !hosts.contains(&narrower) {
+            hosts.push(narrower);
+        }
+    }
+    hosts
+}
+
+fn lower(user: Option<f64>, policy: Option<f64>) -> Option<f64> {
+    match (user, policy) {
+        (Some(user), Some(policy)) => Some(user.min(policy)),
+        (user, policy) => user.or(policy),
+    }
+}
+
+impl ProjectPolicy {
+    /// The policy of the repository at `git_root`, None when it ships none.
+    pub fn load(git_root: &Path) -> Result<Option<Self>, anyhow::Error> {
+        let path = git_root.join(ProjectConfig::DATA_DIR).join(POLICY_FILE);
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(anyhow::anyhow!("Failed to read {}: {}", path.display(), e)),
+        };
+        let policy: Self = toml::from_str(&content).map_err(|e| anyhow::anyhow!("Invalid project policy {}: {}", path.display(), e))?;
+        // Its rules compiled here, so the error names the file rather than the merged config
+        let commands = CommandsConfig { defaults: false, deny: policy.commands.deny.clone(), ask: policy.commands.ask.clone(), allow: Vec::new() };
+        CommandPolicy::new(&commands).map_err(|e| anyhow::anyhow!("Invalid project policy {}: {}", path.display(), e))?;
+        Ok(Some(policy))
+    }
+
+    /// Narrows `config` to what this policy allows, its paths relative to `git_root`.
+    pub fn apply(&self, config: &mut ProjectConfig, git_root: &Path) -> Result<(), anyhow::Error> {
+        config.tools = match (config.tools.take(), &self.tools) {
+            (Some(user), Some(policy)) => Some(user.into_iter().filter(|tool| policy.contains(tool)).collect()),
+            (user, policy) => user.or_else(|| policy.clone()),
+        };
+
+        let resolve = |path: &str| git_root.join(path).canonicalize().map_err(|e| anyhow::anyhow!("Invalid sandbox root {}: {}", path, e));
+        let mut root = match &config.sandbox.root {
+            Some(root) => resolve(root)?,
+            None => git_root.canonicalize()?,
+        };
+        if let Some(policy_root) = &self.sandbox.root {
+            let narrowed = resolve(policy_root)?;
+            if narrowed.starts_with(&root) {
+                config.sandbox.root = Some(narrowed.to_string_lossy().to_string());
+                root = narrowed;
+            } else if !root.starts_with(&narrowed) {
+                anyhow::bail!("The project policy's sandbox root {} is outside the sandbox {} of pprog.toml", policy_root, root.display());
+            }
+        }
+        // A read-only path outside the sandbox would open it for reading, only ones inside are kept
+        for mount in &self.sandbox.read_only {
+            match git_root.join(mount).canonicalize() {
+                Ok(mount) if mount.starts_with(&root) => config.sandbox.read_only.push(mount.to_string_lossy().to_string()),
+                Ok(_) => log::warn!("Ignoring read-only path {} of the project policy, it's outside the sandbox", mount),
+                // Checked in with the policy, one that isn't there is a typo that would leave the path writable
+                Err(e) => anyhow::bail!("Invalid read-only path {} of the project policy: {}", mount, e),
+            }
+        }
+
+        if self.commands.defaults == Some(true) {
+            config.commands.defaults = true;
+        }
+        config.commands.deny.extend(self.commands.deny.iter().cloned());
+        config.commands.ask.extend(self.commands.ask.iter().cloned());
+
+        config.egress.deny.extend(self.egress.deny.iter().cloned());
+        if !self.egress.allow.is_empty() {
+            config.egress.allow = match config.egress.allow.is_empty() {
+                true => self.egress.allow.clone(),
+                false => Some(shared_hosts(&config.egress.allow, &self.egress.allow))
+                    .filter(|hosts| !hosts.is_empty())
+                    .unwrap_or_else(|| vec![NO_HOSTS.to_string()]),
+            };
+        }
+        if self.egress.allow_private == Some(false) {
+            config.egress.allow_private = false;
+        }
+
+        config.budget.daily = lower(config.budget.daily, self.budget.daily);
+        config.budget.monthly = lower(config.budget.monthly, self.budget.monthly);
+        config.budget.require_force |= self.budget.require_force;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BudgetConfig, EgressConfig};
+
+    #[test]
+    fn test_apply() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let repo = dir.path().join("repo");
+        for path in ["app/src", "app/migrations", "vendor", "../outside"] {
+            std::fs::create_dir_all(repo.join(path))?;
+        }
+        std::fs::create_dir_all(repo.join(ProjectConfig::DATA_DIR))?;
+        std::fs::write(repo.join(ProjectConfig::DATA_DIR).join(POLICY_FILE), r#"
+            tools = ["read_file", "write_file", "execute"]
+
+            [sandbox]
+            root = "app"
+            read_only = ["app/migrations", "../outside"]
+
+            [commands]
+            deny = [{ pattern = "terraform\\s+destroy", reason = "Tears down infrastructure" }]
+
+            [egress]
+            allow = ["docs.rs", "api.github.com"]
+            allow_private = true
+
+            [budget]
+            daily = 5.0
+        "#)?;
+        let mut config = ProjectConfig {
+            tools: Some(vec!["read_file".to_string(), "execute".to_string(), "fetch_url".to_string()]),
+            egress: EgressConfig { allow: vec!["github.com".to_string()], deny: Vec::new(), allow_private: false },
+            budget: BudgetConfig { daily: Some(10.0), monthly: Some(100.0), require_force: false },
+            ..ProjectConfig::default()
+        };
+        let policy = ProjectPolicy::load(&repo)?.expect("the policy file is there");
+        policy.apply(&mut config, &repo)?;
+        let root = repo.canonicalize()?;
+
+        assert_eq!(config.tools, Some(vec!["read_file".to_string(), "execute".to_string()]));
+        assert_eq!(config.sandbox.root, Some(root.join("app").to_string_lossy().to_string()));
+        assert_eq!(config.sandbox.read_only, vec![root.join("app/migrations").to_string_lossy().to_string()]);
+        assert_eq!(config.commands.deny.len(), 1);
+        assert!(config.commands.defaults);
+        assert_eq!(config.egress.allow, vec!["api.github.com".to_string()]);
+        assert!(!config.egress.allow_private);
+        assert_eq!((config.budget.daily, config.budget.monthly), (Some(5.0), Some(100.0)));
+
+        // A policy can't widen the sandbox past the one of pprog.toml
+        let mut narrow = ProjectConfig { sandbox: SandboxConfig { root: Some("vendor".to_string()), read_only: Vec::new() }, ..ProjectConfig::default() };
+        assert!(policy.apply(&mut narrow, &repo).is_err());
+        let missing = ProjectPolicy { sandbox: SandboxConfig { root: None, read_only: vec!["app/migratoins".to_string()] }, ..Default::default() };
+        assert!(missing.apply(&mut ProjectConfig::default(), &repo).unwrap_err().to_string().contains("app/migratoins"));
+        assert_eq!(shared_hosts(&["docs.rs".to_string()], &["crates.io".to_string()]), Vec::<String>::new());
+        assert!(toml::from_str::<ProjectPolicy>("[comands]\ndeny = []").is_err());
+        Ok(())
+    }
+}
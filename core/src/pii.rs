@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::config::PiiConfig;
+use crate::inference::types::{ContentItem, Message, ModelResponse};
+
+/// Kinds of personal data always scrubbed, by placeholder name.
+pub const PATTERNS: &[(&str, &str)] = &[
+    ("EMAIL", r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}"),
+    ("PHONE", r"(?:\+\d{1,3}[ .-]?)?(?:\(\d{2,4}\)[ .-]?|\b\d{2,4}[ .-])\d{3,4}[ .-]\d{4}\b"),
+];
+
+// A placeholder as the model writes it back, `[EMAIL_3]`
+const PLACEHOLDER: &str = r"\[[A-Z][A-Z0-9_]*_[0-9]+\]";
+
+// The longest text held back in a stream as a placeholder's possible start
+const MAX_PLACEHOLDER_LEN: usize = 40;
+
+#[derive(Default)]
+struct Placeholders {
+    by_value: HashMap<String, String>,
+    by_placeholder: HashMap<String, String>,
+    counts: HashMap<String, usize>,
+}
+
+/*
+    * Swaps personal data for placeholders like `[EMAIL_1]` in what is sent to the provider,
+    * and the placeholders back for the data in what comes back, so files the model writes
+    * get the real values.  The same value gets the same placeholder for as long as this
+    * lives, so requests sent again still match the provider's prompt cache.
+*/
+pub struct Scrubber {
+    patterns: Vec<(String, Regex)>,
+    allow: Vec<Regex>,
+    placeholder: Regex,
+    placeholders: Mutex<Placeholders>,
+}
+
+impl Scrubber {
+    pub fn new(config: &PiiConfig) -> Result<Self, anyhow::Error> {
+        let compile = |pattern: &str| Regex::new(pattern).map_err(|e| anyhow::anyhow!("Invalid pii pattern {:?}: {}", pattern, e));
+        // A configured pattern named like a built-in one replaces it
+        let mut patterns = PATTERNS.iter()
+            .filter(|(name, _)| !config.patterns.keys().any(|configured| configured.eq_ignore_ascii_case(name)))
+            .map(|(name, pattern)| Ok((name.to_string(), compile(pattern)?)))
+            .collect::<Result<Vec<(String, Regex)>, anyhow::Error>>()?;
+        for (name, pattern) in &config.patterns {
+            patterns.push((name.to_uppercase(), compile(pattern)?));
+        }
+        let allow = config.allow.iter().map(|pattern| compile(pattern)).collect::<Result<Vec<Regex>, anyhow::Error>>()?;
+        Ok(Scrubber { patterns, allow, placeholder: compile(PLACEHOLDER)?, placeholders: Mutex::new(Placeholders::default()) })
+    }
+
+    /// `text` with the personal data in it replaced by placeholders.
+    pub fn scrub(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        let mut placeholders = self.placeholders.lock().unwrap();
+        for (name, regex) in &self.patterns {
+            text = regex.replace_all(&text, |captures: &regex::Captures| {
+                let value = &captures[0];
+                if self.allow.iter().any(|allow| allow.is_match(value)) {
+                    return value.to_string();
+                }
+                if let Some(placeholder) = placeholders.by_value.get(value) {
+                    return placeholder.clone();
+                }
+                let count = placeholders.counts.entry(name.clone()).or_insert(0);
+                *count += 1;
+                let placeholder = format!("[{}_{}]", name, count);
+                placeholders.by_value.insert(value.to_string(), placeholder.clone());
+                placeholders.by_placeholder.insert(placeholder.clone(), value.to_string());
+                placeholder
+            }).into_owned();
+        }
+        text
+    }
+
+    /// `text` with the placeholders handed out so far replaced by their values.
+    pub fn restore(&self, text: &str) -> String {
+        let placeholders = self.placeholders.lock().unwrap();
+        self.placeholder.replace_all(text, |captures: &regex::Captures| {
+            placeholders.by_placeholder.get(&captures[0]).cloned().unwrap_or_else(|| captures[0].to_string())
+        }).into_owned()
+    }
+
+    // Applies `rewrite` to every string in a tool input
+    fn rewrite_value(value: &mut Value, rewrite: &impl Fn(&str) -> String) {
+        match value {
+            Value::String(text) => *text = rewrite(text),
+            Value::Array(items) => items.iter_mut().for_each(|item| Self::rewrite_value(item, rewrite)),
+            Value::Object(fields) => fields.values_mut().for_each(|field| Self::rewrite_value(field, rewrite)),
+            _ => {},
+        }
+    }
+
+    fn rewrite_content(content: &mut [ContentItem], rewrite: impl Fn(&str) -> String) {
+        for item in content {
+            match item {
+                ContentItem::Text { text } => *text = rewrite(text),
+                ContentItem::ToolResult { content, .. } => *content = rewrite(content),
+                ContentItem::ToolUse { input, .. } => Self::rewrite_value(input, &rewrite),
+                ContentItem::Image { .. } | ContentItem::Unknown(_) => {},
+            }
+        }
+    }
+
+    /// The messages and system prompt of a request, scrubbed.
+    pub fn scrub_request(&self, mut messages: Vec<Message>, system_message: Option<&str>) -> (Vec<Message>, Option<String>) {
+        for message in &mut messages {
+            Self::rewrite_content(&mut message.content, |text| self.scrub(text));
+        }
+        (messages, system_message.map(|system| self.scrub(system)))
+    }
+
+    /// `content` of a reply with the values put back.
+    pub fn restore_content(&self, content: &mut [ContentItem]) {
+        Self::rewrite_content(content, |text| self.restore(text));
+    }
+
+    pub fn restore_response(&self, mut response: ModelResponse) -> ModelResponse {
+        self.restore_content(&mut response.content);
+        response
+    }
+
+    /// Where the end of streamed `text` could be the start of a placeholder still coming,
+    /// the length of `text` when it can't.
+    pub fn held_from(text: &str) -> usize {
+        let Some(start) = text.rfind('[') else { return text.len() };
+        let rest = &text[start + 1..];
+        let partial = rest.len() < MAX_PLACEHOLDER_LEN && rest.bytes().all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit() || byte == b'_');
+        match partial {
+            true => start,
+            false => text.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inference::types::Role;
+
+    #[test]
+    fn test_scrub_and_restore() -> Result<(), anyhow::Error> {
+        let scrubber = Scrubber::new(&PiiConfig { allow: vec![r"@example\.com$".to_string()], ..Default::default() })?;
+        let text = "Mail ana@acme.io or call +1 415-555-0132, (415) 555-0199.  Not bob@example.com, 2026-10-14 or v1.2.3.";
+        let scrubbed = scrubber.scrub(text);
+        assert_eq!(scrubbed, "Mail [EMAIL_1] or call [PHONE_1], [PHONE_2].  Not bob@example.com, 2026-10-14 or v1.2.3.");
+        assert_eq!(scrubber.scrub("cc ana@acme.io"), "cc [EMAIL_1]");
+        assert_eq!(scrubber.restore(&scrubbed), text);
+        assert_eq!(scrubber.restore("[EMAIL_9] and [TODO]"), "[EMAIL_9] and [TODO]");
+
+        let history = vec![
+            Message { role: Role::User, content: vec![ContentItem::ToolResult { tool_use_id: "t1".to_string(), content: "owner: ana@acme.io".to_string(), is_error: false }] },
+            Message { role: Role::Assistant, content: vec![ContentItem::ToolUse { id: "t2".to_string(), name: "write_file".to_string(), input: serde_json::json!({"path": "a.txt", "content": "ana@acme.io"}) }] },
+        ];
+        let (messages, system) = scrubber.scrub_request(history, Some("Users: zoe@acme.io"));
+        assert_eq!(system.as_deref(), Some("Users: [EMAIL_2]"));
+        assert_eq!(messages[0].content[0], ContentItem::ToolResult { tool_use_id: "t1".to_string(), content: "owner: [EMAIL_1]".to_string(), is_error: false });
+        let mut reply = messages[1].content.clone();
+        scrubber.restore_content(&mut reply);
+        assert!(matches!(&reply[0], ContentItem::ToolUse { input, .. } if input["content"] == "ana@acme.io"));
+
+        assert_eq!(Scrubber::held_from("write to [EMA"), 9);
+        assert_eq!(Scrubber::held_from("write to [EMAIL_1]"), 18);
+        assert_eq!(Scrubber::held_from("items[i"), 7);
+
+        let custom = Scrubber::new(&PiiConfig { patterns: [("customer".to_string(), r"CUS-[0-9]{6}".to_string())].into(), ..Default::default() })?;
+        assert_eq!(custom.scrub("CUS-004211 at ana@acme.io"), "[CUSTOMER_1] at [EMAIL_1]");
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+/// The tool the model proposes its plan with.
+pub const PLAN_TOOL: &str = "plan";
+
+/// Added to the system prompt while a plan is being drafted.
+pub const PLAN_PROMPT: &str = r#"
+You are in plan mode.  Before reading, writing or running anything, call the `plan` tool once with a numbered list of the steps you will take, then stop and wait.  Other tools are refused until the user approves the plan.  If the user asks for changes, propose the revised plan with the `plan` tool again.
+"#;
+
+/// Where plan mode is for the current run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum PlanState {
+    #[default]
+    Off,
+    /// Waiting for the model to propose a plan, other tools are refused
+    Drafting,
+    /// A plan was proposed and waits for the user to approve or change it
+    Proposed(String),
+    /// The plan was approved and tools run as usual
+    Approved,
+}
+
+/// The plan with its steps numbered 1, 2, … whether or not the model numbered them.
+pub fn numbered(plan: &str) -> String {
+    plan.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .enumerate()
+        .map(|(idx, line)| {
+            let step = line.trim_start_matches(|c: char| c.is_ascii_digit())
+                .strip_prefix(['.', ')'])
+                .filter(|_| line.starts_with(|c: char| c.is_ascii_digit()))
+                .or_else(|| line.strip_prefix("- "))
+                .unwrap_or(line);
+            format!("{}. {}", idx + 1, step.trim())
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// The user message that approves `plan`, pinned so it stays in context for the rest of the run.
+pub fn approval_message(plan: &str) -> String {
+    format!("[Approved plan]\n{}\n\nCarry out this plan step by step.", numbered(plan))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbered_plan() {
+        let plan = "1. Read src/main.rs\n\n2) Add the flag\n- Run cargo test\nUpdate 3 docs";
+        assert_eq!(numbered(plan), "1. Read src/main.rs\n2. Add the flag\n3. Run cargo test\n4. Update 3 docs");
+        assert!(approval_message("12 files").starts_with("[Approved plan]\n1. 12 files\n"));
+    }
+}
@@ -0,0 +1,99 @@
+use std::time::{Duration, Instant};
+
+use tokio::task::JoinHandle;
+
+use crate::config::ProjectConfig;
+use crate::memory::ProjectMemory;
+use crate::prompt::PromptTemplate;
+use crate::repomap;
+use crate::tokens::TokenCounter;
+use crate::tree::GitTree;
+
+// Context gathered longer ago than this is gathered again, the user may have changed files since
+const MAX_AGE: Duration = Duration::from_secs(300);
+
+/// The parts of the system prompt gathered ahead of the first message.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Warm {
+    pub repo_map: String,
+    pub tree: String,
+    pub git_status: String,
+    pub memory: String,
+}
+
+/*
+    * Builds the repository map, the file tree and the git status in the background while the
+    * user types their first message, which on a large monorepo can take seconds the first
+    * request would otherwise wait for.  The token counter of the session is loaded there too,
+    * and run once over the project instructions so its first real count isn't a cold one.
+    * What was gathered is used for the first message only, later ones build their context
+    * fresh since the agent changes files as it works.
+*/
+pub struct Prefetch {
+    started: Instant,
+    task: JoinHandle<Result<(Warm, TokenCounter), anyhow::Error>>,
+}
+
+impl Prefetch {
+    pub fn start(config: &ProjectConfig, template: &PromptTemplate) -> Self {
+        let ignore = config.ignore.clone();
+        let repo_map_tokens = config.repo_map_tokens;
+        let model = config.model.clone();
+        let instructions = template.source().to_string();
+        let task = tokio::task::spawn_blocking(move || {
+            let counter = TokenCounter::for_model(&model);
+            let memory = ProjectMemory::load().unwrap_or_default();
+            counter.count(&instructions);
+            counter.count(&memory);
+            let repo_map = match repo_map_tokens {
+                0 => String::new(),
+                budget => repomap::build(&ignore, budget, |text| counter.count(text))?,
+            };
+            let warm = Warm {
+                repo_map,
+                tree: GitTree::get_tree(&ignore)?,
+                git_status: GitTree::status_summary().unwrap_or_default(),
+                memory,
+            };
+            Ok((warm, counter))
+        });
+        Prefetch { started: Instant::now(), task }
+    }
+
+    /// What was gathered, waiting for it if it isn't done yet, None when it failed or is too old.
+    pub async fn finish(self) -> Option<(Warm, TokenCounter)> {
+        if self.started.elapsed() > MAX_AGE {
+            self.task.abort();
+            return None;
+        }
+        match self.task.await {
+            Ok(Ok(warm)) => Some(warm),
+            Ok(Err(e)) => {
+                log::warn!("Prefetching the workspace context failed, building it now: {}", e);
+                None
+            },
+            Err(e) => {
+                log::warn!("Prefetching the workspace context stopped: {}", e);
+                None
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_prefetch() {
+        let config = ProjectConfig { repo_map_tokens: 0, ..ProjectConfig::default() };
+        let (warm, _) = Prefetch::start(&config, &PromptTemplate::default()).finish().await.expect("run inside the repository");
+        assert!(warm.tree.contains("Cargo.toml"));
+        assert_eq!(warm.repo_map, "");
+
+        if let Some(started) = Instant::now().checked_sub(MAX_AGE * 2) {
+            let stale = Prefetch { started, ..Prefetch::start(&config, &PromptTemplate::default()) };
+            assert!(stale.finish().await.is_none());
+        }
+    }
+}
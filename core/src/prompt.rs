@@ -0,0 +1,239 @@
+use std::fs;
+
+use handlebars::Handlebars;
+use serde::Serialize;
+
+use crate::config::ProjectConfig;
+
+const PROMPTS_DIR: &str = "prompts";
+pub const DEFAULT_TEMPLATE_NAME: &str = "default";
+
+const DEFAULT_TEMPLATE: &str = r#"You are a coding assistant working on a project.
+
+The user will give you instructions on how to change the project code.
+
+Always call 'compile_check' tool after completing changes that the user requests.  If compile_check shows any errors, make subsequent calls to correct the errors. Continue checking and rewriting until there are no more errors.  If there are warnings then do not try to fix them, just let the user know.  If any bash commands are needed like installing packages use tool 'execute'.
+
+Never make any changes outside of the project's root directory.
+Always read and write entire file contents.  Never write partial contents of a file.
+
+When you learn something about the project that will matter in future sessions, like a build quirk or a convention, save it with the 'remember' tool.
+
+The user may also general questions and in that case simply answer but do not execute any tools.
+{{#if memory}}
+
+Project memory, notes saved in earlier sessions:
+{{memory}}
+{{/if}}
+{{cache_break}}
+File tree structure:
+{{tree}}
+{{#if repo_map}}
+Repository map, the public declarations of each file:
+{{repo_map}}
+{{/if}}
+{{#if docs}}
+Excerpts of the project docs that may relate to the request.  Cite the path of any you rely on:
+{{docs}}
+{{/if}}
+{{#if git_status}}
+Git: {{git_status}}
+{{/if}}"#;
+
+/// Values a template can reference as `{{name}}`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PromptVars {
+    pub tree: String,
+    pub repo_map: String,
+    pub docs: String,
+    pub git_status: String,
+    pub memory: String,
+    pub project: String,
+    pub model: String,
+    pub check_cmd: String,
+    pub date: String,
+    /// Ends the part of the prompt that stays the same, cached by providers that are told where
+    pub cache_break: String,
+}
+
+/*
+    * A system prompt template, read from `.pprog/prompts/<name>.md`.  Templates use
+    * handlebars syntax and are rendered in strict mode so a misspelled variable fails
+    * loudly instead of silently sending an empty section to the model.  The default
+    * template is built in but can be overridden by a `default.md` in the prompts directory.
+*/
+#[derive(Debug, Clone)]
+pub struct PromptTemplate {
+    pub name: String,
+    source: String,
+}
+
+impl Default for PromptTemplate {
+    fn default() -> Self {
+        PromptTemplate {
+            name: DEFAULT_TEMPLATE_NAME.to_string(),
+            source: DEFAULT_TEMPLATE.to_string(),
+        }
+    }
+}
+
+impl PromptTemplate {
+    pub fn load(name: &str) -> Result<Self, anyhow::Error> {
+        let path = ProjectConfig::data_dir()?.join(PROMPTS_DIR).join(format!("{}.md", name));
+        match fs::read_to_string(&path) {
+            Ok(source) => {
+                let template = PromptTemplate { name: name.to_string(), source };
+                // Surface syntax errors and unknown variables at startup, not on the first message
+                template.render(&PromptVars::default())?;
+                Ok(template)
+            },
+            Err(_) if name == DEFAULT_TEMPLATE_NAME => Ok(Self::default()),
+            Err(e) => Err(anyhow::anyhow!("Prompt template {:?} not found at {:?}: {}", name, path, e)),
+        }
+    }
+
+    /// The template before rendering.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn render(&self, vars: &PromptVars) -> Result<String, anyhow::Error> {
+        let mut hbs = Handlebars::new();
+        hbs.set_strict_mode(true);
+        hbs.register_escape_fn(handlebars::no_escape);
+        hbs.render_template(&self.source, vars)
+            .map_err(|e| anyhow::anyhow!("Failed to render prompt template {:?}: {}", self.name, e))
+    }
+}
+
+// A line long enough and prose-like enough to be an instruction, so reading it twice is
+// waste.  Short lines and code, like a repeated `fn new() -> Self` in the repository map,
+// are kept wherever they appear.
+fn is_instruction(line: &str) -> bool {
+    line.split([' ', '\t']).filter(|word| !word.is_empty()).count() >= 5
+        && !line.contains(['(', ')', '{', '}', '<', '>', ';', '='])
+}
+
+// Markdown that only decorates: a heading's hashes and bold markers
+fn undecorate(line: &str) -> String {
+    let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+    let text = &line[indent..];
+    let text = match text.trim_start_matches('#') {
+        rest if rest.len() < text.len() && text.len() - rest.len() <= 6 && rest.starts_with(' ') => rest.trim_start_matches(' '),
+        _ => text,
+    };
+    format!("{}{}", &line[..indent], text.replace("**", "").replace("__", ""))
+}
+
+/*
+    * The system prompt with what costs tokens without telling the model anything taken out,
+    * for `compress_prompt`: lines repeating an earlier instruction word for word, heading
+    * and bold markup, horizontal rules and HTML comments, runs of spaces and blank lines, and
+    * trailing whitespace.  Fenced code blocks are left as they are, and so is the
+    * indentation lines start with, which the file tree needs.
+*/
+pub fn compress(prompt: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut seen = std::collections::HashSet::new();
+    let mut fenced = false;
+    for line in prompt.lines() {
+        let trimmed = line.trim_matches([' ', '\t']);
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fenced = !fenced;
+            lines.push(line.trim_end_matches([' ', '\t']).to_string());
+            continue;
+        }
+        if fenced {
+            lines.push(line.to_string());
+            continue;
+        }
+        let rule = trimmed.len() >= 3 && ['-', '*', '_'].iter().any(|mark| trimmed.chars().all(|c| c == *mark));
+        let comment = trimmed.starts_with("<!--") && trimmed.ends_with("-->");
+        if rule || comment {
+            continue;
+        }
+        if trimmed.is_empty() {
+            if lines.last().is_some_and(|last| !last.is_empty()) {
+                lines.push(String::new());
+            }
+            continue;
+        }
+        let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+        let words = trimmed.split([' ', '\t']).filter(|word| !word.is_empty()).collect::<Vec<&str>>().join(" ");
+        let line = undecorate(&format!("{}{}", &line[..indent], words));
+        let text = line.trim_start_matches([' ', '\t']);
+        if is_instruction(text) && !seen.insert(text.to_lowercase()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    let mut compressed = lines.join("\n");
+    if prompt.ends_with('\n') {
+        compressed.push('\n');
+    }
+    compressed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_substitutes_variables() -> Result<(), anyhow::Error> {
+        let template = PromptTemplate { name: "review".to_string(), source: "Review {{project}} using {{check_cmd}}.".to_string() };
+        let vars = PromptVars { project: "pprog".to_string(), check_cmd: "cargo check".to_string(), ..PromptVars::default() };
+        assert_eq!(template.render(&vars)?, "Review pprog using cargo check.");
+
+        let memory = PromptVars { memory: "- uses <T> generics".to_string(), cache_break: "|".to_string(), ..PromptVars::default() };
+        let rendered = PromptTemplate::default().render(&memory)?;
+        let (stable, volatile) = rendered.split_once('|').unwrap();
+        assert!(stable.ends_with("earlier sessions:\n- uses <T> generics\n"));
+        assert!(volatile.starts_with("\nFile tree structure:"));
+        assert!(!PromptTemplate::default().render(&PromptVars::default())?.contains("Project memory"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_compress() {
+        let prompt = [
+            "# Project rules",
+            "",
+            "",
+            "Always run the tests before you commit   **anything**.",
+            "---",
+            "<!-- generated by the docs team -->",
+            "## Testing",
+            "always run the tests before you commit anything.",
+            "\u{1d}",
+            "File tree structure:",
+            "src/",
+            "    main.rs   ",
+            "```toml",
+            "[deps]   # kept",
+            "```",
+            "pub fn new() -> Self",
+            "pub fn new() -> Self",
+        ].join("\n");
+        assert_eq!(compress(&prompt), [
+            "Project rules",
+            "",
+            "Always run the tests before you commit anything.",
+            "Testing",
+            "\u{1d}",
+            "File tree structure:",
+            "src/",
+            "    main.rs",
+            "```toml",
+            "[deps]   # kept",
+            "```",
+            "pub fn new() -> Self",
+            "pub fn new() -> Self",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn test_render_rejects_unknown_variables() {
+        let template = PromptTemplate { name: "typo".to_string(), source: "{{memroy}}".to_string() };
+        assert!(template.render(&PromptVars::default()).is_err());
+    }
+}
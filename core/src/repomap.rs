@@ -0,0 +1,103 @@
+use std::path::Path;
+
+use crate::tree::GitTree;
+
+// Files larger than this are likely generated or vendored, and not scanned
+const MAX_FILE_BYTES: u64 = 200_000;
+
+// Longer signatures are cut, the model can read the file for the rest
+const MAX_SIGNATURE_CHARS: usize = 120;
+
+// Declarations of public items by file extension, matched against trimmed lines
+fn declaration_prefixes(extension: &str) -> &'static [&'static str] {
+    match extension {
+        "rs" => &["pub fn ", "pub async fn ", "pub unsafe fn ", "pub const fn ", "pub struct ", "pub enum ", "pub trait ", "pub type ", "pub mod ", "impl ", "impl<"],
+        "py" => &["def ", "async def ", "class "],
+        "js" | "jsx" | "mjs" | "ts" | "tsx" => &["export ", "function ", "async function ", "class "],
+        "go" => &["func ", "type "],
+        "java" | "kt" | "cs" => &["public "],
+        _ => &[],
+    }
+}
+
+/*
+    * The public declarations of one file, one signature line each.  This is a scan of lines
+    * rather than a parse: a line counts when, indented at most one level, it starts with a
+    * declaration keyword of the language.  Python names starting with `_` are private and
+    * skipped, bodies and trailing braces are dropped.
+*/
+pub fn symbols(path: &str, source: &str) -> Vec<String> {
+    let extension = Path::new(path).extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    let prefixes = declaration_prefixes(extension);
+    if prefixes.is_empty() {
+        return Vec::new();
+    }
+    source.lines()
+        .filter(|line| line.len() - line.trim_start().len() <= 4)
+        .map(|line| line.trim())
+        .filter(|line| prefixes.iter().any(|prefix| line.starts_with(prefix)))
+        .filter(|line| extension != "py" || !line.split_whitespace().nth(1).is_some_and(|name| name.starts_with('_') && !name.starts_with("__init__")))
+        .map(|line| {
+            let signature = line.trim_end_matches(['{', ':', ' ']).trim_end();
+            match signature.char_indices().nth(MAX_SIGNATURE_CHARS) {
+                Some((cut, _)) => format!("{}…", &signature[..cut]),
+                None => signature.to_string(),
+            }
+        })
+        .collect()
+}
+
+/// Lists `files` with their symbols, shallow paths first, stopping before the map would
+/// take more than `budget` tokens by `count`.
+pub fn render(files: &[(String, Vec<String>)], budget: usize, count: impl Fn(&str) -> usize) -> String {
+    let mut files = files.iter().filter(|(_, symbols)| !symbols.is_empty()).collect::<Vec<_>>();
+    files.sort_by_key(|(path, _)| (path.matches('/').count(), path.clone()));
+    let mut map = String::new();
+    let mut used = 0;
+    for (shown, (path, symbols)) in files.iter().enumerate() {
+        let entry = format!("{}\n{}\n", path, symbols.iter().map(|symbol| format!("  {}", symbol)).collect::<Vec<String>>().join("\n"));
+        used += count(&entry);
+        if used > budget {
+            map.push_str(&format!("… {} more files\n", files.len() - shown));
+            break;
+        }
+        map.push_str(&entry);
+    }
+    map
+}
+
+/// The map of the workspace files not in `ignore`, see `render`.
+pub fn build(ignore: &[String], budget: usize, count: impl Fn(&str) -> usize) -> Result<String, anyhow::Error> {
+    let root = GitTree::get_git_root()?;
+    let files = GitTree::workspace_files(ignore)?.into_iter()
+        .filter(|path| !declaration_prefixes(Path::new(path).extension().and_then(|ext| ext.to_str()).unwrap_or_default()).is_empty())
+        .filter(|path| std::fs::metadata(root.join(path)).is_ok_and(|meta| meta.len() <= MAX_FILE_BYTES))
+        .filter_map(|path| {
+            let source = std::fs::read_to_string(root.join(&path)).ok()?;
+            let symbols = symbols(&path, &source);
+            Some((path, symbols))
+        })
+        .collect::<Vec<_>>();
+    Ok(render(&files, budget, count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbols_and_budget() {
+        let rust = "pub struct Chat {\n    id: String,\n}\n\nimpl Chat {\n    pub fn new() -> Self {\n        fn helper() {}\n    }\n}\nfn private() {}\n";
+        assert_eq!(symbols("src/chat.rs", rust), vec!["pub struct Chat", "impl Chat", "pub fn new() -> Self"]);
+        let python = "class Store:\n    def __init__(self):\n        pass\n    def _load(self):\n        pass\n    def save(self, path):\n";
+        assert_eq!(symbols("store.py", python), vec!["class Store", "def __init__(self)", "def save(self, path)"]);
+        assert!(symbols("README.md", "pub fn nope()").is_empty());
+
+        let files = vec![
+            ("src/deep/b.rs".to_string(), vec!["pub fn b()".to_string()]),
+            ("a.rs".to_string(), vec!["pub fn a()".to_string()]),
+        ];
+        assert_eq!(render(&files, 100, |text| text.len()), "a.rs\n  pub fn a()\nsrc/deep/b.rs\n  pub fn b()\n");
+        assert_eq!(render(&files, 20, |text| text.len()), "a.rs\n  pub fn a()\n… 1 more files\n");
+    }
+}
@@ -0,0 +1,71 @@
+use crate::config::ProjectConfig;
+use crate::inference::types::{ContentItem, ModelResponse};
+
+// What the small model answers with to hand the message over
+const ESCALATE: &str = "ESCALATE";
+
+/// Added to the system prompt of the small model when it gets the first try at a message.
+pub const CHEAP_PROMPT: &str = "\nYou are the fast first responder.  Answer directly if the message is a simple question you can answer \
+correctly and with confidence.  If it needs tools, changes to files, careful reasoning, or you are unsure, reply with \
+ESCALATE alone and nothing else, and a stronger model will take over.\n";
+
+/// Whether the small model gets the first try at a message whose request takes `tokens`.
+pub fn tries_cheap(config: &ProjectConfig, tokens: usize) -> bool {
+    config.cheap_first
+        && !config.small_model.is_empty()
+        && config.small_model != config.model
+        && tokens <= config.cheap_first_max_tokens
+}
+
+/// Whether the small model's reply can stand, rather than being handed to `model`.  Calling
+/// a tool counts as escalating, the tool loop is left to the stronger model.
+pub fn accepts(response: &ModelResponse) -> bool {
+    let mut text = String::new();
+    for item in &response.content {
+        match item {
+            ContentItem::Text { text: part } => text.push_str(part),
+            ContentItem::ToolUse { .. } => return false,
+            _ => {},
+        }
+    }
+    let text = text.trim();
+    !text.is_empty() && !text.trim_matches(['*', '`', '.']).starts_with(ESCALATE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inference::types::{ResponseType, Role, StopReason};
+
+    fn response(content: Vec<ContentItem>) -> ModelResponse {
+        ModelResponse {
+            content,
+            id: "msg_1".to_string(),
+            model: "small".to_string(),
+            role: Role::Assistant,
+            message_type: ResponseType::Message,
+            stop_reason: StopReason::EndTurn,
+            stop_sequence: None,
+            usage: None,
+            raw: None,
+            timing: None,
+        }
+    }
+
+    #[test]
+    fn test_accepts() {
+        let text = |text: &str| ContentItem::Text { text: text.to_string() };
+        assert!(accepts(&response(vec![text("`Vec::with_capacity` preallocates.")])));
+        assert!(!accepts(&response(vec![text("ESCALATE")])));
+        assert!(!accepts(&response(vec![text("**ESCALATE**\n")])));
+        assert!(!accepts(&response(vec![text("")])));
+        let tool = ContentItem::ToolUse { id: "t1".to_string(), name: "read_file".to_string(), input: serde_json::json!({}) };
+        assert!(!accepts(&response(vec![text("Let me look."), tool])));
+
+        let mut config = ProjectConfig { cheap_first: true, small_model: "small".to_string(), ..Default::default() };
+        assert!(tries_cheap(&config, 1000));
+        assert!(!tries_cheap(&config, config.cheap_first_max_tokens + 1));
+        config.small_model = config.model.clone();
+        assert!(!tries_cheap(&config, 1000));
+    }
+}
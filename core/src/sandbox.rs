@@ -0,0 +1,157 @@
+use std::path::{Component, Path, PathBuf};
+
+use crate::config::{ProjectConfig, SandboxConfig};
+
+/*
+    * Where the file tools may reach.  Paths the model gives are resolved against `root`, with
+    * symlinks followed, and refused when they end up outside it, so neither `..` nor a link
+    * pointing elsewhere gets a tool out.  Files under `read_only` can be read but not written,
+    * and neither can pprog's own config and state: a write there could loosen the policy or
+    * plant an `auth.key_cmd` that runs on the next start.
+*/
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    root: PathBuf,
+    read_only: Vec<PathBuf>,
+}
+
+// `path` with every part that exists resolved, symlinks included.  The parts that don't exist
+// yet are kept as they are, refusing `..` among them since nothing tells where it leads, and
+// refusing links to nothing since they lead wherever their target will be.
+fn resolve(path: &Path) -> Result<PathBuf, anyhow::Error> {
+    let mut existing = path;
+    let mut missing = Vec::new();
+    loop {
+        match existing.canonicalize() {
+            Ok(resolved) => {
+                let mut resolved = resolved;
+                for part in missing.iter().rev() {
+                    match part {
+                        Component::Normal(name) => resolved.push(name),
+                        Component::CurDir => {},
+                        _ => anyhow::bail!("Path {} goes through a directory that doesn't exist", path.display()),
+                    }
+                }
+                return Ok(resolved);
+            },
+            Err(_) => {
+                // A dangling link isn't missing, writing to it would create its target wherever it points
+                if existing.symlink_metadata().is_ok_and(|meta| meta.file_type().is_symlink()) {
+                    anyhow::bail!("Path {} goes through a symlink whose target doesn't exist", path.display());
+                }
+                let (Some(parent), Some(last)) = (existing.parent(), existing.components().next_back()) else {
+                    anyhow::bail!("Path {} doesn't exist", path.display());
+                };
+                missing.push(last);
+                existing = parent;
+            },
+        }
+    }
+}
+
+impl Sandbox {
+    /// The sandbox of `config`, its paths relative to `git_root` unless absolute.
+    pub fn new(git_root: &Path, config: &SandboxConfig) -> Result<Self, anyhow::Error> {
+        let root = match &config.root {
+            Some(root) => git_root.join(root).canonicalize().map_err(|e| anyhow::anyhow!("Invalid sandbox root {}: {}", root, e))?,
+            None => git_root.canonicalize()?,
+        };
+        // A mount that doesn't exist yet is kept as written, or the path would be writable once created
+        let mut read_only = config.read_only.iter()
+            .map(|mount| resolve(&git_root.join(mount)).map_err(|e| anyhow::anyhow!("Invalid read-only path {}: {}", mount, e)))
+            .collect::<Result<Vec<PathBuf>, anyhow::Error>>()?;
+        // Kept out of reach whether they exist yet or not
+        for path in [ProjectConfig::DATA_DIR, ProjectConfig::CONFIG_FILE] {
+            read_only.push(resolve(&git_root.join(path))?);
+        }
+        Ok(Sandbox { root, read_only })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Where `path` leads, when it's in the sandbox or one of the read-only mounts.
+    pub fn resolve_read(&self, path: &str) -> Result<PathBuf, anyhow::Error> {
+        let resolved = resolve(&self.root.join(path))?;
+        if self.readable(&resolved) {
+            return Ok(resolved);
+        }
+        Err(anyhow::anyhow!("Path {} is outside the workspace {}", path, self.root.display()))
+    }
+
+    /// Whether the file tools could read `path`, for tools that reach files some other way.
+    pub fn can_read(&self, path: &Path) -> bool {
+        resolve(&self.root.join(path)).is_ok_and(|resolved| self.readable(&resolved))
+    }
+
+    fn readable(&self, resolved: &Path) -> bool {
+        resolved.starts_with(&self.root) || self.read_only.iter().any(|mount| resolved.starts_with(mount))
+    }
+
+    /// Where `path` leads, when it's in the sandbox and not under a read-only mount.
+    pub fn resolve_write(&self, path: &str) -> Result<PathBuf, anyhow::Error> {
+        let resolved = resolve(&self.root.join(path))?;
+        if !resolved.starts_with(&self.root) {
+            return Err(anyhow::anyhow!("Path {} is outside the workspace {}", path, self.root.display()));
+        }
+        if self.read_only.iter().any(|mount| resolved.starts_with(mount)) {
+            return Err(anyhow::anyhow!("Path {} is read-only", path));
+        }
+        Ok(resolved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sandbox() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let (repo, outside) = (dir.path().join("repo"), dir.path().join("outside"));
+        std::fs::create_dir_all(repo.join("src"))?;
+        std::fs::create_dir_all(repo.join("vendor"))?;
+        std::fs::create_dir_all(&outside)?;
+        std::fs::write(outside.join("notes.txt"), "")?;
+        std::fs::write(repo.join("src/lib.rs"), "")?;
+        std::os::unix::fs::symlink(&outside, repo.join("src/escape"))?;
+        std::os::unix::fs::symlink(outside.join("bashrc"), repo.join("src/dangling"))?;
+        let config = SandboxConfig { root: None, read_only: vec!["vendor".to_string(), "../outside".to_string(), "generated".to_string()] };
+        let sandbox = Sandbox::new(&repo, &config)?;
+        let root = repo.canonicalize()?;
+
+        assert_eq!(sandbox.resolve_write("src/lib.rs")?, root.join("src/lib.rs"));
+        assert_eq!(sandbox.resolve_write("src/../src/new/mod.rs")?, root.join("src/new/mod.rs"));
+        assert!(sandbox.resolve_write("src/new/../../../x").is_err());
+        assert!(sandbox.resolve_write("../outside/notes.txt").is_err());
+        assert!(sandbox.resolve_write("src/escape/notes.txt").is_err());
+        assert!(sandbox.resolve_write("src/dangling").is_err());
+        assert!(sandbox.resolve_write("src/dangling/new.txt").is_err());
+        assert!(sandbox.resolve_write(outside.join("new.txt").to_str().unwrap()).is_err());
+        assert!(sandbox.resolve_write("vendor/a.rs").is_err());
+        // A mount missing on this machine can't be created and written
+        assert!(sandbox.resolve_write("generated/schema.rs").is_err());
+        assert!(sandbox.resolve_write("./src/../generated").is_err());
+        assert_eq!(sandbox.resolve_read("src/escape/notes.txt")?, outside.canonicalize()?.join("notes.txt"));
+        assert!(sandbox.resolve_read("vendor/a.rs").is_ok());
+        // pprog.toml doesn't exist and can't be created, and the policy can be read but not changed
+        std::fs::create_dir_all(repo.join(".pprog"))?;
+        std::fs::write(repo.join(".pprog/policy.toml"), "")?;
+        let sandbox = Sandbox::new(&repo, &config)?;
+        assert!(sandbox.resolve_write("pprog.toml").is_err());
+        assert!(sandbox.resolve_write("./src/../pprog.toml").is_err());
+        assert!(sandbox.resolve_write(".pprog/policy.toml").is_err());
+        assert!(sandbox.resolve_write(".pprog/new.toml").is_err());
+        assert!(sandbox.resolve_read(".pprog/policy.toml").is_ok());
+
+        let narrow = Sandbox::new(&repo, &SandboxConfig { root: Some("src".to_string()), read_only: Vec::new() })?;
+        assert!(narrow.resolve_read("lib.rs").is_ok());
+        assert!(narrow.resolve_read("../vendor").is_err());
+        assert!(narrow.resolve_read("escape/notes.txt").is_err());
+        assert!(narrow.can_read(&repo.join("src/lib.rs")) && !narrow.can_read(&repo.join("vendor")) && !narrow.can_read(&repo.join("src/escape/notes.txt")));
+        let through_missing = SandboxConfig { root: None, read_only: vec!["missing/../vendor".to_string()] };
+        assert!(Sandbox::new(&repo, &through_missing).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::BTreeSet;
+
+use chrono::{DateTime, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::context::ContextManager;
+use crate::inference::types::{ContentItem, Message, Sampling};
+use crate::store::{SessionSummary, Store};
+use crate::todo::TodoItem;
+
+/// A single conversation, persisted in the project store so it can be exported or
+/// resumed after the server is restarted.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Session {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forked_from: Option<ForkOrigin>,
+    #[serde(default)]
+    pub messages: Vec<Message>,
+    /// Indices of messages the context manager and compactor must keep verbatim
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub pinned: BTreeSet<usize>,
+    /// Index of an assistant message that was cut off while streaming
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub incomplete: Option<usize>,
+    /// The task list the agent keeps with the `todo` tool
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub todos: Vec<TodoItem>,
+    /// The model, temperature and seed of the latest reply, to repeat it with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sampling: Option<Sampling>,
+    /// Messages at the start of the session left in the store, `messages` and the indices
+    /// above count from the first one after them
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub offloaded: usize,
+}
+
+fn is_zero(count: &usize) -> bool {
+    *count == 0
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ForkOrigin {
+    pub session_id: String,
+    pub message_count: usize,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            id: Uuid::new_v4().to_string(),
+            created_at: Utc::now(),
+            title: None,
+            forked_from: None,
+            messages: Vec::new(),
+            pinned: BTreeSet::new(),
+            incomplete: None,
+            todos: Vec::new(),
+            sampling: None,
+            offloaded: 0,
+        }
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load(id: &str) -> Result<Self, anyhow::Error> {
+        Store::open()?.load_session(id)
+    }
+
+    /// The session with at most about `keep` of its latest messages read into memory, for
+    /// resuming it.  0 reads them all.
+    pub fn load_recent(id: &str, keep: usize) -> Result<Self, anyhow::Error> {
+        let mut session = match keep {
+            0 => Store::open()?.load_session(id)?,
+            _ => Store::open()?.load_recent_session(id)?,
+        };
+        session.offload(keep);
+        Ok(session)
+    }
+
+    /*
+        * Drops the oldest messages from memory once there are more than `keep`, returning how
+        * many.  They stay in the store, so this is only for a session saved since they were
+        * added.  The cut is made at the start of a turn, so a tool call is never separated
+        * from its result, and never past a pinned or incomplete message.  Requests never see
+        * offloaded messages, like the ones the context manager leaves out of a long session.
+    */
+    pub fn offload(&mut self, keep: usize) -> usize {
+        if keep == 0 || self.messages.len() <= keep {
+            return 0;
+        }
+        let limit = [Some(self.messages.len() - keep), self.pinned.first().copied(), self.incomplete]
+            .into_iter()
+            .flatten()
+            .min()
+            .unwrap_or(0);
+        let Some(&cut) = ContextManager::turn_starts(&self.messages).iter().rev().find(|&&start| start <= limit) else {
+            return 0;
+        };
+        if cut == 0 {
+            return 0;
+        }
+        self.messages.drain(..cut);
+        self.pinned = self.pinned.iter().map(|idx| idx - cut).collect();
+        self.incomplete = self.incomplete.map(|idx| idx - cut);
+        self.offloaded += cut;
+        cut
+    }
+
+    /// Copies the first `at` messages into a new, unsaved session.  The fork point
+    /// must not separate a tool call from its result or the next request would fail.
+    pub fn fork(&self, at: usize) -> Result<Session, anyhow::Error> {
+        if at > self.messages.len() {
+            return Err(anyhow::anyhow!(
+                "Cannot fork at message {}, session only has {} messages",
+                at, self.messages.len()
+            ));
+        }
+
+        let mut messages = match self.offloaded {
+            0 => Vec::new(),
+            offloaded => Store::open()?.load_messages(&self.id, 0..offloaded)?,
+        };
+        messages.extend_from_slice(&self.messages[..at]);
+        let awaiting_result = messages.last().is_some_and(|msg| {
+            msg.content.iter().any(|item| matches!(item, ContentItem::ToolUse { .. }))
+        });
+        if awaiting_result {
+            return Err(anyhow::anyhow!(
+                "Cannot fork at message {}, it is a tool call awaiting its result",
+                at
+            ));
+        }
+
+        Ok(Session {
+            title: self.title.as_ref().map(|title| format!("{} (fork)", title)),
+            forked_from: Some(ForkOrigin {
+                session_id: self.id.clone(),
+                message_count: self.offloaded + at,
+            }),
+            pinned: self.pinned.iter().filter(|&&idx| idx < at).map(|idx| idx + self.offloaded).collect(),
+            incomplete: self.incomplete.filter(|&idx| idx < at).map(|idx| idx + self.offloaded),
+            messages,
+            ..Session::new()
+        })
+    }
+
+    /// All saved sessions, newest first.
+    pub fn list() -> Result<Vec<SessionSummary>, anyhow::Error> {
+        Store::open()?.list_sessions()
+    }
+
+    pub fn save(&self) -> Result<(), anyhow::Error> {
+        Store::open()?.save_session(self)?;
+        info!("Saved session {}", self.id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inference::types::Role;
+
+    fn text(role: Role, text: &str) -> Message {
+        Message { role, content: vec![ContentItem::Text { text: text.to_string() }] }
+    }
+
+    #[test]
+    fn test_fork_keeps_prefix_and_origin() {
+        let mut session = Session::new();
+        session.messages = vec![
+            text(Role::User, "one"),
+            text(Role::Assistant, "two"),
+            text(Role::User, "three"),
+        ];
+
+        let fork = session.fork(2).unwrap();
+        assert_ne!(fork.id, session.id);
+        assert_eq!(fork.messages, session.messages[..2].to_vec());
+        assert_eq!(fork.forked_from, Some(ForkOrigin { session_id: session.id.clone(), message_count: 2 }));
+        assert!(session.fork(4).is_err());
+    }
+
+    #[test]
+    fn test_offload_cuts_at_turns() {
+        let mut session = Session::new();
+        session.messages = ["one", "two", "three", "four", "five", "six"].iter().enumerate()
+            .map(|(idx, word)| text(if idx % 2 == 0 { Role::User } else { Role::Assistant }, word))
+            .collect();
+        session.pinned.insert(4);
+        assert_eq!(session.offload(0), 0);
+        assert_eq!(session.offload(3), 2);
+        assert_eq!((session.offloaded, session.messages.len(), session.pinned.first()), (2, 4, Some(&2)));
+        // The pinned message stays in memory
+        assert_eq!(session.offload(1), 2);
+        assert_eq!((session.offloaded, session.messages.len()), (4, 2));
+        assert_eq!(session.offload(1), 0);
+    }
+
+    #[test]
+    fn test_fork_rejects_dangling_tool_use() {
+        let mut session = Session::new();
+        session.messages = vec![
+            text(Role::User, "read it"),
+            Message {
+                role: Role::Assistant,
+                content: vec![ContentItem::ToolUse {
+                    id: "t1".to_string(),
+                    name: "read_file".to_string(),
+                    input: serde_json::json!({"path": "a.txt"}),
+                }],
+            },
+        ];
+
+        assert!(session.fork(2).is_err());
+        assert!(session.fork(1).is_ok());
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use serde_json::Value;
+use similar::TextDiff;
+
+use crate::inference::types::{ContentItem, Message, Role};
+
+// Set to write the actual transcripts over the snapshots instead of comparing
+pub const UPDATE_VAR: &str = "PPROG_UPDATE_SNAPSHOTS";
+
+/*
+    * A run as text that is the same every time it makes the same moves, for snapshot tests
+    * of tools and agents built on the crate: each message under its role, tool calls with
+    * their input as JSON with sorted keys, results under the call they answer.  Tool use
+    * ids are random, so they become `#1`, `#2` and so on in the order the calls were made.
+*/
+pub fn transcript(messages: &[Message]) -> String {
+    let mut ids = HashMap::new();
+    let mut calls = HashMap::new();
+    let mut out = String::new();
+    for message in messages {
+        for item in &message.content {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            let role = match message.role {
+                Role::User => "user",
+                Role::Assistant => "assistant",
+                Role::System => "system",
+                Role::Developer => "developer",
+            };
+            match item {
+                ContentItem::Text { text } => out.push_str(&format!("[{}]\n{}\n", role, text.trim_end())),
+                ContentItem::ToolUse { id, name, input } => {
+                    let number = ids.len() + 1;
+                    let number = *ids.entry(id.as_str()).or_insert(number);
+                    calls.insert(id.as_str(), name.as_str());
+                    let input = serde_json::to_string_pretty(&sorted(input)).unwrap_or_default();
+                    out.push_str(&format!("[{} tool_use #{} {}]\n{}\n", role, number, name, input));
+                },
+                ContentItem::ToolResult { tool_use_id, content, is_error } => {
+                    let number = ids.get(tool_use_id.as_str()).map_or("?".to_string(), |number| number.to_string());
+                    let name = calls.get(tool_use_id.as_str()).copied().unwrap_or("unknown");
+                    let failed = if *is_error { " error" } else { "" };
+                    out.push_str(&format!("[{} tool_result #{} {}{}]\n{}\n", role, number, name, failed, content.trim_end()));
+                },
+                ContentItem::Image { source } => {
+                    out.push_str(&format!("[{} image {}, {} bytes of base64]\n", role, source.media_type, source.data.len()));
+                },
+                ContentItem::Unknown(value) => {
+                    let kind = value["type"].as_str().unwrap_or("block");
+                    out.push_str(&format!("[{} {}]\n{}\n", role, kind, serde_json::to_string_pretty(&sorted(value)).unwrap_or_default()));
+                },
+            }
+        }
+    }
+    out
+}
+
+// `value` with the keys of every object in order, whatever order serde_json keeps them in
+fn sorted(value: &Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            let mut entries = object.iter().collect::<Vec<(&String, &Value)>>();
+            entries.sort_by_key(|(key, _)| key.as_str());
+            Value::Object(entries.into_iter().map(|(key, value)| (key.clone(), sorted(value))).collect())
+        },
+        Value::Array(items) => Value::Array(items.iter().map(sorted).collect()),
+        value => value.clone(),
+    }
+}
+
+/// Compares `actual` with the snapshot at `path`, the differences as a unified diff when they differ.
+pub fn compare(path: &Path, actual: &str) -> Result<(), String> {
+    if std::env::var_os(UPDATE_VAR).is_some() {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        return fs::write(path, actual).map_err(|e| format!("Failed to write the snapshot {}: {}", path.display(), e));
+    }
+    let expected = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read the snapshot {}, set {}=1 to write it: {}", path.display(), UPDATE_VAR, e))?;
+    if expected == actual {
+        return Ok(());
+    }
+    let diff = TextDiff::from_lines(expected.as_str(), actual)
+        .unified_diff()
+        .header(&path.display().to_string(), "actual")
+        .to_string();
+    Err(format!("The transcript differs from the snapshot {}, set {}=1 to accept it:\n{}", path.display(), UPDATE_VAR, diff))
+}
+
+/// Panics with a diff unless the transcript of `messages` matches the snapshot at `path`.
+#[track_caller]
+pub fn assert_snapshot(path: impl AsRef<Path>, messages: &[Message]) {
+    if let Err(e) = compare(path.as_ref(), &transcript(messages)) {
+        panic!("{}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_transcript_and_compare() {
+        let run = |id: &str| vec![
+            Message { role: Role::User, content: vec![ContentItem::Text { text: "Read lib.rs".to_string() }] },
+            Message { role: Role::Assistant, content: vec![ContentItem::ToolUse { id: id.to_string(), name: "read_file".to_string(), input: json!({"path": "src/lib.rs", "limit": 5}) }] },
+            Message { role: Role::User, content: vec![ContentItem::ToolResult { tool_use_id: id.to_string(), content: "pub mod agent;\n".to_string(), is_error: false }] },
+            Message { role: Role::Assistant, content: vec![ContentItem::Text { text: "It declares the agent module.".to_string() }] },
+        ];
+        let text = transcript(&run("toolu_1"));
+        assert_eq!(text, transcript(&run("toolu_2")));
+        assert!(text.contains("[assistant tool_use #1 read_file]\n{\n  \"limit\": 5,\n  \"path\": \"src/lib.rs\"\n}\n"));
+        assert!(text.contains("[user tool_result #1 read_file]\npub mod agent;\n"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("read.txt");
+        assert!(compare(&path, &text).unwrap_err().contains(UPDATE_VAR));
+        fs::write(&path, &text).unwrap();
+        assert_eq!(compare(&path, &text), Ok(()));
+        let diff = compare(&path, &text.replace("agent module", "agent and chat modules")).unwrap_err();
+        assert!(diff.contains("-It declares the agent module.\n+It declares the agent and chat modules.\n"));
+    }
+}
@@ -0,0 +1,805 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::config::ProjectConfig;
+use crate::context::ContextManager;
+use crate::crypto::Cipher;
+use crate::inference::types::{self, Message, Timing, Usage};
+use crate::models;
+use crate::session::Session;
+
+const STORE_FILE: &str = "pprog.db";
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS sessions (
+    id TEXT PRIMARY KEY,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    title TEXT,
+    forked_from TEXT,
+    pinned TEXT,
+    incomplete INTEGER,
+    todos TEXT,
+    sampling TEXT,
+    offloaded INTEGER
+);
+CREATE TABLE IF NOT EXISTS messages (
+    session_id TEXT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    message_index INTEGER NOT NULL,
+    role TEXT NOT NULL,
+    content TEXT NOT NULL,
+    PRIMARY KEY (session_id, message_index)
+);
+CREATE VIRTUAL TABLE IF NOT EXISTS message_text USING fts5(
+    session_id UNINDEXED,
+    message_index UNINDEXED,
+    role UNINDEXED,
+    content
+);
+CREATE TABLE IF NOT EXISTS tool_runs (
+    id INTEGER PRIMARY KEY,
+    session_id TEXT NOT NULL,
+    tool_use_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    input TEXT NOT NULL,
+    output TEXT NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    created_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS usage (
+    id INTEGER PRIMARY KEY,
+    session_id TEXT NOT NULL,
+    provider TEXT NOT NULL,
+    model TEXT NOT NULL,
+    input_tokens INTEGER NOT NULL,
+    cache_creation_input_tokens INTEGER NOT NULL,
+    cache_read_input_tokens INTEGER NOT NULL,
+    output_tokens INTEGER NOT NULL,
+    created_at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS usage_created_at ON usage(created_at);
+CREATE TABLE IF NOT EXISTS meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+"#;
+
+// Marker encrypted with the store's key, to tell a wrong key apart from corrupt data
+const KEY_CHECK: &str = "pprog";
+
+// Columns added after a table was first released, applied to databases that predate them
+const MIGRATIONS: &[(&str, &str, &str)] = &[
+    ("sessions", "pinned", "TEXT"),
+    ("sessions", "incomplete", "INTEGER"),
+    ("sessions", "todos", "TEXT"),
+    ("sessions", "sampling", "TEXT"),
+    ("sessions", "offloaded", "INTEGER"),
+    ("usage", "duration_ms", "INTEGER"),
+    ("usage", "first_token_ms", "INTEGER"),
+    ("usage", "cost_usd", "REAL"),
+];
+
+#[derive(Debug, Clone)]
+pub struct SessionSummary {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub title: Option<String>,
+    pub message_count: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub session_id: String,
+    pub message_index: usize,
+    pub role: String,
+    pub snippet: String,
+}
+
+/// One row per inference request, the input to cost reports and stats.
+#[derive(Debug, Clone)]
+pub struct UsageRecord<'a> {
+    pub session_id: &'a str,
+    pub provider: &'a str,
+    pub model: &'a str,
+    pub usage: &'a Usage,
+    pub timing: Option<&'a Timing>,
+}
+
+/// A recorded request, with its cost at the prices when it was made.  None for models
+/// with unknown prices.
+#[derive(Debug, Clone)]
+pub struct UsageEntry {
+    pub session_id: String,
+    pub provider: String,
+    pub model: String,
+    pub created_at: DateTime<Utc>,
+    pub usage: Usage,
+    pub cost: Option<f64>,
+}
+
+/// How fast a model answered, averaged over the requests that were timed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyStats {
+    pub requests: usize,
+    pub total: Duration,
+    /// Over the streamed requests only
+    pub first_token: Option<Duration>,
+    /// Output tokens per second after the first token
+    pub output_rate: Option<f64>,
+}
+
+/// One row per executed tool call.
+#[derive(Debug, Clone)]
+pub struct ToolRun<'a> {
+    pub session_id: &'a str,
+    pub tool_use_id: &'a str,
+    pub name: &'a str,
+    pub input: &'a serde_json::Value,
+    pub output: &'a str,
+    pub duration: Duration,
+}
+
+/*
+    * The project's SQLite database in `.pprog/pprog.db`.  It holds sessions and their
+    * messages, an FTS5 index over message text kept in sync on every save, and the tool
+    * run and usage rows that reports are built from.
+    *
+    * With `encrypt_sessions` titles, message contents and tool run inputs and outputs are
+    * encrypted field by field and the FTS index is left empty, so search decrypts and scans
+    * messages instead.  Once a store is encrypted it stays encrypted.
+*/
+pub struct Store {
+    conn: Connection,
+    cipher: Option<Cipher>,
+}
+
+impl Store {
+    pub fn open() -> Result<Self, anyhow::Error> {
+        let data_dir = ProjectConfig::data_dir()?;
+        let mut store = Self::open_at(&data_dir.join(STORE_FILE))?;
+        let key_id = store.meta("key_id")?;
+        if key_id.is_some() || ProjectConfig::shared()?.encrypt_sessions {
+            let key_id = key_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+            store.encrypt_with(Cipher::from_keychain(&key_id)?)?;
+            store.set_meta("key_id", &key_id)?;
+        }
+        store.import_json_sessions(&data_dir.join("sessions"))?;
+        Ok(store)
+    }
+
+    pub fn open_at(path: &Path) -> Result<Self, anyhow::Error> {
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        conn.execute_batch(SCHEMA)?;
+        Self::migrate(&conn)?;
+        Ok(Self { conn, cipher: None })
+    }
+
+    fn meta(&self, key: &str) -> Result<Option<String>, anyhow::Error> {
+        Ok(self.conn
+            .query_row("SELECT value FROM meta WHERE key = ?1", params![key], |row| row.get(0))
+            .optional()?)
+    }
+
+    fn set_meta(&self, key: &str, value: &str) -> Result<(), anyhow::Error> {
+        self.conn.execute(
+            "INSERT INTO meta (key, value) VALUES (?1, ?2) ON CONFLICT(key) DO UPDATE SET value = ?2",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    /// Encrypts everything stored from now on, converting existing plaintext rows the first
+    /// time a store is opened with a key.
+    pub fn encrypt_with(&mut self, cipher: Cipher) -> Result<(), anyhow::Error> {
+        if let Some(check) = self.meta("key_check")? {
+            cipher.decrypt(&check)
+                .map_err(|_| anyhow::anyhow!("Encryption key does not match the one the session store was encrypted with"))?;
+            self.cipher = Some(cipher);
+            return Ok(());
+        }
+
+        let tx = self.conn.transaction()?;
+        for (table, key, columns) in [
+            ("sessions", "id", &["title"][..]),
+            ("messages", "rowid", &["content"][..]),
+            ("tool_runs", "id", &["input", "output"][..]),
+        ] {
+            for column in columns {
+                let rows = tx
+                    .prepare(&format!("SELECT {}, {} FROM {} WHERE {} IS NOT NULL", key, column, table, column))?
+                    .query_map([], |row| Ok((row.get::<_, rusqlite::types::Value>(0)?, row.get::<_, String>(1)?)))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                for (id, value) in rows {
+                    tx.execute(
+                        &format!("UPDATE {} SET {} = ?1 WHERE {} = ?2", table, column, key),
+                        params![cipher.encrypt(&value)?, id],
+                    )?;
+                }
+            }
+        }
+        tx.execute("DELETE FROM message_text", [])?;
+        tx.execute(
+            "INSERT INTO meta (key, value) VALUES ('key_check', ?1)",
+            params![cipher.encrypt(KEY_CHECK)?],
+        )?;
+        tx.commit()?;
+        self.conn.execute_batch("VACUUM")?;
+        self.cipher = Some(cipher);
+        log::info!("Encrypted the session store");
+        Ok(())
+    }
+
+    /// The cipher of an encrypted store, for the files beside it that hold what was sent.
+    pub fn cipher(&self) -> Option<&Cipher> {
+        self.cipher.as_ref()
+    }
+
+    fn seal(&self, value: &str) -> Result<String, anyhow::Error> {
+        match &self.cipher {
+            Some(cipher) => cipher.encrypt(value),
+            None => Ok(value.to_string()),
+        }
+    }
+
+    fn unseal(&self, value: &str) -> Result<String, anyhow::Error> {
+        match &self.cipher {
+            Some(cipher) => cipher.decrypt(value),
+            None if value.starts_with(crate::crypto::ENCRYPTED_PREFIX) => {
+                Err(anyhow::anyhow!("Session store is encrypted but no key was loaded"))
+            },
+            None => Ok(value.to_string()),
+        }
+    }
+
+    fn migrate(conn: &Connection) -> Result<(), anyhow::Error> {
+        for (table, column, decl) in MIGRATIONS {
+            let exists = conn
+                .prepare(&format!("PRAGMA table_info({})", table))?
+                .query_map([], |row| row.get::<_, String>(1))?
+                .collect::<Result<Vec<String>, _>>()?
+                .iter()
+                .any(|name| name == column);
+            if !exists {
+                conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Sessions used to be saved as one JSON file each, move them into the database once
+    fn import_json_sessions(&mut self, dir: &Path) -> Result<(), anyhow::Error> {
+        if !dir.is_dir() {
+            return Ok(());
+        }
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                match serde_json::from_str::<Session>(&fs::read_to_string(&path)?) {
+                    Ok(session) => self.save_session(&session)?,
+                    Err(e) => log::warn!("Skipping unreadable session file {:?}: {}", path, e),
+                }
+            }
+        }
+        fs::rename(dir, dir.with_extension("imported"))?;
+        log::info!("Imported JSON sessions from {:?}", dir);
+        Ok(())
+    }
+
+    pub fn save_session(&mut self, session: &Session) -> Result<(), anyhow::Error> {
+        let tx = self.conn.unchecked_transaction()?;
+        let forked_from = session.forked_from.as_ref().map(serde_json::to_string).transpose()?;
+        let pinned = serde_json::to_string(&session.pinned)?;
+        let todos = match session.todos.is_empty() {
+            true => None,
+            false => Some(self.seal(&serde_json::to_string(&session.todos)?)?),
+        };
+        let sampling = session.sampling.as_ref().map(serde_json::to_string).transpose()?;
+        tx.execute(
+            r#"
+            INSERT INTO sessions (id, created_at, updated_at, title, forked_from, pinned, incomplete, todos, sampling, offloaded)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+            ON CONFLICT(id) DO UPDATE SET updated_at = ?3, title = ?4, forked_from = ?5, pinned = ?6,
+                incomplete = ?7, todos = ?8, sampling = ?9, offloaded = ?10
+            "#,
+            params![
+                session.id,
+                session.created_at,
+                Utc::now(),
+                session.title.as_deref().map(|title| self.seal(title)).transpose()?,
+                forked_from,
+                pinned,
+                session.incomplete.map(|idx| idx as i64),
+                todos,
+                sampling,
+                session.offloaded as i64,
+            ],
+        )?;
+
+        // Offloaded messages were saved before and can't have changed, only the ones after are written
+        let offloaded = session.offloaded as i64;
+        tx.execute("DELETE FROM messages WHERE session_id = ?1 AND message_index >= ?2", params![session.id, offloaded])?;
+        tx.execute("DELETE FROM message_text WHERE session_id = ?1 AND message_index >= ?2", params![session.id, offloaded])?;
+        for (idx, message) in session.messages.iter().enumerate() {
+            let idx = session.offloaded + idx;
+            let role = format!("{:?}", message.role).to_lowercase();
+            tx.execute(
+                "INSERT INTO messages (session_id, message_index, role, content) VALUES (?1, ?2, ?3, ?4)",
+                params![session.id, idx as i64, role, self.seal(&serde_json::to_string(message)?)?],
+            )?;
+            if self.cipher.is_some() {
+                continue;
+            }
+            tx.execute(
+                "INSERT INTO message_text (session_id, message_index, role, content) VALUES (?1, ?2, ?3, ?4)",
+                params![session.id, idx as i64, role, ContextManager::content_to_string(&message.content)],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Resolves a full session id or a unique prefix of one.
+    pub fn resolve_session_id(&self, id: &str) -> Result<String, anyhow::Error> {
+        let mut stmt = self.conn.prepare("SELECT id FROM sessions WHERE id = ?1 OR id LIKE ?2 || '%' ESCAPE '\\'")?;
+        let escaped = id.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+        let matches = stmt
+            .query_map(params![id, escaped], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+
+        if matches.iter().any(|candidate| candidate == id) {
+            return Ok(id.to_string());
+        }
+        match matches.len() {
+            0 => Err(anyhow::anyhow!("No session found matching '{}'", id)),
+            1 => Ok(matches[0].clone()),
+            _ => Err(anyhow::anyhow!("Session id '{}' is ambiguous, matches: {}", id, matches.join(", "))),
+        }
+    }
+
+    /// The session with all its messages, offloaded ones included.
+    pub fn load_session(&self, id: &str) -> Result<Session, anyhow::Error> {
+        let mut session = self.load_recent_session(id)?;
+        let mut messages = self.load_messages(&session.id, 0..session.offloaded)?;
+        messages.append(&mut session.messages);
+        session.pinned = session.pinned.iter().map(|idx| idx + session.offloaded).collect();
+        session.incomplete = session.incomplete.map(|idx| idx + session.offloaded);
+        session.messages = messages;
+        session.offloaded = 0;
+        Ok(session)
+    }
+
+    /// Messages `range` of a session, by their index in the whole session.
+    pub fn load_messages(&self, session_id: &str, range: std::ops::Range<usize>) -> Result<Vec<Message>, anyhow::Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT content FROM messages WHERE session_id = ?1 AND message_index >= ?2 AND message_index < ?3 ORDER BY message_index",
+        )?;
+        let messages = stmt
+            .query_map(params![session_id, range.start as i64, range.end as i64], |row| row.get::<_, String>(0))?
+            .map(|content| Ok(serde_json::from_str::<Message>(&self.unseal(&content?)?)?))
+            .collect::<Result<Vec<Message>, anyhow::Error>>()?;
+        Ok(messages)
+    }
+
+    /// The session with the messages it had in memory when last saved, see `Session::offload`.
+    pub fn load_recent_session(&self, id: &str) -> Result<Session, anyhow::Error> {
+        let id = self.resolve_session_id(id)?;
+        let (created_at, title, forked_from, pinned, incomplete, todos, sampling, offloaded) = self.conn.query_row(
+            "SELECT created_at, title, forked_from, pinned, incomplete, todos, sampling, offloaded FROM sessions WHERE id = ?1",
+            params![id],
+            |row| Ok((
+                row.get::<_, DateTime<Utc>>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<i64>>(4)?,
+                row.get::<_, Option<String>>(5)?,
+                row.get::<_, Option<String>>(6)?,
+                row.get::<_, Option<i64>>(7)?,
+            )),
+        )?;
+
+        let offloaded = offloaded.unwrap_or(0) as usize;
+        let messages = self.load_messages(&id, offloaded..i64::MAX as usize)?;
+        let todos = match todos {
+            Some(todos) => serde_json::from_str(&self.unseal(&todos)?)?,
+            None => Vec::new(),
+        };
+
+        Ok(Session {
+            id,
+            created_at,
+            title: title.map(|title| self.unseal(&title)).transpose()?,
+            forked_from: forked_from.map(|origin| serde_json::from_str(&origin)).transpose()?,
+            messages,
+            pinned: pinned.map(|pinned| serde_json::from_str(&pinned)).transpose()?.unwrap_or_default(),
+            incomplete: incomplete.map(|idx| idx as usize),
+            todos,
+            sampling: sampling.map(|sampling| serde_json::from_str(&sampling)).transpose()?,
+            offloaded,
+        })
+    }
+
+    /// All sessions, newest first.
+    pub fn list_sessions(&self) -> Result<Vec<SessionSummary>, anyhow::Error> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT s.id, s.created_at, s.title, COUNT(m.message_index)
+            FROM sessions s LEFT JOIN messages m ON m.session_id = s.id
+            GROUP BY s.id
+            ORDER BY s.created_at DESC
+            "#,
+        )?;
+        let sessions = stmt
+            .query_map([], |row| {
+                Ok(SessionSummary {
+                    id: row.get(0)?,
+                    created_at: row.get(1)?,
+                    title: row.get(2)?,
+                    message_count: row.get::<_, i64>(3)? as usize,
+                })
+            })?
+            .collect::<Result<Vec<SessionSummary>, _>>()?
+            .into_iter()
+            .map(|mut session| {
+                session.title = session.title.map(|title| self.unseal(&title)).transpose()?;
+                Ok(session)
+            })
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+        Ok(sessions)
+    }
+
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, anyhow::Error> {
+        if self.cipher.is_some() {
+            return self.scan(query, limit);
+        }
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT session_id, message_index, role, snippet(message_text, 3, '[', ']', '...', 12)
+            FROM message_text
+            WHERE message_text MATCH ?1
+            ORDER BY rank
+            LIMIT ?2
+            "#,
+        )?;
+
+        let hits = stmt
+            .query_map(params![Self::fts_query(query), limit as i64], |row| {
+                Ok(SearchHit {
+                    session_id: row.get(0)?,
+                    message_index: row.get::<_, i64>(1)? as usize,
+                    role: row.get(2)?,
+                    snippet: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(hits)
+    }
+
+    // Search for encrypted stores, every message is decrypted and checked for all terms
+    fn scan(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, anyhow::Error> {
+        let terms: Vec<String> = query.split_whitespace().map(|term| term.to_lowercase()).collect();
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT m.session_id, m.message_index, m.role, m.content
+            FROM messages m JOIN sessions s ON s.id = m.session_id
+            ORDER BY s.updated_at DESC, m.message_index
+            "#,
+        )?;
+        let mut rows = stmt.query([])?;
+        let mut hits = Vec::new();
+        while let Some(row) = rows.next()? {
+            let message: Message = serde_json::from_str(&self.unseal(&row.get::<_, String>(3)?)?)?;
+            let text = ContextManager::content_to_string(&message.content);
+            let lower = text.to_lowercase();
+            if !terms.iter().all(|term| lower.contains(term.as_str())) {
+                continue;
+            }
+
+            hits.push(SearchHit {
+                session_id: row.get(0)?,
+                message_index: row.get::<_, i64>(1)? as usize,
+                role: row.get(2)?,
+                snippet: Self::snippet(&text, &terms[0]),
+            });
+            if hits.len() == limit {
+                break;
+            }
+        }
+        Ok(hits)
+    }
+
+    // Roughly what FTS5's snippet() gives: the first match in brackets with some context
+    fn snippet(text: &str, term: &str) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        let lower: Vec<char> = text.to_lowercase().chars().collect();
+        let needle: Vec<char> = term.chars().collect();
+        let start = (0..lower.len().saturating_sub(needle.len() - 1))
+            .find(|&idx| lower[idx..].starts_with(&needle))
+            .unwrap_or_default()
+            .min(chars.len());
+        let end = (start + needle.len()).min(chars.len());
+
+        let from = start.saturating_sub(40);
+        let to = (end + 40).min(chars.len());
+        format!(
+            "{}{}[{}]{}{}",
+            if from > 0 { "..." } else { "" },
+            chars[from..start].iter().collect::<String>(),
+            chars[start..end].iter().collect::<String>(),
+            chars[end..to].iter().collect::<String>(),
+            if to < chars.len() { "..." } else { "" },
+        )
+    }
+
+    // Quote every term so punctuation in the query is never parsed as FTS5 syntax
+    fn fts_query(query: &str) -> String {
+        query.split_whitespace()
+            .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    pub fn record_tool_run(&self, run: &ToolRun) -> Result<(), anyhow::Error> {
+        self.conn.execute(
+            r#"
+            INSERT INTO tool_runs (session_id, tool_use_id, name, input, output, duration_ms, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            "#,
+            params![
+                run.session_id,
+                run.tool_use_id,
+                run.name,
+                self.seal(&run.input.to_string())?,
+                self.seal(run.output)?,
+                run.duration.as_millis() as i64,
+                Utc::now(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn record_usage(&self, record: &UsageRecord) -> Result<(), anyhow::Error> {
+        self.conn.execute(
+            r#"
+            INSERT INTO usage (session_id, provider, model, input_tokens, cache_creation_input_tokens,
+                cache_read_input_tokens, output_tokens, created_at, duration_ms, first_token_ms, cost_usd)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+            "#,
+            params![
+                record.session_id,
+                record.provider,
+                record.model,
+                record.usage.input_tokens,
+                record.usage.cache_creation_input_tokens,
+                record.usage.cache_read_input_tokens,
+                record.usage.output_tokens,
+                Utc::now(),
+                record.timing.map(|timing| timing.total.as_millis() as i64),
+                record.timing.and_then(|timing| timing.first_token).map(|first| first.as_millis() as i64),
+                models::lookup(record.model).map(|info| info.cost(record.usage)),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Token totals of a session per model.
+    pub fn session_usage(&self, session_id: &str) -> Result<Vec<(String, Usage)>, anyhow::Error> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT model, SUM(input_tokens), SUM(cache_creation_input_tokens),
+                SUM(cache_read_input_tokens), SUM(output_tokens)
+            FROM usage WHERE session_id = ?1 GROUP BY model
+            "#,
+        )?;
+        let rows = stmt.query_map(params![session_id], |row| {
+            Ok((row.get(0)?, Usage {
+                input_tokens: row.get(1)?,
+                cache_creation_input_tokens: row.get(2)?,
+                cache_read_input_tokens: row.get(3)?,
+                output_tokens: row.get(4)?,
+            }))
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// USD a session has cost, at the prices of the models it used.
+    pub fn session_cost(&self, session_id: &str) -> Result<f64, anyhow::Error> {
+        Ok(self.session_usage(session_id)?.iter()
+            .filter_map(|(model, usage)| models::lookup(model).map(|info| info.cost(usage)))
+            .fold(0.0, |total, cost| total + cost))
+    }
+
+    /// Every request recorded since `since`, or ever, oldest first.  Requests recorded before
+    /// costs were stored are priced now.
+    pub fn usage_since(&self, since: Option<DateTime<Utc>>) -> Result<Vec<UsageEntry>, anyhow::Error> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT session_id, provider, model, created_at, input_tokens, cache_creation_input_tokens,
+                cache_read_input_tokens, output_tokens, cost_usd
+            FROM usage WHERE ?1 IS NULL OR created_at >= ?1 ORDER BY created_at
+            "#,
+        )?;
+        let rows = stmt.query_map(params![since], |row| {
+            let usage = Usage {
+                input_tokens: row.get(4)?,
+                cache_creation_input_tokens: row.get(5)?,
+                cache_read_input_tokens: row.get(6)?,
+                output_tokens: row.get(7)?,
+            };
+            let model: String = row.get(2)?;
+            let cost = row.get::<_, Option<f64>>(8)?.or_else(|| models::lookup(&model).map(|info| info.cost(&usage)));
+            Ok(UsageEntry { session_id: row.get(0)?, provider: row.get(1)?, model, created_at: row.get(3)?, usage, cost })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Latency of a session's requests per model, leaving out models with no timed request.
+    pub fn session_latency(&self, session_id: &str) -> Result<Vec<(String, LatencyStats)>, anyhow::Error> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT model, COUNT(*), AVG(duration_ms), AVG(first_token_ms), SUM(output_tokens),
+                SUM(duration_ms - COALESCE(first_token_ms, 0))
+            FROM usage WHERE session_id = ?1 AND duration_ms IS NOT NULL GROUP BY model
+            "#,
+        )?;
+        let millis = |ms: f64| Duration::from_secs_f64(ms.max(0.0) / 1000.0);
+        let rows = stmt.query_map(params![session_id], |row| {
+            let output_tokens: i64 = row.get(4)?;
+            let generating: i64 = row.get(5)?;
+            Ok((row.get(0)?, LatencyStats {
+                requests: row.get::<_, i64>(1)? as usize,
+                total: millis(row.get(2)?),
+                first_token: row.get::<_, Option<f64>>(3)?.map(millis),
+                output_rate: types::output_rate(output_tokens as u32, Duration::from_millis(generating.max(0) as u64)),
+            }))
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inference::types::{ContentItem, Role, Sampling};
+
+    fn sample_session() -> Session {
+        let mut session = Session::new();
+        session.title = Some("Tokio panic".to_string());
+        session.messages = vec![
+            Message { role: Role::User, content: vec![ContentItem::Text { text: "why does tokio panic here?".to_string() }] },
+            Message { role: Role::Assistant, content: vec![ContentItem::Text { text: "The runtime was dropped.".to_string() }] },
+        ];
+        session
+    }
+
+    #[test]
+    fn test_session_round_trip() -> Result<(), anyhow::Error> {
+        let temp_dir = tempfile::tempdir()?;
+        let mut store = Store::open_at(&temp_dir.path().join(STORE_FILE))?;
+
+        let mut session = sample_session();
+        store.save_session(&session)?;
+        session.messages.pop();
+        session.pinned.insert(0);
+        session.incomplete = Some(0);
+        session.todos = crate::todo::parse("[x] Find the panic\n[ ] Keep the runtime alive")?;
+        session.sampling = Some(Sampling { model: "gpt-4o-mini".to_string(), temperature: Some(0.0), seed: Some(7) });
+        store.save_session(&session)?;
+
+        let loaded = store.load_session(&session.id[..8])?;
+        assert_eq!(loaded.id, session.id);
+        assert_eq!(loaded.title, session.title);
+        assert_eq!(loaded.messages, session.messages);
+        assert_eq!(loaded.pinned, session.pinned);
+        assert_eq!(loaded.incomplete, session.incomplete);
+        assert_eq!(loaded.todos, session.todos);
+        assert_eq!(loaded.sampling, session.sampling);
+
+        let summaries = store.list_sessions()?;
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].message_count, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_offloaded_messages_stay_saved() -> Result<(), anyhow::Error> {
+        let temp_dir = tempfile::tempdir()?;
+        let mut store = Store::open_at(&temp_dir.path().join(STORE_FILE))?;
+
+        let mut session = sample_session();
+        session.messages.extend(sample_session().messages);
+        session.pinned.insert(3);
+        store.save_session(&session)?;
+        let all = session.messages.clone();
+        assert_eq!(session.offload(2), 2);
+        session.messages[0].content.push(ContentItem::Text { text: "Edited after offloading".to_string() });
+        store.save_session(&session)?;
+
+        let recent = store.load_recent_session(&session.id)?;
+        assert_eq!((recent.offloaded, recent.messages.len(), recent.pinned.first()), (2, 2, Some(&1)));
+        let full = store.load_session(&session.id)?;
+        assert_eq!(full.messages[..2], all[..2]);
+        assert_eq!(full.messages[2].content.len(), 2);
+        assert_eq!((full.offloaded, full.pinned.first()), (0, Some(&3)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_finds_matching_messages() -> Result<(), anyhow::Error> {
+        let temp_dir = tempfile::tempdir()?;
+        let mut store = Store::open_at(&temp_dir.path().join(STORE_FILE))?;
+        let session = sample_session();
+        store.save_session(&session)?;
+
+        let hits = store.search("tokio panic", 10)?;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].session_id, session.id);
+        assert_eq!(hits[0].message_index, 0);
+        assert!(hits[0].snippet.contains("[tokio]"));
+
+        assert!(store.search("\"unbalanced", 10)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_latency() -> Result<(), anyhow::Error> {
+        let temp_dir = tempfile::tempdir()?;
+        let store = Store::open_at(&temp_dir.path().join(STORE_FILE))?;
+        let usage = Usage { output_tokens: 300, ..Default::default() };
+        let streamed = Timing { total: Duration::from_secs(4), first_token: Some(Duration::from_secs(1)) };
+        let whole = Timing { total: Duration::from_secs(2), first_token: None };
+        for timing in [Some(&streamed), Some(&whole), None] {
+            store.record_usage(&UsageRecord { session_id: "s1", provider: "anthropic", model: "claude-3-5-haiku-latest", usage: &usage, timing })?;
+        }
+
+        let latency = store.session_latency("s1")?;
+        assert_eq!(latency, vec![("claude-3-5-haiku-latest".to_string(), LatencyStats {
+            requests: 2,
+            total: Duration::from_secs(3),
+            first_token: Some(Duration::from_secs(1)),
+            output_rate: Some(120.0),
+        })]);
+        assert!(store.session_latency("s2")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypted_store_hides_plaintext() -> Result<(), anyhow::Error> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join(STORE_FILE);
+        let key = Cipher::generate_key()?;
+        let session = sample_session();
+
+        // Rows written before encryption was enabled are converted too
+        let mut store = Store::open_at(&path)?;
+        store.save_session(&session)?;
+        store.encrypt_with(Cipher::from_key(&key)?)?;
+        drop(store);
+        let raw = fs::read(&path)?;
+        assert!(!raw.windows(6).any(|window| window == b"dropped"));
+
+        let mut store = Store::open_at(&path)?;
+        assert!(store.load_session(&session.id).is_err());
+        assert!(store.encrypt_with(Cipher::from_key(&Cipher::generate_key()?)?).is_err());
+        store.encrypt_with(Cipher::from_key(&key)?)?;
+        let loaded = store.load_session(&session.id)?;
+        assert_eq!(loaded.title, session.title);
+        assert_eq!(loaded.messages, session.messages);
+
+        let hits = store.search("TOKIO panic", 10)?;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].snippet, "why does [tokio] panic here?");
+        Ok(())
+    }
+}
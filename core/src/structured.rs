@@ -0,0 +1,403 @@
+use std::collections::BTreeMap;
+
+use serde::de::DeserializeOwned;
+use serde_json::json;
+pub use serde_json::Value;
+
+use crate::inference::types::{ContentItem, Message, ModelResponse, ResponseSchema, Role};
+
+/// A type the model can be asked to reply with, described by a JSON Schema.
+pub trait JsonSchema {
+    fn json_schema() -> Value;
+
+    /// The name the schema is sent under, letters, digits, `_` and `-` only
+    fn schema_name() -> String {
+        String::from("response")
+    }
+}
+
+pub use pprog_derive::Extract;
+
+/// A type the model fills in from a text with `Chat::extract`, usually derived with
+/// `#[derive(Extract)]`.
+pub trait Extract: JsonSchema + DeserializeOwned {
+    /// What to pull out of the text, added to the system prompt
+    fn instructions() -> String;
+
+    /// Checks beyond what the schema enforces, a failure goes back to the model like a parse error
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+const EXTRACT_PROMPT: &str = "You pull structured data out of the text the user sends.  \
+Take values from the text only, and use null for anything optional it doesn't say.";
+
+macro_rules! schema_of {
+    ($schema:tt => $($ty:ty),+) => {
+        $(impl JsonSchema for $ty {
+            fn json_schema() -> Value {
+                json!($schema)
+            }
+        })+
+    };
+}
+
+schema_of!({"type": "string"} => String);
+schema_of!({"type": "boolean"} => bool);
+schema_of!({"type": "integer"} => i8, i16, i32, i64, isize);
+schema_of!({"type": "integer", "minimum": 0} => u8, u16, u32, u64, usize);
+schema_of!({"type": "number"} => f32, f64);
+
+impl<T: JsonSchema> JsonSchema for Vec<T> {
+    fn json_schema() -> Value {
+        json!({"type": "array", "items": T::json_schema()})
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for Option<T> {
+    fn json_schema() -> Value {
+        json!({"anyOf": [T::json_schema(), {"type": "null"}]})
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for BTreeMap<String, T> {
+    fn json_schema() -> Value {
+        json!({"type": "object", "additionalProperties": T::json_schema()})
+    }
+}
+
+/// The schema of an object with these fields, all of them required.
+pub fn object(fields: &[(&str, Value)]) -> Value {
+    json!({
+        "type": "object",
+        "properties": fields.iter().map(|(name, schema)| (name.to_string(), schema.clone())).collect::<serde_json::Map<String, Value>>(),
+        "required": fields.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+        "additionalProperties": false,
+    })
+}
+
+/// `schema` with a description for the model of what goes in it.
+pub fn described(mut schema: Value, description: &str) -> Value {
+    if let Value::Object(fields) = &mut schema {
+        fields.insert("description".to_string(), json!(description));
+    }
+    schema
+}
+
+/// Sets `keyword` on `schema`, like `enum` or `minimum`.
+pub fn constrain(schema: &mut Value, keyword: &str, value: Value) {
+    if let Value::Object(fields) = schema {
+        fields.insert(keyword.to_string(), value);
+    }
+}
+
+/// Requires `schema`, a string or an array, to have at least one character or item.
+pub fn non_empty(schema: &mut Value) {
+    match schema["type"].as_str() {
+        Some("array") => constrain(schema, "minItems", json!(1)),
+        _ => constrain(schema, "minLength", json!(1)),
+    }
+}
+
+/// The system prompt for filling in a `T`.
+pub fn extract_prompt<T: Extract>() -> String {
+    match T::instructions().trim() {
+        "" => EXTRACT_PROMPT.to_string(),
+        instructions => format!("{}\n\n{}", EXTRACT_PROMPT, instructions),
+    }
+}
+
+pub fn response_schema<T: JsonSchema>() -> ResponseSchema {
+    ResponseSchema { name: T::schema_name(), schema: T::json_schema() }
+}
+
+/// The system prompt asking for `schema` in words, for providers that can't enforce it.
+pub fn schema_prompt(system_message: &str, schema: &ResponseSchema) -> String {
+    format!("{}\nReply with a single JSON value and nothing else, following this JSON Schema:\n{}\n", system_message, schema.schema)
+}
+
+/*
+    * Fixes the usual ways a model's JSON goes wrong without another round trip: prose
+    * around it, and trailing commas before a closing bracket.  Text inside strings is left
+    * as it is.
+*/
+fn repair(text: &str) -> String {
+    let text = match (text.find(['{', '[']), text.rfind(['}', ']'])) {
+        (Some(start), Some(end)) if start < end => &text[start..=end],
+        _ => text,
+    };
+    let mut repaired = String::with_capacity(text.len());
+    let (mut in_string, mut escaped) = (false, false);
+    for c in text.chars() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {},
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == '}' || c == ']' {
+            let kept = repaired.trim_end().len();
+            if repaired[..kept].ends_with(',') {
+                repaired.truncate(kept - 1);
+            }
+        }
+        repaired.push(c);
+    }
+    repaired
+}
+
+// The input of the forced tool call, or else the reply's text with any code fence around it
+fn reply_json(response: &ModelResponse, schema: &ResponseSchema, repair_json: bool) -> Result<Value, anyhow::Error> {
+    let mut text = String::new();
+    for item in &response.content {
+        match item {
+            ContentItem::ToolUse { name, input, .. } if *name == schema.name => return Ok(input.clone()),
+            ContentItem::Text { text: part } => text.push_str(part),
+            _ => {},
+        }
+    }
+    let text = text.trim();
+    let text = match text.strip_prefix("```") {
+        Some(fenced) => fenced.trim_start_matches("json").trim_end_matches("```").trim(),
+        None => text,
+    };
+    if text.is_empty() {
+        return Err(anyhow::anyhow!("The reply was empty"));
+    }
+    match serde_json::from_str(text) {
+        Ok(value) => Ok(value),
+        Err(e) if repair_json => serde_json::from_str(&repair(text)).map_err(|_| e.into()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The reply read as a `T`, after trying `repair` on JSON that doesn't parse when `repair_json` is set.
+pub fn parse<T: DeserializeOwned>(response: &ModelResponse, schema: &ResponseSchema, repair_json: bool) -> Result<T, anyhow::Error> {
+    Ok(serde_json::from_value(reply_json(response, schema, repair_json)?)?)
+}
+
+// Whether `value` is of the JSON Schema `type` called `name`
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+/*
+    * Checks `value` against `schema`, for the keywords the schemas here are made of: `type`,
+    * `enum`, `anyOf`, `properties`, `required`, `additionalProperties`, `items` and the
+    * length, count and range bounds.  Other keywords are ignored.  The error names where in
+    * `value` it went wrong, like `$.steps[2]`.
+*/
+pub fn check_schema(schema: &Value, value: &Value) -> Result<(), String> {
+    check_at(schema, value, "$")
+}
+
+fn check_at(schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+    let types = match &schema["type"] {
+        Value::String(name) => vec![name.as_str()],
+        Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    if !types.is_empty() && !types.iter().any(|name| has_type(value, name)) {
+        return Err(format!("{} is {}, not {}", path, value, types.join(" or ")));
+    }
+    if let Some(options) = schema["enum"].as_array() {
+        if !options.contains(value) {
+            return Err(format!("{} is {}, not one of {}", path, value, Value::Array(options.clone())));
+        }
+    }
+    if let Some(schemas) = schema["anyOf"].as_array() {
+        if !schemas.iter().any(|schema| check_at(schema, value, path).is_ok()) {
+            return Err(format!("{} matches none of the schemas it may be", path));
+        }
+    }
+    let bound = |keyword: &str| schema[keyword].as_f64();
+    let (count, noun) = match value {
+        Value::String(text) => (Some(text.chars().count()), "characters"),
+        Value::Array(items) => (Some(items.len()), "items"),
+        _ => (None, ""),
+    };
+    if let Some(count) = count {
+        let (min, max) = match value.is_string() {
+            true => (bound("minLength"), bound("maxLength")),
+            false => (bound("minItems"), bound("maxItems")),
+        };
+        if min.is_some_and(|min| (count as f64) < min) || max.is_some_and(|max| (count as f64) > max) {
+            return Err(format!("{} has {} {}, outside the bounds", path, count, noun));
+        }
+    }
+    if let Some(number) = value.as_f64() {
+        if bound("minimum").is_some_and(|min| number < min) {
+            return Err(format!("{} is {}, below the minimum", path, number));
+        }
+        if bound("maximum").is_some_and(|max| number > max) {
+            return Err(format!("{} is {}, above the maximum", path, number));
+        }
+    }
+    if let Value::Array(items) = value {
+        if schema["items"].is_object() {
+            for (idx, item) in items.iter().enumerate() {
+                check_at(&schema["items"], item, &format!("{}[{}]", path, idx))?;
+            }
+        }
+    }
+    if let Value::Object(fields) = value {
+        for name in schema["required"].as_array().into_iter().flatten().filter_map(Value::as_str) {
+            if !fields.contains_key(name) {
+                return Err(format!("{} has no {}", path, name));
+            }
+        }
+        for (name, field) in fields {
+            let field_path = format!("{}.{}", path, name);
+            match schema["properties"].get(name) {
+                Some(property) => check_at(property, field, &field_path)?,
+                None => match &schema["additionalProperties"] {
+                    Value::Bool(false) => return Err(format!("{} isn't allowed", field_path)),
+                    additional @ Value::Object(_) => check_at(additional, field, &field_path)?,
+                    _ => {},
+                },
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The message sending the reply back with why it didn't parse, as the result of its tool call if it made one.
+pub fn correction(response: &ModelResponse, error: &anyhow::Error) -> Message {
+    let text = format!("That reply doesn't match the schema: {}.  Reply again with all of the data.", error);
+    let content = match response.content.iter().find(|item| matches!(item, ContentItem::ToolUse { .. })) {
+        Some(ContentItem::ToolUse { id, .. }) => ContentItem::ToolResult { tool_use_id: id.clone(), content: text, is_error: true },
+        _ => ContentItem::Text { text },
+    };
+    Message { role: Role::User, content: vec![content] }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inference::types::{ResponseType, StopReason};
+
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct Finding {
+        path: String,
+        line: usize,
+        severity: Option<String>,
+    }
+
+    impl JsonSchema for Finding {
+        fn json_schema() -> Value {
+            object(&[("path", String::json_schema()), ("line", usize::json_schema()), ("severity", Option::<String>::json_schema())])
+        }
+
+        fn schema_name() -> String {
+            String::from("finding")
+        }
+    }
+
+    /// A bug report.
+    #[derive(Debug, serde::Deserialize, Extract)]
+    struct Report {
+        /// One line on what goes wrong
+        #[extract(non_empty)]
+        summary: String,
+        #[extract(one_of = ["low", "high"])]
+        severity: String,
+        #[extract(min = 1, max = 5)]
+        priority: u8,
+        #[extract(non_empty)]
+        steps: Vec<String>,
+    }
+
+    fn response(content: Vec<ContentItem>) -> ModelResponse {
+        ModelResponse {
+            content,
+            id: "msg_1".to_string(),
+            model: "claude-3-5-haiku-latest".to_string(),
+            role: Role::Assistant,
+            message_type: ResponseType::Message,
+            stop_reason: StopReason::EndTurn,
+            stop_sequence: None,
+            usage: None,
+            raw: None,
+            timing: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_and_correction() {
+        let schema = response_schema::<Finding>();
+        assert_eq!(schema.schema["required"], json!(["path", "line", "severity"]));
+
+        let input = json!({"path": "src/main.rs", "line": 12, "severity": null});
+        let forced = response(vec![ContentItem::ToolUse { id: "t1".to_string(), name: "finding".to_string(), input }]);
+        let finding = parse::<Finding>(&forced, &schema, false).unwrap();
+        assert_eq!(finding, Finding { path: "src/main.rs".to_string(), line: 12, severity: None });
+
+        let fenced = response(vec![ContentItem::Text { text: "```json\n{\"path\": \"a.rs\", \"line\": 1, \"severity\": \"high\"}\n```".to_string() }]);
+        assert_eq!(parse::<Finding>(&fenced, &schema, false).unwrap().severity.as_deref(), Some("high"));
+
+        let prose = response(vec![ContentItem::Text { text: "Line 12 of main.rs".to_string() }]);
+        let error = parse::<Finding>(&prose, &schema, true).unwrap_err();
+        assert!(matches!(correction(&prose, &error).content[0], ContentItem::Text { .. }));
+        assert!(matches!(correction(&forced, &error).content[0], ContentItem::ToolResult { is_error: true, .. }));
+    }
+
+    #[test]
+    fn test_repair() {
+        assert_eq!(repair("Here it is:\n{\"a\": [1, 2,], \"b\": \"x,}\",\n}\nHope that helps."), "{\"a\": [1, 2], \"b\": \"x,}\"}");
+        assert_eq!(repair(r#"{"quote": "say \"hi\",]", }"#), r#"{"quote": "say \"hi\",]"}"#);
+
+        let schema = response_schema::<Finding>();
+        let sloppy = response(vec![ContentItem::Text { text: "{\"path\": \"a.rs\", \"line\": 3, \"severity\": null,}".to_string() }]);
+        assert!(parse::<Finding>(&sloppy, &schema, false).is_err());
+        assert_eq!(parse::<Finding>(&sloppy, &schema, true).unwrap().line, 3);
+    }
+
+    #[test]
+    fn test_check_schema() {
+        let schema = response_schema::<Report>().schema;
+        let report = json!({"summary": "Crash on save", "severity": "low", "priority": 2, "steps": ["Save"]});
+        assert_eq!(check_schema(&schema, &report), Ok(()));
+        assert_eq!(check_schema(&schema, &json!({"summary": "x", "severity": "low", "priority": 2})), Err("$ has no steps".to_string()));
+        let mut wrong = report.clone();
+        wrong["steps"] = json!(["Save", 3]);
+        assert_eq!(check_schema(&schema, &wrong), Err("$.steps[1] is 3, not string".to_string()));
+        wrong["steps"] = json!([]);
+        assert!(check_schema(&schema, &wrong).unwrap_err().contains("0 items"));
+        assert!(check_schema(&schema, &json!({"severity": "urgent"})).is_err());
+        assert!(check_schema(&Finding::json_schema(), &json!({"path": "a.rs", "line": 1, "severity": null})).is_ok());
+    }
+
+    #[test]
+    fn test_derive_extract() {
+        let schema = response_schema::<Report>();
+        assert_eq!(schema.name, "report");
+        assert_eq!(schema.schema["properties"]["summary"], json!({"type": "string", "description": "One line on what goes wrong", "minLength": 1}));
+        assert_eq!(schema.schema["properties"]["severity"]["enum"], json!(["low", "high"]));
+        assert_eq!(schema.schema["properties"]["priority"]["maximum"], json!(5.0));
+        assert_eq!(schema.schema["properties"]["steps"]["minItems"], json!(1));
+        assert!(extract_prompt::<Report>().ends_with("\n\nA bug report."));
+
+        let report = |severity: &str, priority: u8| Report {
+            summary: "Crash on save".to_string(),
+            severity: severity.to_string(),
+            priority,
+            steps: vec!["Open a file".to_string(), "Save it".to_string()],
+        };
+        assert_eq!(report("high", 2).validate(), Ok(()));
+        assert_eq!(report("urgent", 2).validate(), Err("severity is \"urgent\", not one of low, high".to_string()));
+        assert!(report("low", 9).validate().unwrap_err().contains("above the maximum"));
+    }
+}
@@ -0,0 +1,73 @@
+use crate::agent::{self, Controls};
+use crate::chat::Chat;
+use crate::config::ProjectConfig;
+use crate::inference::types::{ContentItem, Message, Role};
+
+/// The tool the model delegates a subtask to a sub-agent with.
+pub const TASK_TOOL: &str = "task";
+
+/// Who a sub-agent works for and the tools it may use.
+#[derive(Debug, Clone)]
+pub struct Delegation {
+    /// Usage and tool runs are recorded against this session, the sub-agent has none of its own
+    pub parent_session: String,
+    pub tools: Vec<String>,
+}
+
+impl Delegation {
+    /// Added to the sub-agent's system prompt.
+    pub fn prompt(&self) -> String {
+        format!(
+            "\nYou are a sub-agent handling one scoped subtask for another agent, which only sees your final answer.  \
+            Only these tools are available: {}.  When done, answer with a concise summary of what you found or did, \
+            with file paths, line numbers and identifiers verbatim.\n",
+            self.tools.join(", "),
+        )
+    }
+
+    pub fn allows(&self, tool: &str) -> bool {
+        self.tools.iter().any(|allowed| allowed == tool)
+    }
+}
+
+/*
+    * Runs `description` as a fresh turn of a sub-agent on the small model and returns its
+    * final answer.  The sub-agent starts from an empty history and is limited to
+    * `subagent_tools`, so the reading and searching it does stays out of the parent's
+    * context.  It can't delegate further.
+*/
+pub async fn run(config: &ProjectConfig, parent_session: &str, description: &str) -> Result<String, anyhow::Error> {
+    let delegation = Delegation {
+        parent_session: parent_session.to_string(),
+        tools: config.subagent_tools.iter().filter(|tool| *tool != TASK_TOOL).cloned().collect(),
+    };
+    let mut child = Chat::for_task(config.with_small_model(), delegation).await?;
+    let message = Message { role: Role::User, content: vec![ContentItem::Text { text: description.to_string() }] };
+    let (_source, mut cancel) = agent::cancel_pair();
+    let outcome = agent::run_turn(&mut child, message, &mut |_| {}, &mut Controls::default(), &mut cancel).await
+        .map_err(|e| anyhow::anyhow!("The sub-agent failed: {}", e))?;
+    let summary = outcome.reply.content.iter()
+        .filter_map(|item| match item {
+            ContentItem::Text { text } => Some(text.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<&str>>()
+        .join("\n");
+    if summary.trim().is_empty() {
+        return Err(anyhow::anyhow!("The sub-agent finished without a summary"));
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delegation_tools() {
+        let delegation = Delegation { parent_session: "s1".to_string(), tools: vec!["read_file".to_string(), "execute".to_string()] };
+        assert!(delegation.allows("execute"));
+        assert!(!delegation.allows("write_file"));
+        assert!(delegation.prompt().contains("Only these tools are available: read_file, execute."));
+    }
+}
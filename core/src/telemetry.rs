@@ -0,0 +1,272 @@
+use std::collections::BTreeMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Map, Value};
+
+use crate::config::TelemetryConfig;
+use crate::inference::types::Usage;
+use crate::models;
+use crate::transport;
+
+// How often spans and counters are sent while pprog keeps running, like in `serve`
+const EXPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+// Spans kept for the next export, older ones are dropped when the collector is down
+const MAX_PENDING_SPANS: usize = 4096;
+
+const SCOPE: &str = "pprog";
+
+// A counter's name and attributes, sorted so repeated adds land on the same point
+type CounterKey = (&'static str, Vec<(&'static str, String)>);
+
+/// A span of pprog's tracing that ended, for export.
+pub struct FinishedSpan<'a> {
+    pub trace_id: u128,
+    pub span_id: u64,
+    pub parent_span_id: Option<u64>,
+    pub name: &'static str,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub fields: &'a Map<String, Value>,
+}
+
+/*
+    * Sends the `tracing` spans of model requests, tool runs and turns, and counters of tokens
+    * and cost, to an OpenTelemetry collector over OTLP/HTTP with JSON bodies.  It only runs
+    * when `[telemetry] endpoint` is set, and exports in the background every
+    * `EXPORT_INTERVAL` and once more on `flush` before pprog exits.
+*/
+struct Exporter {
+    client: reqwest::Client,
+    endpoint: String,
+    headers: BTreeMap<String, String>,
+    resource: Value,
+    started: SystemTime,
+    spans: Mutex<Vec<Value>>,
+    counters: Mutex<BTreeMap<CounterKey, f64>>,
+}
+
+static EXPORTER: OnceLock<Exporter> = OnceLock::new();
+
+/// Starts exporting to `config.endpoint`, doing nothing when it is empty.  Needs a tokio runtime.
+pub fn init(config: &TelemetryConfig) {
+    if config.endpoint.is_empty() {
+        return;
+    }
+    let exporter = Exporter {
+        client: transport::client(),
+        endpoint: config.endpoint.trim_end_matches('/').to_string(),
+        headers: config.headers.clone(),
+        resource: json!({"attributes": attributes(&Map::from_iter([
+            ("service.name".to_string(), json!(config.service_name)),
+            ("service.version".to_string(), json!(env!("CARGO_PKG_VERSION"))),
+        ]))}),
+        started: SystemTime::now(),
+        spans: Mutex::new(Vec::new()),
+        counters: Mutex::new(BTreeMap::new()),
+    };
+    if EXPORTER.set(exporter).is_ok() {
+        tokio::spawn(async {
+            let mut interval = tokio::time::interval(EXPORT_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                flush().await;
+            }
+        });
+    }
+}
+
+pub fn enabled() -> bool {
+    EXPORTER.get().is_some()
+}
+
+/// Queues `span` for the next export.  Spans from other crates are left out, the export's
+/// own HTTP requests would otherwise be traced and exported in turn.
+pub fn record_span(target: &str, span: FinishedSpan) {
+    let Some(exporter) = EXPORTER.get() else { return };
+    let ours = [SCOPE, "pprog_core"].iter().any(|scope| target == *scope || target.strip_prefix(scope).is_some_and(|rest| rest.starts_with("::")));
+    if !ours {
+        return;
+    }
+    let mut spans = exporter.spans.lock().unwrap();
+    if spans.len() < MAX_PENDING_SPANS {
+        spans.push(span_json(&span));
+    }
+}
+
+/// Adds a request's tokens, and its cost when the model's prices are known, to the counters.
+pub fn record_usage(provider: &str, model: &str, usage: &Usage) {
+    let Some(exporter) = EXPORTER.get() else { return };
+    let mut counters = exporter.counters.lock().unwrap();
+    let tokens = [
+        ("input", usage.input_tokens),
+        ("output", usage.output_tokens),
+        ("cache_read", usage.cache_read_input_tokens),
+        ("cache_write", usage.cache_creation_input_tokens),
+    ];
+    for (kind, count) in tokens.into_iter().filter(|(_, count)| *count > 0) {
+        let key = ("pprog.tokens", vec![("model", model.to_string()), ("provider", provider.to_string()), ("type", kind.to_string())]);
+        *counters.entry(key).or_default() += count as f64;
+    }
+    if let Some(info) = models::lookup(model) {
+        let key = ("pprog.cost", vec![("model", model.to_string()), ("provider", provider.to_string())]);
+        *counters.entry(key).or_default() += info.cost(usage);
+    }
+}
+
+/// Sends what was recorded since the last export.  Failures are logged, not returned.
+pub async fn flush() {
+    let Some(exporter) = EXPORTER.get() else { return };
+    let spans = std::mem::take(&mut *exporter.spans.lock().unwrap());
+    if !spans.is_empty() {
+        exporter.post("/v1/traces", traces_body(&exporter.resource, spans)).await;
+    }
+    let counters = exporter.counters.lock().unwrap().clone();
+    if !counters.is_empty() {
+        exporter.post("/v1/metrics", metrics_body(&exporter.resource, &counters, exporter.started, SystemTime::now())).await;
+    }
+}
+
+impl Exporter {
+    async fn post(&self, path: &str, body: Value) {
+        let mut request = self.client.post(format!("{}{}", self.endpoint, path)).json(&body);
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        match request.send().await {
+            Ok(response) if !response.status().is_success() => {
+                log::warn!("The OTLP export to {}{} failed with {}", self.endpoint, path, response.status());
+            },
+            Ok(_) => {},
+            Err(e) => log::warn!("The OTLP export to {}{} failed: {}", self.endpoint, path, e),
+        }
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().to_string()
+}
+
+// OTLP's key-value list, with 64-bit integers as strings like its JSON encoding wants
+fn attributes(fields: &Map<String, Value>) -> Vec<Value> {
+    fields.iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::String(text) => json!({"stringValue": text}),
+                Value::Bool(flag) => json!({"boolValue": flag}),
+                Value::Number(number) if number.is_f64() => json!({"doubleValue": number}),
+                Value::Number(number) => json!({"intValue": number.to_string()}),
+                value => json!({"stringValue": value.to_string()}),
+            };
+            json!({"key": key, "value": value})
+        })
+        .collect()
+}
+
+fn span_json(span: &FinishedSpan) -> Value {
+    let mut fields = span.fields.clone();
+    let error = fields.remove("error");
+    let mut json = json!({
+        "traceId": format!("{:032x}", span.trace_id),
+        "spanId": format!("{:016x}", span.span_id),
+        "name": span.name,
+        // SPAN_KIND_INTERNAL
+        "kind": 1,
+        "startTimeUnixNano": unix_nanos(span.start),
+        "endTimeUnixNano": unix_nanos(span.end),
+        "attributes": attributes(&fields),
+    });
+    if let Some(parent) = span.parent_span_id {
+        json["parentSpanId"] = json!(format!("{:016x}", parent));
+    }
+    if let Some(error) = error {
+        // STATUS_CODE_ERROR
+        json["status"] = json!({"code": 2, "message": error.as_str().map_or_else(|| error.to_string(), str::to_string)});
+    }
+    json
+}
+
+fn traces_body(resource: &Value, spans: Vec<Value>) -> Value {
+    json!({"resourceSpans": [{
+        "resource": resource,
+        "scopeSpans": [{"scope": {"name": SCOPE}, "spans": spans}],
+    }]})
+}
+
+fn metrics_body(resource: &Value, counters: &BTreeMap<CounterKey, f64>, started: SystemTime, now: SystemTime) -> Value {
+    let mut points: BTreeMap<&str, Vec<Value>> = BTreeMap::new();
+    for ((name, labels), value) in counters {
+        let labels = labels.iter().map(|(key, value)| (key.to_string(), json!(value))).collect::<Map<String, Value>>();
+        let mut point = json!({
+            "attributes": attributes(&labels),
+            "startTimeUnixNano": unix_nanos(started),
+            "timeUnixNano": unix_nanos(now),
+        });
+        match *name {
+            "pprog.cost" => point["asDouble"] = json!(value),
+            _ => point["asInt"] = json!((*value as u64).to_string()),
+        }
+        points.entry(name).or_default().push(point);
+    }
+    let metrics = points.into_iter()
+        .map(|(name, points)| json!({
+            "name": name,
+            "unit": if name == "pprog.cost" { "USD" } else { "{token}" },
+            // AGGREGATION_TEMPORALITY_CUMULATIVE, every export has the totals since `started`
+            "sum": {"aggregationTemporality": 2, "isMonotonic": true, "dataPoints": points},
+        }))
+        .collect::<Vec<Value>>();
+    json!({"resourceMetrics": [{
+        "resource": resource,
+        "scopeMetrics": [{"scope": {"name": SCOPE}, "metrics": metrics}],
+    }]})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_otlp_bodies() {
+        let resource = json!({"attributes": []});
+        let started = UNIX_EPOCH + Duration::from_secs(1);
+        let fields = Map::from_iter([
+            ("provider".to_string(), json!("anthropic")),
+            ("output_tokens".to_string(), json!(40)),
+            ("error".to_string(), json!("Anthropic returned 529")),
+        ]);
+        let span = FinishedSpan {
+            trace_id: 0xabc,
+            span_id: 7,
+            parent_span_id: Some(3),
+            name: "query_model",
+            start: started,
+            end: started + Duration::from_millis(250),
+            fields: &fields,
+        };
+        let traces = traces_body(&resource, vec![span_json(&span)]);
+        let span = &traces["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["traceId"], json!("00000000000000000000000000000abc"));
+        assert_eq!(span["parentSpanId"], json!("0000000000000003"));
+        assert_eq!(span["endTimeUnixNano"], json!("1250000000"));
+        assert_eq!(span["attributes"], json!([
+            {"key": "output_tokens", "value": {"intValue": "40"}},
+            {"key": "provider", "value": {"stringValue": "anthropic"}},
+        ]));
+        assert_eq!(span["status"], json!({"code": 2, "message": "Anthropic returned 529"}));
+
+        let counters = BTreeMap::from([
+            (("pprog.cost", vec![("model", "gpt-4o".to_string())]), 0.25),
+            (("pprog.tokens", vec![("model", "gpt-4o".to_string()), ("type", "input".to_string())]), 1200.0),
+        ]);
+        let metrics = metrics_body(&resource, &counters, started, started + Duration::from_secs(10));
+        let metrics = &metrics["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+        assert_eq!(metrics[0]["name"], json!("pprog.cost"));
+        assert_eq!(metrics[0]["sum"]["dataPoints"][0]["asDouble"], json!(0.25));
+        assert_eq!(metrics[1]["unit"], json!("{token}"));
+        assert_eq!(metrics[1]["sum"]["dataPoints"][0]["asInt"], json!("1200"));
+        assert_eq!(metrics[1]["sum"]["dataPoints"][0]["startTimeUnixNano"], json!("1000000000"));
+    }
+}
@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+
+/// The tool the model keeps its task list with.
+pub const TODO_TOOL: &str = "todo";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TodoStatus {
+    Pending,
+    InProgress,
+    Done,
+}
+
+impl TodoStatus {
+    fn marker(self) -> &'static str {
+        match self {
+            TodoStatus::Pending => "[ ]",
+            TodoStatus::InProgress => "[~]",
+            TodoStatus::Done => "[x]",
+        }
+    }
+}
+
+/// One entry of the task list the agent keeps for a session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TodoItem {
+    pub text: String,
+    pub status: TodoStatus,
+}
+
+/// Parses the list sent with the `todo` tool, one task per line starting with its status.
+pub fn parse(list: &str) -> Result<Vec<TodoItem>, anyhow::Error> {
+    list.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .enumerate()
+        .map(|(idx, line)| {
+            let line = line.strip_prefix("- ").unwrap_or(line);
+            let (status, text) = match line.get(..3) {
+                Some("[ ]") => (TodoStatus::Pending, &line[3..]),
+                Some("[~]") => (TodoStatus::InProgress, &line[3..]),
+                Some("[x]" | "[X]") => (TodoStatus::Done, &line[3..]),
+                _ => return Err(anyhow::anyhow!("Task {} doesn't start with [ ], [~] or [x]: {}", idx + 1, line)),
+            };
+            Ok(TodoItem { text: text.trim().to_string(), status })
+        })
+        .collect()
+}
+
+/// The list as the tool takes it, one task per line.
+pub fn render(items: &[TodoItem]) -> String {
+    items.iter()
+        .map(|item| format!("{} {}", item.status.marker(), item.text))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// How far along the list is, e.g. "2 done, 1 in progress, 3 pending".
+pub fn summary(items: &[TodoItem]) -> String {
+    let count = |status| items.iter().filter(|item| item.status == status).count();
+    format!("{} done, {} in progress, {} pending", count(TodoStatus::Done), count(TodoStatus::InProgress), count(TodoStatus::Pending))
+}
+
+/// Added to the system prompt so a resumed session carries on where the list left off.
+pub fn prompt(items: &[TodoItem]) -> String {
+    format!("\nYour task list for this session so far, keep it current with the `todo` tool:\n{}\n", render(items))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_render() -> Result<(), anyhow::Error> {
+        let items = parse("[x] Read src/main.rs\n\n- [~] Add the flag\n[ ]Run cargo test")?;
+        assert_eq!(items[1], TodoItem { text: "Add the flag".to_string(), status: TodoStatus::InProgress });
+        assert_eq!(render(&items), "[x] Read src/main.rs\n[~] Add the flag\n[ ] Run cargo test");
+        assert_eq!(summary(&items), "1 done, 1 in progress, 1 pending");
+        assert!(parse("[ ] ok\nUpdate docs").is_err());
+        Ok(())
+    }
+}
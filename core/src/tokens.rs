@@ -0,0 +1,97 @@
+use tokenizers::Tokenizer;
+
+static GPT2_JSON: &[u8] = include_bytes!("../tokenizers/gpt2.json");
+
+// Characters of English text or code per Claude token, Anthropic's own rule of thumb
+const CLAUDE_CHARS_PER_TOKEN: f64 = 3.5;
+
+/*
+    * Counts tokens on this machine, so deciding what fits the context window or a budget
+    * never needs a round trip to a provider's count_tokens endpoint.  OpenAI-style models get
+    * the bundled BPE tokenizer, which is close to theirs.  Claude's tokenizer isn't published
+    * and the BPE one undercounts it, so Claude models get an estimate from the characters:
+    * ASCII at `CLAUDE_CHARS_PER_TOKEN`, anything else a token a character.  It errs on the
+    * high side, which is the safe one for fitting and budgets.
+*/
+pub enum TokenCounter {
+    Bpe(Box<Tokenizer>),
+    Estimate,
+}
+
+impl TokenCounter {
+    /// The bundled BPE tokenizer, or the estimate should it fail to load.
+    pub fn bpe() -> Self {
+        match Tokenizer::from_bytes(GPT2_JSON) {
+            Ok(tokenizer) => TokenCounter::Bpe(Box::new(tokenizer)),
+            Err(e) => {
+                log::error!("Failed to load the tokenizer, estimating tokens from characters: {}", e);
+                TokenCounter::Estimate
+            },
+        }
+    }
+
+    /// The closest counter there is for `model`.
+    pub fn for_model(model: &str) -> Self {
+        match model.contains("claude") {
+            true => TokenCounter::Estimate,
+            false => TokenCounter::bpe(),
+        }
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        match self {
+            TokenCounter::Bpe(tokenizer) => tokenizer.encode(text, false).map(|encoding| encoding.len()).unwrap_or_else(|_| estimate(text)),
+            TokenCounter::Estimate => estimate(text),
+        }
+    }
+
+    /// How the counts are made, for `--dry-run`.
+    pub fn method(&self) -> &'static str {
+        match self {
+            TokenCounter::Bpe(_) => "BPE tokenizer",
+            TokenCounter::Estimate => "estimate from characters",
+        }
+    }
+}
+
+/// Claude tokens in `text`, estimated from its characters.
+pub fn estimate(text: &str) -> usize {
+    let ascii = text.bytes().filter(u8::is_ascii).count();
+    let other = text.chars().filter(|c| !c.is_ascii()).count();
+    (ascii as f64 / CLAUDE_CHARS_PER_TOKEN).ceil() as usize + other
+}
+
+/// The tokens of a request as it would be sent, counted locally.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RequestEstimate {
+    pub system: usize,
+    /// The conversation fitted to the window, with any examples ahead of it
+    pub messages: usize,
+    pub window: usize,
+    pub reserved_output: usize,
+    pub method: &'static str,
+}
+
+impl RequestEstimate {
+    pub fn input(&self) -> usize {
+        self.system + self.messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters() {
+        assert_eq!(estimate(""), 0);
+        assert_eq!(estimate("Hello, world"), 4);
+        assert_eq!(estimate("héllo"), 3);
+        assert_eq!(estimate("日本語"), 3);
+
+        let counter = TokenCounter::for_model("gpt-4o-mini");
+        assert!(matches!(counter, TokenCounter::Bpe(_)));
+        assert_eq!(counter.count("Hello world"), 2);
+        assert!(matches!(TokenCounter::for_model("anthropic.claude-3-5-sonnet-20241022-v2:0"), TokenCounter::Estimate));
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde_json::Value;
+
+use crate::largefile::LARGE_FILE_TOOL;
+use crate::sandbox::Sandbox;
+
+// Tools whose result depends only on their arguments and the files they name, with the
+// field holding the file
+const CACHEABLE: &[(&str, &str)] = &[("read_file", "path"), (LARGE_FILE_TOOL, "path")];
+
+// Tools that may change files, a call to one empties the cache
+const WRITING: &[&str] = &["write_file", "execute", "compile_check"];
+
+// What a file was when its result was cached, any change to it shows in one of these
+#[derive(Debug, Clone, PartialEq)]
+struct Stamp {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Option<Self> {
+        let meta = std::fs::metadata(path).ok()?;
+        Some(Stamp { path: path.to_path_buf(), modified: meta.modified().ok(), len: meta.len() })
+    }
+}
+
+// `value` with object keys sorted, so the same arguments in another order make the same key
+fn canonical(value: &Value) -> String {
+    match value {
+        Value::Object(object) => {
+            let mut fields = object.iter().collect::<Vec<(&String, &Value)>>();
+            fields.sort_by_key(|(key, _)| *key);
+            let fields = fields.iter()
+                .map(|(key, value)| format!("{}:{}", Value::String(key.to_string()), canonical(value)))
+                .collect::<Vec<String>>();
+            format!("{{{}}}", fields.join(","))
+        },
+        Value::Array(items) => format!("[{}]", items.iter().map(canonical).collect::<Vec<String>>().join(",")),
+        value => value.to_string(),
+    }
+}
+
+/*
+    * Results of tool calls that only read, kept for the rest of the session so a file the
+    * model reads again comes back without touching the disk.  An entry is keyed by the tool
+    * and its arguments, with the path resolved by the sandbox the tools run in, so
+    * `./src/main.rs` and `src/main.rs` are the same file the tool would read, and holds the modification time and size of the file it read: a file that changed
+    * since is read again.  Any call that may write empties the cache, a command can change
+    * files without the model naming them.
+*/
+#[derive(Debug, Default)]
+pub struct ToolCache {
+    entries: HashMap<String, (Stamp, String)>,
+}
+
+impl ToolCache {
+    // The key and file of a call to a cacheable tool, None for any other
+    fn key(sandbox: &Sandbox, name: &str, input: &Value) -> Option<(String, PathBuf)> {
+        let (_, field) = CACHEABLE.iter().find(|(tool, _)| *tool == name)?;
+        let path = sandbox.resolve_read(input.get(*field)?.as_str()?).ok()?;
+        let mut input = input.clone();
+        input[*field] = Value::String(path.to_string_lossy().to_string());
+        Some((format!("{}{}", name, canonical(&input)), path))
+    }
+
+    /// The cached result of calling `name` with `input`, when the file it read is unchanged.
+    pub fn get(&self, sandbox: &Sandbox, name: &str, input: &Value) -> Option<String> {
+        let (key, path) = Self::key(sandbox, name, input)?;
+        let (stamp, output) = self.entries.get(&key)?;
+        (Stamp::of(&path).as_ref() == Some(stamp)).then(|| output.clone())
+    }
+
+    /// Empties the cache ahead of a call to `name` when it may write, whether it succeeds or not.
+    pub fn invalidate(&mut self, name: &str) {
+        if WRITING.contains(&name) {
+            self.entries.clear();
+        }
+    }
+
+    /// Keeps the `output` of a call to a cacheable tool.
+    pub fn insert(&mut self, sandbox: &Sandbox, name: &str, input: &Value, output: &str) {
+        let Some((key, path)) = Self::key(sandbox, name, input) else { return };
+        if let Some(stamp) = Stamp::of(&path) {
+            self.entries.insert(key, (stamp, output.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SandboxConfig;
+    use serde_json::json;
+
+    #[test]
+    fn test_cache() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("notes.md"), "first")?;
+        let sandbox = Sandbox::new(dir.path(), &SandboxConfig::default())?;
+        let mut cache = ToolCache::default();
+        cache.insert(&sandbox, "read_file", &json!({"path": "notes.md"}), "first");
+        assert_eq!(cache.get(&sandbox, "read_file", &json!({"path": "./notes.md"})).as_deref(), Some("first"));
+        assert_eq!(canonical(&json!({"b": 1, "a": [{"d": 2, "c": 3}]})), r#"{"a":[{"c":3,"d":2}],"b":1}"#);
+
+        // A changed file is read again
+        std::fs::write(dir.path().join("notes.md"), "second, longer")?;
+        assert_eq!(cache.get(&sandbox, "read_file", &json!({"path": "notes.md"})), None);
+
+        cache.insert(&sandbox, "read_file", &json!({"path": "notes.md"}), "second, longer");
+        cache.invalidate("read_file");
+        assert!(cache.get(&sandbox, "read_file", &json!({"path": "notes.md"})).is_some());
+        cache.invalidate("execute");
+        cache.insert(&sandbox, "execute", &json!({"statement": "ls"}), "notes.md");
+        assert_eq!(cache.get(&sandbox, "read_file", &json!({"path": "notes.md"})), None);
+        assert_eq!(cache.get(&sandbox, "execute", &json!({"statement": "ls"})), None);
+
+        // Paths are those the tool reads, relative to the sandbox root rather than the git root
+        std::fs::create_dir_all(dir.path().join("app"))?;
+        std::fs::write(dir.path().join("app/notes.md"), "in app")?;
+        let app = Sandbox::new(dir.path(), &SandboxConfig { root: Some("app".to_string()), read_only: Vec::new() })?;
+        cache.insert(&sandbox, "read_file", &json!({"path": "notes.md"}), "second, longer");
+        assert_eq!(cache.get(&app, "read_file", &json!({"path": "notes.md"})), None);
+        cache.insert(&app, "read_file", &json!({"path": "notes.md"}), "in app");
+        assert_eq!(cache.get(&app, "read_file", &json!({"path": "./notes.md"})).as_deref(), Some("in app"));
+        assert_eq!(cache.get(&sandbox, "read_file", &json!({"path": "notes.md"})).as_deref(), Some("second, longer"));
+        // Nor is anything the sandbox refuses cached
+        cache.insert(&app, "read_file", &json!({"path": "../notes.md"}), "second, longer");
+        assert_eq!(cache.get(&app, "read_file", &json!({"path": "../notes.md"})), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{
+    checkpoint::Checkpoint,
+    config::SandboxConfig,
+    inference::types::ContentItem,
+    largefile::{self, Slice, LARGE_FILE_TOOL},
+    memory::ProjectMemory,
+    policy,
+    sandbox::Sandbox,
+    tree::GitTree,
+};
+
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Told to the model in read-only mode, so it doesn't reach for tools that will be refused.
+pub const READ_ONLY_PROMPT: &str = r#"
+You are in read-only mode.  Nothing in the project may change: `write_file` and `remember` are refused, and `execute` and `compile_check` only run commands that read, like `ls`, `cat`, `grep`, `find` and `git log`, `git diff` or `git show`, without redirecting output to files.  Answer from what you can read, and describe changes instead of making them.
+"#;
+
+/// Refuses every tool call that could change the project from now on, for `--read-only`.
+pub fn set_read_only() {
+    READ_ONLY.store(true, Ordering::Relaxed);
+}
+
+pub fn read_only() -> bool {
+    READ_ONLY.load(Ordering::Relaxed)
+}
+
+// Why a tool call can't run in read-only mode, None when it can
+fn read_only_refusal(name: &str, input: &serde_json::Value) -> Option<String> {
+    if !read_only() {
+        return None;
+    }
+    if let Some((_, field)) = policy::COMMAND_TOOLS.iter().find(|(tool, _)| *tool == name) {
+        let command = input[field].as_str().unwrap_or_default();
+        return (!policy::reads_only(command)).then(|| format!(
+            "Read-only mode: `{}` may change the project, so it was not run.  Only commands that read, without redirecting output to files, can run.",
+            command,
+        ));
+    }
+    matches!(name, "write_file" | "remember").then(|| format!("Read-only mode: {} is disabled, nothing in the project may change.", name))
+}
+
+/// Executes tool calls requested by the model against the project, the file tools held to
+/// the `sandbox`.
+#[derive(Default)]
+pub struct Tooler {
+    checkpoint: Option<Checkpoint>,
+    sandbox: SandboxConfig,
+}
+
+impl Tooler {
+    pub fn new(sandbox: SandboxConfig) -> Self {
+        Tooler { checkpoint: None, sandbox }
+    }
+
+    /// Starts a new checkpoint so files written from now on can be restored with undo.
+    pub fn begin_turn(&mut self, session_id: &str) {
+        self.checkpoint = Some(Checkpoint::new(session_id));
+    }
+
+    /// The checkpoint of the current turn, holding the files written so far.
+    pub fn checkpoint(&self) -> Option<&Checkpoint> {
+        self.checkpoint.as_ref()
+    }
+
+    fn extract_string_field<'a>(
+        input: &'a serde_json::Value,
+        field_name: &str
+    ) -> Result<&'a str, anyhow::Error> {
+        input.get(field_name)
+            .ok_or_else(|| anyhow::anyhow!("Missing '{}' field in tool input: {:?}", field_name, input))?
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("'{}' field is not a string: {:?}", field_name, input.get(field_name)))
+    }
+
+    pub fn execute(&mut self, content_item: &ContentItem) -> Result<String, anyhow::Error> {
+        match content_item {
+            ContentItem::ToolUse { name, input, .. } => {
+                if let Some(refusal) = read_only_refusal(name, input) {
+                    return Err(anyhow::anyhow!(refusal));
+                }
+                match GitTree::get_git_root() {
+                    Ok(root_path) => {
+                        let sandbox = || Sandbox::new(&root_path, &self.sandbox);
+                        let tool_result = match name.as_str() {
+                            "write_file" => {
+                                let content = Self::extract_string_field(input, "content")?;
+                                let file_path = Self::extract_string_field(input, "path")?;
+                                let full_path = sandbox()?.resolve_write(file_path)?;
+                                // Checkpoints keep paths relative to the git root
+                                let relative = full_path.strip_prefix(&root_path).unwrap_or(&full_path).to_string_lossy().to_string();
+                                if let Some(checkpoint) = self.checkpoint.as_mut() {
+                                    if let Err(e) = checkpoint.snapshot(&root_path, &relative) {
+                                        log::error!("Failed to checkpoint {:?}: {}", full_path, e);
+                                    }
+                                }
+                                std::fs::write(&full_path, content)
+                                    .map_err(|e| anyhow::anyhow!("Error writing to file {:?}: {:?}.", full_path, e))?;
+                                format!("Successfully wrote content to file {:?}.", full_path)
+                            },
+                            "read_file" => {
+                                let file_path = Self::extract_string_field(input, "path")?;
+                                let full_path = sandbox()?.resolve_read(file_path)?;
+                                std::fs::read_to_string(&full_path)
+                                    .map_err(|e| anyhow::anyhow!("Error reading file {:?}: {:?}.", full_path, e))?
+                            },
+                            LARGE_FILE_TOOL => {
+                                let file_path = Self::extract_string_field(input, "path")?;
+                                let full_path = sandbox()?.resolve_read(file_path)?;
+                                largefile::read(&full_path, &Slice::from_input(input)?)?
+                            },
+                            "compile_check" => {
+                                let check_cmd = Self::extract_string_field(input, "cmd")?;
+                                let output = Command::new("bash")
+                                    .arg("-c")
+                                    .arg(format!("{} & sleep 5; kill $!", check_cmd))
+                                    .current_dir(root_path)
+                                    .output()?;
+
+                                let stdout = String::from_utf8_lossy(&output.stdout);
+                                let stderr = String::from_utf8_lossy(&output.stderr);
+                                format!("Stdout:
+{}
+Stderr:
+{}", stdout, stderr)
+                            },
+                            "execute" => {
+                                let statement = Self::extract_string_field(input, "statement")?;
+                                let output = Command::new("bash")
+                                    .arg("-c")
+                                    .arg(statement)
+                                    .current_dir(root_path)
+                                    .output()?;
+
+                                let stdout = String::from_utf8_lossy(&output.stdout);
+                                let stderr = String::from_utf8_lossy(&output.stderr);
+                                let result = format!("Stdout:
+{}
+Stderr:
+{}", stdout, stderr);
+                                // A nonzero exit goes back as an error the model can correct
+                                if !output.status.success() {
+                                    return Err(anyhow::anyhow!("The statement ended with {}.\n{}", output.status, result));
+                                }
+                                result
+                            },
+                            "remember" => {
+                                let note = Self::extract_string_field(input, "note")?;
+                                ProjectMemory::remember(note)
+                                    .map_err(|e| anyhow::anyhow!("Error saving note to project memory: {:?}.", e))?;
+                                String::from("Saved note to project memory.")
+                            },
+                            _ => return Err(anyhow::anyhow!("Unknown tool: {}", name))
+                        };
+
+                        Ok(tool_result)
+                    },
+                    Err(e) => Err(anyhow::anyhow!("Error getting git root: {}", e))
+                }
+            },
+            _ => Err(anyhow::anyhow!("Not a tool use content item"))
+        }
+    }
+}
+
+/// Restores the files changed by the most recent agent turn.
+pub fn undo_last_turn() -> Result<Vec<String>, anyhow::Error> {
+    let checkpoint = Checkpoint::latest()?
+        .ok_or_else(|| anyhow::anyhow!("Nothing to undo"))?;
+    checkpoint.restore(&GitTree::get_git_root()?)
+}
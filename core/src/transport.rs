@@ -0,0 +1,151 @@
+use std::io::Write;
+use std::sync::OnceLock;
+
+use reqwest::header::{HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING};
+use reqwest::StatusCode;
+
+/// The encodings asked for on provider requests and undone by `Response`.
+pub const ENCODINGS: &str = "gzip, zstd";
+
+static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+/*
+    * The client the providers send with, one for the whole process so every provider,
+    * sub-agent and small model call reuses its pooled connections instead of a TLS
+    * handshake each.  HTTP/2 is negotiated where the server offers it, with the flow control
+    * window growing to fit the connection, so a long streamed reply isn't held back waiting
+    * on window updates.
+*/
+pub fn client() -> reqwest::Client {
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .http2_adaptive_window(true)
+            .build()
+            .unwrap_or_else(|e| {
+                log::error!("Failed to build the HTTP client, using the default one: {}", e);
+                reqwest::Client::new()
+            })
+    }).clone()
+}
+
+/// `request` asking for a compressed response, unless it already names the encodings it takes.
+pub fn compressed(mut request: reqwest::Request) -> reqwest::Request {
+    request.headers_mut().entry(ACCEPT_ENCODING).or_insert(HeaderValue::from_static(ENCODINGS));
+    request
+}
+
+// Undoes a `Content-Encoding` as the body arrives, so a stream can still be read chunk by chunk
+enum Decoder {
+    Identity,
+    Gzip(flate2::write::GzDecoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Decoder<'static, Vec<u8>>),
+}
+
+impl Decoder {
+    fn new(encoding: Option<&str>) -> Result<Self, anyhow::Error> {
+        Ok(match encoding.map(str::trim) {
+            Some("gzip" | "x-gzip") => Decoder::Gzip(flate2::write::GzDecoder::new(Vec::new())),
+            Some("zstd") => Decoder::Zstd(zstd::stream::write::Decoder::new(Vec::new())?),
+            Some("identity") | None => Decoder::Identity,
+            Some(encoding) => anyhow::bail!("Unsupported response encoding {}", encoding),
+        })
+    }
+
+    fn push(&mut self, chunk: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+        match self {
+            Decoder::Identity => Ok(chunk.to_vec()),
+            Decoder::Gzip(decoder) => {
+                decoder.write_all(chunk)?;
+                Ok(std::mem::take(decoder.get_mut()))
+            },
+            Decoder::Zstd(decoder) => {
+                decoder.write_all(chunk)?;
+                decoder.flush()?;
+                Ok(std::mem::take(decoder.get_mut()))
+            },
+        }
+    }
+
+    fn finish(&mut self) -> Result<Vec<u8>, std::io::Error> {
+        match self {
+            Decoder::Identity => Ok(Vec::new()),
+            Decoder::Gzip(decoder) => {
+                decoder.try_finish()?;
+                Ok(std::mem::take(decoder.get_mut()))
+            },
+            Decoder::Zstd(decoder) => {
+                decoder.flush()?;
+                Ok(std::mem::take(decoder.get_mut()))
+            },
+        }
+    }
+}
+
+/// A provider's response with its body decompressed.
+pub struct Response {
+    inner: reqwest::Response,
+    decoder: Decoder,
+    done: bool,
+}
+
+impl Response {
+    pub fn new(inner: reqwest::Response) -> Result<Self, anyhow::Error> {
+        let encoding = inner.headers().get(CONTENT_ENCODING).and_then(|encoding| encoding.to_str().ok()).map(str::to_lowercase);
+        let decoder = Decoder::new(encoding.as_deref())?;
+        Ok(Response { inner, decoder, done: false })
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.inner.status()
+    }
+
+    /// The next part of the body decoded, None at its end.
+    pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        while !self.done {
+            let decoded = match self.inner.chunk().await? {
+                Some(chunk) => self.decoder.push(&chunk)?,
+                None => {
+                    self.done = true;
+                    self.decoder.finish()?
+                },
+            };
+            if !decoded.is_empty() {
+                return Ok(Some(decoded));
+            }
+        }
+        Ok(None)
+    }
+
+    pub async fn text(mut self) -> Result<String, anyhow::Error> {
+        let mut body = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            body.extend(chunk);
+        }
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(encoding: &str, body: Vec<u8>) -> Result<Response, anyhow::Error> {
+        let response = http::Response::builder().status(200).header("Content-Encoding", encoding).body(body)?;
+        Response::new(response.into())
+    }
+
+    #[tokio::test]
+    async fn test_decoding() -> Result<(), anyhow::Error> {
+        let body = r#"{"content": [{"type": "text", "text": "Hello"}]}"#.repeat(50);
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(body.as_bytes())?;
+        assert_eq!(response("gzip", gzip.finish()?)?.text().await?, body);
+        assert_eq!(response("zstd", zstd::encode_all(body.as_bytes(), 3)?)?.text().await?, body);
+        assert_eq!(response("identity", body.clone().into_bytes())?.text().await?, body);
+        assert!(response("br", Vec::new()).is_err());
+
+        let request = reqwest::Client::new().get("https://api.anthropic.com/v1/messages").build()?;
+        assert_eq!(compressed(request).headers()[ACCEPT_ENCODING], ENCODINGS);
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+use std::process::Command;
+use std::path::PathBuf;
+
+pub struct GitTree;
+
+// Changed files listed in the status summary, the rest are counted
+const MAX_STATUS_LINES: usize = 20;
+
+// Recursive type for our tree structure
+enum TreeNode {
+    File,
+    Dir(BTreeMap<String, TreeNode>),
+}
+
+impl GitTree {
+    pub fn get_git_root() -> anyhow::Result<PathBuf> {
+        // Discover repository from current directory
+        let repo = gix::discover(".")?;
+        
+        // Get the working directory path and canonicalize it to get absolute path
+        let root_path = repo.work_dir()
+            .ok_or_else(|| anyhow::anyhow!("Repository has no working directory (might be bare)"))?
+            .canonicalize()?;
+        
+        Ok(root_path)
+    }
+
+    /// Paths relative to the root of every file git tracks or would track.
+    pub fn list_files() -> Result<Vec<String>, anyhow::Error> {
+        let root = Self::get_git_root()?;
+        let mut cmd = Command::new("git");
+        let cmd = cmd.arg("ls-files")
+            .arg("-o")
+            .arg("--exclude-standard")
+            .arg("-c")
+            .current_dir(&root);
+
+        let output = cmd.output()?;
+
+        if !output.status.success() {
+            return Err(std::io::Error::other(
+                String::from_utf8_lossy(&output.stderr).to_string()
+            ).into());
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(String::from)
+            .collect())
+    }
+
+    /*
+        * Whether `path` matches one of `patterns`, written like lines of a `.gitignore`.  A
+        * pattern without a slash matches a file or directory name anywhere in the tree, one
+        * with a slash matches from the root, and `*` stays within one directory while `**`
+        * crosses them.
+    */
+    pub fn matches(path: &str, patterns: &[String]) -> bool {
+        use gix::glob::wildmatch::Mode;
+        let matches = |pattern: &str, value: &str| gix::glob::wildmatch(pattern.into(), value.into(), Mode::NO_MATCH_SLASH_LITERAL);
+        patterns.iter()
+            .map(|pattern| pattern.trim().trim_end_matches('/'))
+            .filter(|pattern| !pattern.is_empty() && !pattern.starts_with('#'))
+            .any(|pattern| match pattern.strip_prefix('/').or(pattern.contains('/').then_some(pattern)) {
+                Some(anchored) => path.match_indices('/').map(|(idx, _)| &path[..idx]).chain([path]).any(|prefix| matches(anchored, prefix)),
+                None => path.split('/').any(|name| matches(pattern, name)),
+            })
+    }
+
+    /// `list_files` without the paths matching `ignore`, see `matches`.
+    pub fn workspace_files(ignore: &[String]) -> Result<Vec<String>, anyhow::Error> {
+        Ok(Self::list_files()?.into_iter().filter(|path| !Self::matches(path, ignore)).collect())
+    }
+
+    /// The branch checked out and the files with uncommitted changes, for the system prompt.
+    pub fn status_summary() -> Result<String, anyhow::Error> {
+        let root = Self::get_git_root()?;
+        let git = |args: &[&str]| -> Result<String, anyhow::Error> {
+            let output = Command::new("git").args(args).current_dir(&root).output()?;
+            if !output.status.success() {
+                return Err(anyhow::anyhow!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()));
+            }
+            Ok(String::from_utf8_lossy(&output.stdout).trim_end().to_string())
+        };
+        let branch = match git(&["symbolic-ref", "--short", "-q", "HEAD"]) {
+            Ok(branch) if !branch.is_empty() => format!("on branch {}", branch),
+            _ => "on a detached HEAD".to_string(),
+        };
+        let status = git(&["status", "--porcelain"])?;
+        let changed = status.lines().collect::<Vec<&str>>();
+        if changed.is_empty() {
+            return Ok(format!("{}, no uncommitted changes", branch));
+        }
+        let mut summary = format!("{}, uncommitted changes to {} file(s):\n{}", branch, changed.len(), changed.iter().take(MAX_STATUS_LINES).copied().collect::<Vec<&str>>().join("\n"));
+        if changed.len() > MAX_STATUS_LINES {
+            summary.push_str(&format!("\n… {} more", changed.len() - MAX_STATUS_LINES));
+        }
+        Ok(summary)
+    }
+
+    pub fn get_tree(ignore: &[String]) -> Result<String, anyhow::Error> {
+        let files = Self::workspace_files(ignore)?;
+
+        let mut tree = BTreeMap::new();
+        for path in files {
+            let parts: Vec<&str> = path.split('/').collect();
+            let file_name = parts.last().unwrap();
+            let dir_parts = &parts[..parts.len() - 1];
+
+            let mut current = &mut tree;
+            for &part in dir_parts {
+                current = current
+                    .entry(part.to_string())
+                    .or_insert(TreeNode::Dir(BTreeMap::new()))
+                    .as_dir_mut()
+                    .unwrap();
+            }
+            current.insert(file_name.to_string(), TreeNode::File);
+        }
+
+        let mut result = String::from(".\n");
+        Self::build_tree_string(&tree, "", &mut result);
+        Ok(result)
+    }
+
+    fn build_tree_string(
+        tree: &BTreeMap<String, TreeNode>,
+        prefix: &str,
+        result: &mut String,
+    ) {
+        for (i, (name, node)) in tree.iter().enumerate() {
+            let is_last_entry = i == tree.len() - 1;
+            let connector = if is_last_entry { "└── " } else { "├── " };
+            let next_prefix = if is_last_entry { "    " } else { "│   " };
+
+            result.push_str(&format!("{}{}{}
+", prefix, connector, name));
+
+            if let TreeNode::Dir(subtree) = node {
+                Self::build_tree_string(subtree, &format!("{}{}", prefix, next_prefix), result);
+            }
+        }
+    }
+}
+
+impl TreeNode {
+    fn as_dir_mut(&mut self) -> Option<&mut BTreeMap<String, TreeNode>> {
+        match self {
+            TreeNode::Dir(map) => Some(map),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_matches() {
+        let patterns = ["# generated".to_string(), "target/".to_string(), "*.snap".to_string(), "/docs/api".to_string(), "src/**/fixtures".to_string()];
+        assert!(GitTree::matches("target/debug/pprog", &patterns));
+        assert!(GitTree::matches("crates/core/target/x", &patterns));
+        assert!(GitTree::matches("tests/snapshots/a.snap", &patterns));
+        assert!(GitTree::matches("docs/api/index.html", &patterns));
+        assert!(!GitTree::matches("site/docs/api/index.html", &patterns));
+        assert!(GitTree::matches("src/parser/fixtures/a.rs", &patterns));
+        assert!(!GitTree::matches("src/main.rs", &patterns));
+    }
+
+    #[test]
+    fn test_get_git_root() -> Result<(), anyhow::Error> {
+
+        // Save current directory
+        let original_dir = env::current_dir()?;
+        
+        // Create a temporary directory structure
+        let temp_dir = tempfile::tempdir()?;
+        
+        // Initialize a git repo in the temp directory
+        let _repo = gix::init(temp_dir.path())?;
+        
+        // Change to a subdirectory to test discovery
+        let subdir = temp_dir.path().join("src").join("nested");
+        std::fs::create_dir_all(&subdir)?;
+        env::set_current_dir(&subdir)?;
+        
+        // Test finding the root
+        let root = GitTree::get_git_root()?;
+        
+        assert_eq!(root, temp_dir.path().canonicalize()?);
+        
+        // Restore original directory
+        env::set_current_dir(original_dir)?;
+        Ok(())
+    }
+}
+
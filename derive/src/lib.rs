@@ -0,0 +1,178 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::punctuated::Punctuated;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Fields, Lit, LitStr, Token};
+
+/*
+    * Derives `JsonSchema` and `Extract` from `pprog_core::structured` for a struct with named
+    * fields, so it can be filled in from text with `Chat::extract`.  Doc comments become the
+    * instructions and field descriptions the model sees, and `#[extract(..)]` on a field adds
+    * constraints that go in the schema and are checked on every reply:
+    *
+    *     #[extract(one_of = ["low", "high"])]   a string that is one of these
+    *     #[extract(min = 1, max = 10)]          a number in this range
+    *     #[extract(non_empty)]                  a string or list with something in it
+*/
+#[proc_macro_derive(Extract, attributes(extract))]
+pub fn derive_extract(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+#[derive(Default)]
+struct Constraints {
+    one_of: Vec<String>,
+    min: Option<f64>,
+    max: Option<f64>,
+    non_empty: bool,
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2, syn::Error> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(syn::Error::new_spanned(input, "Extract needs a struct with named fields")),
+        },
+        _ => return Err(syn::Error::new_spanned(input, "Extract needs a struct with named fields")),
+    };
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let schema_name = snake_case(&name.to_string());
+    let instructions = docs(&input.attrs);
+
+    let mut properties = Vec::new();
+    let mut checks = Vec::new();
+    for field in fields {
+        let ident = field.ident.as_ref().expect("named field");
+        let key = ident.to_string().trim_start_matches("r#").to_string();
+        let ty = &field.ty;
+        let description = docs(&field.attrs);
+        let constraints = constraints(&field.attrs)?;
+
+        let mut schema = quote! {
+            let mut schema = <#ty as ::pprog_core::structured::JsonSchema>::json_schema();
+        };
+        if !description.is_empty() {
+            schema.extend(quote! { schema = ::pprog_core::structured::described(schema, #description); });
+        }
+        if !constraints.one_of.is_empty() {
+            let one_of = &constraints.one_of;
+            schema.extend(quote! { ::pprog_core::structured::constrain(&mut schema, "enum", ::pprog_core::structured::Value::from(vec![#(#one_of),*])); });
+            checks.push(quote! {
+                if ![#(#one_of),*].contains(&self.#ident.as_str()) {
+                    return Err(format!("{} is {:?}, not one of {}", #key, self.#ident, [#(#one_of),*].join(", ")));
+                }
+            });
+        }
+        if let Some(min) = constraints.min {
+            schema.extend(quote! { ::pprog_core::structured::constrain(&mut schema, "minimum", ::pprog_core::structured::Value::from(#min)); });
+            checks.push(quote! {
+                if (self.#ident as f64) < #min {
+                    return Err(format!("{} is {}, below the minimum of {}", #key, self.#ident, #min));
+                }
+            });
+        }
+        if let Some(max) = constraints.max {
+            schema.extend(quote! { ::pprog_core::structured::constrain(&mut schema, "maximum", ::pprog_core::structured::Value::from(#max)); });
+            checks.push(quote! {
+                if (self.#ident as f64) > #max {
+                    return Err(format!("{} is {}, above the maximum of {}", #key, self.#ident, #max));
+                }
+            });
+        }
+        if constraints.non_empty {
+            schema.extend(quote! { ::pprog_core::structured::non_empty(&mut schema); });
+            checks.push(quote! {
+                if self.#ident.is_empty() {
+                    return Err(format!("{} is empty", #key));
+                }
+            });
+        }
+        properties.push(quote! { (#key, { #schema schema }) });
+    }
+
+    Ok(quote! {
+        impl #impl_generics ::pprog_core::structured::JsonSchema for #name #type_generics #where_clause {
+            fn json_schema() -> ::pprog_core::structured::Value {
+                ::pprog_core::structured::object(&[#(#properties),*])
+            }
+
+            fn schema_name() -> String {
+                String::from(#schema_name)
+            }
+        }
+
+        impl #impl_generics ::pprog_core::structured::Extract for #name #type_generics #where_clause {
+            fn instructions() -> String {
+                String::from(#instructions)
+            }
+
+            fn validate(&self) -> Result<(), String> {
+                #(#checks)*
+                Ok(())
+            }
+        }
+    })
+}
+
+// The doc comment lines, joined into a paragraph
+fn docs(attrs: &[Attribute]) -> String {
+    attrs.iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .filter_map(|attr| match &attr.meta {
+            syn::Meta::NameValue(meta) => match &meta.value {
+                syn::Expr::Lit(syn::ExprLit { lit: Lit::Str(line), .. }) => Some(line.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+        .trim()
+        .to_string()
+}
+
+fn constraints(attrs: &[Attribute]) -> Result<Constraints, syn::Error> {
+    let mut constraints = Constraints::default();
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("extract")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("one_of") {
+                let value = meta.value()?;
+                let content;
+                syn::bracketed!(content in value);
+                let values = Punctuated::<LitStr, Token![,]>::parse_terminated(&content)?;
+                constraints.one_of = values.iter().map(LitStr::value).collect();
+            } else if meta.path.is_ident("min") {
+                constraints.min = Some(number(&meta.value()?.parse()?)?);
+            } else if meta.path.is_ident("max") {
+                constraints.max = Some(number(&meta.value()?.parse()?)?);
+            } else if meta.path.is_ident("non_empty") {
+                constraints.non_empty = true;
+            } else {
+                return Err(meta.error("expected one_of, min, max or non_empty"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(constraints)
+}
+
+fn number(lit: &Lit) -> Result<f64, syn::Error> {
+    match lit {
+        Lit::Int(int) => int.base10_parse(),
+        Lit::Float(float) => float.base10_parse(),
+        _ => Err(syn::Error::new_spanned(lit, "expected a number")),
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (idx, c) in name.chars().enumerate() {
+        if c.is_uppercase() && idx > 0 {
+            snake.push('_');
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
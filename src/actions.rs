@@ -0,0 +1,167 @@
+use std::fs;
+use std::path::{Component, Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::chat::Chat;
+use crate::inference::types::{ContentItem, Message, Role};
+use crate::structured::{self, JsonSchema, Value};
+use crate::tree::GitTree;
+
+const ACTION_PROMPT: &str = "You carry out an instruction about a range of lines in a file of the user's project.  \
+Answer a question about the code, like a request to explain it, in `answer` with no edits.  \
+For an instruction to change code, give the edits that make the change, with a one line summary of them in `answer`.  \
+An edit replaces the lines `start_line` to `end_line` of a file, counted from 1 as in the numbered file, with `new_text`.  \
+An `end_line` one less than `start_line` inserts before that line, and a file that doesn't exist yet is created with \
+`start_line` 1 and `end_line` 0.  Keep to the indentation and style of the file.";
+
+/// An instruction about lines of a file, like "explain", "add tests" or "refactor to async".
+#[derive(Debug, Deserialize)]
+pub struct ActionRequest {
+    /// Relative to the root of the repository
+    pub path: String,
+    /// The first and last lines meant, from 1 and inclusive, the whole file when left out
+    #[serde(default)]
+    pub start_line: Option<usize>,
+    #[serde(default)]
+    pub end_line: Option<usize>,
+    pub instruction: String,
+    #[serde(default)]
+    pub persona: Option<String>,
+}
+
+/// Lines `start_line` to `end_line` of `path` replaced with `new_text`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Edit {
+    pub path: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub new_text: String,
+}
+
+impl JsonSchema for Edit {
+    fn json_schema() -> Value {
+        structured::object(&[
+            ("path", structured::described(String::json_schema(), "Relative to the root of the repository")),
+            ("start_line", structured::described(usize::json_schema(), "First line replaced, from 1")),
+            ("end_line", structured::described(usize::json_schema(), "Last line replaced, start_line - 1 to insert")),
+            ("new_text", structured::described(String::json_schema(), "The lines that take their place")),
+        ])
+    }
+}
+
+// What the model replies with
+#[derive(Debug, Deserialize)]
+struct Reply {
+    answer: String,
+    edits: Vec<Edit>,
+}
+
+impl JsonSchema for Reply {
+    fn json_schema() -> Value {
+        structured::object(&[
+            ("answer", structured::described(String::json_schema(), "The answer, or a one line summary of the edits")),
+            ("edits", structured::described(Vec::<Edit>::json_schema(), "The changes to make, none for a question")),
+        ])
+    }
+
+    fn schema_name() -> String {
+        String::from("code_action")
+    }
+}
+
+/// What an action comes back with, prose for a question and the edits for a change.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Action {
+    Prose { text: String },
+    Edit { summary: String, edits: Vec<Edit> },
+}
+
+// `path` as given, if it stays inside the repository
+fn relative(path: &str) -> Result<&Path, anyhow::Error> {
+    let relative = Path::new(path);
+    match relative.components().all(|part| matches!(part, Component::Normal(_) | Component::CurDir)) {
+        true => Ok(relative),
+        false => Err(anyhow::anyhow!("{} is not a path inside the repository", path)),
+    }
+}
+
+// Every edit stays in the repository and inside its file, one that doesn't exist has no lines
+fn check(edits: &[Edit], root: &Path) -> Result<(), anyhow::Error> {
+    for edit in edits {
+        let lines = fs::read_to_string(root.join(relative(&edit.path)?)).map_or(0, |text| text.lines().count());
+        if edit.start_line == 0 || edit.end_line + 1 < edit.start_line || edit.end_line > lines {
+            return Err(anyhow::anyhow!(
+                "The edit of lines {} to {} doesn't fit {}, which has {} lines",
+                edit.start_line, edit.end_line, edit.path, lines,
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn numbered(text: &str) -> String {
+    text.lines().enumerate().map(|(idx, line)| format!("{:>5}  {}\n", idx + 1, line)).collect()
+}
+
+/// Carries out `request` with a single structured reply, no chat session or tool calls.
+pub async fn run(chat: &Chat, request: &ActionRequest) -> Result<Action, anyhow::Error> {
+    let root = GitTree::get_git_root()?;
+    let path = root.join(relative(&request.path)?);
+    let text = fs::read_to_string(&path).map_err(|e| anyhow::anyhow!("Failed to read {}: {}", request.path, e))?;
+    let count = text.lines().count();
+    let (start, end) = (request.start_line.unwrap_or(1).max(1), request.end_line.unwrap_or(count).min(count));
+    if start > end {
+        return Err(anyhow::anyhow!("{} has no lines {} to {}", request.path, start, end));
+    }
+    let prompt = format!(
+        "{}:\n{}\nInstruction about lines {} to {}: {}",
+        request.path, numbered(&text), start, end, request.instruction.trim(),
+    );
+    let message = Message { role: Role::User, content: vec![ContentItem::Text { text: prompt }] };
+    let reply = chat.query_structured::<Reply>(None, ACTION_PROMPT, vec![message]).await?;
+    check(&reply.edits, &root)?;
+    Ok(match reply.edits.is_empty() {
+        true => Action::Prose { text: reply.answer },
+        false => Action::Edit { summary: reply.answer, edits: reply.edits },
+    })
+}
+
+/// Runs `request` on a chat of its own, as the persona it asks for or else `persona`.
+pub async fn answer(request: &ActionRequest, persona: Option<&str>, stop_sequences: &[String]) -> Result<Action, anyhow::Error> {
+    let mut chat = Chat::for_persona(request.persona.as_deref().or(persona)).await?;
+    if !stop_sequences.is_empty() {
+        chat.set_stop_sequences(stop_sequences.to_vec()).await?;
+    }
+    run(&chat, request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edits_are_checked() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("lib.rs"), "fn a() {}\nfn b() {}\n")?;
+        let edit = |path: &str, start_line: usize, end_line: usize| Edit {
+            path: path.to_string(),
+            start_line,
+            end_line,
+            new_text: String::new(),
+        };
+
+        assert!(check(&[edit("lib.rs", 1, 2), edit("lib.rs", 3, 2), edit("./tests.rs", 1, 0)], dir.path()).is_ok());
+        assert!(check(&[edit("lib.rs", 2, 3)], dir.path()).is_err());
+        assert!(check(&[edit("lib.rs", 0, 0)], dir.path()).is_err());
+        assert!(check(&[edit("new.rs", 1, 1)], dir.path()).is_err());
+        assert!(check(&[edit("../lib.rs", 1, 0)], dir.path()).is_err());
+        assert!(check(&[edit("/etc/passwd", 1, 0)], dir.path()).is_err());
+
+        assert_eq!(numbered("fn a() {}\n"), "    1  fn a() {}\n");
+        let action = serde_json::to_value(Action::Prose { text: "It greets.".to_string() })?;
+        assert_eq!(action, serde_json::json!({"kind": "prose", "text": "It greets."}));
+        Ok(())
+    }
+}
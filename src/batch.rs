@@ -0,0 +1,260 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use futures::StreamExt;
+use serde_json::{json, Value};
+
+use crate::agent::{self, AgentEvents, Controls};
+use crate::chat::Chat;
+use crate::config::ProjectConfig;
+use crate::inference::types::{ContentItem, Usage};
+use crate::mentions;
+
+// The first retry waits this long, each one after twice as long as the one before up to the
+// longest wait
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// One prompt of the input file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    pub id: String,
+    pub prompt: String,
+}
+
+/// What a batch run did, for the summary printed at its end.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub ran: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub usage: Usage,
+    pub cost: f64,
+    pub elapsed: Duration,
+}
+
+impl Summary {
+    pub fn report(&self) -> String {
+        format!(
+            "Ran {} prompts, {} failed, {} skipped as done in an earlier run, in {:.1}s\nTokens: {} in, {} cached, {} out, ${:.4}\n",
+            self.ran,
+            self.failed,
+            self.skipped,
+            self.elapsed.as_secs_f64(),
+            self.usage.input_tokens,
+            self.usage.cache_creation_input_tokens + self.usage.cache_read_input_tokens,
+            self.usage.output_tokens,
+            self.cost,
+        )
+    }
+}
+
+/// The prompts of a JSONL file, each line an object with a `prompt` and an optional `id` or a
+/// bare string.  Lines without an id are numbered from 1.
+pub fn parse(input: &str) -> Result<Vec<Item>, anyhow::Error> {
+    let mut items: Vec<Item> = Vec::new();
+    let mut ids = HashSet::new();
+    for (number, line) in input.lines().enumerate().map(|(idx, line)| (idx + 1, line.trim())) {
+        if line.is_empty() {
+            continue;
+        }
+        let value: Value = serde_json::from_str(line).map_err(|e| anyhow::anyhow!("Line {} is not JSON: {}", number, e))?;
+        let item = match value {
+            Value::String(prompt) => Item { id: number.to_string(), prompt },
+            Value::Object(object) => Item {
+                id: match object.get("id") {
+                    Some(Value::String(id)) => id.clone(),
+                    Some(Value::Number(id)) => id.to_string(),
+                    None => number.to_string(),
+                    Some(_) => anyhow::bail!("Line {} has an id that is neither a string nor a number", number),
+                },
+                prompt: object.get("prompt").and_then(Value::as_str)
+                    .ok_or_else(|| anyhow::anyhow!("Line {} has no prompt", number))?
+                    .to_string(),
+            },
+            _ => anyhow::bail!("Line {} is neither a prompt nor an object with one", number),
+        };
+        if !ids.insert(item.id.clone()) {
+            anyhow::bail!("Line {} repeats the id {}", number, item.id);
+        }
+        items.push(item);
+    }
+    Ok(items)
+}
+
+/// Ids the results written to `out` so far answered, failed ones left out so they run again.
+pub fn done(out: &str) -> BTreeSet<String> {
+    out.lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|result| result["ok"] == true)
+        .filter_map(|result| result["id"].as_str().map(str::to_string))
+        .collect()
+}
+
+#[derive(Default)]
+struct Tally {
+    usage: Usage,
+    tool_calls: usize,
+}
+
+impl AgentEvents for Tally {
+    fn on_tool_use_start(&mut self, _id: &str, _name: &str, _input: &serde_json::Value) {
+        self.tool_calls += 1;
+    }
+
+    fn on_usage(&mut self, usage: &Usage) {
+        self.usage.add(usage);
+    }
+}
+
+// One attempt at `item` in a session of its own, the tools run without asking like `pprog -p`
+async fn attempt(config: &ProjectConfig, item: &Item) -> (Result<(String, String), anyhow::Error>, Tally, f64) {
+    let mut tally = Tally::default();
+    let mut chat = match Chat::from_config(config.clone()).await {
+        Ok(chat) => chat,
+        Err(e) => return (Err(e), tally, 0.0),
+    };
+    let (_source, mut cancel) = agent::cancel_pair();
+    let message = mentions::user_message(&item.prompt);
+    let result = agent::run_turn_with(&mut chat, message, &mut tally, &mut Controls::default(), &mut cancel).await
+        .and_then(|outcome| match outcome.incomplete {
+            true => Err(anyhow::anyhow!("The reply was cut off")),
+            false => Ok(outcome.reply.content.iter()
+                .filter_map(|item| match item {
+                    ContentItem::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<&str>>()
+                .join("")),
+        })
+        .map(|reply| (reply, chat.session.id.clone()));
+    (result, tally, chat.session_cost().unwrap_or_default())
+}
+
+// How long to wait before retry `retry`, counting from 1
+fn retry_delay(retry: usize) -> Duration {
+    let doublings = retry.saturating_sub(1).min(16) as u32;
+    RETRY_DELAY.saturating_mul(2u32.pow(doublings)).min(MAX_RETRY_DELAY)
+}
+
+/*
+    * `item` run until it succeeds or `retries` more attempts failed, as its line of results.
+    * An attempt that got as far as running a tool isn't tried again, the tools may have
+    * changed files or run commands that doing it all over would repeat.
+*/
+async fn run_item(config: &ProjectConfig, item: &Item, retries: usize) -> (Value, Usage, f64) {
+    let mut usage = Usage::default();
+    let mut cost = 0.0;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        // Boxed, the future of a whole agent turn is too deep to lay out inline here
+        let (result, tally, spent) = Box::pin(attempt(config, item)).await;
+        usage.add(&tally.usage);
+        cost += spent;
+        match result {
+            Ok((reply, session)) => {
+                let line = json!({
+                    "id": item.id, "ok": true, "reply": reply, "session": session, "attempts": attempts,
+                    "tool_calls": tally.tool_calls, "usage": usage, "cost": cost,
+                });
+                return (line, usage, cost);
+            },
+            Err(e) if attempts > retries || tally.tool_calls > 0 => {
+                let error = match (tally.tool_calls > 0, attempts > retries) {
+                    (true, false) => format!("{:#}, not tried again since tools had run", e),
+                    _ => format!("{:#}", e),
+                };
+                let line = json!({
+                    "id": item.id, "ok": false, "error": error, "attempts": attempts,
+                    "tool_calls": tally.tool_calls, "usage": usage, "cost": cost,
+                });
+                return (line, usage, cost);
+            },
+            Err(e) => {
+                log::warn!("Prompt {} failed, trying again: {:#}", item.id, e);
+                tokio::time::sleep(retry_delay(attempts)).await;
+            },
+        }
+    }
+}
+
+/*
+    * Runs every prompt of `items` as an independent turn, at most `concurrency` at a time, and
+    * appends a JSON line for each to `out` as soon as it's done, in the order they finish.  A
+    * prompt whose id `out` already has a successful line for is skipped, so a run that was
+    * interrupted picks up where it stopped when started again with the same `out`.  A prompt
+    * that fails before any tool ran is tried `retries` more times, waiting longer each time,
+    * before its error is written.
+*/
+pub async fn run(
+    config: &ProjectConfig,
+    items: Vec<Item>,
+    out: &Path,
+    concurrency: usize,
+    retries: usize,
+    mut on_done: impl FnMut(&str, bool),
+) -> Result<Summary, anyhow::Error> {
+    let started = Instant::now();
+    let written = match fs::read_to_string(out) {
+        Ok(written) => written,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(anyhow::anyhow!("Failed to read {}: {}", out.display(), e)),
+    };
+    let done = done(&written);
+    let mut file = OpenOptions::new().create(true).append(true).open(out)?;
+    // The line an interrupted run was writing is cut off, the next one starts on a line of its own
+    if !written.is_empty() && !written.ends_with('\n') {
+        writeln!(file)?;
+    }
+
+    let (skipped, pending): (Vec<Item>, Vec<Item>) = items.into_iter().partition(|item| done.contains(&item.id));
+    let mut summary = Summary { skipped: skipped.len(), ..Default::default() };
+    let mut results = futures::stream::iter(pending.iter())
+        .map(|item| run_item(config, item, retries))
+        .buffer_unordered(concurrency.max(1));
+    while let Some((line, usage, cost)) = results.next().await {
+        writeln!(file, "{}", line)?;
+        file.flush()?;
+        let ok = line["ok"] == true;
+        summary.ran += 1;
+        summary.failed += usize::from(!ok);
+        summary.usage.add(&usage);
+        summary.cost += cost;
+        on_done(line["id"].as_str().unwrap_or_default(), ok);
+    }
+    summary.elapsed = started.elapsed();
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_resume() -> Result<(), anyhow::Error> {
+        let items = parse("{\"id\": \"a\", \"prompt\": \"Label this\"}\n\n\"Bare prompt\"\n{\"id\": 7, \"prompt\": \"Numbered\"}\n")?;
+        assert_eq!(items, vec![
+            Item { id: "a".to_string(), prompt: "Label this".to_string() },
+            Item { id: "3".to_string(), prompt: "Bare prompt".to_string() },
+            Item { id: "7".to_string(), prompt: "Numbered".to_string() },
+        ]);
+        assert!(parse("{\"id\": \"a\", \"prompt\": \"x\"}\n{\"id\": \"a\", \"prompt\": \"y\"}").is_err());
+        assert!(parse("{\"text\": \"x\"}").is_err());
+
+        let out = "{\"id\": \"a\", \"ok\": true}\n{\"id\": \"3\", \"ok\": false}\n{\"id\": \"7\", \"o";
+        assert_eq!(done(out), BTreeSet::from(["a".to_string()]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), Duration::from_secs(2));
+        assert_eq!(retry_delay(3), Duration::from_secs(8));
+        assert_eq!(retry_delay(6), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(usize::MAX), MAX_RETRY_DELAY);
+    }
+}
@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use crate::chat::Chat;
+use crate::config::ProjectConfig;
+use crate::inference::types::{self, ContentItem, Message, Role, Usage};
+use crate::models;
+
+// The standard prompts, from a one word reply to a page of code, so both TTFT and throughput show
+pub const PROMPTS: &[(&str, &str)] = &[
+    ("short", "Reply with just the word ok.  Do not call any tools."),
+    ("explain", "In about 150 words, explain what a mutex is and when to reach for one.  Do not call any tools."),
+    ("code", "Write a Rust function that parses a duration like 1h30m into seconds, with unit tests.  Do not call any tools."),
+];
+
+/// One request of a benchmark run.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub total: Duration,
+    pub first_token: Option<Duration>,
+    pub usage: Usage,
+}
+
+/// The requests made to a model, and how many failed.
+#[derive(Debug, Default)]
+pub struct ModelBench {
+    pub model: String,
+    pub samples: Vec<Sample>,
+    pub errors: usize,
+}
+
+/// The `p`th percentile of `durations` by nearest rank, `p` from 0 to 100.
+pub fn percentile(durations: &[Duration], p: f64) -> Option<Duration> {
+    let mut sorted = durations.to_vec();
+    sorted.sort();
+    let rank = ((p / 100.0 * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len().max(1));
+    sorted.get(rank - 1).copied()
+}
+
+/// The models to compare when none are named: the configured one, the small one and the personas'.
+pub fn configured_models(config: &ProjectConfig) -> Vec<String> {
+    let mut models = vec![config.model.clone()];
+    models.extend(config.personas.values().map(|persona| persona.model.clone()));
+    models.push(config.small_model.clone());
+    let mut seen = std::collections::BTreeSet::new();
+    models.retain(|model| !model.is_empty() && seen.insert(model.clone()));
+    models
+}
+
+/*
+    * Sends every one of `PROMPTS` to each model `runs` times, streamed and one after the
+    * other so requests don't queue behind each other, with the system prompt and tools of
+    * a normal chat so the numbers are what a session would see.  The requests count towards
+    * the usage of a session of their own, like any other.
+*/
+pub async fn run(config: &ProjectConfig, models: &[String], runs: usize, mut on_sample: impl FnMut(&str, &str)) -> Result<Vec<ModelBench>, anyhow::Error> {
+    let mut benches = Vec::new();
+    for model in models {
+        let mut config = config.clone();
+        config.model = model.clone();
+        let chat = Chat::from_config(config).await?;
+        let mut bench = ModelBench { model: model.clone(), ..Default::default() };
+        for (name, prompt) in PROMPTS {
+            for _ in 0..runs {
+                let message = Message { role: Role::User, content: vec![ContentItem::Text { text: prompt.to_string() }] };
+                let (system, messages) = chat.build_request(&[message])?;
+                match chat.query_streamed(&system, messages).await {
+                    Ok(response) => bench.samples.push(Sample {
+                        total: response.timing.as_ref().map(|timing| timing.total).unwrap_or_default(),
+                        first_token: response.timing.as_ref().and_then(|timing| timing.first_token),
+                        usage: response.usage.unwrap_or_default(),
+                    }),
+                    Err(e) => {
+                        log::error!("Benchmark request to {} failed: {}", model, e);
+                        bench.errors += 1;
+                    },
+                }
+                on_sample(model, name);
+            }
+        }
+        benches.push(bench);
+    }
+    Ok(benches)
+}
+
+fn seconds(duration: Option<Duration>) -> String {
+    duration.map_or("-".to_string(), |duration| format!("{:.2}s", duration.as_secs_f64()))
+}
+
+/// The models side by side: latency percentiles, median time to first token, output rate and cost.
+pub fn report(benches: &[ModelBench]) -> String {
+    let width = benches.iter().map(|bench| bench.model.chars().count()).chain(["Model".len()]).max().unwrap_or_default();
+    let mut table = format!(
+        "{:<width$}  {:>8}  {:>6}  {:>7}  {:>7}  {:>7}  {:>10}  {:>7}  {:>12}\n",
+        "Model", "Requests", "Errors", "p50", "p90", "p99", "TTFT p50", "tok/s", "Cost/request",
+    );
+    for bench in benches {
+        let totals = bench.samples.iter().map(|sample| sample.total).collect::<Vec<Duration>>();
+        let first_tokens = bench.samples.iter().filter_map(|sample| sample.first_token).collect::<Vec<Duration>>();
+        let output = bench.samples.iter().map(|sample| sample.usage.output_tokens).sum::<u32>();
+        let generating = bench.samples.iter()
+            .map(|sample| sample.total.saturating_sub(sample.first_token.unwrap_or_default()))
+            .sum::<Duration>();
+        let rate = types::output_rate(output, generating).map_or("-".to_string(), |rate| format!("{:.0}", rate));
+        let cost = match (models::lookup(&bench.model), bench.samples.len()) {
+            (Some(info), count) if count > 0 => format!("${:.4}", bench.samples.iter().map(|sample| info.cost(&sample.usage)).sum::<f64>() / count as f64),
+            _ => "-".to_string(),
+        };
+        table.push_str(&format!(
+            "{:<width$}  {:>8}  {:>6}  {:>7}  {:>7}  {:>7}  {:>10}  {:>7}  {:>12}\n",
+            bench.model,
+            bench.samples.len() + bench.errors,
+            bench.errors,
+            seconds(percentile(&totals, 50.0)),
+            seconds(percentile(&totals, 90.0)),
+            seconds(percentile(&totals, 99.0)),
+            seconds(percentile(&first_tokens, 50.0)),
+            rate,
+            cost,
+        ));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles_and_report() {
+        let durations = (1..=10).map(Duration::from_secs).collect::<Vec<Duration>>();
+        assert_eq!(percentile(&durations, 50.0), Some(Duration::from_secs(5)));
+        assert_eq!(percentile(&durations, 90.0), Some(Duration::from_secs(9)));
+        assert_eq!(percentile(&durations, 99.0), Some(Duration::from_secs(10)));
+        assert_eq!(percentile(&[], 50.0), None);
+
+        let sample = |total: u64, output_tokens: u32| Sample {
+            total: Duration::from_secs(total),
+            first_token: Some(Duration::from_millis(500)),
+            usage: Usage { input_tokens: 1000, output_tokens, ..Default::default() },
+        };
+        let benches = [ModelBench { model: "claude-3-5-haiku-latest".to_string(), samples: vec![sample(2, 150), sample(4, 350)], errors: 1 }];
+        let report = report(&benches);
+        let row = report.lines().nth(1).unwrap().split_whitespace().collect::<Vec<&str>>();
+        assert_eq!(row, ["claude-3-5-haiku-latest", "3", "1", "2.00s", "4.00s", "4.00s", "0.50s", "100", "$0.0018"]);
+    }
+}
@@ -0,0 +1,179 @@
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::chat::Chat;
+use crate::inference::types::{ContentItem, Message, Role};
+use crate::structured::{self, JsonSchema};
+
+// Added to the system prompt, a candidate is one reply and tool calls would be dropped
+const CANDIDATE_PROMPT: &str = "\nAnswer the request directly in this one reply, without calling any tools.\n";
+
+const JUDGE_PROMPT: &str = "You pick the best of several candidate answers to the same request.  \
+Judge correctness first, then completeness, then clarity.";
+
+// Columns narrower than this are hard to read, the candidates are stacked instead
+const MIN_COLUMN_WIDTH: usize = 32;
+const COLUMN_GAP: &str = " │ ";
+
+/// Asks for `n` answers to `message` after the session so far, all at once.  Candidates that
+/// failed or came back without text are dropped, and it errors only when none are left.
+pub async fn candidates(chat: &Chat, message: &Message, n: usize) -> Result<Vec<String>, anyhow::Error> {
+    let mut history = chat.session.messages.clone();
+    history.push(message.clone());
+    let (mut system_message, messages) = chat.build_request(&history)?;
+    system_message.push_str(CANDIDATE_PROMPT);
+    let replies = futures::future::join_all((0..n).map(|_| chat.query(&system_message, messages.clone()))).await;
+    let mut candidates = Vec::new();
+    let mut failure = None;
+    for reply in replies {
+        match reply {
+            Ok(response) => {
+                let text = response.content.iter()
+                    .filter_map(|item| match item {
+                        ContentItem::Text { text } => Some(text.as_str()),
+                        _ => None,
+                    })
+                    .collect::<Vec<&str>>()
+                    .join("\n");
+                if !text.trim().is_empty() {
+                    candidates.push(text);
+                }
+            },
+            Err(e) => {
+                log::warn!("A candidate failed: {:#}", e);
+                failure.get_or_insert(e);
+            },
+        }
+    }
+    match failure {
+        Some(e) if candidates.is_empty() => Err(e),
+        _ if candidates.is_empty() => Err(anyhow::anyhow!("None of the {} candidates answered with text", n)),
+        _ => Ok(candidates),
+    }
+}
+
+/// The candidates in columns of `width` characters altogether, stacked under numbered
+/// headers when the columns would be too narrow.
+pub fn side_by_side(candidates: &[String], width: usize) -> String {
+    let n = candidates.len().max(1);
+    let column = width.saturating_sub(textwrap::core::display_width(COLUMN_GAP) * (n - 1)) / n;
+    if column < MIN_COLUMN_WIDTH {
+        return candidates.iter().enumerate()
+            .map(|(idx, candidate)| format!("── #{} ──\n{}\n", idx + 1, candidate.trim_end()))
+            .collect::<Vec<String>>()
+            .join("\n");
+    }
+    let columns = candidates.iter().enumerate()
+        .map(|(idx, candidate)| {
+            let mut lines = vec![format!("#{}", idx + 1)];
+            for line in candidate.trim_end().lines() {
+                lines.extend(textwrap::wrap(line, column).into_iter().map(|part| part.into_owned()));
+            }
+            lines
+        })
+        .collect::<Vec<Vec<String>>>();
+    let height = columns.iter().map(Vec::len).max().unwrap_or(0);
+    (0..height)
+        .map(|row| {
+            let cells = columns.iter()
+                .map(|lines| {
+                    let cell = lines.get(row).map(String::as_str).unwrap_or_default();
+                    format!("{}{}", cell, " ".repeat(column.saturating_sub(textwrap::core::display_width(cell))))
+                })
+                .collect::<Vec<String>>();
+            format!("{}\n", cells.join(COLUMN_GAP).trim_end())
+        })
+        .collect()
+}
+
+// The candidate number a judge's reply starts with, from 0
+fn parse_choice(reply: &str, count: usize) -> Option<usize> {
+    let digits = reply.trim_start().trim_start_matches(['#', '*', ' '])
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect::<String>();
+    digits.parse::<usize>().ok().filter(|choice| (1..=count).contains(choice)).map(|choice| choice - 1)
+}
+
+// What the judge replies with
+#[derive(Debug, Deserialize)]
+struct Choice {
+    candidate: usize,
+    reason: String,
+}
+
+impl JsonSchema for Choice {
+    fn json_schema() -> Value {
+        structured::object(&[
+            ("candidate", structured::described(usize::json_schema(), "The number of the best candidate")),
+            ("reason", structured::described(String::json_schema(), "One sentence on why it is the best")),
+        ])
+    }
+
+    fn schema_name() -> String {
+        String::from("choice")
+    }
+}
+
+/// Has `model` pick the best candidate for `request`, returning its index and the reason given.
+pub async fn judge(chat: &Chat, model: &str, request: &str, candidates: &[String]) -> Result<(usize, String), anyhow::Error> {
+    let mut text = format!("Request:\n{}\n", request.trim());
+    for (idx, candidate) in candidates.iter().enumerate() {
+        text.push_str(&format!("\nCandidate {}:\n{}\n", idx + 1, candidate.trim()));
+    }
+    let message = Message { role: Role::User, content: vec![ContentItem::Text { text }] };
+    let choice = chat.query_structured::<Choice>(Some(model), JUDGE_PROMPT, vec![message]).await
+        .map_err(|e| e.context(format!("The judging by {} failed", model)))?;
+    if !(1..=candidates.len()).contains(&choice.candidate) {
+        return Err(anyhow::anyhow!("{} picked candidate {} of {}", model, choice.candidate, candidates.len()));
+    }
+    Ok((choice.candidate - 1, choice.reason.trim().to_string()))
+}
+
+/// Whether there is a terminal to pick a candidate on, stdin and stdout being taken.
+pub fn can_pick() -> bool {
+    OpenOptions::new().read(true).write(true).open("/dev/tty").is_ok()
+}
+
+/// Shows the candidates side by side on the terminal and asks which one to keep.
+pub fn pick_on_tty(candidates: &[String]) -> Result<usize, anyhow::Error> {
+    let mut tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    let width = crossterm::terminal::size().ok().filter(|(cols, _)| *cols > 0).map_or(80, |(cols, _)| cols as usize) - 1;
+    write!(tty, "{}", side_by_side(candidates, width))?;
+    let mut reader = BufReader::new(tty.try_clone()?);
+    loop {
+        write!(tty, "Keep which? [1-{}]: ", candidates.len())?;
+        tty.flush()?;
+        let mut answer = String::new();
+        if reader.read_line(&mut answer)? == 0 {
+            return Err(anyhow::anyhow!("No candidate picked"));
+        }
+        if let Some(choice) = parse_choice(&answer, candidates.len()) {
+            return Ok(choice);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_side_by_side_and_choice() {
+        let candidates = vec!["fn a() {}".to_string(), "fn b() {}\n\nreturns b".to_string()];
+        let columns = side_by_side(&candidates, 80);
+        let lines = columns.lines().collect::<Vec<&str>>();
+        assert_eq!(lines[0].trim_end(), format!("#1{}{}#2", " ".repeat(36), COLUMN_GAP));
+        assert!(lines[1].starts_with("fn a() {}") && lines[1].ends_with("fn b() {}"));
+        assert_eq!(lines.len(), 4);
+        assert!(side_by_side(&candidates, 40).starts_with("── #1 ──\nfn a() {}\n"));
+
+        assert_eq!(parse_choice("2\nShorter and correct.", 3), Some(1));
+        assert_eq!(parse_choice("**#3**", 3), Some(2));
+        assert_eq!(parse_choice("4", 3), None);
+        assert_eq!(parse_choice("The second", 3), None);
+    }
+}
@@ -0,0 +1,239 @@
+use std::fmt::Write as _;
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::chat::Chat;
+use crate::inference::types::{ContentItem, Message, Role};
+use crate::structured::{self, JsonSchema, Value};
+
+const CHANGELOG_PROMPT: &str = "You draft the release notes of a project from the commits since its last release.  \
+Sort the changes users of the project would notice into the Keep a Changelog categories: added for new features, \
+changed for changes to what exists, deprecated, removed, fixed for bug fixes and security for vulnerabilities.  \
+Write each entry as a short sentence for users rather than developers, and keep the pull request numbers like (#12) \
+of the commits it comes from.  Merge commits that make one change into one entry, and leave out changes users won't \
+notice, like refactoring, tests, CI and formatting.";
+
+const HEADER: &str = "# Changelog\n\n\
+All notable changes to this project will be documented in this file.\n\n\
+The format is based on [Keep a Changelog](https://keepachangelog.com/en/1.1.0/),\n\
+and this project adheres to [Semantic Versioning](https://semver.org/spec/v2.0.0.html).\n";
+
+// The log is cut at this, leaving out the oldest commits
+const MAX_LOG_CHARS: usize = 80_000;
+// Lines of a commit's body shown with its subject
+const BODY_LINES: usize = 6;
+
+/// A commit on the first-parent line, a merged pull request when it merged one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub hash: String,
+    pub title: String,
+    pub body: String,
+    pub pull_request: Option<u64>,
+}
+
+/// The entries of a release by Keep a Changelog category.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+pub struct ReleaseNotes {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub deprecated: Vec<String>,
+    pub removed: Vec<String>,
+    pub fixed: Vec<String>,
+    pub security: Vec<String>,
+}
+
+impl JsonSchema for ReleaseNotes {
+    fn json_schema() -> Value {
+        let entries = |description| structured::described(Vec::<String>::json_schema(), description);
+        structured::object(&[
+            ("added", entries("New features")),
+            ("changed", entries("Changes to existing behavior")),
+            ("deprecated", entries("Features to be removed in a later release")),
+            ("removed", entries("Features removed")),
+            ("fixed", entries("Bug fixes")),
+            ("security", entries("Fixed vulnerabilities")),
+        ])
+    }
+
+    fn schema_name() -> String {
+        String::from("release_notes")
+    }
+}
+
+impl ReleaseNotes {
+    /// The section of CHANGELOG.md for `version`, dated unless it's unreleased.
+    pub fn render(&self, version: &str, date: &str) -> String {
+        let mut text = match version.eq_ignore_ascii_case("unreleased") {
+            true => String::from("## [Unreleased]\n"),
+            false => format!("## [{}] - {}\n", version.trim_start_matches('v'), date),
+        };
+        let categories = [
+            ("Added", &self.added),
+            ("Changed", &self.changed),
+            ("Deprecated", &self.deprecated),
+            ("Removed", &self.removed),
+            ("Fixed", &self.fixed),
+            ("Security", &self.security),
+        ];
+        for (name, entries) in categories.iter().filter(|(_, entries)| !entries.is_empty()) {
+            let _ = write!(text, "\n### {}\n\n", name);
+            for entry in entries.iter() {
+                let _ = writeln!(text, "- {}", entry.trim().trim_start_matches("- "));
+            }
+        }
+        text
+    }
+}
+
+fn git(args: &[&str]) -> Result<String, anyhow::Error> {
+    let output = Command::new("git").args(args).output()?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// The newest tag reachable from `to`.
+pub fn latest_tag(to: &str) -> Result<String, anyhow::Error> {
+    git(&["describe", "--tags", "--abbrev=0", to])
+        .map(|tag| tag.trim().to_string())
+        .map_err(|_| anyhow::anyhow!("No tag to start from, give one with --from"))
+}
+
+// Commits as `git log` prints them with the format in `changes`
+fn parse_log(log: &str) -> Vec<Change> {
+    log.split('\x1e')
+        .filter_map(|record| {
+            let mut fields = record.trim_start_matches('\n').splitn(3, '\x1f');
+            let (hash, subject, body) = (fields.next()?, fields.next()?, fields.next().unwrap_or_default());
+            // The title of a merged pull request is the first line of the merge's body
+            let merged = subject.strip_prefix("Merge pull request #")
+                .and_then(|rest| rest.split_whitespace().next()?.parse::<u64>().ok());
+            let squashed = subject.trim_end().strip_suffix(')')
+                .and_then(|rest| rest.rsplit_once("(#"))
+                .and_then(|(_, number)| number.parse::<u64>().ok());
+            let (title, body) = match merged {
+                Some(_) => {
+                    let (title, rest) = body.trim().split_once('\n').unwrap_or((body.trim(), ""));
+                    (title.to_string(), rest.trim().to_string())
+                },
+                None => (subject.to_string(), body.trim().to_string()),
+            };
+            Some(Change { hash: hash.to_string(), title, body, pull_request: merged.or(squashed) })
+        })
+        .filter(|change| !change.hash.is_empty())
+        .collect()
+}
+
+/// The changes from `from` up to `to`, one per merge or commit on the first-parent line.
+pub fn changes(from: &str, to: &str) -> Result<Vec<Change>, anyhow::Error> {
+    let log = git(&["log", "--first-parent", "--format=%h%x1f%s%x1f%b%x1e", &format!("{}..{}", from, to)])?;
+    Ok(parse_log(&log))
+}
+
+fn request(changes: &[Change]) -> String {
+    let mut text = String::from("Commits since the last release, newest first:\n");
+    let mut cut = 0;
+    for change in changes {
+        let mut entry = format!("\n{} {}", change.hash, change.title.trim());
+        if let Some(number) = change.pull_request.filter(|number| !change.title.contains(&format!("#{}", number))) {
+            let _ = write!(entry, " (#{})", number);
+        }
+        entry.push('\n');
+        for line in change.body.lines().filter(|line| !line.trim().is_empty()).take(BODY_LINES) {
+            let _ = writeln!(entry, "    {}", line.trim());
+        }
+        if cut > 0 || text.len() + entry.len() > MAX_LOG_CHARS {
+            cut += 1;
+            continue;
+        }
+        text.push_str(&entry);
+    }
+    if cut > 0 {
+        let _ = write!(text, "\n[{} older commits left out]\n", cut);
+    }
+    text
+}
+
+/// Has the model sort `changes` into release notes.
+pub async fn draft(chat: &Chat, changes: &[Change]) -> Result<ReleaseNotes, anyhow::Error> {
+    if changes.is_empty() {
+        return Ok(ReleaseNotes::default());
+    }
+    let message = Message { role: Role::User, content: vec![ContentItem::Text { text: request(changes) }] };
+    chat.query_structured::<ReleaseNotes>(None, CHANGELOG_PROMPT, vec![message]).await
+}
+
+/*
+    * `changelog` with `section` in place of the one for the same version, or else ahead of
+    * the newest release, below the unreleased changes.  Link references at the end stay
+    * there, and an empty changelog gets the Keep a Changelog header.
+*/
+pub fn update(changelog: &str, section: &str) -> String {
+    let changelog = match changelog.trim().is_empty() {
+        true => HEADER,
+        false => changelog,
+    };
+    let heading = section.lines().next().unwrap_or_default();
+    let version = heading.split(']').next().unwrap_or(heading);
+    let mut lines = changelog.lines().collect::<Vec<&str>>();
+    let refs = lines.iter().position(|line| line.starts_with('[') && line.contains("]: ")).unwrap_or(lines.len());
+    let same = lines[..refs].iter().position(|line| line.starts_with(version) && line[version.len()..].starts_with(']'));
+    let (start, end) = match same {
+        Some(same) => (same, lines[same + 1..refs].iter().position(|line| line.starts_with("## ")).map_or(refs, |end| same + 1 + end)),
+        None => {
+            let release = lines[..refs].iter().position(|line| line.starts_with("## [") && !line.starts_with("## [Unreleased]"));
+            (release.unwrap_or(refs), release.unwrap_or(refs))
+        },
+    };
+    let mut section = section.trim_end().lines().chain([""]).collect::<Vec<&str>>();
+    if start > 0 && !lines[start - 1].is_empty() {
+        section.insert(0, "");
+    }
+    lines.splice(start..end, section);
+    format!("{}\n", lines.join("\n").trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_and_update() {
+        let log = "a1b2c3d\x1fMerge pull request #12 from ana/empty-configs\x1fLoad empty configs\n\nThey used to panic.\n\x1e\n\
+            e4f5a6b\x1fAdd a review command (#11)\x1f\x1e\n\
+            c7d8e9f\x1fFix typo\x1f\x1e\n";
+        let changes = parse_log(log);
+        assert_eq!(changes.len(), 3);
+        assert_eq!((changes[0].title.as_str(), changes[0].body.as_str(), changes[0].pull_request), ("Load empty configs", "They used to panic.", Some(12)));
+        assert_eq!(changes[1].pull_request, Some(11));
+        assert_eq!(changes[2].pull_request, None);
+        let text = request(&changes);
+        assert!(text.contains("\na1b2c3d Load empty configs (#12)\n    They used to panic.\n"));
+        assert!(text.contains("\ne4f5a6b Add a review command (#11)\n"));
+
+        let notes = ReleaseNotes {
+            added: vec!["A review command (#11)".to_string()],
+            fixed: vec!["- Empty configs load (#12)".to_string()],
+            ..Default::default()
+        };
+        let section = notes.render("v1.3.0", "2026-10-14");
+        assert_eq!(section, "## [1.3.0] - 2026-10-14\n\n### Added\n\n- A review command (#11)\n\n### Fixed\n\n- Empty configs load (#12)\n");
+
+        let created = update("", &notes.render("unreleased", ""));
+        assert!(created.starts_with(HEADER));
+        assert!(created.ends_with("Semantic Versioning](https://semver.org/spec/v2.0.0.html).\n\n## [Unreleased]\n\n### Added\n\n- A review command (#11)\n\n### Fixed\n\n- Empty configs load (#12)\n"));
+
+        let existing = format!("{}\n## [Unreleased]\n\n### Added\n\n- Old draft\n\n## [1.2.0] - 2026-01-02\n\n### Fixed\n\n- Crash\n\n[1.2.0]: https://example.com/v1.2.0\n", HEADER);
+        let replaced = update(&existing, &notes.render("Unreleased", ""));
+        assert!(!replaced.contains("Old draft"));
+        assert!(replaced.contains("- Empty configs load (#12)\n\n## [1.2.0] - 2026-01-02\n"));
+        let released = update(&existing, &section);
+        assert!(released.contains("- Old draft\n\n## [1.3.0] - 2026-10-14\n\n### Added\n"));
+        assert!(released.contains("- Empty configs load (#12)\n\n## [1.2.0]"));
+        let again = update(&released, &ReleaseNotes { fixed: vec!["Crash on start".to_string()], ..Default::default() }.render("1.2.0", "2026-01-02"));
+        assert!(again.ends_with("## [1.2.0] - 2026-01-02\n\n### Fixed\n\n- Crash on start\n\n[1.2.0]: https://example.com/v1.2.0\n"));
+    }
+}
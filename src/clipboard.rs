@@ -0,0 +1,136 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// The contents of the last fenced code block in `text`.  A fence left open, as in a cut
+/// off reply, runs to the end.
+pub fn last_code_block(text: &str) -> Option<String> {
+    let mut last = None;
+    // Fence characters and length of the block being read, with its lines so far
+    let mut open: Option<(String, Vec<&str>)> = None;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        let fence: String = trimmed.chars().take_while(|c| *c == '`' || *c == '~').collect();
+        let is_fence = fence.len() >= 3 && fence.chars().all(|c| c == fence.chars().next().unwrap_or('`'));
+        match open.as_mut() {
+            None if is_fence => open = Some((fence, Vec::new())),
+            None => {},
+            Some((opening, _)) if is_fence && fence.starts_with(opening.as_str()) && trimmed[fence.len()..].trim().is_empty() => {
+                let (_, lines) = open.take().unwrap_or_default();
+                last = Some(lines.join("\n"));
+            },
+            Some((_, lines)) => lines.push(line),
+        }
+    }
+    if let Some((_, lines)) = open {
+        last = Some(lines.join("\n"));
+    }
+    last
+}
+
+fn is_ssh() -> bool {
+    std::env::var_os("SSH_TTY").is_some() || std::env::var_os("SSH_CONNECTION").is_some()
+}
+
+// Clipboard tools to try in order, the first one that runs wins
+fn clipboard_commands() -> Vec<(&'static str, &'static [&'static str])> {
+    if cfg!(target_os = "macos") {
+        vec![("pbcopy", &[])]
+    } else if cfg!(target_os = "windows") {
+        vec![("clip", &[])]
+    } else {
+        let mut commands: Vec<(&'static str, &'static [&'static str])> = Vec::new();
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            commands.push(("wl-copy", &[]));
+        }
+        commands.push(("xclip", &["-selection", "clipboard"]));
+        commands.push(("xsel", &["--clipboard", "--input"]));
+        commands
+    }
+}
+
+fn pipe_to(program: &str, args: &[&str], text: &str) -> bool {
+    let child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    let Ok(mut child) = child else { return false };
+    let written = child.stdin.take().is_some_and(|mut stdin| stdin.write_all(text.as_bytes()).is_ok());
+    child.wait().is_ok_and(|status| status.success()) && written
+}
+
+// OSC 52 asks the terminal itself to set the clipboard, which works over SSH.  tmux only
+// passes it on when wrapped in its own escape.
+fn osc52(text: &str) -> String {
+    let sequence = format!("\x1b]52;c;{}\x07", STANDARD.encode(text));
+    match std::env::var_os("TMUX") {
+        Some(_) => format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b")),
+        None => sequence,
+    }
+}
+
+/*
+    * Puts `text` on the clipboard, returning how.  Over SSH the local machine's clipboard is
+    * only reachable through the terminal, so OSC 52 is used there and whenever no clipboard
+    * tool is installed.  The sequence goes to /dev/tty so it works with stdout piped.
+*/
+pub fn copy(text: &str) -> Result<&'static str, anyhow::Error> {
+    if !is_ssh() && clipboard_commands().into_iter().any(|(program, args)| pipe_to(program, args, text)) {
+        return Ok("clipboard");
+    }
+    let mut tty = OpenOptions::new().write(true).open("/dev/tty")
+        .map_err(|e| anyhow::anyhow!("No clipboard tool found and no terminal to send OSC 52 to: {}", e))?;
+    tty.write_all(osc52(text).as_bytes())?;
+    tty.flush()?;
+    Ok("terminal clipboard (OSC 52)")
+}
+
+// Parses the `«data PNGf89504E47…»` osascript prints for clipboard data
+fn parse_applescript_data(output: &str) -> Option<Vec<u8>> {
+    let hex = output.trim().strip_prefix("«data PNGf")?.strip_suffix('»')?;
+    (0..hex.len()).step_by(2)
+        .map(|idx| hex.get(idx..idx + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+/// The image on the system clipboard as PNG.
+pub fn paste_image() -> Result<Vec<u8>, anyhow::Error> {
+    let commands: Vec<(&str, Vec<&str>)> = if cfg!(target_os = "macos") {
+        vec![("osascript", vec!["-e", "the clipboard as «class PNGf»"])]
+    } else if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        vec![("wl-paste", vec!["--type", "image/png"]), ("xclip", vec!["-selection", "clipboard", "-t", "image/png", "-o"])]
+    } else {
+        vec![("xclip", vec!["-selection", "clipboard", "-t", "image/png", "-o"])]
+    };
+
+    for (program, args) in commands {
+        let Ok(output) = Command::new(program).args(&args).stdin(Stdio::null()).output() else { continue };
+        if !output.status.success() || output.stdout.is_empty() {
+            continue;
+        }
+        if program == "osascript" {
+            return parse_applescript_data(&String::from_utf8_lossy(&output.stdout))
+                .ok_or_else(|| anyhow::anyhow!("The clipboard holds no image"));
+        }
+        return Ok(output.stdout);
+    }
+    Err(anyhow::anyhow!("The clipboard holds no image, or no clipboard tool (wl-paste, xclip) is installed"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_last_code_block() {
+        let reply = "Run this:\n```sh\ncargo test\n```\nthen\n````rust\nfn main() {\n    ```\n}\n````\ndone";
+        assert_eq!(last_code_block(reply).as_deref(), Some("fn main() {\n    ```\n}"));
+        assert_eq!(last_code_block("cut off\n```\nlet x = 1;").as_deref(), Some("let x = 1;"));
+        assert_eq!(last_code_block("no code here"), None);
+        assert_eq!(parse_applescript_data("«data PNGf89504E47»\n"), Some(vec![0x89, 0x50, 0x4E, 0x47]));
+    }
+}
@@ -0,0 +1,213 @@
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+
+use serde::Deserialize;
+
+use crate::chat::Chat;
+use crate::config::CommitConfig;
+use crate::inference::types::{ContentItem, Message, Role};
+use crate::repl::compose;
+use crate::structured::{self, JsonSchema, Value};
+
+const COMMIT_PROMPT: &str = "You write the git commit message for a staged diff, in the Conventional Commits style.  \
+Pick the type that fits the change best and a scope only when the change is confined to one part of the project.  \
+The subject is in the imperative mood, lower case after the prefix and without a trailing period.  \
+The body says what changed and why in a few wrapped lines, and is empty when the subject says it all.  \
+Set `breaking` only for changes that break users of the project, and say how in the body.";
+
+// A diff beyond this is cut, the model still sees the files changed in the stat
+const MAX_DIFF_CHARS: usize = 60_000;
+// Subjects of earlier commits shown for the project's wording
+const RECENT_SUBJECTS: usize = 10;
+
+/// A commit message in parts, laid out by `CommitConfig::template`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CommitMessage {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub scope: Option<String>,
+    pub subject: String,
+    pub body: String,
+    pub breaking: bool,
+}
+
+impl JsonSchema for CommitMessage {
+    fn json_schema() -> Value {
+        structured::object(&[
+            ("type", structured::described(String::json_schema(), "The Conventional Commits type, like feat or fix")),
+            ("scope", structured::described(Option::<String>::json_schema(), "The part of the project changed, or null")),
+            ("subject", structured::described(String::json_schema(), "One line summary in the imperative mood")),
+            ("body", structured::described(String::json_schema(), "What changed and why, or empty")),
+            ("breaking", structured::described(bool::json_schema(), "Whether the change breaks users of the project")),
+        ])
+    }
+
+    fn schema_name() -> String {
+        String::from("commit_message")
+    }
+}
+
+impl CommitMessage {
+    // Why the message doesn't fit `config`, sent back to the model
+    fn check(&self, config: &CommitConfig) -> Result<(), String> {
+        if !config.types.is_empty() && !config.types.contains(&self.kind) {
+            return Err(format!("type must be one of {}, not {:?}", config.types.join(", "), self.kind));
+        }
+        if let Some(scope) = self.scope.as_deref().filter(|scope| !scope.is_empty()) {
+            if !config.scopes.is_empty() && !config.scopes.iter().any(|allowed| allowed == scope) {
+                return Err(format!("scope must be null or one of {}, not {:?}", config.scopes.join(", "), scope));
+            }
+        }
+        let subject = self.subject.trim();
+        if subject.is_empty() || subject.contains('\n') {
+            return Err(String::from("subject must be one non-empty line"));
+        }
+        let header = self.render("{type}{scope}{breaking}: {subject}");
+        if config.max_subject > 0 && header.chars().count() > config.max_subject {
+            return Err(format!("`{}` is longer than {} characters, shorten the subject", header, config.max_subject));
+        }
+        Ok(())
+    }
+
+    /// The message laid out by `template`, without the blank lines an empty body would leave.
+    pub fn render(&self, template: &str) -> String {
+        let scope = self.scope.as_deref().map(str::trim).filter(|scope| !scope.is_empty());
+        let text = template
+            .replace("{type}", self.kind.trim())
+            .replace("{scope}", &scope.map(|scope| format!("({})", scope)).unwrap_or_default())
+            .replace("{breaking}", if self.breaking { "!" } else { "" })
+            .replace("{subject}", self.subject.trim().trim_end_matches('.'))
+            .replace("{body}", self.body.trim());
+        // An empty part leaves no run of blank lines behind
+        let mut lines: Vec<&str> = Vec::new();
+        for line in text.lines().map(str::trim_end) {
+            if !(line.is_empty() && lines.last().is_none_or(|last| last.is_empty())) {
+                lines.push(line);
+            }
+        }
+        lines.join("\n").trim_end().to_string()
+    }
+}
+
+fn git(args: &[&str]) -> Result<String, anyhow::Error> {
+    let output = Command::new("git").args(args).output()?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn request(stat: &str, diff: &str, recent: &str, config: &CommitConfig) -> String {
+    let mut text = format!("Types: {}\n", config.types.join(", "));
+    if !config.scopes.is_empty() {
+        text.push_str(&format!("Scopes: {}\n", config.scopes.join(", ")));
+    }
+    if config.max_subject > 0 {
+        text.push_str(&format!("The type, scope and subject together fit in {} characters.\n", config.max_subject));
+    }
+    if !recent.trim().is_empty() {
+        text.push_str(&format!("\nRecent commits:\n{}\n", recent.trim_end()));
+    }
+    let mut cut = diff.len().min(MAX_DIFF_CHARS);
+    while !diff.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    text.push_str(&format!("\nStaged changes:\n{}\n```diff\n{}\n```\n", stat.trim_end(), diff[..cut].trim_end()));
+    if cut < diff.len() {
+        text.push_str(&format!("[diff cut off after {} of {} bytes]\n", cut, diff.len()));
+    }
+    text
+}
+
+/// Writes a message for what is staged, with the small model when one is configured.
+pub async fn generate(chat: &Chat) -> Result<String, anyhow::Error> {
+    let diff = git(&["diff", "--cached", "--no-color"])?;
+    if diff.trim().is_empty() {
+        return Err(anyhow::anyhow!("Nothing is staged, add the changes to commit with git add"));
+    }
+    let stat = git(&["diff", "--cached", "--stat", "--no-color"])?;
+    // A repository without commits has no log
+    let recent = git(&["log", "-n", &RECENT_SUBJECTS.to_string(), "--format=%s"]).unwrap_or_default();
+    let config = chat.config().commit.clone();
+    let model = chat.config().with_small_model().model;
+    let text = request(&stat, &diff, &recent, &config);
+    let message = Message { role: Role::User, content: vec![ContentItem::Text { text }] };
+    let reply: CommitMessage = chat.structured_reply(Some(&model), COMMIT_PROMPT, vec![message], |reply: &CommitMessage| reply.check(&config)).await?;
+    Ok(reply.render(&config.template))
+}
+
+/// Runs `git commit` with `message`, its hooks and output as usual.
+pub fn commit(message: &str) -> Result<(), anyhow::Error> {
+    let mut child = Command::new("git").args(["commit", "--file", "-"]).stdin(Stdio::piped()).spawn()?;
+    child.stdin.take().expect("stdin is piped").write_all(message.as_bytes())?;
+    match child.wait()?.success() {
+        true => Ok(()),
+        false => Err(anyhow::anyhow!("git commit failed")),
+    }
+}
+
+/// Shows `message` on the terminal until it is committed, edited in $EDITOR or dropped.
+/// The message to commit, None when it was dropped.
+pub fn confirm_on_tty(mut message: String) -> Result<Option<String>, anyhow::Error> {
+    let mut tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    let mut reader = BufReader::new(tty.try_clone()?);
+    loop {
+        write!(tty, "\n{}\n\nCommit with this message? [y]es, [e]dit, [n]o: ", message)?;
+        tty.flush()?;
+        let mut answer = String::new();
+        if reader.read_line(&mut answer)? == 0 {
+            return Ok(None);
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "" | "y" | "yes" => return Ok(Some(message)),
+            "n" | "no" => return Ok(None),
+            "e" | "edit" => match compose::compose(&message)? {
+                Some(edited) => message = edited,
+                None => writeln!(tty, "The message was left empty, keeping the one before.")?,
+            },
+            _ => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_follow_the_config() {
+        let config = CommitConfig { scopes: vec!["cli".to_string(), "core".to_string()], ..Default::default() };
+        let mut message = CommitMessage {
+            kind: "feat".to_string(),
+            scope: Some("cli".to_string()),
+            subject: "add a commit command.".to_string(),
+            body: "Writes the message from the staged diff.\n".to_string(),
+            breaking: false,
+        };
+        assert_eq!(message.check(&config), Ok(()));
+        assert_eq!(message.render(&config.template), "feat(cli): add a commit command\n\nWrites the message from the staged diff.");
+
+        message.scope = None;
+        message.body = String::new();
+        message.breaking = true;
+        assert_eq!(message.render(&config.template), "feat!: add a commit command");
+        assert_eq!(message.render("[{type}] {subject}\n\n{body}\n\nSigned-off-by: me"), "[feat] add a commit command\n\nSigned-off-by: me");
+
+        message.kind = "feature".to_string();
+        assert!(message.check(&config).unwrap_err().starts_with("type must be one of feat, fix"));
+        message.kind = "fix".to_string();
+        message.scope = Some("docs".to_string());
+        assert!(message.check(&config).is_err());
+        message.scope = None;
+        message.subject = "a".repeat(70);
+        assert!(message.check(&config).unwrap_err().contains("longer than 72 characters"));
+
+        let text = request(" src/a.rs | 1 +\n", "+fn a() {}\n", "fix: load empty configs\n", &config);
+        assert!(text.starts_with("Types: feat, fix, docs"));
+        assert!(text.contains("Scopes: cli, core\n"));
+        assert!(text.contains("Recent commits:\nfix: load empty configs\n"));
+        assert!(text.ends_with("src/a.rs | 1 +\n```diff\n+fn a() {}\n```\n"));
+    }
+}
@@ -0,0 +1,374 @@
+use clap::builder::ValueHint;
+use clap::{Arg, ValueEnum};
+
+use crate::models::MODELS;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+// What the value of an option or argument is completed with
+#[derive(Debug, Clone, PartialEq)]
+enum Values {
+    /// Ids of saved sessions, looked up when completing
+    Sessions,
+    Models,
+    Choices(Vec<String>),
+    Files,
+    /// Anything, nothing is suggested
+    Free,
+}
+
+struct Opt {
+    /// `--long` and `-s` forms
+    names: Vec<String>,
+    help: String,
+    values: Option<Values>,
+}
+
+// A command and what can follow it, `path` is the subcommand words leading to it
+struct Spec {
+    path: String,
+    subcommands: Vec<(String, String)>,
+    options: Vec<Opt>,
+    positional: Option<Values>,
+}
+
+fn values(arg: &Arg) -> Option<Values> {
+    if !arg.get_action().takes_values() {
+        return None;
+    }
+    let choices: Vec<String> = arg.get_possible_values().iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect();
+    Some(match arg.get_id().as_str() {
+        "session" => Values::Sessions,
+        "model" => Values::Models,
+        _ if !choices.is_empty() => Values::Choices(choices),
+        _ if matches!(arg.get_value_hint(), ValueHint::AnyPath | ValueHint::FilePath | ValueHint::DirPath) => Values::Files,
+        _ => Values::Free,
+    })
+}
+
+fn help(text: Option<&clap::builder::StyledStr>) -> String {
+    text.map(|text| text.to_string().lines().next().unwrap_or_default().to_string()).unwrap_or_default()
+}
+
+fn specs(cmd: &clap::Command, path: &str, out: &mut Vec<Spec>) {
+    let mut spec = Spec { path: path.to_string(), subcommands: Vec::new(), options: Vec::new(), positional: None };
+    for arg in cmd.get_arguments().filter(|arg| !arg.is_hide_set()) {
+        if arg.is_positional() {
+            spec.positional = spec.positional.or(values(arg));
+            continue;
+        }
+        let names = arg.get_long_and_visible_aliases().unwrap_or_default().iter()
+            .map(|long| format!("--{}", long))
+            .chain(arg.get_short_and_visible_aliases().unwrap_or_default().iter().map(|short| format!("-{}", short)))
+            .collect();
+        spec.options.push(Opt { names, help: help(arg.get_help()), values: values(arg) });
+    }
+
+    let subcommands: Vec<&clap::Command> = cmd.get_subcommands().filter(|sub| !sub.is_hide_set()).collect();
+    spec.subcommands = subcommands.iter().map(|sub| (sub.get_name().to_string(), help(sub.get_about()))).collect();
+    out.push(spec);
+    // `help` lists the commands it can explain, there is nothing to complete below that
+    if cmd.get_name() == "help" {
+        return;
+    }
+    for sub in subcommands {
+        let sub_path = match path {
+            "" => sub.get_name().to_string(),
+            _ => format!("{} {}", path, sub.get_name()),
+        };
+        specs(sub, &sub_path, out);
+    }
+}
+
+fn model_names() -> Vec<&'static str> {
+    MODELS.iter().map(|info| info.name).collect()
+}
+
+// Word lists for the value kinds that are plain words, None for files and free text
+fn word_list(values: &Values, sessions: &str) -> Option<String> {
+    match values {
+        Values::Sessions => Some(sessions.to_string()),
+        Values::Models => Some(model_names().join(" ")),
+        Values::Choices(choices) => Some(choices.join(" ")),
+        Values::Files | Values::Free => None,
+    }
+}
+
+fn bash(specs: &[Spec], bin: &str) -> String {
+    const SESSIONS: &str = "$(pprog sessions list 2>/dev/null | cut -d' ' -f1)";
+    let mut out = format!("_{}() {{\n", bin);
+    out.push_str("    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n");
+    out.push_str("    local cmd_path=\"\" words=\"\" files=\"\" i\n");
+    out.push_str("    for ((i = 1; i < COMP_CWORD; i++)); do\n        case \"$cmd_path:${COMP_WORDS[i]}\" in\n");
+    for spec in specs {
+        for (sub, _) in &spec.subcommands {
+            let to = if spec.path.is_empty() { sub.clone() } else { format!("{} {}", spec.path, sub) };
+            out.push_str(&format!("            \"{}:{}\") cmd_path=\"{}\" ;;\n", spec.path, sub, to));
+        }
+    }
+    out.push_str("        esac\n    done\n\n    case \"$cmd_path:$prev\" in\n");
+    for spec in specs {
+        for opt in &spec.options {
+            let Some(values) = &opt.values else { continue };
+            let patterns: Vec<String> = opt.names.iter().map(|name| format!("\"{}:{}\"", spec.path, name)).collect();
+            let body = match (values, word_list(values, SESSIONS)) {
+                (_, Some(words)) => format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;", words),
+                (Values::Files, None) => "COMPREPLY=($(compgen -f -- \"$cur\")); return ;;".to_string(),
+                _ => "return ;;".to_string(),
+            };
+            out.push_str(&format!("        {}) {}\n", patterns.join("|"), body));
+        }
+    }
+    out.push_str("    esac\n\n    case \"$cmd_path\" in\n");
+    for spec in specs {
+        let words: Vec<&str> = spec.subcommands.iter().map(|(sub, _)| sub.as_str())
+            .chain(spec.options.iter().flat_map(|opt| opt.names.iter().map(String::as_str)))
+            .collect();
+        let mut body = format!("words=\"{}\"", words.join(" "));
+        match spec.positional.as_ref().map(|values| (values, word_list(values, SESSIONS))) {
+            Some((_, Some(list))) => body.push_str(&format!("; [[ $cur != -* ]] && words=\"$words {}\"", list)),
+            Some((Values::Files, None)) => body.push_str("; files=1"),
+            _ => {},
+        }
+        out.push_str(&format!("        \"{}\") {} ;;\n", spec.path, body));
+    }
+    out.push_str("    esac\n    COMPREPLY=($(compgen -W \"$words\" -- \"$cur\"))\n");
+    out.push_str("    if [[ -n $files && $cur != -* ]]; then\n        COMPREPLY+=($(compgen -f -- \"$cur\"))\n    fi\n}\n\n");
+    out.push_str(&format!("complete -F _{} {}\n", bin, bin));
+    out
+}
+
+// Quotes for single-quoted strings in zsh and bash
+fn sh_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+fn zsh(specs: &[Spec], bin: &str) -> String {
+    const SESSIONS: &str = "compadd -- ${(f)\"$(pprog sessions list 2>/dev/null | cut -d' ' -f1)\"}";
+    let value_body = |values: &Values| match values {
+        Values::Sessions => SESSIONS.to_string(),
+        Values::Models => format!("compadd -- {}", model_names().join(" ")),
+        Values::Choices(choices) => format!("compadd -- {}", choices.join(" ")),
+        Values::Files => "_files".to_string(),
+        Values::Free => ":".to_string(),
+    };
+
+    let mut out = format!("#compdef {}\n\n_{}() {{\n", bin, bin);
+    out.push_str("    local cmd_path=\"\" prev=\"${words[CURRENT-1]}\" i\n");
+    out.push_str("    for ((i = 2; i < CURRENT; i++)); do\n        case \"$cmd_path:${words[i]}\" in\n");
+    for spec in specs {
+        for (sub, _) in &spec.subcommands {
+            let to = if spec.path.is_empty() { sub.clone() } else { format!("{} {}", spec.path, sub) };
+            out.push_str(&format!("            \"{}:{}\") cmd_path=\"{}\" ;;\n", spec.path, sub, to));
+        }
+    }
+    out.push_str("        esac\n    done\n\n    case \"$cmd_path:$prev\" in\n");
+    for spec in specs {
+        for opt in &spec.options {
+            let Some(values) = &opt.values else { continue };
+            let patterns: Vec<String> = opt.names.iter().map(|name| format!("\"{}:{}\"", spec.path, name)).collect();
+            out.push_str(&format!("        {}) {}; return ;;\n", patterns.join("|"), value_body(values)));
+        }
+    }
+    out.push_str("    esac\n\n    local -a subcommands options\n    case \"$cmd_path\" in\n");
+    for spec in specs {
+        let subcommands: Vec<String> = spec.subcommands.iter()
+            .map(|(sub, about)| sh_quote(&format!("{}:{}", sub, about.replace(':', "\\:"))))
+            .collect();
+        let options: Vec<String> = spec.options.iter()
+            .flat_map(|opt| opt.names.iter().map(|name| sh_quote(&format!("{}:{}", name, opt.help.replace(':', "\\:")))))
+            .collect();
+        out.push_str(&format!("        \"{}\")\n", spec.path));
+        out.push_str(&format!("            subcommands=({})\n", subcommands.join(" ")));
+        out.push_str(&format!("            options=({})\n", options.join(" ")));
+        if let Some(values) = &spec.positional {
+            out.push_str(&format!("            [[ $PREFIX != -* ]] && {}\n", value_body(values)));
+        }
+        out.push_str("            ;;\n");
+    }
+    out.push_str("    esac\n");
+    out.push_str("    (( ${#subcommands} )) && _describe -t commands 'command' subcommands\n");
+    out.push_str("    [[ $PREFIX == -* ]] && _describe -t options 'option' options\n");
+    out.push_str("    return 0\n}\n\n");
+    out.push_str(&format!("compdef _{} {}\n", bin, bin));
+    out
+}
+
+fn fish_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn fish(specs: &[Spec], bin: &str) -> String {
+    let value_flags = |values: &Values| match values {
+        Values::Sessions => "-f -a '(pprog sessions list 2>/dev/null | string split -f1 \" \")'".to_string(),
+        Values::Models => format!("-f -a {}", fish_quote(&model_names().join(" "))),
+        Values::Choices(choices) => format!("-f -a {}", fish_quote(&choices.join(" "))),
+        Values::Files => "-F".to_string(),
+        Values::Free => "-f".to_string(),
+    };
+    // The root's own options and subcommands only apply before any subcommand
+    let condition = |path: &str| match path {
+        "" => "__fish_use_subcommand".to_string(),
+        _ => path.split(' ').map(|word| format!("__fish_seen_subcommand_from {}", word)).collect::<Vec<String>>().join("; and "),
+    };
+
+    let mut out = format!("complete -c {} -f\n", bin);
+    for spec in specs {
+        let condition = fish_quote(&condition(&spec.path));
+        for (sub, about) in &spec.subcommands {
+            out.push_str(&format!("complete -c {} -n {} -a {} -d {}\n", bin, condition, sub, fish_quote(about)));
+        }
+        for opt in &spec.options {
+            let mut line = format!("complete -c {} -n {}", bin, condition);
+            for name in &opt.names {
+                match name.strip_prefix("--") {
+                    Some(long) => line.push_str(&format!(" -l {}", long)),
+                    None => line.push_str(&format!(" -s {}", name.trim_start_matches('-'))),
+                }
+            }
+            if let Some(values) = &opt.values {
+                line.push_str(&format!(" -r {}", value_flags(values)));
+            }
+            line.push_str(&format!(" -d {}\n", fish_quote(&opt.help)));
+            out.push_str(&line);
+        }
+        if let Some(values) = &spec.positional {
+            out.push_str(&format!("complete -c {} -n {} {}\n", bin, condition, value_flags(values)));
+        }
+    }
+    out
+}
+
+fn ps_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+fn powershell(specs: &[Spec], bin: &str) -> String {
+    const SESSIONS: &str = "pprog sessions list 2>$null | ForEach-Object { ($_ -split ' ')[0] }";
+    let value_list = |values: &Values| match values {
+        Values::Sessions => Some(SESSIONS.to_string()),
+        Values::Models => Some(model_names().iter().map(|name| ps_quote(name)).collect::<Vec<String>>().join(", ")),
+        Values::Choices(choices) => Some(choices.iter().map(|name| ps_quote(name)).collect::<Vec<String>>().join(", ")),
+        Values::Files | Values::Free => None,
+    };
+
+    let mut out = format!("Register-ArgumentCompleter -Native -CommandName {} -ScriptBlock {{\n", bin);
+    out.push_str("    param($wordToComplete, $commandAst, $cursorPosition)\n");
+    out.push_str("    $words = @($commandAst.CommandElements | Select-Object -Skip 1 |\n");
+    out.push_str("        Where-Object { $_.Extent.EndOffset -lt ($cursorPosition - $wordToComplete.Length) } |\n");
+    out.push_str("        ForEach-Object { $_.ToString() })\n");
+    out.push_str("    $cmdPath = ''\n    foreach ($word in $words) {\n        switch -CaseSensitive (\"${cmdPath}:$word\") {\n");
+    for spec in specs {
+        for (sub, _) in &spec.subcommands {
+            let to = if spec.path.is_empty() { sub.clone() } else { format!("{} {}", spec.path, sub) };
+            out.push_str(&format!("            {} {{ $cmdPath = {} }}\n", ps_quote(&format!("{}:{}", spec.path, sub)), ps_quote(&to)));
+        }
+    }
+    out.push_str("        }\n    }\n    $prev = if ($words.Count) { $words[-1] } else { '' }\n\n");
+    // Returning nothing lets PowerShell fall back to completing paths
+    out.push_str("    $candidates = switch -CaseSensitive (\"${cmdPath}:$prev\") {\n");
+    for spec in specs {
+        for opt in &spec.options {
+            let Some(values) = &opt.values else { continue };
+            let body = value_list(values).map_or("return".to_string(), |list| format!("@({})", list));
+            for name in &opt.names {
+                out.push_str(&format!("        {} {{ {}; break }}\n", ps_quote(&format!("{}:{}", spec.path, name)), body));
+            }
+        }
+    }
+    out.push_str("        default {\n            switch -CaseSensitive ($cmdPath) {\n");
+    for spec in specs {
+        let words: Vec<String> = spec.subcommands.iter().map(|(sub, _)| ps_quote(sub))
+            .chain(spec.options.iter().flat_map(|opt| opt.names.iter().map(|name| ps_quote(name))))
+            .collect();
+        let mut body = format!("@({})", words.join(", "));
+        if let Some(list) = spec.positional.as_ref().and_then(value_list) {
+            body.push_str(&format!("; if ($wordToComplete -notlike '-*') {{ @({}) }}", list));
+        }
+        out.push_str(&format!("                {} {{ {} }}\n", ps_quote(&spec.path), body));
+    }
+    out.push_str("            }\n        }\n    }\n");
+    out.push_str("    $candidates | Where-Object { $_ -like \"$wordToComplete*\" } | ForEach-Object {\n");
+    out.push_str("        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)\n    }\n}\n");
+    out
+}
+
+/*
+    * Writes a completion script for `shell` covering every subcommand and option of `cmd`.
+    * Session ids are looked up by running `pprog sessions list` when completing so they stay
+    * current, model names come from the registry at generation time.
+*/
+pub fn generate(shell: Shell, mut cmd: clap::Command) -> String {
+    cmd.build();
+    let bin = cmd.get_name().to_string();
+    let mut all = Vec::new();
+    specs(&cmd, "", &mut all);
+    match shell {
+        Shell::Bash => bash(&all, &bin),
+        Shell::Zsh => zsh(&all, &bin),
+        Shell::Fish => fish(&all, &bin),
+        Shell::Powershell => powershell(&all, &bin),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(clap::Parser)]
+    #[command(name = "pprog")]
+    struct Cli {
+        #[command(subcommand)]
+        command: Command,
+    }
+
+    #[derive(clap::Subcommand)]
+    enum Command {
+        /// Continue a session
+        Chat {
+            #[arg(long)]
+            session: Option<String>,
+            #[arg(long)]
+            raw: bool,
+        },
+        Export {
+            session: String,
+            #[arg(short, long, value_enum)]
+            format: Shell,
+            #[arg(short, long)]
+            output: std::path::PathBuf,
+        },
+    }
+
+    #[test]
+    fn test_specs() {
+        let mut cmd = <Cli as clap::CommandFactory>::command();
+        cmd.build();
+        let mut all = Vec::new();
+        specs(&cmd, "", &mut all);
+
+        let paths: Vec<&str> = all.iter().map(|spec| spec.path.as_str()).collect();
+        assert_eq!(paths, vec!["", "chat", "export", "help"]);
+        assert_eq!(all[0].subcommands[0], ("chat".to_string(), "Continue a session".to_string()));
+
+        let chat = &all[1];
+        assert_eq!(chat.options[0].names, vec!["--session"]);
+        assert_eq!(chat.options[0].values, Some(Values::Sessions));
+        assert_eq!(chat.options[1].values, None);
+
+        let export = &all[2];
+        assert_eq!(export.positional, Some(Values::Sessions));
+        assert_eq!(export.options[0].names, vec!["--format", "-f"]);
+        assert_eq!(export.options[0].values, Some(Values::Choices(vec!["bash", "zsh", "fish", "powershell"].into_iter().map(String::from).collect())));
+        assert_eq!(export.options[1].values, Some(Values::Files));
+    }
+}
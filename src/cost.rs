@@ -0,0 +1,331 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::IsTerminal;
+
+use chrono::{DateTime, Datelike, Duration, Local, SecondsFormat, TimeZone, Utc};
+use clap::ValueEnum;
+use crossterm::style::Stylize;
+use serde_json::json;
+
+use crate::config::BudgetConfig;
+use crate::inference::types::Usage;
+use crate::store::{Store, UsageEntry};
+
+/// What the rows of `pprog cost` are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GroupBy {
+    Model,
+    /// Local calendar day
+    Day,
+    Session,
+}
+
+/// How `pprog cost export` writes the requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum UsageFormat {
+    Csv,
+    Json,
+}
+
+const CSV_HEADER: &str = "timestamp,session_id,provider,model,input_tokens,cache_write_tokens,cache_read_tokens,output_tokens,cost_usd";
+
+/// A soft budget that the spending of its period has reached.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoftLimit {
+    /// `daily` or `monthly`
+    pub period: &'static str,
+    pub spent: f64,
+    pub limit: f64,
+}
+
+impl fmt::Display for SoftLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let period = match self.period {
+            "daily" => "today",
+            _ => "this month",
+        };
+        write!(f, "${:.2} spent {}, over the {} budget of ${:.2}", self.spent, period, self.period, self.limit)
+    }
+}
+
+/// Requests held back by a soft budget with `require_force`.
+#[derive(Debug)]
+pub struct SoftLimitReached(pub SoftLimit);
+
+impl fmt::Display for SoftLimitReached {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, start pprog with --force to go on", self.0)
+    }
+}
+
+impl std::error::Error for SoftLimitReached {}
+
+// The spend of one row of the report
+#[derive(Default)]
+struct Spend {
+    requests: usize,
+    usage: Usage,
+    cost: f64,
+    // Requests to models with unknown prices, left out of `cost`
+    unpriced: usize,
+}
+
+impl Spend {
+    fn add(&mut self, entry: &UsageEntry) {
+        self.requests += 1;
+        self.usage.add(&entry.usage);
+        match entry.cost {
+            Some(cost) => self.cost += cost,
+            None => self.unpriced += 1,
+        }
+    }
+}
+
+/// A span of time like `7d`, `12h`, `30m` or `2w`.
+pub fn parse_since(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let error = || format!("{} is not a span of time like 7d, 12h, 30m or 2w", value);
+    let unit = value.chars().last().ok_or_else(error)?;
+    let count = value[..value.len() - unit.len_utf8()].parse::<u32>().map_err(|_| error())? as i64;
+    match unit {
+        'm' => Ok(Duration::minutes(count)),
+        'h' => Ok(Duration::hours(count)),
+        'd' => Ok(Duration::days(count)),
+        'w' => Ok(Duration::weeks(count)),
+        _ => Err(error()),
+    }
+}
+
+/*
+    * The spend of `entries` as a table with a row per model, day or session and a total.
+    * Days are in order, the other rows go from the most expensive down.  Sessions are named
+    * by their short id and the title from `titles` when they have one.
+*/
+pub fn report(entries: &[UsageEntry], by: GroupBy, titles: &BTreeMap<String, String>) -> String {
+    let mut rows: BTreeMap<String, Spend> = BTreeMap::new();
+    let mut total = Spend::default();
+    for entry in entries {
+        let key = match by {
+            GroupBy::Model => entry.model.clone(),
+            GroupBy::Day => entry.created_at.with_timezone(&Local).format("%Y-%m-%d").to_string(),
+            GroupBy::Session => entry.session_id.clone(),
+        };
+        rows.entry(key).or_default().add(entry);
+        total.add(entry);
+    }
+    let mut rows = rows.into_iter()
+        .map(|(key, spend)| {
+            let label = match (by, titles.get(&key)) {
+                (GroupBy::Session, Some(title)) => format!("{}  {}", &key[..key.len().min(8)], title),
+                (GroupBy::Session, None) => key[..key.len().min(8)].to_string(),
+                _ => key,
+            };
+            (label, spend)
+        })
+        .collect::<Vec<(String, Spend)>>();
+    if by != GroupBy::Day {
+        rows.sort_by(|(_, a), (_, b)| b.cost.total_cmp(&a.cost));
+    }
+    rows.push(("Total".to_string(), total));
+
+    let heading = match by {
+        GroupBy::Model => "Model",
+        GroupBy::Day => "Day",
+        GroupBy::Session => "Session",
+    };
+    let width = rows.iter().map(|(label, _)| label.chars().count()).chain([heading.len()]).max().unwrap_or_default();
+    let mut table = format!(
+        "{:<width$}  {:>8}  {:>10}  {:>11}  {:>10}  {:>10}  {:>10}\n",
+        heading, "Requests", "Input", "Cache write", "Cache read", "Output", "Cost",
+    );
+    for (label, spend) in &rows {
+        let unpriced = match spend.unpriced {
+            0 => "",
+            _ => "*",
+        };
+        table.push_str(&format!(
+            "{:<width$}  {:>8}  {:>10}  {:>11}  {:>10}  {:>10}  {:>10}\n",
+            label,
+            spend.requests,
+            spend.usage.input_tokens,
+            spend.usage.cache_creation_input_tokens,
+            spend.usage.cache_read_input_tokens,
+            spend.usage.output_tokens,
+            format!("{}${:.4}", unpriced, spend.cost),
+        ));
+    }
+    if rows.iter().any(|(_, spend)| spend.unpriced > 0) {
+        table.push_str("* leaves out requests to models with unknown prices\n");
+    }
+    table
+}
+
+/// The budgets of `budget` that `entries`, the requests since the start of the month, have
+/// reached by `now`.
+pub fn soft_limits(entries: &[UsageEntry], budget: &BudgetConfig, now: DateTime<Local>) -> Vec<SoftLimit> {
+    let today = now.date_naive();
+    let (mut daily, mut monthly) = (0.0, 0.0);
+    for entry in entries {
+        let day = entry.created_at.with_timezone(&Local).date_naive();
+        let cost = entry.cost.unwrap_or_default();
+        if day == today {
+            daily += cost;
+        }
+        if (day.year(), day.month()) == (today.year(), today.month()) {
+            monthly += cost;
+        }
+    }
+    [("daily", budget.daily, daily), ("monthly", budget.monthly, monthly)].into_iter()
+        .filter_map(|(period, limit, spent)| limit.filter(|limit| spent >= *limit).map(|limit| SoftLimit { period, spent, limit }))
+        .collect()
+}
+
+/// The budgets of `budget` reached so far, without opening the store when there are none.
+pub fn crossed_soft_limits(budget: &BudgetConfig) -> Result<Vec<SoftLimit>, anyhow::Error> {
+    if budget.daily.is_none() && budget.monthly.is_none() {
+        return Ok(Vec::new());
+    }
+    let now = Local::now();
+    let month_start = Local.with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0).earliest().map(|start| start.with_timezone(&Utc));
+    let entries = Store::open()?.usage_since(month_start)?;
+    Ok(soft_limits(&entries, budget, now))
+}
+
+/*
+    * Warns on stderr about the soft budgets crossed, once per budget in `warned` so a chat
+    * doesn't repeat it before every message.  With `require_force` and no `force` the next
+    * request is held back with `SoftLimitReached`, every time.
+*/
+pub fn check_soft_limits(budget: &BudgetConfig, force: bool, warned: &mut BTreeSet<&'static str>) -> Result<(), anyhow::Error> {
+    let crossed = crossed_soft_limits(budget)?;
+    for limit in &crossed {
+        if warned.insert(limit.period) {
+            let warning = format!("Warning: {}", limit);
+            match std::io::stderr().is_terminal() {
+                true => eprintln!("{}", warning.yellow().bold()),
+                false => eprintln!("{}", warning),
+            }
+        }
+    }
+    match crossed.into_iter().next() {
+        Some(limit) if budget.require_force && !force => Err(SoftLimitReached(limit).into()),
+        _ => Ok(()),
+    }
+}
+
+// Quoted when it holds a comma, quote or line break, like spreadsheets expect
+fn csv_field(value: &str) -> String {
+    match value.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_string(),
+    }
+}
+
+/// Every request in `entries` as a row, for spreadsheets and chargeback.  The cost is empty
+/// in CSV and null in JSON for models with unknown prices.
+pub fn export(entries: &[UsageEntry], format: UsageFormat) -> String {
+    let timestamp = |entry: &UsageEntry| entry.created_at.to_rfc3339_opts(SecondsFormat::Secs, true);
+    match format {
+        UsageFormat::Csv => {
+            let mut csv = format!("{}\n", CSV_HEADER);
+            for entry in entries {
+                csv.push_str(&format!(
+                    "{},{},{},{},{},{},{},{},{}\n",
+                    timestamp(entry),
+                    csv_field(&entry.session_id),
+                    csv_field(&entry.provider),
+                    csv_field(&entry.model),
+                    entry.usage.input_tokens,
+                    entry.usage.cache_creation_input_tokens,
+                    entry.usage.cache_read_input_tokens,
+                    entry.usage.output_tokens,
+                    entry.cost.map(|cost| format!("{:.6}", cost)).unwrap_or_default(),
+                ));
+            }
+            csv
+        },
+        UsageFormat::Json => {
+            let rows = entries.iter()
+                .map(|entry| json!({
+                    "timestamp": timestamp(entry),
+                    "session_id": entry.session_id,
+                    "provider": entry.provider,
+                    "model": entry.model,
+                    "input_tokens": entry.usage.input_tokens,
+                    "cache_write_tokens": entry.usage.cache_creation_input_tokens,
+                    "cache_read_tokens": entry.usage.cache_read_input_tokens,
+                    "output_tokens": entry.usage.output_tokens,
+                    "cost_usd": entry.cost,
+                }))
+                .collect::<Vec<_>>();
+            // A `Value` always serializes
+            format!("{}\n", serde_json::to_string_pretty(&rows).unwrap_or_default())
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn test_report() {
+        assert_eq!(parse_since("7d"), Ok(Duration::days(7)));
+        assert_eq!(parse_since("12h"), Ok(Duration::hours(12)));
+        assert!(parse_since("soon").is_err());
+
+        let entry = |session: &str, model: &str, cost: Option<f64>| UsageEntry {
+            session_id: session.to_string(),
+            provider: "anthropic".to_string(),
+            model: model.to_string(),
+            created_at: Utc::now(),
+            usage: Usage { input_tokens: 1000, output_tokens: 100, ..Default::default() },
+            cost,
+        };
+        let entries = [
+            entry("aaaaaaaa-1", "claude-3-5-haiku-latest", Some(0.0012)),
+            entry("bbbbbbbb-2", "gpt-4o", Some(0.0035)),
+            entry("bbbbbbbb-2", "local-model", None),
+        ];
+        let table = report(&entries, GroupBy::Model, &BTreeMap::new());
+        let lines = table.lines().collect::<Vec<&str>>();
+        assert!(lines[1].starts_with("gpt-4o ") && lines[1].ends_with("$0.0035"));
+        assert!(lines[3].starts_with("local-model") && lines[3].ends_with("*$0.0000"));
+        assert!(lines[4].starts_with("Total") && lines[4].contains(" 3 ") && lines[4].ends_with("*$0.0047"));
+        assert_eq!(lines[5], "* leaves out requests to models with unknown prices");
+
+        let titles = BTreeMap::from([("aaaaaaaa-1".to_string(), "Tokio panic".to_string())]);
+        let table = report(&entries, GroupBy::Session, &titles);
+        assert!(table.lines().nth(2).unwrap().starts_with("aaaaaaaa  Tokio panic "));
+
+        let csv = export(&entries[1..], UsageFormat::Csv);
+        let lines = csv.lines().collect::<Vec<&str>>();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert!(lines[1].ends_with(",bbbbbbbb-2,anthropic,gpt-4o,1000,0,0,100,0.003500"));
+        assert!(lines[2].ends_with(",local-model,1000,0,0,100,"));
+        assert_eq!(csv_field("a \"b\", c"), "\"a \"\"b\"\", c\"");
+        let json: serde_json::Value = serde_json::from_str(&export(&entries[1..], UsageFormat::Json)).unwrap();
+        assert_eq!(json[1]["cost_usd"], serde_json::Value::Null);
+        assert_eq!(json[0]["output_tokens"], json!(100));
+    }
+
+    #[test]
+    fn test_soft_limits() {
+        let now = Local.with_ymd_and_hms(2026, 3, 15, 12, 0, 0).unwrap();
+        let entry = |days_ago: i64, cost: Option<f64>| UsageEntry {
+            session_id: "s1".to_string(),
+            provider: "openai".to_string(),
+            model: "gpt-4o".to_string(),
+            created_at: (now - Duration::days(days_ago)).with_timezone(&Utc),
+            usage: Usage::default(),
+            cost,
+        };
+        let entries = [entry(0, Some(4.0)), entry(0, None), entry(3, Some(30.0)), entry(20, Some(100.0))];
+        let budget = BudgetConfig { daily: Some(5.0), monthly: Some(30.0), require_force: true };
+        assert_eq!(soft_limits(&entries, &budget, now), vec![SoftLimit { period: "monthly", spent: 34.0, limit: 30.0 }]);
+        let budget = BudgetConfig { daily: Some(4.0), ..Default::default() };
+        let crossed = soft_limits(&entries, &budget, now);
+        assert_eq!(crossed[0].to_string(), "$4.00 spent today, over the daily budget of $4.00");
+    }
+}
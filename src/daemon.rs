@@ -0,0 +1,663 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Arc;
+
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws;
+use actix_web::{http, web, HttpRequest, HttpResponse};
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
+
+use crate::actions::{self, ActionRequest};
+use crate::agent::{self, AgentEvent, ApprovalRequest, CancelSource, CancelToken, Controls, Decision, TurnOutcome};
+use crate::chat::Chat;
+use crate::config::ProjectConfig;
+use crate::inference::stream::StreamEvent;
+use crate::inference::types::Usage;
+use crate::mentions;
+use crate::models;
+use crate::prompt::PromptTemplate;
+use crate::server::{chat_error_response, ErrorResponse};
+use crate::session::Session;
+use crate::store::Store;
+
+// The API token, generated and written to `.pprog/serve-token` when unset
+pub const TOKEN_VAR: &str = "PPROG_SERVE_TOKEN";
+const TOKEN_FILE: &str = "serve-token";
+// Events kept for a slow subscriber before it misses some
+const EVENT_BUFFER: usize = 1024;
+// The largest WebSocket frame taken, enough for a long prompt
+const MAX_FRAME: usize = 1 << 20;
+
+/// The token clients must send, from `PPROG_SERVE_TOKEN` or generated and written for them to read.
+pub fn token() -> Result<String, anyhow::Error> {
+    if let Some(token) = std::env::var(TOKEN_VAR).ok().filter(|token| !token.is_empty()) {
+        return Ok(token);
+    }
+    let token = uuid::Uuid::new_v4().simple().to_string();
+    let path = ProjectConfig::data_dir()?.join(TOKEN_FILE);
+    write_token(&path, &token).map_err(|e| anyhow::anyhow!("Failed to write {}: {}", path.display(), e))?;
+    Ok(token)
+}
+
+fn write_token(path: &std::path::Path, token: &str) -> std::io::Result<()> {
+    // The mode only applies to a new file, one left by an older run may be readable by others
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {},
+    }
+    // Private from the start, and a file or link put there since isn't written through
+    ProjectConfig::private_file().write(true).create_new(true).open(path)?.write_all(token.as_bytes())
+}
+
+struct Pending {
+    id: u64,
+    name: String,
+    input: Value,
+    reason: Option<String>,
+    reply: oneshot::Sender<Decision>,
+}
+
+// What a turn holds while it runs
+struct Turns {
+    chat: Chat,
+    controls: Controls,
+    cancel: CancelToken,
+}
+
+pub(crate) struct AgentSession {
+    pub(crate) id: String,
+    turns: Mutex<Turns>,
+    pub(crate) cancel: CancelSource,
+    pub(crate) events: broadcast::Sender<Value>,
+    pending: Arc<std::sync::Mutex<Vec<Pending>>>,
+}
+
+pub(crate) const BUSY: &str = "A turn is already running in this session";
+
+impl AgentSession {
+    // Opens the chat `request` asks for, with what a client is told about it
+    pub(crate) async fn open(
+        request: &CreateRequest,
+        persona: Option<&str>,
+        stop_sequences: &[String],
+    ) -> Result<(Arc<Self>, Value), anyhow::Error> {
+        let mut chat = Chat::for_persona(request.persona.as_deref().or(persona)).await?;
+        if !stop_sequences.is_empty() {
+            chat.set_stop_sequences(stop_sequences.to_vec()).await?;
+        }
+        if let Some(id) = &request.session {
+            chat.resume(Session::load_recent(id, chat.config().history_messages)?)?;
+        }
+        if let Some(name) = &request.prompt_template {
+            chat.set_prompt_template(PromptTemplate::load(name)?);
+        }
+        let id = chat.session.id.clone();
+        let opened = json!({"id": id, "model": chat.config().model, "approve_each": request.approve_each});
+        let (cancel, token) = agent::cancel_pair();
+        let (approvals, requests) = agent::approval_channel();
+        let session = Arc::new(AgentSession {
+            id,
+            turns: Mutex::new(Turns {
+                chat,
+                controls: Controls { approvals: request.approve_each.then_some(approvals), ..Default::default() },
+                cancel: token,
+            }),
+            cancel,
+            events: broadcast::channel(EVENT_BUFFER).0,
+            pending: Arc::default(),
+        });
+        if request.approve_each {
+            hold_approvals(requests, &session);
+        }
+        Ok((session, opened))
+    }
+
+    // Runs a turn on `text` with its events published, `None` when one is running already
+    pub(crate) async fn run(&self, text: &str) -> Option<Result<TurnOutcome, anyhow::Error>> {
+        let Ok(mut turns) = self.turns.try_lock() else { return None };
+        let Turns { chat, controls, cancel } = &mut *turns;
+        let on_event = &mut |event: AgentEvent| {
+            if let Some(event) = event_json(&event) {
+                let _ = self.events.send(event);
+            }
+        };
+        let result = agent::run_turn(chat, mentions::user_message(text), on_event, controls, cancel).await;
+        let _ = self.events.send(match &result {
+            Ok(outcome) => json!({"type": "done", "message": outcome.reply, "incomplete": outcome.incomplete}),
+            Err(e) => json!({"type": "error", "error": e.to_string()}),
+        });
+        Some(result)
+    }
+
+    pub(crate) fn busy(&self) -> bool {
+        self.turns.try_lock().is_err()
+    }
+
+    // Answers the held tool call `call`
+    pub(crate) fn answer(&self, call: u64, answer: Answer) -> Result<(), (http::StatusCode, String)> {
+        let held = {
+            let mut pending = self.pending.lock().unwrap();
+            let index = pending.iter().position(|held| held.id == call);
+            index.map(|index| pending.remove(index))
+        };
+        let Some(held) = held else {
+            return Err((http::StatusCode::NOT_FOUND, format!("No tool call {} is waiting for approval", call)));
+        };
+        held.reply.send(answer.into())
+            .map_err(|_| (http::StatusCode::GONE, "The turn that made the call has ended".to_string()))
+    }
+}
+
+/*
+    * The agent sessions of `pprog serve` driven over `/api`, for editors and scripts that
+    * would otherwise start a process per prompt.  Unlike the browser routes, which bounce
+    * every tool call through the client, a message sent here runs the whole turn in process
+    * like `pprog -p` does, and what happens during it is published to every subscriber of
+    * the session's events.  Sessions created with `approve_each` hold each tool call until it
+    * is answered through its approvals.  Every request needs the token as a bearer token.
+*/
+pub struct Daemon {
+    token: String,
+    persona: Option<String>,
+    stop_sequences: Vec<String>,
+    sessions: Mutex<HashMap<String, Arc<AgentSession>>>,
+}
+
+impl Daemon {
+    pub fn new(token: String, persona: Option<&str>, stop_sequences: &[String]) -> Self {
+        Daemon {
+            token,
+            persona: persona.map(str::to_string),
+            stop_sequences: stop_sequences.to_vec(),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Compares the whole token whatever the first difference, so timing gives nothing away
+    fn is_token(&self, given: &str) -> bool {
+        given.len() == self.token.len()
+            && given.bytes().zip(self.token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+    }
+
+    pub(crate) fn authorized(&self, req: &HttpRequest) -> bool {
+        let given = req.headers().get(http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        self.is_token(given)
+    }
+
+    // Browsers can't set headers on a WebSocket, so it may bring the token as `?token=`
+    fn authorized_socket(&self, req: &HttpRequest) -> bool {
+        let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).ok();
+        self.authorized(req) || query.and_then(|query| query.get("token").map(|token| self.is_token(token))).unwrap_or(false)
+    }
+
+    async fn session(&self, id: &str) -> Option<Arc<AgentSession>> {
+        self.sessions.lock().await.get(id).cloned()
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub(crate) struct CreateRequest {
+    /// Continue a saved session by id or unique prefix
+    #[serde(default)]
+    session: Option<String>,
+    #[serde(default)]
+    persona: Option<String>,
+    #[serde(default)]
+    prompt_template: Option<String>,
+    /// Hold every tool call until it is answered through the approvals
+    #[serde(default)]
+    approve_each: bool,
+}
+
+#[derive(Deserialize)]
+struct SendRequest {
+    text: String,
+}
+
+/// The answer to a held tool call.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "decision", rename_all = "snake_case")]
+pub(crate) enum Answer {
+    Approve,
+    /// Run it and every later call of the same tool in the session
+    Always,
+    Edit { input: Value },
+    Deny {
+        #[serde(default)]
+        reason: String,
+    },
+}
+
+/// What a WebSocket client sends, one JSON object per text frame.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Command {
+    Message { text: String },
+    Approval {
+        id: u64,
+        #[serde(flatten)]
+        answer: Answer,
+    },
+    Cancel,
+}
+
+impl From<Answer> for Decision {
+    fn from(answer: Answer) -> Self {
+        match answer {
+            Answer::Approve => Decision::Approve,
+            Answer::Always => Decision::AlwaysAllow,
+            Answer::Edit { input } => Decision::Edit(input),
+            Answer::Deny { reason } => Decision::Deny(reason),
+        }
+    }
+}
+
+fn error(status: http::StatusCode, error_type: &str, message: &str) -> HttpResponse {
+    HttpResponse::build(status).json(ErrorResponse::new(message, error_type, status.as_u16()))
+}
+
+fn unauthorized() -> HttpResponse {
+    error(http::StatusCode::UNAUTHORIZED, "authentication_error", "Send the token from .pprog/serve-token or PPROG_SERVE_TOKEN as a bearer token")
+}
+
+fn no_session(id: &str) -> HttpResponse {
+    error(http::StatusCode::NOT_FOUND, "not_found_error", &format!("No open session {}", id))
+}
+
+// The events of a turn, named like the ones of `--output stream-json`
+pub(crate) fn event_json(event: &AgentEvent) -> Option<Value> {
+    match event {
+        AgentEvent::Stream(StreamEvent::Text(text)) => Some(json!({"type": "text", "text": text})),
+        AgentEvent::Stream(StreamEvent::Thinking(text)) => Some(json!({"type": "thinking", "text": text})),
+        AgentEvent::Stream(StreamEvent::Done { stop_reason, stop_sequence, usage, timing }) => Some(json!({
+            "type": "response",
+            "stop_reason": stop_reason,
+            "stop_sequence": stop_sequence,
+            "usage": usage,
+            "duration_ms": timing.total.as_millis() as u64,
+        })),
+        AgentEvent::Stream(_) => None,
+        AgentEvent::ToolStart { id, name, input } => Some(json!({"type": "tool_use", "id": id, "name": name, "input": input})),
+        AgentEvent::ToolDone { id, name, output, is_error } => Some(json!({
+            "type": "tool_result", "tool_use_id": id, "name": name, "content": output, "is_error": is_error,
+        })),
+        AgentEvent::Steer { .. } => None,
+    }
+}
+
+// Holds each tool call the session asks about until a client answers it
+fn hold_approvals(mut requests: mpsc::UnboundedReceiver<ApprovalRequest>, session: &AgentSession) {
+    let pending = session.pending.clone();
+    let events = session.events.clone();
+    tokio::spawn(async move {
+        let mut next = 0;
+        while let Some(ApprovalRequest { name, input, reason, reply }) = requests.recv().await {
+            next += 1;
+            let event = json!({"type": "approval", "id": next, "name": name, "input": input, "reason": reason});
+            {
+                let mut pending = pending.lock().unwrap();
+                // Calls of a turn that was dropped can't be answered anymore
+                pending.retain(|call| !call.reply.is_closed());
+                pending.push(Pending { id: next, name, input, reason, reply });
+            }
+            // Announced once it can be answered
+            let _ = events.send(event);
+        }
+    });
+}
+
+async fn create_session(req: HttpRequest, daemon: web::Data<Daemon>, body: Option<web::Json<CreateRequest>>) -> HttpResponse {
+    if !daemon.authorized(&req) {
+        return unauthorized();
+    }
+    let request = body.map(web::Json::into_inner).unwrap_or_default();
+    let (session, opened) = match AgentSession::open(&request, daemon.persona.as_deref(), &daemon.stop_sequences).await {
+        Ok(opened) => opened,
+        Err(e) => return error(http::StatusCode::BAD_REQUEST, "invalid_request_error", &e.to_string()),
+    };
+    let mut sessions = daemon.sessions.lock().await;
+    if sessions.contains_key(&session.id) {
+        return error(http::StatusCode::CONFLICT, "conflict_error", &format!("Session {} is already open", session.id));
+    }
+    sessions.insert(session.id.clone(), session);
+    HttpResponse::Created().json(opened)
+}
+
+async fn list_sessions(req: HttpRequest, daemon: web::Data<Daemon>) -> HttpResponse {
+    if !daemon.authorized(&req) {
+        return unauthorized();
+    }
+    let sessions = daemon.sessions.lock().await;
+    let mut listed = sessions.iter()
+        .map(|(id, session)| json!({"id": id, "busy": session.busy()}))
+        .collect::<Vec<Value>>();
+    listed.sort_by(|a, b| a["id"].as_str().cmp(&b["id"].as_str()));
+    HttpResponse::Ok().json(listed)
+}
+
+async fn close_session(req: HttpRequest, daemon: web::Data<Daemon>, path: web::Path<String>) -> HttpResponse {
+    if !daemon.authorized(&req) {
+        return unauthorized();
+    }
+    let mut sessions = daemon.sessions.lock().await;
+    match sessions.get(path.as_str()) {
+        None => no_session(&path),
+        Some(session) if session.busy() => {
+            error(http::StatusCode::CONFLICT, "conflict_error", "A turn is running, cancel it first")
+        },
+        Some(_) => {
+            sessions.remove(path.as_str());
+            HttpResponse::Ok().json(json!({"closed": path.as_str()}))
+        },
+    }
+}
+
+/// Runs a turn to completion, tool calls included, and answers with its last reply.
+async fn send_message(
+    req: HttpRequest,
+    daemon: web::Data<Daemon>,
+    path: web::Path<String>,
+    body: web::Json<SendRequest>,
+) -> HttpResponse {
+    if !daemon.authorized(&req) {
+        return unauthorized();
+    }
+    let Some(session) = daemon.session(&path).await else { return no_session(&path) };
+    match session.run(&body.text).await {
+        None => error(http::StatusCode::CONFLICT, "conflict_error", BUSY),
+        Some(Ok(outcome)) => HttpResponse::Ok().json(json!({"message": outcome.reply, "incomplete": outcome.incomplete})),
+        Some(Err(e)) => chat_error_response(e),
+    }
+}
+
+// The next event of a subscription, with the ones it was too slow for reported as `lagged`
+pub(crate) async fn next_event(events: &mut broadcast::Receiver<Value>) -> Option<Value> {
+    match events.recv().await {
+        Ok(event) => Some(event),
+        Err(broadcast::error::RecvError::Lagged(missed)) => Some(json!({"type": "lagged", "missed": missed})),
+        Err(broadcast::error::RecvError::Closed) => None,
+    }
+}
+
+/// The session's events as server-sent events, from now until it is closed.
+async fn stream_events(req: HttpRequest, daemon: web::Data<Daemon>, path: web::Path<String>) -> HttpResponse {
+    if !daemon.authorized(&req) {
+        return unauthorized();
+    }
+    let Some(session) = daemon.session(&path).await else { return no_session(&path) };
+    let events = futures::stream::unfold(session.events.subscribe(), |mut events| async move {
+        let event = next_event(&mut events).await?;
+        let kind = event["type"].as_str().unwrap_or("message").to_string();
+        let chunk = web::Bytes::from(format!("event: {}\ndata: {}\n\n", kind, event));
+        Some((Ok::<_, actix_web::Error>(chunk), events))
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((http::header::CACHE_CONTROL, "no-cache"))
+        .streaming(events)
+}
+
+fn text_frame(event: &Value) -> ws::Message {
+    ws::Message::Text(event.to_string().into())
+}
+
+// Refusals of a client's commands go to that client alone
+fn rejected(error: &str) -> ws::Message {
+    text_frame(&json!({"type": "rejected", "error": error}))
+}
+
+fn run_command(session: &Arc<AgentSession>, frames: &mpsc::UnboundedSender<ws::Message>, text: &[u8]) {
+    match serde_json::from_slice::<Command>(text) {
+        Err(e) => {
+            let _ = frames.send(rejected(&format!("Invalid command: {}", e)));
+        },
+        // The turn goes on when the socket closes, its events still reach the other subscribers
+        Ok(Command::Message { text }) => {
+            let (session, frames) = (session.clone(), frames.clone());
+            actix_web::rt::spawn(async move {
+                if session.run(&text).await.is_none() {
+                    let _ = frames.send(rejected(BUSY));
+                }
+            });
+        },
+        Ok(Command::Approval { id, answer }) => {
+            if let Err((_, message)) = session.answer(id, answer) {
+                let _ = frames.send(rejected(&message));
+            }
+        },
+        Ok(Command::Cancel) => session.cancel.cancel(),
+    }
+}
+
+// Reads the client's frames until it goes away or closes the socket
+async fn read_commands(mut payload: web::Payload, session: Arc<AgentSession>, frames: mpsc::UnboundedSender<ws::Message>) {
+    let mut codec = ws::Codec::new().max_size(MAX_FRAME);
+    let mut buf = web::BytesMut::new();
+    loop {
+        let frame = match codec.decode(&mut buf) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => match payload.next().await {
+                Some(Ok(bytes)) => {
+                    buf.extend_from_slice(&bytes);
+                    continue;
+                },
+                _ => return,
+            },
+            Err(e) => {
+                log::error!("Closing a WebSocket after a bad frame: {}", e);
+                let _ = frames.send(ws::Message::Close(Some(ws::CloseCode::Protocol.into())));
+                return;
+            },
+        };
+        match frame {
+            ws::Frame::Text(text) => run_command(&session, &frames, &text),
+            ws::Frame::Ping(bytes) => {
+                let _ = frames.send(ws::Message::Pong(bytes));
+            },
+            ws::Frame::Pong(_) => {},
+            ws::Frame::Close(reason) => {
+                let _ = frames.send(ws::Message::Close(reason));
+                return;
+            },
+            ws::Frame::Binary(_) | ws::Frame::Continuation(_) => {
+                let _ = frames.send(rejected("Commands are JSON in unfragmented text frames"));
+            },
+        }
+    }
+}
+
+/*
+    * The session's events as JSON text frames, the same ones as `GET .../events`, with
+    * commands going the other way: `message` to run a turn, `approval` to answer a held
+    * tool call and `cancel` to stop the turn running.  Commands are answered through the
+    * events they cause, and only refusals go to the sending client alone, as `rejected`.
+*/
+async fn websocket(req: HttpRequest, daemon: web::Data<Daemon>, path: web::Path<String>, payload: web::Payload) -> HttpResponse {
+    if !daemon.authorized_socket(&req) {
+        return unauthorized();
+    }
+    let Some(session) = daemon.session(&path).await else { return no_session(&path) };
+    let mut response = match ws::handshake(req.head()) {
+        Ok(response) => response,
+        Err(e) => return HttpResponse::from_error(e),
+    };
+    let (frames, outgoing) = mpsc::unbounded_channel::<ws::Message>();
+    let (mut events, forwarded) = (session.events.subscribe(), frames.clone());
+    actix_web::rt::spawn(async move {
+        loop {
+            let event = tokio::select! {
+                _ = forwarded.closed() => return,
+                event = next_event(&mut events) => event,
+            };
+            if event.is_none_or(|event| forwarded.send(text_frame(&event)).is_err()) {
+                return;
+            }
+        }
+    });
+    actix_web::rt::spawn(read_commands(payload, session, frames));
+    let encoded = futures::stream::unfold((outgoing, ws::Codec::new(), false), |(mut outgoing, mut codec, closed)| async move {
+        if closed {
+            return None;
+        }
+        let message = outgoing.recv().await?;
+        let closing = matches!(message, ws::Message::Close(_));
+        let mut buf = web::BytesMut::new();
+        codec.encode(message, &mut buf).ok()?;
+        Some((Ok::<_, actix_web::Error>(buf.freeze()), (outgoing, codec, closing)))
+    });
+    match response.message_body(actix_web::body::BodyStream::new(encoded)) {
+        Ok(response) => HttpResponse::from(response).map_into_boxed_body(),
+        Err(e) => HttpResponse::from_error(e),
+    }
+}
+
+async fn list_approvals(req: HttpRequest, daemon: web::Data<Daemon>, path: web::Path<String>) -> HttpResponse {
+    if !daemon.authorized(&req) {
+        return unauthorized();
+    }
+    let Some(session) = daemon.session(&path).await else { return no_session(&path) };
+    let mut pending = session.pending.lock().unwrap();
+    pending.retain(|call| !call.reply.is_closed());
+    let listed = pending.iter()
+        .map(|call| json!({"id": call.id, "name": call.name, "input": call.input, "reason": call.reason}))
+        .collect::<Vec<Value>>();
+    HttpResponse::Ok().json(listed)
+}
+
+async fn answer_approval(
+    req: HttpRequest,
+    daemon: web::Data<Daemon>,
+    path: web::Path<(String, u64)>,
+    body: web::Json<Answer>,
+) -> HttpResponse {
+    if !daemon.authorized(&req) {
+        return unauthorized();
+    }
+    let (id, call) = path.into_inner();
+    let Some(session) = daemon.session(&id).await else { return no_session(&id) };
+    match session.answer(call, body.into_inner()) {
+        Ok(_) => HttpResponse::Ok().json(json!({"answered": call})),
+        Err((status, message)) => error(status, "not_found_error", &message),
+    }
+}
+
+async fn cancel_turn(req: HttpRequest, daemon: web::Data<Daemon>, path: web::Path<String>) -> HttpResponse {
+    if !daemon.authorized(&req) {
+        return unauthorized();
+    }
+    let Some(session) = daemon.session(&path).await else { return no_session(&path) };
+    session.cancel.cancel();
+    HttpResponse::Ok().json(json!({"cancelled": true}))
+}
+
+/// The tokens the session used per model, with the cost of those with known prices.
+async fn get_usage(req: HttpRequest, daemon: web::Data<Daemon>, path: web::Path<String>) -> HttpResponse {
+    if !daemon.authorized(&req) {
+        return unauthorized();
+    }
+    if daemon.session(&path).await.is_none() {
+        return no_session(&path);
+    }
+    let usage = match Store::open().and_then(|store| store.session_usage(&path)) {
+        Ok(usage) => usage,
+        Err(e) => return chat_error_response(e),
+    };
+    let mut total = Usage::default();
+    let mut cost = 0.0;
+    let per_model = usage.iter()
+        .map(|(model, usage)| {
+            let model_cost = models::lookup(model).map(|info| info.cost(usage));
+            total.add(usage);
+            cost += model_cost.unwrap_or_default();
+            json!({"model": model, "usage": usage, "cost": model_cost})
+        })
+        .collect::<Vec<Value>>();
+    HttpResponse::Ok().json(json!({"session": path.as_str(), "models": per_model, "usage": total, "cost": cost}))
+}
+
+/// Carries out an instruction about lines of a file, answered with prose or the edits to make.
+async fn code_action(req: HttpRequest, daemon: web::Data<Daemon>, body: web::Json<ActionRequest>) -> HttpResponse {
+    if !daemon.authorized(&req) {
+        return unauthorized();
+    }
+    match actions::answer(&body, daemon.persona.as_deref(), &daemon.stop_sequences).await {
+        Ok(action) => HttpResponse::Ok().json(action),
+        Err(e) => chat_error_response(e),
+    }
+}
+
+/// The `/api` routes, registered ahead of the catch-all for the frontend's files.
+pub fn routes(config: &mut web::ServiceConfig) {
+    config.service(
+        web::scope("/api")
+            .route("/sessions", web::post().to(create_session))
+            .route("/sessions", web::get().to(list_sessions))
+            .route("/sessions/{id}", web::delete().to(close_session))
+            .route("/sessions/{id}/messages", web::post().to(send_message))
+            .route("/sessions/{id}/events", web::get().to(stream_events))
+            .route("/sessions/{id}/ws", web::get().to(websocket))
+            .route("/sessions/{id}/approvals", web::get().to(list_approvals))
+            .route("/sessions/{id}/approvals/{call}", web::post().to(answer_approval))
+            .route("/sessions/{id}/cancel", web::post().to(cancel_turn))
+            .route("/sessions/{id}/usage", web::get().to(get_usage))
+            .route("/actions", web::post().to(code_action))
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    #[actix_web::test]
+    async fn test_token_and_answers() {
+        let daemon = web::Data::new(Daemon::new("secret".to_string(), None, &[]));
+        let app = test::init_service(App::new().app_data(daemon.clone()).configure(routes)).await;
+
+        let anonymous = test::TestRequest::get().uri("/api/sessions").to_request();
+        assert_eq!(test::call_service(&app, anonymous).await.status(), http::StatusCode::UNAUTHORIZED);
+        let wrong = test::TestRequest::get().uri("/api/sessions").insert_header(("Authorization", "Bearer secreT")).to_request();
+        assert_eq!(test::call_service(&app, wrong).await.status(), http::StatusCode::UNAUTHORIZED);
+
+        let listed = test::TestRequest::get().uri("/api/sessions").insert_header(("Authorization", "Bearer secret")).to_request();
+        let listed: Value = test::call_and_read_body_json(&app, listed).await;
+        assert_eq!(listed, json!([]));
+        let missing = test::TestRequest::get().uri("/api/sessions/nope/usage").insert_header(("Authorization", "Bearer secret")).to_request();
+        assert_eq!(test::call_service(&app, missing).await.status(), http::StatusCode::NOT_FOUND);
+
+        let answer = |value: Value| serde_json::from_value::<Answer>(value).map(Decision::from).unwrap();
+        assert_eq!(answer(json!({"decision": "approve"})), Decision::Approve);
+        assert_eq!(answer(json!({"decision": "always"})), Decision::AlwaysAllow);
+        assert_eq!(answer(json!({"decision": "deny"})), Decision::Deny(String::new()));
+        assert_eq!(answer(json!({"decision": "edit", "input": {"path": "a.rs"}})), Decision::Edit(json!({"path": "a.rs"})));
+
+        let command = |text: &str| serde_json::from_str::<Command>(text).unwrap();
+        assert_eq!(
+            command(r#"{"type": "approval", "id": 2, "decision": "deny", "reason": "not that file"}"#),
+            Command::Approval { id: 2, answer: Answer::Deny { reason: "not that file".to_string() } },
+        );
+        assert_eq!(command(r#"{"type": "message", "text": "Run the tests"}"#), Command::Message { text: "Run the tests".to_string() });
+        assert_eq!(command(r#"{"type": "cancel"}"#), Command::Cancel);
+    }
+
+    #[actix_web::test]
+    async fn test_token_file_private() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(TOKEN_FILE);
+        std::fs::write(&path, "left by an older run")?;
+        write_token(&path, "fresh")?;
+        assert_eq!(std::fs::read_to_string(&path)?, "fresh");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use crate::auth::AuthFailure;
+use crate::bench;
+use crate::chat::Chat;
+use crate::config::ProjectConfig;
+use crate::inference::types::{ContentItem, InferenceError, Message, Role};
+use crate::models;
+use crate::sandbox::Sandbox;
+use crate::tree::GitTree;
+
+// Asked of each model, the reply is a word so the request costs next to nothing
+const PING: &str = "Reply with just the word ok.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    /// Works, but something is off that may bite later
+    Warn,
+    Fail,
+}
+
+/// One thing `pprog doctor` looked at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &str, status: Status, detail: impl Into<String>) -> Self {
+        Check { name: name.to_string(), status, detail: detail.into() }
+    }
+}
+
+/// The config the other checks run with, the default when pprog.toml doesn't load.
+pub fn config() -> (ProjectConfig, Check) {
+    // The policy first, every other command refuses to start on a broken one
+    if let Err(e) = ProjectConfig::load_or_default() {
+        return (ProjectConfig::default(), Check::new("config", Status::Fail, format!("{:#}, checking with the defaults", e)));
+    }
+    match ProjectConfig::load() {
+        Ok(config) => {
+            let detail = format!("pprog.toml and the repository policy loaded, provider {}, model {}", config.provider, config.model);
+            (config, Check::new("config", Status::Ok, detail))
+        },
+        Err(e) => {
+            let path = ProjectConfig::config_path().map_or("pprog.toml".to_string(), |path| path.display().to_string());
+            (ProjectConfig::default(), Check::new("config", Status::Fail, format!("{}: {}, checking with the defaults", path, e)))
+        },
+    }
+}
+
+// The provider said it has no such model, rather than turning the key down
+fn model_missing(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| match cause.downcast_ref::<InferenceError>() {
+        Some(InferenceError::ApiError(status, body)) => {
+            let body = body.to_lowercase();
+            status.as_u16() == 404 || (body.contains("model") && (body.contains("not found") || body.contains("not_found") || body.contains("does not exist")))
+        },
+        _ => false,
+    })
+}
+
+// What a failed ping says about the credentials and the model
+fn ping_failure(provider: &str, error: &anyhow::Error) -> String {
+    if let Some(failure) = AuthFailure::of(error) {
+        return failure.describe(provider);
+    }
+    let missing_key = error.chain().any(|cause| matches!(cause.downcast_ref::<InferenceError>(), Some(InferenceError::MissingApiKey(_))));
+    match (missing_key, model_missing(error)) {
+        (true, _) => format!("No {} API key, set api_key in pprog.toml or [auth]", provider),
+        (false, true) => format!("Authenticated, but {} has no such model: {:#}", provider, error),
+        (false, false) => format!("Request failed: {:#}", error),
+    }
+}
+
+/*
+    * Sends one short request to `model` through the configured provider, the way a session
+    * would reach it but without tools and past the response cache, so a reply is proof the
+    * key is accepted and the model is served.  The latency is that of the whole reply.
+*/
+pub async fn ping(config: &ProjectConfig, model: &str) -> Check {
+    let mut config = config.clone();
+    config.model = model.to_string();
+    config.no_tools = true;
+    config.max_output_tokens = 16;
+    config.middleware.chain.retain(|name| name != "cache");
+    let name = format!("{} {}", config.provider, model);
+    let chat = match Chat::from_config(config.clone()).await {
+        Ok(chat) => chat,
+        Err(e) => return Check::new(&name, Status::Fail, format!("{:#}", e)),
+    };
+    let message = Message { role: Role::User, content: vec![ContentItem::Text { text: PING.to_string() }] };
+    let started = Instant::now();
+    match chat.query_streamed(PING, vec![message]).await {
+        Ok(response) => {
+            let latency = response.timing.as_ref().map(|timing| timing.total).unwrap_or_else(|| started.elapsed());
+            match models::lookup(model) {
+                Some(_) => Check::new(&name, Status::Ok, format!("authenticated, model available, replied in {}", seconds(latency))),
+                None => Check::new(&name, Status::Warn, format!(
+                    "authenticated, model available, replied in {}, but it isn't in pprog's model table so its context window and cost are guesses",
+                    seconds(latency),
+                )),
+            }
+        },
+        Err(e) => Check::new(&name, Status::Fail, ping_failure(&config.provider, &e)),
+    }
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.2}s", duration.as_secs_f64())
+}
+
+// The first line `program --version` prints, None when it can't be run
+fn version(program: &str) -> Option<String> {
+    let output = Command::new(program).arg("--version").output().ok().filter(|output| output.status.success())?;
+    Some(String::from_utf8_lossy(&output.stdout).lines().next().unwrap_or_default().trim().to_string())
+}
+
+// Whether `program` is a file in one of the directories of PATH, or a path that exists
+fn on_path(program: &str) -> bool {
+    if program.contains(std::path::MAIN_SEPARATOR) {
+        return Path::new(program).is_file();
+    }
+    std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+}
+
+/// git, ripgrep, the sandbox the file tools are held to and the program of `check_cmd`.
+pub fn tools(config: &ProjectConfig) -> Vec<Check> {
+    let mut checks = Vec::new();
+    checks.push(match version("git") {
+        Some(version) => Check::new("git", Status::Ok, version),
+        None => Check::new("git", Status::Fail, "git isn't installed, pprog works from the git root"),
+    });
+    let root = GitTree::get_git_root();
+    checks.push(match &root {
+        Ok(root) => Check::new("repository", Status::Ok, root.display().to_string()),
+        Err(e) => Check::new("repository", Status::Fail, format!("Not in a git repository: {}", e)),
+    });
+    checks.push(match version("rg") {
+        Some(version) => Check::new("rg", Status::Ok, version),
+        None => Check::new("rg", Status::Warn, "ripgrep isn't installed, the model's searches through execute fall back to grep"),
+    });
+    if let Ok(root) = &root {
+        checks.push(match Sandbox::new(root, &config.sandbox) {
+            Ok(sandbox) => {
+                let missing = config.sandbox.read_only.iter().filter(|mount| !root.join(mount).exists()).cloned().collect::<Vec<String>>();
+                match missing.is_empty() {
+                    true => Check::new("sandbox", Status::Ok, format!("file tools held to {}", sandbox.root().display())),
+                    false => Check::new("sandbox", Status::Warn, format!(
+                        "file tools held to {}, read-only paths missing on this machine: {}",
+                        sandbox.root().display(),
+                        missing.join(", "),
+                    )),
+                }
+            },
+            Err(e) => Check::new("sandbox", Status::Fail, format!("{}", e)),
+        });
+    }
+    if let Some(program) = config.check_cmd.split_whitespace().next() {
+        checks.push(match on_path(program) {
+            true => Check::new("check_cmd", Status::Ok, config.check_cmd.clone()),
+            false => Check::new("check_cmd", Status::Warn, format!("{} isn't on PATH, compile_check will fail", program)),
+        });
+    }
+    checks
+}
+
+/// Every check, the providers left out when `offline`.
+pub async fn run(offline: bool) -> Vec<Check> {
+    let (config, loaded) = config();
+    let mut checks = vec![loaded];
+    if !offline {
+        // One at a time, so the latencies aren't those of requests queued behind each other
+        for model in bench::configured_models(&config) {
+            checks.push(ping(&config, &model).await);
+        }
+    }
+    checks.extend(tools(&config));
+    checks
+}
+
+/// The checks one per line, with a last line counting the failures and warnings.
+pub fn report(checks: &[Check]) -> String {
+    let width = checks.iter().map(|check| check.name.chars().count()).max().unwrap_or_default();
+    let mut report = String::new();
+    for check in checks {
+        let status = match check.status {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        report.push_str(&format!("{:<4}  {:<width$}  {}\n", status, check.name, check.detail));
+    }
+    let count = |status: Status| checks.iter().filter(|check| check.status == status).count();
+    match (count(Status::Fail), count(Status::Warn)) {
+        (0, 0) => report.push_str("All checks passed\n"),
+        (failed, warned) => report.push_str(&format!("{} failed, {} with warnings\n", failed, warned)),
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failures_and_report() {
+        let api_error = |status: u16, body: &str| anyhow::Error::new(InferenceError::ApiError(reqwest::StatusCode::from_u16(status).unwrap(), body.to_string()));
+        assert_eq!(ping_failure("openai", &api_error(401, "Incorrect API key")), "The openai API key was rejected as invalid");
+        assert!(ping_failure("openai", &api_error(404, "The model `gpt-9` does not exist")).starts_with("Authenticated, but openai has no such model"));
+        assert!(ping_failure("anthropic", &api_error(400, "{\"type\": \"not_found_error\", \"message\": \"model: claude-x\"}")).starts_with("Authenticated"));
+        assert!(ping_failure("anthropic", &api_error(529, "Overloaded")).starts_with("Request failed"));
+        assert!(on_path("sh"));
+        assert!(!on_path("surely-not-a-program-pprog-knows"));
+
+        let checks = vec![
+            Check::new("config", Status::Ok, "loaded"),
+            Check::new("rg", Status::Warn, "missing"),
+        ];
+        assert_eq!(report(&checks), "ok    config  loaded\nwarn  rg      missing\n0 failed, 1 with warnings\n");
+        assert!(report(&checks[..1]).ends_with("All checks passed\n"));
+    }
+}
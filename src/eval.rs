@@ -0,0 +1,503 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use regex::RegexBuilder;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use similar::TextDiff;
+
+use crate::chat::Chat;
+use crate::config::ProjectConfig;
+use crate::embeddings::{self, EmbeddingProvider};
+use crate::inference::types::{ContentItem, Message, Role};
+use crate::prompt::PromptTemplate;
+use crate::replay;
+use crate::report::{self, Finding, Report, TestCase};
+use crate::structured::{self, JsonSchema};
+
+const GRADE_PROMPT: &str = "You grade the reply of an AI model against the criteria the user gives.  \
+Pass it only if the reply meets every one of them, and say in one sentence why.";
+
+/// A set of prompts to run against models, from a TOML file for `pprog eval`.
+#[derive(Debug, Deserialize)]
+pub struct Suite {
+    /// Models to run every case against, the configured model when empty
+    #[serde(default)]
+    pub models: Vec<String>,
+    /// Grades `judge` assertions, the configured model when unset
+    pub judge_model: Option<String>,
+    /// Prompt template from `.pprog/prompts/` to run the cases with, pprog's own when unset
+    pub template: Option<String>,
+    /// A system prompt used instead of any template
+    pub system: Option<String>,
+    #[serde(default)]
+    pub temperature: f32,
+    /// Seed for providers that take one, the configured seed or 0 when unset
+    pub seed: Option<u64>,
+    /// How close, from 0 to 1, a reply must be to its golden one to pass
+    #[serde(default = "default_similarity")]
+    pub similarity: f32,
+    #[serde(default, rename = "case")]
+    pub cases: Vec<Case>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+fn default_similarity() -> f32 {
+    0.9
+}
+
+/// A reply kept by `pprog eval --update`, that later replies to the case are compared with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Golden {
+    pub model: String,
+    pub reply: String,
+}
+
+/// The golden replies of a suite, by case name.
+pub type Goldens = BTreeMap<String, Golden>;
+
+#[derive(Debug, Deserialize)]
+pub struct Case {
+    pub name: String,
+    pub prompt: String,
+    /// Files attached to the prompt, relative to the suite
+    #[serde(default)]
+    pub fixtures: Vec<PathBuf>,
+    /// Overrides the suite's system prompt for this case
+    pub system: Option<String>,
+    #[serde(default, rename = "assert")]
+    pub asserts: Vec<Assertion>,
+}
+
+/// What a reply must do to pass.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Assertion {
+    Contains {
+        value: String,
+        #[serde(default)]
+        ignore_case: bool,
+    },
+    NotContains {
+        value: String,
+        #[serde(default)]
+        ignore_case: bool,
+    },
+    Regex { pattern: String },
+    /// The whole reply, ignoring whitespace at either end
+    Equals { value: String },
+    /// The reply is JSON, possibly in a code fence, matching `schema`
+    JsonSchema { schema: Value },
+    /// The judge model finds the reply meets `criteria`
+    Judge { criteria: String },
+}
+
+/// An assertion a reply failed.
+#[derive(Debug)]
+pub struct Failure {
+    pub assertion: String,
+    pub reason: String,
+    pub diff: Option<String>,
+}
+
+/// How one case went with one model.
+#[derive(Debug)]
+pub struct Outcome {
+    pub case: String,
+    pub model: String,
+    /// False when the request failed
+    pub replied: bool,
+    pub reply: String,
+    pub failures: Vec<Failure>,
+    /// How long the request and the checks took
+    pub duration: Duration,
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+// What the judge model replies with
+#[derive(Debug, Deserialize)]
+struct Grade {
+    pass: bool,
+    reason: String,
+}
+
+impl JsonSchema for Grade {
+    fn json_schema() -> Value {
+        structured::object(&[
+            ("pass", structured::described(bool::json_schema(), "Whether the reply meets every criterion")),
+            ("reason", structured::described(String::json_schema(), "One sentence on why")),
+        ])
+    }
+
+    fn schema_name() -> String {
+        String::from("grade")
+    }
+}
+
+impl Suite {
+    /// The suite at `path`.
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let text = fs::read_to_string(path).map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+        let mut suite: Suite = toml::from_str(&text).map_err(|e| anyhow::anyhow!("Invalid suite {}: {}", path.display(), e))?;
+        if suite.cases.is_empty() {
+            anyhow::bail!("{} has no [[case]] to run", path.display());
+        }
+        suite.path = path.to_path_buf();
+        Ok(suite)
+    }
+
+    // Fixtures are relative to the directory of the suite
+    fn base(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new("."))
+    }
+
+    /// Where the golden replies are kept, `<suite>.golden.json` next to the suite.
+    pub fn golden_path(&self) -> PathBuf {
+        self.path.with_extension("golden.json")
+    }
+
+    /// The golden replies, none before the first `--update`.
+    pub fn goldens(&self) -> Result<Goldens, anyhow::Error> {
+        let path = self.golden_path();
+        match fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| anyhow::anyhow!("Invalid goldens {}: {}", path.display(), e)),
+            Err(_) => Ok(Goldens::new()),
+        }
+    }
+
+    /// Keeps the replies of the first model as the goldens, returning how many there are.
+    pub fn update_goldens(&self, outcomes: &[Outcome]) -> Result<usize, anyhow::Error> {
+        let Some(first) = outcomes.first().map(|outcome| outcome.model.clone()) else { return Ok(0) };
+        let goldens = outcomes.iter()
+            .filter(|outcome| outcome.model == first && outcome.replied)
+            .map(|outcome| (outcome.case.clone(), Golden { model: outcome.model.clone(), reply: outcome.reply.clone() }))
+            .collect::<Goldens>();
+        fs::write(self.golden_path(), serde_json::to_string_pretty(&goldens)? + "\n")?;
+        Ok(goldens.len())
+    }
+}
+
+impl Case {
+    // The prompt with the fixtures under `base` attached, named as the suite names them
+    fn message(&self, base: &Path) -> Result<Message, anyhow::Error> {
+        let mut content = vec![ContentItem::Text { text: self.prompt.clone() }];
+        for fixture in &self.fixtures {
+            let text = fs::read_to_string(base.join(fixture)).map_err(|e| anyhow::anyhow!("Failed to read the fixture {}: {}", fixture.display(), e))?;
+            content.push(ContentItem::Text { text: format!("{}\n```\n{}\n```", fixture.display(), text.trim_end()) });
+        }
+        Ok(Message { role: Role::User, content })
+    }
+}
+
+impl Assertion {
+    fn describe(&self) -> String {
+        match self {
+            Assertion::Contains { value, .. } => format!("contains {:?}", value),
+            Assertion::NotContains { value, .. } => format!("doesn't contain {:?}", value),
+            Assertion::Regex { pattern } => format!("matches /{}/", pattern),
+            Assertion::Equals { .. } => String::from("equals the expected reply"),
+            Assertion::JsonSchema { .. } => String::from("is JSON matching the schema"),
+            Assertion::Judge { criteria } => format!("judged to meet {:?}", criteria),
+        }
+    }
+
+    // Checks everything but `Judge`, which needs a request
+    fn check(&self, reply: &str) -> Result<(), (String, Option<String>)> {
+        let contains = |value: &str, ignore_case: bool| match ignore_case {
+            true => reply.to_lowercase().contains(&value.to_lowercase()),
+            false => reply.contains(value),
+        };
+        match self {
+            Assertion::Contains { value, ignore_case } if !contains(value, *ignore_case) => Err((String::from("not in the reply"), None)),
+            Assertion::NotContains { value, ignore_case } if contains(value, *ignore_case) => Err((String::from("in the reply"), None)),
+            Assertion::Regex { pattern } => {
+                let regex = RegexBuilder::new(pattern).multi_line(true).build().map_err(|e| (format!("invalid pattern: {}", e), None))?;
+                match regex.is_match(reply) {
+                    true => Ok(()),
+                    false => Err((String::from("no match in the reply"), None)),
+                }
+            },
+            Assertion::Equals { value } if value.trim() != reply.trim() => {
+                let diff = TextDiff::from_lines(&format!("{}\n", value.trim()), &format!("{}\n", reply.trim()))
+                    .unified_diff()
+                    .header("expected", "reply")
+                    .to_string();
+                Err((String::from("the reply differs"), Some(diff)))
+            },
+            Assertion::JsonSchema { schema } => {
+                let text = reply.trim();
+                let text = match text.strip_prefix("```") {
+                    Some(fenced) => fenced.trim_start_matches("json").trim_end_matches("```").trim(),
+                    None => text,
+                };
+                let value: Value = serde_json::from_str(text).map_err(|e| (format!("not JSON: {}", e), None))?;
+                structured::check_schema(schema, &value).map_err(|reason| (reason, None))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/// How alike two texts are, from 0 to 1, by the words they share in order.
+pub fn text_similarity(a: &str, b: &str) -> f32 {
+    TextDiff::from_words(a.trim(), b.trim()).ratio()
+}
+
+// Scores replies against goldens by the cosine of their embeddings, or as text when there are none
+struct Scorer {
+    embedder: Option<EmbeddingProvider>,
+}
+
+impl Scorer {
+    async fn score(&mut self, golden: &str, reply: &str) -> (f32, &'static str) {
+        if golden.trim() == reply.trim() {
+            return (1.0, "exact");
+        }
+        if let Some(embedder) = &self.embedder {
+            match embedder.embed(&[golden.to_string(), reply.to_string()]).await {
+                Ok(vectors) => return (embeddings::cosine(&vectors[0], &vectors[1]), "embeddings"),
+                Err(e) => {
+                    eprintln!("Comparing replies as text, the embeddings failed: {:#}", e);
+                    self.embedder = None;
+                },
+            }
+        }
+        (text_similarity(golden, reply), "text")
+    }
+
+    async fn compare(&mut self, golden: &Golden, reply: &str, similarity: f32) -> Option<Failure> {
+        let (score, by) = self.score(&golden.reply, reply).await;
+        if score >= similarity {
+            return None;
+        }
+        let diff = TextDiff::from_lines(golden.reply.as_str(), reply)
+            .unified_diff()
+            .header(&format!("golden ({})", golden.model), "reply")
+            .to_string();
+        Some(Failure {
+            assertion: String::from("matches the golden reply"),
+            reason: format!("similarity {:.2} by {} is under {:.2}", score, by, similarity),
+            diff: Some(diff),
+        })
+    }
+}
+
+// A chat on `model`, set up the way the suite runs its cases
+async fn chat_for(config: &ProjectConfig, suite: &Suite, model: &str) -> Result<Chat, anyhow::Error> {
+    let mut config = config.clone();
+    config.model = model.to_string();
+    config.temperature = Some(suite.temperature);
+    config.seed = suite.seed.or(config.seed).or(Some(0));
+    let mut chat = Chat::from_config(config).await?;
+    if let Some(name) = &suite.template {
+        chat.set_prompt_template(PromptTemplate::load(name)?);
+    }
+    Ok(chat)
+}
+
+/*
+    * Runs every case of `suite` against each of its models as a single request on an empty
+    * session, then checks the reply against the case's assertions.  Tools are offered as in
+    * a chat but not run, a tool call shows in the reply as `[tool_use name] input`.  A case
+    * with a golden reply also fails when the reply drifted from it by more than `similarity`
+    * allows.  A request that fails fails its case, the rest of the suite still runs.
+*/
+pub async fn run(config: &ProjectConfig, suite: &Suite, goldens: &Goldens) -> Result<Vec<Outcome>, anyhow::Error> {
+    let models = match suite.models.is_empty() {
+        true => vec![config.model.clone()],
+        false => suite.models.clone(),
+    };
+    let judge_model = suite.judge_model.clone().unwrap_or_else(|| config.model.clone());
+    let mut scorer = Scorer { embedder: EmbeddingProvider::from_config(&config.embeddings).ok() };
+    let mut outcomes = Vec::new();
+    for model in &models {
+        let chat = chat_for(config, suite, model).await?;
+        for case in &suite.cases {
+            let message = case.message(suite.base())?;
+            let (system, messages) = match case.system.as_ref().or(suite.system.as_ref()) {
+                Some(system) => (system.clone(), vec![message]),
+                None => chat.build_request(&[message])?,
+            };
+            let started = Instant::now();
+            let mut outcome = Outcome {
+                case: case.name.clone(),
+                model: model.clone(),
+                replied: false,
+                reply: String::new(),
+                failures: Vec::new(),
+                duration: Duration::ZERO,
+            };
+            match chat.query(&system, messages).await {
+                Ok(response) => {
+                    outcome.replied = true;
+                    outcome.reply = replay::render(&response.content);
+                },
+                Err(e) => {
+                    outcome.failures.push(Failure { assertion: String::from("gets a reply"), reason: e.to_string(), diff: None });
+                    outcome.duration = started.elapsed();
+                    outcomes.push(outcome);
+                    continue;
+                },
+            }
+            for assertion in &case.asserts {
+                let checked = match assertion {
+                    Assertion::Judge { criteria } => grade(&chat, &judge_model, case, criteria, &outcome.reply).await,
+                    _ => assertion.check(&outcome.reply),
+                };
+                if let Err((reason, diff)) = checked {
+                    outcome.failures.push(Failure { assertion: assertion.describe(), reason, diff });
+                }
+            }
+            if let Some(golden) = goldens.get(&case.name) {
+                outcome.failures.extend(scorer.compare(golden, &outcome.reply, suite.similarity).await);
+            }
+            outcome.duration = started.elapsed();
+            outcomes.push(outcome);
+        }
+    }
+    Ok(outcomes)
+}
+
+async fn grade(chat: &Chat, judge_model: &str, case: &Case, criteria: &str, reply: &str) -> Result<(), (String, Option<String>)> {
+    let text = format!("Prompt:\n{}\n\nReply:\n{}\n\nCriteria:\n{}", case.prompt.trim(), reply.trim(), criteria.trim());
+    let message = Message { role: Role::User, content: vec![ContentItem::Text { text }] };
+    match chat.query_structured::<Grade>(Some(judge_model), GRADE_PROMPT, vec![message]).await {
+        Ok(grade) if grade.pass => Ok(()),
+        Ok(grade) => Err((format!("{}: {}", judge_model, grade.reason.trim()), None)),
+        Err(e) => Err((format!("the grading by {} failed: {}", judge_model, e), None)),
+    }
+}
+
+/// Every outcome as a PASS or FAIL line, failures with why and any diff, and the totals.
+pub fn report(outcomes: &[Outcome]) -> String {
+    let mut out = String::new();
+    for outcome in outcomes {
+        let status = if outcome.passed() { "PASS" } else { "FAIL" };
+        writeln!(out, "{}  {} ({})", status, outcome.case, outcome.model).ok();
+        for failure in &outcome.failures {
+            writeln!(out, "      {}: {}", failure.assertion, failure.reason).ok();
+            if let Some(diff) = &failure.diff {
+                for line in diff.lines() {
+                    writeln!(out, "      {}", line).ok();
+                }
+            }
+        }
+    }
+    let passed = outcomes.iter().filter(|outcome| outcome.passed()).count();
+    writeln!(out, "{} of {} passed.", passed, outcomes.len()).ok();
+    out
+}
+
+/*
+    * The outcomes for CI: a JUnit test for each case and model, and a SARIF error for each
+    * failed assertion, placed on the line of the suite that names the case.
+*/
+pub fn ci_report(suite: &Suite, outcomes: &[Outcome]) -> Report {
+    let name = suite.path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    let text = fs::read_to_string(&suite.path).unwrap_or_default();
+    let case_line = |case: &str| {
+        let quoted = format!("{:?}", case);
+        text.lines().position(|line| line.trim_start().starts_with("name") && line.contains(&quoted)).map(|index| index as u32 + 1)
+    };
+    let mut report = Report { suite: name.clone(), ..Default::default() };
+    for outcome in outcomes {
+        let failure = outcome.failures.first().map(|first| {
+            let mut details = String::new();
+            for failure in &outcome.failures {
+                writeln!(details, "{}: {}", failure.assertion, failure.reason).ok();
+                if let Some(diff) = &failure.diff {
+                    details.push_str(diff);
+                }
+            }
+            (format!("{}: {}", first.assertion, first.reason), details)
+        });
+        report.cases.push(TestCase {
+            name: outcome.case.clone(),
+            classname: format!("{}.{}", name, outcome.model),
+            time: outcome.duration,
+            failure,
+        });
+        report.findings.extend(outcome.failures.iter().map(|failure| Finding {
+            rule: "eval-failure",
+            level: "error",
+            message: format!("{} with {}: {}: {}", outcome.case, outcome.model, failure.assertion, failure.reason),
+            path: Some(report::repo_path(&suite.path)),
+            line: case_line(&outcome.case),
+        }));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suite_and_checks() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("input.rs"), "fn main() {}\n")?;
+        let path = dir.path().join("suite.toml");
+        fs::write(&path, r#"
+            models = ["claude-3-5-haiku-latest"]
+
+            [[case]]
+            name = "json"
+            prompt = "Describe input.rs as JSON"
+            fixtures = ["input.rs"]
+
+            [[case.assert]]
+            type = "json_schema"
+            schema = { type = "object", required = ["name"], properties = { name = { type = "string" } } }
+
+            [[case.assert]]
+            type = "contains"
+            value = "MAIN"
+            ignore_case = true
+        "#)?;
+        let suite = Suite::load(&path)?;
+        let case = &suite.cases[0];
+        assert!(matches!(&case.message(suite.base())?.content[1], ContentItem::Text { text } if text.ends_with("input.rs\n```\nfn main() {}\n```")));
+
+        let reply = "```json\n{\"name\": \"main\"}\n```";
+        assert!(case.asserts.iter().all(|assertion| assertion.check(reply).is_ok()));
+        assert_eq!(case.asserts[0].check("{\"name\": 1}").unwrap_err().0, "$.name is 1, not string");
+
+        let equals = Assertion::Equals { value: "one\ntwo\n".to_string() };
+        let (_, diff) = equals.check("one\nthree").unwrap_err();
+        assert!(diff.unwrap().contains("-two\n+three"));
+        assert!(Assertion::Regex { pattern: r"^\d+$".to_string() }.check("text\n42").is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_goldens() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("suite.toml");
+        fs::write(&path, "similarity = 0.8\n[[case]]\nname = \"greet\"\nprompt = \"Say hello\"\n")?;
+        let suite = Suite::load(&path)?;
+        assert!(suite.goldens()?.is_empty());
+
+        let outcome = |model: &str, reply: &str| Outcome { case: "greet".to_string(), model: model.to_string(), replied: true, reply: reply.to_string(), failures: Vec::new(), duration: Duration::ZERO };
+        let reply = "Hello there, how can I help you with this project today?";
+        assert_eq!(suite.update_goldens(&[outcome("old", reply), outcome("new", "Hi")])?, 1);
+        assert_eq!(suite.golden_path(), dir.path().join("suite.golden.json"));
+        let goldens = suite.goldens()?;
+        assert_eq!(goldens["greet"].model, "old");
+
+        let mut scorer = Scorer { embedder: None };
+        assert!(scorer.compare(&goldens["greet"], "Hello there, how can I help you with this project now?", suite.similarity).await.is_none());
+        let failure = scorer.compare(&goldens["greet"], "Goodbye.", suite.similarity).await.unwrap();
+        assert!(failure.reason.contains("by text is under 0.80"));
+        assert!(failure.diff.unwrap().contains("+Goodbye."));
+        Ok(())
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+use similar::TextDiff;
+
+use crate::inference::types::{ContentItem, Role};
+use crate::session::Session;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Md,
+    Json,
+    Html,
+}
+
+// Intermediate form shared by the markdown and html renderers
+enum Block {
+    Text { role: Role, text: String },
+    ToolCall { name: String, detail: String, body: String, is_diff: bool },
+    ToolResult { name: String, content: String },
+}
+
+pub fn render(session: &Session, format: ExportFormat) -> Result<String, anyhow::Error> {
+    match format {
+        ExportFormat::Md => Ok(render_markdown(session)),
+        ExportFormat::Json => Ok(serde_json::to_string_pretty(session)?),
+        ExportFormat::Html => Ok(render_html(session)),
+    }
+}
+
+fn role_label(role: &Role) -> &'static str {
+    match role {
+        Role::User => "User",
+        Role::Assistant => "Assistant",
+        Role::System | Role::Developer => "System",
+    }
+}
+
+fn input_str<'a>(input: &'a serde_json::Value, field: &str) -> &'a str {
+    input.get(field).and_then(|v| v.as_str()).unwrap_or_default()
+}
+
+/*
+    * Walks the transcript in order, tracking the last known contents of each file (from
+    * read_file results and earlier write_file calls) so that writes can be shown as diffs
+    * instead of full file dumps.
+*/
+fn collect_blocks(session: &Session) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut tool_uses: HashMap<String, (String, serde_json::Value)> = HashMap::new();
+    let mut known_files: HashMap<String, String> = HashMap::new();
+
+    for message in &session.messages {
+        for item in &message.content {
+            match item {
+                ContentItem::Text { text } => blocks.push(Block::Text {
+                    role: message.role.clone(),
+                    text: text.clone(),
+                }),
+                ContentItem::ToolUse { id, name, input } => {
+                    tool_uses.insert(id.clone(), (name.clone(), input.clone()));
+                    let block = match name.as_str() {
+                        "write_file" => {
+                            let path = input_str(input, "path").to_string();
+                            let content = input_str(input, "content").to_string();
+                            let block = match known_files.get(&path) {
+                                Some(previous) => Block::ToolCall {
+                                    name: name.clone(),
+                                    detail: path.clone(),
+                                    body: TextDiff::from_lines(previous.as_str(), content.as_str())
+                                        .unified_diff()
+                                        .header(&path, &path)
+                                        .to_string(),
+                                    is_diff: true,
+                                },
+                                None => Block::ToolCall {
+                                    name: name.clone(),
+                                    detail: path.clone(),
+                                    body: content.clone(),
+                                    is_diff: false,
+                                },
+                            };
+                            known_files.insert(path, content);
+                            block
+                        },
+                        "read_file" => Block::ToolCall {
+                            name: name.clone(),
+                            detail: input_str(input, "path").to_string(),
+                            body: String::new(),
+                            is_diff: false,
+                        },
+                        "execute" => Block::ToolCall {
+                            name: name.clone(),
+                            detail: String::new(),
+                            body: input_str(input, "statement").to_string(),
+                            is_diff: false,
+                        },
+                        "compile_check" => Block::ToolCall {
+                            name: name.clone(),
+                            detail: String::new(),
+                            body: input_str(input, "cmd").to_string(),
+                            is_diff: false,
+                        },
+                        _ => Block::ToolCall {
+                            name: name.clone(),
+                            detail: String::new(),
+                            body: serde_json::to_string_pretty(input).unwrap_or_default(),
+                            is_diff: false,
+                        },
+                    };
+                    blocks.push(block);
+                },
+                ContentItem::Image { source } => blocks.push(Block::Text {
+                    role: message.role.clone(),
+                    text: format!("[image {}]", source.media_type),
+                }),
+                ContentItem::Unknown(value) => blocks.push(Block::Text {
+                    role: message.role.clone(),
+                    text: format!("[{} block]", value["type"].as_str().unwrap_or("unknown")),
+                }),
+                ContentItem::ToolResult { tool_use_id, content, is_error } => {
+                    let name = match tool_uses.get(tool_use_id) {
+                        Some((name, input)) => {
+                            if name == "read_file" && !is_error && !content.starts_with("Error reading file") {
+                                known_files.insert(input_str(input, "path").to_string(), content.clone());
+                            }
+                            name.clone()
+                        },
+                        None => String::from("unknown"),
+                    };
+                    blocks.push(Block::ToolResult { name, content: content.clone() });
+                },
+            }
+        }
+    }
+
+    blocks
+}
+
+// Picks a backtick fence longer than any run of backticks inside the content
+fn fence(content: &str) -> String {
+    let mut longest = 0;
+    let mut current = 0;
+    for c in content.chars() {
+        if c == '`' {
+            current += 1;
+            longest = longest.max(current);
+        } else {
+            current = 0;
+        }
+    }
+    "`".repeat(longest.max(2) + 1)
+}
+
+fn render_markdown(session: &Session) -> String {
+    let mut out = format!(
+        "# Session {}\n\nCreated: {}\n",
+        session.id,
+        session.created_at.format("%Y-%m-%d %H:%M:%S UTC"),
+    );
+    if let Some(sampling) = &session.sampling {
+        out.push_str(&format!("Sampling: {}\n", sampling));
+    }
+
+    for block in collect_blocks(session) {
+        match block {
+            Block::Text { role, text } => {
+                out.push_str(&format!("\n## {}\n\n{}\n", role_label(&role), text));
+            },
+            Block::ToolCall { name, detail, body, is_diff } => {
+                out.push_str(&format!("\n**Tool call:** `{}`", name));
+                if !detail.is_empty() {
+                    out.push_str(&format!(" `{}`", detail));
+                }
+                out.push('\n');
+                if !body.is_empty() {
+                    let fence = fence(&body);
+                    let lang = if is_diff { "diff" } else { "" };
+                    out.push_str(&format!("\n{}{}\n{}\n{}\n", fence, lang, body.trim_end(), fence));
+                }
+            },
+            Block::ToolResult { name, content } => {
+                let fence = fence(&content);
+                out.push_str(&format!(
+                    "\n**Tool result:** `{}`\n\n{}\n{}\n{}\n",
+                    name, fence, content.trim_end(), fence
+                ));
+            },
+        }
+    }
+
+    out
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn render_diff_html(diff: &str) -> String {
+    diff.lines()
+        .map(|line| {
+            let class = if line.starts_with("+++") || line.starts_with("---") {
+                "diff-header"
+            } else if line.starts_with('+') {
+                "diff-add"
+            } else if line.starts_with('-') {
+                "diff-remove"
+            } else if line.starts_with("@@") {
+                "diff-hunk"
+            } else {
+                "diff-context"
+            };
+            format!("<span class=\"{}\">{}</span>", class, escape_html(line))
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+const HTML_STYLE: &str = r#"
+body { font-family: -apple-system, BlinkMacSystemFont, "Segoe UI", sans-serif; max-width: 960px; margin: 2rem auto; padding: 0 1rem; color: #1f2328; }
+.message { border-radius: 8px; padding: 0.75rem 1rem; margin: 1rem 0; white-space: pre-wrap; }
+.user { background: #ddf4ff; }
+.assistant { background: #f6f8fa; }
+.system { background: #fff8c5; }
+.tool { border-left: 3px solid #8c959f; padding-left: 1rem; margin: 1rem 0; }
+.role { font-weight: 600; margin-bottom: 0.5rem; }
+pre { background: #f6f8fa; padding: 0.75rem; overflow-x: auto; border-radius: 6px; }
+.diff-add { color: #116329; background: #dafbe1; }
+.diff-remove { color: #82071e; background: #ffebe9; }
+.diff-hunk { color: #0550ae; }
+.diff-header { font-weight: 600; }
+"#;
+
+fn render_html(session: &Session) -> String {
+    let mut body = String::new();
+
+    for block in collect_blocks(session) {
+        match block {
+            Block::Text { role, text } => {
+                let label = role_label(&role);
+                body.push_str(&format!(
+                    "<div class=\"message {}\"><div class=\"role\">{}</div>{}</div>\n",
+                    label.to_lowercase(), label, escape_html(&text)
+                ));
+            },
+            Block::ToolCall { name, detail, body: content, is_diff } => {
+                body.push_str(&format!("<div class=\"tool\"><div class=\"role\">Tool call: <code>{}</code>", escape_html(&name)));
+                if !detail.is_empty() {
+                    body.push_str(&format!(" <code>{}</code>", escape_html(&detail)));
+                }
+                body.push_str("</div>");
+                if !content.is_empty() {
+                    let rendered = if is_diff { render_diff_html(&content) } else { escape_html(&content) };
+                    body.push_str(&format!("<pre>{}</pre>", rendered));
+                }
+                body.push_str("</div>\n");
+            },
+            Block::ToolResult { name, content } => {
+                body.push_str(&format!(
+                    "<div class=\"tool\"><div class=\"role\">Tool result: <code>{}</code></div><pre>{}</pre></div>\n",
+                    escape_html(&name), escape_html(&content)
+                ));
+            },
+        }
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Session {id}</title>\n<style>{style}</style>\n</head>\n<body>\n<h1>Session {id}</h1>\n<p>Created: {created}</p>\n{sampling}{body}</body>\n</html>\n",
+        id = escape_html(&session.id),
+        style = HTML_STYLE,
+        created = session.created_at.format("%Y-%m-%d %H:%M:%S UTC"),
+        sampling = session.sampling.as_ref().map_or(String::new(), |sampling| format!("<p>Sampling: {}</p>\n", escape_html(&sampling.to_string()))),
+        body = body,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inference::types::Message;
+    use serde_json::json;
+
+    fn session_with_edit() -> Session {
+        let mut session = Session::new();
+        session.messages = vec![
+            Message {
+                role: Role::User,
+                content: vec![ContentItem::Text { text: "Rename the greeting".to_string() }],
+            },
+            Message {
+                role: Role::Assistant,
+                content: vec![ContentItem::ToolUse {
+                    id: "t1".to_string(),
+                    name: "read_file".to_string(),
+                    input: json!({"path": "main.js"}),
+                }],
+            },
+            Message {
+                role: Role::User,
+                content: vec![ContentItem::ToolResult {
+                    tool_use_id: "t1".to_string(),
+                    content: "console.log('hi');\n".to_string(),
+                    is_error: false,
+                }],
+            },
+            Message {
+                role: Role::Assistant,
+                content: vec![ContentItem::ToolUse {
+                    id: "t2".to_string(),
+                    name: "write_file".to_string(),
+                    input: json!({"path": "main.js", "content": "console.log('hello');\n"}),
+                }],
+            },
+        ];
+        session
+    }
+
+    #[test]
+    fn test_markdown_renders_writes_as_diffs() {
+        let markdown = render(&session_with_edit(), ExportFormat::Md).unwrap();
+
+        assert!(markdown.contains("## User\n\nRename the greeting"));
+        assert!(markdown.contains("```diff"));
+        assert!(markdown.contains("-console.log('hi');"));
+        assert!(markdown.contains("+console.log('hello');"));
+    }
+
+    #[test]
+    fn test_html_escapes_content() {
+        let mut session = Session::new();
+        session.messages = vec![Message {
+            role: Role::Assistant,
+            content: vec![ContentItem::Text { text: "<script>alert(1)</script>".to_string() }],
+        }];
+
+        let html = render(&session, ExportFormat::Html).unwrap();
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(!html.contains("<script>"));
+    }
+
+    #[test]
+    fn test_fence_outgrows_inner_backticks() {
+        assert_eq!(fence("plain"), "```");
+        assert_eq!(fence("has ``` inside"), "````");
+    }
+}
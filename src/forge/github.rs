@@ -0,0 +1,166 @@
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{ChangeRequest, Comment, Forge, Issue, Repo};
+
+// Tokens are taken from these first, then from the OS keychain
+const TOKEN_VARS: &[&str] = &["GITHUB_TOKEN", "GH_TOKEN"];
+pub(super) const KEYCHAIN_ID: &str = "github-token";
+// Overrides the API of the remote's host, like GitHub Actions sets it
+pub(super) const API_VAR: &str = "GITHUB_API_URL";
+
+#[derive(Deserialize)]
+struct User {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct RawIssue {
+    number: u64,
+    title: String,
+    #[serde(default)]
+    body: Option<String>,
+    user: User,
+    html_url: String,
+    // Set when the number is a pull request, which the issues API also answers for
+    #[serde(default)]
+    pull_request: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct RawComment {
+    user: User,
+    #[serde(default)]
+    body: Option<String>,
+    // Set on review comments, left on a line of the diff
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(default)]
+    line: Option<u32>,
+    #[serde(default)]
+    original_line: Option<u32>,
+}
+
+impl From<RawComment> for Comment {
+    fn from(raw: RawComment) -> Self {
+        Comment { author: raw.user.login, body: raw.body.unwrap_or_default(), path: raw.path, line: raw.line.or(raw.original_line) }
+    }
+}
+
+#[derive(Deserialize)]
+struct Branch {
+    #[serde(rename = "ref")]
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Pull {
+    number: u64,
+    title: String,
+    #[serde(default)]
+    body: Option<String>,
+    html_url: String,
+    head: Branch,
+}
+
+/// The REST API of a repository on GitHub or GitHub Enterprise Server.
+pub struct GitHub {
+    client: reqwest::Client,
+    api: String,
+    token: Option<String>,
+    repo: Repo,
+}
+
+impl GitHub {
+    pub fn new(repo: Repo) -> Self {
+        let api = match std::env::var(API_VAR) {
+            Ok(api) if !api.is_empty() => api.trim_end_matches('/').to_string(),
+            _ if repo.host == "github.com" => String::from("https://api.github.com"),
+            // GitHub Enterprise Server
+            _ => format!("https://{}/api/v3", repo.host),
+        };
+        GitHub { client: reqwest::Client::new(), api, token: super::token(TOKEN_VARS, KEYCHAIN_ID), repo }
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: reqwest::RequestBuilder) -> Result<T, anyhow::Error> {
+        let mut request = request
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", "pprog")
+            .header("X-GitHub-Api-Version", "2022-11-28");
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.json::<Value>().await.unwrap_or_default();
+        if !status.is_success() {
+            let message = body["message"].as_str().unwrap_or("no message");
+            let hint = match (status.as_u16(), &self.token) {
+                (401 | 403 | 404, None) => String::from(", set GITHUB_TOKEN or store a token with `pprog forge login`"),
+                _ => String::new(),
+            };
+            return Err(anyhow::anyhow!("GitHub answered {}: {}{}", status, message, hint));
+        }
+        Ok(serde_json::from_value(body)?)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, anyhow::Error> {
+        self.send(self.client.get(self.url(path))).await
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/repos/{}/{}{}", self.api, self.repo.owner, self.repo.name, path)
+    }
+}
+
+impl Forge for GitHub {
+    fn change_request_name(&self) -> &'static str {
+        "pull request"
+    }
+
+    fn token_var(&self) -> &'static str {
+        "GITHUB_TOKEN"
+    }
+
+    fn has_token(&self) -> bool {
+        self.token.is_some()
+    }
+
+    async fn issue(&self, number: u64) -> Result<(Issue, Vec<Comment>), anyhow::Error> {
+        let issue: RawIssue = self.get(&format!("/issues/{}", number)).await?;
+        if issue.pull_request.is_some() {
+            return Err(anyhow::anyhow!("#{} is a pull request, not an issue", number));
+        }
+        let comments: Vec<RawComment> = self.get(&format!("/issues/{}/comments?per_page=100", number)).await?;
+        let issue = Issue {
+            number: issue.number,
+            title: issue.title,
+            body: issue.body.unwrap_or_default(),
+            author: issue.user.login,
+            url: issue.html_url,
+        };
+        Ok((issue, comments.into_iter().map(Comment::from).collect()))
+    }
+
+    async fn change_request(&self, number: u64) -> Result<(ChangeRequest, Vec<Comment>), anyhow::Error> {
+        let pull: Pull = self.get(&format!("/pulls/{}", number)).await?;
+        // The conversation, then the comments left on lines of the diff
+        let mut comments: Vec<RawComment> = self.get(&format!("/issues/{}/comments?per_page=100", number)).await?;
+        comments.extend(self.get::<Vec<RawComment>>(&format!("/pulls/{}/comments?per_page=100", number)).await?);
+        let change = ChangeRequest {
+            number: pull.number,
+            title: pull.title,
+            body: pull.body.unwrap_or_default(),
+            url: pull.html_url,
+            branch: pull.head.name,
+        };
+        Ok((change, comments.into_iter().map(Comment::from).collect()))
+    }
+
+    async fn open_change_request(&self, title: &str, head: &str, base: &str, body: &str) -> Result<String, anyhow::Error> {
+        let request = json!({"title": title, "head": head, "base": base, "body": body, "draft": true});
+        let created: Value = self.send(self.client.post(self.url("/pulls")).json(&request)).await?;
+        Ok(created["html_url"].as_str().unwrap_or_default().to_string())
+    }
+}
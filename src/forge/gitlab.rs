@@ -0,0 +1,234 @@
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{ChangeRequest, Comment, Forge, Issue, Repo};
+
+const TOKEN_VARS: &[&str] = &["GITLAB_TOKEN"];
+pub(super) const KEYCHAIN_ID: &str = "gitlab-token";
+// Overrides the API of the remote's host, like GitLab CI sets it
+pub(super) const API_VAR: &str = "CI_API_V4_URL";
+
+#[derive(Deserialize)]
+struct Author {
+    username: String,
+}
+
+#[derive(Deserialize)]
+struct RawIssue {
+    iid: u64,
+    title: String,
+    #[serde(default)]
+    description: Option<String>,
+    author: Author,
+    web_url: String,
+}
+
+#[derive(Deserialize)]
+struct Position {
+    #[serde(default)]
+    new_path: Option<String>,
+    #[serde(default)]
+    new_line: Option<u32>,
+    #[serde(default)]
+    old_path: Option<String>,
+    #[serde(default)]
+    old_line: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct Note {
+    #[serde(default)]
+    body: String,
+    author: Author,
+    // Notes GitLab writes itself, like "added 1 commit"
+    #[serde(default)]
+    system: bool,
+    #[serde(default)]
+    resolved: bool,
+    // Set on notes left on a line of the diff
+    #[serde(default)]
+    position: Option<Position>,
+}
+
+impl From<Note> for Comment {
+    fn from(note: Note) -> Self {
+        let (path, line) = match note.position {
+            Some(position) => (position.new_path.or(position.old_path), position.new_line.or(position.old_line)),
+            None => (None, None),
+        };
+        Comment { author: note.author.username, body: note.body, path, line }
+    }
+}
+
+#[derive(Deserialize)]
+struct Discussion {
+    #[serde(default)]
+    notes: Vec<Note>,
+}
+
+#[derive(Deserialize)]
+struct MergeRequest {
+    iid: u64,
+    title: String,
+    #[serde(default)]
+    description: Option<String>,
+    web_url: String,
+    source_branch: String,
+}
+
+// The notes people wrote in threads that are still open, in order
+fn open_comments(discussions: Vec<Discussion>) -> Vec<Comment> {
+    discussions.into_iter()
+        .filter(|discussion| !discussion.notes.iter().any(|note| note.resolved))
+        .flat_map(|discussion| discussion.notes)
+        .filter(|note| !note.system)
+        .map(Comment::from)
+        .collect()
+}
+
+/// The REST API of a project on gitlab.com or a self-hosted GitLab.
+pub struct GitLab {
+    client: reqwest::Client,
+    api: String,
+    token: Option<String>,
+    // The project's path, URL-encoded as its id
+    project: String,
+}
+
+impl GitLab {
+    pub fn new(repo: Repo) -> Self {
+        let api = match std::env::var(API_VAR) {
+            Ok(api) if !api.is_empty() => api.trim_end_matches('/').to_string(),
+            _ => format!("https://{}/api/v4", repo.host),
+        };
+        let project = format!("{}/{}", repo.owner, repo.name).replace('/', "%2F");
+        GitLab { client: reqwest::Client::new(), api, token: super::token(TOKEN_VARS, KEYCHAIN_ID), project }
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: reqwest::RequestBuilder) -> Result<T, anyhow::Error> {
+        let mut request = request.header("User-Agent", "pprog");
+        if let Some(token) = &self.token {
+            request = request.header("PRIVATE-TOKEN", token);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.json::<Value>().await.unwrap_or_default();
+        if !status.is_success() {
+            // Validation errors come as an object of messages per field
+            let message = match (&body["message"], &body["error"]) {
+                (Value::String(message), _) | (_, Value::String(message)) => message.clone(),
+                (Value::Null, _) => String::from("no message"),
+                (message, _) => message.to_string(),
+            };
+            let hint = match (status.as_u16(), &self.token) {
+                (401 | 403 | 404, None) => String::from(", set GITLAB_TOKEN or store a token with `pprog forge login`"),
+                _ => String::new(),
+            };
+            return Err(anyhow::anyhow!("GitLab answered {}: {}{}", status, message, hint));
+        }
+        Ok(serde_json::from_value(body)?)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, anyhow::Error> {
+        self.send(self.client.get(self.url(path))).await
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/projects/{}{}", self.api, self.project, path)
+    }
+}
+
+impl Forge for GitLab {
+    fn change_request_name(&self) -> &'static str {
+        "merge request"
+    }
+
+    fn token_var(&self) -> &'static str {
+        "GITLAB_TOKEN"
+    }
+
+    fn has_token(&self) -> bool {
+        self.token.is_some()
+    }
+
+    async fn issue(&self, number: u64) -> Result<(Issue, Vec<Comment>), anyhow::Error> {
+        let issue: RawIssue = self.get(&format!("/issues/{}", number)).await?;
+        let notes: Vec<Note> = self.get(&format!("/issues/{}/notes?sort=asc&per_page=100", number)).await?;
+        let issue = Issue {
+            number: issue.iid,
+            title: issue.title,
+            body: issue.description.unwrap_or_default(),
+            author: issue.author.username,
+            url: issue.web_url,
+        };
+        Ok((issue, notes.into_iter().filter(|note| !note.system).map(Comment::from).collect()))
+    }
+
+    async fn change_request(&self, number: u64) -> Result<(ChangeRequest, Vec<Comment>), anyhow::Error> {
+        let merge: MergeRequest = self.get(&format!("/merge_requests/{}", number)).await?;
+        let discussions = self.get(&format!("/merge_requests/{}/discussions?per_page=100", number)).await?;
+        let change = ChangeRequest {
+            number: merge.iid,
+            title: merge.title,
+            body: merge.description.unwrap_or_default(),
+            url: merge.web_url,
+            branch: merge.source_branch,
+        };
+        Ok((change, open_comments(discussions)))
+    }
+
+    async fn open_change_request(&self, title: &str, head: &str, base: &str, body: &str) -> Result<String, anyhow::Error> {
+        // The title prefix marks a draft on every GitLab version
+        let request = json!({
+            "source_branch": head,
+            "target_branch": base,
+            "title": format!("Draft: {}", title),
+            "description": body,
+        });
+        let created: Value = self.send(self.client.post(self.url("/merge_requests")).json(&request)).await?;
+        Ok(created["web_url"].as_str().unwrap_or_default().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_discussion() {
+        let discussions: Vec<Discussion> = serde_json::from_value(json!([
+            {"notes": [
+                {"body": "added 1 commit", "author": {"username": "ana"}, "system": true},
+                {"body": "Looks good", "author": {"username": "ben"}},
+            ]},
+            {"notes": [
+                {"body": "This can panic", "author": {"username": "cy"}, "resolved": false,
+                 "position": {"new_path": "src/config.rs", "new_line": 12, "old_path": "src/config.rs", "old_line": 10}},
+                {"body": "Agreed", "author": {"username": "ana"}, "resolved": false},
+            ]},
+            {"notes": [
+                {"body": "Typo", "author": {"username": "cy"}, "resolved": true,
+                 "position": {"new_path": "README.md", "new_line": 3}},
+            ]},
+            {"notes": [
+                {"body": "Dropped line", "author": {"username": "cy"},
+                 "position": {"new_path": "src/lib.rs", "new_line": null, "old_path": "src/lib.rs", "old_line": 40}},
+            ]},
+        ])).unwrap();
+        let comments = open_comments(discussions);
+        let summary = comments.iter()
+            .map(|comment| (comment.author.as_str(), comment.path.as_deref(), comment.line))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, vec![
+            ("ben", None, None),
+            ("cy", Some("src/config.rs"), Some(12)),
+            ("ana", None, None),
+            ("cy", Some("src/lib.rs"), Some(40)),
+        ]);
+
+        let repo = Repo { host: "gitlab.example.com".to_string(), owner: "tools/ai".to_string(), name: "pprog".to_string() };
+        let gitlab = GitLab::new(repo);
+        assert!(gitlab.url("/issues/3").ends_with("/projects/tools%2Fai%2Fpprog/issues/3"));
+    }
+}
@@ -0,0 +1,371 @@
+mod github;
+mod gitlab;
+
+pub use github::GitHub;
+pub use gitlab::GitLab;
+
+use std::process::Command;
+
+use crate::chat::Chat;
+use crate::config::ProjectConfig;
+use crate::crypto;
+use crate::inference::types::{ContentItem, Message, Role};
+use crate::oneshot;
+
+// The most of the diff sent along to write the description of a pull request
+const MAX_DIFF: usize = 60_000;
+
+const DESCRIBE_PROMPT: &str = "You write the description of a pull request from the issue it resolves and its diff.  \
+Start with one or two sentences on what changes and why, then list the notable changes as short bullets.  \
+Reply with the description in markdown only, no title and no preamble.";
+
+/// A repository, as named by its remote.
+#[derive(Debug, PartialEq)]
+pub struct Repo {
+    pub host: String,
+    /// The user or group, with any subgroups
+    pub owner: String,
+    pub name: String,
+}
+
+/// The repository of a remote URL, over HTTPS or SSH.
+pub fn parse_remote(url: &str) -> Option<Repo> {
+    let url = url.trim();
+    let rest = match url.split_once("://") {
+        Some((_, rest)) => rest.split_once('@').map_or(rest, |(_, host)| host).to_string(),
+        // scp-like, `git@github.com:owner/name.git`
+        None => url.split_once('@').map_or(url, |(_, host)| host).replacen(':', "/", 1),
+    };
+    let (host, path) = rest.trim_end_matches('/').split_once('/')?;
+    let (owner, name) = path.rsplit_once('/')?;
+    let host = host.split(':').next()?.to_string();
+    let name = name.strip_suffix(".git").unwrap_or(name);
+    (!host.is_empty() && !owner.is_empty() && !name.is_empty())
+        .then(|| Repo { host, owner: owner.to_string(), name: name.to_string() })
+}
+
+/// An issue, however its forge numbers and words it.
+#[derive(Debug)]
+pub struct Issue {
+    pub number: u64,
+    pub title: String,
+    pub body: String,
+    pub author: String,
+    pub url: String,
+}
+
+/// A pull request on GitHub, a merge request on GitLab.
+#[derive(Debug)]
+pub struct ChangeRequest {
+    pub number: u64,
+    pub title: String,
+    pub body: String,
+    pub url: String,
+    /// The branch it merges
+    pub branch: String,
+}
+
+/// A comment in a discussion, with the line it is on when it was left inline on the diff.
+#[derive(Debug)]
+pub struct Comment {
+    pub author: String,
+    pub body: String,
+    pub path: Option<String>,
+    pub line: Option<u32>,
+}
+
+/// Where issues and code review live for a repository.
+// Only called on the concrete forges, so no Send bound is needed on the futures
+#[allow(async_fn_in_trait)]
+pub trait Forge {
+    /// What it calls a change request, like `pull request`
+    fn change_request_name(&self) -> &'static str;
+
+    /// The variable the token is taken from, before the OS keychain
+    fn token_var(&self) -> &'static str;
+
+    fn has_token(&self) -> bool;
+
+    /// The issue with its comments, oldest first.
+    async fn issue(&self, number: u64) -> Result<(Issue, Vec<Comment>), anyhow::Error>;
+
+    /// The change request with its discussion, inline comments included, without the threads
+    /// resolved where the forge tells.
+    async fn change_request(&self, number: u64) -> Result<(ChangeRequest, Vec<Comment>), anyhow::Error>;
+
+    /// Opens a draft change request of `head` into `base`, returning its URL.
+    async fn open_change_request(&self, title: &str, head: &str, base: &str, body: &str) -> Result<String, anyhow::Error>;
+}
+
+pub enum ForgeProvider {
+    GitHub(GitHub),
+    GitLab(GitLab),
+}
+
+impl ForgeProvider {
+    /// The forge `origin` is on, told by its host unless `forge` in pprog.toml says.
+    pub fn for_origin(config: &ProjectConfig) -> Result<Self, anyhow::Error> {
+        let url = git(&["remote", "get-url", "origin"])?;
+        let repo = parse_remote(&url).ok_or_else(|| anyhow::anyhow!("Can't tell the repository of origin {}", url.trim()))?;
+        let forge = match config.forge.as_deref() {
+            Some(forge) => forge.to_string(),
+            None if repo.host == "github.com" || std::env::var_os(github::API_VAR).is_some() => String::from("github"),
+            None if repo.host.contains("gitlab") || std::env::var_os(gitlab::API_VAR).is_some() => String::from("gitlab"),
+            None => anyhow::bail!("Can't tell which forge {} runs, set forge = \"github\" or \"gitlab\" in pprog.toml", repo.host),
+        };
+        match forge.as_str() {
+            "github" => Ok(ForgeProvider::GitHub(GitHub::new(repo))),
+            "gitlab" => Ok(ForgeProvider::GitLab(GitLab::new(repo))),
+            forge => Err(anyhow::anyhow!("Unknown forge {:?}, use github or gitlab", forge)),
+        }
+    }
+
+    /// Keeps `token` in the OS keychain for later runs against this forge.
+    pub fn login(&self, token: &str) -> Result<(), anyhow::Error> {
+        let (key_id, label) = match self {
+            ForgeProvider::GitHub(_) => (github::KEYCHAIN_ID, "pprog GitHub token"),
+            ForgeProvider::GitLab(_) => (gitlab::KEYCHAIN_ID, "pprog GitLab token"),
+        };
+        crypto::keychain_store(key_id, label, token.trim())
+    }
+}
+
+impl Forge for ForgeProvider {
+    fn change_request_name(&self) -> &'static str {
+        match self {
+            ForgeProvider::GitHub(forge) => forge.change_request_name(),
+            ForgeProvider::GitLab(forge) => forge.change_request_name(),
+        }
+    }
+
+    fn token_var(&self) -> &'static str {
+        match self {
+            ForgeProvider::GitHub(forge) => forge.token_var(),
+            ForgeProvider::GitLab(forge) => forge.token_var(),
+        }
+    }
+
+    fn has_token(&self) -> bool {
+        match self {
+            ForgeProvider::GitHub(forge) => forge.has_token(),
+            ForgeProvider::GitLab(forge) => forge.has_token(),
+        }
+    }
+
+    async fn issue(&self, number: u64) -> Result<(Issue, Vec<Comment>), anyhow::Error> {
+        match self {
+            ForgeProvider::GitHub(forge) => forge.issue(number).await,
+            ForgeProvider::GitLab(forge) => forge.issue(number).await,
+        }
+    }
+
+    async fn change_request(&self, number: u64) -> Result<(ChangeRequest, Vec<Comment>), anyhow::Error> {
+        match self {
+            ForgeProvider::GitHub(forge) => forge.change_request(number).await,
+            ForgeProvider::GitLab(forge) => forge.change_request(number).await,
+        }
+    }
+
+    async fn open_change_request(&self, title: &str, head: &str, base: &str, body: &str) -> Result<String, anyhow::Error> {
+        match self {
+            ForgeProvider::GitHub(forge) => forge.open_change_request(title, head, base, body).await,
+            ForgeProvider::GitLab(forge) => forge.open_change_request(title, head, base, body).await,
+        }
+    }
+}
+
+// The token from the first of `vars` that is set, or else the OS keychain
+fn token(vars: &[&str], key_id: &str) -> Option<String> {
+    vars.iter()
+        .find_map(|var| std::env::var(var).ok().filter(|token| !token.trim().is_empty()))
+        .or_else(|| crypto::keychain_lookup(key_id).ok().flatten())
+        .map(|token| token.trim().to_string())
+}
+
+fn git(args: &[&str]) -> Result<String, anyhow::Error> {
+    let output = Command::new("git").args(args).output()?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn require_clean_tree(what: &str) -> Result<(), anyhow::Error> {
+    match git(&["status", "--porcelain"])?.trim().is_empty() {
+        true => Ok(()),
+        false => Err(anyhow::anyhow!("The working tree has changes, commit or stash them so the {} has only the agent's", what)),
+    }
+}
+
+// Commits everything the agent changed, false when it changed nothing
+fn commit_all(message: &str) -> Result<bool, anyhow::Error> {
+    git(&["add", "-A"])?;
+    if git(&["diff", "--cached", "--quiet"]).is_ok() {
+        return Ok(false);
+    }
+    git(&["commit", "-m", message])?;
+    Ok(true)
+}
+
+fn push_comments(prompt: &mut String, comments: &[Comment]) {
+    if comments.is_empty() {
+        return;
+    }
+    prompt.push_str("\n## Comments\n");
+    for comment in comments {
+        let place = match (&comment.path, comment.line) {
+            (Some(path), Some(line)) => format!(" on {}:{}", path, line),
+            (Some(path), None) => format!(" on {}", path),
+            _ => String::new(),
+        };
+        prompt.push_str(&format!("\n@{}{}:\n{}\n", comment.author, place, comment.body.trim()));
+    }
+}
+
+/// The issue as the task for the agent, with the discussion under it.
+pub fn task_prompt(issue: &Issue, comments: &[Comment]) -> String {
+    let body = match issue.body.trim() {
+        "" => "(no description)",
+        body => body,
+    };
+    let mut prompt = format!(
+        "Resolve issue #{} ({}), opened by @{}.\n\n# {}\n\n{}\n",
+        issue.number, issue.url, issue.author, issue.title, body,
+    );
+    push_comments(&mut prompt, comments);
+    prompt
+}
+
+/// The review discussion of a change request as the task for the agent.
+pub fn review_prompt(name: &str, change: &ChangeRequest, comments: &[Comment]) -> String {
+    let mut prompt = format!(
+        "Address the review comments on {} #{} ({}), checked out on its branch {}.\n\n# {}\n\n{}\n",
+        name, change.number, change.url, change.branch, change.title, change.body.trim(),
+    );
+    push_comments(&mut prompt, comments);
+    prompt
+}
+
+async fn describe(chat: &Chat, issue: &Issue, diff: &str) -> Result<String, anyhow::Error> {
+    let diff = match diff.char_indices().nth(MAX_DIFF) {
+        Some((end, _)) => format!("{}\n[diff cut off]", &diff[..end]),
+        None => diff.to_string(),
+    };
+    let text = format!("Issue #{}: {}\n\n{}\n\nDiff:\n{}", issue.number, issue.title, issue.body, diff);
+    let message = Message { role: Role::User, content: vec![ContentItem::Text { text }] };
+    let response = chat.query(DESCRIBE_PROMPT, vec![message]).await?;
+    let description = response.content.iter()
+        .filter_map(|item| match item {
+            ContentItem::Text { text } => Some(text.as_str()),
+            _ => None,
+        })
+        .collect::<String>();
+    Ok(format!("{}\n\nCloses #{}", description.trim(), issue.number))
+}
+
+/*
+    * `pprog forge issue <n>`: runs the agent on the issue like `pprog -p` would, and with
+    * `open` does it on a `pprog/issue-<n>` branch of its own, which is then committed,
+    * pushed to origin and opened as a draft change request into the branch it was started
+    * from, with a description written from the issue and the diff.  Pushing goes through
+    * git and its own credentials, the API calls through the forge's token.
+*/
+pub async fn work_on_issue(chat: Chat, number: u64, options: oneshot::Options, open: bool) -> Result<(), anyhow::Error> {
+    let forge = ForgeProvider::for_origin(chat.config())?;
+    let name = forge.change_request_name();
+    if open && !forge.has_token() {
+        anyhow::bail!("Opening a {} needs a token, set {} or store one with `pprog forge login`", name, forge.token_var());
+    }
+    let (issue, comments) = forge.issue(number).await?;
+    let branches = match open {
+        true => {
+            require_clean_tree(name)?;
+            let base = git(&["rev-parse", "--abbrev-ref", "HEAD"])?.trim().to_string();
+            let branch = format!("pprog/issue-{}", number);
+            git(&["switch", "-c", &branch])?;
+            Some((base, branch))
+        },
+        false => None,
+    };
+    let config = chat.config().clone();
+    oneshot::run(chat, &task_prompt(&issue, &comments), None, options).await?;
+    let Some((base, branch)) = branches else { return Ok(()) };
+
+    let title = format!("{} (#{})", issue.title, number);
+    if !commit_all(&title)? {
+        anyhow::bail!("Nothing changed on {}, so no {} was opened", branch, name);
+    }
+    let diff = git(&["diff", &format!("{}...HEAD", base)])?;
+    let body = describe(&Chat::from_config(config).await?, &issue, &diff).await?;
+    git(&["push", "-u", "origin", &branch])?;
+    let url = forge.open_change_request(&title, &branch, &base, &body).await?;
+    println!("Opened draft {} {}", name, url);
+    Ok(())
+}
+
+/// `pprog forge address <n>`: runs the agent on the open review comments of a change
+/// request, on its branch, and pushes what it changed there.
+pub async fn address_review(chat: Chat, number: u64, options: oneshot::Options) -> Result<(), anyhow::Error> {
+    let forge = ForgeProvider::for_origin(chat.config())?;
+    let name = forge.change_request_name();
+    let (change, comments) = forge.change_request(number).await?;
+    if comments.is_empty() {
+        anyhow::bail!("{} #{} has no open discussion to address", name, number);
+    }
+    require_clean_tree(name)?;
+    git(&["fetch", "origin", &change.branch])?;
+    git(&["switch", &change.branch])?;
+    git(&["merge", "--ff-only", "FETCH_HEAD"])?;
+    oneshot::run(chat, &review_prompt(name, &change, &comments), None, options).await?;
+
+    if !commit_all(&format!("Address review comments on #{}", number))? {
+        anyhow::bail!("Nothing changed, so nothing was pushed to {}", change.branch);
+    }
+    git(&["push", "origin", &change.branch])?;
+    println!("Pushed the changes to {} of {} #{}", change.branch, name, number);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remotes_and_prompts() {
+        let repo = |host: &str, owner: &str| Some(Repo { host: host.to_string(), owner: owner.to_string(), name: "pprog".to_string() });
+        assert_eq!(parse_remote("https://github.com/foomprep/pprog.git\n"), repo("github.com", "foomprep"));
+        assert_eq!(parse_remote("git@github.com:foomprep/pprog.git"), repo("github.com", "foomprep"));
+        assert_eq!(parse_remote("ssh://git@gitlab.example.com:2222/tools/ai/pprog"), repo("gitlab.example.com", "tools/ai"));
+        assert_eq!(parse_remote("https://token@github.com/foomprep/pprog/"), repo("github.com", "foomprep"));
+        assert_eq!(parse_remote("/srv/git/pprog.git"), None);
+        assert_eq!(parse_remote("https://github.com/pprog"), None);
+
+        let issue = Issue {
+            number: 7,
+            title: "Crash on empty config".to_string(),
+            body: "It panics.".to_string(),
+            author: "ana".to_string(),
+            url: "https://github.com/foomprep/pprog/issues/7".to_string(),
+        };
+        let comments = vec![
+            Comment { author: "ben".to_string(), body: "Same here with 0.0.3".to_string(), path: None, line: None },
+            Comment { author: "cy".to_string(), body: "Unwrap here".to_string(), path: Some("src/config.rs".to_string()), line: Some(12) },
+        ];
+        let prompt = task_prompt(&issue, &comments);
+        assert!(prompt.starts_with("Resolve issue #7 (https://github.com/foomprep/pprog/issues/7), opened by @ana."));
+        assert!(prompt.contains("# Crash on empty config\n\nIt panics.\n"));
+        assert!(prompt.contains("\n@ben:\nSame here with 0.0.3\n"));
+        assert!(prompt.ends_with("\n@cy on src/config.rs:12:\nUnwrap here\n"));
+
+        let change = ChangeRequest {
+            number: 9,
+            title: "Load empty configs".to_string(),
+            body: String::new(),
+            url: "https://gitlab.com/foomprep/pprog/-/merge_requests/9".to_string(),
+            branch: "fix-empty".to_string(),
+        };
+        let prompt = review_prompt("merge request", &change, &comments[1..]);
+        assert!(prompt.starts_with("Address the review comments on merge request #9 (https://gitlab.com/foomprep/pprog/-/merge_requests/9), checked out on its branch fix-empty."));
+        assert!(prompt.ends_with("## Comments\n\n@cy on src/config.rs:12:\nUnwrap here\n"));
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use similar::TextDiff;
+
+use crate::agent::{self, AgentEvent, Controls};
+use crate::chat::Chat;
+use crate::inference::types::{ContentItem, Message, Role};
+use crate::tree::GitTree;
+
+// A module beyond this is cut from the prompt, the agent can still read the rest
+const MAX_SOURCE_CHARS: usize = 60_000;
+// The end of a failing run sent back, where the failures and the summary are
+const MAX_OUTPUT_CHARS: usize = 8_000;
+
+/// The module to test, where its tests go by the conventions of its language, and the
+/// command that runs them from the root of the repository.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub source: String,
+    /// None for a language with no convention pprog knows, left to the agent
+    pub tests: Option<String>,
+    pub command: String,
+}
+
+// The directory of `path`, "." for the root
+fn parent(path: &Path) -> String {
+    match path.parent().map(|dir| dir.to_string_lossy().to_string()).filter(|dir| !dir.is_empty()) {
+        Some(dir) => dir,
+        None => String::from("."),
+    }
+}
+
+// The manifest of the crate `path` belongs to, relative to `root`
+fn cargo_manifest(root: &Path, path: &Path) -> Option<PathBuf> {
+    path.ancestors().skip(1).map(|dir| dir.join("Cargo.toml")).find(|manifest| root.join(manifest).is_file())
+}
+
+impl Target {
+    /// The target for `source`, relative to `root`, running `command` when given.
+    pub fn new(root: &Path, source: &str, command: Option<&str>) -> Result<Self, anyhow::Error> {
+        let path = Path::new(source);
+        let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+        let extension = path.extension().map(|ext| ext.to_string_lossy().to_string()).unwrap_or_default();
+        let dir = parent(path);
+        let (tests, default_command) = match extension.as_str() {
+            "rs" => {
+                let command = match cargo_manifest(root, path) {
+                    Some(manifest) if manifest.parent() != Some(Path::new("")) => format!("cargo test --manifest-path {}", manifest.display()),
+                    _ => String::from("cargo test"),
+                };
+                (Some(source.to_string()), Some(command))
+            },
+            "py" => {
+                let tests = format!("tests/test_{}.py", stem);
+                (Some(tests.clone()), Some(format!("python -m pytest {}", tests)))
+            },
+            "go" => {
+                let package = if dir == "." { String::from("./") } else { format!("./{}", dir) };
+                (Some(Path::new(&dir).join(format!("{}_test.go", stem)).to_string_lossy().trim_start_matches("./").to_string()), Some(format!("go test {}", package)))
+            },
+            "js" | "jsx" | "ts" | "tsx" | "mjs" => {
+                let tests = Path::new(&dir).join(format!("{}.test.{}", stem, extension)).to_string_lossy().trim_start_matches("./").to_string();
+                (Some(tests.clone()), Some(format!("npm test -- {}", tests)))
+            },
+            _ => (None, None),
+        };
+        let command = match command.map(str::to_string).or(default_command) {
+            Some(command) => command,
+            None => anyhow::bail!("No test convention is known for {}, give the command that runs its tests with --cmd", source),
+        };
+        Ok(Target { source: source.to_string(), tests, command })
+    }
+
+    fn placement(&self) -> String {
+        match self.tests.as_deref() {
+            Some(tests) if tests == self.source => format!("in a `#[cfg(test)] mod tests` at the end of {}", self.source),
+            Some(tests) => format!("in {}", tests),
+            None => String::from("where this project keeps its tests"),
+        }
+    }
+}
+
+fn task_prompt(target: &Target, source: &str) -> String {
+    let mut cut = source.len().min(MAX_SOURCE_CHARS);
+    while !source.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    let mut text = format!(
+        "Write unit tests for {}, {}.  Cover its public behavior, edge cases and error paths, \
+        in the style and with the test helpers the project already uses.  \
+        Run them with `{}` using the execute tool and fix them until they compile and pass.  \
+        Only change the tests, and if one fails because of a bug in the module, leave that test out \
+        and say what the bug is in your answer.\n\n{}:\n```\n{}\n```\n",
+        target.source, target.placement(), target.command, target.source, &source[..cut],
+    );
+    if cut < source.len() {
+        text.push_str(&format!("[cut off after {} of {} bytes, read the file for the rest]\n", cut, source.len()));
+    }
+    text
+}
+
+fn fix_prompt(command: &str, output: &str) -> String {
+    let mut start = output.len().saturating_sub(MAX_OUTPUT_CHARS);
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+    format!("`{}` still fails.  Fix the tests so they compile and pass:\n```\n{}\n```\n", command, output[start..].trim())
+}
+
+/// Runs `command` in `root`, whether it passed and its output.
+fn run_tests(root: &Path, command: &str) -> Result<(bool, String), anyhow::Error> {
+    let output = Command::new("bash").arg("-c").arg(command).current_dir(root).output()
+        .map_err(|e| anyhow::anyhow!("Could not run {}: {}", command, e))?;
+    let text = format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+    Ok((output.status.success(), text))
+}
+
+/// A unified diff of `files`, from what they held before to what is on disk, for `git apply`.
+fn patch(root: &Path, files: &BTreeMap<String, Option<String>>) -> String {
+    files.iter()
+        .filter_map(|(path, before)| {
+            let after = fs::read_to_string(root.join(path)).ok();
+            if after == *before {
+                return None;
+            }
+            let old_header = if before.is_some() { format!("a/{}", path) } else { String::from("/dev/null") };
+            let new_header = if after.is_some() { format!("b/{}", path) } else { String::from("/dev/null") };
+            let (before, after) = (before.clone().unwrap_or_default(), after.unwrap_or_default());
+            Some(TextDiff::from_lines(&before, &after).unified_diff().header(&old_header, &new_header).to_string())
+        })
+        .collect()
+}
+
+/// How a run went: the tests passed or it gave up, after so many rounds.
+pub struct Generated {
+    pub passed: bool,
+    pub attempts: usize,
+    /// The changes to the files the agent wrote
+    pub patch: String,
+    /// What the agent said at the end
+    pub answer: String,
+}
+
+/*
+    * `pprog gen-tests <path>`: the agent writes tests for the target, then pprog runs its
+    * command itself and sends a failing run back, up to `max_attempts` rounds in all.
+    * `on_attempt` is told as each round starts.  The changes stay in the working tree and
+    * come back as a patch too.
+*/
+pub async fn generate(
+    chat: &mut Chat,
+    target: &Target,
+    max_attempts: usize,
+    mut on_attempt: impl FnMut(usize),
+) -> Result<Generated, anyhow::Error> {
+    let root = GitTree::get_git_root()?;
+    let source = fs::read_to_string(root.join(&target.source)).map_err(|e| anyhow::anyhow!("Failed to read {}: {}", target.source, e))?;
+    let (_cancel_source, mut cancel) = agent::cancel_pair();
+    let mut written: BTreeMap<String, Option<String>> = BTreeMap::new();
+    let mut message = Message { role: Role::User, content: vec![ContentItem::Text { text: task_prompt(target, &source) }] };
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        on_attempt(attempts);
+        let mut record = |event: AgentEvent| {
+            if let AgentEvent::ToolStart { name: "write_file", input, .. } = event {
+                if let Some(path) = input["path"].as_str() {
+                    written.entry(path.to_string()).or_insert_with(|| fs::read_to_string(root.join(path)).ok());
+                }
+            }
+        };
+        let outcome = agent::run_turn(chat, message, &mut record, &mut Controls::default(), &mut cancel).await?;
+        let (passed, output) = run_tests(&root, &target.command)?;
+        if passed || attempts >= max_attempts.max(1) {
+            let answer = outcome.reply.content.iter()
+                .filter_map(|item| match item {
+                    ContentItem::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<&str>>()
+                .join("\n");
+            return Ok(Generated { passed, attempts, patch: patch(&root, &written), answer });
+        }
+        message = Message { role: Role::User, content: vec![ContentItem::Text { text: fix_prompt(&target.command, &output) }] };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conventions() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::create_dir_all(root.join("core/src"))?;
+        fs::write(root.join("Cargo.toml"), "")?;
+        fs::write(root.join("core/Cargo.toml"), "")?;
+
+        let target = |source: &str| Target::new(root, source, None);
+        assert_eq!(target("src/tree.rs")?, Target { source: "src/tree.rs".to_string(), tests: Some("src/tree.rs".to_string()), command: "cargo test".to_string() });
+        assert_eq!(target("core/src/chat.rs")?.command, "cargo test --manifest-path core/Cargo.toml");
+        assert_eq!(target("app/util.py")?.tests.as_deref(), Some("tests/test_util.py"));
+        assert_eq!(target("pkg/store/db.go")?.command, "go test ./pkg/store");
+        assert_eq!(target("main.go")?.tests.as_deref(), Some("main_test.go"));
+        assert_eq!(target("web/src/api.ts")?.tests.as_deref(), Some("web/src/api.test.ts"));
+        assert!(target("lib/parse.ml").is_err());
+        let custom = Target::new(root, "lib/parse.ml", Some("dune test"))?;
+        assert_eq!((custom.tests.as_deref(), custom.placement()), (None, "where this project keeps its tests".to_string()));
+
+        fs::write(root.join("core/src/chat.rs"), "fn a() {}\n#[cfg(test)]\nmod tests {}\n")?;
+        let files = BTreeMap::from([
+            ("core/src/chat.rs".to_string(), Some("fn a() {}\n".to_string())),
+            ("tests/test_util.py".to_string(), None),
+        ]);
+        assert_eq!(patch(root, &files), "--- a/core/src/chat.rs\n+++ b/core/src/chat.rs\n@@ -1 +1,3 @@\n fn a() {}\n+#[cfg(test)]\n+mod tests {}\n");
+
+        let output = format!("{}test a ... FAILED\n", "compiling\n".repeat(2000));
+        let prompt = fix_prompt("cargo test", &output);
+        assert!(prompt.len() < MAX_OUTPUT_CHARS + 100 && prompt.ends_with("test a ... FAILED\n```\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::chat::Chat;
+use crate::config::ProjectConfig;
+use crate::inference::types::{ContentItem, Message, Role};
+use crate::models;
+use crate::review;
+use crate::structured::{self, JsonSchema, Value};
+use crate::tokens::TokenCounter;
+
+const HOOK_PROMPT: &str = "You check a staged git diff against the project's policies before it is committed.  \
+Report only the lines the diff adds that clearly break a policy, with the file, the line of the new file as \
+numbered by the hunk headers, the policy broken and a short reason.  \
+Report nothing for code that merely could be better, and no violations at all when every policy is kept.";
+
+// Replies are a short list at most, and a small cap keeps the hook fast
+const MAX_OUTPUT_TOKENS: u32 = 1024;
+// Parts of a diff beyond this many tokens are reviewed in requests of their own
+const CHUNK_TOKENS: usize = 30_000;
+
+/// An added line that breaks a policy.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Violation {
+    pub path: String,
+    #[serde(default)]
+    pub line: Option<u32>,
+    pub policy: String,
+    pub message: String,
+}
+
+impl JsonSchema for Violation {
+    fn json_schema() -> Value {
+        structured::object(&[
+            ("path", structured::described(String::json_schema(), "The file as named in the diff")),
+            ("line", structured::described(Option::<u32>::json_schema(), "Line of the new file, or null")),
+            ("policy", structured::described(String::json_schema(), "The policy broken, as written")),
+            ("message", structured::described(String::json_schema(), "What breaks it, in a sentence")),
+        ])
+    }
+}
+
+// What the model replies with for a part of the diff
+#[derive(Debug, Deserialize)]
+struct HookReview {
+    violations: Vec<Violation>,
+}
+
+impl JsonSchema for HookReview {
+    fn json_schema() -> Value {
+        structured::object(&[("violations", structured::described(Vec::<Violation>::json_schema(), "Empty when every policy is kept"))])
+    }
+
+    fn schema_name() -> String {
+        String::from("policy_check")
+    }
+}
+
+/// How the check of staged changes went.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Failed(Vec<Violation>),
+    /// Not checked, or not all of it, and why
+    Skipped(String),
+}
+
+// The config the check runs with, the cheap model and a single try per request
+fn hook_config(config: &ProjectConfig) -> ProjectConfig {
+    let mut hook_config = config.with_small_model();
+    if !config.hook.model.is_empty() {
+        hook_config.model = config.hook.model.clone();
+    }
+    hook_config.structured_attempts = 1;
+    hook_config.max_output_tokens = hook_config.max_output_tokens.min(MAX_OUTPUT_TOKENS);
+    hook_config
+}
+
+fn request(policies: &[String], chunk: &str) -> String {
+    let policies = policies.iter().map(|policy| format!("- {}\n", policy.trim())).collect::<String>();
+    format!("Policies:\n{}\nStaged changes:\n```diff\n{}\n```\n", policies, chunk.trim_end())
+}
+
+/*
+    * The `requests` that fit in `max_cost` together, counting each at its input
+    * and a reply of `max_output` tokens, the most it can cost.  A model with no known price
+    * is taken to be a local one that costs nothing.
+*/
+fn within_cost(requests: Vec<String>, model: &str, max_output: u32, max_cost: f64) -> Vec<String> {
+    let Some(info) = models::lookup(model) else {
+        return requests;
+    };
+    let counter = TokenCounter::for_model(model);
+    let mut spent = 0.0;
+    requests.into_iter()
+        .take_while(|text| {
+            let input = counter.count(HOOK_PROMPT) + counter.count(text);
+            spent += (input as f64 * info.input_price + max_output as f64 * info.output_price) / 1_000_000.0;
+            spent <= max_cost
+        })
+        .collect()
+}
+
+/// Checks the staged changes against the policies of `config.hook`, within its time and cost caps.
+pub async fn pre_commit(config: &ProjectConfig) -> Result<Outcome, anyhow::Error> {
+    let diff = review::git_diff(&["--cached"])?;
+    if diff.trim().is_empty() || config.hook.policies.is_empty() {
+        return Ok(Outcome::Passed);
+    }
+    let config = hook_config(config);
+    let counter = TokenCounter::for_model(&config.model);
+    let requests = review::chunks(&diff, CHUNK_TOKENS.min(config.context_window() / 2), &counter).iter()
+        .map(|chunk| request(&config.hook.policies, chunk))
+        .collect::<Vec<String>>();
+    let parts = requests.len();
+    let requests = within_cost(requests, &config.model, config.max_output_tokens, config.hook.max_cost);
+    if requests.is_empty() {
+        return Ok(Outcome::Skipped(format!("checking the changes could cost more than the ${:.2} cap", config.hook.max_cost)));
+    }
+    let checked = requests.len();
+
+    let chat = Chat::from_config(config.clone()).await?;
+    let replies = futures::future::join_all(requests.into_iter().map(|text| {
+        let message = Message { role: Role::User, content: vec![ContentItem::Text { text }] };
+        chat.query_structured::<HookReview>(None, HOOK_PROMPT, vec![message])
+    }));
+    let replies = match tokio::time::timeout(Duration::from_secs(config.hook.timeout_secs), replies).await {
+        Ok(replies) => replies,
+        Err(_) => return Ok(Outcome::Skipped(format!("the check took longer than {}s", config.hook.timeout_secs))),
+    };
+    let mut violations = Vec::new();
+    for reply in replies {
+        match reply {
+            Ok(reply) => violations.extend(reply.violations),
+            Err(e) => return Ok(Outcome::Skipped(format!("the check failed: {:#}", e))),
+        }
+    }
+    Ok(match (violations.is_empty(), checked < parts) {
+        (false, _) => Outcome::Failed(violations),
+        (true, true) => Outcome::Skipped(format!(
+            "only {} of {} parts of the changes fit the ${:.2} cap, and they keep to the policies",
+            checked, parts, config.hook.max_cost,
+        )),
+        (true, false) => Outcome::Passed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_caps() {
+        let mut config = ProjectConfig { small_model: "claude-3-haiku".to_string(), max_output_tokens: 8096, ..Default::default() };
+        let hook = hook_config(&config);
+        assert_eq!((hook.model.as_str(), hook.max_output_tokens, hook.structured_attempts), ("claude-3-haiku", 1024, 1));
+        config.hook.model = "gpt-4o-mini".to_string();
+        assert_eq!(hook_config(&config).model, "gpt-4o-mini");
+
+        let text = request(&config.hook.policies, "+let key = \"sk-123\";\n");
+        assert!(text.starts_with("Policies:\n- No secrets"));
+        assert!(text.ends_with("```diff\n+let key = \"sk-123\";\n```\n"));
+
+        // About 1100 tokens in and 1024 out make each request up to $0.005 on claude-3-5-haiku
+        let requests = vec!["x".repeat(3500); 3];
+        assert_eq!(within_cost(requests.clone(), "claude-3-5-haiku", 1024, 0.05).len(), 3);
+        assert_eq!(within_cost(requests.clone(), "claude-3-5-haiku", 1024, 0.008).len(), 1);
+        assert!(within_cost(requests.clone(), "claude-3-5-haiku", 1024, 0.001).is_empty());
+        assert_eq!(within_cost(requests, "llama3", 1024, 0.0).len(), 3);
+    }
+}
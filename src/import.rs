@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::inference::types::{ContentItem, Message, Role};
+use crate::session::Session;
+
+// Claude.ai data export, `conversations.json`
+#[derive(Deserialize)]
+struct ClaudeConversation {
+    uuid: String,
+    #[serde(default)]
+    name: Option<String>,
+    created_at: DateTime<Utc>,
+    chat_messages: Vec<ClaudeMessage>,
+}
+
+#[derive(Deserialize)]
+struct ClaudeMessage {
+    sender: String,
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    content: Vec<ClaudeContent>,
+}
+
+#[derive(Deserialize)]
+struct ClaudeContent {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: Option<String>,
+}
+
+// ChatGPT data export, `conversations.json`.  Messages form a tree because of edits and
+// regenerations, `current_node` is the leaf of the branch that was shown last.
+#[derive(Deserialize)]
+struct ChatGptConversation {
+    #[serde(default, alias = "conversation_id")]
+    id: Option<String>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    create_time: Option<f64>,
+    mapping: HashMap<String, ChatGptNode>,
+    #[serde(default)]
+    current_node: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatGptNode {
+    #[serde(default)]
+    message: Option<ChatGptMessage>,
+    #[serde(default)]
+    parent: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatGptMessage {
+    author: ChatGptAuthor,
+    #[serde(default)]
+    content: Option<ChatGptContent>,
+}
+
+#[derive(Deserialize)]
+struct ChatGptAuthor {
+    role: String,
+}
+
+#[derive(Deserialize)]
+struct ChatGptContent {
+    #[serde(default)]
+    parts: Vec<Value>,
+}
+
+/// Reads a Claude or ChatGPT export, either a whole `conversations.json` or a single
+/// conversation, and converts every conversation into a session.
+pub fn import_file(path: &Path) -> Result<Vec<Session>, anyhow::Error> {
+    parse(&std::fs::read_to_string(path)?)
+}
+
+fn parse(json: &str) -> Result<Vec<Session>, anyhow::Error> {
+    let conversations = match serde_json::from_str::<Value>(json)? {
+        Value::Array(conversations) => conversations,
+        conversation => vec![conversation],
+    };
+
+    conversations.into_iter()
+        .map(|conversation| {
+            if conversation.get("chat_messages").is_some() {
+                Ok(from_claude(serde_json::from_value(conversation)?))
+            } else if conversation.get("mapping").is_some() {
+                Ok(from_chatgpt(serde_json::from_value(conversation)?))
+            } else {
+                Err(anyhow::anyhow!("Unrecognized conversation format, expected a Claude or ChatGPT export"))
+            }
+        })
+        .collect()
+}
+
+fn from_claude(conversation: ClaudeConversation) -> Session {
+    let turns = conversation.chat_messages.into_iter()
+        .filter_map(|msg| {
+            let role = match msg.sender.as_str() {
+                "human" => Role::User,
+                "assistant" => Role::Assistant,
+                _ => return None,
+            };
+            let text = if msg.content.is_empty() {
+                msg.text
+            } else {
+                msg.content.into_iter()
+                    .filter(|item| item.kind == "text")
+                    .filter_map(|item| item.text)
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            };
+            Some((role, text))
+        });
+
+    build_session(Some(conversation.uuid), conversation.name, Some(conversation.created_at), turns)
+}
+
+fn from_chatgpt(mut conversation: ChatGptConversation) -> Session {
+    // Walk up from the current leaf, then reverse to get the visible branch in order
+    let mut turns = Vec::new();
+    let mut next = conversation.current_node.take();
+    while let Some(node) = next.and_then(|id| conversation.mapping.remove(&id)) {
+        if let Some(message) = node.message {
+            let role = match message.author.role.as_str() {
+                "user" => Some(Role::User),
+                "assistant" => Some(Role::Assistant),
+                _ => None,
+            };
+            if let Some(role) = role {
+                let text = message.content.map(|content| content.parts.iter()
+                    .filter_map(|part| part.as_str())
+                    .collect::<Vec<&str>>()
+                    .join("\n"))
+                    .unwrap_or_default();
+                turns.push((role, text));
+            }
+        }
+        next = node.parent;
+    }
+    turns.reverse();
+
+    let created_at = conversation.create_time
+        .and_then(|secs| DateTime::from_timestamp(secs as i64, 0));
+    build_session(conversation.id, conversation.title, created_at, turns)
+}
+
+/*
+    * Providers require the history to start with a user message and alternate roles, while
+    * exports contain empty messages and consecutive messages from the same side after edits
+    * or failed generations.  Empty text is dropped and same-role neighbours are merged.
+*/
+fn build_session(
+    id: Option<String>,
+    title: Option<String>,
+    created_at: Option<DateTime<Utc>>,
+    turns: impl IntoIterator<Item = (Role, String)>,
+) -> Session {
+    let mut session = Session::new();
+    // Reusing the source id makes importing the same export again update instead of duplicate
+    if let Some(id) = id.filter(|id| uuid::Uuid::parse_str(id).is_ok()) {
+        session.id = id;
+    }
+    session.title = title.filter(|title| !title.trim().is_empty());
+    if let Some(created_at) = created_at {
+        session.created_at = created_at;
+    }
+
+    for (role, text) in turns {
+        let text = text.trim();
+        if text.is_empty() || (session.messages.is_empty() && role != Role::User) {
+            continue;
+        }
+        match session.messages.last_mut() {
+            Some(last) if last.role == role => {
+                last.content.push(ContentItem::Text { text: text.to_string() });
+            },
+            _ => session.messages.push(Message {
+                role,
+                content: vec![ContentItem::Text { text: text.to_string() }],
+            }),
+        }
+    }
+    session
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(session: &Session) -> Vec<(Role, Vec<String>)> {
+        session.messages.iter()
+            .map(|msg| (msg.role.clone(), msg.content.iter()
+                .filter_map(|item| match item {
+                    ContentItem::Text { text } => Some(text.clone()),
+                    _ => None,
+                })
+                .collect()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_claude_export() -> Result<(), anyhow::Error> {
+        let json = r#"[{
+            "uuid": "9b1deb4d-3b7d-4bad-9bdd-2b0d7b3dcb6d",
+            "name": "Borrow checker help",
+            "created_at": "2024-05-01T10:00:00.000000Z",
+            "chat_messages": [
+                {"sender": "human", "text": "why does this not compile?", "content": []},
+                {"sender": "assistant", "text": "", "content": [{"type": "text", "text": "You move the vector."}]},
+                {"sender": "assistant", "text": "", "content": []},
+                {"sender": "human", "text": "thanks"}
+            ]
+        }]"#;
+        let sessions = parse(json)?;
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, "9b1deb4d-3b7d-4bad-9bdd-2b0d7b3dcb6d");
+        assert_eq!(sessions[0].title.as_deref(), Some("Borrow checker help"));
+        assert_eq!(texts(&sessions[0]), vec![
+            (Role::User, vec!["why does this not compile?".to_string()]),
+            (Role::Assistant, vec!["You move the vector.".to_string()]),
+            (Role::User, vec!["thanks".to_string()]),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_chatgpt_export_follows_current_branch() -> Result<(), anyhow::Error> {
+        let json = r#"{
+            "title": "Regex question",
+            "create_time": 1714557600.5,
+            "current_node": "c",
+            "mapping": {
+                "root": {"message": null, "parent": null},
+                "sys": {"message": {"author": {"role": "system"}, "content": {"parts": [""]}}, "parent": "root"},
+                "a": {"message": {"author": {"role": "user"}, "content": {"parts": ["match digits"]}}, "parent": "sys"},
+                "old": {"message": {"author": {"role": "assistant"}, "content": {"parts": ["abandoned"]}}, "parent": "a"},
+                "c": {"message": {"author": {"role": "assistant"}, "content": {"parts": ["Use \\d+"]}}, "parent": "a"}
+            }
+        }"#;
+        let sessions = parse(json)?;
+        assert_eq!(sessions[0].title.as_deref(), Some("Regex question"));
+        assert_eq!(sessions[0].created_at.timestamp(), 1714557600);
+        assert_eq!(texts(&sessions[0]), vec![
+            (Role::User, vec!["match digits".to_string()]),
+            (Role::Assistant, vec!["Use \\d+".to_string()]),
+        ]);
+        Ok(())
+    }
+}
@@ -1,12 +0,0 @@
-pub mod anthropic;
-pub mod openai;
-pub mod deepseek;
-pub mod bedrock;
-pub mod tools;
-pub mod types;
-
-// Re-export the inference types
-pub use anthropic::AnthropicInference;
-pub use openai::OpenAIInference;
-pub use deepseek::DeepSeekInference;
-pub use bedrock::AWSBedrockInference;
\ No newline at end of file
@@ -0,0 +1,272 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+
+use crate::actions::{self, ActionRequest};
+use crate::daemon::{self, AgentSession, Answer, CreateRequest, BUSY};
+
+// The error codes of JSON-RPC 2.0, and the one for requests that were understood but failed
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const FAILED: i64 = -32000;
+
+type Reply = Result<Value, (i64, String)>;
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    // Left out for a notification, which gets no response
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    session: String,
+}
+
+#[derive(Deserialize)]
+struct Prompt {
+    session: String,
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct Approval {
+    session: String,
+    id: u64,
+    #[serde(flatten)]
+    answer: Answer,
+}
+
+// The prompts of a session waiting for their turn to end, oldest first, by ticket
+type Prompts = Arc<Mutex<VecDeque<(u64, Option<Value>)>>>;
+
+struct Open {
+    session: Arc<AgentSession>,
+    prompts: Prompts,
+}
+
+fn response(id: Value, reply: Reply) -> Value {
+    match reply {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err((code, message)) => json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}}),
+    }
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, (i64, String)> {
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|e| (INVALID_PARAMS, format!("Invalid params: {}", e)))
+}
+
+/*
+    * Sends the session's events to the client as `session/event` notifications, and
+    * answers each prompt when the `done` or `error` event of its turn has gone out, so a
+    * client has every chunk of a reply before the response to the prompt.
+*/
+fn forward(session: &AgentSession, prompts: Prompts, output: mpsc::UnboundedSender<Value>) {
+    let mut events = session.events.subscribe();
+    let id = session.id.clone();
+    tokio::spawn(async move {
+        while let Some(event) = daemon::next_event(&mut events).await {
+            let reply = match event["type"].as_str() {
+                Some("done") => Some(Ok(json!({"message": event["message"], "incomplete": event["incomplete"]}))),
+                Some("error") => Some(Err((FAILED, event["error"].as_str().unwrap_or_default().to_string()))),
+                _ => None,
+            };
+            let notification = json!({"jsonrpc": "2.0", "method": "session/event", "params": {"session": id, "event": event}});
+            if output.send(notification).is_err() {
+                return;
+            }
+            let answered = reply.and_then(|reply| Some((prompts.lock().unwrap().pop_front()?.1?, reply)));
+            if let Some((request, reply)) = answered {
+                let _ = output.send(response(request, reply));
+            }
+        }
+    });
+}
+
+/*
+    * `pprog --jsonrpc`: JSON-RPC 2.0 on stdin and stdout, one message per line, for editor
+    * plugins that keep a backend process running.  The sessions are the ones of `/api` in
+    * `pprog serve`: `session/start` opens one, `session/prompt` runs a turn and is answered
+    * when it ends, while its chunks, tool calls and approvals arrive as `session/event`
+    * notifications, `session/approve` answers a held tool call and `session/cancel` stops
+    * the turn.  `code/action` is answered on its own, without a session.
+*/
+struct Server {
+    persona: Option<String>,
+    stop_sequences: Vec<String>,
+    sessions: HashMap<String, Open>,
+    output: mpsc::UnboundedSender<Value>,
+    tickets: u64,
+}
+
+impl Server {
+    fn open(&self, id: &str) -> Result<&Open, (i64, String)> {
+        self.sessions.get(id).ok_or_else(|| (FAILED, format!("No open session {}", id)))
+    }
+
+    // The reply to a request, `None` for a prompt or code action, answered once it is done
+    async fn call(&mut self, method: &str, params_value: Value, id: Option<Value>) -> Option<Reply> {
+        match method {
+            "session/start" => Some(self.start(params_value).await),
+            "session/prompt" => {
+                let Prompt { session, text } = match params(params_value) {
+                    Ok(prompt) => prompt,
+                    Err(e) => return Some(Err(e)),
+                };
+                let (session, prompts) = match self.open(&session) {
+                    Ok(open) => (open.session.clone(), open.prompts.clone()),
+                    Err(e) => return Some(Err(e)),
+                };
+                self.tickets += 1;
+                let (ticket, output) = (self.tickets, self.output.clone());
+                prompts.lock().unwrap().push_back((ticket, id.clone()));
+                // Turns hold the chat's store and callbacks, which stay on this thread
+                tokio::task::spawn_local(async move {
+                    if session.run(&text).await.is_none() {
+                        prompts.lock().unwrap().retain(|(queued, _)| *queued != ticket);
+                        if let Some(id) = id {
+                            let _ = output.send(response(id, Err((FAILED, BUSY.to_string()))));
+                        }
+                    }
+                });
+                None
+            },
+            "code/action" => {
+                let request = match params::<ActionRequest>(params_value) {
+                    Ok(request) => request,
+                    Err(e) => return Some(Err(e)),
+                };
+                let (persona, stop_sequences, output) = (self.persona.clone(), self.stop_sequences.clone(), self.output.clone());
+                tokio::task::spawn_local(async move {
+                    let reply = actions::answer(&request, persona.as_deref(), &stop_sequences).await;
+                    if let Some(id) = id {
+                        let _ = output.send(response(id, reply.map(|action| json!(action)).map_err(|e| (FAILED, e.to_string()))));
+                    }
+                });
+                None
+            },
+            "session/approve" => Some(params(params_value).and_then(|Approval { session, id, answer }| {
+                self.open(&session)?.session.answer(id, answer).map_err(|(_, message)| (FAILED, message))?;
+                Ok(json!({"answered": id}))
+            })),
+            "session/cancel" => Some(params(params_value).and_then(|Target { session }| {
+                self.open(&session)?.session.cancel.cancel();
+                Ok(json!({"cancelled": true}))
+            })),
+            "session/close" => Some(params(params_value).and_then(|Target { session }| {
+                if self.open(&session)?.session.busy() {
+                    return Err((FAILED, "A turn is running, cancel it first".to_string()));
+                }
+                self.sessions.remove(&session);
+                Ok(json!({"closed": session}))
+            })),
+            _ => Some(Err((METHOD_NOT_FOUND, format!("No method {}", method)))),
+        }
+    }
+
+    async fn start(&mut self, params_value: Value) -> Reply {
+        let request = params::<CreateRequest>(params_value)?;
+        let (session, opened) = AgentSession::open(&request, self.persona.as_deref(), &self.stop_sequences).await
+            .map_err(|e| (FAILED, e.to_string()))?;
+        if self.sessions.contains_key(&session.id) {
+            return Err((FAILED, format!("Session {} is already open", session.id)));
+        }
+        let prompts = Prompts::default();
+        forward(&session, prompts.clone(), self.output.clone());
+        self.sessions.insert(session.id.clone(), Open { session, prompts });
+        Ok(opened)
+    }
+
+    // The response to one line from the client, unless it's a notification or a prompt
+    async fn handle(&mut self, line: &str) -> Option<Value> {
+        let request = match serde_json::from_str::<Value>(line) {
+            Ok(request) => request,
+            Err(e) => return Some(response(Value::Null, Err((PARSE_ERROR, format!("Parse error: {}", e))))),
+        };
+        let request = match serde_json::from_value::<Request>(request) {
+            Ok(request) if request.jsonrpc == "2.0" => request,
+            Ok(_) => return Some(response(Value::Null, Err((INVALID_REQUEST, "Only JSON-RPC 2.0 is spoken".to_string())))),
+            Err(e) => return Some(response(Value::Null, Err((INVALID_REQUEST, format!("Invalid request: {}", e))))),
+        };
+        let reply = self.call(&request.method, request.params, request.id.clone()).await?;
+        request.id.map(|id| response(id, reply))
+    }
+}
+
+/// Serves JSON-RPC on stdin and stdout until stdin closes.
+pub async fn run(persona: Option<&str>, stop_sequences: &[String]) -> Result<(), anyhow::Error> {
+    let (output, mut outgoing) = mpsc::unbounded_channel::<Value>();
+    // One writer, so messages never interleave
+    tokio::spawn(async move {
+        let mut stdout = tokio::io::stdout();
+        while let Some(message) = outgoing.recv().await {
+            let line = format!("{}\n", message);
+            if stdout.write_all(line.as_bytes()).await.is_err() || stdout.flush().await.is_err() {
+                return;
+            }
+        }
+    });
+    let mut server = Server {
+        persona: persona.map(str::to_string),
+        stop_sequences: stop_sequences.to_vec(),
+        sessions: HashMap::new(),
+        output: output.clone(),
+        tickets: 0,
+    };
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    tokio::task::LocalSet::new().run_until(async move {
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(response) = server.handle(&line).await {
+                let _ = output.send(response);
+            }
+        }
+        // The client is gone, so are the turns it started
+        for open in server.sessions.values() {
+            open.session.cancel.cancel();
+        }
+        Ok(())
+    }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_requests() {
+        let (output, _outgoing) = mpsc::unbounded_channel();
+        let mut server = Server { persona: None, stop_sequences: Vec::new(), sessions: HashMap::new(), output, tickets: 0 };
+
+        assert_eq!(server.handle("{not json").await.unwrap()["error"]["code"], PARSE_ERROR);
+        assert_eq!(server.handle(r#"{"jsonrpc": "1.0", "id": 1, "method": "session/start"}"#).await.unwrap()["error"]["code"], INVALID_REQUEST);
+        let unknown = server.handle(r#"{"jsonrpc": "2.0", "id": 2, "method": "session/fly"}"#).await.unwrap();
+        assert_eq!(unknown["id"], 2);
+        assert_eq!(unknown["error"]["code"], METHOD_NOT_FOUND);
+
+        let prompt = server.handle(r#"{"jsonrpc": "2.0", "id": "p", "method": "session/prompt", "params": {"session": "nope", "text": "Hi"}}"#).await.unwrap();
+        assert_eq!(prompt["error"], json!({"code": FAILED, "message": "No open session nope"}));
+        let no_text = server.handle(r#"{"jsonrpc": "2.0", "id": 3, "method": "session/prompt", "params": {"session": "nope"}}"#).await.unwrap();
+        assert_eq!(no_text["error"]["code"], INVALID_PARAMS);
+        let approval = server.handle(r#"{"jsonrpc": "2.0", "id": 4, "method": "session/approve", "params": {"session": "s", "id": 1, "decision": "maybe"}}"#).await.unwrap();
+        assert_eq!(approval["error"]["code"], INVALID_PARAMS);
+
+        // Notifications get no response, even when they fail
+        assert_eq!(server.handle(r#"{"jsonrpc": "2.0", "method": "session/cancel", "params": {"session": "nope"}}"#).await, None);
+    }
+}
@@ -0,0 +1,38 @@
+// The frontends and commands of the binary, on top of `pprog-core`
+
+pub mod actions;
+pub mod batch;
+pub mod bench;
+pub mod bestof;
+pub mod changelog;
+pub mod clipboard;
+pub mod commit;
+pub mod completions;
+pub mod logging;
+pub mod cost;
+pub mod daemon;
+pub mod doctor;
+pub mod notify;
+pub mod oneshot;
+pub mod pager;
+pub mod proxy;
+pub mod replay;
+pub mod report;
+pub mod repl;
+pub mod review;
+pub mod server;
+pub mod snippets;
+pub mod eval;
+pub mod export;
+pub mod forge;
+pub mod gentests;
+pub mod hook;
+pub mod import;
+pub mod jsonrpc;
+
+// The core modules under their old paths, so `pprog::chat` and the like keep working
+pub use pprog_core::{
+    agent, audit, auth, chat, checkpoint, config, context, crypto, egress, embeddings, images, inference, largefile, lock, loops, memory, mentions,
+    metrics, models, permissions, pii, plan, policy, prefetch, prompt, repomap, routing, sandbox, secrets, session, snapshot, store, structured, task, telemetry, todo, tokens,
+    toolcache, tooler, transport, tree,
+};
@@ -0,0 +1,369 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::Write;
+use std::num::NonZeroU64;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+use env_logger::filter::{Builder as FilterBuilder, Filter};
+use serde_json::{json, Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata};
+use tracing_core::span::Current;
+
+use crate::telemetry::{self, FinishedSpan};
+
+/// Set to `json` for one JSON object per log line instead of text.
+pub const FORMAT_VAR: &str = "PPROG_LOG_FORMAT";
+
+thread_local! {
+    // The spans entered on this thread, innermost last
+    static ENTERED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+struct SpanData {
+    metadata: &'static Metadata<'static>,
+    fields: Map<String, Value>,
+    parent: Option<u64>,
+    started: Instant,
+    refs: usize,
+    // OpenTelemetry ids, the trace is the outermost span's
+    trace_id: u128,
+    span_id: u64,
+    parent_span_id: Option<u64>,
+    start_time: SystemTime,
+}
+
+struct Inner {
+    filter: Filter,
+    json: bool,
+    file: Mutex<File>,
+    spans: Mutex<HashMap<u64, SpanData>>,
+    next_id: AtomicU64,
+}
+
+/*
+    * Writes both `log` records and `tracing` events to the log file, filtered by `RUST_LOG`
+    * (`info` when it is unset).  Events are prefixed with the spans they happened in and
+    * their fields, and every span logs its duration when it closes, so a slow reply can be
+    * traced to the request, the tool or the round it was spent in.
+*/
+#[derive(Clone)]
+pub struct Logger {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Fields {
+    message: Option<String>,
+    fields: Map<String, Value>,
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record(field, json!(format!("{:?}", value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.record(field, json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.record(field, json!(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.record(field, json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.record(field, json!(value));
+    }
+}
+
+impl Fields {
+    fn record(&mut self, field: &Field, value: Value) {
+        match (field.name(), value) {
+            ("message", Value::String(message)) => self.message = Some(message),
+            (name, value) => {
+                self.fields.insert(name.to_string(), value);
+            },
+        }
+    }
+}
+
+fn log_level(level: &tracing::Level) -> log::Level {
+    match *level {
+        tracing::Level::ERROR => log::Level::Error,
+        tracing::Level::WARN => log::Level::Warn,
+        tracing::Level::INFO => log::Level::Info,
+        tracing::Level::DEBUG => log::Level::Debug,
+        tracing::Level::TRACE => log::Level::Trace,
+    }
+}
+
+// `key=value` pairs, strings without their quotes
+fn pairs(fields: &Map<String, Value>) -> String {
+    fields.iter()
+        .map(|(key, value)| match value {
+            Value::String(text) => format!("{}={}", key, text),
+            value => format!("{}={}", key, value),
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+impl Logger {
+    pub fn new(file: File) -> Self {
+        let mut filter = FilterBuilder::new();
+        filter.filter_level(log::LevelFilter::Info);
+        if let Ok(spec) = std::env::var("RUST_LOG") {
+            filter.parse(&spec);
+        }
+        let json = std::env::var(FORMAT_VAR).is_ok_and(|format| format.eq_ignore_ascii_case("json"));
+        Logger {
+            inner: Arc::new(Inner {
+                filter: filter.build(),
+                json,
+                file: Mutex::new(file),
+                spans: Mutex::new(HashMap::new()),
+                next_id: AtomicU64::new(1),
+            }),
+        }
+    }
+
+    /// Installs this as the `log` logger and the global `tracing` subscriber.
+    pub fn install(self) -> Result<(), anyhow::Error> {
+        log::set_max_level(self.inner.filter.filter());
+        log::set_boxed_logger(Box::new(self.clone()))?;
+        tracing::subscriber::set_global_default(self)?;
+        Ok(())
+    }
+
+    fn enabled_for(&self, level: log::Level, target: &str) -> bool {
+        self.inner.filter.enabled(&log::Metadata::builder().level(level).target(target).build())
+    }
+
+    // The names and fields of `span` and the spans around it, outermost first
+    fn scope(&self, span: Option<u64>) -> Vec<(&'static str, Map<String, Value>)> {
+        let spans = self.inner.spans.lock().unwrap();
+        let mut scope = Vec::new();
+        let mut next = span;
+        while let Some(data) = next.and_then(|id| spans.get(&id)) {
+            scope.push((data.metadata.name(), data.fields.clone()));
+            next = data.parent;
+        }
+        scope.reverse();
+        scope
+    }
+
+    fn write(&self, level: log::Level, target: &str, scope: &[(&'static str, Map<String, Value>)], message: &str, fields: Map<String, Value>) {
+        let timestamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        let line = if self.inner.json {
+            let spans = scope.iter()
+                .map(|(name, fields)| {
+                    let mut span = fields.clone();
+                    span.insert("name".to_string(), json!(name));
+                    Value::Object(span)
+                })
+                .collect::<Vec<Value>>();
+            let mut line = json!({"timestamp": timestamp, "level": level.as_str(), "target": target, "message": message});
+            if !fields.is_empty() {
+                line["fields"] = Value::Object(fields);
+            }
+            if !spans.is_empty() {
+                line["spans"] = Value::Array(spans);
+            }
+            line.to_string()
+        } else {
+            let mut line = format!("[{} {:<5} {}] ", timestamp, level, target);
+            for (name, fields) in scope {
+                match fields.is_empty() {
+                    true => write!(line, "{}:", name),
+                    false => write!(line, "{}{{{}}}:", name, pairs(fields)),
+                }.ok();
+            }
+            if !scope.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(message);
+            if !fields.is_empty() {
+                write!(line, " {}", pairs(&fields)).ok();
+            }
+            line
+        };
+        let mut file = self.inner.file.lock().unwrap();
+        let _ = writeln!(file, "{}", line);
+    }
+
+    fn current(&self) -> Option<u64> {
+        ENTERED.with(|entered| entered.borrow().last().copied())
+    }
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.inner.filter.matches(record) {
+            let scope = self.scope(self.current());
+            self.write(record.level(), record.target(), &scope, &record.args().to_string(), Map::new());
+        }
+    }
+
+    fn flush(&self) {
+        let _ = self.inner.file.lock().unwrap().flush();
+    }
+}
+
+impl tracing::Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.enabled_for(log_level(metadata.level()), metadata.target())
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        let parent = match attrs.parent() {
+            Some(parent) => Some(parent.into_u64()),
+            None if attrs.is_contextual() => self.current(),
+            None => None,
+        };
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut spans = self.inner.spans.lock().unwrap();
+        let (trace_id, parent_span_id) = match parent.and_then(|parent| spans.get(&parent)) {
+            Some(parent) => (parent.trace_id, Some(parent.span_id)),
+            None => (uuid::Uuid::new_v4().as_u128(), None),
+        };
+        let data = SpanData {
+            metadata: attrs.metadata(),
+            fields: fields.fields,
+            parent,
+            started: Instant::now(),
+            refs: 1,
+            trace_id,
+            span_id: uuid::Uuid::new_v4().as_u64_pair().0,
+            parent_span_id,
+            start_time: SystemTime::now(),
+        };
+        spans.insert(id, data);
+        Id::from_non_zero_u64(NonZeroU64::new(id).expect("span ids start at 1"))
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+        if let Some(data) = self.inner.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            data.fields.extend(fields.fields);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let parent = match event.parent() {
+            Some(parent) => Some(parent.into_u64()),
+            None if event.is_contextual() => self.current(),
+            None => None,
+        };
+        let metadata = event.metadata();
+        let scope = self.scope(parent);
+        self.write(log_level(metadata.level()), metadata.target(), &scope, fields.message.as_deref().unwrap_or_default(), fields.fields);
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(idx) = entered.iter().rposition(|id| *id == span.into_u64()) {
+                entered.remove(idx);
+            }
+        });
+    }
+
+    fn current_span(&self) -> Current {
+        let Some(id) = self.current() else { return Current::none() };
+        match self.inner.spans.lock().unwrap().get(&id) {
+            Some(data) => Current::new(Id::from_u64(id), data.metadata),
+            None => Current::none(),
+        }
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self.inner.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            data.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let id = span.into_u64();
+        let (scope, closed) = {
+            let mut spans = self.inner.spans.lock().unwrap();
+            let Some(data) = spans.get_mut(&id) else { return false };
+            data.refs -= 1;
+            if data.refs > 0 {
+                return false;
+            }
+            let closed = spans.remove(&id).expect("span was just found");
+            (closed.parent, closed)
+        };
+        telemetry::record_span(closed.metadata.target(), FinishedSpan {
+            trace_id: closed.trace_id,
+            span_id: closed.span_id,
+            parent_span_id: closed.parent_span_id,
+            name: closed.metadata.name(),
+            start: closed.start_time,
+            end: SystemTime::now(),
+            fields: &closed.fields,
+        });
+        let mut outer = self.scope(scope);
+        outer.push((closed.metadata.name(), closed.fields));
+        let duration = json!(closed.started.elapsed().as_millis() as u64);
+        self.write(log_level(closed.metadata.level()), closed.metadata.target(), &outer, "done", Map::from_iter([("duration_ms".to_string(), duration)]));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spans_and_events() -> Result<(), anyhow::Error> {
+        let path = std::env::temp_dir().join(format!("pprog-log-{}", std::process::id()));
+        let logger = Logger::new(File::create(&path)?);
+        tracing::subscriber::with_default(logger, || {
+            let turn = tracing::info_span!("turn", round = 1);
+            let _entered = turn.enter();
+            let query = tracing::info_span!("query_model", provider = "anthropic", output_tokens = tracing::field::Empty);
+            query.in_scope(|| tracing::info!(attempt = 2, "asking again"));
+            query.record("output_tokens", 40);
+            drop(query);
+            tracing::debug!("not logged at info");
+        });
+        let log = std::fs::read_to_string(&path)?;
+        std::fs::remove_file(&path)?;
+        let lines = log.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("] turn{round=1}:query_model{provider=anthropic}: asking again attempt=2"));
+        assert!(lines[1].contains("] turn{round=1}:query_model{output_tokens=40 provider=anthropic}: done duration_ms="));
+        assert!(lines[2].contains("] turn{round=1}: done duration_ms="));
+        Ok(())
+    }
+}
@@ -1,22 +1,63 @@
-mod inference;
-mod chat;
-mod tree;
-mod config;
-mod server;
-
 use std::fs::OpenOptions;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
 
 use clap::{CommandFactory, Parser, Subcommand};
 use config::ProjectConfig;
-use env_logger::{Builder, Target};
+use pprog::{
+    agent, audit, batch, bench, changelog, chat, commit, completions, config, cost, doctor, embeddings, eval, export, forge, gentests, hook, import, jsonrpc, lock, logging, oneshot, pager, prompt,
+    replay, repl, review, server, session, snippets, store, telemetry, tooler, tree,
+};
+use export::ExportFormat;
+use pprog::inference::wire;
+use session::Session;
+use snippets::Snippet;
 use tree::GitTree;
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+    #[arg(short, long, value_name = "PROMPT", help = "Answer one prompt and exit, piped stdin is attached as context")]
+    print: Option<String>,
+    #[arg(long, value_enum, default_value_t = oneshot::OutputFormat::Text, requires = "print", help = "Output format of --print")]
+    output: oneshot::OutputFormat,
+    #[arg(long, requires = "print", help = "Copy the last code block of the answer to the clipboard")]
+    copy: bool,
+    #[arg(long, global = true, help = "Skip session and workspace locks")]
+    no_lock: bool,
+    #[arg(long, global = true, help = "Refuse every tool call that could change the project, only reading commands run")]
+    read_only: bool,
+    #[arg(long, global = true, help = "Go on past a soft spending budget that has require_force set")]
+    force: bool,
+    #[arg(short, long, global = true, help = "Print token usage, cost, latency and stop reason after each turn")]
+    verbose: bool,
+    #[arg(short, long, requires = "print", conflicts_with = "verbose", help = "Print only errors, the exit status tells how --print went")]
+    quiet: bool,
+    #[arg(long, global = true, help = "Ask before every tool call, to run, deny, edit or always allow it")]
+    approve_each: bool,
+    #[arg(long, global = true, value_name = "NAME", help = "Work as a persona from [personas] in pprog.toml, with its prompt, model and tools")]
+    persona: Option<String>,
+    #[arg(long = "stop", value_name = "TEXT", global = true, help = "End replies where the model writes TEXT, in place of stop_sequences, can be repeated")]
+    stop: Vec<String>,
+    #[arg(long, value_name = "TEXT", requires = "print", conflicts_with = "n", help = "Start the answer with TEXT for the model to continue, e.g. '{\"' for JSON")]
+    prefill: Option<String>,
+    #[arg(long, value_name = "USD", requires = "print", help = "Stop --print once it has cost this much, e.g. 2.00")]
+    max_cost: Option<f64>,
+    #[arg(long, value_name = "TOKENS", requires = "print", value_parser = oneshot::parse_tokens, help = "Stop --print once it has used this many tokens, e.g. 500k")]
+    max_tokens: Option<u64>,
+    #[arg(long, value_name = "MODEL", requires = "print", help = "Have a second model review the answer and changes, and send them back once for fixes")]
+    review_model: Option<String>,
+    #[arg(long, value_name = "N", default_value_t = 1, requires = "print", conflicts_with_all = ["review_model", "max_cost", "max_tokens"], help = "Ask for N answers at once and keep the best, picked on the terminal or by --judge-model")]
+    n: usize,
+    #[arg(long, value_name = "MODEL", requires = "n", help = "Model that picks the best of the --n answers")]
+    judge_model: Option<String>,
+    #[arg(long, requires = "print", help = "Print the tokens and input cost the request would take, counted locally, without sending it")]
+    dry_run: bool,
+    #[arg(long, value_name = "FILE", requires = "review_model", help = "Write the review to FILE for CI, JUnit XML for a .xml name and SARIF for .sarif or .json, can be repeated")]
+    report: Vec<std::path::PathBuf>,
+    #[arg(long, conflicts_with = "print", help = "Speak JSON-RPC on stdin and stdout, for editor plugins")]
+    jsonrpc: bool,
 }
 
 #[derive(Subcommand)]
@@ -25,10 +66,287 @@ enum Commands {
     Init,
     #[command(about = "Start the API server")]
     Serve {
-        #[arg(short, long, default_value = "127.0.0.1")]
+        #[arg(short = 'H', long, default_value = "127.0.0.1")]
         host: String,
         #[arg(short, long, default_value_t = 8080)]
         port: u16,
+        #[arg(long, help = "Listen on this Unix domain socket instead of the host and port")]
+        socket: Option<std::path::PathBuf>,
+        #[arg(long, help = "Continue a saved session by id or unique prefix")]
+        session: Option<String>,
+        #[arg(long, help = "System prompt template from .pprog/prompts/<name>.md")]
+        prompt_template: Option<String>,
+    },
+    #[command(about = "Chat in the terminal, the default when no command is given")]
+    Chat {
+        #[arg(long, help = "Continue a saved session by id or unique prefix")]
+        session: Option<String>,
+        #[arg(long, help = "System prompt template from .pprog/prompts/<name>.md")]
+        prompt_template: Option<String>,
+        #[arg(long, help = "Full screen layout with panes for tool output and file diffs")]
+        tui: bool,
+        #[arg(long, help = "Print replies as plain text instead of rendering markdown")]
+        raw: bool,
+        #[arg(long, help = "Write the first message in $EDITOR, Ctrl-X does the same at the prompt")]
+        editor: bool,
+        #[arg(long, help = "Have the model propose a plan to approve before it runs any tools")]
+        plan: bool,
+    },
+    #[command(about = "Export a session transcript as markdown, json or html")]
+    Export {
+        #[arg(help = "Session id or unique prefix")]
+        session: String,
+        #[arg(short, long, value_enum, default_value_t = ExportFormat::Md)]
+        format: ExportFormat,
+        #[arg(short, long, help = "Write to file instead of stdout")]
+        output: Option<std::path::PathBuf>,
+    },
+    #[command(about = "Fork a session into a new one, keeping the first N messages")]
+    Fork {
+        #[arg(help = "Session id or unique prefix")]
+        session: String,
+        #[arg(long, help = "Number of messages to keep, defaults to all")]
+        at: Option<usize>,
+    },
+    #[command(about = "Import conversations from a Claude or ChatGPT export")]
+    Import {
+        #[arg(help = "conversations.json from the export, or a single conversation")]
+        file: std::path::PathBuf,
+    },
+    #[command(about = "Run a suite of prompts against models and check the replies")]
+    Eval {
+        #[arg(help = "Suite of cases in TOML")]
+        suite: std::path::PathBuf,
+        #[arg(long = "model", value_name = "MODEL", help = "Model to run the cases against instead of the suite's, can be repeated")]
+        models: Vec<String>,
+        #[arg(long, help = "Keep the replies of the first model as the golden ones later runs are compared with")]
+        update: bool,
+        #[arg(long, value_name = "FILE", help = "Write the results to FILE for CI, JUnit XML for a .xml name and SARIF for .sarif or .json, can be repeated")]
+        report: Vec<std::path::PathBuf>,
+    },
+    #[command(about = "Run every prompt of a JSONL file as its own turn and write the replies as JSONL")]
+    Batch {
+        #[arg(help = "Prompts, one per line as a JSON string or an object with a prompt and an id")]
+        prompts: std::path::PathBuf,
+        #[arg(long, default_value_t = 4, help = "Prompts run at once")]
+        concurrency: usize,
+        #[arg(long, default_value_t = 2, help = "Times a prompt that failed before running any tool is tried again")]
+        retries: usize,
+        #[arg(long, help = "Results file, prompts it already has a reply for are skipped")]
+        out: std::path::PathBuf,
+    },
+    #[command(about = "Time a standard set of prompts on each configured model and compare them")]
+    Bench {
+        #[arg(long = "model", value_name = "MODEL", help = "Model to benchmark instead of the configured ones, can be repeated")]
+        models: Vec<String>,
+        #[arg(long, default_value_t = 3, help = "Times to send each prompt to each model")]
+        runs: usize,
+    },
+    #[command(about = "Check the config, the providers and the tools pprog needs before relying on it")]
+    Doctor {
+        #[arg(long, help = "Skip the requests to the providers")]
+        offline: bool,
+    },
+    #[command(about = "Ask the recorded questions of a session again and diff the answers")]
+    Replay {
+        #[arg(help = "Session id or unique prefix")]
+        session: String,
+        #[arg(long, help = "Model to replay against, defaults to the configured model")]
+        model: Option<String>,
+        #[arg(long, help = "Temperature to replay with", default_value_t = 0.0)]
+        temperature: f32,
+        #[arg(long, help = "Use responses cached by an earlier replay instead of the API")]
+        cached: bool,
+    },
+    #[command(about = "Restore files changed by the last agent turn")]
+    Undo,
+    #[command(about = "Manage saved sessions")]
+    Sessions {
+        #[command(subcommand)]
+        command: SessionCommands,
+    },
+    #[command(about = "Search saved sessions")]
+    Search {
+        query: String,
+        #[arg(short, long, default_value_t = 20)]
+        limit: usize,
+    },
+    #[command(about = "Manage the embeddings index behind semantic_search")]
+    Index {
+        #[command(subcommand)]
+        command: IndexCommands,
+    },
+    #[command(about = "Work from the issues and reviews of GitHub or GitLab", visible_aliases = ["gh", "gl"])]
+    Forge {
+        #[command(subcommand)]
+        command: ForgeCommands,
+    },
+    #[command(about = "Write a Conventional Commits message for the staged changes, then edit and commit it")]
+    Commit {
+        #[arg(long, short, help = "Commit with the message as written, without asking")]
+        yes: bool,
+        #[arg(long, conflicts_with = "yes", help = "Only print the message")]
+        print: bool,
+    },
+    #[command(about = "Review a diff a chunk at a time and report the problems found by file, line and severity")]
+    Review {
+        #[arg(long, value_name = "RANGE|FILE", help = "Commits to diff like main...HEAD, a patch file or - for stdin, the uncommitted changes by default")]
+        diff: Option<String>,
+        #[arg(long, value_name = "MODEL", help = "Model to review with, defaults to the configured model")]
+        model: Option<String>,
+        #[arg(long, value_name = "FILE", help = "Write the findings to FILE for CI, JUnit XML for a .xml name and SARIF for .sarif or .json, can be repeated")]
+        report: Vec<std::path::PathBuf>,
+    },
+    #[command(about = "Draft release notes from the commits since a tag into CHANGELOG.md")]
+    Changelog {
+        #[arg(long, value_name = "TAG", help = "Where the release starts, the latest tag by default")]
+        from: Option<String>,
+        #[arg(long, value_name = "REV", default_value = "HEAD", help = "Where the release ends")]
+        to: String,
+        #[arg(long, value_name = "VERSION", default_value = "Unreleased", help = "Version the notes are for, dated today")]
+        release: String,
+        #[arg(short, long, help = "Changelog to update, CHANGELOG.md at the root of the repository by default")]
+        output: Option<std::path::PathBuf>,
+        #[arg(long, help = "Print the notes instead of updating the changelog")]
+        print: bool,
+    },
+    #[command(about = "Write unit tests for a module and fix them until they compile and pass, printing the patch")]
+    GenTests {
+        #[arg(help = "The module to test, relative to the root of the repository")]
+        path: String,
+        #[arg(long, value_name = "COMMAND", help = "Runs the tests, the usual one for the language by default")]
+        cmd: Option<String>,
+        #[arg(long, default_value_t = 3, help = "Rounds of running the tests and sending failures back before giving up")]
+        attempts: usize,
+        #[arg(short, long, help = "Write the patch to file instead of stdout")]
+        output: Option<std::path::PathBuf>,
+    },
+    #[command(about = "Checks to run from git hooks")]
+    Hook {
+        #[command(subcommand)]
+        command: HookCommands,
+    },
+    #[command(about = "Manage saved prompt snippets")]
+    Snippets {
+        #[command(subcommand)]
+        command: SnippetCommands,
+    },
+    #[command(about = "Show what the model requests cost", args_conflicts_with_subcommands = true)]
+    Cost {
+        #[command(subcommand)]
+        command: Option<CostCommands>,
+        #[arg(long, value_name = "SPAN", value_parser = cost::parse_since, help = "Only requests this recent, like 7d, 12h or 2w")]
+        since: Option<chrono::Duration>,
+        #[arg(long, value_enum, default_value_t = cost::GroupBy::Model, help = "What each row of the table is")]
+        by: cost::GroupBy,
+    },
+    #[command(about = "Check the audit log of prompts and responses")]
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommands,
+    },
+    #[command(about = "Look into what was sent to the model")]
+    Debug {
+        #[command(subcommand)]
+        command: DebugCommands,
+    },
+    #[command(about = "Print a shell completion script")]
+    Completions {
+        #[arg(value_enum)]
+        shell: completions::Shell,
+    },
+}
+
+#[derive(Subcommand)]
+enum SessionCommands {
+    #[command(about = "List saved sessions, newest first")]
+    List,
+    #[command(about = "Show the transcript of a session")]
+    Show {
+        #[arg(help = "Session id or unique prefix")]
+        session: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum IndexCommands {
+    #[command(about = "Embed the workspace files changed since the last sync")]
+    Build,
+    #[command(about = "Show what the index holds")]
+    Status,
+    #[command(about = "Drop the index, the next sync embeds every file again")]
+    Clear,
+}
+
+#[derive(Subcommand)]
+enum CostCommands {
+    #[command(about = "Write every request with its tokens and cost, for spreadsheets")]
+    Export {
+        #[arg(short, long, value_enum, default_value_t = cost::UsageFormat::Csv)]
+        format: cost::UsageFormat,
+        #[arg(long, value_name = "SPAN", value_parser = cost::parse_since, help = "Only requests this recent, like 7d, 12h or 2w")]
+        since: Option<chrono::Duration>,
+        #[arg(short, long, help = "Write to file instead of stdout")]
+        output: Option<std::path::PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum AuditCommands {
+    #[command(about = "Check that no entry was changed, removed or reordered")]
+    Verify {
+        #[arg(help = "Log to check, .pprog/audit.jsonl by default")]
+        path: Option<std::path::PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum DebugCommands {
+    #[command(about = "Write the last request and its response, redacted, to a file")]
+    Last {
+        #[arg(long, short, help = "Where to write it, a new file in .pprog by default")]
+        output: Option<std::path::PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ForgeCommands {
+    #[command(about = "Run the agent on an issue, with its title, description and comments as the task")]
+    Issue {
+        number: u64,
+        #[arg(long, visible_alias = "mr", help = "Work on a branch of its own, then push it and open a draft pull or merge request")]
+        pr: bool,
+    },
+    #[command(about = "Run the agent on the open review comments of a pull or merge request, on its branch, and push the changes")]
+    Address {
+        number: u64,
+    },
+    #[command(about = "Keep a token for the forge of origin in the OS keychain, read from stdin")]
+    Login,
+}
+
+#[derive(Subcommand)]
+enum HookCommands {
+    #[command(about = "Check the staged changes against the [hook] policies with a cheap model, failing on a violation")]
+    PreCommit,
+}
+
+#[derive(Subcommand)]
+enum SnippetCommands {
+    #[command(about = "Save a snippet, from the argument, piped stdin or $EDITOR")]
+    Add {
+        #[arg(help = "Letters, digits, - and _")]
+        name: String,
+        #[arg(help = "Snippet text, {{name}} marks a placeholder")]
+        text: Option<String>,
+    },
+    #[command(about = "List saved snippets")]
+    List,
+    #[command(about = "Print a snippet with its placeholders filled in")]
+    Use {
+        name: String,
+        #[arg(help = "Placeholder values as name=value")]
+        values: Vec<String>,
     },
 }
 
@@ -46,20 +364,95 @@ fn setup_logger() -> Result<(), anyhow::Error> {
         .truncate(true)
         .open(&log_file_path)?;
 
-    let mut builder = Builder::from_default_env();
-    builder
-        .target(Target::Pipe(Box::new(file)))
-        .format_timestamp_secs()
-        .filter_level(log::LevelFilter::Info)
-        .init();
+    logging::Logger::new(file).install()
+}
 
-    Ok(())
+// Warns about the soft budgets already crossed before anything is sent
+fn check_soft_limits(force: bool) -> Result<(), anyhow::Error> {
+    let budget = ProjectConfig::load_or_default()?.budget;
+    cost::check_soft_limits(&budget, force, &mut Default::default())
+}
+
+fn pager_enabled() -> bool {
+    ProjectConfig::load().map_or(true, |config| config.pager)
+}
+
+async fn run_repl(
+    session: Option<&str>,
+    prompt_template: Option<&str>,
+    persona: Option<&str>,
+    stop: &[String],
+    tui: bool,
+    options: repl::Options,
+) -> Result<(), anyhow::Error> {
+    let history = ProjectConfig::shared()?.history_messages;
+    let session = session.map(|id| Session::load_recent(id, history)).transpose()?;
+    let template = prompt_template.map(prompt::PromptTemplate::load).transpose()?;
+    let _workspace = lock::Lock::workspace()?;
+    check_soft_limits(options.force)?;
+
+    let mut chat = chat::Chat::for_persona(persona).await?;
+    if !stop.is_empty() {
+        chat.set_stop_sequences(stop.to_vec()).await?;
+    }
+    if let Some(session) = session {
+        chat.resume(session)?;
+    }
+    if let Some(template) = template {
+        chat.set_prompt_template(template);
+    }
+    if options.plan {
+        chat.start_planning();
+    }
+    chat.prefetch();
+    if tui {
+        repl::tui::run(chat, options.approve_each).await
+    } else {
+        repl::run(chat, options).await
+    }
+}
+
+// The chat and options of an agent run on an issue or review, printed like `pprog -p`
+async fn forge_run(cli: &Cli) -> Result<(chat::Chat, oneshot::Options), anyhow::Error> {
+    let mut chat = chat::Chat::for_persona(cli.persona.as_deref()).await?;
+    if !cli.stop.is_empty() {
+        chat.set_stop_sequences(cli.stop.clone()).await?;
+    }
+    let options = oneshot::Options {
+        format: oneshot::OutputFormat::Text,
+        copy: false,
+        verbose: cli.verbose,
+        quiet: false,
+        approve_each: cli.approve_each,
+        budget: agent::Budget::default(),
+        review_model: None,
+        n: 1,
+        judge_model: None,
+        dry_run: false,
+        report: Vec::new(),
+    };
+    Ok((chat, options))
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
     setup_logger()?;
+    match ProjectConfig::load_or_default() {
+        Ok(config) => {
+            telemetry::init(&config.telemetry);
+            audit::init(&config.audit)?;
+        },
+        // Only the commands that set up or report on the config run past a broken policy
+        Err(e) if matches!(cli.command, Some(Commands::Init) | Some(Commands::Doctor { .. })) => log::error!("{:#}", e),
+        Err(e) => return Err(e.context("Refusing to start").into()),
+    }
+    if cli.no_lock {
+        lock::disable();
+    }
+    if cli.read_only {
+        tooler::set_read_only();
+    }
 
     match &cli.command {
         Some(Commands::Init) => {
@@ -84,7 +477,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 if !gitignore_contents.contains("pprog.toml") {
                     println!("Adding config to .gitignore.");
                     let mut gitignore = std::fs::OpenOptions::new()
-                        .write(true)
                         .append(true)
                         .create(true)
                         .open(gitignore_path)
@@ -99,8 +491,464 @@ pprog.toml
                 println!("Init successful.");
             }
         }
-        Some(Commands::Serve { host, port }) => {
-            server::start_server(host.clone(), *port).await?;
+        Some(Commands::Serve { host, port, socket, session, prompt_template }) => {
+            let history = ProjectConfig::shared()?.history_messages;
+            let session = session.as_deref().map(|id| Session::load_recent(id, history)).transpose()?;
+            let template = prompt_template.as_deref().map(prompt::PromptTemplate::load).transpose()?;
+            let _workspace = lock::Lock::workspace()?;
+            check_soft_limits(cli.force)?;
+            server::start_server(host.clone(), *port, socket.clone(), session, template, cli.persona.as_deref(), &cli.stop).await?;
+        }
+        Some(Commands::Chat { session, prompt_template, tui, raw, editor, plan }) => {
+            let options = repl::Options { raw: *raw, editor: *editor, verbose: cli.verbose, plan: *plan, approve_each: cli.approve_each, force: cli.force };
+            run_repl(session.as_deref(), prompt_template.as_deref(), cli.persona.as_deref(), &cli.stop, *tui, options).await?;
+        }
+        Some(Commands::Export { session, format, output }) => {
+            let session = Session::load(session)?;
+            let rendered = export::render(&session, *format)?;
+            match output {
+                Some(path) => std::fs::write(path, rendered)?,
+                None => pager::page(&rendered, pager_enabled())?,
+            }
+        }
+        Some(Commands::Fork { session, at }) => {
+            let session = Session::load(session)?;
+            let fork = session.fork(at.unwrap_or(session.messages.len()))?;
+            fork.save()?;
+            println!("Forked session {} into {}", session.id, fork.id);
+        }
+        Some(Commands::Import { file }) => {
+            let sessions = import::import_file(file)?;
+            let mut store = store::Store::open()?;
+            for session in &sessions {
+                let _lock = lock::Lock::session(&session.id)?;
+                store.save_session(session)?;
+                println!(
+                    "Imported {} ({} msgs)  {}",
+                    session.id,
+                    session.messages.len(),
+                    session.title.as_deref().unwrap_or("(untitled)"),
+                );
+            }
+            println!("Imported {} conversations.", sessions.len());
+        }
+        Some(Commands::Eval { suite, models, update, report }) => {
+            let mut suite = eval::Suite::load(suite)?;
+            if !models.is_empty() {
+                suite.models = models.clone();
+            }
+            let config = ProjectConfig::load_or_default()?;
+            let goldens = match update {
+                true => eval::Goldens::new(),
+                false => suite.goldens()?,
+            };
+            let outcomes = eval::run(&config, &suite, &goldens).await?;
+            print!("{}", eval::report(&outcomes));
+            let ci_report = eval::ci_report(&suite, &outcomes);
+            for path in report {
+                ci_report.write(path)?;
+            }
+            if *update {
+                let count = suite.update_goldens(&outcomes)?;
+                println!("Kept {} golden replies in {}.", count, suite.golden_path().display());
+            }
+            if outcomes.iter().any(|outcome| !outcome.passed()) {
+                telemetry::flush().await;
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Batch { prompts, concurrency, retries, out }) => {
+            let items = batch::parse(&std::fs::read_to_string(prompts)?)?;
+            let _workspace = lock::Lock::workspace()?;
+            check_soft_limits(cli.force)?;
+            let config = ProjectConfig::load_or_default()?;
+            let total = items.len();
+            let mut done = 0;
+            let progress = std::io::stderr().is_terminal();
+            let summary = batch::run(&config, items, out, *concurrency, *retries, |id, ok| {
+                done += 1;
+                if progress {
+                    eprint!("\r\x1b[K{}/{} {} {}", done, total, id, if ok { "done" } else { "failed" });
+                }
+            }).await?;
+            if progress {
+                eprint!("\r\x1b[K");
+            }
+            print!("{}", summary.report());
+            if summary.failed > 0 {
+                telemetry::flush().await;
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Bench { models, runs }) => {
+            let config = ProjectConfig::load_or_default()?;
+            let models = match models.is_empty() {
+                true => bench::configured_models(&config),
+                false => models.clone(),
+            };
+            let total = models.len() * bench::PROMPTS.len() * runs;
+            let mut done = 0;
+            let progress = std::io::stderr().is_terminal();
+            let benches = bench::run(&config, &models, *runs, |model, prompt| {
+                done += 1;
+                if progress {
+                    eprint!("\r\x1b[K{}/{} {} {}", done, total, model, prompt);
+                }
+            }).await?;
+            if progress {
+                eprint!("\r\x1b[K");
+            }
+            print!("{}", bench::report(&benches));
+        }
+        Some(Commands::Doctor { offline }) => {
+            let checks = doctor::run(*offline).await;
+            print!("{}", doctor::report(&checks));
+            if checks.iter().any(|check| check.status == doctor::Status::Fail) {
+                telemetry::flush().await;
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Replay { session, model, temperature, cached }) => {
+            let session = Session::load(session)?;
+            let mut config = ProjectConfig::load_or_default()?;
+            if let Some(model) = model {
+                config.model = model.clone();
+            }
+            config.temperature = Some(*temperature);
+            // The seed of the session, so providers that take one sample the way it was
+            if let Some(seed) = session.sampling.as_ref().and_then(|sampling| sampling.seed) {
+                config.seed = Some(seed);
+            }
+            let model = config.model.clone();
+            let chat = chat::Chat::from_config(config).await?;
+
+            let steps = replay::replay(&chat, &model, &session, *cached).await?;
+            let mut report = String::new();
+            for step in &steps {
+                if step.changed() {
+                    report.push_str(&format!("#{} changed\n{}\n", step.message_index, step.diff()));
+                } else {
+                    report.push_str(&format!("#{} unchanged\n", step.message_index));
+                }
+            }
+            let changed = steps.iter().filter(|step| step.changed()).count();
+            report.push_str(&format!("{} of {} responses changed.\n", changed, steps.len()));
+            pager::page(&report, chat.config().pager)?;
+        }
+        Some(Commands::Undo) => {
+            let _workspace = lock::Lock::workspace()?;
+            for path in tooler::undo_last_turn()? {
+                println!("Restored {}", path);
+            }
+        }
+        Some(Commands::Sessions { command: SessionCommands::List }) => {
+            let listing: String = Session::list()?.iter()
+                .map(|session| format!(
+                    "{}  {}  {:>4} msgs  {}\n",
+                    session.id,
+                    session.created_at.format("%Y-%m-%d %H:%M"),
+                    session.message_count,
+                    session.title.as_deref().unwrap_or("(untitled)"),
+                ))
+                .collect();
+            pager::page(&listing, pager_enabled())?;
+        }
+        Some(Commands::Sessions { command: SessionCommands::Show { session } }) => {
+            let session = Session::load(session)?;
+            let config = ProjectConfig::load_or_default()?;
+            let mut transcript = export::render(&session, ExportFormat::Md)?;
+            if std::io::stdout().is_terminal() {
+                transcript = repl::render_markdown(&transcript, &config);
+            }
+            pager::page(&transcript, config.pager)?;
+        }
+        Some(Commands::Search { query, limit }) => {
+            let hits = store::Store::open()?.search(query, *limit)?;
+            if hits.is_empty() {
+                println!("No matches.");
+            }
+            let results: String = hits.iter()
+                .map(|hit| format!("{} #{} {}: {}\n", hit.session_id, hit.message_index, hit.role, hit.snippet.replace('\n', " ")))
+                .collect();
+            pager::page(&results, pager_enabled())?;
+        }
+        Some(Commands::Index { command: IndexCommands::Build }) => {
+            let config = ProjectConfig::load_or_default()?;
+            let (_index, embedder, stats) = embeddings::sync_workspace(&config).await?;
+            println!("Embedded {} files ({} chunks) with {}, dropped {} removed files.", stats.indexed, stats.chunks, embedder.model(), stats.removed);
+        }
+        Some(Commands::Index { command: IndexCommands::Status }) => {
+            let status = embeddings::index::Index::open(embeddings::WORKSPACE_COLLECTION)?.status()?;
+            println!("{} files, {} chunks", status.files, status.chunks);
+            if !status.models.is_empty() {
+                println!("Embedded with {}", status.models.join(", "));
+            }
+        }
+        Some(Commands::Index { command: IndexCommands::Clear }) => {
+            embeddings::index::Index::open(embeddings::WORKSPACE_COLLECTION)?.clear()?;
+            println!("Index cleared.");
+        }
+        Some(Commands::Forge { command: ForgeCommands::Issue { number, pr } }) => {
+            let _workspace = lock::Lock::workspace()?;
+            check_soft_limits(cli.force)?;
+            let (chat, options) = forge_run(&cli).await?;
+            forge::work_on_issue(chat, *number, options, *pr).await?;
+        }
+        Some(Commands::Forge { command: ForgeCommands::Address { number } }) => {
+            let _workspace = lock::Lock::workspace()?;
+            check_soft_limits(cli.force)?;
+            let (chat, options) = forge_run(&cli).await?;
+            forge::address_review(chat, *number, options).await?;
+        }
+        Some(Commands::Forge { command: ForgeCommands::Login }) => {
+            let forge = forge::ForgeProvider::for_origin(&ProjectConfig::load_or_default()?)?;
+            if std::io::stdin().is_terminal() {
+                eprint!("Token: ");
+            }
+            let mut token = String::new();
+            std::io::stdin().read_line(&mut token)?;
+            if token.trim().is_empty() {
+                eprintln!("No token given.");
+                std::process::exit(1);
+            }
+            forge.login(&token)?;
+            println!("Stored the token in the OS keychain.");
+        }
+        Some(Commands::Commit { yes, print }) => {
+            check_soft_limits(cli.force)?;
+            let chat = chat::Chat::for_persona(cli.persona.as_deref()).await?;
+            let message = commit::generate(&chat).await?;
+            // Off a terminal the message is printed, for hooks and scripts to use
+            let message = match (*yes, *print || !std::io::stdin().is_terminal()) {
+                (true, _) => Some(message),
+                (false, true) => {
+                    println!("{}", message);
+                    None
+                },
+                (false, false) => commit::confirm_on_tty(message)?,
+            };
+            if let Some(message) = message {
+                commit::commit(&message)?;
+            }
+        }
+        Some(Commands::Review { diff, model, report }) => {
+            let diff = match diff.as_deref() {
+                Some("-") => std::io::read_to_string(std::io::stdin())?,
+                Some(patch) if std::path::Path::new(patch).is_file() => std::fs::read_to_string(patch)?,
+                Some(range) => review::git_diff(&[range])?,
+                None => review::git_diff(&["HEAD"])?,
+            };
+            if diff.trim().is_empty() {
+                return Err("No changes to review".into());
+            }
+            check_soft_limits(cli.force)?;
+            let chat = chat::Chat::for_persona(cli.persona.as_deref()).await?;
+            let model = model.clone().unwrap_or_else(|| chat.config().model.clone());
+            let started = std::time::Instant::now();
+            let progress = std::io::stderr().is_terminal();
+            let findings = review::review_diff(&chat, &model, &diff, |part, parts| {
+                if progress {
+                    eprint!("\r\x1b[KReviewing part {} of {}", part, parts);
+                }
+            }).await?;
+            if progress {
+                eprint!("\r\x1b[K");
+            }
+            print!("{}", review::diff_report(&findings));
+            let ci_report = review::diff_ci_report(&model, &findings, started.elapsed());
+            for path in report {
+                ci_report.write(path)?;
+            }
+            if findings.iter().any(|finding| finding.severity == review::Severity::Error) {
+                telemetry::flush().await;
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Changelog { from, to, release, output, print }) => {
+            let from = match from {
+                Some(from) => from.clone(),
+                None => changelog::latest_tag(to)?,
+            };
+            let changes = changelog::changes(&from, to)?;
+            if changes.is_empty() {
+                return Err(format!("No commits from {} to {}", from, to).into());
+            }
+            check_soft_limits(cli.force)?;
+            let chat = chat::Chat::for_persona(cli.persona.as_deref()).await?;
+            let notes = changelog::draft(&chat, &changes).await?;
+            let section = notes.render(release, &chrono::Local::now().format("%Y-%m-%d").to_string());
+            if *print {
+                print!("{}", section);
+            } else {
+                let path = match output {
+                    Some(path) => path.clone(),
+                    None => GitTree::get_git_root()?.join("CHANGELOG.md"),
+                };
+                let existing = std::fs::read_to_string(&path).unwrap_or_default();
+                std::fs::write(&path, changelog::update(&existing, &section))?;
+                println!("Wrote the notes of {} commits since {} to {}", changes.len(), from, path.display());
+            }
+        }
+        Some(Commands::GenTests { path, cmd, attempts, output }) => {
+            let root = GitTree::get_git_root()?;
+            let source = std::fs::canonicalize(path).map_err(|e| format!("{}: {}", path, e))?;
+            let Ok(source) = source.strip_prefix(root.canonicalize()?) else {
+                return Err(format!("{} is not in the repository", path).into());
+            };
+            let target = gentests::Target::new(&root, &source.to_string_lossy(), cmd.as_deref())?;
+            let _workspace = lock::Lock::workspace()?;
+            check_soft_limits(cli.force)?;
+            let mut chat = chat::Chat::for_persona(cli.persona.as_deref()).await?;
+            if !cli.stop.is_empty() {
+                chat.set_stop_sequences(cli.stop.clone()).await?;
+            }
+            let generated = gentests::generate(&mut chat, &target, *attempts, |attempt| {
+                eprintln!("Round {} of {}, the tests run with `{}`", attempt, attempts, target.command);
+            }).await?;
+            if !generated.answer.trim().is_empty() {
+                eprintln!("{}", generated.answer.trim_end());
+            }
+            match output {
+                Some(output) => std::fs::write(output, &generated.patch)?,
+                None => print!("{}", generated.patch),
+            }
+            if !generated.passed {
+                eprintln!("The tests still fail after round {}, the changes are left in the working tree.", generated.attempts);
+                telemetry::flush().await;
+                std::process::exit(1);
+            }
+            eprintln!("The tests pass in round {}, the changes are in the working tree.", generated.attempts);
+        }
+        Some(Commands::Hook { command: HookCommands::PreCommit }) => {
+            // No workspace lock, git runs hooks for the agent's own commits too
+            let config = ProjectConfig::load_or_default()?;
+            let outcome = match check_soft_limits(cli.force) {
+                Ok(()) => hook::pre_commit(&config).await?,
+                Err(e) => hook::Outcome::Skipped(e.to_string()),
+            };
+            match outcome {
+                hook::Outcome::Passed => {},
+                hook::Outcome::Failed(violations) => {
+                    for violation in &violations {
+                        let location = match violation.line {
+                            Some(line) => format!("{}:{}", violation.path, line),
+                            None => violation.path.clone(),
+                        };
+                        eprintln!("{}  {}: {}", location, violation.policy, violation.message);
+                    }
+                    eprintln!("pprog: the staged changes break the policies above, fix them or commit with --no-verify");
+                    telemetry::flush().await;
+                    std::process::exit(1);
+                },
+                hook::Outcome::Skipped(reason) => {
+                    eprintln!("pprog: did not fully check the staged changes, {}", reason);
+                    if config.hook.strict {
+                        telemetry::flush().await;
+                        std::process::exit(1);
+                    }
+                },
+            }
+        }
+        Some(Commands::Snippets { command: SnippetCommands::Add { name, text } }) => {
+            let text = match text {
+                Some(text) => Some(text.clone()),
+                None => match oneshot::read_piped_stdin()? {
+                    Some(text) => Some(text),
+                    None => repl::compose::compose("")?,
+                },
+            };
+            let Some(text) = text else {
+                return Err("Nothing saved, the snippet is empty".into());
+            };
+            let path = Snippet::save(name, &text)?;
+            println!("Saved {}", path.display());
+        }
+        Some(Commands::Snippets { command: SnippetCommands::List }) => {
+            let listing: String = Snippet::list()?.iter()
+                .map(|snippet| format!("{}  {}\n", snippet.name, snippet.text.lines().next().unwrap_or_default()))
+                .collect();
+            pager::page(&listing, pager_enabled())?;
+        }
+        Some(Commands::Snippets { command: SnippetCommands::Use { name, values } }) => {
+            let values = snippets::parse_values(values.iter().map(String::as_str))?;
+            println!("{}", Snippet::load(name)?.expand(&values)?);
+        }
+        Some(Commands::Cost { command: Some(CostCommands::Export { format, since, output }), .. }) => {
+            let entries = store::Store::open()?.usage_since(since.map(|since| chrono::Utc::now() - since))?;
+            let exported = cost::export(&entries, *format);
+            match output {
+                Some(path) => std::fs::write(path, exported)?,
+                None => print!("{}", exported),
+            }
+        }
+        Some(Commands::Cost { command: None, since, by }) => {
+            let store = store::Store::open()?;
+            let entries = store.usage_since(since.map(|since| chrono::Utc::now() - since))?;
+            let titles = match by {
+                cost::GroupBy::Session => store.list_sessions()?.into_iter()
+                    .filter_map(|session| Some((session.id, session.title?)))
+                    .collect(),
+                _ => Default::default(),
+            };
+            match entries.is_empty() {
+                true => println!("No requests recorded."),
+                false => print!("{}", cost::report(&entries, *by, &titles)),
+            }
+        }
+        Some(Commands::Audit { command: AuditCommands::Verify { path } }) => {
+            let path = match path {
+                Some(path) => path.clone(),
+                None => audit::project_log()?,
+            };
+            match audit::verify(&path, store::Store::open()?.cipher())? {
+                audit::Verification::Intact { entries } => println!("{} entries, the chain is intact.", entries),
+                audit::Verification::Broken { line, reason } => {
+                    eprintln!("Line {} of {}: {}", line, path.display(), reason);
+                    std::process::exit(1);
+                },
+            }
+        }
+        Some(Commands::Debug { command: DebugCommands::Last { output } }) => {
+            let path = wire::dump_last(output.as_deref())?;
+            println!("Wrote {}", path.display());
+        }
+        Some(Commands::Completions { shell }) => {
+            print!("{}", completions::generate(*shell, Cli::command()));
+        }
+        None if cli.print.is_some() => {
+            let prompt = cli.print.as_deref().unwrap_or_default();
+            let stdin = oneshot::read_piped_stdin()?;
+            let _workspace = lock::Lock::workspace()?;
+            let mut chat = chat::Chat::for_persona(cli.persona.as_deref()).await?;
+            if !cli.stop.is_empty() {
+                chat.set_stop_sequences(cli.stop.clone()).await?;
+            }
+            chat.set_prefill(cli.prefill.clone());
+            let budget = agent::Budget { max_cost: cli.max_cost, max_tokens: cli.max_tokens, ..Default::default() };
+            let options = oneshot::Options { format: cli.output, copy: cli.copy, verbose: cli.verbose, quiet: cli.quiet, approve_each: cli.approve_each, budget, review_model: cli.review_model.clone(), n: cli.n, judge_model: cli.judge_model.clone(), dry_run: cli.dry_run, report: cli.report.clone() };
+            // Nothing is spent on a dry run
+            let checked = match cli.dry_run {
+                true => Ok(()),
+                false => check_soft_limits(cli.force),
+            };
+            let result = match checked {
+                Ok(()) => oneshot::run(chat, prompt, stdin, options).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                eprintln!("Error: {}", e);
+                telemetry::flush().await;
+                std::process::exit(oneshot::exit_code(&e));
+            }
+        }
+        None if cli.jsonrpc => {
+            let _workspace = lock::Lock::workspace()?;
+            check_soft_limits(cli.force)?;
+            jsonrpc::run(cli.persona.as_deref(), &cli.stop).await?;
+        }
+        None if std::io::stdin().is_terminal() && std::io::stdout().is_terminal() => {
+            let options = repl::Options { raw: false, editor: false, verbose: cli.verbose, plan: false, approve_each: cli.approve_each, force: cli.force };
+            run_repl(None, None, cli.persona.as_deref(), &cli.stop, false, options).await?;
         }
         None => {
             let mut cmd = Cli::command();
@@ -108,5 +956,15 @@ pprog.toml
         }
     }
 
+    telemetry::flush().await;
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+}
@@ -0,0 +1,141 @@
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::config::WebhookConfig;
+
+// A webhook that doesn't answer in this long is given up on, the run is done either way
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Shows a desktop notification through notify-send on Linux and osascript on macOS.
+pub fn send(title: &str, body: &str) -> Result<(), anyhow::Error> {
+    let mut command = if cfg!(target_os = "macos") {
+        let script = format!(
+            "display notification {} with title {}",
+            applescript_string(body),
+            applescript_string(title),
+        );
+        let mut command = Command::new("osascript");
+        command.arg("-e").arg(script);
+        command
+    } else {
+        let mut command = Command::new("notify-send");
+        command.arg("--app-name=pprog").arg(title).arg(body);
+        command
+    };
+    let status = command.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null()).status()
+        .map_err(|e| anyhow::anyhow!("Could not run {:?}: {}", command.get_program(), e))?;
+    if !status.success() {
+        return Err(anyhow::anyhow!("{:?} exited with {}", command.get_program(), status));
+    }
+    Ok(())
+}
+
+fn applescript_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn format_duration(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        _ => format!("{}m {}s", secs / 60, secs % 60),
+    }
+}
+
+/*
+    * Tells the user a turn is done once it ran for `after_secs` or longer, 0 turns this off.
+    * `focused` is whether the terminal has focus when that is known; the REPL doesn't track
+    * focus while a turn runs, so there it is None and the notification is always sent.
+*/
+pub fn turn_finished(after_secs: u64, elapsed: Duration, focused: Option<bool>, error: Option<&str>, cost: f64) {
+    if after_secs == 0 || elapsed < Duration::from_secs(after_secs) || focused == Some(true) {
+        return;
+    }
+    let (title, summary) = match error {
+        Some(error) => ("pprog run failed", error.lines().next().unwrap_or_default().to_string()),
+        None => ("pprog run finished", "Done".to_string()),
+    };
+    let body = format!("{} after {}, ${:.4}", summary, format_duration(elapsed), cost);
+    if let Err(e) = send(title, &body) {
+        log::warn!("Could not send notification: {}", e);
+    }
+}
+
+/// How an unattended run went, for `run_finished`.
+pub struct RunSummary<'a> {
+    /// The prompt or what the run was started for
+    pub task: &'a str,
+    pub session_id: &'a str,
+    pub error: Option<String>,
+    pub cost: f64,
+    pub elapsed: Duration,
+}
+
+fn webhook_payload(config: &WebhookConfig, summary: &RunSummary) -> Value {
+    let status = match summary.error {
+        Some(_) => "failed",
+        None => "succeeded",
+    };
+    let mut task = summary.task.lines().find(|line| !line.trim().is_empty()).unwrap_or_default().trim().to_string();
+    if task.chars().count() > 100 {
+        task = format!("{}...", task.chars().take(100).collect::<String>());
+    }
+    let transcript = Some(config.transcript_url.replace("{session}", summary.session_id)).filter(|url| !url.is_empty());
+    let mut text = format!("pprog run {}: {}\n{}, ${:.4}", status, task, format_duration(summary.elapsed), summary.cost);
+    if let Some(error) = &summary.error {
+        text.push_str(&format!("\n{}", error.lines().next().unwrap_or_default()));
+    }
+    if let Some(url) = &transcript {
+        text.push_str(&format!("\n<{}|Transcript>", url));
+    }
+    json!({
+        "text": text,
+        "status": status,
+        "task": task,
+        "error": summary.error,
+        "cost_usd": summary.cost,
+        "duration_secs": summary.elapsed.as_secs_f64(),
+        "session_id": summary.session_id,
+        "transcript_url": transcript,
+    })
+}
+
+/// POSTs the summary of a finished run to the configured webhook, if there is one.
+pub async fn run_finished(config: &WebhookConfig, summary: &RunSummary<'_>) {
+    if config.url.is_empty() || (config.only_failures && summary.error.is_none()) {
+        return;
+    }
+    let request = reqwest::Client::new().post(&config.url).timeout(WEBHOOK_TIMEOUT).json(&webhook_payload(config, summary));
+    match request.send().await.and_then(|response| response.error_for_status()) {
+        Ok(_) => {},
+        Err(e) => log::warn!("Could not post the run to the webhook: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(42)), "42s");
+        assert_eq!(format_duration(Duration::from_secs(605)), "10m 5s");
+        assert_eq!(applescript_string("say \"hi\""), "\"say \\\"hi\\\"\"");
+    }
+
+    #[test]
+    fn test_webhook_payload() {
+        let config = WebhookConfig { url: "https://hooks.slack.com/x".to_string(), transcript_url: "https://ci.example.com/{session}.html".to_string(), only_failures: false };
+        let mut summary = RunSummary { task: "\nFix the flaky test\nin ci", session_id: "abc", error: None, cost: 0.0123, elapsed: Duration::from_secs(75) };
+        let payload = webhook_payload(&config, &summary);
+        assert_eq!(payload["text"], "pprog run succeeded: Fix the flaky test\n1m 15s, $0.0123\n<https://ci.example.com/abc.html|Transcript>");
+        assert_eq!((payload["status"].as_str(), payload["transcript_url"].as_str()), (Some("succeeded"), Some("https://ci.example.com/abc.html")));
+
+        summary.error = Some("Stopped, the agent looks stuck\nmore".to_string());
+        let payload = webhook_payload(&WebhookConfig::default(), &summary);
+        assert_eq!(payload["text"], "pprog run failed: Fix the flaky test\n1m 15s, $0.0123\nStopped, the agent looks stuck");
+        assert!(payload["transcript_url"].is_null());
+    }
+}
@@ -0,0 +1,545 @@
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use serde_json::json;
+
+use crate::bestof;
+use crate::agent::{self, AgentEvent, ApprovalRequest, Budget, BudgetExceeded, Controls, Decision, Denied, KeyRequest, RoundLimit, SecretRequest, ToolFailed};
+use crate::loops::Stuck;
+use crate::chat::Chat;
+use crate::clipboard;
+use crate::cost::SoftLimitReached;
+use crate::inference::stream::StreamEvent;
+use crate::inference::types::{self, ContentItem, InferenceError, Message, StopReason, Usage};
+use crate::mentions;
+use crate::models;
+use crate::notify;
+use crate::repl;
+use crate::review::{self, Verdict};
+use crate::secrets::SecretsBlocked;
+use crate::store::Store;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// The answer as plain text
+    Text,
+    /// One JSON object with the answer, tool calls and usage once the run is done
+    Json,
+    /// A JSON object per line for every event as it happens, ending with the result
+    StreamJson,
+}
+
+/// Exit statuses of a failed run, so scripts and CI jobs can branch on the outcome.
+pub const EXIT_ERROR: i32 = 1;
+pub const EXIT_GAVE_UP: i32 = 2;
+pub const EXIT_BUDGET: i32 = 3;
+pub const EXIT_DENIED: i32 = 4;
+pub const EXIT_PROVIDER: i32 = 5;
+
+/// The exit status for an error from `run`.
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    if error.is::<RoundLimit>() || error.is::<Stuck>() || error.is::<ToolFailed>() {
+        EXIT_GAVE_UP
+    } else if error.is::<BudgetExceeded>() || error.is::<SoftLimitReached>() {
+        EXIT_BUDGET
+    } else if error.is::<Denied>() || error.is::<SecretsBlocked>() {
+        EXIT_DENIED
+    } else if error.chain().any(|cause| cause.is::<InferenceError>()) {
+        EXIT_PROVIDER
+    } else {
+        EXIT_ERROR
+    }
+}
+
+/// How a run reports, from the top-level flags.
+pub struct Options {
+    pub format: OutputFormat,
+    /// Copy the last code block of the answer
+    pub copy: bool,
+    /// Print the usage footer on stderr
+    pub verbose: bool,
+    /// Print nothing but errors, the exit status tells how the run went
+    pub quiet: bool,
+    /// Ask on the terminal before every tool call
+    pub approve_each: bool,
+    /// Stop the run once it has spent this much
+    pub budget: Budget,
+    /// Have this model review the answer and send it back once for changes
+    pub review_model: Option<String>,
+    /// Candidate answers to ask for at once, 1 for a normal run
+    pub n: usize,
+    /// Has this model pick the best of the `n` candidates instead of asking on the terminal
+    pub judge_model: Option<String>,
+    /// Print what the request would take instead of sending it
+    pub dry_run: bool,
+    /// Write the review to these files for CI, in the format each name calls for
+    pub report: Vec<PathBuf>,
+}
+
+/// Parses a token count like `500k` or `2m`.
+pub fn parse_tokens(value: &str) -> Result<u64, String> {
+    let value = value.trim().to_lowercase();
+    let (digits, scale) = match value.strip_suffix('k') {
+        Some(digits) => (digits, 1_000.0),
+        None => match value.strip_suffix('m') {
+            Some(digits) => (digits, 1_000_000.0),
+            None => (value.as_str(), 1.0),
+        },
+    };
+    match digits.trim().parse::<f64>() {
+        Ok(count) if count > 0.0 => Ok((count * scale) as u64),
+        _ => Err(format!("{} is not a token count like 200000 or 500k", value)),
+    }
+}
+
+// Piped input beyond this is cut, a diff or log that large wouldn't fit the context anyway
+const MAX_STDIN_CHARS: usize = 200_000;
+
+/// Reads everything piped to stdin, None when stdin is a terminal or nothing was piped.
+pub fn read_piped_stdin() -> Result<Option<String>, anyhow::Error> {
+    if io::stdin().is_terminal() {
+        return Ok(None);
+    }
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input)?;
+    Ok(Some(input).filter(|input| !input.trim().is_empty()))
+}
+
+fn stdin_attachment(input: &str) -> String {
+    let mut cut = input.len().min(MAX_STDIN_CHARS);
+    while !input.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    let mut text = format!("stdin\n```\n{}\n```", input[..cut].trim_end());
+    if cut < input.len() {
+        text.push_str(&format!("\n[stdin cut off after {} of {} bytes]", cut, input.len()));
+    }
+    text
+}
+
+fn text_of(message: &Message) -> String {
+    message.content.iter()
+        .filter_map(|item| match item {
+            ContentItem::Text { text } => Some(text.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+fn emit(value: serde_json::Value) {
+    let mut stdout = io::stdout();
+    let _ = writeln!(stdout, "{}", value);
+    let _ = stdout.flush();
+}
+
+// What a run did, collected from agent events for the result object
+#[derive(Default)]
+struct RunLog {
+    tool_calls: Vec<serde_json::Value>,
+    usage: Usage,
+    stop_reason: Option<StopReason>,
+    stop_sequence: Option<String>,
+    first_token: Option<Instant>,
+    generating: Duration,
+}
+
+impl RunLog {
+    // What a run cut short got done, for picking it up by hand
+    fn progress(&self, session_id: &str) -> String {
+        let mut written = self.tool_calls.iter()
+            .filter(|call| call["name"] == "write_file")
+            .filter_map(|call| call["input"]["path"].as_str())
+            .collect::<Vec<&str>>();
+        written.dedup();
+        let mut progress = format!("Made {} tool call(s) before stopping", self.tool_calls.len());
+        if !written.is_empty() {
+            progress.push_str(&format!(", wrote {}", written.join(", ")));
+        }
+        format!("{}.  Continue with `pprog chat --session {}`", progress, session_id)
+    }
+
+    fn record(&mut self, event: AgentEvent, stream: bool) {
+        if let AgentEvent::Stream(StreamEvent::Text(_) | StreamEvent::ToolInput(_)) = event {
+            self.first_token.get_or_insert_with(Instant::now);
+        }
+        match event {
+            AgentEvent::Stream(StreamEvent::Text(text)) if stream => emit(json!({"type": "text", "text": text})),
+            AgentEvent::Stream(StreamEvent::Done { stop_reason, stop_sequence, usage, timing }) => {
+                if let Some(usage) = usage {
+                    self.usage.add(usage);
+                }
+                self.stop_reason = Some(stop_reason.clone());
+                self.stop_sequence = stop_sequence.clone();
+                self.generating += timing.generating();
+                if stream {
+                    emit(json!({
+                        "type": "response",
+                        "stop_reason": stop_reason,
+                        "stop_sequence": stop_sequence,
+                        "usage": usage,
+                        "duration_ms": timing.total.as_millis() as u64,
+                        "first_token_ms": timing.first_token.map(|first| first.as_millis() as u64),
+                    }));
+                }
+            },
+            AgentEvent::Stream(_) => {},
+            AgentEvent::ToolStart { id, name, input } => {
+                if stream {
+                    emit(json!({"type": "tool_use", "id": id, "name": name, "input": input}));
+                }
+                self.tool_calls.push(json!({"id": id, "name": name, "input": input}));
+            },
+            AgentEvent::ToolDone { id, name, output, .. } => {
+                if stream {
+                    emit(json!({"type": "tool_result", "tool_use_id": id, "name": name, "content": output}));
+                }
+                if let Some(call) = self.tool_calls.iter_mut().rev().find(|call| call["id"] == id) {
+                    call["output"] = json!(output);
+                }
+            },
+            AgentEvent::Steer { .. } => {},
+        }
+    }
+}
+
+fn copy_code_block(answer: &str, quiet: bool) {
+    match clipboard::last_code_block(answer) {
+        Some(block) => match clipboard::copy(&block) {
+            Ok(target) if !quiet => eprintln!("Copied the last code block to the {}", target),
+            Ok(_) => {},
+            Err(e) => eprintln!("Could not copy: {}", e),
+        },
+        None if !quiet => eprintln!("Nothing copied, the answer has no code block"),
+        None => {},
+    }
+}
+
+/*
+    * Asks for `n` answers at once and keeps the best, picked by the judge model or by hand
+    * on the terminal.  Candidates are single replies without tools, since several agents
+    * writing the same files would clash.  Only the kept answer is added to the session.
+*/
+async fn best_of(mut chat: Chat, message: Message, options: &Options) -> Result<(), anyhow::Error> {
+    if options.judge_model.is_none() && !bestof::can_pick() {
+        anyhow::bail!("No terminal to pick the best of {} candidates on, give a --judge-model", options.n);
+    }
+    let started = Instant::now();
+    let cost_before = chat.session_cost().unwrap_or_default();
+    let candidates = bestof::candidates(&chat, &message, options.n).await?;
+    let (chosen, reason) = match &options.judge_model {
+        Some(model) => bestof::judge(&chat, model, &text_of(&message), &candidates).await?,
+        None => {
+            let shown = candidates.clone();
+            (tokio::task::spawn_blocking(move || bestof::pick_on_tty(&shown)).await??, String::new())
+        },
+    };
+    let answer = candidates[chosen].clone();
+    chat.record_exchange(message, answer.clone());
+
+    match options.format {
+        _ if options.quiet => {},
+        OutputFormat::Text => {
+            if let Some(model) = &options.judge_model {
+                eprintln!("{} picked #{} of {}: {}", model, chosen + 1, candidates.len(), reason);
+            }
+            let mut stdout = io::stdout();
+            writeln!(stdout, "{}", answer.trim_end())?;
+            stdout.flush()?;
+        },
+        OutputFormat::Json | OutputFormat::StreamJson => emit(json!({
+            "type": "result",
+            "session_id": chat.session.id,
+            "text": answer,
+            "candidates": candidates,
+            "chosen": chosen + 1,
+            "judge": options.judge_model.as_ref().map(|model| json!({"model": model, "reason": reason})),
+        })),
+    }
+    if options.verbose {
+        let cost = chat.session_cost().unwrap_or_default() - cost_before;
+        eprintln!("{} candidates · ${:.4} · {:.1}s", candidates.len(), cost, started.elapsed().as_secs_f32());
+    }
+    if options.copy {
+        copy_code_block(&answer, options.quiet);
+    }
+    Ok(())
+}
+
+// The size and input cost of the request for `message`, counted locally with nothing sent
+fn dry_run(chat: &Chat, message: &Message, format: OutputFormat) -> Result<(), anyhow::Error> {
+    let estimate = chat.estimate_request(message)?;
+    let model = &chat.config().model;
+    let input = Usage { input_tokens: estimate.input() as u32, ..Default::default() };
+    let cost = models::lookup(model).map(|info| info.cost(&input));
+    let fill = estimate.input() as f64 / estimate.window.max(1) as f64 * 100.0;
+    match format {
+        OutputFormat::Text => {
+            println!("Model     {}", model);
+            println!("System    {} tokens", estimate.system);
+            println!("Messages  {} tokens", estimate.messages);
+            println!("Input     {} of {} tokens ({:.1}%), {} kept for output", estimate.input(), estimate.window, fill, estimate.reserved_output);
+            match cost {
+                Some(cost) => println!("Cost      ${:.4} for the input", cost),
+                None => println!("Cost      unknown, no prices for {}", model),
+            }
+            println!("Counted   {}, nothing was sent", estimate.method);
+        },
+        OutputFormat::Json | OutputFormat::StreamJson => emit(json!({
+            "type": "dry_run",
+            "model": model,
+            "tokens": estimate,
+            "input_tokens": estimate.input(),
+            "input_cost": cost,
+        })),
+    }
+    Ok(())
+}
+
+/*
+    * Answers one prompt without any interaction, e.g. `git diff | pprog -p "review this"`.
+    * Piped stdin is attached to the prompt, tools run as in the REPL up to a bound, and only
+    * the final answer is written to stdout so the output can be piped on.  The json formats
+    * report the run as structured events instead, for programs driving pprog.  How it went
+    * is posted to the webhook, when one is configured.
+*/
+pub async fn run(chat: Chat, prompt: &str, stdin: Option<String>, options: Options) -> Result<(), anyhow::Error> {
+    let webhook = chat.config().webhook.clone();
+    if webhook.url.is_empty() || options.dry_run {
+        return run_prompt(chat, prompt, stdin, options).await;
+    }
+    let session_id = chat.session.id.clone();
+    let cost_before = chat.session_cost().unwrap_or_default();
+    let started = Instant::now();
+    let result = run_prompt(chat, prompt, stdin, options).await;
+    let cost = Store::open().and_then(|store| store.session_cost(&session_id)).unwrap_or_default() - cost_before;
+    let summary = notify::RunSummary {
+        task: prompt,
+        session_id: &session_id,
+        error: result.as_ref().err().map(|e| e.to_string()),
+        cost,
+        elapsed: started.elapsed(),
+    };
+    notify::run_finished(&webhook, &summary).await;
+    result
+}
+
+async fn run_prompt(mut chat: Chat, prompt: &str, stdin: Option<String>, options: Options) -> Result<(), anyhow::Error> {
+    let format = options.format;
+    let mut message = mentions::user_message(prompt);
+    if let Some(input) = stdin {
+        message.content.push(ContentItem::Text { text: stdin_attachment(&input) });
+    }
+
+    if options.dry_run {
+        return dry_run(&chat, &message, format);
+    }
+    if options.n > 1 {
+        return best_of(chat, message, &options).await;
+    }
+
+    // Nothing is shown while the turn runs, Ctrl-C still stops the process as usual
+    let (_source, mut cancel) = agent::cancel_pair();
+    let mut log = RunLog::default();
+    let stream = format == OutputFormat::StreamJson && !options.quiet;
+    let started = Instant::now();
+    let cost_before = chat.session_cost().unwrap_or_default();
+    if options.budget.max_cost.is_some() && models::lookup(&chat.config().model).is_none() {
+        anyhow::bail!("No prices are known for {}, so --max-cost can't be enforced", chat.config().model);
+    }
+    let approvals = options.approve_each.then(|| {
+        let (approvals, mut requests) = agent::approval_channel();
+        // Asked on the terminal, stdin and stdout are the prompt and the answer
+        tokio::spawn(async move {
+            while let Some(ApprovalRequest { name, input, reason, reply }) = requests.recv().await {
+                let decision = tokio::task::spawn_blocking(move || repl::approve::ask_on_tty(&name, &input, reason.as_deref())).await;
+                let _ = reply.send(decision.unwrap_or(Decision::Deny(String::new())));
+            }
+        });
+        approvals
+    });
+    // Secrets are asked about on the terminal too, or else masked or blocked as configured
+    let secrets = options.approve_each.then(|| {
+        let (secrets, mut requests) = agent::secret_channel();
+        tokio::spawn(async move {
+            while let Some(SecretRequest { found, default, reply }) = requests.recv().await {
+                let action = tokio::task::spawn_blocking(move || repl::approve::ask_secrets_on_tty(&found, default)).await;
+                let _ = reply.send(action.unwrap_or(default));
+            }
+        });
+        secrets
+    });
+    // A rejected API key nothing in [auth] replaces is asked for on the terminal as well
+    let keys = options.approve_each.then(|| {
+        let (keys, mut requests) = agent::key_channel();
+        tokio::spawn(async move {
+            while let Some(KeyRequest { provider, failure, reply }) = requests.recv().await {
+                let key = tokio::task::spawn_blocking(move || repl::approve::ask_key_on_tty(&provider, failure)).await;
+                let _ = reply.send(key.unwrap_or_default());
+            }
+        });
+        keys
+    });
+    let request = text_of(&message);
+    let mut controls = Controls { approvals, steering: None, budget: Some(options.budget), secrets, keys };
+    let mut result = agent::run_turn(&mut chat, message, &mut |event| log.record(event, stream), &mut controls, &mut cancel).await;
+    let mut verdict = None;
+    if let (Some(model), Ok(outcome)) = (&options.review_model, &result) {
+        let reviewing = Instant::now();
+        let reviewed = review::review(&chat, model, &request, &text_of(&outcome.reply)).await;
+        if let Ok(verdict) = &reviewed {
+            let report = review::ci_report(model, verdict, reviewing.elapsed());
+            for path in &options.report {
+                report.write(path)?;
+            }
+        }
+        match reviewed {
+            Ok(Verdict::Approved) => {
+                if stream {
+                    emit(json!({"type": "review", "model": model, "approved": true}));
+                }
+                verdict = Some(Verdict::Approved);
+            },
+            Ok(Verdict::Revise(critique)) => {
+                if format == OutputFormat::Text && !options.quiet {
+                    eprintln!("{} asked for changes, revising:\n{}", model, critique);
+                }
+                if stream {
+                    emit(json!({"type": "review", "model": model, "approved": false, "critique": critique}));
+                }
+                if let Some(budget) = controls.budget.as_mut() {
+                    budget.spent = log.usage.clone();
+                }
+                result = agent::run_turn(&mut chat, review::revision_message(&critique), &mut |event| log.record(event, stream), &mut controls, &mut cancel).await;
+                verdict = Some(Verdict::Revise(critique));
+            },
+            Err(e) => result = Err(e),
+        }
+    }
+    let footer = options.verbose.then(|| {
+        let cost = chat.session_cost().unwrap_or_default() - cost_before;
+        let first_token = log.first_token.map(|first| first.duration_since(started));
+        let rate = types::output_rate(log.usage.output_tokens, log.generating);
+        repl::usage_footer(&log.usage, cost, started.elapsed(), first_token, rate, log.stop_reason.as_ref().map(StopReason::as_str))
+    });
+    let outcome = match result {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            if let Some(footer) = footer {
+                eprintln!("{}", footer);
+            }
+            if format != OutputFormat::Text && !options.quiet {
+                emit(json!({"type": "error", "session_id": chat.session.id, "message": e.to_string()}));
+            }
+            if e.is::<BudgetExceeded>() && !options.quiet {
+                eprintln!("{}", log.progress(&chat.session.id));
+            }
+            return Err(e);
+        },
+    };
+
+    let answer = text_of(&outcome.reply);
+
+    match format {
+        _ if options.quiet => {},
+        OutputFormat::Text => {
+            let mut stdout = io::stdout();
+            writeln!(stdout, "{}", answer.trim_end())?;
+            stdout.flush()?;
+        },
+        OutputFormat::Json | OutputFormat::StreamJson => {
+            let mut result = json!({
+                "type": "result",
+                "session_id": chat.session.id,
+                "text": answer,
+                "stop_reason": log.stop_reason,
+                "stop_sequence": log.stop_sequence,
+                "incomplete": outcome.incomplete,
+                "usage": log.usage,
+            });
+            if format == OutputFormat::Json {
+                result["tool_calls"] = json!(log.tool_calls);
+            }
+            if let (Some(model), Some(verdict)) = (&options.review_model, &verdict) {
+                result["review"] = match verdict {
+                    Verdict::Approved => json!({"model": model, "approved": true}),
+                    Verdict::Revise(critique) => json!({"model": model, "approved": false, "critique": critique}),
+                };
+            }
+            emit(result);
+        },
+    }
+
+    // Reported on stderr so stdout stays just the answer
+    if let Some(footer) = footer {
+        eprintln!("{}", footer);
+    }
+    if options.copy {
+        copy_code_block(&answer, options.quiet);
+    }
+
+    let denied = controls.approvals.map_or(0, |approvals| approvals.denied());
+    if denied > 0 {
+        return Err(Denied { calls: denied }.into());
+    }
+    if outcome.incomplete {
+        // Only a stream that broke off leaves an answer cut off here
+        return Err(InferenceError::NetworkError(format!(
+            "The answer was cut off, continue it with `pprog chat --session {}`",
+            chat.session.id,
+        )).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stdin_attachment_is_cut() {
+        assert_eq!(stdin_attachment("diff --git a b\n"), "stdin\n```\ndiff --git a b\n```");
+        let long = "é".repeat(MAX_STDIN_CHARS);
+        assert!(stdin_attachment(&long).ends_with(&format!("[stdin cut off after {} of {} bytes]", MAX_STDIN_CHARS, long.len())));
+    }
+
+    #[test]
+    fn test_run_log_collects_tool_calls_and_usage() {
+        let mut log = RunLog::default();
+        let input = json!({"path": "a.rs"});
+        let usage = Usage { input_tokens: 10, output_tokens: 2, ..Default::default() };
+        log.record(AgentEvent::ToolStart { id: "t1", name: "read_file", input: &input }, false);
+        log.record(AgentEvent::ToolDone { id: "t1", name: "read_file", output: "fn main() {}", is_error: false }, false);
+        for _ in 0..2 {
+            let done = StreamEvent::Done { stop_reason: StopReason::EndTurn, stop_sequence: None, usage: Some(usage.clone()), timing: Default::default() };
+            log.record(AgentEvent::Stream(&done), false);
+        }
+        assert_eq!(log.tool_calls, vec![json!({"id": "t1", "name": "read_file", "input": input, "output": "fn main() {}"})]);
+        assert_eq!((log.usage.input_tokens, log.usage.output_tokens), (20, 4));
+        assert_eq!(log.stop_reason, Some(StopReason::EndTurn));
+    }
+
+    #[test]
+    fn test_parse_tokens() {
+        assert_eq!(parse_tokens("500k"), Ok(500_000));
+        assert_eq!(parse_tokens("1.5M"), Ok(1_500_000));
+        assert_eq!(parse_tokens("20000"), Ok(20_000));
+        assert!(parse_tokens("lots").is_err());
+    }
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(exit_code(&RoundLimit { rounds: 25 }.into()), EXIT_GAVE_UP);
+        assert_eq!(exit_code(&Stuck { reason: String::new() }.into()), EXIT_GAVE_UP);
+        let failed = ToolFailed { tool: "execute".to_string(), attempts: 4, error: String::new() };
+        assert_eq!(exit_code(&failed.into()), EXIT_GAVE_UP);
+        assert_eq!(exit_code(&Denied { calls: 1 }.into()), EXIT_DENIED);
+        let budget = BudgetExceeded { spent: "$2.10".to_string(), limit: "$2.00".to_string(), next: None };
+        assert_eq!(exit_code(&budget.into()), EXIT_BUDGET);
+        let provider = anyhow::Error::new(InferenceError::MissingApiKey("no key".to_string())).context("Could not send");
+        assert_eq!(exit_code(&provider), EXIT_PROVIDER);
+        assert_eq!(exit_code(&anyhow::anyhow!("Workspace is busy")), EXIT_ERROR);
+    }
+}
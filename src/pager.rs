@@ -0,0 +1,39 @@
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+/*
+    * Prints `text`, through $PAGER (less by default) when stdout is a terminal and the text
+    * is taller than it.  less gets `-FRX` unless LESS is set, so colors pass through and the
+    * text stays on screen after quitting.  `enabled` is the `pager` config setting.
+*/
+pub fn page(text: &str, enabled: bool) -> Result<(), anyhow::Error> {
+    let fits = crossterm::terminal::size().is_ok_and(|(_, rows)| text.lines().count() < rows as usize);
+    if !enabled || fits || !io::stdout().is_terminal() {
+        print!("{}", text);
+        return Ok(());
+    }
+
+    let pager = std::env::var("PAGER").ok().filter(|pager| !pager.trim().is_empty()).unwrap_or_else(|| "less".to_string());
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(&pager).stdin(Stdio::piped());
+    if std::env::var_os("LESS").is_none() {
+        command.env("LESS", "FRX");
+    }
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            log::warn!("Could not start pager {:?}: {}", pager, e);
+            print!("{}", text);
+            return Ok(());
+        },
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager closing early, e.g. on q, is a broken pipe and not an error
+        match stdin.write_all(text.as_bytes()) {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e.into()),
+            _ => {},
+        }
+    }
+    child.wait()?;
+    Ok(())
+}
@@ -0,0 +1,351 @@
+use actix_web::{http, web, HttpRequest, HttpResponse};
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use crate::chat::Chat;
+use crate::config::ProjectConfig;
+use crate::daemon::Daemon;
+use crate::images;
+use crate::inference::stream::StreamEvent;
+use crate::inference::types::{ContentItem, InferenceError, Message, ModelResponse, Role, StopReason, Usage};
+
+// The model name that stands for the configured one
+const DEFAULT_MODEL: &str = "pprog";
+
+/// `stop` is a single string or a list of them.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Stop {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct CompletionRequest {
+    #[serde(default)]
+    model: String,
+    messages: Vec<Value>,
+    #[serde(default)]
+    stream: bool,
+    #[serde(default)]
+    stream_options: Option<Value>,
+    #[serde(default)]
+    max_tokens: Option<u32>,
+    #[serde(default)]
+    max_completion_tokens: Option<u32>,
+    #[serde(default)]
+    temperature: Option<f32>,
+    #[serde(default)]
+    seed: Option<u64>,
+    #[serde(default)]
+    stop: Option<Stop>,
+    #[serde(default)]
+    tools: Option<Value>,
+}
+
+// An error in the shape OpenAI clients read
+fn openai_error(status: http::StatusCode, kind: &str, message: &str) -> HttpResponse {
+    HttpResponse::build(status).json(json!({"error": {"message": message, "type": kind, "code": Value::Null}}))
+}
+
+fn request_error(message: &str) -> HttpResponse {
+    openai_error(http::StatusCode::BAD_REQUEST, "invalid_request_error", message)
+}
+
+fn provider_error(e: anyhow::Error) -> HttpResponse {
+    let message = format!("{:#}", e);
+    match e.downcast_ref::<InferenceError>() {
+        Some(InferenceError::ApiError(status, _)) => openai_error(*status, "api_error", &message),
+        Some(InferenceError::NetworkError(_)) => openai_error(http::StatusCode::BAD_GATEWAY, "api_error", &message),
+        _ => openai_error(http::StatusCode::INTERNAL_SERVER_ERROR, "server_error", &message),
+    }
+}
+
+// The content of an OpenAI message, a string or a list of text and image parts
+fn content_items(content: &Value) -> Result<Vec<ContentItem>, String> {
+    let parts = match content {
+        Value::String(text) => return Ok(vec![ContentItem::Text { text: text.clone() }]),
+        Value::Null => return Ok(Vec::new()),
+        Value::Array(parts) => parts,
+        _ => return Err("Message content must be a string or a list of parts".to_string()),
+    };
+    parts.iter()
+        .map(|part| match part["type"].as_str() {
+            Some("text") => Ok(ContentItem::Text { text: part["text"].as_str().unwrap_or_default().to_string() }),
+            Some("image_url") => {
+                let url = part["image_url"]["url"].as_str().unwrap_or_default();
+                // Images are sent to the provider inline, so only data URLs can be taken
+                let data = url.strip_prefix("data:")
+                    .and_then(|url| url.split_once(";base64,"))
+                    .ok_or_else(|| "Only images given as base64 data URLs are supported".to_string())?
+                    .1;
+                let bytes = base64::engine::general_purpose::STANDARD.decode(data).map_err(|e| format!("Invalid image data: {}", e))?;
+                images::from_bytes(&bytes).map_err(|e| e.to_string())
+            },
+            kind => Err(format!("Unsupported content part {}", kind.unwrap_or("without a type"))),
+        })
+        .collect()
+}
+
+/// The system prompt and messages of a chat completion request, in the form the providers take.
+fn request_messages(messages: &[Value]) -> Result<(String, Vec<Message>), String> {
+    let mut system = Vec::new();
+    let mut converted: Vec<Message> = Vec::new();
+    for message in messages {
+        let role = match message["role"].as_str() {
+            Some("system" | "developer") => {
+                let text = content_items(&message["content"])?.into_iter()
+                    .filter_map(|item| match item {
+                        ContentItem::Text { text } => Some(text),
+                        _ => None,
+                    })
+                    .collect::<Vec<String>>();
+                system.push(text.join("\n"));
+                continue;
+            },
+            Some("user") => Role::User,
+            Some("assistant") if message.get("tool_calls").is_none_or(Value::is_null) => Role::Assistant,
+            Some("assistant" | "tool" | "function") => return Err("Tool calls are not supported, the proxy sends no tools".to_string()),
+            role => return Err(format!("Unsupported role {}", role.unwrap_or("missing"))),
+        };
+        let content = content_items(&message["content"])?;
+        // Turns of the same role in a row are one turn to the providers that need them to alternate
+        match converted.last_mut() {
+            Some(last) if last.role == role => last.content.extend(content),
+            _ => converted.push(Message { role, content }),
+        }
+    }
+    if converted.is_empty() {
+        return Err("No user or assistant messages".to_string());
+    }
+    Ok((system.join("\n\n"), converted))
+}
+
+/*
+    * The config a request is sent with: `model` names a persona, the configured model when it
+    * is empty or `pprog`, or else another model of the configured provider.  Sampling and
+    * limits come from the request where it sets them, and the agent's tools are left out, the
+    * client is the one having the conversation.
+*/
+fn request_config(base: &ProjectConfig, request: &CompletionRequest) -> Result<ProjectConfig, anyhow::Error> {
+    let mut config = match request.model.as_str() {
+        "" | DEFAULT_MODEL => base.clone(),
+        name if base.personas.contains_key(name) => base.with_persona(name)?,
+        model => ProjectConfig { model: model.to_string(), ..base.clone() },
+    };
+    if let Some(max_tokens) = request.max_completion_tokens.or(request.max_tokens) {
+        config.max_output_tokens = max_tokens;
+    }
+    if request.temperature.is_some() {
+        config.temperature = request.temperature;
+    }
+    if request.seed.is_some() {
+        config.seed = request.seed;
+    }
+    config.stop_sequences = match &request.stop {
+        Some(Stop::One(stop)) => vec![stop.clone()],
+        Some(Stop::Many(stops)) => stops.clone(),
+        None => config.stop_sequences,
+    };
+    config.no_tools = true;
+    Ok(config)
+}
+
+fn finish_reason(stop_reason: &StopReason) -> &'static str {
+    match stop_reason {
+        StopReason::MaxTokens => "length",
+        StopReason::ToolUse => "tool_calls",
+        StopReason::Refusal => "content_filter",
+        StopReason::EndTurn | StopReason::StopSequence | StopReason::Other(_) => "stop",
+    }
+}
+
+fn usage_json(usage: &Usage) -> Value {
+    let prompt_tokens = usage.input_tokens + usage.cache_creation_input_tokens + usage.cache_read_input_tokens;
+    json!({
+        "prompt_tokens": prompt_tokens,
+        "completion_tokens": usage.output_tokens,
+        "total_tokens": prompt_tokens + usage.output_tokens,
+        "prompt_tokens_details": {"cached_tokens": usage.cache_read_input_tokens},
+    })
+}
+
+fn completion_json(response: &ModelResponse, created: i64) -> Value {
+    let text = response.content.iter()
+        .filter_map(|item| match item {
+            ContentItem::Text { text } => Some(text.as_str()),
+            _ => None,
+        })
+        .collect::<String>();
+    json!({
+        "id": format!("chatcmpl-{}", response.id),
+        "object": "chat.completion",
+        "created": created,
+        "model": response.model,
+        "choices": [{
+            "index": 0,
+            "message": {"role": "assistant", "content": text},
+            "finish_reason": finish_reason(&response.stop_reason),
+        }],
+        "usage": usage_json(&response.usage.clone().unwrap_or_default()),
+    })
+}
+
+fn chunk_json(id: &str, model: &str, created: i64, delta: Value, finish_reason: Option<&str>) -> Value {
+    json!({
+        "id": id,
+        "object": "chat.completion.chunk",
+        "created": created,
+        "model": model,
+        "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}],
+    })
+}
+
+fn sse(event: &Value) -> web::Bytes {
+    web::Bytes::from(format!("data: {}\n\n", event))
+}
+
+// Streams the reply as completion chunks, the request is answered before it is sent
+fn stream_completion(chat: Chat, system: String, messages: Vec<Message>, include_usage: bool, created: i64) -> HttpResponse {
+    let (tx, rx) = mpsc::unbounded_channel::<web::Bytes>();
+    let id = format!("chatcmpl-{}", uuid::Uuid::new_v4().simple());
+    let model = chat.config().model.clone();
+    actix_web::rt::spawn(async move {
+        let _ = tx.send(sse(&chunk_json(&id, &model, created, json!({"role": "assistant", "content": ""}), None)));
+        let on_event = |event: &StreamEvent| match event {
+            StreamEvent::Text(text) => {
+                let _ = tx.send(sse(&chunk_json(&id, &model, created, json!({"content": text}), None)));
+            },
+            StreamEvent::Done { stop_reason, usage, .. } => {
+                let _ = tx.send(sse(&chunk_json(&id, &model, created, json!({}), Some(finish_reason(stop_reason)))));
+                if include_usage {
+                    let mut chunk = chunk_json(&id, &model, created, json!({}), None);
+                    chunk["choices"] = json!([]);
+                    chunk["usage"] = usage_json(&usage.clone().unwrap_or_default());
+                    let _ = tx.send(sse(&chunk));
+                }
+            },
+            _ => {},
+        };
+        // The reply is finished even when the client goes away, so its usage is still recorded
+        if let Err(e) = chat.query_streaming(&system, messages, on_event).await {
+            let _ = tx.send(sse(&json!({"error": {"message": format!("{:#}", e), "type": "api_error", "code": Value::Null}})));
+        }
+        let _ = tx.send(web::Bytes::from_static(b"data: [DONE]\n\n"));
+    });
+    let chunks = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (Ok::<_, actix_web::Error>(chunk), rx))
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((http::header::CACHE_CONTROL, "no-cache"))
+        .streaming(chunks)
+}
+
+/// `POST /v1/chat/completions`, answered by the configured provider and counted in its usage.
+async fn chat_completions(req: HttpRequest, daemon: web::Data<Daemon>, body: web::Json<CompletionRequest>) -> HttpResponse {
+    if !daemon.authorized(&req) {
+        return openai_error(http::StatusCode::UNAUTHORIZED, "invalid_request_error", "Send the token from .pprog/serve-token or PPROG_SERVE_TOKEN as the API key");
+    }
+    let request = body.into_inner();
+    if request.tools.as_ref().and_then(Value::as_array).is_some_and(|tools| !tools.is_empty()) {
+        return request_error("Tools are not supported by the proxy");
+    }
+    let (system, messages) = match request_messages(&request.messages) {
+        Ok(converted) => converted,
+        Err(e) => return request_error(&e),
+    };
+    let shared = match ProjectConfig::shared() {
+        Ok(shared) => shared,
+        Err(e) => return provider_error(e),
+    };
+    let config = match request_config(&shared, &request) {
+        Ok(config) => config,
+        Err(e) => return request_error(&e.to_string()),
+    };
+    let chat = match Chat::from_config(config).await {
+        Ok(chat) => chat,
+        Err(e) => return provider_error(e),
+    };
+    let created = chrono::Utc::now().timestamp();
+    if request.stream {
+        let include_usage = request.stream_options.as_ref().is_some_and(|options| options["include_usage"] == true);
+        return stream_completion(chat, system, messages, include_usage, created);
+    }
+    match chat.query(&system, messages).await {
+        Ok(response) => HttpResponse::Ok().json(completion_json(&response, created)),
+        Err(e) => provider_error(e),
+    }
+}
+
+/// `GET /v1/models`, the configured model and those of the personas.
+async fn list_models(req: HttpRequest, daemon: web::Data<Daemon>) -> HttpResponse {
+    if !daemon.authorized(&req) {
+        return openai_error(http::StatusCode::UNAUTHORIZED, "invalid_request_error", "Send the token from .pprog/serve-token or PPROG_SERVE_TOKEN as the API key");
+    }
+    let config = match ProjectConfig::shared() {
+        Ok(config) => config,
+        Err(e) => return provider_error(e),
+    };
+    let models = [DEFAULT_MODEL.to_string(), config.model.clone()].into_iter()
+        .chain(config.personas.keys().cloned())
+        .map(|id| json!({"id": id, "object": "model", "created": 0, "owned_by": config.provider}))
+        .collect::<Vec<Value>>();
+    HttpResponse::Ok().json(json!({"object": "list", "data": models}))
+}
+
+/// The `/v1` routes, registered ahead of the catch-all for the frontend's files.
+pub fn routes(config: &mut web::ServiceConfig) {
+    config.service(
+        web::scope("/v1")
+            .route("/chat/completions", web::post().to(chat_completions))
+            .route("/models", web::get().to(list_models))
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inference::types::ResponseType;
+
+    #[test]
+    fn test_openai_shapes() {
+        let (system, messages) = request_messages(&[
+            json!({"role": "system", "content": "Be brief."}),
+            json!({"role": "user", "content": "Hi"}),
+            json!({"role": "user", "content": [{"type": "text", "text": "What is a mutex?"}]}),
+        ]).unwrap();
+        assert_eq!(system, "Be brief.");
+        assert_eq!(messages, vec![Message {
+            role: Role::User,
+            content: vec![ContentItem::Text { text: "Hi".to_string() }, ContentItem::Text { text: "What is a mutex?".to_string() }],
+        }]);
+        assert!(request_messages(&[json!({"role": "tool", "content": "42", "tool_call_id": "call_1"})]).is_err());
+        assert!(request_messages(&[json!({"role": "system", "content": "Alone"})]).is_err());
+
+        let request: CompletionRequest = serde_json::from_value(json!({"model": "gpt-4o", "messages": [], "stop": "END", "max_tokens": 64})).unwrap();
+        let config = request_config(&ProjectConfig::default(), &request).unwrap();
+        assert_eq!((config.model.as_str(), config.max_output_tokens, config.no_tools), ("gpt-4o", 64, true));
+        assert_eq!(config.stop_sequences, ["END"]);
+
+        let response = ModelResponse {
+            content: vec![ContentItem::Text { text: "A lock.".to_string() }],
+            id: "msg_1".to_string(),
+            model: "claude-3-5-haiku-latest".to_string(),
+            role: Role::Assistant,
+            message_type: ResponseType::Message,
+            stop_reason: StopReason::MaxTokens,
+            stop_sequence: None,
+            usage: Some(Usage { input_tokens: 10, cache_read_input_tokens: 5, output_tokens: 3, ..Default::default() }),
+            raw: None,
+            timing: None,
+        };
+        let completion = completion_json(&response, 0);
+        assert_eq!(completion["choices"][0]["message"]["content"], "A lock.");
+        assert_eq!(completion["choices"][0]["finish_reason"], "length");
+        assert_eq!(completion["usage"]["prompt_tokens"], 15);
+        assert_eq!(completion["usage"]["total_tokens"], 18);
+    }
+}
@@ -0,0 +1,166 @@
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+
+use crate::agent::Decision;
+use crate::auth::AuthFailure;
+use crate::secrets::SecretAction;
+use super::compose::compose;
+use super::{describe_tool, pending_diff};
+
+/// Asked under a tool call waiting for approval.
+pub const PROMPT: &str = "Run it? [y]es, [n]o, [a]lways for this tool, [e]dit, or say why not: ";
+
+/// Asked under the secrets found in a message before it is sent.
+pub const SECRETS_PROMPT: &str = "Send it? [m]ask them, [s]end as is, [b]lock: ";
+
+/// What an answer to `SECRETS_PROMPT` picks, `default` for an empty one and None to ask again.
+pub fn secret_action(answer: &str, default: SecretAction) -> Option<SecretAction> {
+    match answer.trim() {
+        "" => Some(default),
+        "m" | "mask" => Some(SecretAction::Mask),
+        "s" | "send" => Some(SecretAction::Send),
+        "b" | "block" => Some(SecretAction::Block),
+        _ => None,
+    }
+}
+
+/*
+    * Reads a new API key on the terminal after `failure`, without echoing it.  Enter on an
+    * empty line, Esc, Ctrl-C or Ctrl-D give up, as does a terminal that can't be read.
+*/
+pub fn ask_key_on_tty(provider: &str, failure: AuthFailure) -> Option<String> {
+    let ask = || -> Result<Option<String>, anyhow::Error> {
+        let mut tty = OpenOptions::new().write(true).open("/dev/tty")?;
+        write!(tty, "! {}\r\nNew API key for {}, or Enter to give up: ", failure.describe(provider), provider)?;
+        tty.flush()?;
+        terminal::enable_raw_mode()?;
+        let _cooked = scopeguard::guard((), |_| {
+            let _ = terminal::disable_raw_mode();
+        });
+        let mut key = String::new();
+        let answer = loop {
+            let Event::Key(KeyEvent { code, modifiers, kind: KeyEventKind::Press, .. }) = event::read()? else { continue };
+            match code {
+                KeyCode::Enter => break Some(key),
+                KeyCode::Esc => break None,
+                KeyCode::Char('c' | 'd') if modifiers.contains(KeyModifiers::CONTROL) => break None,
+                KeyCode::Backspace => {
+                    key.pop();
+                },
+                KeyCode::Char(c) => key.push(c),
+                _ => {},
+            }
+        };
+        write!(tty, "\r\n")?;
+        Ok(answer.filter(|key| !key.trim().is_empty()))
+    };
+    ask().unwrap_or_else(|e| {
+        log::error!("Could not ask for a new API key: {}", e);
+        None
+    })
+}
+
+/// Asks about secrets on the terminal like `ask_on_tty`, `default` when it can't be opened.
+pub fn ask_secrets_on_tty(found: &[String], default: SecretAction) -> SecretAction {
+    let ask = || -> Result<SecretAction, anyhow::Error> {
+        let mut tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+        for line in found {
+            writeln!(tty, "! {}", line)?;
+        }
+        let mut reader = BufReader::new(tty.try_clone()?);
+        loop {
+            write!(tty, "{}", SECRETS_PROMPT)?;
+            tty.flush()?;
+            let mut answer = String::new();
+            if reader.read_line(&mut answer)? == 0 {
+                return Ok(default);
+            }
+            if let Some(action) = secret_action(&answer, default) {
+                return Ok(action);
+            }
+        }
+    };
+    ask().unwrap_or_else(|e| {
+        log::error!("Could not ask about the secrets found: {}", e);
+        default
+    })
+}
+
+// Opens the arguments as pretty JSON in $EDITOR, None when nothing was saved
+fn edit_arguments(input: &serde_json::Value) -> Result<Option<serde_json::Value>, anyhow::Error> {
+    let Some(edited) = compose(&serde_json::to_string_pretty(input)?)? else { return Ok(None) };
+    let edited = serde_json::from_str(&edited).map_err(|e| anyhow::anyhow!("The edited arguments are not valid JSON: {}", e))?;
+    Ok(Some(edited))
+}
+
+/// The decision an answer to `PROMPT` stands for, None to ask again.  Any answer that isn't
+/// one of the options denies the call with the answer as feedback for the model.
+pub fn decision(answer: &str, input: &serde_json::Value) -> Result<Option<Decision>, anyhow::Error> {
+    Ok(match answer.trim() {
+        "" => None,
+        "y" | "yes" => Some(Decision::Approve),
+        "n" | "no" => Some(Decision::Deny(String::new())),
+        "a" | "always" => Some(Decision::AlwaysAllow),
+        "e" | "edit" => edit_arguments(input)?.map(Decision::Edit),
+        feedback => Some(Decision::Deny(feedback.to_string())),
+    })
+}
+
+/*
+    * Asks about a tool call on the terminal, for runs whose stdin and stdout are taken by the
+    * prompt and the answer.  Nothing else shows tool calls there, so the call and its diff
+    * are printed first, with why the command policy asks when it does.  A terminal that can't
+    * be opened denies the call.
+*/
+pub fn ask_on_tty(name: &str, input: &serde_json::Value, reason: Option<&str>) -> Decision {
+    let ask = || -> Result<Decision, anyhow::Error> {
+        let mut tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+        writeln!(tty, "> {}", describe_tool(name, input))?;
+        if let Some(diff) = pending_diff(name, input) {
+            write!(tty, "{}", diff)?;
+        }
+        if let Some(reason) = reason {
+            writeln!(tty, "! {}", reason)?;
+        }
+        let mut reader = BufReader::new(tty.try_clone()?);
+        loop {
+            write!(tty, "{}", PROMPT)?;
+            tty.flush()?;
+            let mut answer = String::new();
+            if reader.read_line(&mut answer)? == 0 {
+                return Ok(Decision::Deny(String::new()));
+            }
+            match decision(&answer, input) {
+                Ok(Some(decision)) => return Ok(decision),
+                Ok(None) => {},
+                Err(e) => writeln!(tty, "{}", e)?,
+            }
+        }
+    };
+    ask().unwrap_or_else(|e| {
+        log::error!("Could not ask about the {} tool call: {}", name, e);
+        Decision::Deny(String::new())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decision() -> Result<(), anyhow::Error> {
+        let input = serde_json::json!({"statement": "rm -rf target"});
+        assert_eq!(decision(" y\n", &input)?, Some(Decision::Approve));
+        assert_eq!(decision("always", &input)?, Some(Decision::AlwaysAllow));
+        assert_eq!(decision("n", &input)?, Some(Decision::Deny(String::new())));
+        assert_eq!(decision("use cargo clean instead\n", &input)?, Some(Decision::Deny("use cargo clean instead".to_string())));
+        assert_eq!(decision("", &input)?, None);
+        assert_eq!(secret_action("\n", SecretAction::Block), Some(SecretAction::Block));
+        assert_eq!(secret_action("s", SecretAction::Mask), Some(SecretAction::Send));
+        assert_eq!(secret_action("yes", SecretAction::Mask), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::chat::Chat;
+use crate::clipboard;
+use crate::images;
+use crate::inference::types::{ContentItem, Role};
+use crate::inference::tools;
+use crate::inference::wire;
+use crate::models;
+use crate::session::Session;
+use crate::snippets::{self, Snippet};
+use crate::store::{LatencyStats, Store};
+use crate::todo;
+
+/// Commands handled by the REPL itself, with their usage for `/help`.
+pub const COMMANDS: &[(&str, &str)] = &[
+    ("/model", "/model [name]  show or switch the model"),
+    ("/cost", "/cost  tokens and cost of this session"),
+    ("/compact", "/compact  summarize older turns now"),
+    ("/clear", "/clear  start a new session"),
+    ("/tools", "/tools  list the tools the model can call"),
+    ("/resume", "/resume [id]  list recent sessions or continue one"),
+    ("/copy", "/copy  copy the last code block of the reply to the clipboard"),
+    ("/image", "/image [path]  attach an image file, or the clipboard's image, to the next message"),
+    ("/plan", "/plan [off]  plan the next request and approve the plan before tools run"),
+    ("/todo", "/todo  show the agent's task list for this session"),
+    ("/debug", "/debug dump [path]  write the last request and response, redacted, to a file"),
+    ("/snippet", "/snippet [name] [name=value ...]  list snippets or send one, asking for missing values"),
+    ("/help", "/help  show this list"),
+];
+
+// Sessions listed by a bare /resume
+const RECENT_SESSIONS: usize = 10;
+
+/// Splits `/name args` into the command and its argument.  Lines not starting with a known
+/// command are None so a message like `/etc/hosts is empty` still goes to the model.
+pub fn parse(line: &str) -> Option<(&str, Option<&str>)> {
+    let line = line.trim();
+    let (name, arg) = match line.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, Some(arg.trim())),
+        None => (line, None),
+    };
+    COMMANDS.iter().any(|(command, _)| *command == name)
+        .then_some((name, arg.filter(|arg| !arg.is_empty())))
+}
+
+/// Command names completing the line so far, only while the command itself is being typed.
+pub fn complete(before_cursor: &str) -> Vec<String> {
+    if before_cursor.contains(char::is_whitespace) || !before_cursor.starts_with('/') {
+        return Vec::new();
+    }
+    COMMANDS.iter()
+        .map(|(name, _)| name.to_string())
+        .filter(|name| name.starts_with(before_cursor))
+        .collect()
+}
+
+// Like `3.4s average over 12 requests, first token 0.8s, 120 tok/s`
+fn latency_summary(stats: &LatencyStats) -> String {
+    let mut summary = format!("{:.1}s average over {} requests", stats.total.as_secs_f64(), stats.requests);
+    if let Some(first) = stats.first_token {
+        summary.push_str(&format!(", first token {:.1}s", first.as_secs_f64()));
+    }
+    if let Some(rate) = stats.output_rate {
+        summary.push_str(&format!(", {:.0} tok/s", rate));
+    }
+    summary
+}
+
+/// The snippet named by the argument of `/snippet name name=value ...` and the values given.
+pub fn snippet(arg: &str) -> Result<(Snippet, BTreeMap<String, String>), anyhow::Error> {
+    let mut words = arg.split_whitespace();
+    let snippet = Snippet::load(words.next().unwrap_or_default())?;
+    Ok((snippet, snippets::parse_values(words)?))
+}
+
+/// Runs a slash command, returning the text to show.  `attachments` are the items waiting
+/// to go out with the next message.
+pub async fn run(chat: &mut Chat, name: &str, arg: Option<&str>, attachments: &mut Vec<ContentItem>) -> Result<String, anyhow::Error> {
+    match (name, arg) {
+        ("/model", None) => Ok(format!("Model: {}", chat.config().model)),
+        ("/model", Some(model)) => {
+            chat.set_model(model).await?;
+            Ok(format!("Switched to {}", model))
+        },
+        ("/cost", _) => {
+            let store = Store::open()?;
+            let usage = store.session_usage(&chat.session.id)?;
+            let latency = store.session_latency(&chat.session.id)?;
+            let mut lines: Vec<String> = usage.iter()
+                .map(|(model, usage)| {
+                    let cost = models::lookup(model)
+                        .map(|info| format!("${:.4}", info.cost(usage)))
+                        .unwrap_or_else(|| "unknown price".to_string());
+                    let mut line = format!(
+                        "{}: {} in, {} cached, {} out, {}",
+                        model,
+                        usage.input_tokens,
+                        usage.cache_creation_input_tokens + usage.cache_read_input_tokens,
+                        usage.output_tokens,
+                        cost,
+                    );
+                    if let Some((_, stats)) = latency.iter().find(|(timed, _)| timed == model) {
+                        line.push_str(&format!(", {}", latency_summary(stats)));
+                    }
+                    line
+                })
+                .collect();
+            if let Some((full, compressed)) = chat.prompt_savings() {
+                let saved = full.saturating_sub(compressed) as f64 / full.max(1) as f64 * 100.0;
+                lines.push(format!("System prompt: {} tokens, {} compressed (-{:.0}%)", full, compressed, saved));
+            }
+            lines.push(format!("Total: ${:.4}", chat.session_cost()?));
+            Ok(lines.join("\n"))
+        },
+        ("/compact", _) => match chat.compact().await? {
+            true => Ok(format!("Compacted, context is {:.0}% full", chat.context_fill() * 100.0)),
+            false => Ok("Nothing to compact yet".to_string()),
+        },
+        ("/clear", _) => {
+            chat.new_session();
+            Ok(format!("Started session {}", chat.session.id))
+        },
+        ("/plan", Some("off")) => {
+            chat.stop_planning();
+            Ok("Plan mode off".to_string())
+        },
+        ("/plan", None) => {
+            chat.start_planning();
+            Ok("Plan mode on, the next request gets a plan to approve before any tool runs".to_string())
+        },
+        ("/plan", Some(arg)) => Err(anyhow::anyhow!("Unknown argument {:?}, use /plan or /plan off", arg)),
+        ("/todo", _) if chat.session.todos.is_empty() => Ok("No task list in this session yet".to_string()),
+        ("/todo", _) => Ok(format!("{}\n{}", todo::render(&chat.session.todos), todo::summary(&chat.session.todos))),
+        ("/tools", _) => Ok(tools::definitions().iter()
+            .map(|tool| format!("{}  {}", tool.name, tool.description))
+            .collect::<Vec<String>>()
+            .join("\n")),
+        ("/resume", None) => {
+            let sessions = Session::list()?;
+            if sessions.is_empty() {
+                return Ok("No saved sessions".to_string());
+            }
+            Ok(sessions.iter()
+                .take(RECENT_SESSIONS)
+                .map(|session| format!(
+                    "{}  {}  {}",
+                    &session.id[..8.min(session.id.len())],
+                    session.created_at.format("%Y-%m-%d %H:%M"),
+                    session.title.as_deref().unwrap_or("(untitled)"),
+                ))
+                .collect::<Vec<String>>()
+                .join("\n"))
+        },
+        ("/resume", Some(id)) => {
+            let session = Session::load_recent(id, chat.config().history_messages)?;
+            let summary = format!(
+                "Resumed {} ({} msgs)  {}",
+                session.id,
+                session.offloaded + session.messages.len(),
+                session.title.as_deref().unwrap_or("(untitled)"),
+            );
+            chat.resume(session)?;
+            Ok(summary)
+        },
+        ("/copy", _) => {
+            let reply = chat.session.messages.iter().rev()
+                .find(|message| message.role == Role::Assistant)
+                .map(|message| message.content.iter()
+                    .filter_map(|item| match item {
+                        ContentItem::Text { text } => Some(text.as_str()),
+                        _ => None,
+                    })
+                    .collect::<Vec<&str>>()
+                    .join("\n"))
+                .ok_or_else(|| anyhow::anyhow!("No reply to copy from yet"))?;
+            let block = clipboard::last_code_block(&reply).ok_or_else(|| anyhow::anyhow!("The last reply has no code block"))?;
+            let target = clipboard::copy(&block)?;
+            Ok(format!("Copied the last code block to the {}", target))
+        },
+        ("/image", arg) => {
+            let (image, source) = match arg {
+                Some(path) => (images::from_file(Path::new(path))?, path.to_string()),
+                None => (images::from_bytes(&clipboard::paste_image()?)?, "the clipboard image".to_string()),
+            };
+            let size = match &image {
+                ContentItem::Image { source } => (source.data.len() * 3 / 4).div_ceil(1024),
+                _ => 0,
+            };
+            attachments.push(image);
+            Ok(format!("Attached {} ({} KB), it is sent with your next message", source, size))
+        },
+        ("/snippet", None) => {
+            let snippets = Snippet::list()?;
+            if snippets.is_empty() {
+                return Ok("No snippets saved, add one with `pprog snippets add <name>`".to_string());
+            }
+            Ok(snippets.iter()
+                .map(|snippet| format!("{}  {}", snippet.name, snippet.text.lines().next().unwrap_or_default()))
+                .collect::<Vec<String>>()
+                .join("\n"))
+        },
+        // The frontends send the expanded snippet instead of showing it
+        ("/snippet", Some(arg)) => {
+            let (snippet, values) = snippet(arg)?;
+            snippet.expand(&values)
+        },
+        ("/debug", Some(arg)) if arg == "dump" || arg.starts_with("dump ") => {
+            let output = arg.strip_prefix("dump").map(str::trim).filter(|path| !path.is_empty());
+            let path = wire::dump_last(output.map(Path::new))?;
+            Ok(format!("Wrote {}", path.display()))
+        },
+        ("/debug", _) => Err(anyhow::anyhow!("Use /debug dump [path]")),
+        ("/help", _) => Ok(COMMANDS.iter().map(|(_, usage)| *usage).collect::<Vec<&str>>().join("\n")),
+        _ => Err(anyhow::anyhow!("Unknown command {}, /help lists the commands", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_complete() {
+        assert_eq!(parse("/model  gpt-4o "), Some(("/model", Some("gpt-4o"))));
+        assert_eq!(parse("/cost"), Some(("/cost", None)));
+        assert_eq!(parse("what does /cost do"), None);
+        assert_eq!(parse("/etc/hosts is empty"), None);
+        assert_eq!(complete("/c"), vec!["/cost", "/compact", "/clear", "/copy"]);
+        assert!(complete("/model g").is_empty());
+    }
+}
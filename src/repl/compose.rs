@@ -0,0 +1,31 @@
+use std::process::Command;
+
+/*
+    * Opens $VISUAL or $EDITOR (vi if neither is set) on a scratch file holding `initial`
+    * and returns what was saved.  The editor is run through the shell so values like
+    * `code --wait` work.  None when the editor failed or the file was left empty.
+*/
+pub fn compose(initial: &str) -> Result<Option<String>, anyhow::Error> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+
+    let path = std::env::temp_dir().join(format!("pprog-prompt-{}.md", uuid::Uuid::new_v4()));
+    std::fs::write(&path, initial)?;
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(&path)
+        .status();
+    let text = std::fs::read_to_string(&path);
+    let _ = std::fs::remove_file(&path);
+
+    let status = status.map_err(|e| anyhow::anyhow!("Could not start editor {:?}: {}", editor, e))?;
+    if !status.success() {
+        return Ok(None);
+    }
+    Ok(Some(text?.trim_end().to_string()).filter(|text| !text.trim().is_empty()))
+}
@@ -0,0 +1,543 @@
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+
+use crossterm::{
+    cursor,
+    event::{self, DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::Print,
+    terminal::{self, Clear, ClearType},
+};
+
+use crate::config::ProjectConfig;
+use super::compose::compose;
+use super::keys::{Action, Keymap};
+
+pub enum ReadResult {
+    Line(String),
+    /// Ctrl-C, the line was discarded
+    Interrupted,
+    /// Ctrl-D on an empty line, or end of input
+    Eof,
+    /// The regenerate key on an empty line
+    Regenerate,
+    /// The approve_tool key on an empty line
+    Approve,
+}
+
+// Restores cooked mode however reading ends, including on panic.  Bracketed paste is on
+// while reading so a pasted block arrives as one event instead of lines being sent one by one.
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnableBracketedPaste)?;
+        Ok(RawMode)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), DisableBracketedPaste);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+// Completion candidates shown at once, the rest are summarized
+const MAX_LISTED: usize = 20;
+
+// Entries kept in the history file, older ones are dropped when it is loaded
+const MAX_HISTORY: usize = 1000;
+
+// Shown in front of the lines after the first one
+const CONTINUATION_PROMPT: &str = "… ";
+
+/// Input history, optionally kept in a file with one JSON string per entry so multi-line
+/// entries survive.
+#[derive(Default)]
+pub struct History {
+    entries: Vec<String>,
+    path: Option<PathBuf>,
+}
+
+impl History {
+    pub fn load(path: PathBuf) -> Result<Self, anyhow::Error> {
+        let mut entries: Vec<String> = match fs::read_to_string(&path) {
+            Ok(content) => content.lines().filter_map(|line| serde_json::from_str(line).ok()).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        if entries.len() > MAX_HISTORY {
+            entries.drain(..entries.len() - MAX_HISTORY);
+            let content: String = entries.iter().map(|entry| format!("{}\n", serde_json::to_string(entry).unwrap())).collect();
+            ProjectConfig::private_file().create(true).write(true).truncate(true).open(&path)?.write_all(content.as_bytes())?;
+        }
+        Ok(Self { entries, path: Some(path) })
+    }
+
+    pub fn push(&mut self, line: &str) {
+        if line.trim().is_empty() || self.entries.last().map(String::as_str) == Some(line) {
+            return;
+        }
+        self.entries.push(line.to_string());
+        if let Some(path) = &self.path {
+            // What was typed can hold anything pasted, keys included, so only its owner reads it
+            let appended = ProjectConfig::private_file().create(true).append(true).open(path)
+                .and_then(|mut file| writeln!(file, "{}", serde_json::to_string(line).unwrap()));
+            if let Err(e) = appended {
+                log::error!("Could not save input history to {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// The newest entry before `before` containing `query`.
+    fn search(&self, query: &str, before: usize) -> Option<usize> {
+        self.entries[..before.min(self.entries.len())].iter().rposition(|entry| entry.contains(query))
+    }
+}
+
+// Ctrl-R state: what is typed so far and the entry it matched
+struct Search {
+    query: String,
+    found: Option<usize>,
+}
+
+/// Returns the candidates for the word before the cursor, given the line up to the cursor.
+pub type Completer = Box<dyn Fn(&str) -> Vec<String>>;
+
+/*
+    * A small line editor on top of crossterm: cursor movement, word deletion, history
+    * browsing with the arrow keys, Ctrl-R search and tab completion.  A line ending in a
+    * backslash continues on the next one and pasted text keeps its newlines; the editor key
+    * hands the input to $EDITOR instead.  Keys for actions come from the `Keymap`.  When stdin isn't a terminal lines are read as-is, with
+    * the same backslash continuation, so input can be piped in.
+*/
+#[derive(Default)]
+pub struct LineEditor {
+    history: History,
+    completer: Option<Completer>,
+    keymap: Keymap,
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    pub fn set_history(&mut self, history: History) {
+        self.history = history;
+    }
+
+    pub fn set_completer(&mut self, completer: impl Fn(&str) -> Vec<String> + 'static) {
+        self.completer = Some(Box::new(completer));
+    }
+
+    // Completes the word before the cursor as far as all candidates agree, and lists them
+    // when that doesn't add anything
+    fn complete(&self, line: &mut LineState, stdout: &mut impl Write) -> io::Result<()> {
+        let Some(completer) = &self.completer else { return Ok(()) };
+        let before: String = line.chars[..line.cursor].iter().collect();
+        let candidates = completer(&before);
+        let Some(first) = candidates.first() else { return Ok(()) };
+
+        let word_start = line.chars[..line.cursor].iter()
+            .rposition(|c| c.is_whitespace())
+            .map_or(0, |idx| idx + 1);
+        let word_len = line.cursor - word_start;
+        let common: Vec<char> = candidates.iter().skip(1).fold(first.chars().collect(), |common: Vec<char>, candidate| {
+            common.into_iter().zip(candidate.chars()).take_while(|(a, b)| a == b).map(|(a, _)| a).collect()
+        });
+
+        if common.len() > word_len {
+            line.chars.splice(word_start..line.cursor, common.iter().copied());
+            line.cursor = word_start + common.len();
+            if candidates.len() == 1 && !first.ends_with('/') {
+                line.insert(' ');
+            }
+        } else if candidates.len() > 1 {
+            let mut listing = candidates.iter().take(MAX_LISTED).cloned().collect::<Vec<String>>().join("  ");
+            if candidates.len() > MAX_LISTED {
+                listing.push_str(&format!("  ({} more)", candidates.len() - MAX_LISTED));
+            }
+            execute!(stdout, Print("\r\n"), Print(listing), Print("\r\n"))?;
+        }
+        Ok(())
+    }
+
+    pub fn add_history(&mut self, line: &str) {
+        self.history.push(line);
+    }
+
+    fn read_piped(&self) -> io::Result<ReadResult> {
+        let mut text = String::new();
+        loop {
+            let mut line = String::new();
+            if io::stdin().lock().read_line(&mut line)? == 0 {
+                return Ok(match text.is_empty() {
+                    true => ReadResult::Eof,
+                    false => ReadResult::Line(text),
+                });
+            }
+            let line = line.trim_end_matches(['\r', '\n']);
+            match line.strip_suffix('\\') {
+                Some(continued) => {
+                    text.push_str(continued);
+                    text.push('\n');
+                },
+                None => {
+                    text.push_str(line);
+                    return Ok(ReadResult::Line(text));
+                },
+            }
+        }
+    }
+
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<ReadResult> {
+        if !io::stdin().is_terminal() {
+            return self.read_piped();
+        }
+
+        let raw = RawMode::enable()?;
+        let mut stdout = io::stdout();
+        let mut line = LineState::default();
+        // Index into history while browsing, the entry count is the line being written
+        let mut history_index = self.history.entries.len();
+        let mut draft = String::new();
+        let mut search: Option<Search> = None;
+        // Row of the cursor within the input as last drawn
+        let mut row = 0;
+
+        loop {
+            row = match &search {
+                Some(search) => {
+                    let mut shown = LineState::default();
+                    if let Some(found) = search.found {
+                        let entry = &self.history.entries[found];
+                        shown.set(entry);
+                        shown.cursor = entry[..entry.find(&search.query).unwrap_or_default()].chars().count();
+                    }
+                    let status = if search.found.is_some() || search.query.is_empty() { "" } else { "failing " };
+                    shown.render(&mut stdout, &format!("({}reverse-i-search)`{}': ", status, search.query), row)?
+                },
+                None => line.render(&mut stdout, prompt, row)?,
+            };
+
+            let event = event::read()?;
+            if let Event::Paste(text) = &event {
+                search = None;
+                line.insert_str(&text.replace("\r\n", "\n").replace('\r', "\n"));
+                continue;
+            }
+            let Event::Key(KeyEvent { code, modifiers, kind, .. }) = event else { continue };
+            if kind != KeyEventKind::Press {
+                continue;
+            }
+            let ctrl = modifiers.contains(KeyModifiers::CONTROL);
+            let action = self.keymap.action(code, modifiers);
+
+            if let Some(current) = &mut search {
+                match code {
+                    _ if action == Some(Action::Cancel) => {
+                        search = None;
+                        continue;
+                    },
+                    KeyCode::Char('r') if ctrl => {
+                        let before = current.found.unwrap_or(self.history.entries.len());
+                        current.found = self.history.search(&current.query, before).or(current.found);
+                        continue;
+                    },
+                    KeyCode::Char('g') if ctrl => {
+                        search = None;
+                        continue;
+                    },
+                    KeyCode::Esc => {
+                        search = None;
+                        continue;
+                    },
+                    KeyCode::Char(c) if !ctrl => {
+                        current.query.push(c);
+                        let before = current.found.map_or(self.history.entries.len(), |found| found + 1);
+                        current.found = self.history.search(&current.query, before);
+                        continue;
+                    },
+                    KeyCode::Backspace => {
+                        current.query.pop();
+                        current.found = self.history.search(&current.query, self.history.entries.len());
+                        continue;
+                    },
+                    // Anything else takes the match and is handled as usual
+                    _ => {
+                        if let Some(found) = current.found {
+                            line.set(&self.history.entries[found]);
+                            history_index = found;
+                        }
+                        search = None;
+                    },
+                }
+            }
+
+            match action {
+                Some(Action::Submit) if line.cursor == line.chars.len() && line.chars.last() == Some(&'\\') => {
+                    line.backspace();
+                    line.insert('\n');
+                    continue;
+                },
+                Some(Action::Submit) => {
+                    line.cursor = line.chars.len();
+                    line.render(&mut stdout, prompt, row)?;
+                    execute!(stdout, Print("\r\n"))?;
+                    return Ok(ReadResult::Line(line.text()));
+                },
+                Some(Action::Newline) => {
+                    line.insert('\n');
+                    continue;
+                },
+                Some(Action::Cancel) => {
+                    execute!(stdout, Print("^C\r\n"))?;
+                    return Ok(ReadResult::Interrupted);
+                },
+                Some(Action::Editor) => {
+                    // The editor needs the terminal back in cooked mode
+                    execute!(stdout, Print("\r\n"))?;
+                    drop(raw);
+                    return Ok(match compose(&line.text()).map_err(io::Error::other)? {
+                        Some(text) => {
+                            println!("{}", text);
+                            ReadResult::Line(text)
+                        },
+                        None => ReadResult::Interrupted,
+                    });
+                },
+                Some(Action::Regenerate) => {
+                    if line.chars.is_empty() {
+                        execute!(stdout, Print("\r\n"))?;
+                        return Ok(ReadResult::Regenerate);
+                    }
+                    continue;
+                },
+                Some(Action::ApproveTool) => {
+                    if line.chars.is_empty() {
+                        execute!(stdout, Print("\r\n"))?;
+                        return Ok(ReadResult::Approve);
+                    }
+                    continue;
+                },
+                None => {},
+            }
+
+            match code {
+                KeyCode::Char('r') if ctrl => search = Some(Search { query: String::new(), found: None }),
+                KeyCode::Char('d') if ctrl => {
+                    if line.chars.is_empty() {
+                        execute!(stdout, Print("\r\n"))?;
+                        return Ok(ReadResult::Eof);
+                    }
+                    line.delete();
+                },
+                KeyCode::Tab => self.complete(&mut line, &mut stdout)?,
+                KeyCode::Up if line.move_row(-1) => {},
+                KeyCode::Down if line.move_row(1) => {},
+                KeyCode::Up if history_index > 0 => {
+                    if history_index == self.history.entries.len() {
+                        draft = line.text();
+                    }
+                    history_index -= 1;
+                    line.set(&self.history.entries[history_index]);
+                },
+                KeyCode::Down if history_index < self.history.entries.len() => {
+                    history_index += 1;
+                    match self.history.entries.get(history_index) {
+                        Some(entry) => line.set(entry),
+                        None => line.set(&draft),
+                    }
+                },
+                _ => {
+                    line.edit(code, ctrl);
+                },
+            }
+        }
+    }
+}
+
+/// The line being edited, shared with the TUI input box.
+#[derive(Default)]
+pub struct LineState {
+    pub chars: Vec<char>,
+    pub cursor: usize,
+}
+
+impl LineState {
+    pub fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    /// Applies an editing key, returns false if the key isn't one.
+    pub fn edit(&mut self, code: KeyCode, ctrl: bool) -> bool {
+        match code {
+            KeyCode::Char('a') if ctrl => self.cursor = 0,
+            KeyCode::Char('e') if ctrl => self.cursor = self.chars.len(),
+            KeyCode::Char('u') if ctrl => {
+                self.chars.drain(..self.cursor);
+                self.cursor = 0;
+            },
+            KeyCode::Char('w') if ctrl => self.delete_word(),
+            KeyCode::Char(c) if !ctrl => self.insert(c),
+            KeyCode::Backspace => self.backspace(),
+            KeyCode::Delete => self.delete(),
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.chars.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.chars.len(),
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn set(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    fn insert(&mut self, c: char) {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    fn insert_str(&mut self, text: &str) {
+        let chars: Vec<char> = text.chars().collect();
+        self.chars.splice(self.cursor..self.cursor, chars.iter().copied());
+        self.cursor += chars.len();
+    }
+
+    // Row and column of the cursor, counting rows by newlines in the input
+    fn position(&self) -> (usize, usize) {
+        let before = &self.chars[..self.cursor];
+        let row_start = before.iter().rposition(|c| *c == '\n').map_or(0, |idx| idx + 1);
+        (before.iter().filter(|c| **c == '\n').count(), self.cursor - row_start)
+    }
+
+    // Moves the cursor to the same column of the row `delta` away, false if there is none
+    fn move_row(&mut self, delta: isize) -> bool {
+        let (row, col) = self.position();
+        let starts: Vec<usize> = std::iter::once(0)
+            .chain(self.chars.iter().enumerate().filter(|(_, c)| **c == '\n').map(|(idx, _)| idx + 1))
+            .collect();
+        let Some(target) = row.checked_add_signed(delta).filter(|target| *target < starts.len()) else { return false };
+        let end = starts.get(target + 1).map_or(self.chars.len(), |next| next - 1);
+        self.cursor = (starts[target] + col).min(end);
+        true
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn delete_word(&mut self) {
+        let mut start = self.cursor;
+        while start > 0 && self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        self.chars.drain(start..self.cursor);
+        self.cursor = start;
+    }
+
+    // Redraws the input from the row the cursor was left on, returns the row it is on now
+    fn render(&self, stdout: &mut impl Write, prompt: &str, row: usize) -> io::Result<usize> {
+        if row > 0 {
+            queue!(stdout, cursor::MoveUp(row as u16))?;
+        }
+        queue!(stdout, cursor::MoveToColumn(0), Clear(ClearType::FromCursorDown))?;
+        let text = self.text();
+        for (idx, line) in text.split('\n').enumerate() {
+            match idx {
+                0 => queue!(stdout, Print(prompt), Print(line))?,
+                _ => queue!(stdout, Print("\r\n"), Print(CONTINUATION_PROMPT), Print(line))?,
+            }
+        }
+
+        let (row, col) = self.position();
+        let rows_below = text.matches('\n').count() - row;
+        if rows_below > 0 {
+            queue!(stdout, cursor::MoveUp(rows_below as u16))?;
+        }
+        let prompt_width = match row {
+            0 => prompt.chars().count(),
+            _ => CONTINUATION_PROMPT.chars().count(),
+        };
+        queue!(stdout, cursor::MoveToColumn((prompt_width + col) as u16))?;
+        stdout.flush()?;
+        Ok(row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_editing() {
+        let mut line = LineState::default();
+        line.set("cargo tset");
+        line.backspace();
+        line.backspace();
+        line.backspace();
+        line.insert('e');
+        line.insert('s');
+        line.insert('t');
+        assert_eq!(line.text(), "cargo test");
+
+        line.set(&format!("{}  --all", line.text()));
+        line.delete_word();
+        assert_eq!(line.text(), "cargo test  ");
+        line.delete_word();
+        assert_eq!(line.text(), "cargo ");
+        assert_eq!(line.cursor, 6);
+
+        line.set("first line\nsecond");
+        assert_eq!(line.position(), (1, 6));
+        assert!(line.move_row(-1));
+        assert_eq!(line.position(), (0, 6));
+        assert!(!line.move_row(-1));
+    }
+
+    #[test]
+    fn test_history_is_saved_and_searched() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("history");
+        let mut history = History::load(path.clone())?;
+        for line in ["cargo test", "fix the build\nand the tests", "cargo test", "git status", ""] {
+            history.push(line);
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        }
+        let history = History::load(path)?;
+        assert_eq!(history.entries, vec!["cargo test", "fix the build\nand the tests", "cargo test", "git status"]);
+        assert_eq!(history.search("cargo", 4), Some(2));
+        assert_eq!(history.search("cargo", 2), Some(0));
+        assert_eq!(history.search("tests", 4), Some(1));
+        assert_eq!(history.search("missing", 4), None);
+        Ok(())
+    }
+}
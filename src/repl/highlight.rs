@@ -0,0 +1,338 @@
+use crossterm::style::{Color, Stylize};
+
+/// Colors for each kind of token.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    pub plain: Color,
+    pub keyword: Color,
+    pub string: Color,
+    pub comment: Color,
+    pub number: Color,
+    pub type_name: Color,
+}
+
+impl Theme {
+    /// Looks up a theme by its name in `highlight_theme`.  `colors` is what the terminal
+    /// supports, terminals limited to 8 colors get the closest basic ones.  `none` turns
+    /// highlighting off.
+    pub fn named(name: &str, colors: u16) -> Option<Theme> {
+        let extended = colors >= 256;
+        match name {
+            "dark" if extended => Some(Theme {
+                plain: Color::AnsiValue(252),
+                keyword: Color::AnsiValue(176),
+                string: Color::AnsiValue(150),
+                comment: Color::AnsiValue(244),
+                number: Color::AnsiValue(215),
+                type_name: Color::AnsiValue(117),
+            }),
+            "dark" => Some(Theme {
+                plain: Color::White,
+                keyword: Color::Magenta,
+                string: Color::Green,
+                comment: Color::DarkGrey,
+                number: Color::Yellow,
+                type_name: Color::Cyan,
+            }),
+            "light" if extended => Some(Theme {
+                plain: Color::AnsiValue(236),
+                keyword: Color::AnsiValue(90),
+                string: Color::AnsiValue(28),
+                comment: Color::AnsiValue(245),
+                number: Color::AnsiValue(130),
+                type_name: Color::AnsiValue(25),
+            }),
+            "light" => Some(Theme {
+                plain: Color::Black,
+                keyword: Color::DarkMagenta,
+                string: Color::DarkGreen,
+                comment: Color::DarkGrey,
+                number: Color::DarkYellow,
+                type_name: Color::DarkBlue,
+            }),
+            _ => None,
+        }
+    }
+}
+
+struct Language {
+    keywords: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+    // Rust lifetimes share the quote with char literals, so it only opens a short literal
+    char_quote: Option<char>,
+    // Whether capitalized identifiers are types, as in most C-like languages
+    capitalized_types: bool,
+    case_insensitive: bool,
+}
+
+const C_LIKE_COMMENTS: &[&str] = &["//"];
+
+static RUST: Language = Language {
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "false",
+        "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return",
+        "self", "Self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while",
+    ],
+    line_comments: C_LIKE_COMMENTS,
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"'],
+    char_quote: Some('\''),
+    capitalized_types: true,
+    case_insensitive: false,
+};
+
+static PYTHON: Language = Language {
+    keywords: &[
+        "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif", "else",
+        "except", "False", "finally", "for", "from", "global", "if", "import", "in", "is", "lambda", "None",
+        "nonlocal", "not", "or", "pass", "raise", "return", "self", "True", "try", "while", "with", "yield",
+    ],
+    line_comments: &["#"],
+    block_comment: None,
+    quotes: &['"', '\''],
+    char_quote: None,
+    capitalized_types: true,
+    case_insensitive: false,
+};
+
+static JAVASCRIPT: Language = Language {
+    keywords: &[
+        "async", "await", "break", "case", "catch", "class", "const", "continue", "default", "delete", "do",
+        "else", "export", "extends", "false", "finally", "for", "from", "function", "if", "import", "in",
+        "instanceof", "interface", "let", "new", "null", "of", "return", "static", "super", "switch", "this",
+        "throw", "true", "try", "type", "typeof", "undefined", "var", "void", "while", "yield",
+    ],
+    line_comments: C_LIKE_COMMENTS,
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\'', '`'],
+    char_quote: None,
+    capitalized_types: true,
+    case_insensitive: false,
+};
+
+static GO: Language = Language {
+    keywords: &[
+        "break", "case", "chan", "const", "continue", "default", "defer", "else", "false", "for", "func", "go",
+        "if", "import", "interface", "map", "nil", "package", "range", "return", "select", "struct", "switch",
+        "true", "type", "var",
+    ],
+    line_comments: C_LIKE_COMMENTS,
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '`'],
+    char_quote: Some('\''),
+    capitalized_types: false,
+    case_insensitive: false,
+};
+
+static C_FAMILY: Language = Language {
+    keywords: &[
+        "abstract", "auto", "bool", "break", "case", "catch", "char", "class", "const", "continue", "default",
+        "delete", "do", "double", "else", "enum", "extends", "false", "final", "float", "for", "if", "implements",
+        "import", "include", "int", "interface", "long", "namespace", "new", "null", "nullptr", "package",
+        "private", "protected", "public", "return", "short", "signed", "sizeof", "static", "struct", "switch",
+        "template", "this", "throw", "true", "try", "typedef", "union", "unsigned", "using", "virtual", "void",
+        "volatile", "while",
+    ],
+    line_comments: C_LIKE_COMMENTS,
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"'],
+    char_quote: Some('\''),
+    capitalized_types: true,
+    case_insensitive: false,
+};
+
+static SHELL: Language = Language {
+    keywords: &[
+        "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if", "in", "local",
+        "return", "then", "until", "while",
+    ],
+    line_comments: &["#"],
+    block_comment: None,
+    quotes: &['"', '\''],
+    char_quote: None,
+    capitalized_types: false,
+    case_insensitive: false,
+};
+
+static DATA: Language = Language {
+    keywords: &["true", "false", "null"],
+    line_comments: &["#"],
+    block_comment: None,
+    quotes: &['"', '\''],
+    char_quote: None,
+    capitalized_types: false,
+    case_insensitive: false,
+};
+
+static SQL: Language = Language {
+    keywords: &[
+        "and", "as", "by", "create", "delete", "desc", "from", "group", "index", "insert", "into", "join",
+        "key", "left", "limit", "not", "null", "on", "or", "order", "primary", "select", "set", "table",
+        "update", "values", "where",
+    ],
+    line_comments: &["--"],
+    block_comment: Some(("/*", "*/")),
+    quotes: &['\''],
+    char_quote: None,
+    capitalized_types: false,
+    case_insensitive: true,
+};
+
+fn language(tag: &str) -> Option<&'static Language> {
+    match tag.to_lowercase().as_str() {
+        "rust" | "rs" => Some(&RUST),
+        "python" | "py" => Some(&PYTHON),
+        "javascript" | "js" | "jsx" | "typescript" | "ts" | "tsx" | "json5" => Some(&JAVASCRIPT),
+        "go" | "golang" => Some(&GO),
+        "c" | "h" | "cpp" | "c++" | "hpp" | "java" | "kotlin" | "kt" | "cs" | "csharp" | "swift" => Some(&C_FAMILY),
+        "sh" | "bash" | "shell" | "zsh" | "console" => Some(&SHELL),
+        "json" | "toml" | "yaml" | "yml" | "ini" => Some(&DATA),
+        "sql" | "sqlite" => Some(&SQL),
+        _ => None,
+    }
+}
+
+/*
+    * Highlights one fenced code block line by line with a small lexer per language:
+    * keywords, strings, comments, numbers and type names.  Block comments can span lines
+    * so that state is kept between calls.  Code in languages without a lexer is printed
+    * in the theme's plain color.
+*/
+pub struct Highlighter {
+    language: Option<&'static Language>,
+    theme: Option<Theme>,
+    in_block_comment: bool,
+}
+
+impl Highlighter {
+    /// `tag` is the info string of the fence, e.g. `rust` or `py title="x"`.  Without a
+    /// theme lines are returned unchanged.
+    pub fn new(tag: &str, theme: Option<Theme>) -> Self {
+        let tag = tag.split_whitespace().next().unwrap_or_default();
+        Highlighter { language: language(tag), theme, in_block_comment: false }
+    }
+
+    pub fn line(&mut self, line: &str) -> String {
+        let Some(theme) = self.theme else { return line.to_string() };
+        let Some(lang) = self.language else { return format!("{}", line.with(theme.plain)) };
+        let chars: Vec<char> = line.chars().collect();
+        let mut out = String::new();
+        let mut idx = 0;
+
+        let push = |out: &mut String, text: &[char], color: Color| {
+            let text: String = text.iter().collect();
+            out.push_str(&format!("{}", text.with(color)));
+        };
+
+        while idx < chars.len() {
+            let rest = &chars[idx..];
+            if self.in_block_comment {
+                let (_, end) = lang.block_comment.expect("in a block comment without block comments");
+                let close = find(rest, end).map(|pos| pos + end.chars().count());
+                if close.is_some() {
+                    self.in_block_comment = false;
+                }
+                let len = close.unwrap_or(rest.len());
+                push(&mut out, &rest[..len], theme.comment);
+                idx += len;
+                continue;
+            }
+            if lang.line_comments.iter().any(|marker| starts_with(rest, marker)) {
+                push(&mut out, rest, theme.comment);
+                break;
+            }
+            if let Some((start, _)) = lang.block_comment.filter(|(start, _)| starts_with(rest, start)) {
+                self.in_block_comment = true;
+                push(&mut out, &rest[..start.chars().count()], theme.comment);
+                idx += start.chars().count();
+                continue;
+            }
+
+            let c = chars[idx];
+            let len = if lang.quotes.contains(&c) {
+                string_len(rest, None)
+            } else if lang.char_quote == Some(c) {
+                string_len(rest, Some(10)).filter(|&len| len <= 3 || rest[1] == '\\')
+            } else {
+                None
+            };
+            if let Some(len) = len {
+                push(&mut out, &rest[..len], theme.string);
+                idx += len;
+                continue;
+            }
+
+            if c.is_alphanumeric() || c == '_' {
+                let len = rest.iter().take_while(|ch| ch.is_alphanumeric() || **ch == '_').count();
+                let word: String = rest[..len].iter().collect();
+                let color = if c.is_ascii_digit() {
+                    theme.number
+                } else if lang.keywords.contains(&word.as_str())
+                    || (lang.case_insensitive && lang.keywords.contains(&word.to_lowercase().as_str()))
+                {
+                    theme.keyword
+                } else if lang.capitalized_types && c.is_uppercase() {
+                    theme.type_name
+                } else {
+                    theme.plain
+                };
+                push(&mut out, &rest[..len], color);
+                idx += len;
+                continue;
+            }
+
+            push(&mut out, &rest[..1], theme.plain);
+            idx += 1;
+        }
+        out
+    }
+}
+
+fn starts_with(chars: &[char], marker: &str) -> bool {
+    let marker: Vec<char> = marker.chars().collect();
+    chars.starts_with(&marker)
+}
+
+fn find(chars: &[char], marker: &str) -> Option<usize> {
+    (0..chars.len()).find(|&idx| starts_with(&chars[idx..], marker))
+}
+
+// Length of the string literal at the start of `chars` including both quotes, None if it
+// isn't closed within `max` characters or the line
+fn string_len(chars: &[char], max: Option<usize>) -> Option<usize> {
+    let quote = chars[0];
+    let mut idx = 1;
+    while idx < chars.len() && max.is_none_or(|max| idx <= max) {
+        match chars[idx] {
+            '\\' => idx += 2,
+            c if c == quote => return Some(idx + 1),
+            _ => idx += 1,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlights_rust() {
+        let theme = Theme::named("dark", 8).unwrap();
+        let mut highlighter = Highlighter::new("rust", Some(theme));
+        let line = highlighter.line("fn get<'a>(s: &'a str) -> Option<char> { let c = 'x'; \"hi\" } // done /*");
+        assert!(line.contains(&format!("{}", "fn".with(theme.keyword))));
+        assert!(line.contains(&format!("{}", "Option".with(theme.type_name))));
+        assert!(line.contains(&format!("{}", "'x'".with(theme.string))));
+        assert!(line.contains(&format!("{}", "\"hi\"".with(theme.string))));
+        assert!(line.contains(&format!("{}", "// done /*".with(theme.comment))));
+        assert!(!line.contains(&format!("{}", "'a>(s: &'".with(theme.string))));
+
+        let code = highlighter.line("/* a");
+        assert!(code.ends_with(&format!("{}", " a".with(theme.comment))));
+        assert_eq!(highlighter.line("b */"), format!("{}", "b */".with(theme.comment)));
+        assert!(!highlighter.in_block_comment);
+    }
+}
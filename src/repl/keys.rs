@@ -0,0 +1,150 @@
+use crossterm::event::{KeyCode, KeyModifiers};
+
+use crate::config::KeyBindings;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Submit,
+    Newline,
+    Cancel,
+    Editor,
+    Regenerate,
+    ApproveTool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Chord {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Chord {
+    // Parses chords like `ctrl-x`, `alt-enter` or `f5`, modifiers first
+    fn parse(text: &str) -> Result<Self, anyhow::Error> {
+        let text = text.trim().to_lowercase();
+        let mut parts: Vec<&str> = text.split('-').collect();
+        // `ctrl--` binds the minus key
+        if text.ends_with("--") {
+            parts.truncate(parts.len() - 2);
+            parts.push("-");
+        }
+        let key = parts.pop().filter(|key| !key.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("Key chord {:?} has no key", text))?;
+
+        let mut modifiers = KeyModifiers::NONE;
+        for part in parts {
+            modifiers |= match part {
+                "ctrl" | "control" | "c" => KeyModifiers::CONTROL,
+                "alt" | "meta" | "m" => KeyModifiers::ALT,
+                "shift" | "s" => KeyModifiers::SHIFT,
+                _ => return Err(anyhow::anyhow!("Unknown modifier {:?} in key chord {:?}", part, text)),
+            };
+        }
+        let code = match key {
+            "enter" | "return" => KeyCode::Enter,
+            "tab" => KeyCode::Tab,
+            "esc" | "escape" => KeyCode::Esc,
+            "space" => KeyCode::Char(' '),
+            "backspace" => KeyCode::Backspace,
+            "delete" | "del" => KeyCode::Delete,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            _ if key.chars().count() == 1 => KeyCode::Char(key.chars().next().unwrap_or_default()),
+            _ if key.starts_with('f') && key[1..].parse::<u8>().is_ok() => KeyCode::F(key[1..].parse()?),
+            _ => return Err(anyhow::anyhow!("Unknown key {:?} in key chord {:?}", key, text)),
+        };
+        Ok(Chord { code, modifiers })
+    }
+
+    fn matches(&self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        // Terminals report shifted letters as the capital with or without SHIFT
+        let (code, modifiers) = match code {
+            KeyCode::Char(c) => (KeyCode::Char(c.to_ascii_lowercase()), modifiers - KeyModifiers::SHIFT),
+            _ => (code, modifiers),
+        };
+        let wanted = match self.code {
+            KeyCode::Char(_) => self.modifiers - KeyModifiers::SHIFT,
+            _ => self.modifiers,
+        };
+        self.code == code && wanted == modifiers
+    }
+}
+
+/// The `[keys]` config resolved to chords.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: Vec<(Action, Chord)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::from_config(&KeyBindings::default()).expect("default key bindings parse")
+    }
+}
+
+impl Keymap {
+    pub fn from_config(keys: &KeyBindings) -> Result<Self, anyhow::Error> {
+        let entries = [
+            (Action::Submit, "submit", &keys.submit),
+            (Action::Newline, "newline", &keys.newline),
+            (Action::Cancel, "cancel", &keys.cancel),
+            (Action::Editor, "editor", &keys.editor),
+            (Action::Regenerate, "regenerate", &keys.regenerate),
+            (Action::ApproveTool, "approve_tool", &keys.approve_tool),
+        ];
+        let mut bindings = Vec::new();
+        for (action, name, chord) in entries {
+            let chord = Chord::parse(chord).map_err(|e| anyhow::anyhow!("keys.{} in pprog.toml: {}", name, e))?;
+            bindings.push((action, chord));
+        }
+        Ok(Keymap { bindings })
+    }
+
+    pub fn action(&self, code: KeyCode, modifiers: KeyModifiers) -> Option<Action> {
+        self.bindings.iter().find(|(_, chord)| chord.matches(code, modifiers)).map(|(action, _)| *action)
+    }
+
+    /// How the chord for `action` was written in the config, for hints.
+    pub fn describe(keys: &KeyBindings, action: Action) -> String {
+        let chord = match action {
+            Action::Submit => &keys.submit,
+            Action::Newline => &keys.newline,
+            Action::Cancel => &keys.cancel,
+            Action::Editor => &keys.editor,
+            Action::Regenerate => &keys.regenerate,
+            Action::ApproveTool => &keys.approve_tool,
+        };
+        chord.split('-')
+            .map(|part| {
+                let mut chars = part.chars();
+                chars.next().map(|first| first.to_uppercase().chain(chars).collect()).unwrap_or_else(|| "-".to_string())
+            })
+            .collect::<Vec<String>>()
+            .join("-")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chords() -> Result<(), anyhow::Error> {
+        let keymap = Keymap::from_config(&KeyBindings { editor: "Ctrl-E".to_string(), ..Default::default() })?;
+        assert_eq!(keymap.action(KeyCode::Char('e'), KeyModifiers::CONTROL), Some(Action::Editor));
+        assert_eq!(keymap.action(KeyCode::Char('x'), KeyModifiers::CONTROL), None);
+        assert_eq!(keymap.action(KeyCode::Enter, KeyModifiers::NONE), Some(Action::Submit));
+        assert_eq!(keymap.action(KeyCode::Enter, KeyModifiers::ALT), Some(Action::Newline));
+        assert_eq!(Chord::parse("ctrl--")?, Chord { code: KeyCode::Char('-'), modifiers: KeyModifiers::CONTROL });
+        assert_eq!(Chord::parse("f5")?.code, KeyCode::F(5));
+        assert!(Chord::parse("hyper-x").is_err());
+        assert_eq!(Keymap::describe(&KeyBindings::default(), Action::Editor), "Ctrl-X");
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+use crossterm::style::Stylize;
+
+use super::highlight::{Highlighter, Theme};
+
+/*
+    * Renders markdown for the terminal as it streams in.  Text is buffered until a line is
+    * complete since most constructs can only be recognized by their line, and table rows
+    * are held until the table ends so the columns can be aligned.  Covers what models
+    * actually write: headings, lists, quotes, rules, tables, fenced code and inline code,
+    * bold, italics and links.  Code blocks are highlighted with `theme` if there is one.
+*/
+#[derive(Default)]
+pub struct MarkdownRenderer {
+    pending: String,
+    theme: Option<Theme>,
+    // Set while inside a fenced code block
+    code: Option<Highlighter>,
+    table: Vec<String>,
+}
+
+impl MarkdownRenderer {
+    pub fn new(theme: Option<Theme>) -> Self {
+        MarkdownRenderer { theme, ..Default::default() }
+    }
+
+    /// Takes the next piece of the response, returns what can be printed so far.
+    pub fn push(&mut self, text: &str) -> String {
+        self.pending.push_str(text);
+        let mut out = String::new();
+        while let Some(end) = self.pending.find('\n') {
+            let line: String = self.pending.drain(..=end).collect();
+            self.line(line.trim_end_matches(['\n', '\r']), &mut out);
+        }
+        out
+    }
+
+    /// Renders whatever is still buffered at the end of a response.
+    pub fn finish(&mut self) -> String {
+        let mut out = String::new();
+        if !self.pending.is_empty() {
+            let line = std::mem::take(&mut self.pending);
+            self.line(&line, &mut out);
+        }
+        self.flush_table(&mut out);
+        self.code = None;
+        out
+    }
+
+    fn line(&mut self, line: &str, out: &mut String) {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") {
+            self.flush_table(out);
+            // The fence itself is replaced by a rule naming the language
+            let lang = trimmed.trim_start_matches('`').trim();
+            let label = match self.code.take() {
+                Some(_) => String::new(),
+                None => {
+                    self.code = Some(Highlighter::new(lang, self.theme));
+                    if lang.is_empty() { String::new() } else { format!("── {} ", lang) }
+                },
+            };
+            out.push_str(&format!("{}\n", format!("{:─<40}", label).dark_grey()));
+            return;
+        }
+        if let Some(highlighter) = self.code.as_mut() {
+            out.push_str(&highlighter.line(line));
+            out.push('\n');
+            return;
+        }
+
+        if trimmed.starts_with('|') {
+            self.table.push(trimmed.to_string());
+            return;
+        }
+        self.flush_table(out);
+
+        let indent = &line[..line.len() - trimmed.len()];
+        let rendered = if let Some((level, heading)) = heading(trimmed) {
+            let heading = inline(heading);
+            match level {
+                1 => format!("{}", heading.bold().underlined()),
+                _ => format!("{}", heading.bold()),
+            }
+        } else if is_rule(trimmed) {
+            format!("{}", "─".repeat(40).dark_grey())
+        } else if let Some(quote) = trimmed.strip_prefix('>') {
+            format!("{} {}", "│".dark_grey(), inline(quote.trim_start()).italic())
+        } else if let Some(item) = ["- ", "* ", "+ "].iter().find_map(|marker| trimmed.strip_prefix(marker)) {
+            format!("{}• {}", indent, inline(item))
+        } else {
+            format!("{}{}", indent, inline(trimmed))
+        };
+        out.push_str(&rendered);
+        out.push('\n');
+    }
+
+    fn flush_table(&mut self, out: &mut String) {
+        if self.table.is_empty() {
+            return;
+        }
+        let rows: Vec<Vec<String>> = std::mem::take(&mut self.table).iter()
+            .filter(|row| !is_separator_row(row))
+            .map(|row| split_row(row).iter().map(|cell| inline(cell)).collect())
+            .collect();
+
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let widths: Vec<usize> = (0..columns)
+            .map(|col| rows.iter().filter_map(|row| row.get(col)).map(|cell| visible_len(cell)).max().unwrap_or(0))
+            .collect();
+
+        for (idx, row) in rows.iter().enumerate() {
+            let cells: Vec<String> = (0..columns)
+                .map(|col| {
+                    let cell = row.get(col).map(String::as_str).unwrap_or("");
+                    let padding = " ".repeat(widths[col] - visible_len(cell));
+                    if idx == 0 {
+                        format!("{}{}", cell.bold(), padding)
+                    } else {
+                        format!("{}{}", cell, padding)
+                    }
+                })
+                .collect();
+            out.push_str(&cells.join(&format!(" {} ", "│".dark_grey())));
+            out.push('\n');
+        }
+    }
+}
+
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    let rest = line[level..].strip_prefix(' ')?;
+    (1..=6).contains(&level).then_some((level, rest.trim()))
+}
+
+fn is_rule(line: &str) -> bool {
+    let line = line.trim();
+    line.len() >= 3 && ['-', '*', '_'].iter().any(|&c| line.chars().all(|ch| ch == c))
+}
+
+fn is_separator_row(row: &str) -> bool {
+    row.chars().all(|c| matches!(c, '|' | '-' | ':' | ' '))
+}
+
+fn split_row(row: &str) -> Vec<&str> {
+    let row = row.trim().trim_start_matches('|');
+    let row = row.strip_suffix('|').unwrap_or(row);
+    row.split('|').map(str::trim).collect()
+}
+
+/// Length of text as shown, ignoring escape sequences.
+fn visible_len(text: &str) -> usize {
+    let mut len = 0;
+    let mut in_escape = false;
+    for c in text.chars() {
+        match c {
+            '\x1b' => in_escape = true,
+            c if in_escape => in_escape = !c.is_ascii_alphabetic(),
+            _ => len += 1,
+        }
+    }
+    len
+}
+
+// Finds the closing `marker` after position `from`, so unmatched markers are left as text
+fn closing(chars: &[char], from: usize, marker: &[char]) -> Option<usize> {
+    (from..chars.len().saturating_sub(marker.len() - 1))
+        .find(|&idx| chars[idx..].starts_with(marker))
+}
+
+/// Styles inline code, bold, italics and links within one line.
+fn inline(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::new();
+    let mut idx = 0;
+    while idx < chars.len() {
+        let c = chars[idx];
+        if c == '`' {
+            if let Some(end) = closing(&chars, idx + 1, &['`']) {
+                let code: String = chars[idx + 1..end].iter().collect();
+                out.push_str(&format!("{}", code.yellow()));
+                idx = end + 1;
+                continue;
+            }
+        }
+        if (c == '*' || c == '_') && chars.get(idx + 1) == Some(&c) {
+            if let Some(end) = closing(&chars, idx + 2, &[c, c]).filter(|&end| end > idx + 2) {
+                let bold: String = chars[idx + 2..end].iter().collect();
+                out.push_str(&format!("{}", inline(&bold).bold()));
+                idx = end + 2;
+                continue;
+            }
+        }
+        // Single markers only count when they hug a word, so `a * b` stays as written
+        if (c == '*' || c == '_') && chars.get(idx + 1).is_some_and(|next| !next.is_whitespace())
+            && (idx == 0 || !chars[idx - 1].is_alphanumeric())
+        {
+            if let Some(end) = closing(&chars, idx + 1, &[c]).filter(|&end| end > idx + 1 && !chars[end - 1].is_whitespace()) {
+                let italic: String = chars[idx + 1..end].iter().collect();
+                out.push_str(&format!("{}", inline(&italic).italic()));
+                idx = end + 1;
+                continue;
+            }
+        }
+        if c == '[' {
+            if let Some(mid) = closing(&chars, idx + 1, &[']', '(']) {
+                if let Some(end) = closing(&chars, mid + 2, &[')']) {
+                    let label: String = chars[idx + 1..mid].iter().collect();
+                    let url: String = chars[mid + 2..end].iter().collect();
+                    out.push_str(&format!("{} {}", label.underlined(), format!("({})", url).dark_grey()));
+                    idx = end + 1;
+                    continue;
+                }
+            }
+        }
+        out.push(c);
+        idx += 1;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(text: &str) -> String {
+        let mut out = String::new();
+        let mut in_escape = false;
+        for c in text.chars() {
+            match c {
+                '\x1b' => in_escape = true,
+                c if in_escape => in_escape = !c.is_ascii_alphabetic(),
+                c => out.push(c),
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_renders_streamed_markdown() {
+        let mut renderer = MarkdownRenderer::default();
+        let mut out = String::new();
+        let text = "## Plan\n- read `main.rs`\n- **fix** the *bug*, a * b\n\n| file | lines |\n|---|---:|\n| a.rs | 1 |\n| main.rs | 120 |\n```rust\nlet x = *y;\n```\ndone";
+        for chunk in text.as_bytes().chunks(3) {
+            out.push_str(&renderer.push(std::str::from_utf8(chunk).unwrap()));
+        }
+        out.push_str(&renderer.finish());
+
+        let lines: Vec<String> = strip(&out).lines().map(|line| line.trim_end().to_string()).collect();
+        assert_eq!(lines, vec![
+            "Plan",
+            "• read main.rs",
+            "• fix the bug, a * b",
+            "",
+            "file    │ lines",
+            "a.rs    │ 1",
+            "main.rs │ 120",
+            &format!("── rust {}", "─".repeat(32)),
+            "let x = *y;",
+            &"─".repeat(40),
+            "done",
+        ]);
+    }
+}
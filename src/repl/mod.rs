@@ -0,0 +1,725 @@
+pub mod approve;
+mod commands;
+pub mod compose;
+mod editor;
+mod highlight;
+mod keys;
+mod markdown;
+mod progress;
+mod theme;
+pub mod tui;
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent};
+use crossterm::style::SetForegroundColor;
+use crossterm::{cursor, queue, terminal, Command};
+use similar::TextDiff;
+
+use tokio::sync::mpsc;
+
+use crate::agent::{self, AgentEvent, ApprovalRequest, CancelToken, Controls, Decision, KeyRequest, SecretRequest};
+use crate::chat::Chat;
+use crate::config::ProjectConfig;
+use crate::cost;
+use crate::inference::stream::StreamEvent;
+use crate::inference::types::{self, Message, StopReason, Usage};
+use crate::memory::ProjectMemory;
+use crate::mentions;
+use crate::notify;
+use crate::plan::{self, PLAN_TOOL};
+use crate::secrets::SecretAction;
+use crate::todo::{self, TODO_TOOL};
+use crate::tree::GitTree;
+use editor::{History, LineEditor, ReadResult};
+use highlight::Theme;
+use keys::{Action, Keymap};
+use markdown::MarkdownRenderer;
+use progress::Progress;
+use theme::Palette;
+
+const PROMPT: &str = "> ";
+
+// Lines of a pending diff printed under a tool call, the rest is summarized
+const DIFF_PREVIEW_LINES: usize = 60;
+
+// How often the status line is redrawn while nothing else is printed
+const STATUS_INTERVAL: Duration = Duration::from_millis(100);
+
+// Input history in the data dir, shared by every REPL session in the workspace
+const HISTORY_FILE: &str = "history";
+
+// One line describing a tool call, using the argument that identifies what it acts on
+pub(crate) fn describe_tool(name: &str, input: &serde_json::Value) -> String {
+    let detail = ["path", "cmd", "statement", "note", "description", "query"].iter()
+        .find_map(|field| input.get(field).and_then(|value| value.as_str()))
+        .unwrap_or_default();
+    let detail = detail.lines().next().unwrap_or_default();
+    format!("{} {}", name, detail).trim_end().to_string()
+}
+
+/// The line `-v` prints after a turn, `usage` summed over the turn's responses.
+pub(crate) fn usage_footer(usage: &Usage, cost: f64, elapsed: Duration, first_token: Option<Duration>, rate: Option<f64>, stop_reason: Option<&str>) -> String {
+    let mut parts = vec![format!("{} in", usage.input_tokens)];
+    if usage.cache_creation_input_tokens > 0 || usage.cache_read_input_tokens > 0 {
+        parts.push(format!("{} cache write, {} cache read", usage.cache_creation_input_tokens, usage.cache_read_input_tokens));
+    }
+    parts.push(format!("{} out", usage.output_tokens));
+    parts.push(format!("${:.4}", cost));
+    let mut time = format!("{:.1}s", elapsed.as_secs_f64());
+    if let Some(first) = first_token {
+        time.push_str(&format!(", first token {:.1}s", first.as_secs_f64()));
+    }
+    if let Some(rate) = rate {
+        time.push_str(&format!(", {:.0} tok/s", rate));
+    }
+    parts.push(time);
+    parts.push(stop_reason.unwrap_or("no response").to_string());
+    parts.join(" · ")
+}
+
+/*
+    * The change a file writing tool call is about to make, as a unified diff against the file
+    * on disk.  Tool calls are reported before they run, so this shows what will change rather
+    * than what did.  None for tools that don't write files.
+*/
+pub(crate) fn pending_diff(name: &str, input: &serde_json::Value) -> Option<String> {
+    let (path, content) = match name {
+        "write_file" => (input["path"].as_str()?, input["content"].as_str()?),
+        _ => return None,
+    };
+    let previous = GitTree::get_git_root()
+        .ok()
+        .and_then(|root| std::fs::read_to_string(root.join(path)).ok());
+    let (old_header, old) = match &previous {
+        Some(previous) => (path.to_string(), previous.as_str()),
+        None => ("/dev/null".to_string(), ""),
+    };
+    Some(TextDiff::from_lines(old, content).unified_diff().header(&old_header, path).to_string())
+}
+
+struct Printer {
+    // Whether the cursor is mid-line after streamed text
+    mid_line: bool,
+    markdown: Option<MarkdownRenderer>,
+    palette: Palette,
+    // The status line, None when stdout isn't a terminal
+    progress: Option<Progress>,
+    status_shown: bool,
+    // For the -v footer
+    usage: Usage,
+    stop_reason: Option<StopReason>,
+    first_token: Option<Instant>,
+    // Time the responses spent writing, for the output rate
+    generating: Duration,
+}
+
+impl Printer {
+    fn event(&mut self, event: AgentEvent) {
+        let mut stdout = io::stdout();
+        self.clear_status();
+        match &event {
+            AgentEvent::Stream(StreamEvent::Text(_) | StreamEvent::ToolInput(_)) => {
+                self.first_token.get_or_insert_with(Instant::now);
+            },
+            AgentEvent::Stream(StreamEvent::Done { stop_reason, usage, timing, .. }) => {
+                if let Some(usage) = usage {
+                    self.usage.add(usage);
+                }
+                self.stop_reason = Some(stop_reason.clone());
+                self.generating += timing.generating();
+            },
+            _ => {},
+        }
+        if let Some(progress) = self.progress.as_mut() {
+            match &event {
+                AgentEvent::Stream(event) => progress.stream(event),
+                AgentEvent::ToolStart { name, input, .. } => progress.tool_started(describe_tool(name, input)),
+                AgentEvent::ToolDone { .. } => progress.tool_done(),
+                AgentEvent::Steer { .. } => {},
+            }
+        }
+        match event {
+            AgentEvent::Stream(StreamEvent::Text(text)) => match self.markdown.as_mut() {
+                Some(renderer) => {
+                    let rendered = renderer.push(text);
+                    print!("{}", self.assistant_text(&rendered));
+                },
+                None => {
+                    print!("{}", self.assistant_text(text));
+                    self.mid_line = !text.ends_with('\n');
+                },
+            },
+            AgentEvent::Stream(_) => {},
+            AgentEvent::ToolStart { name, input, .. } => {
+                self.end_line();
+                println!("{}", self.palette.tool(format!("  > {}", describe_tool(name, input))));
+                if let Some(proposed) = input["plan"].as_str().filter(|_| name == PLAN_TOOL) {
+                    for step in plan::numbered(proposed).lines() {
+                        println!("    {}", self.assistant_text(step));
+                    }
+                }
+                if let Some(items) = input["items"].as_str().filter(|_| name == TODO_TOOL).and_then(|items| todo::parse(items).ok()) {
+                    for line in todo::render(&items).lines() {
+                        println!("    {}", self.assistant_text(line));
+                    }
+                }
+                if let Some(diff) = pending_diff(name, input) {
+                    self.print_diff(&diff);
+                }
+            },
+            AgentEvent::ToolDone { name, output, .. } => {
+                println!("{}", self.palette.tool(format!("    {}: {} lines", name, output.lines().count())));
+            },
+            AgentEvent::Steer { text } => {
+                self.end_line();
+                println!("{}", self.palette.info(format!("  (sent with the tool results: {})", text)));
+            },
+        }
+        self.draw_status();
+        let _ = stdout.flush();
+    }
+
+    /*
+        * The status line sits on the line below the reply and is erased before anything
+        * else is printed.  Raw text streaming in leaves the cursor mid-line, so the status is
+        * only drawn once the line is done; rendered markdown always prints whole lines.
+    */
+    fn draw_status(&mut self) {
+        let Some(progress) = self.progress.as_ref() else { return };
+        if self.mid_line {
+            return;
+        }
+        let width = terminal::size().ok().filter(|(cols, _)| *cols > 0).map_or(80, |(cols, _)| cols as usize) - 1;
+        let mut stdout = io::stdout();
+        let _ = queue!(stdout, cursor::MoveToColumn(0), terminal::Clear(terminal::ClearType::CurrentLine));
+        print!("{}", self.palette.info(progress.line(Instant::now(), width)));
+        let _ = stdout.flush();
+        self.status_shown = true;
+    }
+
+    fn clear_status(&mut self) {
+        if self.status_shown {
+            let _ = queue!(io::stdout(), cursor::MoveToColumn(0), terminal::Clear(terminal::ClearType::CurrentLine));
+            self.status_shown = false;
+        }
+    }
+
+    fn print_diff(&self, diff: &str) {
+        let lines: Vec<&str> = diff.lines().collect();
+        if lines.is_empty() {
+            println!("{}", self.palette.tool("    (no changes)"));
+        }
+        for line in lines.iter().take(DIFF_PREVIEW_LINES) {
+            println!("    {}", Palette::paint(self.palette.diff_line(line), line));
+        }
+        if lines.len() > DIFF_PREVIEW_LINES {
+            println!("{}", self.palette.tool(format!("    ... {} more lines", lines.len() - DIFF_PREVIEW_LINES)));
+        }
+    }
+
+    // Spans in rendered markdown end by going back to the default color, which has to be
+    // the assistant's when it has one
+    fn assistant_text(&self, text: &str) -> String {
+        let Some(color) = self.palette.assistant.filter(|_| !text.is_empty()) else { return text.to_string() };
+        let mut set = String::new();
+        let _ = SetForegroundColor(color).write_ansi(&mut set);
+        format!("{}{}\x1b[39m", set, text.replace("\x1b[39m", &set))
+    }
+
+    fn end_line(&mut self) {
+        if let Some(renderer) = self.markdown.as_mut() {
+            let rendered = renderer.finish();
+            print!("{}", self.assistant_text(&rendered));
+        }
+        if self.mid_line {
+            println!();
+            self.mid_line = false;
+        }
+    }
+}
+
+struct Style {
+    markdown: bool,
+    theme: Option<Theme>,
+    palette: Palette,
+    status: bool,
+    verbose: bool,
+}
+
+// What the user can do during a turn: answer the tool calls waiting with --approve-each, say
+// what is done with secrets about to be sent, give a new key for one rejected and send
+// messages that steer it
+struct TurnInput {
+    controls: Controls,
+    requests: Option<mpsc::UnboundedReceiver<ApprovalRequest>>,
+    secret_requests: mpsc::UnboundedReceiver<SecretRequest>,
+    key_requests: mpsc::UnboundedReceiver<KeyRequest>,
+    steer: mpsc::UnboundedSender<String>,
+}
+
+// A question that holds up the turn until it's answered at the prompt
+enum Waiting {
+    Approval(ApprovalRequest),
+    Secrets(SecretRequest),
+    Key(KeyRequest),
+}
+
+/*
+    * Reads lines typed while a turn runs and queues them to steer it.  The terminal stays in
+    * cooked mode so output and Ctrl-C work as usual, which also means the terminal echoes the
+    * typing and a line only arrives once Enter is pressed.  Stopped while anything else reads
+    * the terminal, so it can't take their keys.
+*/
+struct SteeringReader {
+    stop: Arc<AtomicBool>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+impl SteeringReader {
+    fn start(steer: mpsc::UnboundedSender<String>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread = std::thread::spawn(move || {
+            let mut line = String::new();
+            while !stopped.load(Ordering::Relaxed) {
+                if !event::poll(STATUS_INTERVAL).unwrap_or(false) {
+                    continue;
+                }
+                match event::read() {
+                    Ok(Event::Key(KeyEvent { code: KeyCode::Enter, .. })) if !line.trim().is_empty() => {
+                        let _ = steer.send(std::mem::take(&mut line).trim().to_string());
+                    },
+                    Ok(Event::Key(KeyEvent { code: KeyCode::Char(c), .. })) => line.push(c),
+                    Ok(_) => {},
+                    Err(_) => break,
+                }
+            }
+        });
+        SteeringReader { stop, thread }
+    }
+
+    fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}
+
+// Asks about a tool call at the prompt, a prompt left with Ctrl-C or Ctrl-D denies it
+fn ask_approval(editor: &mut LineEditor, input: &serde_json::Value, palette: Palette) -> Decision {
+    loop {
+        let answer = match tokio::task::block_in_place(|| editor.read_line(approve::PROMPT)) {
+            Ok(ReadResult::Line(answer)) => answer,
+            Ok(ReadResult::Approve) => return Decision::Approve,
+            Ok(ReadResult::Regenerate) => continue,
+            Ok(ReadResult::Interrupted | ReadResult::Eof) => return Decision::Deny(String::new()),
+            Err(e) => {
+                eprintln!("{}", palette.error(format!("Error: {}", e)));
+                return Decision::Deny(String::new());
+            },
+        };
+        match approve::decision(&answer, input) {
+            Ok(Some(decision)) => return decision,
+            Ok(None) => {},
+            Err(e) => eprintln!("{}", palette.error(format!("Error: {}", e))),
+        }
+    }
+}
+
+// Asks what to do with the secrets found in a message, a prompt left with Ctrl-C or Ctrl-D blocks it
+fn ask_secrets(editor: &mut LineEditor, request: &SecretRequest, palette: Palette) -> SecretAction {
+    for line in &request.found {
+        println!("{}", palette.error(format!("  ! {}", line)));
+    }
+    loop {
+        match tokio::task::block_in_place(|| editor.read_line(approve::SECRETS_PROMPT)) {
+            Ok(ReadResult::Line(answer)) => {
+                if let Some(action) = approve::secret_action(&answer, request.default) {
+                    return action;
+                }
+            },
+            Ok(ReadResult::Approve) => return request.default,
+            Ok(ReadResult::Regenerate) => {},
+            Ok(ReadResult::Interrupted | ReadResult::Eof) => return SecretAction::Block,
+            Err(e) => {
+                eprintln!("{}", palette.error(format!("Error: {}", e)));
+                return SecretAction::Block;
+            },
+        }
+    }
+}
+
+async fn turn(chat: &mut Chat, message: Message, pin: bool, style: &Style, cancel: &mut CancelToken, editor: &mut LineEditor, input: &mut TurnInput) {
+    let printer = Arc::new(Mutex::new(Printer {
+        mid_line: false,
+        markdown: style.markdown.then(|| MarkdownRenderer::new(style.theme)),
+        palette: style.palette,
+        progress: style.status.then(Progress::new),
+        status_shown: false,
+        usage: Usage::default(),
+        stop_reason: None,
+        first_token: None,
+        generating: Duration::ZERO,
+    }));
+    // Keeps the clock on the status line going while a tool runs or the model is quiet
+    let ticker = style.status.then(|| {
+        let printer = printer.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(STATUS_INTERVAL).await;
+                printer.lock().unwrap_or_else(PoisonError::into_inner).draw_status();
+            }
+        })
+    });
+    let started = Instant::now();
+    let cost_before = chat.session_cost().unwrap_or_default();
+    let outcome = {
+        let TurnInput { controls, requests, secret_requests, key_requests, steer } = input;
+        let mut reader = Some(SteeringReader::start(steer.clone()));
+        let mut on_event = |event: AgentEvent| printer.lock().unwrap_or_else(PoisonError::into_inner).event(event);
+        let run = agent::run_turn(chat, message, &mut on_event, controls, cancel);
+        tokio::pin!(run);
+        let outcome = loop {
+            let request = async {
+                match requests.as_mut() {
+                    Some(requests) => requests.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            let waiting = tokio::select! {
+                outcome = &mut run => break outcome,
+                Some(request) = request => Waiting::Approval(request),
+                Some(request) = secret_requests.recv() => Waiting::Secrets(request),
+                Some(request) = key_requests.recv() => Waiting::Key(request),
+            };
+            // The status line would be drawn over the question
+            let progress = {
+                let mut printer = printer.lock().unwrap_or_else(PoisonError::into_inner);
+                printer.clear_status();
+                printer.progress.take()
+            };
+            if let Some(reader) = reader.take() {
+                reader.stop();
+            }
+            match waiting {
+                Waiting::Approval(request) => {
+                    if let Some(reason) = &request.reason {
+                        eprintln!("{}", style.palette.error(format!("! {}", reason)));
+                    }
+                    let decision = ask_approval(editor, &request.input, style.palette);
+                    let _ = request.reply.send(decision);
+                },
+                Waiting::Secrets(request) => {
+                    let action = ask_secrets(editor, &request, style.palette);
+                    let _ = request.reply.send(action);
+                },
+                Waiting::Key(request) => {
+                    let key = tokio::task::block_in_place(|| approve::ask_key_on_tty(&request.provider, request.failure));
+                    let _ = request.reply.send(key);
+                },
+            }
+            reader = Some(SteeringReader::start(steer.clone()));
+            printer.lock().unwrap_or_else(PoisonError::into_inner).progress = progress;
+        };
+        if let Some(reader) = reader {
+            reader.stop();
+        }
+        outcome
+    };
+    if let Some(ticker) = ticker {
+        ticker.abort();
+    }
+    let mut printer = printer.lock().unwrap_or_else(PoisonError::into_inner);
+    printer.clear_status();
+    printer.progress = None;
+    printer.end_line();
+
+    let cost = chat.session_cost().unwrap_or_default() - cost_before;
+    if style.verbose {
+        let first_token = printer.first_token.map(|first| first.duration_since(started));
+        let rate = types::output_rate(printer.usage.output_tokens, printer.generating);
+        println!("{}", style.palette.info(usage_footer(&printer.usage, cost, started.elapsed(), first_token, rate, printer.stop_reason.as_ref().map(StopReason::as_str))));
+    }
+    drop(printer);
+    if !cancel.is_cancelled() {
+        let error = outcome.as_ref().err().map(|e| e.to_string());
+        notify::turn_finished(chat.config().notify_after_secs, started.elapsed(), None, error.as_deref(), cost);
+    }
+
+    match outcome {
+        Ok(outcome) => {
+            if pin {
+                if let Err(e) = chat.pin_last_turn() {
+                    eprintln!("{}", style.palette.error(format!("Could not pin message: {}", e)));
+                }
+            }
+            if outcome.incomplete {
+                println!("{}", style.palette.info("(reply was cut off, press Enter on an empty line to continue)"));
+            }
+        },
+        Err(e) => eprintln!("{}", style.palette.error(format!("Error: {}", e))),
+    }
+}
+
+// Expands a `/snippet` line, asking at the prompt for placeholders it didn't give values for
+fn fill_snippet(editor: &mut LineEditor, arg: &str) -> Result<Option<String>, anyhow::Error> {
+    let (snippet, mut values) = commands::snippet(arg)?;
+    for name in snippet.placeholders() {
+        if values.contains_key(&name) {
+            continue;
+        }
+        match tokio::task::block_in_place(|| editor.read_line(&format!("{}: ", name)))? {
+            ReadResult::Line(value) => values.insert(name, value),
+            _ => return Ok(None),
+        };
+    }
+    snippet.expand(&values).map(Some)
+}
+
+/// What the user made of a proposed plan.
+enum PlanReview {
+    Approve(String),
+    Change(String),
+    Discard,
+    Ask,
+    Quit,
+}
+
+// Asks about the proposed plan: y runs it, e edits it in $EDITOR before running it, and
+// any other text is sent for the model to revise it
+fn review_plan(editor: &mut LineEditor, plan: &str) -> Result<PlanReview, anyhow::Error> {
+    let answer = match tokio::task::block_in_place(|| editor.read_line("Run this plan? [y]es, [e]dit, or say what to change: "))? {
+        ReadResult::Line(answer) => answer,
+        ReadResult::Interrupted => return Ok(PlanReview::Discard),
+        ReadResult::Eof => return Ok(PlanReview::Quit),
+        ReadResult::Regenerate => return Ok(PlanReview::Ask),
+        ReadResult::Approve => return Ok(PlanReview::Approve(plan.to_string())),
+    };
+    Ok(match answer.trim() {
+        "" => PlanReview::Ask,
+        "y" | "yes" => PlanReview::Approve(plan.to_string()),
+        "e" | "edit" => match compose::compose(&plan::numbered(plan))? {
+            Some(edited) => PlanReview::Approve(edited),
+            None => PlanReview::Ask,
+        },
+        _ => PlanReview::Change(answer),
+    })
+}
+
+/// Renders markdown with terminal styling, as replies are shown in the REPL.
+pub fn render_markdown(text: &str, config: &ProjectConfig) -> String {
+    let theme = Theme::named(config.code_theme(), crossterm::style::available_color_count());
+    let mut renderer = MarkdownRenderer::new(theme);
+    let mut rendered = renderer.push(text);
+    rendered.push_str(&renderer.finish());
+    rendered
+}
+
+/// How the REPL presents itself, from the `chat` flags.
+pub struct Options {
+    /// Print replies as-is instead of rendering markdown
+    pub raw: bool,
+    /// Write the first message in $EDITOR
+    pub editor: bool,
+    /// Print usage, cost and latency after every turn
+    pub verbose: bool,
+    /// Start in plan mode
+    pub plan: bool,
+    /// Ask before every tool call
+    pub approve_each: bool,
+    /// Go on past a soft budget with `require_force`
+    pub force: bool,
+}
+
+/*
+    * Interactive chat in the terminal.  Ctrl-C while a reply is running cancels it and
+    * keeps what arrived so far, at the prompt it discards the line.  Ctrl-D quits.  The
+    * conversation is the chat's session, so it is saved after every turn like in the
+    * server and can be resumed with `--session`.  Replies are rendered as markdown unless
+    * `raw` is set or stdout isn't a terminal.
+*/
+pub async fn run(mut chat: Chat, options: Options) -> Result<(), anyhow::Error> {
+    let theme_name = chat.config().code_theme();
+    let theme = Theme::named(theme_name, crossterm::style::available_color_count());
+    if theme.is_none() && theme_name != "none" {
+        log::warn!("Unknown highlight_theme {:?}, code blocks won't be highlighted", theme_name);
+    }
+    let palette = Palette::from_config(&chat.config().theme)?;
+    let style = Style { markdown: !options.raw && io::stdout().is_terminal(), theme, palette, status: io::stdout().is_terminal(), verbose: options.verbose };
+    let (source, mut cancel) = agent::cancel_pair();
+    // Installing the handler also stops Ctrl-C from killing the process mid-request.  At the
+    // prompt the terminal is in raw mode and the editor reads Ctrl-C as a key instead.
+    tokio::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            source.cancel();
+        }
+    });
+
+    let mut editor = LineEditor::new();
+    editor.set_keymap(Keymap::from_config(&chat.config().keys)?);
+    match ProjectConfig::data_dir().and_then(|dir| History::load(dir.join(HISTORY_FILE))) {
+        Ok(history) => editor.set_history(history),
+        Err(e) => log::error!("Could not load input history: {}", e),
+    }
+    editor.set_completer(|before_cursor| {
+        let mut candidates = commands::complete(before_cursor);
+        if candidates.is_empty() && before_cursor.contains('@') {
+            let files = GitTree::list_files().unwrap_or_default();
+            candidates = mentions::complete(before_cursor, &files);
+        }
+        candidates
+    });
+    println!("{}", palette.info(format!("pprog {}, session {}", env!("CARGO_PKG_VERSION"), chat.session.id)));
+    let keys = &chat.config().keys;
+    println!("{}", palette.info(format!(
+        "Ctrl-C cancels a reply, Ctrl-D quits, {} opens $EDITOR, {} regenerates, /help lists commands.",
+        Keymap::describe(keys, Action::Editor),
+        Keymap::describe(keys, Action::Regenerate),
+    )));
+
+    let (approvals, requests) = match options.approve_each {
+        true => {
+            let (approvals, requests) = agent::approval_channel();
+            (Some(approvals), Some(requests))
+        },
+        false => (None, None),
+    };
+    let (steer, steering) = mpsc::unbounded_channel();
+    let (secrets, secret_requests) = agent::secret_channel();
+    let (keys, key_requests) = agent::key_channel();
+    let controls = Controls { approvals, steering: Some(steering), budget: None, secrets: Some(secrets), keys: Some(keys) };
+    let mut input = TurnInput { controls, requests, secret_requests, key_requests, steer };
+    // Images from /image waiting for the next message
+    let mut attachments = Vec::new();
+    // Soft budgets already warned about, the check before starting warned about those crossed then
+    let mut budgets_warned = cost::crossed_soft_limits(&chat.config().budget).unwrap_or_default().iter()
+        .map(|limit| limit.period)
+        .collect();
+    let mut composed = match options.editor {
+        true => compose::compose("")?,
+        false => None,
+    };
+    loop {
+        if let Some(proposed) = chat.proposed_plan().map(str::to_string) {
+            match review_plan(&mut editor, &proposed)? {
+                PlanReview::Approve(approved) => {
+                    let message = chat.approve_plan(&approved);
+                    turn(&mut chat, message, true, &style, &mut cancel, &mut editor, &mut input).await;
+                },
+                PlanReview::Change(changes) => {
+                    editor.add_history(&changes);
+                    turn(&mut chat, mentions::user_message(&changes), false, &style, &mut cancel, &mut editor, &mut input).await;
+                },
+                PlanReview::Discard => {
+                    chat.start_planning();
+                    println!("{}", palette.info("Plan discarded, the next request gets a new one"));
+                },
+                PlanReview::Ask => {},
+                PlanReview::Quit => break,
+            }
+            continue;
+        }
+
+        // Typed during the last turn after it stopped taking messages
+        if composed.is_none() {
+            composed = input.controls.queued();
+        }
+        let line = match composed.take() {
+            Some(line) => {
+                println!("{}{}", PROMPT, Palette::paint(palette.user, &line));
+                line
+            },
+            None => match tokio::task::block_in_place(|| editor.read_line(PROMPT))? {
+                ReadResult::Line(line) => line,
+                ReadResult::Interrupted | ReadResult::Approve => continue,
+                ReadResult::Eof => break,
+                ReadResult::Regenerate => {
+                    match chat.rewind_last_turn() {
+                        Some(message) => turn(&mut chat, message, false, &style, &mut cancel, &mut editor, &mut input).await,
+                        None => eprintln!("{}", palette.error("No previous message to regenerate")),
+                    }
+                    continue;
+                },
+            },
+        };
+        editor.add_history(&line);
+
+        if line.trim().is_empty() {
+            if chat.is_incomplete() {
+                match chat.continue_message() {
+                    Ok(message) => turn(&mut chat, message, false, &style, &mut cancel, &mut editor, &mut input).await,
+                    Err(e) => eprintln!("{}", palette.error(format!("Error: {}", e))),
+                }
+            }
+            continue;
+        }
+
+        if let Some(("/snippet", Some(arg))) = commands::parse(&line) {
+            match fill_snippet(&mut editor, arg) {
+                Ok(text) => composed = text,
+                Err(e) => eprintln!("{}", palette.error(format!("Error: {}", e))),
+            }
+            continue;
+        }
+
+        if let Some((name, arg)) = commands::parse(&line) {
+            match commands::run(&mut chat, name, arg, &mut attachments).await {
+                Ok(output) => println!("{}", output),
+                Err(e) => eprintln!("{}", palette.error(format!("Error: {}", e))),
+            }
+            continue;
+        }
+
+        if let Some(note) = ProjectMemory::parse_command(&line) {
+            match ProjectMemory::remember(note) {
+                Ok(_) => println!("{}", palette.info(format!("Remembered: {}", note))),
+                Err(e) => eprintln!("{}", palette.error(format!("Could not save note: {}", e))),
+            }
+            continue;
+        }
+
+        let (text, pin) = match Chat::parse_pin_command(&line) {
+            Some(text) => (text.to_string(), true),
+            None => (line, false),
+        };
+        if let Err(e) = cost::check_soft_limits(&chat.config().budget, options.force, &mut budgets_warned) {
+            eprintln!("{}", palette.error(format!("Error: {}", e)));
+            continue;
+        }
+        let mut message = mentions::user_message(&text);
+        message.content.append(&mut attachments);
+        turn(&mut chat, message, pin, &style, &mut cancel, &mut editor, &mut input).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_tool() {
+        assert_eq!(describe_tool("read_file", &serde_json::json!({"path": "src/main.rs"})), "read_file src/main.rs");
+        assert_eq!(describe_tool("execute", &serde_json::json!({"statement": "cargo fmt\ncargo test"})), "execute cargo fmt");
+        assert_eq!(describe_tool("unknown", &serde_json::json!({})), "unknown");
+    }
+
+    #[test]
+    fn test_pending_diff_of_new_file() {
+        let input = serde_json::json!({"path": "does/not/exist.txt", "content": "one\ntwo\n"});
+        assert_eq!(pending_diff("write_file", &input).as_deref(), Some("--- /dev/null\n+++ does/not/exist.txt\n@@ -0,0 +1,2 @@\n+one\n+two\n"));
+        assert_eq!(pending_diff("read_file", &input), None);
+    }
+
+    #[test]
+    fn test_usage_footer() {
+        let usage = Usage { input_tokens: 1200, cache_read_input_tokens: 800, output_tokens: 310, ..Default::default() };
+        let rate = types::output_rate(usage.output_tokens, Duration::from_millis(2480));
+        let footer = usage_footer(&usage, 0.0123, Duration::from_millis(3420), Some(Duration::from_millis(810)), rate, Some("end_turn"));
+        assert_eq!(footer, "1200 in · 0 cache write, 800 cache read · 310 out · $0.0123 · 3.4s, first token 0.8s, 125 tok/s · end_turn");
+        assert_eq!(usage_footer(&Usage::default(), 0.0, Duration::ZERO, None, None, None), "0 in · 0 out · $0.0000 · 0.0s · no response");
+    }
+}
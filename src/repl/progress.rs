@@ -0,0 +1,105 @@
+use std::time::{Duration, Instant};
+
+use crate::inference::stream::StreamEvent;
+
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+// Streamed text is counted at about four characters a token until the provider's usage
+// arrives with the real count at the end of each response
+const CHARS_PER_TOKEN: usize = 4;
+
+/// What a running turn is doing, for the status line shown below the reply.
+pub struct Progress {
+    started: Instant,
+    // Output tokens of the responses that are done
+    tokens: u32,
+    // Characters of the response streaming now, and when its first one arrived
+    streamed: usize,
+    first_token: Option<Instant>,
+    // The tool being run and since when
+    tool: Option<(String, Instant)>,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Progress {
+            started: Instant::now(),
+            tokens: 0,
+            streamed: 0,
+            first_token: None,
+            tool: None,
+        }
+    }
+
+    pub fn stream(&mut self, event: &StreamEvent) {
+        match event {
+            StreamEvent::Text(text) | StreamEvent::Thinking(text) | StreamEvent::ToolInput(text) => {
+                self.first_token.get_or_insert_with(Instant::now);
+                self.streamed += text.chars().count();
+            },
+            StreamEvent::ToolUse { .. } => {},
+            StreamEvent::Done { usage, .. } => {
+                self.tokens += usage.as_ref().map_or((self.streamed / CHARS_PER_TOKEN) as u32, |usage| usage.output_tokens);
+                self.streamed = 0;
+                self.first_token = None;
+            },
+        }
+    }
+
+    pub fn tool_started(&mut self, description: String) {
+        self.tool = Some((description, Instant::now()));
+    }
+
+    pub fn tool_done(&mut self) {
+        self.tool = None;
+    }
+
+    /// The status line at `now`, cut to `width` columns.
+    pub fn line(&self, now: Instant, width: usize) -> String {
+        let elapsed = now.duration_since(self.started);
+        let spinner = SPINNER[(elapsed.as_millis() / 100) as usize % SPINNER.len()];
+        let tokens = self.tokens as usize + self.streamed / CHARS_PER_TOKEN;
+        let mut parts = vec![format!("{} {}", spinner, format_secs(elapsed)), format!("{} tokens", tokens)];
+
+        match (&self.tool, self.first_token) {
+            (Some((tool, since)), _) => parts.push(format!("running {} ({})", tool, format_secs(now.duration_since(*since)))),
+            (None, Some(first)) => {
+                let secs = now.duration_since(first).as_secs_f64();
+                if secs >= 1.0 {
+                    parts.push(format!("{:.0} tokens/s", (self.streamed / CHARS_PER_TOKEN) as f64 / secs));
+                }
+            },
+            (None, None) => parts.push("waiting for the model".to_string()),
+        }
+        parts.join(" · ").chars().take(width).collect()
+    }
+}
+
+fn format_secs(elapsed: Duration) -> String {
+    format!("{}s", elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inference::types::StopReason;
+    use crate::inference::types::Usage;
+
+    #[test]
+    fn test_progress_line() {
+        let mut progress = Progress::new();
+        let start = progress.started;
+        assert_eq!(progress.line(start, 80), "⠋ 0s · 0 tokens · waiting for the model");
+
+        progress.stream(&StreamEvent::Text("x".repeat(400)));
+        let first = progress.first_token.unwrap_or(start);
+        assert_eq!(progress.line(first + Duration::from_secs(2), 80).split(" · ").last(), Some("50 tokens/s"));
+
+        let usage = Usage { output_tokens: 120, ..Default::default() };
+        progress.stream(&StreamEvent::Done { stop_reason: StopReason::ToolUse, stop_sequence: None, usage: Some(usage), timing: Default::default() });
+        progress.tool_started("execute cargo test".to_string());
+        let line = progress.line(start + Duration::from_millis(4250), 80);
+        assert_eq!(line, "⠹ 4s · 120 tokens · running execute cargo test (4s)");
+        assert_eq!(progress.line(start, 12), "⠋ 0s · 120 t");
+    }
+}
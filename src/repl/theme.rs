@@ -0,0 +1,151 @@
+use std::fmt::Display;
+
+use crossterm::style::{Color, Stylize};
+
+use crate::config::ThemeConfig;
+
+/// Colors for the parts of the terminal chat, None leaves the terminal's own color.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Palette {
+    pub user: Option<Color>,
+    pub assistant: Option<Color>,
+    pub tool: Option<Color>,
+    pub info: Option<Color>,
+    pub error: Option<Color>,
+    pub diff_added: Option<Color>,
+    pub diff_removed: Option<Color>,
+    pub diff_hunk: Option<Color>,
+}
+
+// Parses a color from `[theme]`: a crossterm name, `#rrggbb`, a 256-color number or `default`
+fn parse_color(text: &str) -> Result<Option<Color>, anyhow::Error> {
+    let text = text.trim();
+    if text == "default" || text == "none" {
+        return Ok(None);
+    }
+    if let Some(hex) = text.strip_prefix('#').filter(|hex| hex.len() == 6) {
+        let channel = |idx: usize| u8::from_str_radix(&hex[idx..idx + 2], 16);
+        if let (Ok(r), Ok(g), Ok(b)) = (channel(0), channel(2), channel(4)) {
+            return Ok(Some(Color::Rgb { r, g, b }));
+        }
+    }
+    if let Ok(value) = text.parse::<u8>() {
+        return Ok(Some(Color::AnsiValue(value)));
+    }
+    Color::try_from(text.replace("gray", "grey").as_str()).map(Some).map_err(|_| anyhow::anyhow!("Unknown color {:?}", text))
+}
+
+impl Palette {
+    fn preset(name: &str) -> Option<Palette> {
+        match name {
+            "dark" => Some(Palette {
+                user: Some(Color::Cyan),
+                assistant: None,
+                tool: Some(Color::DarkGrey),
+                info: Some(Color::DarkGrey),
+                error: Some(Color::Red),
+                diff_added: Some(Color::Green),
+                diff_removed: Some(Color::Red),
+                diff_hunk: Some(Color::Cyan),
+            }),
+            "light" => Some(Palette {
+                user: Some(Color::DarkBlue),
+                assistant: None,
+                tool: Some(Color::DarkGrey),
+                info: Some(Color::DarkGrey),
+                error: Some(Color::DarkRed),
+                diff_added: Some(Color::DarkGreen),
+                diff_removed: Some(Color::DarkRed),
+                diff_hunk: Some(Color::DarkCyan),
+            }),
+            "none" => Some(Palette::default()),
+            _ => None,
+        }
+    }
+
+    /// The preset with the colors set in `[theme]`.  Everything is left uncolored when
+    /// `NO_COLOR` is set, see https://no-color.org.
+    pub fn from_config(theme: &ThemeConfig) -> Result<Self, anyhow::Error> {
+        if std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+            return Ok(Palette::default());
+        }
+        Self::configured(theme)
+    }
+
+    fn configured(theme: &ThemeConfig) -> Result<Self, anyhow::Error> {
+        let mut palette = Self::preset(&theme.preset)
+            .ok_or_else(|| anyhow::anyhow!("theme.preset in pprog.toml: unknown preset {:?}, use dark, light or none", theme.preset))?;
+        let overrides = [
+            ("user", &theme.user, &mut palette.user),
+            ("assistant", &theme.assistant, &mut palette.assistant),
+            ("tool", &theme.tool, &mut palette.tool),
+            ("info", &theme.info, &mut palette.info),
+            ("error", &theme.error, &mut palette.error),
+            ("diff_added", &theme.diff_added, &mut palette.diff_added),
+            ("diff_removed", &theme.diff_removed, &mut palette.diff_removed),
+            ("diff_hunk", &theme.diff_hunk, &mut palette.diff_hunk),
+        ];
+        for (name, value, color) in overrides {
+            if let Some(value) = value {
+                *color = parse_color(value).map_err(|e| anyhow::anyhow!("theme.{} in pprog.toml: {}", name, e))?;
+            }
+        }
+        Ok(palette)
+    }
+
+    /// `text` in `color`, as-is for None.
+    pub fn paint(color: Option<Color>, text: impl Display) -> String {
+        match color {
+            Some(color) => text.to_string().with(color).to_string(),
+            None => text.to_string(),
+        }
+    }
+
+    pub fn info(&self, text: impl Display) -> String {
+        Self::paint(self.info, text)
+    }
+
+    pub fn error(&self, text: impl Display) -> String {
+        Self::paint(self.error, text)
+    }
+
+    pub fn tool(&self, text: impl Display) -> String {
+        Self::paint(self.tool, text)
+    }
+
+    /// The color of a line of a unified diff.
+    pub fn diff_line(&self, line: &str) -> Option<Color> {
+        match line.chars().next() {
+            Some('+') => self.diff_added,
+            Some('-') => self.diff_removed,
+            Some('@') => self.diff_hunk,
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_palette_from_config() -> Result<(), anyhow::Error> {
+        let theme = ThemeConfig {
+            preset: "light".to_string(),
+            user: Some("#ff8700".to_string()),
+            tool: Some("244".to_string()),
+            error: Some("default".to_string()),
+            ..Default::default()
+        };
+        let palette = Palette::configured(&theme)?;
+        assert_eq!(palette.user, Some(Color::Rgb { r: 255, g: 135, b: 0 }));
+        assert_eq!(palette.tool, Some(Color::AnsiValue(244)));
+        assert_eq!(palette.error, None);
+        assert_eq!(palette.diff_line("+added"), Some(Color::DarkGreen));
+
+        assert!(Palette::configured(&ThemeConfig { preset: "solarized".to_string(), ..Default::default() }).is_err());
+        assert!(Palette::configured(&ThemeConfig { info: Some("dark_grey".to_string()), ..Default::default() }).is_ok());
+        assert!(Palette::configured(&ThemeConfig { info: Some("dim".to_string()), ..Default::default() }).is_err());
+        Ok(())
+    }
+}
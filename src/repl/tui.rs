@@ -0,0 +1,631 @@
+use std::io::{self, Write};
+use std::time::Instant;
+
+use crossterm::{
+    cursor,
+    event::{self, DisableFocusChange, EnableFocusChange, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{Attribute, Color, Print, SetAttribute, SetForegroundColor},
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use tokio::sync::mpsc;
+
+use crate::agent::{self, AgentEvent, ApprovalRequest, Controls, Decision};
+use crate::chat::Chat;
+use crate::config::KeyBindings;
+use crate::inference::stream::StreamEvent;
+use crate::memory::ProjectMemory;
+use crate::mentions;
+use crate::notify;
+use crate::plan;
+use crate::todo::{self, TODO_TOOL};
+use super::approve;
+use super::commands;
+use super::editor::LineState;
+use super::keys::{self, Keymap};
+use super::theme::Palette;
+use super::{describe_tool, pending_diff};
+
+// Lines of tool output kept, older ones scroll away
+const TOOL_OUTPUT_LIMIT: usize = 500;
+
+// Restores the terminal however the TUI exits, including on panic
+struct Screen;
+
+impl Screen {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, EnableFocusChange)?;
+        Ok(Screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), DisableFocusChange, cursor::Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Agent events turned into owned values so they can be queued while the turn runs.
+enum Update {
+    Text(String),
+    ToolStart(String),
+    ToolDone { name: String, output: String },
+    Diff(String),
+    Steer(String),
+    Todos(String),
+}
+
+impl Update {
+    fn from_event(event: AgentEvent) -> Vec<Update> {
+        match event {
+            AgentEvent::Stream(StreamEvent::Text(text)) => vec![Update::Text(text.clone())],
+            AgentEvent::Stream(_) => vec![],
+            AgentEvent::ToolStart { name, input, .. } => {
+                let mut updates = vec![Update::ToolStart(describe_tool(name, input))];
+                updates.extend(pending_diff(name, input).map(Update::Diff));
+                if let Some(items) = input["items"].as_str().filter(|_| name == TODO_TOOL).and_then(|items| todo::parse(items).ok()) {
+                    updates.push(Update::Todos(todo::render(&items)));
+                }
+                updates
+            },
+            AgentEvent::ToolDone { name, output, .. } => vec![Update::ToolDone { name: name.to_string(), output: output.to_string() }],
+            AgentEvent::Steer { text } => vec![Update::Steer(text.to_string())],
+        }
+    }
+}
+
+/// Splits text into lines of at most `width` characters, breaking at spaces where possible.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    for raw in text.replace('\t', "    ").split('\n') {
+        let mut line = String::new();
+        let mut len = 0;
+        for word in raw.split_inclusive(' ') {
+            let mut word: Vec<char> = word.chars().collect();
+            if len + word.len() > width && len > 0 {
+                lines.push(std::mem::take(&mut line).trim_end().to_string());
+                len = 0;
+            }
+            // Words longer than the line are cut
+            while word.len() > width {
+                lines.push(word.drain(..width).collect());
+            }
+            len += word.len();
+            line.extend(word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+struct StyledLine {
+    text: String,
+    color: Color,
+}
+
+fn styled(text: &str, width: usize, color: Color) -> Vec<StyledLine> {
+    wrap(text, width).into_iter().map(|text| StyledLine { text, color }).collect()
+}
+
+// Colors left to the terminal become Reset
+fn color(color: Option<Color>) -> Color {
+    color.unwrap_or(Color::Reset)
+}
+
+enum Entry {
+    User(String),
+    Assistant(String),
+    Tool(String),
+    Notice(String),
+    Error(String),
+}
+
+enum Action {
+    Submit(String),
+    Regenerate,
+    Cancel,
+    Approve,
+    Quit,
+}
+
+/*
+    * Full screen mode for long agent sessions.  The conversation takes the left pane, tool
+    * output and the diffs of files written during the session are on the right, and a status
+    * bar shows the model, how full the context is and what the session has cost so far.
+*/
+struct App {
+    conversation: Vec<Entry>,
+    tool_output: Vec<String>,
+    diffs: Vec<String>,
+    // The agent's task list, rendered
+    todos: String,
+    input: LineState,
+    // Conversation lines scrolled up from the bottom
+    scroll: usize,
+    busy: bool,
+    keymap: Keymap,
+    // Status bar hints while a turn runs, while idle and while a tool call waits for approval
+    hints: (String, String, String),
+    approving: bool,
+    // From focus events, None until the terminal sends one
+    focused: Option<bool>,
+    status: String,
+    palette: Palette,
+}
+
+impl App {
+    fn new(keys: &KeyBindings, palette: Palette) -> Result<Self, anyhow::Error> {
+        let hints = (
+            format!("{} cancel", Keymap::describe(keys, keys::Action::Cancel)),
+            format!(
+                "{} send, {} regenerate, PgUp/PgDn scroll, Ctrl-D quit",
+                Keymap::describe(keys, keys::Action::Submit),
+                Keymap::describe(keys, keys::Action::Regenerate),
+            ),
+            format!(
+                "y run, n or why not to deny, a always allow, e edit the arguments, {} run",
+                Keymap::describe(keys, keys::Action::ApproveTool),
+            ),
+        );
+        Ok(App {
+            conversation: Vec::new(),
+            tool_output: Vec::new(),
+            diffs: Vec::new(),
+            todos: String::new(),
+            input: LineState::default(),
+            scroll: 0,
+            busy: false,
+            keymap: Keymap::from_config(keys)?,
+            hints,
+            approving: false,
+            focused: None,
+            status: String::new(),
+            palette,
+        })
+    }
+
+    fn refresh_status(&mut self, chat: &Chat) {
+        let cost = chat.session_cost().map(|cost| format!("${:.4}", cost)).unwrap_or_else(|_| "$?".to_string());
+        self.status = format!(
+            "{} | context {:.0}% | {} | session {}",
+            chat.config().model,
+            chat.context_fill() * 100.0,
+            cost,
+            &chat.session.id[..8.min(chat.session.id.len())],
+        );
+        self.todos = todo::render(&chat.session.todos);
+    }
+
+    fn apply(&mut self, update: Update) {
+        match update {
+            Update::Text(text) => match self.conversation.last_mut() {
+                Some(Entry::Assistant(existing)) => existing.push_str(&text),
+                _ => self.conversation.push(Entry::Assistant(text)),
+            },
+            Update::ToolStart(description) => {
+                self.tool_output.push(format!("> {}", description));
+                self.conversation.push(Entry::Tool(description));
+            },
+            Update::ToolDone { name, output } => {
+                self.tool_output.push(format!("[{}]", name));
+                self.tool_output.extend(output.lines().map(str::to_string));
+                let excess = self.tool_output.len().saturating_sub(TOOL_OUTPUT_LIMIT);
+                self.tool_output.drain(..excess);
+            },
+            Update::Diff(diff) => {
+                self.diffs.extend(diff.lines().map(str::to_string));
+            },
+            Update::Steer(text) => self.conversation.push(Entry::User(text)),
+            Update::Todos(todos) => self.todos = todos,
+        }
+    }
+
+    fn handle_key(&mut self, event: Event) -> Option<Action> {
+        match event {
+            Event::FocusGained => self.focused = Some(true),
+            Event::FocusLost => self.focused = Some(false),
+            _ => {},
+        }
+        let Event::Key(KeyEvent { code, modifiers, kind, .. }) = event else { return None };
+        if kind != KeyEventKind::Press {
+            return None;
+        }
+        let ctrl = modifiers.contains(KeyModifiers::CONTROL);
+        match self.keymap.action(code, modifiers) {
+            Some(keys::Action::Cancel) if self.busy => return Some(Action::Cancel),
+            Some(keys::Action::Cancel) => {
+                self.input.set("");
+                return None;
+            },
+            Some(keys::Action::ApproveTool) if self.approving => return Some(Action::Approve),
+            Some(keys::Action::Submit) => {
+                let line = self.input.text();
+                self.input.set("");
+                self.scroll = 0;
+                return Some(Action::Submit(line));
+            },
+            Some(keys::Action::Regenerate) if !self.busy && self.input.chars.is_empty() => return Some(Action::Regenerate),
+            // The input is a single line and $EDITOR can't share the screen, both only work in the REPL
+            Some(_) => return None,
+            None => {},
+        }
+        match code {
+            KeyCode::Char('d') if ctrl && self.input.chars.is_empty() && !self.busy => return Some(Action::Quit),
+            KeyCode::PageUp => self.scroll += 10,
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            _ => {
+                self.input.edit(code, ctrl);
+            },
+        }
+        None
+    }
+
+    fn conversation_lines(&self, width: usize) -> Vec<StyledLine> {
+        let mut lines = Vec::new();
+        for entry in &self.conversation {
+            match entry {
+                Entry::User(text) => lines.extend(styled(&format!("you: {}", text), width, color(self.palette.user))),
+                Entry::Assistant(text) => lines.extend(styled(text.trim(), width, color(self.palette.assistant))),
+                Entry::Tool(text) => lines.extend(styled(&format!("  > {}", text), width, color(self.palette.tool))),
+                Entry::Notice(text) => lines.extend(styled(text, width, color(self.palette.info))),
+                Entry::Error(text) => lines.extend(styled(text, width, color(self.palette.error))),
+            }
+            if !matches!(entry, Entry::Tool(_)) {
+                lines.push(StyledLine { text: String::new(), color: Color::Reset });
+            }
+        }
+        lines
+    }
+
+    fn draw(&self) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        if width < 20 || height < 6 {
+            return Ok(());
+        }
+        let body = height - 2;
+        let left = width * 3 / 5;
+        let right = width - left - 1;
+        let todo_lines: Vec<StyledLine> = self.todos.lines()
+            .flat_map(|line| styled(line, right, if line.starts_with("[~]") { color(self.palette.tool) } else { Color::Reset }))
+            .collect();
+        let todos_height = match todo_lines.len() {
+            0 => 0,
+            lines => (lines + 1).min(body / 3),
+        };
+        let tools_height = (body - todos_height) / 2;
+
+        let mut stdout = io::stdout();
+        queue!(stdout, cursor::Hide, Clear(ClearType::All))?;
+
+        let conversation = self.conversation_lines(left);
+        draw_pane(&mut stdout, Rect { x: 0, y: 0, width: left, height: body }, "conversation", &conversation, self.scroll)?;
+
+        let tool_lines: Vec<StyledLine> = self.tool_output.iter()
+            .flat_map(|line| styled(line, right, if line.starts_with('>') { color(self.palette.tool) } else { Color::Reset }))
+            .collect();
+        draw_pane(&mut stdout, Rect { x: left + 1, y: 0, width: right, height: todos_height }, "tasks", &todo_lines, 0)?;
+        draw_pane(&mut stdout, Rect { x: left + 1, y: todos_height, width: right, height: tools_height }, "tool output", &tool_lines, 0)?;
+
+        let diff_lines: Vec<StyledLine> = self.diffs.iter()
+            .flat_map(|line| styled(line, right, color(self.palette.diff_line(line))))
+            .collect();
+        let diffs_top = todos_height + tools_height;
+        draw_pane(&mut stdout, Rect { x: left + 1, y: diffs_top, width: right, height: body - diffs_top }, "file diffs", &diff_lines, 0)?;
+
+        for row in 0..body {
+            queue!(stdout, cursor::MoveTo(left as u16, row as u16), SetForegroundColor(Color::DarkGrey), Print('│'))?;
+        }
+
+        let hint = match (self.approving, self.busy) {
+            (true, _) => &self.hints.2,
+            (false, true) => &self.hints.0,
+            (false, false) => &self.hints.1,
+        };
+        let status = format!(" {}{} | {}", if self.busy { "working | " } else { "" }, self.status, hint);
+        queue!(
+            stdout,
+            cursor::MoveTo(0, body as u16),
+            SetAttribute(Attribute::Reverse),
+            Print(pad(&status, width)),
+            SetAttribute(Attribute::Reset),
+        )?;
+
+        // Keep the end of the input visible when it is longer than the line
+        let prompt = "> ";
+        let visible = width - prompt.len() - 1;
+        let start = self.input.cursor.saturating_sub(visible);
+        let input: String = self.input.chars.iter().skip(start).take(visible).collect();
+        queue!(
+            stdout,
+            cursor::MoveTo(0, (body + 1) as u16),
+            SetForegroundColor(Color::Reset),
+            Print(prompt),
+            Print(&input),
+            cursor::MoveTo((prompt.len() + self.input.cursor - start) as u16, (body + 1) as u16),
+            cursor::Show,
+        )?;
+        stdout.flush()
+    }
+}
+
+fn pad(text: &str, width: usize) -> String {
+    let mut padded: String = text.chars().take(width).collect();
+    let len = padded.chars().count();
+    padded.extend(std::iter::repeat_n(' ', width - len));
+    padded
+}
+
+#[derive(Clone, Copy)]
+struct Rect {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+// Draws a titled pane showing the last lines that fit, `scroll` lines up from the bottom
+fn draw_pane(stdout: &mut impl Write, area: Rect, title: &str, lines: &[StyledLine], scroll: usize) -> io::Result<()> {
+    let Rect { x, y, width, height } = area;
+    if height == 0 {
+        return Ok(());
+    }
+    queue!(
+        stdout,
+        cursor::MoveTo(x as u16, y as u16),
+        SetAttribute(Attribute::Bold),
+        SetForegroundColor(Color::DarkGrey),
+        Print(pad(&format!(" {}", title), width)),
+        SetAttribute(Attribute::Reset),
+    )?;
+
+    let rows = height - 1;
+    let end = lines.len().saturating_sub(scroll.min(lines.len().saturating_sub(rows)));
+    let start = end.saturating_sub(rows);
+    for (row, line) in lines[start..end].iter().enumerate() {
+        queue!(
+            stdout,
+            cursor::MoveTo(x as u16, (y + 1 + row) as u16),
+            SetForegroundColor(line.color),
+            Print(pad(&line.text, width)),
+        )?;
+    }
+    Ok(())
+}
+
+// Terminal events are read on their own thread so they arrive while a turn is running
+fn spawn_input() -> mpsc::UnboundedReceiver<Event> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if tx.send(event).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+// What an answer typed to a tool call waiting for approval decides.  `e` puts the arguments
+// in the input line as JSON, to be changed and sent back from there.
+fn approval_answer(line: &str, request: &ApprovalRequest, app: &mut App) -> Option<Decision> {
+    let line = line.trim();
+    if line == "e" || line == "edit" {
+        app.input.set(&request.input.to_string());
+        return None;
+    }
+    if line.starts_with('{') {
+        return match serde_json::from_str(line) {
+            Ok(edited) => Some(Decision::Edit(edited)),
+            Err(e) => {
+                app.conversation.push(Entry::Error(format!("The edited arguments are not valid JSON: {}", e)));
+                app.input.set(line);
+                None
+            },
+        };
+    }
+    approve::decision(line, &request.input).ok().flatten()
+}
+
+pub async fn run(mut chat: Chat, approve_each: bool) -> Result<(), anyhow::Error> {
+    let (source, mut cancel) = agent::cancel_pair();
+    let _screen = Screen::enter()?;
+    let mut events = spawn_input();
+    let mut app = App::new(&chat.config().keys, Palette::from_config(&chat.config().theme)?)?;
+    app.refresh_status(&chat);
+    // Images from /image waiting for the next message
+    let mut attachments = Vec::new();
+    let (approvals, mut requests) = match approve_each {
+        true => {
+            let (approvals, requests) = agent::approval_channel();
+            (Some(approvals), Some(requests))
+        },
+        false => (None, None),
+    };
+    // Lines sent while a turn runs steer it, see `Controls::steering`
+    let (steer, steering) = mpsc::unbounded_channel();
+    let mut controls = Controls { approvals, steering: Some(steering), budget: None, secrets: None, keys: None };
+    let mut queued = None;
+
+    loop {
+        let (line, regenerate) = match queued.take() {
+            Some(line) => (line, false),
+            None => {
+                app.draw()?;
+                let Some(event) = events.recv().await else { break };
+                match app.handle_key(event) {
+                    Some(Action::Submit(line)) => (line, false),
+                    Some(Action::Regenerate) => (String::new(), true),
+                    Some(Action::Quit) => break,
+                    Some(Action::Cancel | Action::Approve) | None => continue,
+                }
+            },
+        };
+
+        let (message, pin) = if regenerate {
+            match chat.rewind_last_turn() {
+                Some(message) => {
+                    app.conversation.push(Entry::Notice("(regenerating the last reply)".to_string()));
+                    (message, false)
+                },
+                None => {
+                    app.conversation.push(Entry::Error("No previous message to regenerate".to_string()));
+                    continue;
+                },
+            }
+        } else if line.trim().is_empty() {
+            if !chat.is_incomplete() {
+                continue;
+            }
+            (chat.continue_message()?, false)
+        } else if let Some(proposed) = chat.proposed_plan().filter(|_| matches!(line.trim(), "y" | "yes")) {
+            let proposed = proposed.to_string();
+            app.conversation.push(Entry::User(line.clone()));
+            (chat.approve_plan(&proposed), true)
+        } else if let Some(("/snippet", Some(arg))) = commands::parse(&line) {
+            // Placeholders can't be asked for here, they all need values on the line
+            match commands::snippet(arg).and_then(|(snippet, values)| snippet.expand(&values)) {
+                Ok(text) => {
+                    app.conversation.push(Entry::User(text.clone()));
+                    let mut message = mentions::user_message(&text);
+                    message.content.append(&mut attachments);
+                    (message, false)
+                },
+                Err(e) => {
+                    app.conversation.push(Entry::Error(format!("Error: {}", e)));
+                    continue;
+                },
+            }
+        } else if let Some((name, arg)) = commands::parse(&line) {
+            let entry = match commands::run(&mut chat, name, arg, &mut attachments).await {
+                Ok(output) => Entry::Notice(output),
+                Err(e) => Entry::Error(format!("Error: {}", e)),
+            };
+            app.conversation.push(entry);
+            app.refresh_status(&chat);
+            continue;
+        } else if let Some(note) = ProjectMemory::parse_command(&line) {
+            let entry = match ProjectMemory::remember(note) {
+                Ok(_) => Entry::Notice(format!("Remembered: {}", note)),
+                Err(e) => Entry::Error(format!("Could not save note: {}", e)),
+            };
+            app.conversation.push(entry);
+            continue;
+        } else {
+            app.conversation.push(Entry::User(line.clone()));
+            let (text, pin) = match Chat::parse_pin_command(&line) {
+                Some(text) => (text.to_string(), true),
+                None => (line, false),
+            };
+            let mut message = mentions::user_message(&text);
+            message.content.append(&mut attachments);
+            (message, pin)
+        };
+
+        app.busy = true;
+        let started = Instant::now();
+        let cost_before = chat.session_cost().unwrap_or_default();
+        let (tx, mut updates) = mpsc::unbounded_channel();
+        let result = {
+            let mut on_event = |event: AgentEvent| {
+                for update in Update::from_event(event) {
+                    let _ = tx.send(update);
+                }
+            };
+            let turn = agent::run_turn(&mut chat, message, &mut on_event, &mut controls, &mut cancel);
+            tokio::pin!(turn);
+            // The tool call waiting for an answer
+            let mut pending: Option<ApprovalRequest> = None;
+            loop {
+                app.approving = pending.is_some();
+                app.draw()?;
+                let request = async {
+                    match requests.as_mut() {
+                        Some(requests) => requests.recv().await,
+                        None => std::future::pending().await,
+                    }
+                };
+                tokio::select! {
+                    result = &mut turn => break result,
+                    Some(update) = updates.recv() => app.apply(update),
+                    Some(request) = request => {
+                        let reason = request.reason.as_ref().map(|reason| format!(" ({})", reason)).unwrap_or_default();
+                        app.conversation.push(Entry::Notice(format!("Run {}?{}", describe_tool(&request.name, &request.input), reason)));
+                        pending = Some(request);
+                    },
+                    Some(event) = events.recv() => {
+                        let decision = match app.handle_key(event) {
+                            Some(Action::Cancel) => {
+                                source.cancel();
+                                pending.is_some().then(|| Decision::Deny(String::new()))
+                            },
+                            Some(Action::Approve) => Some(Decision::Approve),
+                            Some(Action::Submit(line)) => match pending.as_ref() {
+                                Some(request) => approval_answer(&line, request, &mut app),
+                                None if line.trim().is_empty() => None,
+                                None => {
+                                    app.conversation.push(Entry::Notice(format!("(queued, sent after the running tool call: {})", line.trim())));
+                                    let _ = steer.send(line.trim().to_string());
+                                    None
+                                },
+                            },
+                            _ => None,
+                        };
+                        if let Some(decision) = decision {
+                            if let Some(request) = pending.take() {
+                                let _ = request.reply.send(decision);
+                            }
+                        }
+                    },
+                }
+            }
+        };
+        while let Ok(update) = updates.try_recv() {
+            app.apply(update);
+        }
+        app.busy = false;
+        app.approving = false;
+        queued = controls.queued();
+
+        if !cancel.is_cancelled() {
+            let error = result.as_ref().err().map(|e| e.to_string());
+            let cost = chat.session_cost().unwrap_or_default() - cost_before;
+            notify::turn_finished(chat.config().notify_after_secs, started.elapsed(), app.focused, error.as_deref(), cost);
+        }
+        match result {
+            Ok(outcome) => {
+                if pin {
+                    if let Err(e) = chat.pin_last_turn() {
+                        app.conversation.push(Entry::Error(format!("Could not pin message: {}", e)));
+                    }
+                }
+                if outcome.incomplete {
+                    app.conversation.push(Entry::Notice("(reply was cut off, press Enter on an empty line to continue)".to_string()));
+                }
+                if let Some(proposed) = chat.proposed_plan() {
+                    app.conversation.push(Entry::Notice(format!(
+                        "Proposed plan:\n{}\nEnter y to run it, or say what to change",
+                        plan::numbered(proposed),
+                    )));
+                }
+            },
+            Err(e) => app.conversation.push(Entry::Error(format!("Error: {}", e))),
+        }
+        app.refresh_status(&chat);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("the quick brown fox", 10), vec!["the quick", "brown fox"]);
+        assert_eq!(wrap("abcdefghij klm\n\nx", 4), vec!["abcd", "efgh", "ij", "klm", "", "x"]);
+    }
+}
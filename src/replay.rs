@@ -0,0 +1,111 @@
+use std::fs;
+use std::path::PathBuf;
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use similar::TextDiff;
+
+use crate::chat::Chat;
+use crate::config::ProjectConfig;
+use crate::inference::types::{ContentItem, Message, Role};
+use crate::session::Session;
+
+/// One recorded assistant message and what the model answers now given the same history.
+pub struct ReplayStep {
+    pub message_index: usize,
+    pub recorded: String,
+    pub replayed: String,
+}
+
+impl ReplayStep {
+    pub fn changed(&self) -> bool {
+        self.recorded != self.replayed
+    }
+
+    pub fn diff(&self) -> String {
+        TextDiff::from_lines(&self.recorded, &self.replayed)
+            .unified_diff()
+            .header("recorded", "replayed")
+            .to_string()
+    }
+}
+
+#[derive(Serialize)]
+struct CacheKey<'a> {
+    model: &'a str,
+    system: &'a str,
+    messages: &'a [Message],
+}
+
+fn cache_path(key: &CacheKey) -> Result<PathBuf, anyhow::Error> {
+    let hash = format!("{:x}", Sha256::digest(serde_json::to_vec(key)?));
+    let dir = ProjectConfig::data_dir()?.join("replay");
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(format!("{}.json", hash)))
+}
+
+// Text and tool calls of a message in a stable form so recorded and replayed answers compare
+pub fn render(content: &[ContentItem]) -> String {
+    content.iter()
+        .filter_map(|item| match item {
+            ContentItem::Text { text } => Some(text.trim().to_string()),
+            ContentItem::ToolUse { name, input, .. } => Some(format!("[tool_use {}] {}", name, input)),
+            ContentItem::ToolResult { .. } | ContentItem::Image { .. } | ContentItem::Unknown(_) => None,
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+        + "\n"
+}
+
+/*
+    * Re-asks every assistant message of a recorded session given the recorded history before
+    * it, including the recorded tool results, so no tool is run again and each step is judged
+    * on the same input.  Live answers are cached under `.pprog/replay/` keyed by the exact
+    * request, and `cached` replays from that cache only, which makes a replay reproducible.
+*/
+pub async fn replay(chat: &Chat, model: &str, session: &Session, cached: bool) -> Result<Vec<ReplayStep>, anyhow::Error> {
+    let mut steps = Vec::new();
+    for (idx, message) in session.messages.iter().enumerate() {
+        if message.role != Role::Assistant || idx == 0 {
+            continue;
+        }
+
+        let (system, messages) = chat.build_request(&session.messages[..idx])?;
+        let path = cache_path(&CacheKey { model, system: &system, messages: &messages })?;
+        let content: Vec<ContentItem> = if cached {
+            let json = fs::read_to_string(&path)
+                .map_err(|_| anyhow::anyhow!("No cached response for message #{}, replay without --cached first", idx))?;
+            serde_json::from_str(&json)?
+        } else {
+            let response = chat.query(&system, messages).await?;
+            fs::write(&path, serde_json::to_string(&response.content)?)?;
+            response.content
+        };
+
+        steps.push(ReplayStep {
+            message_index: idx,
+            recorded: render(&message.content),
+            replayed: render(&content),
+        });
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_ignores_tool_use_ids() {
+        let recorded = vec![
+            ContentItem::Text { text: "Reading it.  ".to_string() },
+            ContentItem::ToolUse { id: "toolu_1".to_string(), name: "read_file".to_string(), input: serde_json::json!({"path": "a.rs"}) },
+        ];
+        let replayed = vec![
+            ContentItem::Text { text: "Reading it.".to_string() },
+            ContentItem::ToolUse { id: "toolu_2".to_string(), name: "read_file".to_string(), input: serde_json::json!({"path": "a.rs"}) },
+        ];
+        assert_eq!(render(&recorded), render(&replayed));
+        assert_eq!(render(&recorded), "Reading it.\n[tool_use read_file] {\"path\":\"a.rs\"}\n");
+    }
+}
@@ -0,0 +1,183 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::tree::GitTree;
+
+// The rules SARIF results refer to, with what each means
+const RULES: &[(&str, &str)] = &[
+    ("eval-failure", "An eval case failed one of its assertions"),
+    ("review-finding", "A problem the reviewing model raised with the work"),
+];
+
+/// One test of a JUnit report, failed when `failure` is set.
+#[derive(Debug, Clone)]
+pub struct TestCase {
+    pub name: String,
+    pub classname: String,
+    pub time: Duration,
+    /// A one line summary of why it failed, and the details
+    pub failure: Option<(String, String)>,
+}
+
+/// One result of a SARIF report, placed in a file when the location is known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub rule: &'static str,
+    /// `error`, `warning` or `note`
+    pub level: &'static str,
+    pub message: String,
+    pub path: Option<String>,
+    pub line: Option<u32>,
+}
+
+/*
+    * The outcome of a run for CI, written as JUnit XML or SARIF so test tabs and code
+    * scanning show it natively: JUnit for pass and fail per test, SARIF for findings that
+    * can be annotated on the lines they are about.  The format follows the file name, a
+    * `.xml` one gets JUnit and a `.sarif` or `.json` one SARIF.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub suite: String,
+    pub cases: Vec<TestCase>,
+    pub findings: Vec<Finding>,
+}
+
+/// `path` relative to the root of the repository, what SARIF locations are resolved against.
+pub fn repo_path(path: &Path) -> String {
+    let relative = GitTree::get_git_root().ok()
+        .zip(path.canonicalize().ok())
+        .and_then(|(root, path)| path.strip_prefix(root.canonicalize().unwrap_or(root)).ok().map(Path::to_path_buf));
+    relative.as_deref().unwrap_or(path).to_string_lossy().replace('\\', "/")
+}
+
+fn escape_xml(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_control() || matches!(c, '\n' | '\t' | '\r'))
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&apos;".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+impl Report {
+    pub fn write(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let name = path.file_name().map(|name| name.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let text = if name.ends_with(".xml") {
+            self.junit()
+        } else if name.ends_with(".sarif") || name.ends_with(".json") {
+            serde_json::to_string_pretty(&self.sarif())?
+        } else {
+            anyhow::bail!("Can't tell the report format of {}, name it .xml for JUnit or .sarif or .json for SARIF", path.display());
+        };
+        fs::write(path, text).map_err(|e| anyhow::anyhow!("Failed to write the report {}: {}", path.display(), e))
+    }
+
+    pub fn junit(&self) -> String {
+        let failures = self.cases.iter().filter(|case| case.failure.is_some()).count();
+        let time = self.cases.iter().map(|case| case.time).sum::<Duration>().as_secs_f64();
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        writeln!(xml, "<testsuites name=\"pprog\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">", self.cases.len(), failures, time).ok();
+        writeln!(xml, "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">", escape_xml(&self.suite), self.cases.len(), failures, time).ok();
+        for case in &self.cases {
+            let attributes = format!(
+                "name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                escape_xml(&case.name), escape_xml(&case.classname), case.time.as_secs_f64(),
+            );
+            match &case.failure {
+                None => writeln!(xml, "    <testcase {} />", attributes).ok(),
+                Some((message, details)) => writeln!(
+                    xml,
+                    "    <testcase {}>\n      <failure message=\"{}\">{}</failure>\n    </testcase>",
+                    attributes, escape_xml(message), escape_xml(details),
+                ).ok(),
+            };
+        }
+        xml.push_str("  </testsuite>\n</testsuites>\n");
+        xml
+    }
+
+    pub fn sarif(&self) -> Value {
+        let results = self.findings.iter()
+            .map(|finding| {
+                let mut result = json!({
+                    "ruleId": finding.rule,
+                    "level": finding.level,
+                    "message": {"text": finding.message},
+                });
+                if let Some(path) = &finding.path {
+                    let mut location = json!({"artifactLocation": {"uri": path, "uriBaseId": "%SRCROOT%"}});
+                    if let Some(line) = finding.line {
+                        location["region"] = json!({"startLine": line});
+                    }
+                    result["locations"] = json!([{"physicalLocation": location}]);
+                }
+                result
+            })
+            .collect::<Vec<Value>>();
+        let rules = RULES.iter()
+            .filter(|(id, _)| self.findings.iter().any(|finding| finding.rule == *id))
+            .map(|(id, description)| json!({"id": id, "shortDescription": {"text": description}}))
+            .collect::<Vec<Value>>();
+        json!({
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {"driver": {"name": "pprog", "version": env!("CARGO_PKG_VERSION"), "rules": rules}},
+                "results": results,
+            }],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_junit_and_sarif() -> Result<(), anyhow::Error> {
+        let report = Report {
+            suite: "smoke".to_string(),
+            cases: vec![
+                TestCase { name: "greets".to_string(), classname: "smoke.haiku".to_string(), time: Duration::from_millis(1500), failure: None },
+                TestCase {
+                    name: "json".to_string(),
+                    classname: "smoke.haiku".to_string(),
+                    time: Duration::from_millis(500),
+                    failure: Some(("contains \"<name>\": not in the reply".to_string(), "reply & more".to_string())),
+                },
+            ],
+            findings: vec![Finding {
+                rule: "review-finding",
+                level: "warning",
+                message: "The flag is never read".to_string(),
+                path: Some("src/main.rs".to_string()),
+                line: Some(42),
+            }],
+        };
+        let xml = report.junit();
+        assert!(xml.contains("<testsuite name=\"smoke\" tests=\"2\" failures=\"1\" time=\"2.000\">"));
+        assert!(xml.contains("<testcase name=\"greets\" classname=\"smoke.haiku\" time=\"1.500\" />"));
+        assert!(xml.contains("<failure message=\"contains &quot;&lt;name&gt;&quot;: not in the reply\">reply &amp; more</failure>"));
+
+        let sarif = report.sarif();
+        let result = &sarif["runs"][0]["results"][0];
+        assert_eq!(result["locations"][0]["physicalLocation"]["region"]["startLine"], 42);
+        assert_eq!(sarif["runs"][0]["tool"]["driver"]["rules"].as_array().map(Vec::len), Some(1));
+
+        let dir = tempfile::tempdir()?;
+        report.write(&dir.path().join("results.sarif.json"))?;
+        assert!(fs::read_to_string(dir.path().join("results.sarif.json"))?.contains("\"version\": \"2.1.0\""));
+        assert!(report.write(&dir.path().join("results.txt")).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,436 @@
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::chat::Chat;
+use crate::inference::types::{ContentItem, Message, Role};
+use crate::report::{Finding, Report, TestCase};
+use crate::structured::{self, JsonSchema, Value};
+use crate::tokens::TokenCounter;
+
+const REVIEW_PROMPT: &str = "You review the work of a coding assistant against the request it was given.  \
+Check that the answer and the changes do what was asked, are correct, and leave nothing requested undone.  \
+If they do, reply with APPROVED alone on the first line.  \
+Otherwise reply with a short list of the concrete problems to fix, without rewriting the work yourself.  \
+Start each problem that is about one place in the code with its path and line, as path:line.";
+
+const DIFF_REVIEW_PROMPT: &str = "You review a code change, given as part of a unified diff.  \
+Look for bugs, security problems, missing error handling, unclear code and changes that break callers, \
+in the lines the diff adds or changes rather than the code around them.  \
+Give each problem with the file it is in and the line of the new file, as numbered by the hunk headers, \
+and say what is wrong and how to fix it in a sentence or two.  \
+Use error for what will break, warning for what is likely wrong or risky, and note for smaller improvements.  \
+Leave out style nits a formatter would fix, and give no findings for a part that looks right.";
+
+// A diff beyond this is cut, the reviewer still sees the answer and the start of the changes
+const MAX_DIFF_CHARS: usize = 100_000;
+// Tokens of each diff review request kept for the prompt and the framing of the chunk
+const CHUNK_OVERHEAD: usize = 1000;
+
+/// What the reviewer made of a turn.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Approved,
+    /// The problems to fix, sent back for a revision
+    Revise(String),
+}
+
+impl Verdict {
+    fn parse(reply: &str) -> Verdict {
+        let reply = reply.trim();
+        let first_line = reply.lines().next().unwrap_or_default().trim().trim_matches(['*', '.', ' ']);
+        if first_line.eq_ignore_ascii_case("approved") {
+            Verdict::Approved
+        } else {
+            Verdict::Revise(reply.to_string())
+        }
+    }
+}
+
+fn review_request(request: &str, answer: &str, diff: &str) -> String {
+    let mut text = format!("Request:\n{}\n\nAnswer:\n{}\n", request.trim(), answer.trim());
+    if !diff.trim().is_empty() {
+        let mut cut = diff.len().min(MAX_DIFF_CHARS);
+        while !diff.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        text.push_str(&format!("\nChanges made:\n```diff\n{}\n```\n", diff[..cut].trim_end()));
+        if cut < diff.len() {
+            text.push_str(&format!("[diff cut off after {} of {} bytes]\n", cut, diff.len()));
+        }
+    }
+    text
+}
+
+/// Has `model` judge the answer to `request` and the files changed by the turn it ended.
+pub async fn review(chat: &Chat, model: &str, request: &str, answer: &str) -> Result<Verdict, anyhow::Error> {
+    let text = review_request(request, answer, &chat.turn_diff()?);
+    let reply = chat.ask(model, REVIEW_PROMPT, text).await
+        .map_err(|e| e.context(format!("The review by {} failed", model)))?;
+    Ok(Verdict::parse(&reply))
+}
+
+/// The user message asking for one round of revisions.
+pub fn revision_message(critique: &str) -> Message {
+    let text = format!("A reviewer went over your work and raised these points.  Address them, then answer again in full:\n\n{}", critique);
+    Message { role: Role::User, content: vec![ContentItem::Text { text }] }
+}
+
+/// The problems of a critique, one a list item, placed at the first `path:line` each mentions.
+pub fn findings(critique: &str) -> Vec<Finding> {
+    let location = Regex::new(r"([\w./-]+\.[A-Za-z0-9]+):(\d+)").unwrap();
+    let items = critique.lines()
+        .map(str::trim)
+        .filter_map(|line| line.strip_prefix(['-', '*']).or_else(|| line.split_once(". ").filter(|(n, _)| n.parse::<u32>().is_ok()).map(|(_, item)| item)))
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect::<Vec<String>>();
+    let items = match items.is_empty() {
+        true => vec![critique.trim().to_string()],
+        false => items,
+    };
+    items.into_iter()
+        .map(|message| {
+            let found = location.captures(&message).map(|captures| (captures[1].to_string(), captures[2].parse().ok()));
+            Finding {
+                rule: "review-finding",
+                level: "warning",
+                path: found.as_ref().map(|(path, _)| path.trim_start_matches("./").to_string()),
+                line: found.and_then(|(_, line)| line),
+                message,
+            }
+        })
+        .collect()
+}
+
+/// A review for CI: one test that fails when changes were asked for, and the problems raised.
+pub fn ci_report(model: &str, verdict: &Verdict, time: Duration) -> Report {
+    let (failure, findings) = match verdict {
+        Verdict::Approved => (None, Vec::new()),
+        Verdict::Revise(critique) => (Some((format!("{} asked for changes", model), critique.clone())), findings(critique)),
+    };
+    Report {
+        suite: String::from("review"),
+        cases: vec![TestCase { name: String::from("review"), classname: format!("review.{}", model), time, failure }],
+        findings,
+    }
+}
+
+/// How much a problem a diff review raised matters, the SARIF level it is reported at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    pub fn level(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+}
+
+impl JsonSchema for Severity {
+    fn json_schema() -> Value {
+        let mut schema = String::json_schema();
+        structured::constrain(&mut schema, "enum", serde_json::json!(["error", "warning", "note"]));
+        schema
+    }
+}
+
+/// A problem a diff review raised, at a line of the new file when it's about one.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct DiffFinding {
+    pub path: String,
+    #[serde(default)]
+    pub line: Option<u32>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl JsonSchema for DiffFinding {
+    fn json_schema() -> Value {
+        structured::object(&[
+            ("path", structured::described(String::json_schema(), "The file as named in the diff")),
+            ("line", structured::described(Option::<u32>::json_schema(), "Line of the new file, or null for the file as a whole")),
+            ("severity", Severity::json_schema()),
+            ("message", structured::described(String::json_schema(), "What is wrong and how to fix it")),
+        ])
+    }
+}
+
+// What the model replies with for a chunk
+#[derive(Debug, Deserialize)]
+struct ChunkReview {
+    findings: Vec<DiffFinding>,
+}
+
+impl JsonSchema for ChunkReview {
+    fn json_schema() -> Value {
+        structured::object(&[("findings", structured::described(Vec::<DiffFinding>::json_schema(), "The problems found, none when it looks right"))])
+    }
+
+    fn schema_name() -> String {
+        String::from("diff_review")
+    }
+}
+
+// The new path of each file in `diff`
+fn diff_paths(diff: &str) -> BTreeSet<String> {
+    diff.lines()
+        .filter_map(|line| line.strip_prefix("+++ "))
+        .filter(|path| *path != "/dev/null")
+        .chain(diff.lines().filter_map(|line| line.strip_prefix("--- ")).filter(|path| *path != "/dev/null"))
+        .map(|path| path.split('\t').next().unwrap_or_default().trim())
+        .map(|path| path.strip_prefix("b/").or_else(|| path.strip_prefix("a/")).unwrap_or(path).to_string())
+        .collect()
+}
+
+// `diff` split before each line starting with `marker`, what comes ahead of the first kept with it
+fn split_before<'a>(diff: &'a str, marker: &str) -> Vec<&'a str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    for (offset, _) in diff.match_indices(marker) {
+        if offset > start && diff[..offset].ends_with('\n') {
+            pieces.push(&diff[start..offset]);
+            start = offset;
+        }
+    }
+    pieces.push(&diff[start..]);
+    pieces.into_iter().filter(|piece| !piece.is_empty()).collect()
+}
+
+/*
+    * A hunk cut into parts of at most `max_tokens`, going by the tokens of its lines, each
+    * under a header of its own so the line numbers of the new file still add up.  A hunk
+    * without a header that parses is left whole.
+*/
+fn split_hunk(hunk: &str, max_tokens: usize, counter: &TokenCounter) -> Vec<String> {
+    let header = Regex::new(r"^@@ -(\d+)(?:,\d+)? \+(\d+)(?:,\d+)? @@(.*)").unwrap();
+    let mut lines = hunk.lines();
+    let first = lines.next().unwrap_or_default();
+    let Some(captures) = header.captures(first) else {
+        return vec![hunk.to_string()];
+    };
+    let (mut old, mut new) = (captures[1].parse::<u64>().unwrap_or(1), captures[2].parse::<u64>().unwrap_or(1));
+    let context = &captures[3];
+    let budget = max_tokens.saturating_sub(counter.count(first));
+    let mut parts = Vec::new();
+    let mut body = String::new();
+    let (mut tokens, mut start_old, mut start_new, mut old_count, mut new_count) = (0, old, new, 0, 0);
+    for line in lines {
+        let line_tokens = counter.count(line) + 1;
+        if !body.is_empty() && tokens + line_tokens > budget {
+            parts.push(format!("@@ -{},{} +{},{} @@{}\n{}", start_old, old_count, start_new, new_count, context, body));
+            body.clear();
+            (tokens, start_old, start_new, old_count, new_count) = (0, old, new, 0, 0);
+        }
+        let (ahead_old, ahead_new) = match line.chars().next() {
+            Some('+') => (0, 1),
+            Some('-') => (1, 0),
+            Some('\\') => (0, 0),
+            _ => (1, 1),
+        };
+        body.push_str(line);
+        body.push('\n');
+        tokens += line_tokens;
+        (old, new) = (old + ahead_old, new + ahead_new);
+        (old_count, new_count) = (old_count + ahead_old, new_count + ahead_new);
+    }
+    parts.push(format!("@@ -{},{} +{},{} @@{}\n{}", start_old, old_count, start_new, new_count, context, body));
+    parts
+}
+
+/*
+    * `diff` in chunks of about `max_tokens` at most, for reviewing one request at a time.
+    * Whole files are packed together while they fit, a file too big for a chunk goes hunk
+    * by hunk and a hunk too big for one line by line, each part under the headers of its file.
+*/
+pub fn chunks(diff: &str, max_tokens: usize, counter: &TokenCounter) -> Vec<String> {
+    let mut sections = Vec::new();
+    for file in split_before(diff, "diff --git ") {
+        let tokens = counter.count(file);
+        if tokens <= max_tokens {
+            sections.push((file.to_string(), tokens));
+            continue;
+        }
+        let hunks = split_before(file, "@@ ");
+        let (header, hunks) = match hunks.first() {
+            Some(first) if !first.starts_with("@@ ") => (*first, &hunks[1..]),
+            _ => ("", &hunks[..]),
+        };
+        let budget = max_tokens.saturating_sub(counter.count(header));
+        for hunk in hunks {
+            for part in split_hunk(hunk, budget, counter) {
+                let tokens = counter.count(header) + counter.count(&part);
+                sections.push((format!("{}{}", header, part), tokens));
+            }
+        }
+    }
+    let mut chunks: Vec<(String, usize)> = Vec::new();
+    for (section, tokens) in sections {
+        match chunks.last_mut() {
+            Some((chunk, size)) if *size + tokens <= max_tokens => {
+                chunk.push_str(&section);
+                *size += tokens;
+            },
+            _ => chunks.push((section, tokens)),
+        }
+    }
+    chunks.into_iter().map(|(chunk, _)| chunk).collect()
+}
+
+// One finding per problem, the most severe first and then in file order
+fn merge(mut findings: Vec<DiffFinding>) -> Vec<DiffFinding> {
+    let mut seen = BTreeSet::new();
+    findings.retain(|finding| seen.insert((finding.path.clone(), finding.line, finding.message.trim().to_lowercase())));
+    findings.sort_by(|a, b| (a.severity, &a.path, a.line).cmp(&(b.severity, &b.path, b.line)));
+    findings
+}
+
+/// The diff git gives for `args`, without colors.
+pub fn git_diff(args: &[&str]) -> Result<String, anyhow::Error> {
+    let output = std::process::Command::new("git").args(["diff", "--no-color"]).args(args).output()?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("git diff {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Reviews `diff` with `model` a chunk at a time, `on_chunk` told as each one starts, and
+/// merges what the chunks turned up.
+pub async fn review_diff(chat: &Chat, model: &str, diff: &str, mut on_chunk: impl FnMut(usize, usize)) -> Result<Vec<DiffFinding>, anyhow::Error> {
+    let mut config = chat.config().clone();
+    config.model = model.to_string();
+    let counter = TokenCounter::for_model(model);
+    let budget = config.context_window()
+        .saturating_sub(config.max_output_tokens as usize + counter.count(DIFF_REVIEW_PROMPT) + CHUNK_OVERHEAD)
+        .max(CHUNK_OVERHEAD);
+    let chunks = chunks(diff, budget, &counter);
+    let mut findings = Vec::new();
+    for (idx, chunk) in chunks.iter().enumerate() {
+        on_chunk(idx + 1, chunks.len());
+        let paths = diff_paths(chunk);
+        let text = format!("Part {} of {} of the diff:\n```diff\n{}\n```\n", idx + 1, chunks.len(), chunk.trim_end());
+        let message = Message { role: Role::User, content: vec![ContentItem::Text { text }] };
+        let check = |reply: &ChunkReview| match reply.findings.iter().find(|finding| !paths.contains(&finding.path)) {
+            Some(finding) => Err(format!("{} is not a file in this part of the diff, which has {}", finding.path, paths.iter().cloned().collect::<Vec<String>>().join(", "))),
+            None => Ok(()),
+        };
+        let reply = chat.structured_reply(Some(model), DIFF_REVIEW_PROMPT, vec![message], check).await
+            .map_err(|e| e.context(format!("The review of part {} of {} failed", idx + 1, chunks.len())))?;
+        findings.extend(reply.findings);
+    }
+    Ok(merge(findings))
+}
+
+/// The findings of a diff review as lines of `path:line severity message`, with a count.
+pub fn diff_report(findings: &[DiffFinding]) -> String {
+    let mut text = String::new();
+    for finding in findings {
+        let location = match finding.line {
+            Some(line) => format!("{}:{}", finding.path, line),
+            None => finding.path.clone(),
+        };
+        let _ = writeln!(text, "{}  {:<7}  {}", location, finding.severity.level(), finding.message.trim().replace('\n', " "));
+    }
+    let files = findings.iter().map(|finding| &finding.path).collect::<BTreeSet<_>>().len();
+    let counts = [Severity::Error, Severity::Warning, Severity::Note].iter()
+        .map(|severity| (findings.iter().filter(|finding| finding.severity == *severity).count(), severity.level()))
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, level)| format!("{} {}{}", count, level, if count == 1 { "" } else { "s" }))
+        .collect::<Vec<String>>();
+    match findings.is_empty() {
+        true => text.push_str("No problems found.\n"),
+        false => {
+            let _ = writeln!(text, "{} in {} file{}.", counts.join(", "), files, if files == 1 { "" } else { "s" });
+        },
+    }
+    text
+}
+
+/// A diff review for CI: one test that fails on an error, and every finding.
+pub fn diff_ci_report(model: &str, findings: &[DiffFinding], time: Duration) -> Report {
+    let errors = findings.iter().filter(|finding| finding.severity == Severity::Error).count();
+    let failure = (errors > 0).then(|| (format!("{} found {} errors", model, errors), diff_report(findings)));
+    Report {
+        suite: String::from("review"),
+        cases: vec![TestCase { name: String::from("diff"), classname: format!("review.{}", model), time, failure }],
+        findings: findings.iter()
+            .map(|finding| Finding {
+                rule: "review-finding",
+                level: finding.severity.level(),
+                message: finding.message.clone(),
+                path: Some(finding.path.clone()),
+                line: finding.line,
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verdict() {
+        assert_eq!(Verdict::parse("APPROVED"), Verdict::Approved);
+        assert_eq!(Verdict::parse("**Approved.**\nLooks good."), Verdict::Approved);
+        assert_eq!(Verdict::parse("- The flag is never read\n"), Verdict::Revise("- The flag is never read".to_string()));
+        assert!(review_request("Add a flag", "Done.", "").ends_with("Answer:\nDone.\n"));
+
+        let found = findings("Two problems:\n- src/main.rs:42 reads the flag before parsing\n2. The README is not updated\n");
+        assert_eq!(found.len(), 2);
+        assert_eq!((found[0].path.as_deref(), found[0].line), (Some("src/main.rs"), Some(42)));
+        assert_eq!((found[1].path.as_deref(), found[1].message.as_str()), (None, "The README is not updated"));
+    }
+
+    #[test]
+    fn test_diff_chunks() {
+        let file = |name: &str, lines: usize| {
+            let added = (1..=lines).map(|idx| format!("+line {}\n", idx)).collect::<String>();
+            format!("diff --git a/{0} b/{0}\n--- a/{0}\n+++ b/{0}\n@@ -1,0 +1,{1} @@ fn main\n{2}", name, lines, added)
+        };
+        let diff = format!("{}{}", file("src/a.rs", 2), file("src/b.rs", 40));
+        let counter = TokenCounter::Estimate;
+        assert_eq!(chunks(&diff, 10_000, &counter), vec![diff.clone()]);
+        assert_eq!(diff_paths(&diff), BTreeSet::from(["src/a.rs".to_string(), "src/b.rs".to_string()]));
+
+        let small = chunks(&diff, 60, &counter);
+        assert!(small.len() > 2);
+        assert_eq!(small[0], file("src/a.rs", 2));
+        // Every part of b.rs has its headers and picks up where the last left off
+        let mut next = 1;
+        for part in &small[1..] {
+            assert!(part.starts_with("diff --git a/src/b.rs b/src/b.rs\n--- a/src/b.rs\n+++ b/src/b.rs\n@@ -1,0 +"));
+            let count = part.lines().filter(|line| line.starts_with("+line")).count();
+            assert!(part.contains(&format!(" +{},{} @@ fn main\n+line {}\n", next, count, next)));
+            next += count;
+        }
+        assert_eq!(next, 41);
+
+        let finding = |path: &str, line: Option<u32>, severity: Severity, message: &str| DiffFinding {
+            path: path.to_string(),
+            line,
+            severity,
+            message: message.to_string(),
+        };
+        let merged = merge(vec![
+            finding("src/b.rs", Some(3), Severity::Note, "Name it better"),
+            finding("src/b.rs", Some(9), Severity::Error, "Panics on empty input"),
+            finding("src/a.rs", None, Severity::Warning, "No tests"),
+            finding("src/b.rs", Some(9), Severity::Error, "panics on empty input"),
+        ]);
+        assert_eq!(merged.iter().map(|finding| finding.line).collect::<Vec<_>>(), vec![Some(9), None, Some(3)]);
+        assert_eq!(diff_report(&merged), "src/b.rs:9  error    Panics on empty input\nsrc/a.rs  warning  No tests\nsrc/b.rs:3  note     Name it better\n1 error, 1 warning, 1 note in 2 files.\n");
+        assert!(diff_ci_report("fake", &merged, Duration::ZERO).cases[0].failure.is_some());
+    }
+}
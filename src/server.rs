@@ -4,23 +4,66 @@ use handlebars::Handlebars;
 use include_dir::{include_dir, Dir};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::sync::Mutex;
 use std::collections::HashMap;
+use tokio::sync::{Mutex, Notify};
 use actix_web::http;
+use std::path::PathBuf;
 use std::process::Command;
 use std::str;
 
-use crate::chat::Chat;
+use crate::chat::{Chat, RegenerateOptions};
+use crate::daemon::{self, Daemon};
+use crate::memory::ProjectMemory;
+use crate::mentions;
+use crate::metrics;
+use crate::prompt::PromptTemplate;
+use crate::proxy;
+use crate::session::Session;
+use crate::tooler;
+use crate::inference::stream::StreamEvent;
 use crate::inference::types::{Message, Role, ContentItem, InferenceError};
 
 #[derive(Deserialize)]
 pub struct ChatRequest {
     message: Message,
+    /// Ends this reply at any of these instead of the configured `stop_sequences`
+    #[serde(default)]
+    stop_sequences: Option<Vec<String>>,
+    /// What the reply starts with, continued by the model
+    #[serde(default)]
+    prefill: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct EditRequest {
+    text: String,
+}
+
+#[derive(Deserialize)]
+pub struct PinRequest {
+    index: usize,
+    pinned: bool,
 }
 
 #[derive(Serialize, Clone)]
 pub struct ChatResponse {
     message: Message,
+    /// The reply was cut off, `GET /continue` picks it up
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    incomplete: bool,
+    /// The stop sequence the reply ended at
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_sequence: Option<String>,
+}
+
+impl ChatResponse {
+    fn new(message: Message) -> Self {
+        ChatResponse { message, incomplete: false, stop_sequence: None }
+    }
+
+    fn from_chat(chat: &Chat, message: Message) -> Self {
+        ChatResponse { message, incomplete: chat.is_incomplete(), stop_sequence: None }
+    }
 }
 
 #[derive(Serialize)]
@@ -30,6 +73,12 @@ pub struct ErrorResponse {
     status_code: u16,
 }
 
+impl ErrorResponse {
+    pub(crate) fn new(error: &str, error_type: &str, status_code: u16) -> Self {
+        ErrorResponse { error: parse_error_message(error), error_type: error_type.to_string(), status_code }
+    }
+}
+
 #[derive(Serialize)]
 pub struct DiffResponse {
     diff: String,
@@ -37,6 +86,8 @@ pub struct DiffResponse {
 
 pub struct AppState {
     chat: Mutex<Chat>,
+    // Notified by `GET /cancel`, kept outside the chat mutex which a running request holds
+    cancel: Notify,
     static_files: HashMap<String, Vec<u8>>,
 }
 
@@ -100,26 +151,128 @@ fn handle_inference_error(error: InferenceError) -> HttpResponse {
                 status_code: 500,
             })
         }
+        InferenceError::SecretsBlocked(blocked) => {
+            HttpResponse::BadRequest().json(ErrorResponse {
+                error: Value::String(blocked.to_string()),
+                error_type: "secrets_blocked".to_string(),
+                status_code: 400,
+            })
+        }
     }
 }
 
+#[get("/metrics")]
+async fn get_metrics() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(metrics::render())
+}
+
 #[get("/messages")]
 async fn get_messages(data: web::Data<AppState>) -> impl Responder {
-    let chat = data.chat.lock().unwrap();
-    HttpResponse::Ok().json(&chat.messages)
+    let chat = data.chat.lock().await;
+    HttpResponse::Ok().json(&chat.session.messages)
 }
 
 #[get("/clear")]
 async fn clear_chat(data: web::Data<AppState>) -> impl Responder {
-    let mut chat = data.chat.lock().unwrap();
-    let system_prompt = chat.messages.first().filter(|msg| msg.role == Role::System).cloned();
-    chat.messages.clear();
+    let mut chat = data.chat.lock().await;
+    let system_prompt = chat.session.messages.first().filter(|msg| msg.role == Role::System).cloned();
+    chat.new_session();
     if let Some(prompt) = system_prompt {
-        chat.messages.push(prompt);
+        chat.session.messages.push(prompt);
     }
     HttpResponse::Ok().json(json!({"cleared": true, "message": "Chat history cleared"}))
 }
 
+#[get("/compact")]
+async fn compact_chat(data: web::Data<AppState>) -> impl Responder {
+    let mut chat = data.chat.lock().await;
+    match chat.compact().await {
+        Ok(compacted) => HttpResponse::Ok().json(json!({"compacted": compacted})),
+        Err(e) => HttpResponse::InternalServerError().json(ErrorResponse {
+            error: parse_error_message(&e.to_string()),
+            error_type: "compaction_error".to_string(),
+            status_code: 500,
+        })
+    }
+}
+
+pub(crate) fn chat_error_response(e: anyhow::Error) -> HttpResponse {
+    match e.downcast::<InferenceError>() {
+        Ok(inference_error) => handle_inference_error(inference_error),
+        Err(other_error) => HttpResponse::InternalServerError().json(ErrorResponse {
+            error: parse_error_message(&other_error.to_string()),
+            error_type: "unknown_error".to_string(),
+            status_code: 500,
+        })
+    }
+}
+
+async fn regenerate_handler(
+    data: web::Data<AppState>,
+    req: web::Json<RegenerateOptions>
+) -> impl Responder {
+    let mut chat = data.chat.lock().await;
+    match chat.regenerate(req.into_inner()).await {
+        Ok(message) => HttpResponse::Ok().json(ChatResponse::from_chat(&chat, message)),
+        Err(e) => chat_error_response(e),
+    }
+}
+
+async fn edit_handler(
+    data: web::Data<AppState>,
+    req: web::Json<EditRequest>
+) -> impl Responder {
+    let mut chat = data.chat.lock().await;
+    match chat.edit_last(req.into_inner().text).await {
+        Ok(message) => HttpResponse::Ok().json(ChatResponse::from_chat(&chat, message)),
+        Err(e) => chat_error_response(e),
+    }
+}
+
+#[get("/continue")]
+async fn continue_response(data: web::Data<AppState>) -> impl Responder {
+    let mut chat = data.chat.lock().await;
+    match chat.continue_response().await {
+        Ok(message) => HttpResponse::Ok().json(ChatResponse::from_chat(&chat, message)),
+        Err(e) => chat_error_response(e),
+    }
+}
+
+#[get("/cancel")]
+async fn cancel_response(data: web::Data<AppState>) -> impl Responder {
+    data.cancel.notify_waiters();
+    HttpResponse::Ok().json(json!({"cancelled": true}))
+}
+
+async fn pin_handler(
+    data: web::Data<AppState>,
+    req: web::Json<PinRequest>
+) -> impl Responder {
+    let mut chat = data.chat.lock().await;
+    match chat.set_pinned(req.index, req.pinned) {
+        Ok(_) => HttpResponse::Ok().json(json!({"pinned": &chat.session.pinned})),
+        Err(e) => HttpResponse::BadRequest().json(ErrorResponse {
+            error: parse_error_message(&e.to_string()),
+            error_type: "pin_error".to_string(),
+            status_code: 400,
+        })
+    }
+}
+
+#[get("/undo")]
+async fn undo_turn() -> impl Responder {
+    match tooler::undo_last_turn() {
+        Ok(restored) => HttpResponse::Ok().json(json!({"restored": restored})),
+        Err(e) => HttpResponse::InternalServerError().json(ErrorResponse {
+            error: parse_error_message(&e.to_string()),
+            error_type: "undo_error".to_string(),
+            status_code: 500,
+        })
+    }
+}
+
 #[get("/diff")]
 async fn get_diff() -> impl Responder {
     // Run git diff command
@@ -165,79 +318,85 @@ async fn chat_handler(
     data: web::Data<AppState>, 
     req: web::Json<ChatRequest>
 ) -> impl Responder {
-    let mut chat = data.chat.lock().unwrap();
+    let mut chat = data.chat.lock().await;
 
     match &req.0.message.content[0] {
-        ContentItem::Text { .. } => {
-            let new_msg = Message {
-                role: Role::User,
-                content: vec![req.0.message.content[0].clone()]
+        ContentItem::Text { text } if ProjectMemory::parse_command(text).is_some() => {
+            // Handled locally, the note never goes to the model
+            let note = ProjectMemory::parse_command(text).unwrap_or_default();
+            let reply = match ProjectMemory::remember(note) {
+                Ok(_) => format!("Remembered: {}", note),
+                Err(e) => format!("Could not save note: {}", e),
             };
-
-            match chat.send_message(new_msg).await {
+            HttpResponse::Ok().json(ChatResponse::new(Message {
+                role: Role::Assistant,
+                content: vec![ContentItem::Text { text: reply }],
+            }))
+        },
+        ContentItem::Text { text } if Chat::parse_pin_command(text).is_some() => {
+            let text = Chat::parse_pin_command(text).unwrap_or_default().to_string();
+            match chat.send_pinned(&text).await {
+                Ok(returned_msg) => HttpResponse::Ok().json(ChatResponse::from_chat(&chat, returned_msg)),
+                Err(e) => chat_error_response(e),
+            }
+        },
+        ContentItem::Text { text } => {
+            let new_msg = mentions::user_message(text);
+
+            chat.set_prefill(req.0.prefill.clone());
+            let mut stop_sequence = None;
+            let on_event = |event: &StreamEvent| {
+                if let StreamEvent::Done { stop_sequence: Some(stop), .. } = event {
+                    stop_sequence = Some(stop.clone());
+                }
+            };
+            let sent = match req.0.stop_sequences.clone() {
+                Some(stop_sequences) => chat.send_message_stopping_at(new_msg, stop_sequences, on_event, data.cancel.notified()).await,
+                None => chat.send_message_streaming(new_msg, on_event, data.cancel.notified()).await,
+            };
+            match sent {
                 Ok(returned_msg) => {
-                    HttpResponse::Ok().json(ChatResponse {
-                        message: returned_msg,
-                    })
+                    HttpResponse::Ok().json(ChatResponse { stop_sequence, ..ChatResponse::from_chat(&chat, returned_msg) })
                 },
-                Err(e) => {
-                    match e.downcast::<InferenceError>() {
-                        Ok(inference_error) => handle_inference_error(inference_error),
-                        Err(other_error) => HttpResponse::InternalServerError().json(ErrorResponse {
-                            error: parse_error_message(&other_error.to_string()),
-                            error_type: "unknown_error".to_string(),
-                            status_code: 500,
-                        })
-                    }
-                }
+                Err(e) => chat_error_response(e),
             }
         },
         ContentItem::ToolUse { id, .. } => {
-            match chat.handle_tool_use(&req.0.message.content[0]).await {
-                Ok(tool_use_result) => HttpResponse::Ok().json(ChatResponse {
-                    message: Message {
-                        role: Role::User,
-                        content: vec![
-                            ContentItem::ToolResult {
-                                tool_use_id: id.to_string(),
-                                content: tool_use_result
-                            }
-                        ]
+            // A failed tool is still a result, for the client to send back to the model
+            let (content, is_error) = match chat.handle_tool_use(&req.0.message.content[0]).await {
+                Ok(tool_use_result) => (tool_use_result, false),
+                Err(e) => (format!("Error: {}", e), true),
+            };
+            HttpResponse::Ok().json(ChatResponse::new(Message {
+                role: Role::User,
+                content: vec![
+                    ContentItem::ToolResult {
+                        tool_use_id: id.to_string(),
+                        content,
+                        is_error,
                     }
-                }),
-                Err(e) => {
-                    HttpResponse::InternalServerError().json(ErrorResponse {
-                        error: parse_error_message(&e.to_string()),
-                        error_type: "tool_error".to_string(),
-                        status_code: 500,
-                    })
-                }
-            }
+                ]
+            }))
         },
-        ContentItem::ToolResult { .. } => {
+        // Tool results and images are sent as they came
+        ContentItem::ToolResult { .. } | ContentItem::Image { .. } => {
             let msg = Message {
                 role: Role::User,
                 content: req.0.message.content.clone(),
             };
             
-            match chat.send_message(msg).await {
+            match chat.send_message_streaming(msg, |_| {}, data.cancel.notified()).await {
                 Ok(returned_msg) => {
-                    HttpResponse::Ok().json(ChatResponse {
-                        message: returned_msg,
-                    })
+                    HttpResponse::Ok().json(ChatResponse::from_chat(&chat, returned_msg))
                 },
-                Err(e) => {
-                    match e.downcast::<InferenceError>() {
-                        Ok(inference_error) => handle_inference_error(inference_error),
-                        Err(other_error) => HttpResponse::InternalServerError().json(ErrorResponse {
-                            error: parse_error_message(&other_error.to_string()),
-                            error_type: "unknown_error".to_string(),
-                            status_code: 500,
-                        })
-                    }
-                }
+                Err(e) => chat_error_response(e),
             }
-        }
+        },
+        ContentItem::Unknown(value) => HttpResponse::BadRequest().json(ErrorResponse {
+            error: json!(format!("Unknown content block: {}", value)),
+            error_type: "invalid_request_error".to_string(),
+            status_code: 400,
+        }),
     }
 }
 
@@ -268,7 +427,15 @@ fn process_files(dir: &Dir, base_path: &str, static_files: &mut HashMap<String,
     }
 }
 
-pub async fn start_server(host: String, port: u16) -> std::io::Result<()> {
+pub async fn start_server(
+    host: String,
+    port: u16,
+    socket: Option<PathBuf>,
+    session: Option<Session>,
+    prompt_template: Option<PromptTemplate>,
+    persona: Option<&str>,
+    stop_sequences: &[String],
+) -> std::io::Result<()> {
     let server_url = format!("http://{}:{}", host, port);
     let template_data = json!({
         "server_url": server_url
@@ -279,14 +446,27 @@ pub async fn start_server(host: String, port: u16) -> std::io::Result<()> {
     
     process_files(&DIST_DIR, "", &mut static_files, &mut hbs, &template_data);
 
+    let mut chat = Chat::for_persona(persona).await.map_err(std::io::Error::other)?;
+    if !stop_sequences.is_empty() {
+        chat.set_stop_sequences(stop_sequences.to_vec()).await.map_err(std::io::Error::other)?;
+    }
+    if let Some(session) = session {
+        chat.resume(session).map_err(std::io::Error::other)?;
+    }
+    if let Some(template) = prompt_template {
+        chat.set_prompt_template(template);
+    }
+
     let app_state = web::Data::new(AppState {
-        chat: Mutex::new(Chat::new().await),
+        chat: Mutex::new(chat),
+        cancel: Notify::new(),
         static_files,
     });
 
-    println!("Starting server on {}:{}", host, port);
+    let token = daemon::token().map_err(std::io::Error::other)?;
+    let daemon = web::Data::new(Daemon::new(token, persona, stop_sequences));
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         let cors = Cors::default()
             .allowed_origin(&server_url)
             .allowed_origin(&format!("http://localhost:{}", port))
@@ -303,15 +483,41 @@ pub async fn start_server(host: String, port: u16) -> std::io::Result<()> {
         App::new()
             .wrap(cors)
             .app_data(app_state.clone())
+            .app_data(daemon.clone())
+            .configure(daemon::routes)
+            .configure(proxy::routes)
             .route("/chat", web::post().to(chat_handler))
+            .route("/regenerate", web::post().to(regenerate_handler))
+            .route("/edit", web::post().to(edit_handler))
+            .route("/pin", web::post().to(pin_handler))
+            .service(continue_response)
+            .service(cancel_response)
             .service(clear_chat)
+            .service(compact_chat)
+            .service(undo_turn)
             .service(get_messages)
             .service(get_diff)
+            .service(get_metrics)
             .service(index)
-    })
-    .bind(format!("{}:{}", host, port))?
-    .run()
-    .await
+    });
+    let server = match socket {
+        #[cfg(unix)]
+        Some(path) => {
+            // A socket left behind by a server that didn't shut down cleanly would fail the bind
+            if path.exists() {
+                std::fs::remove_file(&path)?;
+            }
+            println!("Starting server on {}", path.display());
+            server.bind_uds(path)?
+        },
+        #[cfg(not(unix))]
+        Some(_) => return Err(std::io::Error::other("--socket needs Unix domain sockets, which this platform doesn't have")),
+        None => {
+            println!("Starting server on {}:{}", host, port);
+            server.bind(format!("{}:{}", host, port))?
+        },
+    };
+    server.run().await
 }
 
 #[get("/{filename:.*}")]
@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::ProjectConfig;
+
+const SNIPPETS_DIR: &str = "snippets";
+
+/*
+    * A reusable piece of prompt saved as `.pprog/snippets/<name>.md`.  `{{name}}` in the
+    * text is a placeholder filled in when the snippet is used, the same syntax as prompt
+    * templates but without their variables or helpers, anything else is kept verbatim.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snippet {
+    pub name: String,
+    pub text: String,
+}
+
+fn dir() -> Result<PathBuf, anyhow::Error> {
+    Ok(ProjectConfig::data_dir()?.join(SNIPPETS_DIR))
+}
+
+// Names become file names, so they are kept to characters that can't leave the directory
+fn check_name(name: &str) -> Result<(), anyhow::Error> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(anyhow::anyhow!("Snippet name {:?} may only use letters, digits, - and _", name));
+    }
+    Ok(())
+}
+
+// Placeholder names in the order they first appear, with the byte range of each use
+fn placeholders(text: &str) -> Vec<(String, usize, usize)> {
+    let mut found = Vec::new();
+    let mut rest = 0;
+    while let Some(start) = text[rest..].find("{{").map(|idx| rest + idx) {
+        let Some(end) = text[start..].find("}}").map(|idx| start + idx + 2) else { break };
+        let name = text[start + 2..end - 2].trim();
+        if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            found.push((name.to_string(), start, end));
+            rest = end;
+        } else {
+            rest = start + 2;
+        }
+    }
+    found
+}
+
+impl Snippet {
+    pub fn save(name: &str, text: &str) -> Result<PathBuf, anyhow::Error> {
+        check_name(name)?;
+        let dir = dir()?;
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}.md", name));
+        fs::write(&path, format!("{}\n", text.trim_end()))?;
+        Ok(path)
+    }
+
+    pub fn load(name: &str) -> Result<Self, anyhow::Error> {
+        check_name(name)?;
+        let path = dir()?.join(format!("{}.md", name));
+        let text = fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("Snippet {:?} not found at {:?}: {}", name, path, e))?;
+        Ok(Snippet { name: name.to_string(), text: text.trim_end().to_string() })
+    }
+
+    /// Every saved snippet, by name.
+    pub fn list() -> Result<Vec<Self>, anyhow::Error> {
+        let dir = dir()?;
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut snippets = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else { continue };
+            if path.extension().is_some_and(|ext| ext == "md") && check_name(name).is_ok() {
+                snippets.push(Self::load(name)?);
+            }
+        }
+        snippets.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(snippets)
+    }
+
+    /// The placeholders the text uses, each once.
+    pub fn placeholders(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for (name, _, _) in placeholders(&self.text) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    /// The text with every placeholder replaced, failing on any without a value.
+    pub fn expand(&self, values: &BTreeMap<String, String>) -> Result<String, anyhow::Error> {
+        let missing: Vec<String> = self.placeholders().into_iter().filter(|name| !values.contains_key(name)).collect();
+        if !missing.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Snippet {:?} needs {}, pass them as name=value",
+                self.name,
+                missing.join(", "),
+            ));
+        }
+        let mut expanded = String::new();
+        let mut last = 0;
+        for (name, start, end) in placeholders(&self.text) {
+            expanded.push_str(&self.text[last..start]);
+            expanded.push_str(&values[&name]);
+            last = end;
+        }
+        expanded.push_str(&self.text[last..]);
+        Ok(expanded)
+    }
+}
+
+/// Placeholder values given as `name=value` arguments.
+pub fn parse_values<'a>(args: impl IntoIterator<Item = &'a str>) -> Result<BTreeMap<String, String>, anyhow::Error> {
+    args.into_iter()
+        .map(|arg| match arg.split_once('=') {
+            Some((name, value)) => Ok((name.to_string(), value.to_string())),
+            None => Err(anyhow::anyhow!("Expected name=value for a snippet placeholder, got {:?}", arg)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_placeholders() -> Result<(), anyhow::Error> {
+        let snippet = Snippet {
+            name: "pr".to_string(),
+            text: "Describe {{ ticket }} for {{audience}}, see {{ticket}}.  Keep {{ not a placeholder }} and {{".to_string(),
+        };
+        assert_eq!(snippet.placeholders(), vec!["ticket", "audience"]);
+        let values = parse_values(["ticket=ABC-12", "audience=reviewers"])?;
+        assert_eq!(snippet.expand(&values)?, "Describe ABC-12 for reviewers, see ABC-12.  Keep {{ not a placeholder }} and {{");
+        assert!(snippet.expand(&parse_values(["ticket=1"])?).is_err());
+        assert!(parse_values(["ticket"]).is_err());
+        assert!(check_name("../escape").is_err());
+        Ok(())
+    }
+}